@@ -0,0 +1,517 @@
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use ts_rs::TS;
+
+use crate::merge::Rgb16Image;
+use crate::simd;
+
+pub const DEFAULT_ALGORITHM: &str = "average";
+
+pub type AlgorithmParams = Map<String, Value>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ParameterKind {
+    Number { min: f64, max: f64, step: f64 },
+    Boolean,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ParameterSchema {
+    pub name: &'static str,
+    pub label: &'static str,
+    #[serde(flatten)]
+    pub kind: ParameterKind,
+    pub default: Value,
+}
+
+#[derive(Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct AlgorithmInfo {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub description: &'static str,
+    pub parameters: Vec<ParameterSchema>,
+}
+
+// 新しい合成方式はこのトレイトを実装して REGISTRY に追加する
+pub trait MergeAlgorithm: Sync {
+    fn name(&self) -> &'static str;
+    fn label(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn parameters(&self) -> Vec<ParameterSchema>;
+    fn merge(&self, frames: &[Rgb16Image], params: &AlgorithmParams) -> Result<Rgb16Image, String>;
+    // 合成中に確保する作業領域の、1画素あたりの見積もり（入力フレーム自体は含めない）
+    fn working_bytes_per_pixel(&self, _frames: usize) -> u64 {
+        6
+    }
+    // タイルに分けて合成するときに必要な周囲の余白（width・height は元の画像の大きさ）。
+    // 画素ごとに合成する方式は余白がいらない
+    fn tile_support(&self, _width: u32, _height: u32) -> TileSupport {
+        TileSupport {
+            margin: 0,
+            alignment: 1,
+        }
+    }
+    // 元の画像（full_size）の一部を切り出したタイルを、元の画像と同じ結果になるよう合成する
+    fn merge_tile(
+        &self,
+        frames: &[Rgb16Image],
+        params: &AlgorithmParams,
+        _full_size: (u32, u32),
+    ) -> Result<Rgb16Image, String> {
+        self.merge(frames, params)
+    }
+}
+
+// タイルの周囲に付ける余白と、タイルの左上を揃える間隔（ピラミッドの標本位置を元の画像と合わせる）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileSupport {
+    pub margin: u32,
+    pub alignment: u32,
+}
+
+static REGISTRY: &[&dyn MergeAlgorithm] = &[&Average, &ExposureFusion];
+
+pub fn list() -> Vec<AlgorithmInfo> {
+    REGISTRY
+        .iter()
+        .map(|algorithm| AlgorithmInfo {
+            name: algorithm.name(),
+            label: algorithm.label(),
+            description: algorithm.description(),
+            parameters: algorithm.parameters(),
+        })
+        .collect()
+}
+
+pub fn find(name: &str) -> Result<&'static dyn MergeAlgorithm, String> {
+    REGISTRY
+        .iter()
+        .copied()
+        .find(|algorithm| algorithm.name() == name)
+        .ok_or_else(|| format!("不明な合成方式です: {}", name))
+}
+
+// 未指定のパラメータは既定値で埋め、型と範囲を検証する
+pub fn resolve_params(
+    algorithm: &dyn MergeAlgorithm,
+    params: &AlgorithmParams,
+) -> Result<AlgorithmParams, String> {
+    let schemas = algorithm.parameters();
+    for key in params.keys() {
+        if !schemas.iter().any(|schema| schema.name == key) {
+            return Err(format!(
+                "{} に存在しないパラメータです: {}",
+                algorithm.name(),
+                key
+            ));
+        }
+    }
+
+    let mut resolved = Map::new();
+    for schema in schemas {
+        let value = params
+            .get(schema.name)
+            .cloned()
+            .unwrap_or(schema.default.clone());
+        let valid = match &schema.kind {
+            ParameterKind::Number { min, max, .. } => value
+                .as_f64()
+                .is_some_and(|number| (*min..=*max).contains(&number)),
+            ParameterKind::Boolean => value.is_boolean(),
+        };
+        if !valid {
+            return Err(format!("パラメータ {} の値が不正です", schema.name));
+        }
+        resolved.insert(schema.name.to_string(), value);
+    }
+    Ok(resolved)
+}
+
+fn number(params: &AlgorithmParams, name: &str) -> f64 {
+    params.get(name).and_then(Value::as_f64).unwrap_or_default()
+}
+
+struct Average;
+
+impl MergeAlgorithm for Average {
+    fn name(&self) -> &'static str {
+        "average"
+    }
+
+    fn label(&self) -> &'static str {
+        "平均合成"
+    }
+
+    fn description(&self) -> &'static str {
+        "全フレームを画素ごとに単純平均します"
+    }
+
+    fn parameters(&self) -> Vec<ParameterSchema> {
+        Vec::new()
+    }
+
+    fn merge(
+        &self,
+        frames: &[Rgb16Image],
+        _params: &AlgorithmParams,
+    ) -> Result<Rgb16Image, String> {
+        let width = frames[0].width();
+        let height = frames[0].height();
+        let mut merged = Rgb16Image::new(width, height);
+
+        let sources: Vec<&[u16]> = frames
+            .iter()
+            .map(|frame| frame.as_raw().as_slice())
+            .collect();
+        simd::average_u16(&sources, &mut merged);
+
+        Ok(merged)
+    }
+}
+
+// Mertens らの露光融合。コントラスト・彩度・適正露出の重みをピラミッド上でブレンドする
+struct ExposureFusion;
+
+impl MergeAlgorithm for ExposureFusion {
+    fn name(&self) -> &'static str {
+        "fusion"
+    }
+
+    fn label(&self) -> &'static str {
+        "露光融合"
+    }
+
+    fn description(&self) -> &'static str {
+        "各フレームの適正露出部分を重み付けして多重解像度で合成します"
+    }
+
+    fn parameters(&self) -> Vec<ParameterSchema> {
+        let weight = |name, label| ParameterSchema {
+            name,
+            label,
+            kind: ParameterKind::Number {
+                min: 0.0,
+                max: 3.0,
+                step: 0.1,
+            },
+            default: Value::from(1.0),
+        };
+        vec![
+            weight("contrastWeight", "コントラストの重み"),
+            weight("saturationWeight", "彩度の重み"),
+            weight("exposureWeight", "適正露出の重み"),
+            ParameterSchema {
+                name: "exposureSigma",
+                label: "適正露出の許容幅",
+                kind: ParameterKind::Number {
+                    min: 0.05,
+                    max: 1.0,
+                    step: 0.05,
+                },
+                default: Value::from(0.2),
+            },
+        ]
+    }
+
+    // フレームごとの RGB・重みの平面とピラミッド、出力側の累積用ピラミッド
+    fn working_bytes_per_pixel(&self, frames: usize) -> u64 {
+        frames as u64 * 32 + 24
+    }
+
+    // 最も粗い段の 1 画素は元の画像の 2^(段数 - 1) 画素にあたり、縮小・拡大のたびに近傍が広がる。
+    // 段数を元の画像に合わせて、その広がり（約 2^(段数 + 1) 画素）を余白にする
+    fn tile_support(&self, width: u32, height: u32) -> TileSupport {
+        let levels = pyramid_levels(width as usize, height as usize) as u32;
+        TileSupport {
+            margin: 2 << levels,
+            alignment: 1 << (levels - 1),
+        }
+    }
+
+    fn merge(&self, frames: &[Rgb16Image], params: &AlgorithmParams) -> Result<Rgb16Image, String> {
+        let levels = pyramid_levels(frames[0].width() as usize, frames[0].height() as usize);
+        self.fuse(frames, params, levels)
+    }
+
+    // タイルの大きさで段数を決めると、最も粗い段がタイルごとの平均になって継ぎ目が出る
+    fn merge_tile(
+        &self,
+        frames: &[Rgb16Image],
+        params: &AlgorithmParams,
+        (width, height): (u32, u32),
+    ) -> Result<Rgb16Image, String> {
+        self.fuse(
+            frames,
+            params,
+            pyramid_levels(width as usize, height as usize),
+        )
+    }
+}
+
+impl ExposureFusion {
+    fn fuse(
+        &self,
+        frames: &[Rgb16Image],
+        params: &AlgorithmParams,
+        levels: usize,
+    ) -> Result<Rgb16Image, String> {
+        let width = frames[0].width() as usize;
+        let height = frames[0].height() as usize;
+        let contrast_weight = number(params, "contrastWeight") as f32;
+        let saturation_weight = number(params, "saturationWeight") as f32;
+        let exposure_weight = number(params, "exposureWeight") as f32;
+        let sigma = number(params, "exposureSigma") as f32;
+
+        let planes: Vec<[Plane; 3]> = frames.iter().map(split_channels).collect();
+        let mut weights: Vec<Plane> = planes
+            .iter()
+            .map(|channels| {
+                fusion_weight(
+                    channels,
+                    contrast_weight,
+                    saturation_weight,
+                    exposure_weight,
+                    sigma,
+                )
+            })
+            .collect();
+
+        simd::normalize_weights(
+            &mut weights
+                .iter_mut()
+                .map(|weight| weight.data.as_mut_slice())
+                .collect::<Vec<_>>(),
+        );
+
+        let mut output: Vec<Plane> = Vec::new();
+        for c in 0..3 {
+            let mut blended: Option<Vec<Plane>> = None;
+            for (channels, weight) in planes.iter().zip(&weights) {
+                let laplacian = laplacian_pyramid(&channels[c], levels);
+                let gaussian = gaussian_pyramid(weight, levels);
+                let weighted: Vec<Plane> = laplacian
+                    .iter()
+                    .zip(&gaussian)
+                    .map(|(l, g)| l.multiply(g))
+                    .collect();
+                blended = Some(match blended {
+                    Some(acc) => acc.iter().zip(&weighted).map(|(a, b)| a.add(b)).collect(),
+                    None => weighted,
+                });
+            }
+            output.push(collapse(&blended.unwrap_or_default()));
+        }
+
+        Ok(Rgb16Image::from_fn(width as u32, height as u32, |x, y| {
+            let i = y as usize * width + x as usize;
+            let to_u16 = |v: f32| (v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+            Rgb([
+                to_u16(output[0].data[i]),
+                to_u16(output[1].data[i]),
+                to_u16(output[2].data[i]),
+            ])
+        }))
+    }
+}
+
+// 1チャンネルの浮動小数点の画像。ピラミッドの各段に使う
+#[derive(Clone, Default)]
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl Plane {
+    fn get(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.data[y * self.width + x]
+    }
+
+    fn map2(&self, other: &Plane, f: impl Fn(f32, f32) -> f32) -> Plane {
+        Plane {
+            width: self.width,
+            height: self.height,
+            data: self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(a, b)| f(*a, *b))
+                .collect(),
+        }
+    }
+
+    fn add(&self, other: &Plane) -> Plane {
+        self.map2(other, |a, b| a + b)
+    }
+
+    fn subtract(&self, other: &Plane) -> Plane {
+        self.map2(other, |a, b| a - b)
+    }
+
+    fn multiply(&self, other: &Plane) -> Plane {
+        self.map2(other, |a, b| a * b)
+    }
+
+    // 5タップの二項フィルタでぼかしてから 1/2 に間引く
+    fn downsample(&self) -> Plane {
+        const TAPS: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut total = 0.0;
+                for (j, ty) in TAPS.iter().enumerate() {
+                    for (i, tx) in TAPS.iter().enumerate() {
+                        let sx = (x * 2) as isize + i as isize - 2;
+                        let sy = (y * 2) as isize + j as isize - 2;
+                        total += tx * ty * self.get(sx, sy);
+                    }
+                }
+                data.push(total);
+            }
+        }
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn upsample(&self, width: usize, height: usize) -> Plane {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sy = (y as f32 + 0.5) / 2.0 - 0.5;
+            let y0 = sy.floor();
+            let fy = sy - y0;
+            for x in 0..width {
+                let sx = (x as f32 + 0.5) / 2.0 - 0.5;
+                let x0 = sx.floor();
+                let fx = sx - x0;
+                let (x0, y0) = (x0 as isize, y0 as isize);
+                let top = self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx;
+                let bottom = self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx;
+                data.push(top * (1.0 - fy) + bottom * fy);
+            }
+        }
+        Plane {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+fn split_channels(image: &Rgb16Image) -> [Plane; 3] {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let mut channels: [Plane; 3] = std::array::from_fn(|_| Plane {
+        width,
+        height,
+        data: Vec::with_capacity(width * height),
+    });
+    for pixel in image.pixels() {
+        for (c, channel) in channels.iter_mut().enumerate() {
+            channel.data.push(pixel[c] as f32 / u16::MAX as f32);
+        }
+    }
+    channels
+}
+
+fn fusion_weight(
+    channels: &[Plane; 3],
+    contrast_weight: f32,
+    saturation_weight: f32,
+    exposure_weight: f32,
+    sigma: f32,
+) -> Plane {
+    let width = channels[0].width;
+    let height = channels[0].height;
+    let gray = Plane {
+        width,
+        height,
+        data: simd::luma_planes(&channels[0].data, &channels[1].data, &channels[2].data),
+    };
+
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let i = y as usize * width + x as usize;
+            let contrast =
+                (gray.get(x - 1, y) + gray.get(x + 1, y) + gray.get(x, y - 1) + gray.get(x, y + 1)
+                    - 4.0 * gray.get(x, y))
+                .abs();
+
+            let values = [
+                channels[0].data[i],
+                channels[1].data[i],
+                channels[2].data[i],
+            ];
+            let mean = (values[0] + values[1] + values[2]) / 3.0;
+            let saturation = (values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / 3.0).sqrt();
+
+            let exposure: f32 = values
+                .iter()
+                .map(|v| (-(v - 0.5).powi(2) / (2.0 * sigma * sigma)).exp())
+                .product();
+
+            data.push(
+                contrast.powf(contrast_weight)
+                    * saturation.powf(saturation_weight)
+                    * exposure.powf(exposure_weight)
+                    + 1e-12,
+            );
+        }
+    }
+
+    Plane {
+        width,
+        height,
+        data,
+    }
+}
+
+fn pyramid_levels(width: usize, height: usize) -> usize {
+    let mut levels = 1;
+    let mut size = width.min(height);
+    while size > 8 && levels < 8 {
+        size = size.div_ceil(2);
+        levels += 1;
+    }
+    levels
+}
+
+fn gaussian_pyramid(plane: &Plane, levels: usize) -> Vec<Plane> {
+    let mut pyramid = vec![plane.clone()];
+    for _ in 1..levels {
+        let next = pyramid[pyramid.len() - 1].downsample();
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+pub fn laplacian_pyramid(plane: &Plane, levels: usize) -> Vec<Plane> {
+    let gaussian = gaussian_pyramid(plane, levels);
+    let mut pyramid = Vec::with_capacity(levels);
+    for level in 0..levels - 1 {
+        let current = &gaussian[level];
+        let expanded = gaussian[level + 1].upsample(current.width, current.height);
+        pyramid.push(current.subtract(&expanded));
+    }
+    pyramid.push(gaussian[levels - 1].clone());
+    pyramid
+}
+
+pub fn collapse(pyramid: &[Plane]) -> Plane {
+    let mut current = pyramid[pyramid.len() - 1].clone();
+    for level in pyramid[..pyramid.len() - 1].iter().rev() {
+        current = current.upsample(level.width, level.height).add(level);
+    }
+    current
+}
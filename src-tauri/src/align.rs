@@ -0,0 +1,683 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use image::Rgb;
+
+use crate::merge::{MergeQuality, Rgb16Image};
+
+// 中央値しきい値ビットマップ（MTB）の近傍除外幅（8bit 換算）
+const MTB_EXCLUSION: i32 = 4;
+// これより小さい解像度ではビットマップが粗すぎるため、ピラミッドを打ち切る
+const MIN_LEVEL_SIZE: usize = 16;
+// 回転・拡大率の探索で評価する画素数の上限（これを超える段は間引く）
+const MAX_ERROR_SAMPLES: usize = 1 << 16;
+const MAX_GRID_STEPS: usize = 12;
+const MAX_REFINE_ITERATIONS: usize = 16;
+const LANCZOS_A: usize = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum AlignModel {
+    #[default]
+    Translation,
+    // 平行移動に加えて小さな回転と拡大縮小を補正する（手持ちの長秒露出向け）
+    Similarity,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum AlignPrecision {
+    #[default]
+    Pixel,
+    // 1 画素未満のずれも推定し、Lanczos 補間で揃える（遅いが縁がぼけにくい）
+    Subpixel,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AlignParams {
+    pub max_shift: u32,
+    pub model: AlignModel,
+    // similarity で探索する回転角の上限（度）
+    pub max_rotation: f64,
+    // similarity で探索する拡大率の 1 からの差の上限
+    pub max_scale: f64,
+    pub align_precision: AlignPrecision,
+}
+
+impl Default for AlignParams {
+    fn default() -> Self {
+        Self {
+            max_shift: 32,
+            model: AlignModel::Translation,
+            max_rotation: 3.0,
+            max_scale: 0.03,
+            align_precision: AlignPrecision::Pixel,
+        }
+    }
+}
+
+// 基準の (x, y) に、フレームの「中心まわりに rotation 度回して scale 倍し、(dx, dy) ずらした位置」が対応する
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct AlignTransform {
+    pub dx: f64,
+    pub dy: f64,
+    pub rotation: f64,
+    pub scale: f64,
+}
+
+impl AlignTransform {
+    pub const IDENTITY: AlignTransform = AlignTransform {
+        dx: 0.0,
+        dy: 0.0,
+        rotation: 0.0,
+        scale: 1.0,
+    };
+
+    fn translation(offset: [i32; 2]) -> Self {
+        Self {
+            dx: offset[0] as f64,
+            dy: offset[1] as f64,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn offset(&self) -> [i32; 2] {
+        [self.dx.round() as i32, self.dy.round() as i32]
+    }
+
+    fn is_translation(&self) -> bool {
+        self.rotation == 0.0 && self.scale == 1.0
+    }
+
+    // 幅 width・高さ height の画像上で、基準側の座標に対応するフレーム側の座標を返す
+    fn mapper(&self, width: usize, height: usize, unit: f64) -> impl Fn(f64, f64) -> (f64, f64) {
+        let cx = (width as f64 - 1.0) / 2.0;
+        let cy = (height as f64 - 1.0) / 2.0;
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (a, b) = (self.scale * cos, self.scale * sin);
+        let (dx, dy) = (self.dx / unit, self.dy / unit);
+        move |x, y| {
+            let (rx, ry) = (x - cx, y - cy);
+            (cx + a * rx - b * ry + dx, cy + b * rx + a * ry + dy)
+        }
+    }
+}
+
+struct Bitmaps {
+    width: usize,
+    height: usize,
+    threshold: Vec<bool>,
+    exclusion: Vec<bool>,
+}
+
+// 露出差に強い MTB 方式で各フレームのずれを求め、基準フレームに揃える
+pub fn align_frames(
+    frames: &[Rgb16Image],
+    reference: usize,
+    params: &AlignParams,
+    quality: MergeQuality,
+) -> (Vec<Rgb16Image>, Vec<AlignTransform>) {
+    let levels = shift_levels(params.max_shift);
+    let reference_pyramid = gray_pyramid(&frames[reference], levels);
+
+    let mut aligned = Vec::with_capacity(frames.len());
+    let mut transforms = Vec::with_capacity(frames.len());
+    for (index, frame) in frames.iter().enumerate() {
+        if index == reference {
+            aligned.push(frame.clone());
+            transforms.push(AlignTransform::IDENTITY);
+            continue;
+        }
+
+        let pyramid = gray_pyramid(frame, levels);
+        let offset = estimate_shift(
+            &reference_pyramid,
+            &pyramid,
+            params.max_shift as i32,
+            quality,
+        );
+        let mut transform = match params.model {
+            AlignModel::Translation => AlignTransform::translation(offset),
+            AlignModel::Similarity => {
+                estimate_similarity(&reference_pyramid, &pyramid, offset, params, quality)
+            }
+        };
+        if params.align_precision == AlignPrecision::Subpixel && quality.refines_at_full() {
+            transform = refine_subpixel(&reference_pyramid[0], &pyramid[0], transform);
+        }
+        aligned.push(warp_image(frame, &transform, params.align_precision));
+        transforms.push(transform);
+    }
+
+    (aligned, transforms)
+}
+
+// 保存しておいた変換で揃える。推定を省くだけで、結果は align_frames と同じになる
+pub fn apply_transforms(
+    frames: &[Rgb16Image],
+    transforms: &[AlignTransform],
+    precision: AlignPrecision,
+) -> Vec<Rgb16Image> {
+    frames
+        .iter()
+        .zip(transforms)
+        .map(|(frame, transform)| {
+            if *transform == AlignTransform::IDENTITY {
+                frame.clone()
+            } else {
+                warp_image(frame, transform, precision)
+            }
+        })
+        .collect()
+}
+
+fn shift_levels(max_shift: u32) -> usize {
+    let mut levels = 1;
+    while (1u32 << levels) <= max_shift.max(1) && levels < 8 {
+        levels += 1;
+    }
+    levels
+}
+
+fn gray_pyramid(image: &Rgb16Image, levels: usize) -> Vec<Bitmaps> {
+    let mut width = image.width() as usize;
+    let mut height = image.height() as usize;
+    let mut gray: Vec<i32> = image
+        .pixels()
+        .map(|p| ((54 * p[0] as u32 + 183 * p[1] as u32 + 19 * p[2] as u32) >> 16) as i32)
+        .collect();
+
+    let mut pyramid = vec![bitmaps(&gray, width, height)];
+    for _ in 1..levels {
+        if width / 2 < MIN_LEVEL_SIZE || height / 2 < MIN_LEVEL_SIZE {
+            break;
+        }
+        let next_width = width / 2;
+        let next_height = height / 2;
+        let mut next = Vec::with_capacity(next_width * next_height);
+        for y in 0..next_height {
+            for x in 0..next_width {
+                let i = y * 2 * width + x * 2;
+                next.push((gray[i] + gray[i + 1] + gray[i + width] + gray[i + width + 1]) / 4);
+            }
+        }
+        gray = next;
+        width = next_width;
+        height = next_height;
+        pyramid.push(bitmaps(&gray, width, height));
+    }
+    pyramid
+}
+
+fn bitmaps(gray: &[i32], width: usize, height: usize) -> Bitmaps {
+    let mut histogram = [0usize; 256];
+    for value in gray {
+        histogram[(*value).clamp(0, 255) as usize] += 1;
+    }
+    let half = gray.len() / 2;
+    let mut count = 0;
+    let mut median = 0;
+    for (value, n) in histogram.iter().enumerate() {
+        count += n;
+        if count > half {
+            median = value as i32;
+            break;
+        }
+    }
+
+    Bitmaps {
+        width,
+        height,
+        threshold: gray.iter().map(|v| *v > median).collect(),
+        exclusion: gray
+            .iter()
+            .map(|v| (v - median).abs() > MTB_EXCLUSION)
+            .collect(),
+    }
+}
+
+// quality の推定段より細かい段は、間引いた画素で前後 1 画素だけ詰める（Fast では詰めずに拡大する）
+fn estimate_shift(
+    reference: &[Bitmaps],
+    target: &[Bitmaps],
+    max_shift: i32,
+    quality: MergeQuality,
+) -> [i32; 2] {
+    let levels = reference.len().min(target.len());
+    let first = quality.estimation_level().min(levels - 1);
+    let mut shift = [0i32, 0i32];
+
+    for level in (0..levels).rev() {
+        if level < first && !quality.refines_at_full() {
+            shift = [shift[0] << (level + 1), shift[1] << (level + 1)];
+            break;
+        }
+        let error_at = |candidate: [i32; 2]| {
+            if level < first {
+                let transform = AlignTransform::translation(candidate);
+                warped_error(&reference[level], &target[level], &transform, 1.0)
+            } else {
+                shifted_error(&reference[level], &target[level], candidate)
+            }
+        };
+        let base = [shift[0] * 2, shift[1] * 2];
+        let base = if level == levels - 1 { [0, 0] } else { base };
+        // 誤差が同じなら移動量の小さい候補を優先する
+        let mut best = base;
+        let mut best_error = error_at(base);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let candidate = [base[0] + dx, base[1] + dy];
+                if candidate == base {
+                    continue;
+                }
+                let error = error_at(candidate);
+                if error < best_error {
+                    best_error = error;
+                    best = candidate;
+                }
+            }
+        }
+        shift = best;
+    }
+
+    [
+        shift[0].clamp(-max_shift, max_shift),
+        shift[1].clamp(-max_shift, max_shift),
+    ]
+}
+
+// 重なり領域の広さに依存しないよう、不一致画素の割合で評価する
+fn shifted_error(reference: &Bitmaps, target: &Bitmaps, shift: [i32; 2]) -> f64 {
+    let mut error = 0usize;
+    let mut overlap = 0usize;
+    for y in 0..reference.height as i32 {
+        let ty = y + shift[1];
+        if ty < 0 || ty >= target.height as i32 {
+            continue;
+        }
+        for x in 0..reference.width as i32 {
+            let tx = x + shift[0];
+            if tx < 0 || tx >= target.width as i32 {
+                continue;
+            }
+            let r = y as usize * reference.width + x as usize;
+            let t = ty as usize * target.width + tx as usize;
+            overlap += 1;
+            if reference.exclusion[r]
+                && target.exclusion[t]
+                && reference.threshold[r] != target.threshold[t]
+            {
+                error += 1;
+            }
+        }
+    }
+    if overlap == 0 {
+        return f64::MAX;
+    }
+    error as f64 / overlap as f64
+}
+
+// 平行移動の結果から、粗いピラミッド段で回転・拡大率を総当たりし、細かい段で座標ごとに詰める
+fn estimate_similarity(
+    reference: &[Bitmaps],
+    target: &[Bitmaps],
+    offset: [i32; 2],
+    params: &AlignParams,
+    quality: MergeQuality,
+) -> AlignTransform {
+    let levels = reference.len().min(target.len());
+    let first = quality.estimation_level().min(levels - 1);
+    let max_shift = params.max_shift as f64;
+    let mut best = AlignTransform::translation(offset);
+
+    for level in (0..levels).rev() {
+        if level < first && !quality.refines_at_full() {
+            break;
+        }
+        let (reference, target) = (&reference[level], &target[level]);
+        let unit = (1u32 << level) as f64;
+        // その段で画像の隅がおよそ 1 画素動く量を刻み幅にする
+        let radius = (reference.width as f64).hypot(reference.height as f64) / 2.0;
+        let steps = [unit, unit, (1.0 / radius).to_degrees(), 1.0 / radius];
+        let mut best_error = warped_error(reference, target, &best, unit);
+
+        if level == levels - 1 {
+            let rotations = grid(params.max_rotation, steps[2]);
+            let scales = grid(params.max_scale, steps[3]);
+            let start = best;
+            for rotation in &rotations {
+                for scale in &scales {
+                    let candidate = AlignTransform {
+                        rotation: *rotation,
+                        scale: 1.0 + scale,
+                        ..start
+                    };
+                    let error = warped_error(reference, target, &candidate, unit);
+                    if error < best_error {
+                        best_error = error;
+                        best = candidate;
+                    }
+                }
+            }
+        }
+
+        for _ in 0..MAX_REFINE_ITERATIONS {
+            let mut improved = false;
+            for (axis, step) in steps.iter().enumerate() {
+                for sign in [-1.0, 1.0] {
+                    let mut values = [best.dx, best.dy, best.rotation, best.scale - 1.0];
+                    values[axis] += sign * step;
+                    let candidate = AlignTransform {
+                        dx: values[0].clamp(-max_shift, max_shift),
+                        dy: values[1].clamp(-max_shift, max_shift),
+                        rotation: values[2].clamp(-params.max_rotation, params.max_rotation),
+                        scale: 1.0 + values[3].clamp(-params.max_scale, params.max_scale),
+                    };
+                    let error = warped_error(reference, target, &candidate, unit);
+                    if error < best_error {
+                        best_error = error;
+                        best = candidate;
+                        improved = true;
+                    }
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+    }
+    best
+}
+
+// 0 を中心に step 刻みで ±limit の範囲を並べる
+fn grid(limit: f64, step: f64) -> Vec<f64> {
+    let count = (limit / step).floor().min(MAX_GRID_STEPS as f64) as i32;
+    let step = if count > 0 { limit / count as f64 } else { 0.0 };
+    (-count..=count).map(|i| i as f64 * step).collect()
+}
+
+// shifted_error と同じ基準を、回転・拡大を含む対応で評価する。大きな段は間引いて数える
+fn warped_error(
+    reference: &Bitmaps,
+    target: &Bitmaps,
+    transform: &AlignTransform,
+    unit: f64,
+) -> f64 {
+    let map = transform.mapper(reference.width, reference.height, unit);
+    let pixels = reference.width * reference.height;
+    let stride = ((pixels / MAX_ERROR_SAMPLES) as f64).sqrt().ceil().max(1.0) as usize;
+    let mut error = 0usize;
+    let mut overlap = 0usize;
+    for y in (0..reference.height).step_by(stride) {
+        for x in (0..reference.width).step_by(stride) {
+            let (tx, ty) = map(x as f64, y as f64);
+            let (tx, ty) = (tx.round(), ty.round());
+            if tx < 0.0 || ty < 0.0 || tx >= target.width as f64 || ty >= target.height as f64 {
+                continue;
+            }
+            let r = y * reference.width + x;
+            let t = ty as usize * target.width + tx as usize;
+            overlap += 1;
+            if reference.exclusion[r]
+                && target.exclusion[t]
+                && reference.threshold[r] != target.threshold[t]
+            {
+                error += 1;
+            }
+        }
+    }
+    if overlap == 0 {
+        return f64::MAX;
+    }
+    error as f64 / overlap as f64
+}
+
+// 原寸の段で前後 1 画素の誤差に放物線を当てはめ、平行移動を 1 画素未満まで詰める
+fn refine_subpixel(
+    reference: &Bitmaps,
+    target: &Bitmaps,
+    transform: AlignTransform,
+) -> AlignTransform {
+    let center = warped_error(reference, target, &transform, 1.0);
+    let mut refined = transform;
+    for axis in 0..2 {
+        let error_at = |delta: f64| {
+            let mut candidate = transform;
+            match axis {
+                0 => candidate.dx += delta,
+                _ => candidate.dy += delta,
+            }
+            warped_error(reference, target, &candidate, 1.0)
+        };
+        let (before, after) = (error_at(-1.0), error_at(1.0));
+        let curvature = before - 2.0 * center + after;
+        // 中央が極小でなければ整数の推定値のままにする
+        if curvature <= 0.0 || before < center || after < center {
+            continue;
+        }
+        let delta = ((before - after) / (2.0 * curvature)).clamp(-0.5, 0.5);
+        match axis {
+            0 => refined.dx += delta,
+            _ => refined.dy += delta,
+        }
+    }
+    refined
+}
+
+// 画像の外にはみ出した部分は端の画素で埋める
+pub fn warp_image(
+    image: &Rgb16Image,
+    transform: &AlignTransform,
+    precision: AlignPrecision,
+) -> Rgb16Image {
+    if precision == AlignPrecision::Pixel && transform.is_translation() {
+        return shift_image(image, transform.offset());
+    }
+    if *transform == AlignTransform::IDENTITY {
+        return image.clone();
+    }
+    let map = transform.mapper(image.width() as usize, image.height() as usize, 1.0);
+    let max_x = image.width() as f64 - 1.0;
+    let max_y = image.height() as f64 - 1.0;
+    Rgb16Image::from_fn(image.width(), image.height(), |x, y| {
+        let (sx, sy) = map(x as f64, y as f64);
+        let (sx, sy) = (sx.clamp(0.0, max_x), sy.clamp(0.0, max_y));
+        match precision {
+            AlignPrecision::Pixel => bilinear(image, sx, sy),
+            AlignPrecision::Subpixel => lanczos(image, sx, sy),
+        }
+    })
+}
+
+fn lanczos(image: &Rgb16Image, x: f64, y: f64) -> Rgb<u16> {
+    let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+    let weights_x = lanczos_weights(x - x0 as f64);
+    let weights_y = lanczos_weights(y - y0 as f64);
+    let max_x = image.width() as i64 - 1;
+    let max_y = image.height() as i64 - 1;
+    let mut sum = [0.0f64; 3];
+    let mut total = 0.0;
+    for (j, wy) in weights_y.iter().enumerate() {
+        let sy = (y0 + j as i64 + 1 - LANCZOS_A as i64).clamp(0, max_y) as u32;
+        for (i, wx) in weights_x.iter().enumerate() {
+            let sx = (x0 + i as i64 + 1 - LANCZOS_A as i64).clamp(0, max_x) as u32;
+            let weight = wx * wy;
+            let pixel = image.get_pixel(sx, sy);
+            for c in 0..3 {
+                sum[c] += pixel[c] as f64 * weight;
+            }
+            total += weight;
+        }
+    }
+    Rgb(sum.map(|value| (value / total).round().clamp(0.0, u16::MAX as f64) as u16))
+}
+
+// floor(x) - A + 1 から floor(x) + A までの各画素に掛ける重み
+fn lanczos_weights(fraction: f64) -> [f64; 2 * LANCZOS_A] {
+    std::array::from_fn(|i| {
+        let distance = fraction - (i as f64 + 1.0 - LANCZOS_A as f64);
+        lanczos_kernel(distance)
+    })
+}
+
+fn lanczos_kernel(x: f64) -> f64 {
+    let a = LANCZOS_A as f64;
+    if x.abs() < 1e-9 {
+        1.0
+    } else if x.abs() >= a {
+        0.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        a * px.sin() * (px / a).sin() / (px * px)
+    }
+}
+
+fn bilinear(image: &Rgb16Image, x: f64, y: f64) -> Rgb<u16> {
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let x1 = (x0 + 1).min(image.width() - 1);
+    let y1 = (y0 + 1).min(image.height() - 1);
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let p00 = image.get_pixel(x0, y0);
+    let p10 = image.get_pixel(x1, y0);
+    let p01 = image.get_pixel(x0, y1);
+    let p11 = image.get_pixel(x1, y1);
+    Rgb(std::array::from_fn(|c| {
+        let top = p00[c] as f64 * (1.0 - fx) + p10[c] as f64 * fx;
+        let bottom = p01[c] as f64 * (1.0 - fx) + p11[c] as f64 * fx;
+        (top * (1.0 - fy) + bottom * fy).round() as u16
+    }))
+}
+
+pub fn shift_image(image: &Rgb16Image, offset: [i32; 2]) -> Rgb16Image {
+    if offset == [0, 0] {
+        return image.clone();
+    }
+    let max_x = image.width() as i32 - 1;
+    let max_y = image.height() as i32 - 1;
+    Rgb16Image::from_fn(image.width(), image.height(), |x, y| {
+        let sx = (x as i32 + offset[0]).clamp(0, max_x) as u32;
+        let sy = (y as i32 + offset[1]).clamp(0, max_y) as u32;
+        *image.get_pixel(sx, sy)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 縦横どちらの方向にも構造を持つ、露出だけが異なる市松状のパターン
+    fn textured(gain: f64) -> Rgb16Image {
+        Rgb16Image::from_fn(128, 96, |x, y| {
+            let fx = x as f64 / 4.0;
+            let fy = y as f64 / 3.0;
+            let value =
+                (0.5 + 0.25 * fx.sin() * fy.cos() + 0.2 * (fx * 0.3 + fy * 0.5).sin()) * gain;
+            let v = (value.clamp(0.0, 1.0) * u16::MAX as f64) as u16;
+            Rgb([v, v, v])
+        })
+    }
+
+    #[test]
+    fn recovers_translation_between_exposures() {
+        let frames = vec![textured(1.0), shift_image(&textured(0.6), [3, -2])];
+
+        let (_, transforms) = align_frames(&frames, 0, &AlignParams::default(), MergeQuality::Best);
+
+        let offsets: Vec<[i32; 2]> = transforms.iter().map(|t| t.offset()).collect();
+        assert_eq!(offsets, vec![[0, 0], [-3, 2]]);
+    }
+
+    #[test]
+    fn keeps_static_frames_in_place() {
+        let frames = vec![textured(1.0), textured(0.5), textured(1.4)];
+
+        let (_, transforms) = align_frames(&frames, 1, &AlignParams::default(), MergeQuality::Best);
+
+        assert_eq!(transforms, vec![AlignTransform::IDENTITY; 3]);
+    }
+
+    // 大きな明暗の塊と細かい模様を重ねた、回転の手がかりがある画像
+    fn scene(gain: f64) -> Rgb16Image {
+        Rgb16Image::from_fn(192, 144, |x, y| {
+            let (fx, fy) = (x as f64, y as f64);
+            let blocks = if (x / 24 + y / 18) % 3 == 0 {
+                0.25
+            } else {
+                0.0
+            };
+            let value = (0.35 + blocks + 0.15 * (fx / 9.0).sin() * (fy / 7.0).cos()) * gain;
+            let v = (value.clamp(0.0, 1.0) * u16::MAX as f64) as u16;
+            Rgb([v, v, v])
+        })
+    }
+
+    #[test]
+    fn similarity_recovers_small_rotation_and_scale() {
+        let rotated = warp_image(
+            &scene(0.7),
+            &AlignTransform {
+                dx: 2.0,
+                dy: -1.0,
+                rotation: 1.5,
+                scale: 1.01,
+            },
+            AlignPrecision::Pixel,
+        );
+        let params = AlignParams {
+            model: AlignModel::Similarity,
+            ..Default::default()
+        };
+
+        let (_, transforms) =
+            align_frames(&[scene(1.0), rotated], 0, &params, MergeQuality::Balanced);
+
+        // rotated はフレームを逆向きに変換したものなので、推定値は逆変換に近くなる
+        let estimate = transforms[1];
+        assert!((estimate.rotation + 1.5).abs() < 0.3, "{:?}", estimate);
+        assert!(
+            (estimate.scale - 1.0 / 1.01).abs() < 0.006,
+            "{:?}",
+            estimate
+        );
+    }
+
+    #[test]
+    fn subpixel_recovers_fractional_shift() {
+        let shifted = warp_image(
+            &scene(0.6),
+            &AlignTransform {
+                dx: 1.5,
+                dy: -0.25,
+                ..AlignTransform::IDENTITY
+            },
+            AlignPrecision::Subpixel,
+        );
+        let params = AlignParams {
+            align_precision: AlignPrecision::Subpixel,
+            ..Default::default()
+        };
+
+        let (_, transforms) =
+            align_frames(&[scene(1.0), shifted], 0, &params, MergeQuality::Balanced);
+
+        let estimate = transforms[1];
+        assert!((estimate.dx + 1.5).abs() < 0.3, "{:?}", estimate);
+        assert!((estimate.dy - 0.25).abs() < 0.3, "{:?}", estimate);
+    }
+
+    #[test]
+    fn lanczos_keeps_integer_samples() {
+        let image = scene(1.0);
+
+        let warped = warp_image(
+            &image,
+            &AlignTransform::translation([2, -1]),
+            AlignPrecision::Subpixel,
+        );
+
+        assert_eq!(warped, shift_image(&image, [2, -1]));
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::align::AlignTransform;
+use crate::geometry::Roi;
+use crate::paths;
+
+const SIDECAR_VERSION: u32 = 1;
+
+// align ステージで推定した変換の保存形式。同じブラケットを別の設定で合成し直すときに transformsPath で渡す
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransformSidecar {
+    pub version: u32,
+    // 合成に使った順序（暗い順）のファイル名。フォルダを移しても使えるようにパスは含めない
+    pub files: Vec<String>,
+    // 位置合わせした画像の大きさ（roi を指定した場合は切り出し後）
+    pub width: u32,
+    pub height: u32,
+    pub roi: Option<Roi>,
+    pub transforms: Vec<AlignTransform>,
+}
+
+impl TransformSidecar {
+    pub fn new(
+        paths: &[String],
+        (width, height): (u32, u32),
+        roi: Option<Roi>,
+        transforms: Vec<AlignTransform>,
+    ) -> Self {
+        Self {
+            version: SIDECAR_VERSION,
+            files: file_names(paths),
+            width,
+            height,
+            roi,
+            transforms,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(paths::extended(path), text)
+            .map_err(|e| format!("位置合わせの変換を保存できません: {}", e))
+    }
+}
+
+// 保存した変換を読み込み、今回の入力と同じブラケット・同じ大きさで推定したものか確かめる
+pub fn load(
+    path: &str,
+    paths: &[String],
+    size: (u32, u32),
+    roi: Option<Roi>,
+) -> Result<Vec<AlignTransform>, String> {
+    let file = paths::input_file(path)?;
+    let text =
+        fs::read_to_string(file).map_err(|e| format!("位置合わせの変換を読み込めません: {}", e))?;
+    let sidecar: TransformSidecar = serde_json::from_str(&text)
+        .map_err(|e| format!("位置合わせの変換の形式が不正です: {}", e))?;
+    if sidecar.version != SIDECAR_VERSION {
+        return Err(format!(
+            "対応していない変換ファイルのバージョンです: {}",
+            sidecar.version
+        ));
+    }
+    if sidecar.files != file_names(paths) || sidecar.transforms.len() != paths.len() {
+        return Err("変換ファイルの入力が今回の入力と一致しません".to_string());
+    }
+    if (sidecar.width, sidecar.height) != size || sidecar.roi != roi {
+        return Err("変換ファイルの画像サイズまたは roi が今回の合成と一致しません".to_string());
+    }
+    Ok(sidecar.transforms)
+}
+
+fn file_names(paths: &[String]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| {
+            Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_rejects_other_brackets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hdr_transforms.json");
+        let inputs = vec!["/a/dark.jpg".to_string(), "/a/bright.jpg".to_string()];
+        let transforms = vec![
+            AlignTransform::IDENTITY,
+            AlignTransform {
+                dx: 1.5,
+                dy: -2.0,
+                rotation: 0.3,
+                scale: 1.01,
+            },
+        ];
+        TransformSidecar::new(&inputs, (64, 48), None, transforms.clone())
+            .save(&path)
+            .unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        // 別のフォルダに移した同じファイル名の入力には使える
+        let moved = vec!["/b/dark.jpg".to_string(), "/b/bright.jpg".to_string()];
+        assert_eq!(load(&path, &moved, (64, 48), None).unwrap(), transforms);
+
+        let reversed: Vec<String> = inputs.iter().rev().cloned().collect();
+        assert!(load(&path, &reversed, (64, 48), None).is_err());
+        assert!(load(&path, &inputs, (32, 24), None).is_err());
+        let roi = Roi {
+            x: 0,
+            y: 0,
+            width: 64,
+            height: 48,
+        };
+        assert!(load(&path, &inputs, (64, 48), Some(roi)).is_err());
+    }
+}
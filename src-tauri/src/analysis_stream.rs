@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+// 1 回の通知に含める件数
+const BATCH_SIZE: usize = 25;
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisError {
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisBatch<T> {
+    pub job_id: String,
+    // この通知で新しく解析できた分
+    pub stats: Vec<T>,
+    pub errors: Vec<AnalysisError>,
+    pub processed: usize,
+    pub total: usize,
+    // 最後の通知だけ true。中止したときは cancelled も true になる
+    pub done: bool,
+    pub cancelled: bool,
+}
+
+// 実行中の解析ジョブの中止フラグ
+#[derive(Default)]
+pub struct AnalysisJobs {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl AnalysisJobs {
+    pub fn start(&self) -> Result<(String, Arc<AtomicBool>), String> {
+        let id = format!(
+            "analysis-{}",
+            self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id.clone(), cancelled.clone());
+        Ok((id, cancelled))
+    }
+
+    // 実行中のジョブが見つかったときだけ true を返す
+    pub fn cancel(&self, job_id: &str) -> Result<bool, String> {
+        let running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(match running.get(job_id) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        })
+    }
+
+    pub fn finish(&self, job_id: &str) {
+        self.running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(job_id);
+    }
+}
+
+// paths を順に解析し、BATCH_SIZE 件ごとに emit する。1 件の失敗では止めず errors に入れる
+pub fn run<T>(
+    job_id: &str,
+    paths: &[String],
+    cancelled: &AtomicBool,
+    analyze: impl Fn(&str) -> Result<T, String>,
+    emit: impl Fn(AnalysisBatch<T>),
+) {
+    let total = paths.len();
+    let mut processed = 0;
+    for chunk in paths.chunks(BATCH_SIZE) {
+        let mut stats = Vec::new();
+        let mut errors = Vec::new();
+        for path in chunk {
+            if cancelled.load(Ordering::Relaxed) {
+                break;
+            }
+            match analyze(path) {
+                Ok(stat) => stats.push(stat),
+                Err(message) => errors.push(AnalysisError {
+                    path: path.clone(),
+                    message,
+                }),
+            }
+            processed += 1;
+        }
+        let is_cancelled = cancelled.load(Ordering::Relaxed);
+        emit(AnalysisBatch {
+            job_id: job_id.to_string(),
+            stats,
+            errors,
+            processed,
+            total,
+            done: is_cancelled || processed == total,
+            cancelled: is_cancelled,
+        });
+        if is_cancelled {
+            return;
+        }
+    }
+    if total == 0 {
+        emit(AnalysisBatch {
+            job_id: job_id.to_string(),
+            stats: Vec::new(),
+            errors: Vec::new(),
+            processed: 0,
+            total: 0,
+            done: true,
+            cancelled: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn paths(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("{}.jpg", i)).collect()
+    }
+
+    #[test]
+    fn emits_batches_and_collects_errors() {
+        let batches = RefCell::new(Vec::new());
+        let cancelled = AtomicBool::new(false);
+
+        run(
+            "analysis-1",
+            &paths(60),
+            &cancelled,
+            |path| match path {
+                "7.jpg" => Err("読み込めません".to_string()),
+                _ => Ok(path.len()),
+            },
+            |batch| batches.borrow_mut().push(batch),
+        );
+
+        let batches = batches.into_inner();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].stats.len(), BATCH_SIZE - 1);
+        assert_eq!(batches[0].errors[0].path, "7.jpg");
+        assert_eq!(batches[2].processed, 60);
+        assert!(batches[2].done && !batches[2].cancelled);
+        assert!(batches[..2].iter().all(|batch| !batch.done));
+    }
+
+    #[test]
+    fn stops_after_cancel() {
+        let jobs = AnalysisJobs::default();
+        let (job_id, cancelled) = jobs.start().unwrap();
+        let batches = RefCell::new(Vec::new());
+
+        run(
+            &job_id,
+            &paths(100),
+            &cancelled,
+            |path| {
+                if path == "30.jpg" {
+                    jobs.cancel(&job_id).unwrap();
+                }
+                Ok(())
+            },
+            |batch| batches.borrow_mut().push(batch),
+        );
+        jobs.finish(&job_id);
+
+        let batches = batches.into_inner();
+        let last = batches.last().unwrap();
+        assert!(last.done && last.cancelled);
+        assert_eq!(last.processed, 31);
+        assert!(!jobs.cancel(&job_id).unwrap());
+    }
+}
@@ -0,0 +1,332 @@
+// フロントエンドへ送るイベントの名前と、ペイロードの型。
+// ここに足したイベントは、テストで書き出す src/types/api.ts の EventPayloads にも入る
+macro_rules! events {
+    ($($name:ident = $event:literal => $payload:ty,)*) => {
+        $(pub const $name: &str = $event;)*
+
+        #[cfg(test)]
+        fn event_payloads(visitor: &mut impl ts_rs::TypeVisitor) -> Vec<(&'static str, String)> {
+            vec![$({
+                visitor.visit::<$payload>();
+                <$payload as ts_rs::TS>::visit_generics(visitor);
+                ($event, <$payload as ts_rs::TS>::name())
+            }),*]
+        }
+    };
+}
+
+events! {
+    FILE_DETECTED_EVENT = "hdr://file-detected" => String,
+    OPEN_FILES_EVENT = "hdr://open-files" => crate::launch::LaunchRequest,
+    // 起動中のアプリに渡された画像の枚数が合わず、下書きを作れなかった
+    LAUNCH_REJECTED_EVENT = "hdr://launch-rejected" => crate::launch::LaunchRejected,
+    SECOND_INSTANCE_EVENT = "hdr://second-instance" => crate::SecondInstance,
+    // 終了を待っている合成の数
+    SHUTDOWN_WAITING_EVENT = "hdr://shutdown-waiting" => usize,
+    BRACKET_TIMEOUT_EVENT = "hdr://bracket-timeout" => crate::grouping::BracketGroup,
+    MERGE_PROGRESS_EVENT = "hdr://merge-progress" => crate::progress::MergeProgress,
+    BACKLOG_EVENT = "hdr://merge-backlog" => crate::jobs::MergeBacklog,
+    ANALYSIS_PROGRESS_EVENT = "hdr://analysis-progress"
+        => crate::analysis_stream::AnalysisBatch<crate::ImageStat>,
+    JOB_FAILED_EVENT = "hdr://job-failed" => crate::panic_report::JobFailure,
+    RESOURCE_USAGE_EVENT = "hdr://resource-usage" => crate::resources::ResourceUsage,
+    // アイドル状態に入ったら true、抜けたら false
+    IDLE_EVENT = "hdr://idle-changed" => bool,
+    DASHBOARD_EVENT = "hdr://dashboard" => crate::dashboard::DashboardSummary,
+    // 自動合成を止めているアプリ。再開したら空
+    AUTO_PAUSE_EVENT = "hdr://auto-pause" => Vec<String>,
+}
+
+// フロントエンドの src/types/api.ts は、Rust の型から ts-rs で導いた宣言を cargo test で書き出す。
+// ペイロードや引数の型を変えたらテストが失敗するので、書き出し直す
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use serde_json::Value;
+    use ts_rs::{TypeVisitor, TS};
+
+    use crate::algorithms::AlgorithmInfo;
+    use crate::api_version::{ApiNegotiation, MergeRequestV2};
+    use crate::batch::{BatchRequest, BatchResult};
+    use crate::bracket_recommend::BracketRecommendation;
+    use crate::capabilities::Capabilities;
+    use crate::clipboard::ClipboardCopy;
+    use crate::compare::CompareResult;
+    use crate::compare_tiles::{CompareTiles, TileSpec};
+    use crate::config::{ImportSummary, Preset, Settings};
+    use crate::dashboard::DashboardSummary;
+    use crate::deflicker::{DeflickerOptions, DeflickerResult};
+    use crate::detection_log::{Detection, DetectionKind, DetectionOutcome};
+    use crate::disk_cache::CacheUsage;
+    use crate::false_color::{FalseColorMode, FalseColorResult};
+    use crate::folder_stats::WatchFolderStats;
+    use crate::frame_quality::FrameQuality;
+    use crate::grouping::{BracketGroup, GroupInput, GroupingRules};
+    use crate::hdr_preview::HdrPreviewParams;
+    use crate::history::{HistoryEntry, HistoryFilter, HistoryPage, StaleReprocess};
+    use crate::input_check::InputCheck;
+    use crate::jobs::{MergeBacklog, PendingJob};
+    use crate::launch::{DropSuggestion, LaunchRequest};
+    use crate::maintenance::CleanupReport;
+    use crate::merge::{MergeMode, MergeRequest, MergeResult};
+    use crate::pending_groups::PendingGroup;
+    use crate::probe::ProbeResult;
+    use crate::projects::Project;
+    use crate::recycle::DeleteReport;
+    use crate::report::ReportFormat;
+    use crate::send_to::SentOutput;
+    use crate::social_export::{SocialExport, SocialPlatform};
+    use crate::soft_proof::{SoftProofParams, SoftProofResult};
+    use crate::stats::UsageStats;
+    use crate::sweep::SweepPreview;
+    use crate::synthetic::{TestBracket, TestBracketOptions};
+    use crate::timelapse::{TimelapseRequest, TimelapseResult};
+    use crate::ImageStat;
+
+    const BINDINGS_PATH: &str = "../src/types/api.ts";
+
+    // コマンドの引数（Tauri が受け取る camelCase の名前）と戻り値。lib.rs の #[tauri::command] と合わせる
+    macro_rules! commands {
+        ($($command:ident { $($arg:ident: $ty:ty),* $(,)? } -> $result:ty;)*) => {
+            fn command_signatures(visitor: &mut Declarations) -> Vec<String> {
+                vec![$({
+                    let args: Vec<String> = vec![$({
+                        visit_root::<$ty>(visitor);
+                        argument(stringify!($arg), &<$ty as TS>::name())
+                    }),*];
+                    visit_root::<$result>(visitor);
+                    let args = match args.is_empty() {
+                        true => "{}".to_string(),
+                        false => format!("{{ {} }}", args.join("; ")),
+                    };
+                    format!(
+                        "  {}: {{ args: {}; result: {} }};\n",
+                        stringify!($command),
+                        args,
+                        <$result as TS>::name()
+                    )
+                }),*]
+            }
+        };
+    }
+
+    commands! {
+        watcher_set_folder { folder: String } -> ();
+        watcher_start {} -> ();
+        watcher_stop {} -> ();
+        watcher_is_running {} -> bool;
+        watcher_is_idle {} -> bool;
+        watcher_inject_event { path: String, kind: DetectionKind } -> DetectionOutcome;
+        watch_folder_stats {} -> WatchFolderStats;
+        detection_log_entries { limit: Option<usize> } -> Vec<Detection>;
+        detection_log_export { path: String } -> usize;
+        analyze_images { paths: Vec<String> } -> Vec<ImageStat>;
+        analyze_images_stream { paths: Vec<String> } -> String;
+        analyze_images_cancel { jobId: String } -> bool;
+        recommend_bracket { path: String } -> BracketRecommendation;
+        get_thumbnail { path: String, maxSize: Option<u32> } -> String;
+        copy_result_to_clipboard { id: Option<u64>, path: Option<String> } -> ClipboardCopy;
+        get_hdr_preview { path: String, params: Option<HdrPreviewParams> } -> String;
+        get_soft_proof { path: String, params: Option<SoftProofParams> } -> SoftProofResult;
+        cache_stats {} -> Vec<CacheUsage>;
+        validate_merge_inputs { paths: Vec<String> } -> Vec<InputCheck>;
+        suggest_frame_exclusions { paths: Vec<String> } -> FrameQuality;
+        group_images { inputs: Vec<GroupInput>, rules: Option<GroupingRules> } -> Vec<BracketGroup>;
+        settings_get {} -> Settings;
+        settings_set { settings: Settings } -> ();
+        write_root_add {} -> Vec<String>;
+        write_root_remove { path: String } -> Vec<String>;
+        plugin_allow_add {} -> Vec<String>;
+        plugin_allow_remove { path: String } -> Vec<String>;
+        presets_list {} -> Vec<Preset>;
+        preset_save { preset: Preset } -> ();
+        preset_delete { name: String } -> ();
+        project_create {
+            name: String,
+            watchFolder: Option<String>,
+            outputDir: Option<String>,
+        } -> Project;
+        project_list {} -> Vec<Project>;
+        project_open { id: String } -> Project;
+        project_close {} -> ();
+        project_current {} -> Option<Project>;
+        history_list { filter: Option<HistoryFilter> } -> Vec<HistoryEntry>;
+        history_search {
+            query: Option<String>,
+            from: Option<String>,
+            to: Option<String>,
+            tags: Option<Vec<String>>,
+            offset: Option<usize>,
+            limit: Option<usize>,
+        } -> HistoryPage;
+        history_export {
+            format: ReportFormat,
+            path: String,
+            filter: Option<HistoryFilter>,
+        } -> usize;
+        history_set_rating { id: u64, stars: u8 } -> HistoryEntry;
+        history_set_tags { id: u64, tags: Vec<String> } -> HistoryEntry;
+        history_send_to { id: u64, target: String } -> SentOutput;
+        export_social {
+            historyId: u64,
+            platform: SocialPlatform,
+            outputDir: Option<String>,
+        } -> SocialExport;
+        config_export { path: String } -> ();
+        config_import { path: String } -> ImportSummary;
+        list_merge_algorithms {} -> Vec<AlgorithmInfo>;
+        api_negotiate { clientVersion: u32 } -> ApiNegotiation;
+        get_capabilities {} -> Capabilities;
+        merge_hdr { request: MergeRequest } -> MergeResult;
+        merge_hdr_v2 { request: MergeRequestV2 } -> MergeResult;
+        group_create { paths: Vec<String>, mergeMode: Option<MergeMode> } -> PendingGroup;
+        group_add_file { groupId: u64, path: String } -> PendingGroup;
+        group_remove_file { groupId: u64, path: String } -> PendingGroup;
+        group_list {} -> Vec<PendingGroup>;
+        group_delete { groupId: u64 } -> PendingGroup;
+        group_merge {
+            groupId: u64,
+            preset: Option<String>,
+            automatic: Option<bool>,
+        } -> MergeResult;
+        merge_backlog {} -> MergeBacklog;
+        merge_cancel { jobId: String } -> bool;
+        merge_prefetch { paths: Vec<String> } -> bool;
+        history_reprocess_stale { presetName: String } -> StaleReprocess;
+        recent_folders_list {} -> Vec<String>;
+        recent_outputs_list {} -> Vec<String>;
+        take_launch_request {} -> Option<LaunchRequest>;
+        handle_dropped_paths { paths: Vec<String> } -> DropSuggestion;
+        delete_to_recycle { paths: Vec<String> } -> DeleteReport;
+        maintenance_cleanup {} -> CleanupReport;
+        jobs_pending {} -> Vec<PendingJob>;
+        jobs_resume { index: usize } -> MergeResult;
+        jobs_clear_pending {} -> ();
+        stats_get {} -> UsageStats;
+        stats_reset {} -> ();
+        merge_sweep {
+            request: MergeRequest,
+            parameterGrid: BTreeMap<String, Vec<Value>>,
+        } -> Vec<SweepPreview>;
+        merge_timelapse { request: TimelapseRequest } -> TimelapseResult;
+        merge_batch { request: BatchRequest } -> BatchResult;
+        deflicker_sequence { folder: String, options: Option<DeflickerOptions> } -> DeflickerResult;
+        compare_images { pathA: String, pathB: String } -> CompareResult;
+        generate_compare_tiles {
+            sourcePath: String,
+            mergedPath: String,
+            tileSpec: Option<TileSpec>,
+        } -> CompareTiles;
+        generate_false_color { path: String, mode: FalseColorMode } -> FalseColorResult;
+        probe_pixels {
+            path: String,
+            x: u32,
+            y: u32,
+            radius: u32,
+            sourcePaths: Option<Vec<String>>,
+        } -> ProbeResult;
+        generate_test_bracket {
+            outputDir: String,
+            options: Option<TestBracketOptions>,
+        } -> TestBracket;
+        auto_pause_status {} -> Vec<String>;
+        dashboard_summary {} -> DashboardSummary;
+    }
+
+    // Option の引数は省略すると None になる
+    fn argument(name: &str, ty: &str) -> String {
+        match ty.strip_suffix(" | null") {
+            Some(_) => format!("{}?: {}", name, ty),
+            None => format!("{}: {}", name, ty),
+        }
+    }
+
+    // たどった型の宣言を名前ごとに集める。Vec や Option など宣言を持たない型の中身は、
+    // visit_dependencies・visit_generics が中の型を visit してくる
+    #[derive(Default)]
+    struct Declarations(BTreeMap<String, String>);
+
+    impl TypeVisitor for Declarations {
+        fn visit<T: TS + 'static + ?Sized>(&mut self) {
+            if T::output_path().is_none() || self.0.contains_key(&T::ident()) {
+                return;
+            }
+            self.0.insert(T::ident(), T::decl());
+            T::visit_dependencies(self);
+            T::visit_generics(self);
+        }
+    }
+
+    // コマンドやイベントに直接出てくる型。Vec<T> などのときは中の T から宣言を集める
+    fn visit_root<T: TS + 'static>(visitor: &mut impl TypeVisitor) {
+        visitor.visit::<T>();
+        T::visit_generics(visitor);
+    }
+
+    fn typescript() -> String {
+        let mut declarations = Declarations::default();
+        let events = event_payloads(&mut declarations);
+        let commands = command_signatures(&mut declarations);
+
+        let mut text = String::from(
+            "// src-tauri/src/api_schema.rs から生成したファイルです。直接編集せず、\n\
+             // VHDR_UPDATE_API_TYPES=1 cargo test api_schema で書き出し直してください\n",
+        );
+        for declaration in declarations.0.values() {
+            text.push_str(&format!("\nexport {}\n", declaration));
+        }
+        text.push_str("\nexport interface EventPayloads {\n");
+        for (event, payload) in events {
+            text.push_str(&format!("  \"{}\": {};\n", event, payload));
+        }
+        text.push_str("}\n\nexport interface Commands {\n");
+        for command in commands {
+            text.push_str(&command);
+        }
+        text.push_str("}\n");
+        text
+    }
+
+    // serde_json は 64bit の整数も JSON の数値で書き出すので、bigint の型を残さない。
+    // .cargo/config.toml の TS_RS_LARGE_INT で number にしている
+    #[test]
+    fn integers_are_typed_as_numbers() {
+        let text = typescript();
+        let bigint = text
+            .lines()
+            .filter(|line| line.contains("bigint"))
+            .collect::<Vec<_>>();
+        assert!(bigint.is_empty(), "{:#?}", bigint);
+    }
+
+    #[test]
+    fn event_names_are_unique() {
+        let mut events: Vec<&str> = event_payloads(&mut Declarations::default())
+            .into_iter()
+            .map(|(event, _)| event)
+            .collect();
+        let count = events.len();
+        events.sort();
+        events.dedup();
+        assert_eq!(events.len(), count);
+    }
+
+    // 生成したファイルと食い違ったら VHDR_UPDATE_API_TYPES=1 で書き出し直す
+    #[test]
+    fn typescript_bindings_are_up_to_date() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(BINDINGS_PATH);
+        let generated = typescript();
+        if std::env::var_os("VHDR_UPDATE_API_TYPES").is_some() {
+            std::fs::write(&path, &generated).unwrap();
+        }
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            current == generated,
+            "{} が古くなっています。VHDR_UPDATE_API_TYPES=1 cargo test api_schema で更新してください",
+            BINDINGS_PATH
+        );
+    }
+}
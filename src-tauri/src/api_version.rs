@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::algorithms::AlgorithmParams;
+use crate::capabilities::{self, Capabilities};
+use crate::deliverables::Deliverable;
+use crate::exr_color::ExrColorSpace;
+use crate::frame_select::FrameSelection;
+use crate::geometry::{PerspectiveCorners, Roi};
+use crate::gray_card::GrayCardSettings;
+use crate::levels::AutoLevels;
+use crate::merge::{MergeMode, MergeQuality, MergeRequest};
+use crate::output_color::OutputColorSpace;
+use crate::pipeline::PipelineStage;
+
+pub const API_VERSION: u32 = 2;
+// merge_hdr の平らな MergeRequest を v1 とする
+pub const MIN_API_VERSION: u32 = 1;
+
+// v1 の後に追加した機能。フロントエンドは使う前に api_negotiate の features で確かめる
+pub const FEATURES: &[&str] = &[
+    "mergeRequestV2",
+    "layeredExr",
+    "transformSidecar",
+    "autoExcludeBadFrames",
+    "grayCard",
+    "analysisStream",
+    "bracketRecommendation",
+    "noiseStack",
+    "hybridStack",
+    "longExposure",
+    "exifExposureCompensation",
+    "exrPreview",
+    "autoLevels",
+    "exrColorSpace",
+    "outputColorSpace",
+    "dngOutput",
+    "pauseWhileRunning",
+];
+
+#[derive(Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiNegotiation {
+    // 以後のやり取りに使う版。フロントエンドとバックエンドの新しくない方
+    pub api_version: u32,
+    pub latest_version: u32,
+    pub min_version: u32,
+    pub features: Vec<&'static str>,
+    pub capabilities: Capabilities,
+}
+
+pub fn negotiate(client_version: u32) -> Result<ApiNegotiation, String> {
+    if client_version < MIN_API_VERSION {
+        return Err(format!(
+            "API バージョン {} には対応していません（{} 以上）",
+            client_version, MIN_API_VERSION
+        ));
+    }
+    let api_version = client_version.min(API_VERSION);
+    Ok(ApiNegotiation {
+        api_version,
+        latest_version: API_VERSION,
+        min_version: MIN_API_VERSION,
+        // v1 のフロントエンドには新しい機能を知らせない
+        features: if api_version >= 2 {
+            FEATURES.to_vec()
+        } else {
+            Vec::new()
+        },
+        capabilities: capabilities::get(),
+    })
+}
+
+// 項目を用途ごとにまとめた合成要求。各まとまりは省略でき、知らない項目は無視するので、
+// 項目を足しても古いフロントエンドの要求はそのまま受け付けられる
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MergeRequestV2 {
+    pub api_version: u32,
+    pub paths: Vec<String>,
+    pub job_id: Option<String>,
+    pub preset: Option<String>,
+    pub automatic: bool,
+    pub output: OutputOptions,
+    pub processing: ProcessingOptions,
+    pub geometry: GeometryOptions,
+    pub frames: FrameOptions,
+    pub color: ColorOptions,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutputOptions {
+    pub dir: Option<String>,
+    pub template: Option<String>,
+    pub group_name: Option<String>,
+    pub exr: bool,
+    pub layered_exr: bool,
+    pub exr_preview: bool,
+    pub dng: bool,
+    pub exr_color_space: Option<ExrColorSpace>,
+    pub color_space: OutputColorSpace,
+    pub save_transforms: bool,
+    pub clipping_map: bool,
+    pub short_reference: bool,
+    pub deliverables: Vec<Deliverable>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProcessingOptions {
+    pub mode: MergeMode,
+    pub algorithm: Option<String>,
+    pub algorithm_params: AlgorithmParams,
+    pub pipeline: Option<Vec<PipelineStage>>,
+    pub quality: MergeQuality,
+    pub deterministic: bool,
+    pub memory_budget_mb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GeometryOptions {
+    pub roi: Option<Roi>,
+    pub perspective: Option<PerspectiveCorners>,
+    pub auto_straighten: bool,
+    pub transforms_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FrameOptions {
+    pub selection: Option<FrameSelection>,
+    pub allow_partial: bool,
+    pub auto_exclude_bad_frames: bool,
+    pub exif_exposure_compensation: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ColorOptions {
+    pub gray_card: Option<GrayCardSettings>,
+    pub auto_levels: Option<AutoLevels>,
+}
+
+impl MergeRequestV2 {
+    pub fn into_request(self) -> Result<MergeRequest, String> {
+        if self.api_version != 2 {
+            return Err(format!(
+                "MergeRequestV2 の apiVersion は 2 を指定してください: {}",
+                self.api_version
+            ));
+        }
+        let Self {
+            paths,
+            job_id,
+            preset,
+            automatic,
+            output,
+            processing,
+            geometry,
+            frames,
+            color,
+            ..
+        } = self;
+        Ok(MergeRequest {
+            paths,
+            output_dir: output.dir,
+            output_template: output.template,
+            group_name: output.group_name,
+            output_exr: output.exr,
+            output_layered_exr: output.layered_exr,
+            exr_preview: output.exr_preview,
+            output_dng: output.dng,
+            exr_color_space: output.exr_color_space,
+            output_color_space: output.color_space,
+            save_transforms: output.save_transforms,
+            transforms_path: geometry.transforms_path,
+            deliverables: output.deliverables,
+            algorithm: processing.algorithm,
+            algorithm_params: processing.algorithm_params,
+            pipeline: processing.pipeline,
+            roi: geometry.roi,
+            perspective: geometry.perspective,
+            auto_straighten: geometry.auto_straighten,
+            deterministic: processing.deterministic,
+            frame_selection: frames.selection,
+            allow_partial: frames.allow_partial,
+            auto_exclude_bad_frames: frames.auto_exclude_bad_frames,
+            exif_exposure_compensation: frames.exif_exposure_compensation,
+            clipping_map: output.clipping_map,
+            output_short_reference: output.short_reference,
+            quality: processing.quality,
+            merge_mode: processing.mode,
+            gray_card: color.gray_card,
+            auto_levels: color.auto_levels,
+            preset,
+            job_id,
+            automatic,
+            memory_budget_mb: processing.memory_budget_mb,
+            ..MergeRequest::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn converts_sections_into_the_flat_request() {
+        let request: MergeRequestV2 = serde_json::from_value(json!({
+            "apiVersion": 2,
+            "paths": ["a.jpg", "b.jpg"],
+            "jobId": "job-1",
+            "output": { "dir": "/out", "exr": true, "layeredExr": true },
+            "processing": { "algorithm": "fusion", "deterministic": true },
+            "frames": { "allowPartial": true },
+            // 後の版で足した項目は無視する
+            "future": { "option": 1 }
+        }))
+        .unwrap();
+
+        let request = request.into_request().unwrap();
+        assert_eq!(request.paths, ["a.jpg", "b.jpg"]);
+        assert_eq!(request.job_id.as_deref(), Some("job-1"));
+        assert_eq!(request.output_dir.as_deref(), Some("/out"));
+        assert!(request.output_exr && request.output_layered_exr);
+        assert_eq!(request.algorithm.as_deref(), Some("fusion"));
+        assert!(request.deterministic && request.allow_partial);
+        assert!(!request.auto_straighten && request.gray_card.is_none());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let request = |api_version| MergeRequestV2 {
+            api_version,
+            paths: vec!["a.jpg".to_string()],
+            ..MergeRequestV2::default()
+        };
+        assert!(request(1).into_request().is_err());
+        assert!(request(3).into_request().is_err());
+        assert!(request(2).into_request().is_ok());
+    }
+
+    #[test]
+    fn negotiates_the_older_version() {
+        let v1 = negotiate(1).unwrap();
+        assert_eq!(v1.api_version, 1);
+        assert!(v1.features.is_empty());
+
+        let newer = negotiate(5).unwrap();
+        assert_eq!(newer.api_version, API_VERSION);
+        assert!(newer.features.contains(&"mergeRequestV2"));
+
+        assert!(negotiate(0).is_err());
+    }
+}
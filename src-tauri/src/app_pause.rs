@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+// 動いているプロセスを確かめ直す間隔
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// 比較用のプロセス名。パスを除き、大文字小文字と Windows の .exe を区別しない
+fn normalize(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name).trim();
+    let lower = base.to_lowercase();
+    match lower.strip_suffix(".exe") {
+        Some(stem) => stem.to_string(),
+        None => lower,
+    }
+}
+
+// 設定の pauseWhileRunning のうち、いま動いているもの（設定に書いた名前のまま返す）
+pub fn blockers(configured: &[String], running: &[String]) -> Vec<String> {
+    let running: Vec<String> = running.iter().map(|name| normalize(name)).collect();
+    configured
+        .iter()
+        .filter(|name| !name.trim().is_empty())
+        .filter(|name| running.contains(&normalize(name)))
+        .cloned()
+        .collect()
+}
+
+// 動いているプロセスの実行ファイル名。取得できない環境では空を返す
+pub fn running_processes() -> Vec<String> {
+    #[cfg(target_os = "linux")]
+    {
+        linux_processes()
+    }
+    #[cfg(windows)]
+    {
+        windows::processes()
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        std::process::Command::new("ps")
+            .args(["-A", "-o", "comm="])
+            .output()
+            .map(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// comm は 15 文字で切れるため、コマンドラインの先頭（Wine・Proton のゲームは Windows のパス）も見る
+#[cfg(target_os = "linux")]
+fn linux_processes() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut names = Vec::new();
+    for entry in entries.flatten() {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+        if let Ok(comm) = std::fs::read_to_string(entry.path().join("comm")) {
+            names.push(comm.trim().to_string());
+        }
+        if let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) {
+            if let Some(program) = cmdline.split(|&b| b == 0).next().filter(|p| !p.is_empty()) {
+                names.push(String::from_utf8_lossy(program).to_string());
+            }
+        }
+    }
+    names
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::c_void;
+
+    const TH32CS_SNAPPROCESS: u32 = 0x0000_0002;
+
+    #[repr(C)]
+    struct ProcessEntry32W {
+        size: u32,
+        usage: u32,
+        process_id: u32,
+        default_heap_id: usize,
+        module_id: u32,
+        threads: u32,
+        parent_process_id: u32,
+        priority_class_base: i32,
+        flags: u32,
+        exe_file: [u16; 260],
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateToolhelp32Snapshot(flags: u32, process_id: u32) -> *mut c_void;
+        fn Process32FirstW(snapshot: *mut c_void, entry: *mut ProcessEntry32W) -> i32;
+        fn Process32NextW(snapshot: *mut c_void, entry: *mut ProcessEntry32W) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+
+    pub fn processes() -> Vec<String> {
+        let mut names = Vec::new();
+        // SAFETY: スナップショットのハンドルは確認してから使って閉じ、構造体には大きさを設定している
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+            // INVALID_HANDLE_VALUE
+            if snapshot.is_null() || snapshot as isize == -1 {
+                return names;
+            }
+            let mut entry: ProcessEntry32W = std::mem::zeroed();
+            entry.size = std::mem::size_of::<ProcessEntry32W>() as u32;
+            let mut ok = Process32FirstW(snapshot, &mut entry);
+            while ok != 0 {
+                let length = entry
+                    .exe_file
+                    .iter()
+                    .position(|&c| c == 0)
+                    .unwrap_or(entry.exe_file.len());
+                names.push(String::from_utf16_lossy(&entry.exe_file[..length]));
+                ok = Process32NextW(snapshot, &mut entry);
+            }
+            CloseHandle(snapshot);
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_process_names_loosely() {
+        let configured = vec![
+            "VRChat.exe".to_string(),
+            "obs64.exe".to_string(),
+            "game".to_string(),
+            " ".to_string(),
+        ];
+        let running = vec![
+            "C:\\Program Files\\VRChat\\vrchat.exe".to_string(),
+            "/usr/bin/obs64".to_string(),
+            "explorer.exe".to_string(),
+        ];
+
+        assert_eq!(
+            blockers(&configured, &running),
+            vec!["VRChat.exe".to_string(), "obs64.exe".to_string()]
+        );
+        assert!(blockers(&[], &running).is_empty());
+        // テストを走らせているプロセス自身は見つかる
+        #[cfg(target_os = "linux")]
+        assert!(!running_processes().is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::contact_sheet::{self, Tile};
+use crate::exr_preview;
+use crate::merge::{self, MergeRequest, MergeResult};
+use crate::output_lock::OutputReservation;
+use crate::paths;
+use crate::progress::CANCELLED;
+use crate::watch_filter::OwnOutputs;
+use crate::workdir::write_atomically;
+
+const SUMMARY_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequest {
+    pub requests: Vec<MergeRequest>,
+    // 一覧画像とまとめの保存先。未指定なら最初に成功した合成の出力フォルダ
+    pub report_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItem {
+    pub paths: Vec<String>,
+    pub output_png_path: Option<String>,
+    pub output_exr_path: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub algorithm: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+// *_batch.json に保存する内容
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchSummary {
+    pub version: u32,
+    pub started_at: String,
+    pub finished_at: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    // 中止したジョブがあれば、そこで残りを合成せずに終えている
+    pub cancelled: bool,
+    pub items: Vec<BatchItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchResult {
+    pub summary_path: String,
+    // 成功した合成が1つもないか、書き出しに失敗したときは None
+    pub contact_sheet_path: Option<String>,
+    // 一覧画像はおまけなので、書き出せなくてもまとめは返す
+    pub contact_sheet_error: Option<String>,
+    pub summary: BatchSummary,
+}
+
+// 順に合成した結果を記録し、最後に一覧画像とまとめの JSON を書き出す
+pub struct Batch {
+    started_at: DateTime<Local>,
+    items: Vec<BatchItem>,
+    cancelled: bool,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self {
+            started_at: Local::now(),
+            items: Vec::new(),
+            cancelled: false,
+        }
+    }
+
+    pub fn record(&mut self, paths: Vec<String>, result: &Result<MergeResult, String>) {
+        let item = match result {
+            Ok(merged) => BatchItem {
+                paths,
+                output_png_path: Some(merged.output_png_path.clone()),
+                output_exr_path: merged.output_exr_path.clone(),
+                width: Some(merged.width),
+                height: Some(merged.height),
+                algorithm: Some(merged.algorithm.clone()),
+                duration_ms: Some(merged.duration_ms),
+                error: None,
+            },
+            Err(e) => {
+                self.cancelled |= e == CANCELLED;
+                BatchItem {
+                    paths,
+                    output_png_path: None,
+                    output_exr_path: None,
+                    width: None,
+                    height: None,
+                    algorithm: None,
+                    duration_ms: None,
+                    error: Some(e.clone()),
+                }
+            }
+        };
+        self.items.push(item);
+    }
+
+    // 中止されたら残りのジョブは始めない
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    // report_dir を省いたときの保存先（出力か入力のフォルダ）も allowedWriteRoots の中に限る
+    pub fn finish(
+        self,
+        report_dir: Option<&Path>,
+        write_roots: &[PathBuf],
+        own_outputs: Option<&OwnOutputs>,
+    ) -> Result<BatchResult, String> {
+        let report_dir = match report_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => self
+                .items
+                .iter()
+                .find_map(|item| item.output_png_path.as_ref())
+                .or_else(|| self.items.first().and_then(|item| item.paths.first()))
+                .and_then(|path| Path::new(path).parent())
+                .map(Path::to_path_buf)
+                .ok_or("まとめの保存先がありません")?,
+        };
+        paths::ensure_writable(&report_dir, write_roots)?;
+        std::fs::create_dir_all(paths::extended(&report_dir))
+            .map_err(|e| format!("出力先フォルダを作成できません: {}", e))?;
+        let stem = format!("{}_batch", self.started_at.format("%Y%m%d_%H%M%S"));
+        // 同じ秒に始めたバッチのまとめを上書きしない
+        let reservation = OutputReservation::reserve(&report_dir, &stem, false, |name| {
+            vec![format!("{}.json", name), format!("{}_contact.jpg", name)]
+        })?;
+        let stem = reservation.name();
+        let summary_path = report_dir.join(format!("{}.json", stem));
+        let contact_sheet_path = report_dir.join(format!("{}_contact.jpg", stem));
+
+        let succeeded = self
+            .items
+            .iter()
+            .filter(|item| item.error.is_none())
+            .count();
+        let summary = BatchSummary {
+            version: SUMMARY_VERSION,
+            started_at: self.started_at.to_rfc3339(),
+            finished_at: Local::now().to_rfc3339(),
+            succeeded,
+            failed: self.items.len() - succeeded,
+            cancelled: self.cancelled,
+            items: self.items,
+        };
+        // 一覧画像の失敗でまとめを失わないよう、まとめを先に書く
+        let text = serde_json::to_string_pretty(&summary).map_err(|e| e.to_string())?;
+        write_atomically(None, &summary_path, |partial| {
+            std::fs::write(partial, text).map_err(|e| e.to_string())
+        })
+        .map_err(|e| format!("まとめを保存できません: {}", e))?;
+        let (contact_sheet_path, contact_sheet_error) =
+            match write_contact_sheet(&summary.items, &contact_sheet_path, own_outputs) {
+                Ok(true) => (Some(contact_sheet_path.to_string_lossy().to_string()), None),
+                Ok(false) => (None, None),
+                Err(e) => (None, Some(e)),
+            };
+        Ok(BatchResult {
+            summary_path: summary_path.to_string_lossy().to_string(),
+            contact_sheet_path,
+            contact_sheet_error,
+            summary,
+        })
+    }
+}
+
+// 全画像を保持しないよう、読み込んだ合成結果はすぐ縮小する。消された出力は空のコマにする
+fn write_contact_sheet(
+    items: &[BatchItem],
+    path: &Path,
+    own_outputs: Option<&OwnOutputs>,
+) -> Result<bool, String> {
+    if items.iter().all(|item| item.output_png_path.is_none()) {
+        return Ok(false);
+    }
+    let tiles: Vec<Tile> = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let image = item
+                .output_png_path
+                .as_ref()
+                .and_then(|path| merge::load_rgb16(path).ok())
+                .map(|image| exr_preview::thumbnail(&image, contact_sheet::TILE_SIZE));
+            // ラベルは英数字しか描けないため、出力・入力のうちそのまま描ける名前を優先する
+            let names: Vec<String> = item
+                .output_png_path
+                .iter()
+                .chain(&item.paths)
+                .filter_map(|path| Path::new(path).file_stem())
+                .map(|stem| stem.to_string_lossy().to_string())
+                .collect();
+            let name = names
+                .iter()
+                .find(|name| contact_sheet::drawable(name))
+                .or(names.first())
+                .cloned()
+                .unwrap_or_default();
+            let detail = match (&item.error, item.width, item.height) {
+                (Some(e), _, _) if e == CANCELLED => "CANCELLED".to_string(),
+                (Some(_), _, _) => "FAILED".to_string(),
+                (None, Some(width), Some(height)) => format!(
+                    "{}X{} {}",
+                    width,
+                    height,
+                    item.algorithm.as_deref().unwrap_or_default()
+                ),
+                _ => String::new(),
+            };
+            Tile {
+                image,
+                lines: vec![format!("#{} {}", index + 1, name), detail],
+            }
+        })
+        .collect();
+    if let Some(own_outputs) = own_outputs {
+        own_outputs.record(path);
+    }
+    write_atomically(None, path, |partial| {
+        contact_sheet::write_jpeg(&contact_sheet::render(&tiles), partial)
+    })?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{self, TestBracketOptions};
+
+    #[test]
+    fn summarizes_successes_and_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let bracket = synthetic::generate_bracket(dir.path(), &options).unwrap();
+        let request = MergeRequest {
+            paths: bracket.paths.clone(),
+            ..Default::default()
+        };
+        let own_outputs = OwnOutputs::default();
+
+        let mut batch = Batch::new();
+        batch.record(request.paths.clone(), &merge::run_merge(&request));
+        batch.record(
+            vec!["missing.jpg".to_string()],
+            &Err("読み込めません".to_string()),
+        );
+        let elsewhere = tempfile::tempdir().unwrap();
+        let outside = Batch {
+            items: batch.items.clone(),
+            ..Batch::new()
+        };
+        assert!(outside
+            .finish(None, &[elsewhere.path().to_path_buf()], None)
+            .is_err());
+        let result = batch.finish(None, &[], Some(&own_outputs)).unwrap();
+
+        assert_eq!((result.summary.succeeded, result.summary.failed), (1, 1));
+        assert!(!result.summary.cancelled);
+        let saved: BatchSummary =
+            serde_json::from_str(&std::fs::read_to_string(&result.summary_path).unwrap()).unwrap();
+        assert_eq!(saved.items, result.summary.items);
+        assert_eq!(result.contact_sheet_error, None);
+        let sheet = result.contact_sheet_path.unwrap();
+        assert!(sheet.ends_with("_batch_contact.jpg"));
+        assert!(image::open(&sheet).is_ok());
+        assert!(own_outputs.contains(Path::new(&sheet)));
+        // 既定では合成結果と同じフォルダに保存する
+        assert_eq!(
+            Path::new(&result.summary_path).parent(),
+            Path::new(result.summary.items[0].output_png_path.as_ref().unwrap()).parent()
+        );
+    }
+}
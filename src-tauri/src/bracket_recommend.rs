@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::capabilities::MAX_MERGE_FRAMES;
+use crate::color::{luminance, srgb_to_linear, u16_to_unit};
+use crate::merge::Rgb16Image;
+
+// 1 枚で階調が残る範囲（sRGB 0.05〜0.95）の線形輝度
+const WELL_EXPOSED_LOW: f32 = 0.004;
+const WELL_EXPOSED_HIGH: f32 = 0.89;
+const CLIPPED_HIGH: f32 = 0.98;
+const CLIPPED_LOW: f32 = 0.02;
+// 白飛び・黒つぶれがこの割合を超えたら、その先にも階調があるとみなす
+const CLIPPED_THRESHOLD: f64 = 0.002;
+// 白飛び・黒つぶれの先にあるとみなす範囲（EV）の下限と上限
+const MIN_CLIPPED_EXTENSION: f64 = 2.0;
+const MAX_CLIPPED_EXTENSION: f64 = 6.0;
+const PERCENTILE: f64 = 0.005;
+// 間隔を広げすぎるとフレーム間で階調がつながらない
+const SPACINGS: [f64; 4] = [1.0, 1.5, 2.0, 3.0];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct BracketRecommendation {
+    pub frames: usize,
+    pub ev_spacing: f64,
+    // 試し撮りの露出からブラケットの中央をずらす量（正なら明るく）
+    pub center_ev: f64,
+    // 推定した場面の明るさの幅
+    pub scene_range_ev: f64,
+    pub clipped_highlights: f64,
+    pub clipped_shadows: f64,
+    // frames 枚でも場面の幅を覆いきれない場合
+    pub exceeds_max_frames: bool,
+}
+
+// 白飛び・黒つぶれのある場面では、その先の明るさは測れないため割合から見積もる
+pub fn recommend(image: &Rgb16Image) -> BracketRecommendation {
+    let mut lumas: Vec<f32> = image
+        .pixels()
+        .map(|pixel| luminance(pixel.0.map(|v| srgb_to_linear(u16_to_unit(v)))))
+        .collect();
+    let count = lumas.len().max(1) as f64;
+    let clipped_highlights = lumas
+        .iter()
+        .filter(|&&luma| luma >= srgb_to_linear(CLIPPED_HIGH))
+        .count() as f64
+        / count;
+    let clipped_shadows = lumas
+        .iter()
+        .filter(|&&luma| luma <= srgb_to_linear(CLIPPED_LOW))
+        .count() as f64
+        / count;
+
+    lumas.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |fraction: f64| {
+        let index = ((lumas.len() as f64 - 1.0) * fraction).round() as usize;
+        (lumas.get(index).copied().unwrap_or(0.18) as f64).max(1e-5)
+    };
+    let scene_low = percentile(PERCENTILE).log2() - extension(clipped_shadows);
+    let scene_high = percentile(1.0 - PERCENTILE).log2() + extension(clipped_highlights);
+    let scene_range_ev = scene_high - scene_low;
+
+    let window_low = (WELL_EXPOSED_LOW as f64).log2();
+    let window_high = (WELL_EXPOSED_HIGH as f64).log2();
+    let frame_range = window_high - window_low;
+    let center_ev = (window_low + window_high) / 2.0 - (scene_low + scene_high) / 2.0;
+
+    // 最大枚数に収まる中で、間隔がいちばん狭いものを選ぶ
+    let needed = (scene_range_ev - frame_range).max(0.0);
+    let frames_for = |spacing: f64| ((needed / spacing).ceil() as usize + 1).max(2);
+    let widest = SPACINGS[SPACINGS.len() - 1];
+    let ev_spacing = SPACINGS
+        .iter()
+        .copied()
+        .find(|&spacing| frames_for(spacing) <= MAX_MERGE_FRAMES)
+        .unwrap_or(widest);
+    let frames = frames_for(ev_spacing).min(MAX_MERGE_FRAMES);
+
+    BracketRecommendation {
+        frames,
+        ev_spacing,
+        center_ev,
+        scene_range_ev,
+        clipped_highlights,
+        clipped_shadows,
+        exceeds_max_frames: frames_for(ev_spacing) > MAX_MERGE_FRAMES,
+    }
+}
+
+fn extension(clipped: f64) -> f64 {
+    if clipped <= CLIPPED_THRESHOLD {
+        return 0.0;
+    }
+    (MIN_CLIPPED_EXTENSION + (clipped / CLIPPED_THRESHOLD).log2() / 2.0)
+        .clamp(MIN_CLIPPED_EXTENSION, MAX_CLIPPED_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{linear_to_srgb, unit_to_u16};
+    use image::Rgb;
+
+    fn scene(low: f32, high: f32) -> Rgb16Image {
+        Rgb16Image::from_fn(200, 10, |x, _| {
+            let luma = low * (high / low).powf(x as f32 / 199.0);
+            Rgb([unit_to_u16(linear_to_srgb(luma.min(1.0))); 3])
+        })
+    }
+
+    #[test]
+    fn low_contrast_scene_needs_a_short_bracket() {
+        let recommendation = recommend(&scene(0.02, 0.5));
+
+        assert_eq!(recommendation.frames, 2);
+        assert_eq!(recommendation.ev_spacing, 1.0);
+        assert!(recommendation.clipped_highlights < 0.01);
+        assert!((recommendation.scene_range_ev - 4.64).abs() < 0.1);
+    }
+
+    #[test]
+    fn clipped_scene_needs_more_frames_and_a_darker_center() {
+        // 右 3 割ほどが白飛びしている
+        let recommendation = recommend(&scene(0.01, 8.0));
+
+        assert!(recommendation.clipped_highlights > 0.25);
+        assert_eq!(recommendation.frames, 4);
+        assert_eq!(recommendation.ev_spacing, 1.5);
+        assert!(!recommendation.exceeds_max_frames);
+        assert!(recommendation.center_ev < -1.0);
+        assert!(recommendation.scene_range_ev > 10.0);
+    }
+}
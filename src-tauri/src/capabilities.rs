@@ -0,0 +1,62 @@
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::algorithms::{self, AlgorithmInfo};
+use crate::decode::{self, ImageLimits};
+use crate::formats;
+use crate::frame_select;
+use crate::output_path;
+use crate::pipeline;
+
+#[derive(Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    pub version: &'static str,
+    // 監視・合成の入力として扱える拡張子
+    pub input_formats: Vec<&'static str>,
+    // 監視の対象に指定できるが、まだ合成の入力にはできない拡張子
+    pub watch_only_formats: Vec<&'static str>,
+    // プローブ・比較で読み込める拡張子（合成出力の EXR を含む）
+    pub inspect_formats: Vec<&'static str>,
+    pub output_formats: Vec<&'static str>,
+    pub algorithms: Vec<AlgorithmInfo>,
+    pub pipeline_stages: Vec<&'static str>,
+    pub output_template_placeholders: Vec<&'static str>,
+    pub gpu_available: bool,
+    pub max_merge_frames: usize,
+    // frameSelection 指定時に候補として渡せる枚数
+    pub max_selection_candidates: usize,
+    // デコード時に確保できる最大バイト数（設定の imageLimits から決まる）
+    pub max_decode_bytes: Option<u64>,
+    // 設定の imageLimits。これを超える画像は読み込まない
+    pub image_limits: ImageLimits,
+    pub cpu_threads: usize,
+}
+
+pub const MAX_MERGE_FRAMES: usize = 5;
+
+pub fn get() -> Capabilities {
+    let image_limits = decode::current_limits();
+    let mut inspect_formats = formats::DECODABLE_EXTENSIONS.to_vec();
+    inspect_formats.push("exr");
+
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        input_formats: formats::DECODABLE_EXTENSIONS.to_vec(),
+        watch_only_formats: formats::UNDECODABLE_EXTENSIONS.to_vec(),
+        inspect_formats,
+        output_formats: vec!["png", "exr", "dng"],
+        algorithms: algorithms::list(),
+        pipeline_stages: pipeline::STAGE_NAMES.to_vec(),
+        output_template_placeholders: output_path::PLACEHOLDERS.to_vec(),
+        // GPU 実装は未搭載
+        gpu_available: false,
+        max_merge_frames: MAX_MERGE_FRAMES,
+        max_selection_candidates: frame_select::MAX_SELECTION_CANDIDATES,
+        max_decode_bytes: Some(image_limits.max_alloc()),
+        image_limits,
+        cpu_threads: std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1),
+    }
+}
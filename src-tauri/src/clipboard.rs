@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use image::{ImageFormat, RgbImage};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::exr_preview;
+use crate::merge::Rgb16Image;
+
+// チャットやレビューツールに貼る確認用なので、原寸ではなくこの長辺まで縮小する
+const MAX_LONG_EDGE: u32 = 2048;
+// クリップボードへ渡す PNG のパスを外部コマンドに渡す環境変数。引数の引用符の違いを避ける
+const IMAGE_PATH_VAR: &str = "VHDR_CLIPBOARD_IMAGE";
+
+static NEXT_COPY: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipboardCopy {
+    pub source_path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+// 合成結果は表示用に階調を圧縮済みのため、8bit に落として縮小するだけでよい
+pub fn bitmap(image: &Rgb16Image) -> RgbImage {
+    exr_preview::thumbnail(image, MAX_LONG_EDGE)
+}
+
+// クリップボードを扱う依存を増やさないよう、OS の標準のコマンド（Windows は PowerShell、macOS は osascript、
+// Linux は wl-copy か xclip）に一時 PNG を渡す。コマンドの終了まで待つので、非同期のコマンドからは
+// spawn_blocking で呼ぶ。一時 PNG はコピーごとに別の名前にし、同時に呼ばれても互いに上書きしない
+pub fn copy_image(image: &Rgb16Image, temp_dir: &Path) -> Result<(u32, u32), String> {
+    let bitmap = bitmap(image);
+    std::fs::create_dir_all(temp_dir).map_err(|e| e.to_string())?;
+    let path = temp_dir.join(format!(
+        "clipboard_{}_{}.png",
+        std::process::id(),
+        NEXT_COPY.fetch_add(1, Ordering::Relaxed)
+    ));
+    bitmap
+        .save_with_format(&path, ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    let copied = run_clipboard_command(&path);
+    let _ = std::fs::remove_file(&path);
+    copied?;
+    Ok(bitmap.dimensions())
+}
+
+fn run_clipboard_command(path: &Path) -> Result<(), String> {
+    let mut command = clipboard_command(path)?;
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = command
+        .env(IMAGE_PATH_VAR, path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| {
+            format!(
+                "クリップボードにコピーできません（{} を起動できません）: {}",
+                program, e
+            )
+        })?;
+    if !status.success() {
+        return Err(format!(
+            "クリップボードにコピーできません（{} が失敗しました: {}）",
+            program, status
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn clipboard_command(_path: &Path) -> Result<Command, String> {
+    let mut command = Command::new("powershell");
+    command.stdin(Stdio::null()).args([
+        "-NoProfile",
+        "-NonInteractive",
+        "-STA",
+        "-Command",
+        "Add-Type -AssemblyName System.Windows.Forms, System.Drawing; \
+         $image = [System.Drawing.Image]::FromFile($env:VHDR_CLIPBOARD_IMAGE); \
+         [System.Windows.Forms.Clipboard]::SetImage($image)",
+    ]);
+    Ok(command)
+}
+
+#[cfg(target_os = "macos")]
+fn clipboard_command(_path: &Path) -> Result<Command, String> {
+    let mut command = Command::new("osascript");
+    command.stdin(Stdio::null()).args([
+        "-e",
+        "set the clipboard to (read (POSIX file (system attribute \"VHDR_CLIPBOARD_IMAGE\")) as «class PNGf»)",
+    ]);
+    Ok(command)
+}
+
+// Wayland なら wl-copy、それ以外は xclip。どちらもクリップボードを保持するため自身を常駐させてから終了する
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn clipboard_command(path: &Path) -> Result<Command, String> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let image = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut command = Command::new("wl-copy");
+        command.stdin(image).args(["--type", "image/png"]);
+        return Ok(command);
+    }
+    let mut command = Command::new("xclip");
+    command
+        .stdin(Stdio::null())
+        .args(["-selection", "clipboard", "-target", "image/png", "-in"])
+        .arg(path);
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn bitmap_is_eight_bit_and_limited_in_size() {
+        let image = Rgb16Image::from_pixel(4096, 1024, Rgb([u16::MAX, 32896, 0]));
+
+        let bitmap = bitmap(&image);
+
+        assert_eq!(bitmap.dimensions(), (2048, 512));
+        assert_eq!(*bitmap.get_pixel(0, 0), Rgb([255, 128, 0]));
+    }
+}
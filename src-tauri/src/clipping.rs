@@ -0,0 +1,103 @@
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::geometry::{self, Roi};
+use crate::merge::Rgb16Image;
+
+// いずれかのチャンネルがこの値以上なら白飛びとみなす
+const CLIP_THRESHOLD: u16 = 64224;
+const UNRECOVERABLE_COLOR: Rgba<u8> = Rgba([255, 0, 64, 200]);
+const RECOVERED_COLOR: Rgba<u8> = Rgba([0, 200, 255, 120]);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ClippingSummary {
+    pub path: String,
+    // すべてのフレームで白飛びしていた（合成でも復元できない）画素の割合
+    pub unrecoverable_fraction: f64,
+    // 一部のフレームだけで白飛びしていた（他のフレームから復元した）画素の割合
+    pub recovered_fraction: f64,
+}
+
+pub struct ClippingMap {
+    pub overlay: RgbaImage,
+    pub unrecoverable_fraction: f64,
+    pub recovered_fraction: f64,
+}
+
+// 合成結果に重ねる透過 PNG を作る。復元できなかった画素は赤、復元できた画素は水色で示す
+pub fn clipping_map(
+    frames: &[Rgb16Image],
+    roi: Option<&Roi>,
+    output_size: (u32, u32),
+) -> Result<ClippingMap, String> {
+    let first = frames.first().ok_or("フレームがありません")?;
+    let (width, height) = first.dimensions();
+    let mut overlay = RgbaImage::new(width, height);
+
+    for (x, y, pixel) in overlay.enumerate_pixels_mut() {
+        let clipped = frames
+            .iter()
+            .filter(|frame| {
+                frame
+                    .get_pixel(x, y)
+                    .0
+                    .iter()
+                    .any(|&value| value >= CLIP_THRESHOLD)
+            })
+            .count();
+        if clipped == frames.len() {
+            *pixel = UNRECOVERABLE_COLOR;
+        } else if clipped > 0 {
+            *pixel = RECOVERED_COLOR;
+        }
+    }
+
+    let mut overlay = match roi {
+        Some(roi) => geometry::crop_roi(&overlay, roi)?,
+        None => overlay,
+    };
+    // 割合は合成範囲内の画素で数える
+    let total = overlay.pixels().len().max(1) as f64;
+    let count = |color: &Rgba<u8>| overlay.pixels().filter(|pixel| *pixel == color).count() as f64;
+    let unrecoverable_fraction = count(&UNRECOVERABLE_COLOR) / total;
+    let recovered_fraction = count(&RECOVERED_COLOR) / total;
+    // 射影補正やリサイズで出力サイズが変わった場合は、近似として出力に合わせて拡縮する
+    if overlay.dimensions() != output_size {
+        overlay = imageops::resize(&overlay, output_size.0, output_size.1, FilterType::Nearest);
+    }
+
+    Ok(ClippingMap {
+        overlay,
+        unrecoverable_fraction,
+        recovered_fraction,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn separates_unrecoverable_and_recovered_pixels() {
+        let bright = Rgb16Image::from_fn(4, 1, |x, _| match x {
+            0 | 1 => Rgb([65535, 65535, 65535]),
+            _ => Rgb([30000, 30000, 30000]),
+        });
+        let dark = Rgb16Image::from_fn(4, 1, |x, _| match x {
+            0 => Rgb([65535, 40000, 40000]),
+            _ => Rgb([8000, 8000, 8000]),
+        });
+
+        let map = clipping_map(&[dark, bright], None, (4, 1)).unwrap();
+
+        assert_eq!(map.overlay.get_pixel(0, 0), &UNRECOVERABLE_COLOR);
+        assert_eq!(map.overlay.get_pixel(1, 0), &RECOVERED_COLOR);
+        assert_eq!(map.overlay.get_pixel(2, 0).0[3], 0);
+        assert_eq!(map.unrecoverable_fraction, 0.25);
+        assert_eq!(map.recovered_fraction, 0.25);
+    }
+}
@@ -0,0 +1,218 @@
+use std::path::Path;
+
+use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STRIDE: u32 = 4;
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareResult {
+    pub ssim: f64,
+    // 完全一致の場合は None
+    pub psnr: Option<f64>,
+    pub heatmap_path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub fn compare(
+    a: &ImageBuffer<Rgb<u16>, Vec<u16>>,
+    b: &ImageBuffer<Rgb<u16>, Vec<u16>>,
+    heatmap_path: &Path,
+) -> Result<CompareResult, String> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err("比較する画像のサイズが一致しません".to_string());
+    }
+
+    let width = a.width();
+    let height = a.height();
+    let luma_a = luma_plane(a);
+    let luma_b = luma_plane(b);
+
+    let ssim = calculate_ssim(&luma_a, &luma_b, width, height);
+    let psnr = calculate_psnr(a, b);
+
+    write_heatmap(&luma_a, &luma_b, width, height, heatmap_path)?;
+
+    Ok(CompareResult {
+        ssim,
+        psnr,
+        heatmap_path: heatmap_path.to_string_lossy().to_string(),
+        width,
+        height,
+    })
+}
+
+fn luma_plane(image: &ImageBuffer<Rgb<u16>, Vec<u16>>) -> Vec<f64> {
+    image
+        .pixels()
+        .map(|p| {
+            (0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64) / u16::MAX as f64
+        })
+        .collect()
+}
+
+fn calculate_psnr(
+    a: &ImageBuffer<Rgb<u16>, Vec<u16>>,
+    b: &ImageBuffer<Rgb<u16>, Vec<u16>>,
+) -> Option<f64> {
+    let mut total = 0.0f64;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        for c in 0..3 {
+            let diff = (pa[c] as f64 - pb[c] as f64) / u16::MAX as f64;
+            total += diff * diff;
+        }
+    }
+
+    let count = (a.width() as f64) * (a.height() as f64) * 3.0;
+    if count == 0.0 || total == 0.0 {
+        return None;
+    }
+    let mse = total / count;
+    Some(10.0 * (1.0 / mse).log10())
+}
+
+// 8x8 窓を 4px ずつずらした平均 SSIM（輝度のみ）
+fn calculate_ssim(a: &[f64], b: &[f64], width: u32, height: u32) -> f64 {
+    if width < SSIM_WINDOW || height < SSIM_WINDOW {
+        return ssim_window(a, b, width, 0, 0, width, height);
+    }
+
+    let mut total = 0.0;
+    let mut windows = 0usize;
+    let mut y = 0;
+    while y + SSIM_WINDOW <= height {
+        let mut x = 0;
+        while x + SSIM_WINDOW <= width {
+            total += ssim_window(a, b, width, x, y, SSIM_WINDOW, SSIM_WINDOW);
+            windows += 1;
+            x += SSIM_STRIDE;
+        }
+        y += SSIM_STRIDE;
+    }
+
+    total / windows as f64
+}
+
+fn ssim_window(a: &[f64], b: &[f64], stride: u32, x0: u32, y0: u32, w: u32, h: u32) -> f64 {
+    let n = (w * h) as f64;
+    if n == 0.0 {
+        return 1.0;
+    }
+
+    let mut sum_a = 0.0;
+    let mut sum_b = 0.0;
+    let mut sum_aa = 0.0;
+    let mut sum_bb = 0.0;
+    let mut sum_ab = 0.0;
+    for y in y0..y0 + h {
+        for x in x0..x0 + w {
+            let index = (y * stride + x) as usize;
+            let va = a[index];
+            let vb = b[index];
+            sum_a += va;
+            sum_b += vb;
+            sum_aa += va * va;
+            sum_bb += vb * vb;
+            sum_ab += va * vb;
+        }
+    }
+
+    let mean_a = sum_a / n;
+    let mean_b = sum_b / n;
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let covariance = sum_ab / n - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+        / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2))
+}
+
+fn write_heatmap(a: &[f64], b: &[f64], width: u32, height: u32, path: &Path) -> Result<(), String> {
+    let diffs: Vec<f64> = a.iter().zip(b).map(|(va, vb)| (va - vb).abs()).collect();
+    let max_diff = diffs.iter().cloned().fold(0.0f64, f64::max);
+
+    let mut heatmap = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(width, height);
+    for (pixel, diff) in heatmap.pixels_mut().zip(&diffs) {
+        let t = if max_diff > 0.0 { diff / max_diff } else { 0.0 };
+        *pixel = heat_color(t);
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    heatmap.save(path).map_err(|e| e.to_string())
+}
+
+// 黒 → 赤 → 黄 → 白 のカラーランプ
+fn heat_color(t: f64) -> Rgb<u8> {
+    let t = t.clamp(0.0, 1.0) * 3.0;
+    let r = t.min(1.0);
+    let g = (t - 1.0).clamp(0.0, 1.0);
+    let b = (t - 2.0).clamp(0.0, 1.0);
+    Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(offset: u16) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(32, 24, |x, y| {
+            let v = (x * 1500 + y * 800) as u16 + offset;
+            Rgb([v, v, v])
+        })
+    }
+
+    #[test]
+    fn identical_images_score_perfectly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heatmap.png");
+        let image = gradient(0);
+
+        let result = compare(&image, &image, &path).unwrap();
+
+        assert!((result.ssim - 1.0).abs() < 1e-9);
+        assert_eq!(result.psnr, None);
+        assert_eq!((result.width, result.height), (32, 24));
+        let heatmap = image::open(&path).unwrap().to_rgb8();
+        assert!(heatmap.pixels().all(|pixel| *pixel == Rgb([0, 0, 0])));
+    }
+
+    #[test]
+    fn differences_lower_the_scores_and_show_in_the_heatmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("heatmap.png");
+        let a = gradient(0);
+        let mut b = a.clone();
+        for y in 0..8 {
+            for x in 0..8 {
+                b.put_pixel(x, y, Rgb([u16::MAX, 0, 0]));
+            }
+        }
+
+        let slight = compare(&a, &gradient(500), &dir.path().join("slight.png")).unwrap();
+        let result = compare(&a, &b, &path).unwrap();
+
+        assert!(result.ssim < slight.ssim && slight.ssim < 1.0);
+        assert!(result.psnr.unwrap() < slight.psnr.unwrap());
+        // 差の一番大きい画素が白、変わっていない画素は黒になる
+        let heatmap = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(*heatmap.get_pixel(0, 0), Rgb([255, 255, 255]));
+        assert_eq!(*heatmap.get_pixel(20, 20), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn rejects_images_of_different_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = ImageBuffer::from_pixel(16, 16, Rgb([0u16, 0, 0]));
+
+        assert!(compare(&gradient(0), &small, &dir.path().join("heatmap.png")).is_err());
+        assert!(!dir.path().join("heatmap.png").exists());
+    }
+}
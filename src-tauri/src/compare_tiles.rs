@@ -0,0 +1,267 @@
+use image::imageops::{self, FilterType};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::disk_cache::{self, DiskCache};
+use crate::geometry::Roi;
+use crate::merge::{self, Rgb16Image};
+use crate::paths;
+
+const MAX_LEVEL: u32 = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TileSpec {
+    // 0 が原寸、1 つ上がるごとに 1/2
+    pub level: u32,
+    pub tile_size: u32,
+    // 合成結果の原寸の座標で表示範囲を指定する。未指定なら全体
+    pub region: Option<Roi>,
+}
+
+impl Default for TileSpec {
+    fn default() -> Self {
+        Self {
+            level: 0,
+            tile_size: 512,
+            region: None,
+        }
+    }
+}
+
+impl TileSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.level > MAX_LEVEL {
+            return Err(format!("level は {} 以下で指定してください", MAX_LEVEL));
+        }
+        if !(64..=2048).contains(&self.tile_size) {
+            return Err("tileSize は 64〜2048 で指定してください".to_string());
+        }
+        Ok(())
+    }
+}
+
+// 縮小後の画像上でのタイルの位置
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct TileRect {
+    pub column: u32,
+    pub row: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct TilePair {
+    #[serde(flatten)]
+    pub rect: TileRect,
+    pub source_path: String,
+    pub merged_path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareTiles {
+    pub level: u32,
+    pub tile_size: u32,
+    // 指定した level での画像全体の大きさ
+    pub width: u32,
+    pub height: u32,
+    pub tiles: Vec<TilePair>,
+}
+
+pub fn level_size(width: u32, height: u32, level: u32) -> (u32, u32) {
+    let scale = |side: u32| side.div_ceil(1 << level).max(1);
+    (scale(width), scale(height))
+}
+
+// 表示範囲に掛かるタイルを、行ごとに左から並べる
+pub fn layout(width: u32, height: u32, spec: &TileSpec) -> Vec<TileRect> {
+    let (level_width, level_height) = level_size(width, height, spec.level);
+    let region = spec.region.unwrap_or(Roi {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    });
+    let to_level = |value: u32| value >> spec.level;
+    let to_level_ceil = |value: u32| value.div_ceil(1 << spec.level);
+    let columns = |start: u32, end: u32, limit: u32| {
+        let end = end.min(limit);
+        let first = start / spec.tile_size;
+        let last = end.div_ceil(spec.tile_size);
+        first..last.max(first)
+    };
+
+    let column_range = columns(
+        to_level(region.x),
+        to_level_ceil(region.x.saturating_add(region.width)),
+        level_width,
+    );
+    let row_range = columns(
+        to_level(region.y),
+        to_level_ceil(region.y.saturating_add(region.height)),
+        level_height,
+    );
+    row_range
+        .flat_map(|row| column_range.clone().map(move |column| (column, row)))
+        .map(|(column, row)| {
+            let x = column * spec.tile_size;
+            let y = row * spec.tile_size;
+            TileRect {
+                column,
+                row,
+                x,
+                y,
+                width: spec.tile_size.min(level_width - x),
+                height: spec.tile_size.min(level_height - y),
+            }
+        })
+        .collect()
+}
+
+// 合成前の画像を合成結果と同じ大きさ・縮小率にそろえる。サイズが違う場合（切り抜き・縮小後）は全体を引き伸ばす
+pub fn level_image(image: &Rgb16Image, width: u32, height: u32, level: u32) -> Rgb16Image {
+    let (level_width, level_height) = level_size(width, height, level);
+    if image.dimensions() == (level_width, level_height) {
+        return image.clone();
+    }
+    imageops::resize(image, level_width, level_height, FilterType::Triangle)
+}
+
+pub fn tile(image: &Rgb16Image, rect: &TileRect) -> image::RgbImage {
+    let cropped = imageops::crop_imm(image, rect.x, rect.y, rect.width, rect.height).to_image();
+    image::DynamicImage::ImageRgb16(cropped).to_rgb8()
+}
+
+// タイルは元画像と合成結果の両方の更新日時を含む名前でキャッシュし、
+// キャッシュにないタイルがあるときだけ画像を読み込む
+pub fn generate(
+    cache: &DiskCache,
+    source_path: &str,
+    merged_path: &str,
+    spec: &TileSpec,
+) -> Result<CompareTiles, String> {
+    spec.validate()?;
+    let source_file = paths::input_file(source_path)?;
+    let merged_file = paths::input_file(merged_path)?;
+    let (width, height) = image::image_dimensions(&merged_file)
+        .map_err(|e| format!("{} を読み込めません: {}", merged_path, e))?;
+    let merged_key = disk_cache::entry_name(&merged_file, "", "")?;
+
+    let mut levels: Option<[Rgb16Image; 2]> = None;
+    let mut tiles = Vec::new();
+    for rect in layout(width, height, spec) {
+        let mut written = Vec::with_capacity(2);
+        for (index, side) in ["source", "merged"].iter().enumerate() {
+            let variant = format!(
+                "tile:{}:{}:{}:{}:{}:{}",
+                merged_key, spec.level, spec.tile_size, rect.column, rect.row, side
+            );
+            let entry = disk_cache::entry_name(&source_file, &variant, "png")?;
+            let path = match cache.get(&entry) {
+                Some(path) => path,
+                None => {
+                    if levels.is_none() {
+                        let source = merge::load_rgb16(source_path)?;
+                        let merged = merge::load_rgb16(merged_path)?;
+                        levels = Some([
+                            level_image(&source, width, height, spec.level),
+                            level_image(&merged, width, height, spec.level),
+                        ]);
+                    }
+                    let image = &levels.as_ref().expect("読み込み済み")[index];
+                    let tile = tile(image, &rect);
+                    cache.put(&entry, &source_file, |partial| {
+                        tile.save_with_format(partial, image::ImageFormat::Png)
+                            .map_err(|e| e.to_string())
+                    })?
+                }
+            };
+            written.push(path.to_string_lossy().to_string());
+        }
+        let merged_path = written.pop().unwrap_or_default();
+        let source_path = written.pop().unwrap_or_default();
+        tiles.push(TilePair {
+            rect,
+            source_path,
+            merged_path,
+        });
+    }
+
+    let (level_width, level_height) = level_size(width, height, spec.level);
+    Ok(CompareTiles {
+        level: spec.level,
+        tile_size: spec.tile_size,
+        width: level_width,
+        height: level_height,
+        tiles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_covers_region_at_level() {
+        let spec = TileSpec {
+            level: 1,
+            tile_size: 100,
+            region: Some(Roi {
+                x: 150,
+                y: 0,
+                width: 300,
+                height: 10,
+            }),
+        };
+
+        let tiles = layout(1001, 401, &spec);
+
+        // 1/2 で 501x201、範囲は x 75〜225 → 列 0〜2、行 0
+        assert_eq!(level_size(1001, 401, 1), (501, 201));
+        assert_eq!(
+            tiles.iter().map(|t| (t.column, t.row)).collect::<Vec<_>>(),
+            [(0, 0), (1, 0), (2, 0)]
+        );
+        let edge = layout(
+            1001,
+            401,
+            &TileSpec {
+                level: 1,
+                tile_size: 100,
+                region: None,
+            },
+        );
+        let last = edge.last().unwrap();
+        assert_eq!((last.x, last.y, last.width, last.height), (500, 200, 1, 1));
+        assert_eq!(edge.len(), 6 * 3);
+    }
+
+    #[test]
+    fn source_is_scaled_to_merged_geometry() {
+        let source = Rgb16Image::from_pixel(400, 200, image::Rgb([1000, 2000, 3000]));
+
+        let scaled = level_image(&source, 200, 100, 1);
+        let same = level_image(&source, 800, 400, 1);
+
+        assert_eq!(scaled.dimensions(), (100, 50));
+        assert_eq!(same, source);
+        let rect = layout(800, 400, &TileSpec::default())[0];
+        assert_eq!(
+            tile(
+                &same,
+                &TileRect {
+                    width: 10,
+                    height: 10,
+                    ..rect
+                }
+            )
+            .dimensions(),
+            (10, 10)
+        );
+    }
+}
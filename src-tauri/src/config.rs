@@ -0,0 +1,538 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use chrono::Local;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use ts_rs::TS;
+
+use crate::algorithms::AlgorithmParams;
+use crate::decode::ImageLimits;
+use crate::disk_cache::CacheLimits;
+use crate::grouping::GroupingRules;
+use crate::idle::IdleSettings;
+use crate::output_path::DefaultOutputDir;
+use crate::pipeline::PipelineStage;
+use crate::prefetch::PrefetchSettings;
+use crate::send_to::SendTarget;
+use crate::watch_filter::WatchTiming;
+use crate::workdir::write_atomically;
+use crate::worker_priority::WorkerPriority;
+
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+const BUNDLE_KIND: &str = "vhdr-config";
+pub const MAX_RECENT: usize = 10;
+
+// MIGRATIONS[n] はバージョン n の設定をバージョン n + 1 に変換する
+type Migration = fn(&mut Value) -> Result<(), String>;
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub watch_folder: Option<String>,
+    // 監視で検出する拡張子（空なら合成できる形式すべて）
+    pub watch_extensions: Vec<String>,
+    // 監視フォルダ内でも検出しない出力フォルダ（アプリが書き出したファイルは常に除外する）
+    pub watch_ignore_dirs: Vec<String>,
+    // 重複判定と書き込み完了の待ち時間（拡張子ごとに上書きできる）。監視の開始時に読み込む
+    pub watch_timing: WatchTiming,
+    pub grouping: GroupingRules,
+    pub output_dir: Option<String>,
+    // 合成要求にもプロジェクトにも出力先がないときの出力先。既定は先頭の入力と同じフォルダ
+    pub default_output_dir: DefaultOutputDir,
+    // 合成ごとの出力先テンプレート（MergeRequest の outputTemplate が未指定のときに使う）
+    pub output_template: Option<String>,
+    pub output_exr: bool,
+    // 有効にすると監視フォルダ内への出力・削除を一切行わない
+    pub protect_watch_folder: bool,
+    // 指定するとフロントエンドからの書き込み（出力・書き出し・削除）をこれらのフォルダの中に限る
+    pub allowed_write_roots: Vec<String>,
+    // パイプラインの external ステージで実行してよい実行ファイルのフルパス。空なら external ステージは使えない
+    pub allowed_plugins: Vec<String>,
+    pub default_preset: Option<String>,
+    // 次回起動時に開き直すプロジェクト
+    pub active_project: Option<String>,
+    // 新しい順。存在しなくなったパスは一覧の取得時に取り除く
+    pub recent_folders: Vec<String>,
+    pub recent_outputs: Vec<String>,
+    // サムネイル・解析結果キャッシュの上限（超えると使われていない順に消す）
+    pub cache_limits: CacheLimits,
+    // 合成中に次のジョブの入力をデコードしておく
+    pub prefetch: PrefetchSettings,
+    // 合成の作業領域の上限（MB）。未指定なら確保できる限り原寸で合成する
+    pub memory_budget_mb: Option<u64>,
+    // 有効にすると、プリセットの保存時にそのプリセットで合成した履歴を更新が必要な状態にする
+    pub reprocess_on_preset_change: bool,
+    // 同時に実行する合成の上限。超えた分は順番待ちにする（未指定なら 2）
+    pub max_concurrent_jobs: Option<usize>,
+    // 合成を走らせるスレッドの優先度。background にすると録画・ゲームの邪魔をしにくい
+    pub worker_priority: WorkerPriority,
+    // これらのプロセス（例: vrchat.exe, obs64.exe）が動いている間は自動合成を順番待ちにし、終了したら再開する
+    pub pause_while_running: Vec<String>,
+    // 検出・合成がしばらくないときにメモリ上の状態を手放す
+    pub idle: IdleSettings,
+    // 読み込む画像の幅・高さ・画素数の上限。超えるファイルはデコードせずにエラーにする
+    pub image_limits: ImageLimits,
+    // 合成結果を書き出すほかのプロジェクトのフォルダ。プリセットの sendTo と履歴から使う
+    pub send_targets: Vec<SendTarget>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Preset {
+    pub name: String,
+    pub description: Option<String>,
+    pub algorithm: Option<String>,
+    pub algorithm_params: AlgorithmParams,
+    pub pipeline: Option<Vec<PipelineStage>>,
+    pub output_exr: bool,
+    pub deterministic: bool,
+    // このプリセットで合成したら、結果を自動的に送る送り先（sendTargets の名前）
+    pub send_to: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConfigFile {
+    pub schema_version: u32,
+    pub settings: Settings,
+    pub presets: Vec<Preset>,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            settings: Settings::default(),
+            presets: Vec::new(),
+        }
+    }
+}
+
+pub struct ConfigStore {
+    path: PathBuf,
+    data: Mutex<ConfigFile>,
+    // 新しいバージョンのアプリで保存された設定は、壊さないよう書き込みを止める
+    read_only_reason: Option<String>,
+}
+
+impl ConfigStore {
+    pub fn load(path: PathBuf) -> Self {
+        let (data, read_only_reason) = match load_file(&path) {
+            Ok(data) => (data, None),
+            Err(reason) => (ConfigFile::default(), Some(reason)),
+        };
+        Self {
+            path,
+            data: Mutex::new(data),
+            read_only_reason,
+        }
+    }
+
+    pub fn snapshot(&self) -> Result<ConfigFile, String> {
+        let data = self.data.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(data.clone())
+    }
+
+    pub fn update<T>(
+        &self,
+        apply: impl FnOnce(&mut ConfigFile) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if let Some(reason) = &self.read_only_reason {
+            return Err(reason.clone());
+        }
+        let mut data = self.data.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut next = data.clone();
+        let value = apply(&mut next)?;
+        save_file(&self.path, &next)?;
+        *data = next;
+        Ok(value)
+    }
+}
+
+fn load_file(path: &Path) -> Result<ConfigFile, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ConfigFile::default()),
+        Err(e) => return Err(format!("設定ファイルを読み込めません: {}", e)),
+    };
+
+    let mut value: Value = match serde_json::from_str(&text) {
+        Ok(value) => value,
+        Err(_) => {
+            // 壊れたファイルは退避して初期設定で起動する
+            backup(path, "corrupt")?;
+            return Ok(ConfigFile::default());
+        }
+    };
+
+    let version = schema_version(&value);
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "設定ファイルのバージョン（{}）がこのアプリ（{}）より新しいため、設定を保存できません",
+            version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    if version < CURRENT_SCHEMA_VERSION {
+        backup(path, &format!("v{}", version))?;
+        migrate(&mut value)?;
+        let data: ConfigFile = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        save_file(path, &data)?;
+        return Ok(data);
+    }
+
+    serde_json::from_value(value).map_err(|e| format!("設定ファイルの形式が不正です: {}", e))
+}
+
+fn schema_version(value: &Value) -> u32 {
+    value
+        .get("schemaVersion")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32
+}
+
+pub fn migrate(value: &mut Value) -> Result<(), String> {
+    let mut version = schema_version(value);
+    while version < CURRENT_SCHEMA_VERSION {
+        let migration = MIGRATIONS
+            .get(version as usize)
+            .ok_or_else(|| format!("バージョン {} の設定を移行できません", version))?;
+        migration(value)?;
+        version += 1;
+        value["schemaVersion"] = json!(version);
+    }
+    Ok(())
+}
+
+// バージョン番号を持たない初期の設定は、設定項目がトップレベルに並んでいる
+fn migrate_v0_to_v1(value: &mut Value) -> Result<(), String> {
+    let legacy = value
+        .as_object()
+        .cloned()
+        .ok_or("設定ファイルの形式が不正です")?;
+    let presets = legacy.get("presets").cloned().unwrap_or_else(|| json!([]));
+    let mut settings = legacy;
+    settings.remove("presets");
+    settings.remove("schemaVersion");
+
+    *value = json!({
+        "settings": settings,
+        "presets": presets,
+    });
+    Ok(())
+}
+
+fn backup(path: &Path, suffix: &str) -> Result<(), String> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let backup_path = path.with_file_name(format!("{}.{}.bak", file_name, suffix));
+    fs::copy(path, &backup_path)
+        .map(|_| ())
+        .map_err(|e| format!("設定ファイルのバックアップに失敗しました: {}", e))
+}
+
+// 書き込み途中で終了しても既存の設定が壊れないよう、一時ファイルから置き換える
+fn save_file(path: &Path, data: &ConfigFile) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let text = serde_json::to_string_pretty(data).map_err(|e| e.to_string())?;
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, text).map_err(|e| format!("設定ファイルを保存できません: {}", e))?;
+    fs::rename(&temp_path, path).map_err(|e| format!("設定ファイルを保存できません: {}", e))
+}
+
+#[derive(Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+    pub presets_added: usize,
+    pub presets_updated: usize,
+}
+
+// 別の端末と共有するため、設定とプリセットを1つの JSON にまとめる
+pub fn export_bundle(config: &ConfigFile, path: &Path) -> Result<(), String> {
+    let mut value = serde_json::to_value(config).map_err(|e| e.to_string())?;
+    value["kind"] = json!(BUNDLE_KIND);
+    value["exportedAt"] = json!(Local::now().to_rfc3339());
+    let text = serde_json::to_string_pretty(&value).map_err(|e| e.to_string())?;
+    write_atomically(None, path, |partial| {
+        fs::write(partial, text).map_err(|e| e.to_string())
+    })
+    .map_err(|e| format!("設定を書き出せません: {}", e))
+}
+
+// 古いバージョンで書き出された設定も、通常の読み込みと同じ移行処理を通す
+pub fn read_bundle(path: &Path) -> Result<ConfigFile, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("設定を読み込めません: {}", e))?;
+    let mut value: Value =
+        serde_json::from_str(&text).map_err(|e| format!("設定ファイルの形式が不正です: {}", e))?;
+    let object = value
+        .as_object_mut()
+        .ok_or("設定ファイルの形式が不正です")?;
+    if object.remove("kind") != Some(json!(BUNDLE_KIND)) {
+        return Err("VHDR の設定ファイルではありません".to_string());
+    }
+    object.remove("exportedAt");
+
+    if schema_version(&value) > CURRENT_SCHEMA_VERSION {
+        return Err("より新しいバージョンのアプリで書き出された設定です".to_string());
+    }
+    migrate(&mut value)?;
+    serde_json::from_value(value).map_err(|e| format!("設定ファイルの形式が不正です: {}", e))
+}
+
+// プリセットは名前が同じものを上書きし、それ以外は追加する
+pub fn apply_bundle(
+    config: &mut ConfigFile,
+    imported: ConfigFile,
+) -> Result<ImportSummary, String> {
+    let mut summary = ImportSummary {
+        presets_added: 0,
+        presets_updated: 0,
+    };
+    for preset in imported.presets {
+        if config.presets.iter().any(|p| p.name == preset.name) {
+            summary.presets_updated += 1;
+        } else {
+            summary.presets_added += 1;
+        }
+        save_preset(&mut config.presets, preset)?;
+    }
+    // 書き込み先の制限と実行を許可した外部コマンドはファイルから広げられないよう、取り込み前のものを残す
+    let write_roots = std::mem::take(&mut config.settings.allowed_write_roots);
+    let plugins = std::mem::take(&mut config.settings.allowed_plugins);
+    config.settings = imported.settings;
+    config.settings.allowed_write_roots = write_roots;
+    config.settings.allowed_plugins = plugins;
+    Ok(summary)
+}
+
+// settings_set で設定を置き換える。allowedWriteRoots・allowedPlugins は WebView から広げられないよう、
+// ネイティブのダイアログを通す write_root_add・write_root_remove・plugin_allow_add・plugin_allow_remove でだけ変えられる。
+// 最近使ったフォルダ・出力はバックエンドが記録するため、読み込んだ後に増えた分を古い設定で消さないよう今のものを残す
+pub fn replace_settings(current: &mut Settings, mut incoming: Settings) -> Result<(), String> {
+    if incoming.allowed_write_roots != current.allowed_write_roots {
+        return Err(
+            "allowedWriteRoots は書き込み先フォルダの追加・削除から変更してください".to_string(),
+        );
+    }
+    if incoming.allowed_plugins != current.allowed_plugins {
+        return Err("allowedPlugins は外部コマンドの追加・削除から変更してください".to_string());
+    }
+    incoming.recent_folders = std::mem::take(&mut current.recent_folders);
+    incoming.recent_outputs = std::mem::take(&mut current.recent_outputs);
+    *current = incoming;
+    Ok(())
+}
+
+pub fn push_recent(list: &mut Vec<String>, path: &str) {
+    list.retain(|existing| existing != path);
+    list.insert(0, path.to_string());
+    list.truncate(MAX_RECENT);
+}
+
+// 取り除いた項目があれば true を返す
+pub fn prune_recent(list: &mut Vec<String>) -> bool {
+    let before = list.len();
+    list.retain(|path| Path::new(path).exists());
+    list.len() != before
+}
+
+pub fn save_preset(presets: &mut Vec<Preset>, preset: Preset) -> Result<(), String> {
+    if preset.name.trim().is_empty() {
+        return Err("プリセット名が空です".to_string());
+    }
+    match presets.iter_mut().find(|p| p.name == preset.name) {
+        Some(existing) => *existing = preset,
+        None => presets.push(preset),
+    }
+    Ok(())
+}
+
+pub fn remove_preset(presets: &mut Vec<Preset>, name: &str) -> Result<(), String> {
+    let before = presets.len();
+    presets.retain(|p| p.name != name);
+    if presets.len() == before {
+        return Err(format!("プリセット {} が見つかりません", name));
+    }
+    Ok(())
+}
+
+pub fn delete_preset(config: &mut ConfigFile, name: &str) -> Result<(), String> {
+    remove_preset(&mut config.presets, name)?;
+    if config.settings.default_preset.as_deref() == Some(name) {
+        config.settings.default_preset = None;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_unversioned_file_and_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let legacy = json!({
+            "watchFolder": "C:/capture",
+            "outputExr": true,
+            "presets": [{ "name": "interior", "algorithm": "fusion" }]
+        });
+        fs::write(&path, legacy.to_string()).unwrap();
+
+        let store = ConfigStore::load(path.clone());
+        let config = store.snapshot().unwrap();
+
+        assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(config.settings.watch_folder.as_deref(), Some("C:/capture"));
+        assert!(config.settings.output_exr);
+        assert_eq!(config.presets[0].algorithm.as_deref(), Some("fusion"));
+
+        let backup = fs::read_to_string(dir.path().join("config.json.v0.bak")).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&backup).unwrap(), legacy);
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["schemaVersion"], json!(CURRENT_SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn refuses_to_overwrite_newer_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let newer = json!({ "schemaVersion": CURRENT_SCHEMA_VERSION + 1, "future": true });
+        fs::write(&path, newer.to_string()).unwrap();
+
+        let store = ConfigStore::load(path.clone());
+
+        assert!(store.update(|_| Ok(())).is_err());
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved, newer);
+    }
+
+    #[test]
+    fn bundle_round_trip_merges_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle_path = dir.path().join("bundle.json");
+        let mut source = ConfigFile::default();
+        source.settings.output_exr = true;
+        for name in ["interior", "exterior"] {
+            save_preset(
+                &mut source.presets,
+                Preset {
+                    name: name.to_string(),
+                    algorithm: Some("fusion".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+        export_bundle(&source, &bundle_path).unwrap();
+        // 一時ファイルに書いてから名前を変えるので、書き出し途中のファイルは残らない
+        assert!(!crate::workdir::partial_path(None, &bundle_path).exists());
+
+        let mut target = ConfigFile::default();
+        save_preset(
+            &mut target.presets,
+            Preset {
+                name: "interior".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let summary = apply_bundle(&mut target, read_bundle(&bundle_path).unwrap()).unwrap();
+
+        assert_eq!(summary.presets_added, 1);
+        assert_eq!(summary.presets_updated, 1);
+        assert_eq!(target.presets, source.presets);
+        assert!(target.settings.output_exr);
+
+        // 取り込んだファイルの allowedWriteRoots は使わない
+        let mut target = ConfigFile::default();
+        target.settings.allowed_write_roots = vec!["/photos".to_string()];
+        source.settings.allowed_write_roots = Vec::new();
+        source.settings.allowed_plugins = vec!["/bin/sh".to_string()];
+        export_bundle(&source, &bundle_path).unwrap();
+        apply_bundle(&mut target, read_bundle(&bundle_path).unwrap()).unwrap();
+        assert_eq!(target.settings.allowed_write_roots, vec!["/photos"]);
+        assert!(target.settings.allowed_plugins.is_empty());
+    }
+
+    #[test]
+    fn settings_changes_keep_write_roots() {
+        let mut current = Settings {
+            allowed_write_roots: vec!["/photos".to_string()],
+            ..Settings::default()
+        };
+        let widened = Settings {
+            output_exr: true,
+            ..Settings::default()
+        };
+        assert!(replace_settings(&mut current, widened).is_err());
+        assert!(!current.output_exr);
+        let with_plugin = Settings {
+            allowed_plugins: vec!["/bin/sh".to_string()],
+            ..current.clone()
+        };
+        assert!(replace_settings(&mut current, with_plugin).is_err());
+        assert!(current.allowed_plugins.is_empty());
+
+        let changed = Settings {
+            output_exr: true,
+            ..current.clone()
+        };
+        // 画面が設定を読み込んだ後に記録された最近使ったフォルダ
+        push_recent(&mut current.recent_folders, "/photos/day2");
+        push_recent(&mut current.recent_outputs, "/photos/out");
+        replace_settings(&mut current, changed).unwrap();
+        assert!(current.output_exr);
+        assert_eq!(current.recent_folders, vec!["/photos/day2"]);
+        assert_eq!(current.recent_outputs, vec!["/photos/out"]);
+    }
+
+    #[test]
+    fn rejects_unrelated_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("other.json");
+        fs::write(&path, json!({ "settings": {} }).to_string()).unwrap();
+
+        assert!(read_bundle(&path).is_err());
+    }
+
+    #[test]
+    fn recent_lists_move_duplicates_to_front_and_prune_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().to_string_lossy().to_string();
+        let missing = dir.path().join("gone").to_string_lossy().to_string();
+        let mut list = Vec::new();
+        push_recent(&mut list, &existing);
+        push_recent(&mut list, &missing);
+        push_recent(&mut list, &existing);
+
+        assert_eq!(list, vec![existing.clone(), missing]);
+        assert!(prune_recent(&mut list));
+        assert_eq!(list, vec![existing]);
+
+        for i in 0..MAX_RECENT + 3 {
+            push_recent(&mut list, &i.to_string());
+        }
+        assert_eq!(list.len(), MAX_RECENT);
+    }
+
+    #[test]
+    fn corrupt_file_is_backed_up_and_replaced_with_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(&path, "{ not json").unwrap();
+
+        let store = ConfigStore::load(path);
+
+        assert_eq!(store.snapshot().unwrap(), ConfigFile::default());
+        assert!(dir.path().join("config.json.corrupt.bak").exists());
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{imageops, Rgb, RgbImage};
+
+// 1コマの縮小画像の長辺
+pub const TILE_SIZE: u32 = 240;
+const MAX_COLUMNS: u32 = 6;
+const GAP: u32 = 8;
+// 3x5 の字形を何倍で描くか
+const GLYPH_SCALE: u32 = 2;
+const GLYPH_ADVANCE: u32 = 4 * GLYPH_SCALE;
+const LINE_HEIGHT: u32 = 6 * GLYPH_SCALE;
+const LABEL_LINES: u32 = 2;
+const LABEL_HEIGHT: u32 = LABEL_LINES * LINE_HEIGHT + GAP;
+const BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+const EMPTY_TILE: Rgb<u8> = Rgb([64, 64, 64]);
+const TEXT: Rgb<u8> = Rgb([230, 230, 230]);
+const QUALITY: u8 = 85;
+
+// 一覧の1コマ。image が None のコマ（合成に失敗したものなど）は灰色で描く
+pub struct Tile {
+    pub image: Option<RgbImage>,
+    // 先頭の2行だけを描く
+    pub lines: Vec<String>,
+}
+
+const UNKNOWN: [u8; 5] = [0b111, 0b001, 0b010, 0b000, 0b010];
+
+// フォントの依存を増やさないよう、ラベルは英数字と一部の記号だけの小さな字形で描く。
+// 小文字は大文字で描き、ほかの文字（日本語のファイル名など）は None
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        ' ' => [0; 5],
+        _ => return None,
+    })
+}
+
+// そのまま描ける文字だけでできているか
+pub fn drawable(text: &str) -> bool {
+    text.chars().all(|c| glyph(c).is_some())
+}
+
+// 描けない文字が続くところは ? 1 つにまとめ、英数字の部分（連番など）を読めるように残す
+fn label_glyphs(text: &str) -> Vec<[u8; 5]> {
+    let mut glyphs = Vec::new();
+    let mut unknown = false;
+    for c in text.chars() {
+        let bits = glyph(c);
+        match bits {
+            Some(bits) => glyphs.push(bits),
+            None if !unknown => glyphs.push(UNKNOWN),
+            None => {}
+        }
+        unknown = bits.is_none();
+    }
+    glyphs
+}
+
+// 幅に収まらない分は切り捨てる
+fn draw_text(sheet: &mut RgbImage, x: u32, y: u32, text: &str) {
+    let max_chars = (TILE_SIZE / GLYPH_ADVANCE) as usize;
+    for (index, bits) in label_glyphs(text).iter().take(max_chars).enumerate() {
+        let left = x + index as u32 * GLYPH_ADVANCE;
+        for (row, bits) in bits.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..GLYPH_SCALE {
+                    for dx in 0..GLYPH_SCALE {
+                        sheet.put_pixel(
+                            left + column * GLYPH_SCALE + dx,
+                            y + row as u32 * GLYPH_SCALE + dy,
+                            TEXT,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 左上から行ごとに並べ、各コマの下にラベルを描く
+pub fn render(tiles: &[Tile]) -> RgbImage {
+    let count = tiles.len().max(1) as u32;
+    let columns = count.min(MAX_COLUMNS);
+    let rows = count.div_ceil(columns);
+    let cell_width = TILE_SIZE + GAP;
+    let cell_height = TILE_SIZE + LABEL_HEIGHT + GAP;
+    let mut sheet = RgbImage::from_pixel(
+        columns * cell_width + GAP,
+        rows * cell_height + GAP,
+        BACKGROUND,
+    );
+
+    for (index, tile) in tiles.iter().enumerate() {
+        let left = GAP + index as u32 % columns * cell_width;
+        let top = GAP + index as u32 / columns * cell_height;
+        match &tile.image {
+            Some(image) => {
+                // 縦長・横長のどちらもコマの中央に置く
+                let x = left + (TILE_SIZE - image.width().min(TILE_SIZE)) / 2;
+                let y = top + (TILE_SIZE - image.height().min(TILE_SIZE)) / 2;
+                imageops::replace(&mut sheet, image, x as i64, y as i64);
+            }
+            None => {
+                let empty = RgbImage::from_pixel(TILE_SIZE, TILE_SIZE, EMPTY_TILE);
+                imageops::replace(&mut sheet, &empty, left as i64, top as i64);
+            }
+        }
+        for (line, text) in tile.lines.iter().take(LABEL_LINES as usize).enumerate() {
+            draw_text(
+                &mut sheet,
+                left,
+                top + TILE_SIZE + GAP / 2 + line as u32 * LINE_HEIGHT,
+                text,
+            );
+        }
+    }
+    sheet
+}
+
+pub fn write_jpeg(sheet: &RgbImage, path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("一覧画像を保存できません: {}", e))?;
+    JpegEncoder::new_with_quality(BufWriter::new(file), QUALITY)
+        .encode_image(sheet)
+        .map_err(|e| format!("一覧画像を保存できません: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_tiles_in_rows_with_labels() {
+        let tile = |image: Option<RgbImage>| Tile {
+            image,
+            lines: vec!["#1 HDR_MERGE".to_string()],
+        };
+        let mut tiles: Vec<Tile> = (0..7)
+            .map(|_| tile(Some(RgbImage::from_pixel(TILE_SIZE, 120, Rgb([255, 0, 0])))))
+            .collect();
+        tiles.push(tile(None));
+
+        let sheet = render(&tiles);
+
+        // 6列で2行になる
+        assert_eq!(sheet.width(), 6 * (TILE_SIZE + GAP) + GAP);
+        assert_eq!(sheet.height(), 2 * (TILE_SIZE + LABEL_HEIGHT + GAP) + GAP);
+        // 横長のコマは上下の中央に置く
+        assert_eq!(*sheet.get_pixel(GAP, GAP), BACKGROUND);
+        assert_eq!(*sheet.get_pixel(GAP, GAP + TILE_SIZE / 2), Rgb([255, 0, 0]));
+        let second_row = GAP + TILE_SIZE + LABEL_HEIGHT + GAP;
+        assert_eq!(
+            *sheet.get_pixel(GAP + TILE_SIZE + GAP, second_row),
+            EMPTY_TILE
+        );
+        // ラベルの # の左上
+        assert_eq!(*sheet.get_pixel(GAP, GAP + TILE_SIZE + GAP / 2), TEXT);
+    }
+
+    #[test]
+    fn collapses_characters_without_glyphs() {
+        assert!(drawable("hdr_merge_20240101_120000"));
+        assert!(!drawable("夕焼け_001"));
+        // 日本語の部分は ? 1 文字にまとめ、連番は残す
+        let glyphs = label_glyphs("夕焼け_001");
+        assert_eq!(glyphs.len(), 5);
+        assert_eq!(glyphs[0], UNKNOWN);
+        assert_eq!(glyphs[1..], label_glyphs("_001")[..]);
+    }
+}
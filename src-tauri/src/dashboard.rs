@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use serde::Serialize;
+use ts_rs::TS;
+
+pub const DASHBOARD_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct DashboardSummary {
+    pub watching: bool,
+    pub folder: Option<String>,
+    // 手動グループに入っていて、まだ合成していないファイルの数
+    pub pending_files: usize,
+    // 実行中と空きを待っている合成ジョブの数
+    pub queue_depth: usize,
+    pub last_result_path: Option<String>,
+    // 起動してから失敗した合成と監視のエラーの数（キャンセルは含まない）
+    pub error_count: u64,
+    pub idle: bool,
+}
+
+// ヘッダーの状態表示に使う集計。変化があったときだけ通知する
+#[derive(Default)]
+pub struct Dashboard {
+    last_result_path: Mutex<Option<String>>,
+    errors: AtomicU64,
+    last_emitted: Mutex<Option<DashboardSummary>>,
+}
+
+impl Dashboard {
+    pub fn record_result(&self, path: &str) {
+        *self
+            .last_result_path
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(path.to_string());
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn last_result_path(&self) -> Option<String> {
+        self.last_result_path
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn error_count(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    // 前回通知した内容と違うときだけ true を返し、通知したものとして覚える
+    pub fn should_emit(&self, summary: &DashboardSummary) -> bool {
+        let mut last = self
+            .last_emitted
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if last.as_ref() == Some(summary) {
+            return false;
+        }
+        *last = Some(summary.clone());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emits_only_when_summary_changes() {
+        let dashboard = Dashboard::default();
+        let summary = DashboardSummary {
+            watching: true,
+            ..Default::default()
+        };
+        assert!(dashboard.should_emit(&summary));
+        assert!(!dashboard.should_emit(&summary));
+
+        dashboard.record_error();
+        dashboard.record_result("/out/hdr.png");
+        let changed = DashboardSummary {
+            error_count: dashboard.error_count(),
+            last_result_path: dashboard.last_result_path(),
+            ..summary
+        };
+        assert_eq!(changed.error_count, 1);
+        assert!(dashboard.should_emit(&changed));
+    }
+}
@@ -0,0 +1,326 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::{PoisonError, RwLock};
+
+use image::codecs::{bmp::BmpDecoder, jpeg::JpegDecoder, png::PngDecoder};
+use image::codecs::{tiff::TiffDecoder, webp::WebPDecoder};
+use image::io::{Limits, Reader};
+use image::{ColorType, DynamicImage, ImageDecoder, ImageFormat};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::merge::Rgb16Image;
+use crate::output_color;
+
+const DEFAULT_LIMITS: ImageLimits = ImageLimits {
+    max_width: 65535,
+    max_height: 65535,
+    // 16bit RGB で約 1.2GB
+    max_pixels: 200_000_000,
+};
+
+// 設定の imageLimits。起動時と設定の変更時に set_limits で更新する
+static LIMITS: RwLock<ImageLimits> = RwLock::new(DEFAULT_LIMITS);
+
+// ヘッダーの大きさだけが壊れたファイルで巨大なバッファを確保しないよう、デコード前に確かめる上限
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImageLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        DEFAULT_LIMITS
+    }
+}
+
+impl ImageLimits {
+    // デコーダーが確保できる大きさ。透過付き 16bit に変換してから RGB にする画像でも maxPixels まで読める
+    pub fn max_alloc(&self) -> u64 {
+        self.max_pixels.saturating_mul(8)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    ImageTooLarge {
+        path: String,
+        width: u32,
+        height: u32,
+        limits: ImageLimits,
+    },
+    Unreadable(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::ImageTooLarge {
+                path,
+                width,
+                height,
+                limits,
+            } => write!(
+                f,
+                "{} は大きすぎるため読み込めません（{}×{}、上限 {}×{}・{} 画素）",
+                path, width, height, limits.max_width, limits.max_height, limits.max_pixels
+            ),
+            DecodeError::Unreadable(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<DecodeError> for String {
+    fn from(error: DecodeError) -> Self {
+        error.to_string()
+    }
+}
+
+pub fn set_limits(limits: ImageLimits) {
+    *LIMITS.write().unwrap_or_else(PoisonError::into_inner) = limits;
+}
+
+pub fn current_limits() -> ImageLimits {
+    *LIMITS.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn check_dimensions(
+    path: &str,
+    (width, height): (u32, u32),
+    limits: &ImageLimits,
+) -> Result<(), DecodeError> {
+    if width > limits.max_width
+        || height > limits.max_height
+        || width as u64 * height as u64 > limits.max_pixels
+    {
+        return Err(DecodeError::ImageTooLarge {
+            path: path.to_string(),
+            width,
+            height,
+            limits: *limits,
+        });
+    }
+    Ok(())
+}
+
+// 合成で使う 16bit RGB のバッファへ直接デコードする。
+// image::open().to_rgb16() はデコード結果と変換後の2枚を同時に持つため、8bit の入力では
+// 画像1枚あたり 16bit バッファの 1.5 倍のメモリを使っていた
+pub fn decode_rgb16(path: &Path) -> Result<Rgb16Image, DecodeError> {
+    decode_rgb16_with(path, &current_limits())
+}
+
+fn decode_rgb16_with(path: &Path, limits: &ImageLimits) -> Result<Rgb16Image, DecodeError> {
+    let label = path.to_string_lossy();
+    let read_error = |e: image::ImageError| {
+        DecodeError::Unreadable(format!("{} を読み込めません: {}", label, e))
+    };
+    let reader = Reader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| DecodeError::Unreadable(format!("{} を読み込めません: {}", label, e)))?;
+    let format = reader.format();
+    let file = reader.into_inner();
+    // バッファを確保する前にヘッダーの大きさを確かめる
+    let check = |dimensions| check_dimensions(&label, dimensions, limits);
+
+    match format {
+        Some(ImageFormat::Jpeg) => {
+            let decoder = JpegDecoder::new(file).map_err(read_error)?;
+            check(decoder.dimensions())?;
+            decode_as_srgb(decoder, limits)
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(file).map_err(read_error)?;
+            check(decoder.dimensions())?;
+            decode_as_srgb(decoder, limits)
+        }
+        Some(ImageFormat::Tiff) => {
+            let decoder = TiffDecoder::new(file).map_err(read_error)?;
+            check(decoder.dimensions())?;
+            decode_with(decoder, limits)
+        }
+        Some(ImageFormat::Bmp) => {
+            let decoder = BmpDecoder::new(file).map_err(read_error)?;
+            check(decoder.dimensions())?;
+            decode_with(decoder, limits)
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(file).map_err(read_error)?;
+            check(decoder.dimensions())?;
+            decode_with(decoder, limits)
+        }
+        _ => {
+            check(image::image_dimensions(path).map_err(read_error)?)?;
+            image::open(path).map(DynamicImage::into_rgb16)
+        }
+    }
+    .map_err(read_error)
+}
+
+// EXR は画素を読む前にヘッダーの大きさ（先頭のレイヤー）を確かめる
+pub fn check_exr(path: &Path) -> Result<(), DecodeError> {
+    check_exr_with(path, &current_limits())
+}
+
+fn check_exr_with(path: &Path, limits: &ImageLimits) -> Result<(), DecodeError> {
+    let label = path.to_string_lossy();
+    let meta = exr::meta::MetaData::read_from_file(path, false)
+        .map_err(|e| DecodeError::Unreadable(format!("{} を読み込めません: {}", label, e)))?;
+    let Some(header) = meta.headers.first() else {
+        return Err(DecodeError::Unreadable(format!(
+            "{} にレイヤーがありません",
+            label
+        )));
+    };
+    let size = header.layer_size;
+    let dimension = |value: usize| u32::try_from(value).unwrap_or(u32::MAX);
+    check_dimensions(
+        &label,
+        (dimension(size.width()), dimension(size.height())),
+        limits,
+    )
+}
+
+// 合成・サムネイル・書き出しは sRGB の値で扱うため、Display P3・Rec.2020 で書き出した
+// 自分の PNG・JPEG（outputColorSpace）は読み込むときに sRGB へ戻す
+fn decode_as_srgb<'a>(
+    mut decoder: impl ImageDecoder<'a>,
+    limits: &ImageLimits,
+) -> Result<Rgb16Image, image::ImageError> {
+    let color_space = decoder
+        .icc_profile()
+        .and_then(|profile| output_color::written_color_space(&profile));
+    let image = decode_with(decoder, limits)?;
+    Ok(match color_space {
+        Some(color_space) => output_color::to_srgb(&image, color_space),
+        None => image,
+    })
+}
+
+fn decode_with<'a>(
+    mut decoder: impl ImageDecoder<'a>,
+    limits: &ImageLimits,
+) -> Result<Rgb16Image, image::ImageError> {
+    let mut decoder_limits = Limits::default();
+    decoder_limits.max_image_width = Some(limits.max_width);
+    decoder_limits.max_image_height = Some(limits.max_height);
+    decoder_limits.max_alloc = Some(limits.max_alloc());
+    decoder.set_limits(decoder_limits)?;
+    let (width, height) = decoder.dimensions();
+    let len = width as usize * height as usize * 3;
+    let color_type = decoder.color_type();
+    if !matches!(color_type, ColorType::Rgb8 | ColorType::Rgb16) {
+        // 灰色・透過付きなどは変換が必要なため従来どおり変換する（16bit RGB なら複製しない）
+        return Ok(DynamicImage::from_decoder(decoder)?.into_rgb16());
+    }
+
+    let mut data = vec![0u16; len];
+    {
+        // SAFETY: u16 の領域をバイト列として見るだけで、長さはバイト数に揃えている
+        let bytes =
+            unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, len * 2) };
+        let used = decoder.total_bytes() as usize;
+        decoder.read_image(&mut bytes[..used])?;
+    }
+    if color_type == ColorType::Rgb8 {
+        expand_in_place(&mut data);
+    }
+    Ok(Rgb16Image::from_raw(width, height, data).expect("バッファの長さは画素数と一致する"))
+}
+
+// 先頭の半分に詰めてデコードした 8bit 値を、後ろから 16bit に広げる。
+// i 番目の書き込み先は i 番目以降のバイトなので、まだ読んでいない値を上書きしない
+fn expand_in_place(data: &mut [u16]) {
+    for i in (0..data.len()).rev() {
+        let byte = data[i / 2].to_ne_bytes()[i % 2];
+        data[i] = byte as u16 * 257;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    #[test]
+    fn matches_generic_decoder_for_8_and_16_bit_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let rgb8 = ImageBuffer::from_fn(33, 17, |x, y| {
+            Rgb([(x * 7) as u8, (y * 13) as u8, (x * y) as u8])
+        });
+        let rgb16: Rgb16Image = ImageBuffer::from_fn(33, 17, |x, y| {
+            Rgb([(x * 1999) as u16, (y * 3001) as u16, 40000])
+        });
+        let gray = image::GrayImage::from_fn(9, 5, |x, _| image::Luma([(x * 20) as u8]));
+        let cases = [
+            (
+                dir.path().join("a.png"),
+                DynamicImage::ImageRgb8(rgb8.clone()),
+            ),
+            (dir.path().join("b.jpg"), DynamicImage::ImageRgb8(rgb8)),
+            (
+                dir.path().join("c.png"),
+                DynamicImage::ImageRgb16(rgb16.clone()),
+            ),
+            (dir.path().join("d.tif"), DynamicImage::ImageRgb16(rgb16)),
+            (dir.path().join("e.png"), DynamicImage::ImageLuma8(gray)),
+        ];
+
+        for (path, image) in cases {
+            image.save(&path).unwrap();
+            let expected = image::open(&path).unwrap().to_rgb16();
+            assert_eq!(decode_rgb16(&path).unwrap(), expected, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.png");
+        std::fs::write(&path, b"\x89PNG\r\n\x1a\nbroken").unwrap();
+
+        assert!(decode_rgb16(&path)
+            .unwrap_err()
+            .to_string()
+            .contains("broken.png"));
+    }
+
+    #[test]
+    fn rejects_images_over_the_limits_before_decoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.png");
+        image::RgbImage::new(40, 20).save(&path).unwrap();
+        let limits = |max_width, max_pixels| ImageLimits {
+            max_width,
+            max_height: 100,
+            max_pixels,
+        };
+
+        for limits in [limits(39, 10_000), limits(100, 799)] {
+            let error = decode_rgb16_with(&path, &limits).unwrap_err();
+            assert!(matches!(
+                error,
+                DecodeError::ImageTooLarge {
+                    width: 40,
+                    height: 20,
+                    ..
+                }
+            ));
+        }
+        assert!(decode_rgb16_with(&path, &limits(40, 800)).is_ok());
+
+        // EXR はヘッダーだけ読んで確かめる
+        let exr = dir.path().join("large.exr");
+        crate::encode::write_exr(&Rgb16Image::new(40, 20), &exr, true, &Default::default())
+            .unwrap();
+        assert!(matches!(
+            check_exr_with(&exr, &limits(39, 10_000)),
+            Err(DecodeError::ImageTooLarge { width: 40, .. })
+        ));
+        assert!(check_exr_with(&exr, &limits(40, 800)).is_ok());
+    }
+}
@@ -0,0 +1,279 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{linear_to_srgb, srgb_to_linear, u16_to_unit, unit_to_u16};
+use crate::encode;
+use crate::exr_color::ExrColorSpace;
+use crate::exr_preview;
+use crate::formats;
+use crate::frame_select;
+use crate::merge::{self, MergeRequest, Rgb16Image};
+use crate::output_color::{self, OutputColorSpace};
+use crate::output_lock::OutputReservation;
+use crate::progress::ProgressReporter;
+use crate::workdir;
+
+const DEFAULT_OUTPUT_FOLDER: &str = "deflickered";
+// 補正の上限。これを超える差はちらつきではなく、日の出・日没など実際の明るさの変化とみなす
+const MAX_CORRECTION_EV: f64 = 2.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DeflickerOptions {
+    // 明るさを平均する前後のフレーム数（自身を含む奇数）。大きいほど滑らかになるが、明るさの変化に遅れる
+    pub window: usize,
+    // 0 なら補正しない。1 で平均した明るさに合わせる
+    pub strength: f64,
+    // deflicker_sequence の書き出し先。未指定なら folder の中の deflickered フォルダ
+    pub output_dir: Option<String>,
+}
+
+impl Default for DeflickerOptions {
+    fn default() -> Self {
+        Self {
+            window: 7,
+            strength: 1.0,
+            output_dir: None,
+        }
+    }
+}
+
+impl DeflickerOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window < 3 || self.window.is_multiple_of(2) {
+            return Err("デフリッカーの window は3以上の奇数にしてください".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.strength) {
+            return Err("デフリッカーの strength は0〜1で指定してください".to_string());
+        }
+        Ok(())
+    }
+}
+
+// 書き直すときに合成時と同じ形式で書くための、元の要求の出力設定
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputEncoding {
+    pub color_space: OutputColorSpace,
+    pub exr_color_space: Option<ExrColorSpace>,
+    pub exr_preview: bool,
+    pub deterministic: bool,
+}
+
+impl From<&MergeRequest> for OutputEncoding {
+    fn from(request: &MergeRequest) -> Self {
+        Self {
+            color_space: request.output_color_space,
+            exr_color_space: request.exr_color_space,
+            exr_preview: request.exr_preview,
+            deterministic: request.deterministic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct DeflickerFrame {
+    pub path: String,
+    pub output_paths: Vec<String>,
+    // フレームの平均輝度（log2）
+    pub measured_ev: f64,
+    // かけた補正。正なら明るくした
+    pub correction_ev: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct DeflickerResult {
+    pub output_dir: String,
+    pub frames: Vec<DeflickerFrame>,
+}
+
+pub fn default_output_dir(folder: &Path) -> PathBuf {
+    folder.join(DEFAULT_OUTPUT_FOLDER)
+}
+
+// 合成済みの連番（フォルダ直下の画像を名前順）の明るさをそろえ、同じ名前の PNG で書き出す
+pub fn deflicker_sequence(
+    folder: &Path,
+    options: &DeflickerOptions,
+) -> Result<DeflickerResult, String> {
+    let inputs = formats::list_decodable(folder)?;
+    let output_dir = options
+        .output_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_output_dir(folder));
+    std::fs::create_dir_all(&output_dir)
+        .map_err(|e| format!("出力先フォルダを作成できません: {}", e))?;
+    let outputs: Vec<Vec<PathBuf>> = inputs
+        .iter()
+        .map(|input| {
+            let mut name = input.file_stem().unwrap_or_default().to_os_string();
+            name.push(".png");
+            vec![output_dir.join(name)]
+        })
+        .collect();
+    Ok(DeflickerResult {
+        output_dir: output_dir.to_string_lossy().to_string(),
+        frames: deflicker_files(&inputs, &outputs, options, &OutputEncoding::default())?,
+    })
+}
+
+// inputs[i] を補正して outputs[i] のすべて（拡張子が exr なら EXR、それ以外は PNG）に encoding の形式で書き出す。
+// 全フレームを保持しないよう、明るさを測る読み込みと書き出しの読み込みを分ける。
+// 入力の PNG は合成時に EXR を書いたのと同じ 16bit の sRGB の値で読めるため、EXR もそこから書き直す
+pub fn deflicker_files(
+    inputs: &[PathBuf],
+    outputs: &[Vec<PathBuf>],
+    options: &DeflickerOptions,
+    encoding: &OutputEncoding,
+) -> Result<Vec<DeflickerFrame>, String> {
+    options.validate()?;
+    if inputs.len() < 2 {
+        return Err("デフリッカーには2枚以上のフレームが必要です".to_string());
+    }
+    let load = |path: &Path| merge::load_rgb16(&path.to_string_lossy());
+    let evs: Vec<f64> = inputs
+        .iter()
+        .map(|path| load(path).map(|image| frame_select::estimate_ev(&image)))
+        .collect::<Result<_, _>>()?;
+    let corrections = corrections(&evs, options);
+
+    let progress = ProgressReporter::default();
+    let mut frames = Vec::new();
+    for ((input, targets), (&measured_ev, &correction_ev)) in
+        inputs.iter().zip(outputs).zip(evs.iter().zip(&corrections))
+    {
+        let image = apply(&load(input)?, correction_ev);
+        // 書き直している間に、同じフレームへ書き出す合成が割り込まないようにする
+        let _reservation = OutputReservation::reserve_files(targets)?;
+        for target in targets {
+            let partial = workdir::partial_path(None, target);
+            let written = match formats::extension_of(target).as_deref() {
+                Some("exr") => encode::write_exr_with_preview(
+                    &image,
+                    &partial,
+                    encoding.deterministic,
+                    encoding
+                        .exr_preview
+                        .then(|| exr_preview::preview_attribute(&image)),
+                    encoding.exr_color_space,
+                    &progress,
+                ),
+                _ => {
+                    let converted = output_color::convert(&image, encoding.color_space);
+                    encode::write_png_as(
+                        converted.as_ref().unwrap_or(&image),
+                        &partial,
+                        encoding.color_space,
+                        &progress,
+                    )
+                }
+            };
+            written
+                .and_then(|_| workdir::move_into_place(&partial, target))
+                .inspect_err(|_| {
+                    let _ = std::fs::remove_file(&partial);
+                })?;
+        }
+        frames.push(DeflickerFrame {
+            path: input.to_string_lossy().to_string(),
+            output_paths: targets
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            measured_ev,
+            correction_ev,
+        });
+    }
+    Ok(frames)
+}
+
+// 前後 window 枚の平均との差を補正にする。端では窓を詰めて、あるフレームだけで平均する
+pub fn corrections(evs: &[f64], options: &DeflickerOptions) -> Vec<f64> {
+    let half = options.window / 2;
+    (0..evs.len())
+        .map(|index| {
+            let window = &evs[index.saturating_sub(half)..(index + half + 1).min(evs.len())];
+            let smoothed = window.iter().sum::<f64>() / window.len() as f64;
+            ((smoothed - evs[index]) * options.strength)
+                .clamp(-MAX_CORRECTION_EV, MAX_CORRECTION_EV)
+        })
+        .collect()
+}
+
+// 線形空間で明るさだけを変え、色の比は保つ
+pub fn apply(image: &Rgb16Image, correction_ev: f64) -> Rgb16Image {
+    let gain = 2f32.powf(correction_ev as f32);
+    let mut corrected = image.clone();
+    for value in corrected.iter_mut() {
+        *value = unit_to_u16(linear_to_srgb(srgb_to_linear(u16_to_unit(*value)) * gain));
+    }
+    corrected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn smooths_brightness_around_each_frame() {
+        let options = DeflickerOptions {
+            window: 3,
+            ..DeflickerOptions::default()
+        };
+        let evs = [-3.0, -3.0, -2.7, -3.0, -3.0];
+
+        let corrections = corrections(&evs, &options);
+
+        assert!(corrections[2] < -0.15);
+        assert!(corrections[1] > 0.0 && corrections[3] > 0.0);
+        assert_eq!(corrections[0], 0.0);
+        let none = DeflickerOptions {
+            strength: 0.0,
+            ..options
+        };
+        assert!(super::corrections(&evs, &none).iter().all(|c| *c == 0.0));
+    }
+
+    #[test]
+    fn evens_out_a_flickering_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let levels = [0.2f32, 0.2, 0.26, 0.2, 0.2];
+        for (index, level) in levels.iter().enumerate() {
+            let value = unit_to_u16(linear_to_srgb(*level));
+            let frame = Rgb16Image::from_pixel(16, 16, Rgb([value; 3]));
+            merge::save_png(&frame, &dir.path().join(format!("frame_{}.png", index))).unwrap();
+        }
+
+        let result = deflicker_sequence(dir.path(), &DeflickerOptions::default()).unwrap();
+
+        assert_eq!(result.frames.len(), 5);
+        let evs: Vec<f64> = result
+            .frames
+            .iter()
+            .map(|frame| {
+                assert!(frame.output_paths[0].contains(DEFAULT_OUTPUT_FOLDER));
+                frame_select::estimate_ev(&merge::load_rgb16(&frame.output_paths[0]).unwrap())
+            })
+            .collect();
+        let spread = |evs: &[f64]| {
+            evs.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+                - evs.iter().copied().fold(f64::INFINITY, f64::min)
+        };
+        let measured: Vec<f64> = result.frames.iter().map(|f| f.measured_ev).collect();
+        assert!(spread(&evs) < spread(&measured) / 2.0);
+    }
+
+    #[test]
+    fn rejects_even_windows() {
+        let options = DeflickerOptions {
+            window: 4,
+            ..DeflickerOptions::default()
+        };
+        assert!(options.validate().is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use image::imageops::{self, FilterType};
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{linear_to_srgb, luminance, srgb_to_linear, u16_to_unit, unit_to_u16};
+use crate::merge::{MergeQuality, Rgb16Image};
+
+// Fast / Balanced で動体の候補を探す縮小率
+const MASK_SCALE: u32 = 4;
+
+// 露出比の推定と判定に使う、白飛び・黒つぶれしていない範囲（sRGB 値）
+const WELL_EXPOSED: std::ops::RangeInclusive<f32> = 0.05..=0.95;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DeghostParams {
+    // 露出を揃えた後の輝度比（log2）がこの値を超えた画素を動体とみなす
+    pub threshold: f32,
+}
+
+impl Default for DeghostParams {
+    fn default() -> Self {
+        Self { threshold: 0.5 }
+    }
+}
+
+// 基準フレームと食い違う画素を、露出比を掛けた基準フレームの値で置き換える
+pub fn deghost_frames(
+    frames: &[Rgb16Image],
+    reference: usize,
+    params: &DeghostParams,
+    quality: MergeQuality,
+) -> Vec<Rgb16Image> {
+    let reference_frame = &frames[reference];
+    frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            if index == reference {
+                return frame.clone();
+            }
+            let ratio = match exposure_ratio(frame, reference_frame) {
+                Some(ratio) => ratio,
+                None => return frame.clone(),
+            };
+            match quality {
+                MergeQuality::Best => {
+                    replace_ghosts(frame, reference_frame, ratio, params.threshold)
+                }
+                MergeQuality::Fast | MergeQuality::Balanced => {
+                    replace_ghosts_coarse(frame, reference_frame, ratio, params.threshold, quality)
+                }
+            }
+        })
+        .collect()
+}
+
+fn linear_rgb(pixel: &Rgb<u16>) -> [f32; 3] {
+    [
+        srgb_to_linear(u16_to_unit(pixel[0])),
+        srgb_to_linear(u16_to_unit(pixel[1])),
+        srgb_to_linear(u16_to_unit(pixel[2])),
+    ]
+}
+
+fn is_well_exposed(pixel: &Rgb<u16>) -> bool {
+    pixel
+        .0
+        .iter()
+        .all(|v| WELL_EXPOSED.contains(&u16_to_unit(*v)))
+}
+
+// 両フレームで適正露出の画素について、リニア輝度比の中央値を露出比とする
+pub fn exposure_ratio(frame: &Rgb16Image, reference: &Rgb16Image) -> Option<f32> {
+    let mut ratios: Vec<f32> = frame
+        .pixels()
+        .zip(reference.pixels())
+        .step_by(7)
+        .filter(|(f, r)| is_well_exposed(f) && is_well_exposed(r))
+        .map(|(f, r)| luminance(linear_rgb(f)) / luminance(linear_rgb(r)).max(1e-6))
+        .collect();
+    if ratios.len() < 16 {
+        return None;
+    }
+    let middle = ratios.len() / 2;
+    let (_, median, _) = ratios.select_nth_unstable_by(middle, |a, b| a.total_cmp(b));
+    Some(*median)
+}
+
+fn replace_ghosts(
+    frame: &Rgb16Image,
+    reference: &Rgb16Image,
+    ratio: f32,
+    threshold: f32,
+) -> Rgb16Image {
+    let mut output = frame.clone();
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        if let Some(replacement) =
+            ghost_replacement(pixel, reference.get_pixel(x, y), ratio, threshold)
+        {
+            *pixel = replacement;
+        }
+    }
+    output
+}
+
+// 1/4 に縮小した画像で動体の候補を探し、原寸ではその周りだけを判定する。
+// Fast は判定を省き、候補のブロックをまるごと置き換える
+fn replace_ghosts_coarse(
+    frame: &Rgb16Image,
+    reference: &Rgb16Image,
+    ratio: f32,
+    threshold: f32,
+    quality: MergeQuality,
+) -> Rgb16Image {
+    let (width, height) = frame.dimensions();
+    let (small_width, small_height) = (width / MASK_SCALE, height / MASK_SCALE);
+    if small_width == 0 || small_height == 0 {
+        return replace_ghosts(frame, reference, ratio, threshold);
+    }
+    let small_frame = imageops::resize(frame, small_width, small_height, FilterType::Triangle);
+    let small_reference =
+        imageops::resize(reference, small_width, small_height, FilterType::Triangle);
+    let mut mask: Vec<bool> = small_frame
+        .pixels()
+        .zip(small_reference.pixels())
+        .map(|(f, r)| ghost_replacement(f, r, ratio, threshold).is_some())
+        .collect();
+    if quality == MergeQuality::Balanced {
+        mask = dilate(&mask, small_width as usize, small_height as usize);
+    }
+
+    let mut output = frame.clone();
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let mx = (x / MASK_SCALE).min(small_width - 1) as usize;
+        let my = (y / MASK_SCALE).min(small_height - 1) as usize;
+        if !mask[my * small_width as usize + mx] {
+            continue;
+        }
+        let reference_pixel = reference.get_pixel(x, y);
+        let replacement = match quality {
+            MergeQuality::Fast => Some(Rgb(linear_rgb(reference_pixel)
+                .map(|v| unit_to_u16(linear_to_srgb((v * ratio).min(1.0)))))),
+            _ => ghost_replacement(pixel, reference_pixel, ratio, threshold),
+        };
+        if let Some(replacement) = replacement {
+            *pixel = replacement;
+        }
+    }
+    output
+}
+
+// 縮小で境界の画素が候補から漏れないよう、1 画素ぶん広げる
+fn dilate(mask: &[bool], width: usize, height: usize) -> Vec<bool> {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            (y.saturating_sub(1)..=(y + 1).min(height - 1)).any(|ny| {
+                (x.saturating_sub(1)..=(x + 1).min(width - 1)).any(|nx| mask[ny * width + nx])
+            })
+        })
+        .collect()
+}
+
+// 露出比を掛けた基準フレームの値と食い違う画素なら、置き換える値を返す
+fn ghost_replacement(
+    pixel: &Rgb<u16>,
+    reference_pixel: &Rgb<u16>,
+    ratio: f32,
+    threshold: f32,
+) -> Option<Rgb<u16>> {
+    if !is_well_exposed(pixel) || !is_well_exposed(reference_pixel) {
+        return None;
+    }
+
+    let frame_luma = luminance(linear_rgb(pixel));
+    let expected = linear_rgb(reference_pixel).map(|v| v * ratio);
+    let expected_luma = luminance(expected);
+    if frame_luma <= 0.0 || expected_luma <= 0.0 {
+        return None;
+    }
+
+    if (frame_luma / expected_luma).log2().abs() > threshold {
+        Some(Rgb(
+            expected.map(|v| unit_to_u16(linear_to_srgb(v.min(1.0))))
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 露出だけが違う 2 枚のうち、片方の一部に動体（明るい四角）がある
+    fn frames() -> Vec<Rgb16Image> {
+        let reference = Rgb16Image::from_fn(128, 96, |x, _| {
+            let v = 20000 + (x as u16) * 100;
+            Rgb([v, v, v])
+        });
+        let mut frame = Rgb16Image::from_fn(128, 96, |x, y| {
+            let expected = linear_rgb(reference.get_pixel(x, y)).map(|v| v * 0.5);
+            Rgb(expected.map(|v| unit_to_u16(linear_to_srgb(v))))
+        });
+        for y in 40..64 {
+            for x in 50..78 {
+                frame.put_pixel(x, y, Rgb([50000, 50000, 50000]));
+            }
+        }
+        vec![reference, frame]
+    }
+
+    #[test]
+    fn balanced_quality_matches_full_resolution_mask() {
+        let frames = frames();
+        let params = DeghostParams::default();
+
+        let best = deghost_frames(&frames, 0, &params, MergeQuality::Best);
+        let balanced = deghost_frames(&frames, 0, &params, MergeQuality::Balanced);
+
+        assert_ne!(best[1], frames[1]);
+        assert_eq!(balanced[1], best[1]);
+    }
+}
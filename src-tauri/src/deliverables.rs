@@ -0,0 +1,329 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use image::codecs::jpeg::{JpegEncoder, PixelDensity};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::encode;
+use crate::merge::Rgb16Image;
+use crate::output_color::{self, OutputColorSpace};
+use crate::output_path;
+use crate::print_output::{self, InkWarning, PrintLayout, PrintSettings};
+use crate::progress::ProgressReporter;
+
+pub const MAX_DELIVERABLES: usize = 8;
+// 主出力と一緒に書き出すファイル（*_preview.jpg・*_layers.exr など）の suffix。
+// 拡張子が違っても紛らわしく、大文字小文字を区別しないファイルシステムでは重なるため使わせない
+const RESERVED_SUFFIXES: &[&str] = &["_preview", "_layers", "_transforms", "_short", "_clipping"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliverableFormat {
+    Png,
+    Jpeg,
+    Tiff,
+    Exr,
+}
+
+impl DeliverableFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            DeliverableFormat::Png => "png",
+            DeliverableFormat::Jpeg => "jpg",
+            DeliverableFormat::Tiff => "tif",
+            DeliverableFormat::Exr => "exr",
+        }
+    }
+
+    fn default_bit_depth(self) -> u8 {
+        match self {
+            DeliverableFormat::Jpeg => 8,
+            DeliverableFormat::Exr => 32,
+            DeliverableFormat::Png | DeliverableFormat::Tiff => 16,
+        }
+    }
+}
+
+// 1回の合成から追加で書き出す出力。名前は `{基本名}{suffix}.{拡張子}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct Deliverable {
+    pub format: DeliverableFormat,
+    pub suffix: String,
+    // 長辺の最大画素数。超える場合だけ縮小する
+    pub max_size: Option<u32>,
+    // PNG・TIFF は 8 か 16（既定 16）。JPEG は 8、EXR は 32bit float のみ
+    pub bit_depth: Option<u8>,
+    // JPEG の品質（1〜100、既定 90）
+    pub quality: Option<u8>,
+    // TIFF・JPEG に解像度を書き込む
+    pub print: Option<PrintSettings>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliverableOutput {
+    pub suffix: String,
+    pub format: DeliverableFormat,
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub print: Option<PrintLayout>,
+    pub ink_warning: Option<InkWarning>,
+}
+
+impl Deliverable {
+    fn bit_depth(&self) -> u8 {
+        self.bit_depth
+            .unwrap_or_else(|| self.format.default_bit_depth())
+    }
+
+    pub fn file_name(&self, base_name: &str) -> String {
+        format!("{}{}.{}", base_name, self.suffix, self.format.extension())
+    }
+}
+
+pub fn validate(deliverables: &[Deliverable]) -> Result<(), String> {
+    if deliverables.len() > MAX_DELIVERABLES {
+        return Err(format!("追加の出力は最大{}個までです", MAX_DELIVERABLES));
+    }
+    let mut names = Vec::new();
+    for deliverable in deliverables {
+        // 空の suffix は主出力の PNG・EXR と名前が重なるため認めない
+        if deliverable.suffix.is_empty()
+            || output_path::sanitize_component(&deliverable.suffix) != deliverable.suffix
+        {
+            return Err(format!(
+                "出力の suffix に使えない文字が含まれています: \"{}\"",
+                deliverable.suffix
+            ));
+        }
+        if RESERVED_SUFFIXES
+            .iter()
+            .any(|reserved| deliverable.suffix.eq_ignore_ascii_case(reserved))
+        {
+            return Err(format!(
+                "suffix \"{}\" は合成結果と一緒に書き出すファイルに使うため指定できません",
+                deliverable.suffix
+            ));
+        }
+        let name = deliverable.file_name("");
+        if names.contains(&name) {
+            return Err(format!("出力の名前が重複しています: {}", name));
+        }
+        names.push(name);
+
+        let depths: &[u8] = match deliverable.format {
+            DeliverableFormat::Png | DeliverableFormat::Tiff => &[8, 16],
+            DeliverableFormat::Jpeg => &[8],
+            DeliverableFormat::Exr => &[32],
+        };
+        if !depths.contains(&deliverable.bit_depth()) {
+            return Err(format!(
+                "{} の出力では bitDepth に {:?} を指定してください",
+                deliverable.format.extension(),
+                depths
+            ));
+        }
+        if deliverable.max_size.is_some_and(|size| size < 16) {
+            return Err("maxSize は 16 以上で指定してください".to_string());
+        }
+        if deliverable
+            .quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+        {
+            return Err("quality は 1〜100 で指定してください".to_string());
+        }
+        if let Some(print) = &deliverable.print {
+            if !matches!(
+                deliverable.format,
+                DeliverableFormat::Tiff | DeliverableFormat::Jpeg
+            ) {
+                return Err("print は TIFF・JPEG の出力でのみ指定できます".to_string());
+            }
+            print.validate()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn resize_for(image: &Rgb16Image, deliverable: &Deliverable) -> Option<Rgb16Image> {
+    let (width, height) = image.dimensions();
+    let max_size = deliverable.max_size?;
+    if width.max(height) <= max_size {
+        return None;
+    }
+    let scale = max_size as f64 / width.max(height) as f64;
+    let target = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+    Some(imageops::resize(
+        image,
+        target(width),
+        target(height),
+        FilterType::Lanczos3,
+    ))
+}
+
+// color_space は PNG・JPEG だけに使い、TIFF・EXR は sRGB のまま書く
+pub fn write(
+    image: &Rgb16Image,
+    deliverable: &Deliverable,
+    path: &Path,
+    print: Option<&PrintLayout>,
+    color_space: OutputColorSpace,
+    deterministic: bool,
+    progress: &ProgressReporter,
+) -> Result<(), String> {
+    if let (Some(print), DeliverableFormat::Tiff) = (print, deliverable.format) {
+        return print_output::write_tiff(image, path, deliverable.bit_depth() == 8, print.dpi);
+    }
+    let converted = match deliverable.format {
+        DeliverableFormat::Png | DeliverableFormat::Jpeg => {
+            output_color::convert(image, color_space)
+        }
+        DeliverableFormat::Tiff | DeliverableFormat::Exr => None,
+    };
+    let image = converted.as_ref().unwrap_or(image);
+    let eight_bit = || DynamicImage::ImageRgb16(image.clone()).to_rgb8();
+    match (deliverable.format, deliverable.bit_depth()) {
+        (DeliverableFormat::Png, 16) => encode::write_png_as(image, path, color_space, progress),
+        (DeliverableFormat::Png, _) => encode::write_png8(&eight_bit(), path, color_space),
+        (DeliverableFormat::Tiff, 16) => image
+            .save_with_format(path, ImageFormat::Tiff)
+            .map_err(|e| e.to_string()),
+        (DeliverableFormat::Tiff, _) => eight_bit()
+            .save_with_format(path, ImageFormat::Tiff)
+            .map_err(|e| e.to_string()),
+        (DeliverableFormat::Jpeg, _) => {
+            let quality = deliverable.quality.unwrap_or(90);
+            let mut jpeg = Vec::new();
+            let mut encoder = JpegEncoder::new_with_quality(&mut jpeg, quality);
+            if let Some(print) = print {
+                encoder.set_pixel_density(PixelDensity::dpi(print.dpi as u16));
+            }
+            encoder
+                .encode_image(&eight_bit())
+                .map_err(|e| e.to_string())?;
+            if let Some(profile) = output_color::icc_profile(color_space) {
+                jpeg = output_color::embed_jpeg_icc(&jpeg, &profile)?;
+            }
+            let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+            file.write_all(&jpeg)
+                .and_then(|_| file.flush())
+                .map_err(|e| e.to_string())
+        }
+        (DeliverableFormat::Exr, _) => encode::write_exr(image, path, deterministic, progress),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliverable(format: DeliverableFormat, suffix: &str) -> Deliverable {
+        Deliverable {
+            format,
+            suffix: suffix.to_string(),
+            max_size: None,
+            bit_depth: None,
+            quality: None,
+            print: None,
+        }
+    }
+
+    #[test]
+    fn rejects_conflicting_or_unsupported_outputs() {
+        let jpeg = deliverable(DeliverableFormat::Jpeg, "_web");
+        assert!(validate(&[jpeg.clone(), deliverable(DeliverableFormat::Tiff, "_web")]).is_ok());
+        assert!(validate(&[jpeg.clone(), jpeg.clone()]).is_err());
+        assert!(validate(&[deliverable(DeliverableFormat::Png, "")]).is_err());
+        assert!(validate(&[deliverable(DeliverableFormat::Png, "/../x")]).is_err());
+        for reserved in ["_preview", "_Layers", "_transforms", "_short", "_clipping"] {
+            assert!(
+                validate(&[deliverable(DeliverableFormat::Tiff, reserved)]).is_err(),
+                "{}",
+                reserved
+            );
+        }
+        assert!(validate(&[Deliverable {
+            bit_depth: Some(16),
+            ..jpeg
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn writes_each_format_at_requested_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = Rgb16Image::from_fn(400, 100, |x, y| {
+            image::Rgb([x as u16 * 150, y as u16 * 600, 30000])
+        });
+        let progress = ProgressReporter::default();
+        let cases = [
+            (
+                Deliverable {
+                    max_size: Some(200),
+                    quality: Some(80),
+                    ..deliverable(DeliverableFormat::Jpeg, "_web")
+                },
+                (200, 50),
+            ),
+            (deliverable(DeliverableFormat::Tiff, "_print"), (400, 100)),
+            (
+                Deliverable {
+                    bit_depth: Some(8),
+                    ..deliverable(DeliverableFormat::Png, "_8bit")
+                },
+                (400, 100),
+            ),
+        ];
+
+        for (deliverable, size) in cases {
+            let resized = resize_for(&image, &deliverable);
+            let path = dir.path().join(deliverable.file_name("hdr"));
+            write(
+                resized.as_ref().unwrap_or(&image),
+                &deliverable,
+                &path,
+                None,
+                OutputColorSpace::Srgb,
+                true,
+                &progress,
+            )
+            .unwrap();
+            assert_eq!(image::image_dimensions(&path).unwrap(), size, "{:?}", path);
+        }
+        let tiff = image::open(dir.path().join("hdr_print.tif")).unwrap();
+        assert_eq!(tiff.to_rgb16(), image);
+    }
+
+    #[test]
+    fn wide_gamut_jpegs_embed_an_icc_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p3.jpg");
+        let image = Rgb16Image::from_pixel(16, 16, image::Rgb([u16::MAX, 0, 0]));
+        let jpeg = deliverable(DeliverableFormat::Jpeg, "_p3");
+        let progress = ProgressReporter::default();
+
+        write(
+            &image,
+            &jpeg,
+            &path,
+            None,
+            OutputColorSpace::DisplayP3,
+            true,
+            &progress,
+        )
+        .unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.windows(12).any(|w| w == b"ICC_PROFILE\0"));
+        let decoded = image::open(&path).unwrap().to_rgb8();
+        // sRGB の赤は P3 では彩度の低い赤になる
+        let [r, g, _] = decoded.get_pixel(8, 8).0;
+        assert!(r > 200 && g > 30);
+    }
+}
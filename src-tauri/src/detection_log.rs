@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+// メモリに残す件数。ファイルはこの倍を超えたら残っている分だけで書き直す
+const MAX_ENTRIES: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum DetectionOutcome {
+    Detected,
+    Debounced,
+    UnsupportedExtension,
+    OwnOutput,
+    IgnoredDir,
+    // 書き込み途中。読めるようになったら改めて判定する
+    Incomplete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum DetectionKind {
+    Create,
+    Modify,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct Detection {
+    pub path: String,
+    pub detected_at: String,
+    pub event_kind: DetectionKind,
+    pub outcome: DetectionOutcome,
+}
+
+struct LogState {
+    entries: VecDeque<Detection>,
+    written: usize,
+}
+
+// 監視で受け取ったイベントの記録。起動ごとに新しく始め、前回の分は .prev に残す
+pub struct DetectionLog {
+    path: PathBuf,
+    state: Mutex<LogState>,
+}
+
+impl DetectionLog {
+    pub fn open(path: PathBuf) -> Self {
+        if path.exists() {
+            let _ = fs::rename(&path, previous_path(&path));
+        }
+        Self {
+            path,
+            state: Mutex::new(LogState {
+                entries: VecDeque::new(),
+                written: 0,
+            }),
+        }
+    }
+
+    pub fn record(&self, detection: Detection) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let line = serde_json::to_string(&detection).map_err(|e| e.to_string())?;
+        state.entries.push_back(detection);
+        while state.entries.len() > MAX_ENTRIES {
+            state.entries.pop_front();
+        }
+
+        if state.written >= MAX_ENTRIES * 2 {
+            let lines: Vec<String> = state
+                .entries
+                .iter()
+                .filter_map(|entry| serde_json::to_string(entry).ok())
+                .collect();
+            state.written = lines.len();
+            return self.write(&format!("{}\n", lines.join("\n")), false);
+        }
+        state.written += 1;
+        self.write(&format!("{}\n", line), true)
+    }
+
+    // 新しい順に返す
+    pub fn entries(&self, limit: Option<usize>) -> Result<Vec<Detection>, String> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(state
+            .entries
+            .iter()
+            .rev()
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    pub fn export(&self, path: &Path) -> Result<usize, String> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let text = serde_json::to_string_pretty(&state.entries).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("検出ログを書き出せません: {}", e))?;
+        Ok(state.entries.len())
+    }
+
+    fn write(&self, text: &str, append: bool) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(&self.path)
+            .map_err(|e| format!("検出ログを保存できません: {}", e))?;
+        file.write_all(text.as_bytes())
+            .map_err(|e| format!("検出ログを保存できません: {}", e))
+    }
+}
+
+fn previous_path(path: &Path) -> PathBuf {
+    path.with_extension("prev.jsonl")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(path: &str, outcome: DetectionOutcome) -> Detection {
+        Detection {
+            path: path.to_string(),
+            detected_at: "2024-01-01T00:00:00+09:00".to_string(),
+            event_kind: DetectionKind::Create,
+            outcome,
+        }
+    }
+
+    #[test]
+    fn persists_session_and_keeps_previous() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("detections.jsonl");
+        let log = DetectionLog::open(path.clone());
+        log.record(detection("a.jpg", DetectionOutcome::Detected))
+            .unwrap();
+        log.record(detection("a.jpg", DetectionOutcome::Debounced))
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let entries = log.entries(Some(1)).unwrap();
+        assert_eq!(entries[0].outcome, DetectionOutcome::Debounced);
+
+        let next = DetectionLog::open(path.clone());
+        assert!(next.entries(None).unwrap().is_empty());
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("detections.prev.jsonl"))
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn caps_memory_and_rewrites_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("detections.jsonl");
+        let log = DetectionLog::open(path.clone());
+        for i in 0..MAX_ENTRIES * 2 + 1 {
+            log.record(detection(&i.to_string(), DetectionOutcome::OwnOutput))
+                .unwrap();
+        }
+
+        assert_eq!(log.entries(None).unwrap().len(), MAX_ENTRIES);
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, MAX_ENTRIES);
+
+        let export = dir.path().join("export.json");
+        assert_eq!(log.export(&export).unwrap(), MAX_ENTRIES);
+        let exported: Vec<Detection> =
+            serde_json::from_str(&fs::read_to_string(&export).unwrap()).unwrap();
+        assert_eq!(exported.last().unwrap().path, (MAX_ENTRIES * 2).to_string());
+    }
+}
@@ -0,0 +1,327 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::merge::PARTIAL_SUFFIX;
+
+pub const THUMBNAIL_CACHE: &str = "thumbnails";
+pub const ANALYSIS_CACHE: &str = "analysis";
+// 容量で管理するキャッシュ。maintenance の経過時間による削除の対象外にする
+pub const MANAGED_CACHES: &[&str] = &[THUMBNAIL_CACHE, ANALYSIS_CACHE];
+// エントリの名前は元ファイルから作ったハッシュで元に戻せないため、元ファイルのパスを隣の {エントリ}.source に書いておく
+const SOURCE_SUFFIX: &str = ".source";
+const MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CacheLimits {
+    pub thumbnail_max_mb: u64,
+    pub analysis_max_mb: u64,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            thumbnail_max_mb: 512,
+            analysis_max_mb: 64,
+        }
+    }
+}
+
+impl CacheLimits {
+    pub fn max_bytes(&self, name: &str) -> u64 {
+        match name {
+            THUMBNAIL_CACHE => self.thumbnail_max_mb * MB,
+            _ => self.analysis_max_mb * MB,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheUsage {
+    pub name: String,
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+}
+
+// 最終利用時刻はファイルの更新日時で持つ。再起動後も順序が保たれ、索引ファイルが要らない
+pub struct DiskCache {
+    name: String,
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl DiskCache {
+    pub fn new(root: &Path, name: &str, limits: &CacheLimits) -> Self {
+        Self {
+            name: name.to_string(),
+            dir: root.join(name),
+            max_bytes: limits.max_bytes(name),
+        }
+    }
+
+    pub fn get(&self, entry: &str) -> Option<PathBuf> {
+        let path = self.dir.join(entry);
+        let file = fs::File::options().write(true).open(&path).ok()?;
+        let _ = file.set_modified(SystemTime::now());
+        Some(path)
+    }
+
+    // source はエントリの元ファイル。消えたら maintenance の片付けでエントリも消す
+    pub fn put(
+        &self,
+        entry: &str,
+        source: &Path,
+        write: impl FnOnce(&Path) -> Result<(), String>,
+    ) -> Result<PathBuf, String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let path = self.dir.join(entry);
+        let partial_path = self.dir.join(format!("{}{}", entry, PARTIAL_SUFFIX));
+        write(&partial_path)
+            .and_then(|_| fs::rename(&partial_path, &path).map_err(|e| e.to_string()))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&partial_path);
+            })?;
+        // 記録できなくてもエントリは使え、元ファイルが消えたときに片付けられないだけで済む
+        let _ = fs::write(source_path(&path), source.to_string_lossy().as_bytes());
+        self.evict(&path);
+        Ok(path)
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let entries = self.entries();
+        CacheUsage {
+            name: self.name.clone(),
+            entries: entries.len(),
+            bytes: entries.iter().map(|entry| entry.size).sum(),
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    // 上限を超えた分を、使われていない順に消す。書き込んだばかりのエントリは残す
+    fn evict(&self, keep: &Path) {
+        let mut entries = self.entries();
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        entries.sort_by_key(|entry| entry.used_at);
+        for entry in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if entry.path != keep && fs::remove_file(&entry.path).is_ok() {
+                let _ = fs::remove_file(source_path(&entry.path));
+                total -= entry.size;
+            }
+        }
+    }
+
+    fn entries(&self) -> Vec<Entry> {
+        let Ok(read_dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        read_dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                !name.ends_with(PARTIAL_SUFFIX) && !name.ends_with(SOURCE_SUFFIX)
+            })
+            .filter_map(|entry| {
+                let metadata = entry
+                    .metadata()
+                    .ok()
+                    .filter(|metadata| metadata.is_file())?;
+                Some(Entry {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    used_at: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                })
+            })
+            .collect()
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    used_at: SystemTime,
+}
+
+fn source_path(entry: &Path) -> PathBuf {
+    let mut name = entry.as_os_str().to_os_string();
+    name.push(SOURCE_SUFFIX);
+    PathBuf::from(name)
+}
+
+// dir のキャッシュのうち、元ファイルが消えたエントリとその記録。
+// 元ファイルを記録していないエントリと、ドライブの切断などで確かめられない元ファイルのエントリは残す
+pub fn orphaned_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut orphaned = Vec::new();
+    for record in read_dir.filter_map(|entry| entry.ok()) {
+        let record = record.path();
+        let Some(entry) = record
+            .to_str()
+            .and_then(|record| record.strip_suffix(SOURCE_SUFFIX))
+            .map(PathBuf::from)
+        else {
+            continue;
+        };
+        let Ok(source) = fs::read_to_string(&record) else {
+            continue;
+        };
+        let missing = matches!(
+            fs::metadata(source.trim_end()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound
+        );
+        if missing {
+            if entry.is_file() {
+                orphaned.push(entry);
+            }
+            orphaned.push(record);
+        }
+    }
+    orphaned
+}
+
+// 64bit の FNV-1a。DefaultHasher は Rust のバージョンで値が変わり、更新のたびにキャッシュが使えなくなるため使わない
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    // 長さを先に入れ、続けて入れた値の境目がずれても同じ値にならないようにする
+    fn field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+// 元ファイルのパス・サイズ・更新日時から名前を作る。元ファイルが変わると別エントリになり、古い方は LRU で消える
+pub fn entry_name(source: &Path, variant: &str, extension: &str) -> Result<String, String> {
+    let metadata = fs::metadata(source)
+        .map_err(|e| format!("{} を読み込めません: {}", source.to_string_lossy(), e))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let mut hasher = Fnv1a::new();
+    hasher.field(source.to_string_lossy().as_bytes());
+    hasher.field(&metadata.len().to_le_bytes());
+    hasher.field(&modified.as_secs().to_le_bytes());
+    hasher.field(&modified.subsec_nanos().to_le_bytes());
+    hasher.field(variant.as_bytes());
+    Ok(format!("{:016x}.{}", hasher.0, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn write_bytes(size: usize) -> impl FnOnce(&Path) -> Result<(), String> {
+        move |path| fs::write(path, vec![0u8; size]).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries_over_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache {
+            name: ANALYSIS_CACHE.to_string(),
+            dir: dir.path().join(ANALYSIS_CACHE),
+            max_bytes: 25,
+        };
+        let source = dir.path().join("shot.jpg");
+        let old = cache.put("a", &source, write_bytes(10)).unwrap();
+        let used = cache.put("b", &source, write_bytes(10)).unwrap();
+        for (path, age) in [(&old, 30), (&used, 20)] {
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        }
+        // 古い方を読むと最近使ったことになり、もう一方が先に消える
+        assert!(cache.get("a").is_some());
+
+        cache.put("c", &source, write_bytes(10)).unwrap();
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(!source_path(&used).exists());
+        let usage = cache.usage();
+        assert_eq!((usage.entries, usage.bytes), (2, 20));
+    }
+
+    #[test]
+    fn keeps_new_entry_even_if_larger_than_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache {
+            name: THUMBNAIL_CACHE.to_string(),
+            dir: dir.path().to_path_buf(),
+            max_bytes: 5,
+        };
+        let source = dir.path().join("shot.jpg");
+
+        let path = cache.put("big.png", &source, write_bytes(10)).unwrap();
+
+        assert!(path.exists());
+        assert_eq!(cache.usage().entries, 1);
+    }
+
+    #[test]
+    fn finds_entries_whose_source_was_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache {
+            name: THUMBNAIL_CACHE.to_string(),
+            dir: dir.path().join(THUMBNAIL_CACHE),
+            max_bytes: 1024,
+        };
+        let kept = dir.path().join("kept.jpg");
+        let removed = dir.path().join("removed.jpg");
+        fs::write(&kept, b"jpeg").unwrap();
+        fs::write(&removed, b"jpeg").unwrap();
+        cache.put("kept.png", &kept, write_bytes(10)).unwrap();
+        let orphan = cache.put("removed.png", &removed, write_bytes(10)).unwrap();
+        fs::write(cache.dir.join("unrecorded.png"), b"png").unwrap();
+        fs::remove_file(&removed).unwrap();
+
+        let mut orphaned = orphaned_files(&cache.dir);
+        orphaned.sort();
+
+        assert_eq!(orphaned, vec![orphan.clone(), source_path(&orphan)]);
+        // 記録はエントリとして数えない
+        assert_eq!(cache.usage().entries, 3);
+    }
+
+    #[test]
+    fn entry_names_are_stable_across_builds() {
+        // FNV-1a の公開されているテスト値
+        let mut hasher = Fnv1a::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xaf63_dc4c_8601_ec8c);
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("shot.jpg");
+        fs::write(&source, b"jpeg").unwrap();
+        let name = entry_name(&source, "256", "png").unwrap();
+        assert_eq!(name, entry_name(&source, "256", "png").unwrap());
+        assert_ne!(name, entry_name(&source, "512", "png").unwrap());
+        assert!(name.ends_with(".png") && name.len() == 20);
+    }
+}
@@ -0,0 +1,444 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::exif::CameraInfo;
+use crate::merge::Rgb16Image;
+use crate::progress::ProgressReporter;
+use crate::radiance::Radiance;
+
+const ROWS_PER_STRIP: u32 = 64;
+const TYPE_BYTE: u16 = 1;
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+const TYPE_SRATIONAL: u16 = 10;
+const PHOTOMETRIC_LINEAR_RAW: u16 = 34892;
+const ILLUMINANT_D65: u16 = 21;
+// XYZ（D65）→ 線形 sRGB。合成結果の RGB をそのままカメラの色として扱う
+const XYZ_TO_LINEAR_SRGB: [f64; 9] = [
+    3.2406, -1.5372, -0.4986, -0.9689, 1.8758, 0.0415, 0.0557, -0.2040, 1.0570,
+];
+const SOFTWARE: &str = "VHDR";
+
+enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SRational(Vec<(i32, i32)>),
+}
+
+impl Value {
+    fn kind(&self) -> u16 {
+        match self {
+            Value::Byte(_) => TYPE_BYTE,
+            Value::Ascii(_) => TYPE_ASCII,
+            Value::Short(_) => TYPE_SHORT,
+            Value::Long(_) => TYPE_LONG,
+            Value::Rational(_) => TYPE_RATIONAL,
+            Value::SRational(_) => TYPE_SRATIONAL,
+        }
+    }
+
+    fn count(&self) -> u32 {
+        (match self {
+            Value::Byte(values) => values.len(),
+            Value::Ascii(text) => text.len() + 1,
+            Value::Short(values) => values.len(),
+            Value::Long(values) => values.len(),
+            Value::Rational(values) => values.len(),
+            Value::SRational(values) => values.len(),
+        }) as u32
+    }
+
+    // リトルエンディアンで書く
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Byte(values) => values.clone(),
+            Value::Ascii(text) => text.bytes().chain([0]).collect(),
+            Value::Short(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Long(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Rational(values) => values
+                .iter()
+                .flat_map(|(n, d)| n.to_le_bytes().into_iter().chain(d.to_le_bytes()))
+                .collect(),
+            Value::SRational(values) => values
+                .iter()
+                .flat_map(|(n, d)| n.to_le_bytes().into_iter().chain(d.to_le_bytes()))
+                .collect(),
+        }
+    }
+}
+
+// IFD と、値の場所に収まらない値を続けて並べる。start は IFD を置くファイル上の位置
+fn encode_ifd(entries: &[(u16, Value)], start: u32) -> Vec<u8> {
+    let mut sorted: Vec<&(u16, Value)> = entries.iter().collect();
+    sorted.sort_by_key(|(tag, _)| *tag);
+    let data_start = start + 2 + 12 * sorted.len() as u32 + 4;
+    let mut ifd = (sorted.len() as u16).to_le_bytes().to_vec();
+    let mut data = Vec::new();
+    for (tag, value) in sorted {
+        ifd.extend(tag.to_le_bytes());
+        ifd.extend(value.kind().to_le_bytes());
+        ifd.extend(value.count().to_le_bytes());
+        let mut bytes = value.bytes();
+        if bytes.len() <= 4 {
+            bytes.resize(4, 0);
+            ifd.extend(bytes);
+        } else {
+            ifd.extend((data_start + data.len() as u32).to_le_bytes());
+            data.extend(bytes);
+            // 値は偶数の位置から始める
+            data.resize(data.len().div_ceil(2) * 2, 0);
+        }
+    }
+    // 次の IFD はない
+    ifd.extend(0u32.to_le_bytes());
+    ifd.extend(data);
+    // 続く IFD や画素が4バイト境界から始まるようにそろえる
+    ifd.resize(ifd.len().div_ceil(4) * 4, 0);
+    ifd
+}
+
+fn rational(value: f64) -> (u32, u32) {
+    ((value * 1000.0).round().max(0.0) as u32, 1000)
+}
+
+// 撮影日時とレンズなど、元のフレームの EXIF から現像ソフトのレンズプロファイルの選択に要る項目だけを写す
+fn exif_entries(camera: &CameraInfo) -> Vec<(u16, Value)> {
+    let mut entries = Vec::new();
+    if let Some(f_number) = camera.f_number {
+        entries.push((0x829d, Value::Rational(vec![rational(f_number)])));
+    }
+    if let Some(date) = &camera.date_time_original {
+        entries.push((0x9003, Value::Ascii(date.clone())));
+    }
+    if let Some(focal_length) = camera.focal_length {
+        entries.push((0x920a, Value::Rational(vec![rational(focal_length)])));
+    }
+    if let Some(lens_make) = &camera.lens_make {
+        entries.push((0xa433, Value::Ascii(lens_make.clone())));
+    }
+    if let Some(lens_model) = &camera.lens_model {
+        entries.push((0xa434, Value::Ascii(lens_model.clone())));
+    }
+    entries
+}
+
+fn camera_model(camera: &CameraInfo) -> String {
+    match (&camera.make, &camera.model) {
+        (Some(make), Some(model)) if model.starts_with(make.as_str()) => model.clone(),
+        (Some(make), Some(model)) => format!("{} {}", make, model),
+        (None, Some(model)) => model.clone(),
+        _ => SOFTWARE.to_string(),
+    }
+}
+
+fn main_entries(
+    width: u32,
+    height: u32,
+    strip_offsets: Vec<u32>,
+    strip_byte_counts: Vec<u32>,
+    exif_offset: Option<u32>,
+    baseline_exposure: f64,
+    camera: &CameraInfo,
+) -> Vec<(u16, Value)> {
+    let mut entries = vec![
+        (254, Value::Long(vec![0])),
+        (256, Value::Long(vec![width])),
+        (257, Value::Long(vec![height])),
+        (258, Value::Short(vec![16; 3])),
+        (259, Value::Short(vec![1])),
+        (262, Value::Short(vec![PHOTOMETRIC_LINEAR_RAW])),
+        (273, Value::Long(strip_offsets)),
+        (274, Value::Short(vec![1])),
+        (277, Value::Short(vec![3])),
+        (278, Value::Long(vec![ROWS_PER_STRIP])),
+        (279, Value::Long(strip_byte_counts)),
+        (284, Value::Short(vec![1])),
+        (305, Value::Ascii(SOFTWARE.to_string())),
+        (50706, Value::Byte(vec![1, 4, 0, 0])),
+        (50707, Value::Byte(vec![1, 1, 0, 0])),
+        (50708, Value::Ascii(camera_model(camera))),
+        (50717, Value::Long(vec![u16::MAX as u32; 3])),
+        (
+            50721,
+            Value::SRational(
+                XYZ_TO_LINEAR_SRGB
+                    .iter()
+                    .map(|v| ((v * 10000.0).round() as i32, 10000))
+                    .collect(),
+            ),
+        ),
+        // 合成結果は白バランス済みなので、白は RGB が等しい
+        (50728, Value::Rational(vec![(1, 1); 3])),
+        // BaselineExposure。最も明るい値を白に縮めた分を戻し、基準フレームの明るさで開く
+        (
+            50730,
+            Value::SRational(vec![((baseline_exposure * 1000.0).round() as i32, 1000)]),
+        ),
+        (50778, Value::Short(vec![ILLUMINANT_D65])),
+    ];
+    if let Some(make) = &camera.make {
+        entries.push((271, Value::Ascii(make.clone())));
+    }
+    if let Some(model) = &camera.model {
+        entries.push((272, Value::Ascii(model.clone())));
+    }
+    // LensInfo。単焦点・絞りひとつとして書く
+    if let (Some(focal_length), Some(f_number)) = (camera.focal_length, camera.f_number) {
+        let (focal_length, f_number) = (rational(focal_length), rational(f_number));
+        entries.push((
+            50736,
+            Value::Rational(vec![focal_length, focal_length, f_number, f_number]),
+        ));
+    }
+    if let Some(offset) = exif_offset {
+        entries.push((0x8769, Value::Long(vec![offset])));
+    }
+    entries
+}
+
+// トーンマップ前の線形の放射輝度（radiance::Radiance）を、現像ソフトで RAW と同じ調整ができる
+// LinearRaw の DNG（非圧縮 16bit）にする。baseline_exposure は BaselineExposure に書く EV
+pub fn write_dng(
+    image: &Rgb16Image,
+    baseline_exposure: f64,
+    path: &Path,
+    camera: &CameraInfo,
+    progress: &ProgressReporter,
+) -> Result<(), String> {
+    let (width, height) = image.dimensions();
+    let row_bytes = width as u64 * 3 * 2;
+    let strips: Vec<u32> = (0..height.div_ceil(ROWS_PER_STRIP))
+        .map(|strip| ROWS_PER_STRIP.min(height - strip * ROWS_PER_STRIP))
+        .collect();
+    let byte_counts = strips
+        .iter()
+        .map(|rows| u32::try_from(*rows as u64 * row_bytes))
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| "DNG に書き出すには画像が大きすぎます".to_string())?;
+
+    // IFD の大きさは値によらないので、仮の位置で並べてから位置を決める
+    let exif = exif_entries(camera);
+    let placeholder = main_entries(
+        width,
+        height,
+        vec![0; strips.len()],
+        byte_counts.clone(),
+        (!exif.is_empty()).then_some(0),
+        baseline_exposure,
+        camera,
+    );
+    let exif_start = 8 + encode_ifd(&placeholder, 8).len() as u32;
+    let exif_ifd = match exif.is_empty() {
+        true => Vec::new(),
+        false => encode_ifd(&exif, exif_start),
+    };
+    let data_start = exif_start as u64 + exif_ifd.len() as u64;
+    let mut offsets = Vec::with_capacity(strips.len());
+    let mut offset = data_start;
+    for count in &byte_counts {
+        offsets.push(u32::try_from(offset).map_err(|_| "DNG に書き出すには画像が大きすぎます")?);
+        offset += *count as u64;
+    }
+    u32::try_from(offset).map_err(|_| "DNG に書き出すには画像が大きすぎます")?;
+    let entries = main_entries(
+        width,
+        height,
+        offsets,
+        byte_counts,
+        (!exif.is_empty()).then_some(exif_start),
+        baseline_exposure,
+        camera,
+    );
+
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(file);
+    let write_error = |e: std::io::Error| e.to_string();
+    writer.write_all(b"II*\0").map_err(write_error)?;
+    writer.write_all(&8u32.to_le_bytes()).map_err(write_error)?;
+    writer
+        .write_all(&encode_ifd(&entries, 8))
+        .map_err(write_error)?;
+    writer.write_all(&exif_ifd).map_err(write_error)?;
+    let row_len = width as usize * 3;
+    let mut bytes = Vec::with_capacity(row_bytes as usize * ROWS_PER_STRIP as usize);
+    for (strip, rows) in image
+        .as_raw()
+        .chunks(row_len * ROWS_PER_STRIP as usize)
+        .enumerate()
+    {
+        progress.check_cancelled()?;
+        bytes.clear();
+        bytes.extend(rows.iter().flat_map(|value| value.to_le_bytes()));
+        writer.write_all(&bytes).map_err(write_error)?;
+        let written = (strip as u32 + 1) * ROWS_PER_STRIP;
+        progress.report("encodeDng", written as f64 / height.max(1) as f64);
+    }
+    writer.flush().map_err(write_error)
+}
+
+// write_dng で書き出した DNG から放射輝度を読み戻す。HDR プレビューをトーンマップ前の値から作るのに使う。
+// 非圧縮 16bit RGB の LinearRaw だけを読み、それ以外の DNG はエラーにする
+pub fn read_radiance(path: &Path) -> Result<Radiance, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("{} を読み込めません: {}", path.to_string_lossy(), e))?;
+    let unsupported = || {
+        format!(
+            "{} はこのアプリで書き出した LinearRaw の DNG ではありません",
+            path.to_string_lossy()
+        )
+    };
+    let u16_at = |at: usize| {
+        bytes
+            .get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |at: usize| {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if bytes.get(..4) != Some(b"II*\0".as_slice()) {
+        return Err(unsupported());
+    }
+    let ifd = u32_at(4).ok_or_else(unsupported)? as usize;
+    let count = u16_at(ifd).ok_or_else(unsupported)? as usize;
+    // 項目の個数と、値（4バイトに収まらなければその位置）
+    let find = |tag: u16| {
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|entry| u16_at(*entry) == Some(tag))
+            .and_then(|entry| Some((u32_at(entry + 4)? as usize, u32_at(entry + 8)?)))
+    };
+    // 1 つだけなら値そのもの、複数なら値の並びの位置
+    let longs = |tag: u16| -> Option<Vec<u32>> {
+        let (count, value) = find(tag)?;
+        match count {
+            1 => Some(vec![value]),
+            _ => (0..count).map(|i| u32_at(value as usize + i * 4)).collect(),
+        }
+    };
+    let short = |tag: u16| find(tag).map(|(_, value)| value & 0xffff);
+    let supported = short(259) == Some(1)
+        && short(262) == Some(PHOTOMETRIC_LINEAR_RAW as u32)
+        && short(277) == Some(3);
+    if !supported {
+        return Err(unsupported());
+    }
+    let (_, width) = find(256).ok_or_else(unsupported)?;
+    let (_, height) = find(257).ok_or_else(unsupported)?;
+    let offsets = longs(273).ok_or_else(unsupported)?;
+    let byte_counts = longs(279).ok_or_else(unsupported)?;
+    let mut raw = Vec::with_capacity(bytes.len() / 2);
+    for (offset, byte_count) in offsets.iter().zip(&byte_counts) {
+        let (start, end) = (*offset as usize, *offset as usize + *byte_count as usize);
+        let strip = bytes.get(start..end).ok_or_else(unsupported)?;
+        raw.extend(
+            strip
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]])),
+        );
+    }
+    let image = Rgb16Image::from_raw(width, height, raw).ok_or_else(unsupported)?;
+    let baseline_exposure = match find(50730) {
+        Some((_, at)) => {
+            let at = at as usize;
+            let numerator = u32_at(at).ok_or_else(unsupported)? as i32;
+            let denominator = u32_at(at + 4).ok_or_else(unsupported)? as i32;
+            numerator as f64 / denominator.max(1) as f64
+        }
+        None => 0.0,
+    };
+    Ok(Radiance {
+        image,
+        baseline_exposure,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exif;
+    use image::Rgb;
+
+    // IFD0 の項目の型・個数・値の場所（4バイト）
+    fn ifd0(bytes: &[u8]) -> Vec<(u16, u16, u32, u32)> {
+        let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let start = u32_at(4) as usize;
+        (0..u16_at(start) as usize)
+            .map(|i| start + 2 + i * 12)
+            .map(|entry| {
+                (
+                    u16_at(entry),
+                    u16_at(entry + 2),
+                    u32_at(entry + 4),
+                    u32_at(entry + 8),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_a_linear_raw_dng_with_camera_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.dng");
+        let image = Rgb16Image::from_fn(70, 130, |x, _| match x {
+            0 => Rgb([u16::MAX; 3]),
+            _ => Rgb([32768, 0, 0]),
+        });
+        let camera = CameraInfo {
+            make: Some("Xyz".to_string()),
+            model: Some("Xyz Z1".to_string()),
+            lens_model: Some("35mm F2".to_string()),
+            focal_length: Some(35.0),
+            f_number: Some(2.0),
+            ..Default::default()
+        };
+
+        write_dng(&image, 2.5, &path, &camera, &ProgressReporter::default()).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], b"II*\0");
+        let entries = ifd0(&bytes);
+        let tags: Vec<u16> = entries.iter().map(|entry| entry.0).collect();
+        let mut sorted = tags.clone();
+        sorted.sort();
+        assert_eq!(tags, sorted);
+        let find = |tag: u16| *entries.iter().find(|entry| entry.0 == tag).unwrap();
+        assert_eq!(find(262).3, PHOTOMETRIC_LINEAR_RAW as u32);
+        assert_eq!(find(50706).3.to_le_bytes(), [1, 4, 0, 0]);
+        // 130 行は 64 行ずつ 3 つのストリップになる
+        let (_, _, strips, strip_offsets) = find(273);
+        assert_eq!(strips, 3);
+        let first = strip_offsets as usize;
+        let first = u32::from_le_bytes(bytes[first..first + 4].try_into().unwrap()) as usize;
+        let pixels: Vec<u16> = bytes[first..first + 12]
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        // 線形の値をそのまま書き、BaselineExposure で明るさを戻す
+        assert_eq!(pixels, [u16::MAX, u16::MAX, u16::MAX, 32768, 0, 0]);
+        let (_, kind, count, at) = find(50730);
+        assert_eq!((kind, count), (TYPE_SRATIONAL, 1));
+        let at = at as usize;
+        let numerator = i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let denominator = i32::from_le_bytes(bytes[at + 4..at + 8].try_into().unwrap());
+        assert_eq!(numerator as f64 / denominator as f64, 2.5);
+        assert_eq!(bytes.len(), first + 70 * 130 * 6);
+        // カメラとレンズはもとの EXIF と同じ形で読める
+        assert_eq!(exif::read_camera(&path).unwrap(), camera);
+
+        // 放射輝度として読み戻せる
+        let radiance = read_radiance(&path).unwrap();
+        assert_eq!(radiance.image, image);
+        assert_eq!(radiance.baseline_exposure, 2.5);
+        std::fs::write(&path, b"II*\0").unwrap();
+        assert!(read_radiance(&path).is_err());
+    }
+}
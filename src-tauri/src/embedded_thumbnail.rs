@@ -0,0 +1,429 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use image::codecs::jpeg::JpegDecoder;
+use image::DynamicImage;
+
+use crate::formats;
+
+// 壊れたファイルで IFD の鎖が循環しても止まるよう、たどる数を制限する
+const MAX_IFDS: usize = 32;
+// 埋め込みの JPEG としてあり得ない大きさは読まない
+const MAX_PREVIEW_BYTES: u32 = 64 * 1024 * 1024;
+
+// 一覧用のサムネイルを、本体を展開せずにファイルへ埋め込まれた JPEG から作る。
+// JPEG は EXIF の IFD1、TIFF 系の RAW（CR2・NEF・ARW・DNG・RW2 など）は IFD と SubIFD、RAF はヘッダーが指す JPEG を探す。
+// 長辺が max_size 以上のうち最も小さいものを使い、足りなければ展開できる形式は None を返して本体の展開に任せる。
+// 本体を展開できない RAW は小さくても最も大きい埋め込みを使う
+pub fn read(path: &Path, max_size: u32) -> Option<DynamicImage> {
+    let mut file = File::open(path).ok()?;
+    let candidates = candidates(&mut file).unwrap_or_default();
+    // 大きさは SOF までのヘッダーだけで調べ、本体を読むのは選んだ 1 つだけにする
+    let mut previews: Vec<((u64, u32), (u32, u32))> = candidates
+        .into_iter()
+        .filter_map(|candidate| Some((candidate, jpeg_dimensions(&mut file, candidate)?)))
+        .collect();
+    previews.sort_by_key(|(_, (width, height))| (*width).max(*height));
+
+    let chosen = match previews
+        .iter()
+        .position(|(_, (width, height))| (*width).max(*height) >= max_size)
+    {
+        Some(index) => previews.swap_remove(index),
+        None if !formats::is_decodable(path) => previews.pop()?,
+        None => return None,
+    };
+    let ((offset, length), _) = chosen;
+    let bytes = read_at(&mut file, offset, length)?;
+    let mut decoder = JpegDecoder::new(Cursor::new(bytes)).ok()?;
+    // 大きなプレビューは DCT の段階で縮めて展開する
+    let side = max_size.min(u16::MAX as u32) as u16;
+    decoder.scale(side, side).ok()?;
+    DynamicImage::from_decoder(decoder).ok()
+}
+
+// 埋め込み JPEG の候補（ファイル先頭からの位置と長さ）
+fn candidates(file: &mut File) -> Option<Vec<(u64, u32)>> {
+    let head = read_at(file, 0, 16)?;
+    if head.starts_with(b"FUJIFILMCCD-RAW") {
+        let header = read_at(file, 84, 8)?;
+        let offset = u32::from_be_bytes(header[0..4].try_into().ok()?);
+        let length = u32::from_be_bytes(header[4..8].try_into().ok()?);
+        return Some(vec![(offset as u64, length)]);
+    }
+    if head.starts_with(&[0xFF, 0xD8]) {
+        let base = exif_tiff_offset(file, 0)?;
+        return Some(Tiff::open(file, base)?.previews(file));
+    }
+    Some(Tiff::open(file, 0)?.previews(file))
+}
+
+// 埋め込み JPEG のマーカーを SOF までたどって幅と高さを読む。SOF が候補の範囲外なら None
+fn jpeg_dimensions(file: &mut File, (start, length): (u64, u32)) -> Option<(u32, u32)> {
+    let end = start + length as u64;
+    if read_at(file, start, 2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut offset = start + 2;
+    while offset + 4 <= end {
+        let marker = read_at(file, offset, 4)?;
+        if marker[0] != 0xFF {
+            return None;
+        }
+        let segment = u16::from_be_bytes([marker[2], marker[3]]) as u64;
+        match marker[1] {
+            // 詰め物の 0xFF
+            0xFF => {
+                offset += 1;
+                continue;
+            }
+            // DHT・JPG・DAC を除く SOF0〜SOF15
+            0xC0..=0xCF if !matches!(marker[1], 0xC4 | 0xC8 | 0xCC) => {
+                let frame = read_at(file, offset + 4, 5)?;
+                let height = u16::from_be_bytes([frame[1], frame[2]]) as u32;
+                let width = u16::from_be_bytes([frame[3], frame[4]]) as u32;
+                return (width > 0 && height > 0).then_some((width, height));
+            }
+            0xDA | 0xD9 => return None,
+            _ => offset += 2 + segment,
+        }
+    }
+    None
+}
+
+// start から始まる JPEG の APP1（Exif）に入っている TIFF 構造の位置
+pub fn exif_tiff_offset(file: &mut File, start: u64) -> Option<u64> {
+    let mut offset = start + 2;
+    loop {
+        let marker = read_at(file, offset, 4)?;
+        if marker[0] != 0xFF || marker[1] == 0xDA || marker[1] == 0xD9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([marker[2], marker[3]]) as u64;
+        if marker[1] == 0xE1 && read_at(file, offset + 4, 6)? == b"Exif\0\0" {
+            return Some(offset + 10);
+        }
+        offset += 2 + length;
+    }
+}
+
+pub fn read_at(file: &mut File, offset: u64, length: u32) -> Option<Vec<u8>> {
+    if length == 0 || length > MAX_PREVIEW_BYTES {
+        return None;
+    }
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut bytes = vec![0; length as usize];
+    file.read_exact(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+pub struct Entry {
+    pub tag: u16,
+    pub kind: u16,
+    pub count: u32,
+    pub value: u32,
+}
+
+// IFD のオフセットは base（EXIF なら APP1 内の TIFF ヘッダー）からの相対
+pub struct Tiff {
+    base: u64,
+    little_endian: bool,
+    first_ifd: u32,
+}
+
+impl Tiff {
+    pub fn open(file: &mut File, base: u64) -> Option<Self> {
+        let header = read_at(file, base, 8)?;
+        let little_endian = match &header[0..2] {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let mut tiff = Self {
+            base,
+            little_endian,
+            first_ifd: 0,
+        };
+        // 42 が TIFF・DNG。ORF は 0x4F52・0x5352、RW2 は 0x55
+        if !matches!(tiff.u16(&header[2..4]), 42 | 0x4F52 | 0x5352 | 0x55) {
+            return None;
+        }
+        tiff.first_ifd = tiff.u32(&header[4..8]);
+        Some(tiff)
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    // IFD0 の項目
+    pub fn first_entries(&self, file: &mut File) -> Option<Vec<Entry>> {
+        self.entries(file, self.first_ifd)
+    }
+
+    // offset（Exif IFD など、項目が指す IFD）の項目
+    pub fn entries(&self, file: &mut File, offset: u32) -> Option<Vec<Entry>> {
+        (offset != 0)
+            .then(|| self.ifd(file, offset))?
+            .map(|(entries, _)| entries)
+    }
+
+    // RATIONAL の値。分子・分母は値の欄が指す位置にある
+    pub fn rational(&self, file: &mut File, entry: &Entry) -> Option<f64> {
+        let bytes = read_at(file, self.base + entry.value as u64, 8)?;
+        let denominator = self.u32(&bytes[4..8]);
+        (denominator != 0).then(|| self.u32(&bytes[0..4]) as f64 / denominator as f64)
+    }
+
+    // ASCII の値。4バイト以下なら値の欄に直接入っている。末尾の NUL と空白は除く
+    pub fn ascii(&self, file: &mut File, entry: &Entry) -> Option<String> {
+        let bytes = match entry.count {
+            0 => return None,
+            1..=4 => {
+                let raw = match self.little_endian {
+                    true => entry.value.to_le_bytes(),
+                    false => entry.value.to_be_bytes(),
+                };
+                raw[..entry.count as usize].to_vec()
+            }
+            count => read_at(file, self.base + entry.value as u64, count)?,
+        };
+        let text = String::from_utf8_lossy(&bytes)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        (!text.is_empty()).then_some(text)
+    }
+
+    // IFD の項目と次の IFD の位置
+    fn ifd(&self, file: &mut File, offset: u32) -> Option<(Vec<Entry>, u32)> {
+        let start = self.base + offset as u64;
+        let count = self.u16(&read_at(file, start, 2)?) as u32;
+        let bytes = read_at(file, start + 2, count * 12 + 4)?;
+        let entries = bytes
+            .chunks_exact(12)
+            .map(|raw| {
+                let kind = self.u16(&raw[2..4]);
+                let count = self.u32(&raw[4..8]);
+                // 1個の SHORT は値の欄の先頭2バイトに入る
+                let value = if kind == 3 && count == 1 {
+                    self.u16(&raw[8..10]) as u32
+                } else {
+                    self.u32(&raw[8..12])
+                };
+                Entry {
+                    tag: self.u16(&raw[0..2]),
+                    kind,
+                    count,
+                    value,
+                }
+            })
+            .collect();
+        Some((entries, self.u32(&bytes[bytes.len() - 4..])))
+    }
+
+    // SHORT・LONG の配列の値。1個なら値の欄そのもの
+    pub fn values(&self, file: &mut File, entry: &Entry) -> Option<Vec<u32>> {
+        let size = match entry.kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return None,
+        };
+        if entry.count == 1 {
+            return Some(vec![entry.value]);
+        }
+        let bytes = read_at(
+            file,
+            self.base + entry.value as u64,
+            entry.count.checked_mul(size)?,
+        )?;
+        Some(
+            bytes
+                .chunks_exact(size as usize)
+                .map(|raw| match size {
+                    2 => self.u16(raw) as u32,
+                    _ => self.u32(raw),
+                })
+                .collect(),
+        )
+    }
+
+    // IFD0 からの鎖と SubIFD をたどった、読める IFD。読めない IFD・SubIFD の一覧は飛ばす
+    pub fn ifds(&self, file: &mut File) -> Vec<Vec<Entry>> {
+        self.walk(file, false).unwrap_or_default()
+    }
+
+    // 書き込み途中の判定用。途中で読めない IFD があれば None
+    pub fn ifds_strict(&self, file: &mut File) -> Option<Vec<Vec<Entry>>> {
+        self.walk(file, true)
+    }
+
+    fn walk(&self, file: &mut File, strict: bool) -> Option<Vec<Vec<Entry>>> {
+        let mut pending = vec![self.first_ifd];
+        let mut ifds = Vec::new();
+        let mut visited = Vec::new();
+        while let Some(offset) = pending.pop() {
+            if offset == 0 || visited.contains(&offset) || visited.len() >= MAX_IFDS {
+                continue;
+            }
+            visited.push(offset);
+            let Some((entries, next)) = self.ifd(file, offset) else {
+                if strict {
+                    return None;
+                }
+                continue;
+            };
+            pending.push(next);
+            if let Some(sub_ifds) = entries.iter().find(|entry| entry.tag == 0x014A) {
+                match self.values(file, sub_ifds) {
+                    Some(offsets) => pending.extend(offsets.into_iter().take(MAX_IFDS)),
+                    None if strict => return None,
+                    None => {}
+                }
+            }
+            ifds.push(entries);
+        }
+        Some(ifds)
+    }
+
+    // 埋め込み JPEG の候補を集める
+    fn previews(&self, file: &mut File) -> Vec<(u64, u32)> {
+        let mut previews = Vec::new();
+        for entries in self.ifds(file) {
+            let find = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+            // JPEGInterchangeFormat / JPEGInterchangeFormatLength
+            if let (Some(start), Some(length)) = (find(0x0201), find(0x0202)) {
+                previews.push((self.base + start.value as u64, length.value));
+            }
+            // Panasonic の JpgFromRaw
+            if let Some(jpeg) = find(0x002E).filter(|entry| entry.kind == 7) {
+                previews.push((self.base + jpeg.value as u64, jpeg.count));
+            }
+            // JPEG 圧縮で1本のストリップにまとまった画像（CR2 の IFD0 など）
+            let compression = find(0x0103).map(|entry| entry.value);
+            if let (Some(6 | 7), Some(start), Some(length)) =
+                (compression, find(0x0111), find(0x0117))
+            {
+                if start.count == 1 && length.count == 1 {
+                    previews.push((self.base + start.value as u64, length.value));
+                }
+            }
+        }
+        // 可逆 JPEG の RAW 本体など、通常の JPEG として読めない候補は read で寸法を取れず外れる
+        previews.dedup();
+        previews
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{Rgb, RgbImage};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_pixel(width, height, Rgb([200, 100, 50]));
+        let mut bytes = Vec::new();
+        JpegEncoder::new(&mut bytes).encode_image(&image).unwrap();
+        bytes
+    }
+
+    // IFD0 が JPEGInterchangeFormat で埋め込み JPEG を指すだけの最小の TIFF 構造
+    fn tiff_with_preview(preview: &[u8]) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        let data_start = 8 + 2 + 2 * 12 + 4;
+        for (tag, value) in [(0x0201u16, data_start), (0x0202, preview.len() as u32)] {
+            tiff.extend(tag.to_le_bytes());
+            tiff.extend(4u16.to_le_bytes());
+            tiff.extend(1u32.to_le_bytes());
+            tiff.extend(value.to_le_bytes());
+        }
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(preview);
+        tiff
+    }
+
+    #[test]
+    fn reads_preview_dimensions_from_the_jpeg_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preview.jpg");
+        let bytes = jpeg(80, 30);
+        std::fs::write(&path, &bytes).unwrap();
+        let mut file = File::open(&path).unwrap();
+
+        assert_eq!(
+            jpeg_dimensions(&mut file, (0, bytes.len() as u32)),
+            Some((80, 30))
+        );
+        // SOF が候補の範囲に入っていない
+        assert_eq!(jpeg_dimensions(&mut file, (0, 4)), None);
+        assert_eq!(
+            jpeg_dimensions(&mut file, (1, bytes.len() as u32 - 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn skips_unreadable_ifds_when_collecting_previews() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.nef");
+        let mut bytes = tiff_with_preview(&jpeg(64, 48));
+        // 次の IFD がファイルの外を指している
+        bytes[34..38].copy_from_slice(&0x7FFF_0000u32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let tiff = Tiff::open(&mut file, 0).unwrap();
+
+        assert_eq!(tiff.ifds(&mut file).len(), 1);
+        assert!(tiff.ifds_strict(&mut file).is_none());
+        assert_eq!(read(&path, 64).unwrap().width(), 64);
+    }
+
+    #[test]
+    fn uses_the_embedded_preview_of_raw_and_jpeg_files() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // 展開できない RAW は小さくても埋め込みを使う
+        let raw = dir.path().join("shot.nef");
+        std::fs::write(&raw, tiff_with_preview(&jpeg(160, 120))).unwrap();
+        let thumbnail = read(&raw, 256).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (160, 120));
+        // 大きなプレビューは要求に近い大きさまで縮めて展開される
+        std::fs::write(&raw, tiff_with_preview(&jpeg(1600, 1200))).unwrap();
+        let thumbnail = read(&raw, 200).unwrap();
+        assert!(thumbnail.width() >= 200 && thumbnail.width() <= 400);
+
+        // JPEG は APP1 の EXIF に入れた IFD1 相当の埋め込みを読む
+        let exif = [b"Exif\0\0".as_slice(), &tiff_with_preview(&jpeg(320, 240))].concat();
+        let mut file = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        file.extend((exif.len() as u16 + 2).to_be_bytes());
+        file.extend(&exif);
+        file.extend(&jpeg(4000, 3000)[2..]);
+        let photo = dir.path().join("photo.jpg");
+        std::fs::write(&photo, &file).unwrap();
+        assert_eq!(read(&photo, 256).unwrap().width(), 320);
+        // 埋め込みが小さすぎる JPEG は本体の展開に任せる
+        assert!(read(&photo, 1024).is_none());
+
+        let plain = dir.path().join("plain.jpg");
+        std::fs::write(&plain, jpeg(64, 64)).unwrap();
+        assert!(read(&plain, 256).is_none());
+    }
+}
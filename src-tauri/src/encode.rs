@@ -0,0 +1,452 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use exr::meta::attribute::Preview;
+use exr::prelude::{
+    Encoding, Image, ImageAttributes, IntegerBounds, Layer, LayerAttributes, LineOrder,
+    SpecificChannels, Vec2, WritableImage,
+};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::exr_color::{ExrColorSpace, ExrPixels};
+use crate::filters::SharpenParams;
+use crate::merge::Rgb16Image;
+use crate::output_color::{self, OutputColorSpace};
+use crate::progress::{ProgressReporter, CANCELLED};
+
+// 進捗の通知と中止の確認を行う行数
+const PNG_BAND_ROWS: u32 = 64;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EncodeParams {
+    // 書き出す直前にかけるアンシャープマスク。追加の出力（deliverables）は縮小したあとにかける
+    pub sharpen: Option<SharpenParams>,
+}
+
+pub fn write_png(
+    image: &Rgb16Image,
+    path: &Path,
+    progress: &ProgressReporter,
+) -> Result<(), String> {
+    write_png_as(image, path, OutputColorSpace::Srgb, progress)
+}
+
+// 数十行ずつ書き出し、帯ごとに進捗を通知して中止要求を確認する。
+// 画素は color_space に変換済みの値で渡す（sRGB 以外は ICC プロファイルを埋め込む）
+pub fn write_png_as(
+    image: &Rgb16Image,
+    path: &Path,
+    color_space: OutputColorSpace,
+    progress: &ProgressReporter,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png_encoder(
+        BufWriter::new(file),
+        image.width(),
+        image.height(),
+        color_space,
+    )?;
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    let mut stream = writer.stream_writer().map_err(|e| e.to_string())?;
+
+    let row_len = image.width() as usize * 3;
+    let mut bytes = Vec::with_capacity(row_len * 2 * PNG_BAND_ROWS as usize);
+    for (band, rows) in image
+        .as_raw()
+        .chunks(row_len * PNG_BAND_ROWS as usize)
+        .enumerate()
+    {
+        progress.check_cancelled()?;
+        bytes.clear();
+        // PNG の 16bit 値はビッグエンディアン
+        bytes.extend(rows.iter().flat_map(|value| value.to_be_bytes()));
+        stream.write_all(&bytes).map_err(|e| e.to_string())?;
+        let written = (band as u32 + 1) * PNG_BAND_ROWS;
+        progress.report("encodePng", written as f64 / image.height().max(1) as f64);
+    }
+    stream.finish().map_err(|e| e.to_string())
+}
+
+pub fn write_png8(
+    image: &image::RgbImage,
+    path: &Path,
+    color_space: OutputColorSpace,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png_encoder(
+        BufWriter::new(file),
+        image.width(),
+        image.height(),
+        color_space,
+    )?;
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(image.as_raw())
+        .map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())
+}
+
+fn png_encoder<'a, W: Write>(
+    writer: W,
+    width: u32,
+    height: u32,
+    color_space: OutputColorSpace,
+) -> Result<png::Encoder<'a, W>, String> {
+    let mut encoder = match output_color::icc_profile(color_space) {
+        Some(profile) => {
+            let mut info = png::Info::with_size(width, height);
+            info.icc_profile = Some(profile.into());
+            png::Encoder::with_info(writer, info).map_err(|e| e.to_string())?
+        }
+        None => {
+            let mut encoder = png::Encoder::new(writer, width, height);
+            tag_srgb(&mut encoder);
+            encoder
+        }
+    };
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+    Ok(encoder)
+}
+
+// sRGB の出力には sRGB チャンクを書く。sRGB を解釈しないビューア向けに同じ意味の gAMA・cHRM も付ける
+fn tag_srgb<W: Write>(encoder: &mut png::Encoder<'_, W>) {
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455));
+    encoder.set_source_chromaticities(png::SourceChromaticities::new(
+        (0.3127, 0.3290),
+        (0.64, 0.33),
+        (0.30, 0.60),
+        (0.15, 0.06),
+    ));
+}
+
+pub fn write_exr(
+    image: &Rgb16Image,
+    path: &Path,
+    deterministic: bool,
+    progress: &ProgressReporter,
+) -> Result<(), String> {
+    write_exr_with_preview(image, path, deterministic, None, None, progress)
+}
+
+// preview はヘッダーに埋め込む縮小画像。EXR を表示できないビューアーやファイルブラウザーが使う。
+// color_space を指定すると線形化してその原色に変換し、chromaticities 属性を書く
+pub fn write_exr_with_preview(
+    image: &Rgb16Image,
+    path: &Path,
+    deterministic: bool,
+    preview: Option<Preview>,
+    color_space: Option<ExrColorSpace>,
+    progress: &ProgressReporter,
+) -> Result<(), String> {
+    let pixels = ExrPixels::new(color_space);
+    let channels =
+        SpecificChannels::rgb(|Vec2(x, y)| pixels.convert(image.get_pixel(x as u32, y as u32).0));
+    let mut exr_image =
+        Image::from_channels((image.width() as usize, image.height() as usize), channels);
+    exr_image.layer_data.attributes.preview = preview;
+    exr_image.attributes.chromaticities = pixels.chromaticities();
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let writer = CancellableWriter {
+        inner: file,
+        progress,
+    };
+    let on_progress = |fraction: f64| progress.report("encodeExr", fraction);
+
+    let result = if deterministic {
+        // 並列圧縮ではブロックの書き込み順が実行ごとに変わるため、行順を固定して逐次で書き出す
+        exr_image.layer_data.encoding.line_order = LineOrder::Increasing;
+        exr_image
+            .write()
+            .non_parallel()
+            .on_progress(on_progress)
+            .to_buffered(writer)
+    } else {
+        exr_image
+            .write()
+            .on_progress(on_progress)
+            .to_buffered(writer)
+    };
+    match result {
+        Err(_) if progress.is_cancelled() => Err(CANCELLED.to_string()),
+        result => result.map_err(|e| e.to_string()),
+    }
+}
+
+// 名前付きのレイヤーを持つ EXR を書き出す。全レイヤーが同じ大きさであること
+pub fn write_layered_exr(
+    layers: &[(String, &Rgb16Image)],
+    path: &Path,
+    deterministic: bool,
+    color_space: Option<ExrColorSpace>,
+    progress: &ProgressReporter,
+) -> Result<(), String> {
+    let Some((_, first)) = layers.first() else {
+        return Err("書き出すレイヤーがありません".to_string());
+    };
+    let size = (first.width() as usize, first.height() as usize);
+    if layers
+        .iter()
+        .any(|(_, image)| image.dimensions() != first.dimensions())
+    {
+        return Err("レイヤーの画像サイズが揃っていません".to_string());
+    }
+    let line_order = if deterministic {
+        LineOrder::Increasing
+    } else {
+        LineOrder::Unspecified
+    };
+    let pixels = ExrPixels::new(color_space);
+    let exr_layers: Vec<_> = layers
+        .iter()
+        .map(|(name, image)| {
+            let mut encoding = Encoding::FAST_LOSSLESS;
+            encoding.line_order = line_order;
+            Layer::new(
+                size,
+                LayerAttributes::named(name.as_str()),
+                encoding,
+                SpecificChannels::rgb(|Vec2(x, y)| {
+                    pixels.convert(image.get_pixel(x as u32, y as u32).0)
+                }),
+            )
+        })
+        .collect();
+    let mut attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+    attributes.chromaticities = pixels.chromaticities();
+    let exr_image = Image::from_layers(attributes, exr_layers);
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let writer = CancellableWriter {
+        inner: file,
+        progress,
+    };
+    let on_progress = |fraction: f64| progress.report("encodeExr", fraction);
+
+    let result = if deterministic {
+        exr_image
+            .write()
+            .non_parallel()
+            .on_progress(on_progress)
+            .to_buffered(writer)
+    } else {
+        exr_image
+            .write()
+            .on_progress(on_progress)
+            .to_buffered(writer)
+    };
+    match result {
+        Err(_) if progress.is_cancelled() => Err(CANCELLED.to_string()),
+        result => result.map_err(|e| e.to_string()),
+    }
+}
+
+// EXR の書き出しは途中で止める手段がないため、中止要求があれば書き込みを失敗させて打ち切る
+struct CancellableWriter<'a, W> {
+    inner: W,
+    progress: &'a ProgressReporter,
+}
+
+impl<W: Write> Write for CancellableWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.progress.is_cancelled() {
+            return Err(io::Error::other(CANCELLED));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for CancellableWriter<'_, W> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn gradient() -> Rgb16Image {
+        Rgb16Image::from_fn(40, 150, |x, y| {
+            image::Rgb([x as u16 * 1000, y as u16 * 400, 123])
+        })
+    }
+
+    #[test]
+    fn png_reports_progress_per_band_and_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.png");
+        let fractions = Arc::new(Mutex::new(Vec::new()));
+        let recorded = fractions.clone();
+        let progress =
+            ProgressReporter::new(None, move |p| recorded.lock().unwrap().push(p.fraction));
+
+        write_png(&gradient(), &path, &progress).unwrap();
+
+        assert_eq!(
+            *fractions.lock().unwrap(),
+            vec![64.0 / 150.0, 128.0 / 150.0, 1.0]
+        );
+        assert_eq!(image::open(&path).unwrap().to_rgb16(), gradient());
+    }
+
+    #[test]
+    fn png_outputs_are_tagged_as_srgb() {
+        let dir = tempfile::tempdir().unwrap();
+        let path16 = dir.path().join("out.png");
+        let path8 = dir.path().join("out8.png");
+        write_png(&gradient(), &path16, &ProgressReporter::default()).unwrap();
+        let eight_bit = image::DynamicImage::ImageRgb16(gradient()).to_rgb8();
+        write_png8(&eight_bit, &path8, OutputColorSpace::Srgb).unwrap();
+
+        for path in [&path16, &path8] {
+            let reader = png::Decoder::new(File::open(path).unwrap())
+                .read_info()
+                .unwrap();
+            let info = reader.info();
+            assert_eq!(info.srgb, Some(png::SrgbRenderingIntent::Perceptual));
+            assert_eq!(
+                info.source_gamma,
+                Some(png::ScaledFloat::from_scaled(45455))
+            );
+            assert!(info.source_chromaticities.is_some());
+        }
+        assert_eq!(image::open(&path8).unwrap().to_rgb8(), eight_bit);
+    }
+
+    #[test]
+    fn wide_gamut_pngs_embed_an_icc_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p3.png");
+        write_png_as(
+            &gradient(),
+            &path,
+            OutputColorSpace::DisplayP3,
+            &ProgressReporter::default(),
+        )
+        .unwrap();
+
+        let reader = png::Decoder::new(File::open(&path).unwrap())
+            .read_info()
+            .unwrap();
+        let info = reader.info();
+        assert!(info.srgb.is_none());
+        assert_eq!(
+            info.icc_profile.as_deref(),
+            output_color::icc_profile(OutputColorSpace::DisplayP3).as_deref()
+        );
+
+        // 合成結果と同じく変換してから書いた広色域の PNG は、読み込むと sRGB の値に戻る
+        for color_space in [OutputColorSpace::DisplayP3, OutputColorSpace::Rec2020] {
+            let converted = output_color::convert(&gradient(), color_space).unwrap();
+            write_png_as(&converted, &path, color_space, &ProgressReporter::default()).unwrap();
+            let loaded = crate::decode::decode_rgb16(&path).unwrap();
+            for (original, loaded) in gradient().pixels().zip(loaded.pixels()) {
+                for channel in 0..3 {
+                    assert!(
+                        (original[channel] as i32 - loaded[channel] as i32).abs() < 64,
+                        "{:?}: {:?} {:?}",
+                        color_space,
+                        original,
+                        loaded
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layered_exr_keeps_named_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layers.exr");
+        let green = Rgb16Image::from_pixel(40, 150, image::Rgb([0, u16::MAX, 0]));
+        let layers = [
+            ("merged".to_string(), &gradient()),
+            ("frame1".to_string(), &green),
+        ];
+        let mismatched = [
+            ("merged".to_string(), &gradient()),
+            ("frame1".to_string(), &Rgb16Image::new(4, 4)),
+        ];
+
+        write_layered_exr(&layers, &path, true, None, &ProgressReporter::default()).unwrap();
+
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        let names: Vec<String> = image
+            .layer_data
+            .iter()
+            .map(|layer| layer.attributes.layer_name.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["merged", "frame1"]);
+        let green_channel = image.layer_data[1]
+            .channel_data
+            .list
+            .iter()
+            .find(|channel| channel.name.to_string() == "G")
+            .unwrap();
+        assert!(green_channel.sample_data.values_as_f32().all(|v| v == 1.0));
+        assert!(write_layered_exr(
+            &mismatched,
+            &dir.path().join("bad.exr"),
+            true,
+            None,
+            &ProgressReporter::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn exr_color_space_is_converted_and_tagged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aces.exr");
+        let white = Rgb16Image::from_pixel(8, 8, image::Rgb([u16::MAX; 3]));
+
+        write_exr_with_preview(
+            &white,
+            &path,
+            true,
+            None,
+            Some(ExrColorSpace::AcesCg),
+            &ProgressReporter::default(),
+        )
+        .unwrap();
+
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        let chromaticities = image.attributes.chromaticities.unwrap();
+        assert_eq!(chromaticities, ExrColorSpace::AcesCg.chromaticities());
+        let red = image.layer_data[0]
+            .channel_data
+            .list
+            .iter()
+            .find(|channel| channel.name.to_string() == "R")
+            .unwrap();
+        assert!(red
+            .sample_data
+            .values_as_f32()
+            .all(|v| (v - 1.0).abs() < 0.001));
+    }
+
+    #[test]
+    fn cancelled_encode_stops_with_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let progress = ProgressReporter::new(None, |_| {});
+        progress.cancel();
+
+        let png = write_png(&gradient(), &dir.path().join("out.png"), &progress);
+        let exr = write_exr(&gradient(), &dir.path().join("out.exr"), true, &progress);
+
+        assert_eq!(png.unwrap_err(), CANCELLED);
+        assert_eq!(exr.unwrap_err(), CANCELLED);
+    }
+}
@@ -0,0 +1,496 @@
+use std::fs::File;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::deflicker;
+use crate::embedded_thumbnail::{self, read_at, Entry, Tiff};
+use crate::merge::Rgb16Image;
+use crate::paths;
+
+const TAG_MAKE: u16 = 0x010f;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_EXPOSURE_TIME: u16 = 0x829a;
+const TAG_F_NUMBER: u16 = 0x829d;
+const TAG_ISO: u16 = 0x8827;
+const TAG_RECOMMENDED_EXPOSURE_INDEX: u16 = 0x8832;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_FOCAL_LENGTH: u16 = 0x920a;
+const TAG_LENS_MAKE: u16 = 0xa433;
+const TAG_LENS_MODEL: u16 = 0xa434;
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+// 同じ設定で撮ったはずのフレームのずれ（絞りのちらつきなど）だけを補正する。これを超える差は設定の違いとみなす
+const MAX_COMPENSATION_EV: f64 = 1.0;
+
+// EXIF に記録された撮影時の露出。記録されていない項目は None
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExposureInfo {
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<f64>,
+}
+
+// 現像ソフトがレンズプロファイルを選ぶのに使うカメラとレンズの記録
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraInfo {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens_make: Option<String>,
+    pub lens_model: Option<String>,
+    pub focal_length: Option<f64>,
+    pub f_number: Option<f64>,
+    // EXIF の書式（`2024:05:01 12:34:56`）のまま
+    pub date_time_original: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ExposureCompensation {
+    pub path: String,
+    // 露出時間・絞り・ISO から求めた写る明るさ（log2。ISO 100・f/1・1 秒を 0 とする）
+    pub exif_ev: f64,
+    // 線形の値にかけた補正。正なら明るくした
+    pub correction_ev: f64,
+}
+
+impl ExposureInfo {
+    // 露出時間がなければ求められない。絞りと ISO は記録がなければ変わらなかったものとみなす
+    pub fn ev(&self) -> Option<f64> {
+        let time = self.exposure_time.filter(|time| *time > 0.0)?;
+        let f_number = self.f_number.filter(|f| *f > 0.0).unwrap_or(1.0);
+        let iso = self.iso.filter(|iso| *iso > 0.0).unwrap_or(100.0);
+        Some((time * iso / 100.0 / (f_number * f_number)).log2())
+    }
+}
+
+// JPEG の APP1、PNG の eXIf、TIFF・DNG と TIFF 系の RAW（CR2・NEF・ARW・ORF・RW2 など）のファイル自体、
+// RAF の埋め込み JPEG から露出を読む。
+// 読めなければ None
+pub fn read_exposure(path: &Path) -> Option<ExposureInfo> {
+    read_tiff(path, parse_exposure)
+}
+
+pub fn read_camera(path: &Path) -> Option<CameraInfo> {
+    read_tiff(path, parse_camera)
+}
+
+// 拡張子ではなく先頭のバイト列で形式を見分け、サムネイルと同じ TIFF の読み取りで IFD をたどる
+fn read_tiff<T>(path: &Path, parse: impl FnOnce(&Tiff, &mut File) -> Option<T>) -> Option<T> {
+    let mut file = File::open(paths::extended(path)).ok()?;
+    let head = read_at(&mut file, 0, 8)?;
+    let base = if head.starts_with(&[0xff, 0xd8]) {
+        embedded_thumbnail::exif_tiff_offset(&mut file, 0)?
+    } else if head.starts_with(b"\x89PNG") {
+        png_exif_offset(&mut file)?
+    } else if head.starts_with(b"FUJIFILM") {
+        // RAF は TIFF ではないが、84 バイト目が指す埋め込み JPEG の APP1 に EXIF が入っている
+        let jpeg = read_at(&mut file, 84, 4)?;
+        let jpeg = u32::from_be_bytes(jpeg.try_into().ok()?) as u64;
+        embedded_thumbnail::exif_tiff_offset(&mut file, jpeg)?
+    } else {
+        0
+    };
+    let tiff = Tiff::open(&mut file, base)?;
+    parse(&tiff, &mut file)
+}
+
+// levels ごと（noiseStack・longExposure ではすべてのフレーム）に、EXIF の露出の中央値へ合わせる
+pub fn compensate(
+    paths: &[String],
+    images: &mut [Rgb16Image],
+    levels: &[Vec<usize>],
+) -> Result<Vec<ExposureCompensation>, String> {
+    let evs = exif_evs(paths)?;
+    let mut corrections = vec![0.0; paths.len()];
+    for level in levels {
+        let mut sorted: Vec<f64> = level.iter().map(|&index| evs[index]).collect();
+        sorted.sort_by(f64::total_cmp);
+        let reference = sorted[sorted.len() / 2];
+        for &index in level {
+            corrections[index] = reference - evs[index];
+        }
+    }
+    apply_corrections(paths, images, &evs, &corrections)
+}
+
+// タイムラプスの bracket 用。暗い順に k 番目のフレームを、先頭のブラケットの k 番目の EXIF の露出（reference）へ合わせる。
+// reference が空ならこのブラケットが先頭なので補正しない
+pub fn compensate_to_reference(
+    paths: &[String],
+    images: &mut [Rgb16Image],
+    reference: &[f64],
+) -> Result<Vec<ExposureCompensation>, String> {
+    let evs = exif_evs(paths)?;
+    let mut order: Vec<usize> = (0..paths.len()).collect();
+    order.sort_by(|&a, &b| evs[a].total_cmp(&evs[b]));
+    let mut corrections = vec![0.0; paths.len()];
+    if !reference.is_empty() {
+        if reference.len() != paths.len() {
+            return Err(format!(
+                "先頭のブラケットと枚数が違うため EXIF の露出をそろえられません（{}枚）",
+                paths.len()
+            ));
+        }
+        for (&index, &target) in order.iter().zip(reference) {
+            corrections[index] = target - evs[index];
+        }
+    }
+    apply_corrections(paths, images, &evs, &corrections)
+}
+
+fn exif_evs(paths: &[String]) -> Result<Vec<f64>, String> {
+    paths
+        .iter()
+        .map(|path| {
+            read_exposure(Path::new(path))
+                .and_then(|info| info.ev())
+                .ok_or_else(|| format!("EXIF に露出時間が記録されていません: {}", path))
+        })
+        .collect()
+}
+
+fn apply_corrections(
+    paths: &[String],
+    images: &mut [Rgb16Image],
+    evs: &[f64],
+    corrections: &[f64],
+) -> Result<Vec<ExposureCompensation>, String> {
+    if let Some(index) = corrections
+        .iter()
+        .position(|correction| correction.abs() > MAX_COMPENSATION_EV)
+    {
+        return Err(format!(
+            "EXIF の露出が {:.2}EV 違うため補正できません: {}",
+            corrections[index], paths[index]
+        ));
+    }
+    for (image, &correction) in images.iter_mut().zip(corrections) {
+        if correction != 0.0 {
+            *image = deflicker::apply(image, correction);
+        }
+    }
+    Ok(paths
+        .iter()
+        .zip(evs.iter().zip(corrections))
+        .map(|(path, (&exif_ev, &correction_ev))| ExposureCompensation {
+            path: path.clone(),
+            exif_ev,
+            correction_ev,
+        })
+        .collect())
+}
+
+// PNG の eXIf チャンクの中身の位置。画像データより後ろの eXIf は見ない
+fn png_exif_offset(file: &mut File) -> Option<u64> {
+    let mut offset = 8u64;
+    loop {
+        let header = read_at(file, offset, 8)?;
+        let length = u32::from_be_bytes(header[0..4].try_into().ok()?) as u64;
+        match &header[4..8] {
+            b"eXIf" => return Some(offset + 8),
+            b"IDAT" | b"IEND" => return None,
+            _ => offset += 12 + length,
+        }
+    }
+}
+
+// IFD0 と、IFD0 が指す Exif IFD の項目。TIFF では IFD0 に直接書くカメラもあるため両方を見る
+fn exif_entries(tiff: &Tiff, file: &mut File) -> Option<Vec<Vec<Entry>>> {
+    let ifd0 = tiff.first_entries(file)?;
+    let exif_ifd = ifd0
+        .iter()
+        .find(|entry| entry.tag == TAG_EXIF_IFD && entry.kind == TYPE_LONG)
+        .and_then(|entry| tiff.entries(file, entry.value));
+    Some(std::iter::once(ifd0).chain(exif_ifd).collect())
+}
+
+fn parse_exposure(tiff: &Tiff, file: &mut File) -> Option<ExposureInfo> {
+    let mut info = ExposureInfo {
+        exposure_time: None,
+        f_number: None,
+        iso: None,
+    };
+    let mut recommended_exposure_index = None;
+    for entry in exif_entries(tiff, file)?.iter().flatten() {
+        match (entry.tag, entry.kind) {
+            (TAG_EXPOSURE_TIME, TYPE_RATIONAL) => info.exposure_time = tiff.rational(file, entry),
+            (TAG_F_NUMBER, TYPE_RATIONAL) => info.f_number = tiff.rational(file, entry),
+            // SHORT が多いが LONG で書くカメラもあり、複数の値なら先頭を使う
+            (TAG_ISO, TYPE_SHORT | TYPE_LONG) => {
+                info.iso = tiff
+                    .values(file, entry)
+                    .and_then(|values| values.first().map(|&iso| iso as f64));
+            }
+            (TAG_RECOMMENDED_EXPOSURE_INDEX, TYPE_LONG) => {
+                recommended_exposure_index = Some(entry.value as f64);
+            }
+            _ => {}
+        }
+    }
+    // ISO 65535 以上は ISOSpeedRatings が 65535 に張り付くため、推奨露光指数を使う
+    if info.iso.is_none_or(|iso| iso >= u16::MAX as f64) {
+        info.iso = recommended_exposure_index.or(info.iso);
+    }
+    Some(info)
+}
+
+// カメラは IFD0、レンズと撮影条件は Exif IFD にある
+fn parse_camera(tiff: &Tiff, file: &mut File) -> Option<CameraInfo> {
+    let mut info = CameraInfo::default();
+    for entry in exif_entries(tiff, file)?.iter().flatten() {
+        match (entry.tag, entry.kind) {
+            (TAG_MAKE, TYPE_ASCII) => info.make = tiff.ascii(file, entry),
+            (TAG_MODEL, TYPE_ASCII) => info.model = tiff.ascii(file, entry),
+            (TAG_LENS_MAKE, TYPE_ASCII) => info.lens_make = tiff.ascii(file, entry),
+            (TAG_LENS_MODEL, TYPE_ASCII) => info.lens_model = tiff.ascii(file, entry),
+            (TAG_DATE_TIME_ORIGINAL, TYPE_ASCII) => {
+                info.date_time_original = tiff.ascii(file, entry)
+            }
+            (TAG_FOCAL_LENGTH, TYPE_RATIONAL) => info.focal_length = tiff.rational(file, entry),
+            (TAG_F_NUMBER, TYPE_RATIONAL) => info.f_number = tiff.rational(file, entry),
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+// リトルエンディアンの TIFF に、IFD0 から Exif IFD を指して露出時間・絞り・ISO を書く
+#[cfg(test)]
+pub(crate) fn exif_tiff(exposure_time: (u32, u32), f_number: (u32, u32), iso: u16) -> Vec<u8> {
+    exif_tiff_with_iso(exposure_time, f_number, TYPE_SHORT, iso as u32)
+}
+
+#[cfg(test)]
+fn exif_tiff_with_iso(
+    exposure_time: (u32, u32),
+    f_number: (u32, u32),
+    iso_kind: u16,
+    iso: u32,
+) -> Vec<u8> {
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend(8u32.to_le_bytes());
+    let entry = |tag: u16, kind: u16, value: u32| {
+        let mut bytes = tag.to_le_bytes().to_vec();
+        bytes.extend(kind.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(value.to_le_bytes());
+        bytes
+    };
+    // IFD0 は 8 バイト目、Exif IFD は 26 バイト目、分数の値は 68 バイト目から
+    tiff.extend(1u16.to_le_bytes());
+    tiff.extend(entry(TAG_EXIF_IFD, TYPE_LONG, 26));
+    tiff.extend(0u32.to_le_bytes());
+    tiff.extend(3u16.to_le_bytes());
+    tiff.extend(entry(TAG_EXPOSURE_TIME, TYPE_RATIONAL, 68));
+    tiff.extend(entry(TAG_F_NUMBER, TYPE_RATIONAL, 76));
+    // SHORT は値の欄の先頭2バイトに入る
+    tiff.extend(entry(TAG_ISO, iso_kind, iso));
+    tiff.extend(0u32.to_le_bytes());
+    for value in [exposure_time.0, exposure_time.1, f_number.0, f_number.1] {
+        tiff.extend(value.to_le_bytes());
+    }
+    tiff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{srgb_to_linear, u16_to_unit};
+    use image::Rgb;
+    use std::io::BufWriter;
+
+    fn save_png_with_exif(image: &Rgb16Image, path: &Path, exif: &[u8]) {
+        let file = std::fs::File::create(path).unwrap();
+        let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_chunk(png::chunk::ChunkType(*b"eXIf"), exif)
+            .unwrap();
+        let data: Vec<u8> = image
+            .as_raw()
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        writer.write_image_data(&data).unwrap();
+    }
+
+    #[test]
+    fn reads_exposure_from_jpeg_app1() {
+        let dir = tempfile::tempdir().unwrap();
+        let tiff = exif_tiff((1, 125), (56, 10), 400);
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xe1];
+        jpeg.extend((tiff.len() as u16 + 8).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(&tiff);
+        jpeg.extend([0xff, 0xda, 0, 2, 0xff, 0xd9]);
+        let path = dir.path().join("shot.jpg");
+        std::fs::write(&path, &jpeg).unwrap();
+
+        let info = read_exposure(&path).unwrap();
+
+        assert_eq!(info.exposure_time, Some(1.0 / 125.0));
+        assert_eq!(info.f_number, Some(5.6));
+        assert_eq!(info.iso, Some(400.0));
+        // 1/125 秒・ISO 400 は 4/125 秒・ISO 100 と同じ明るさに写る
+        let same = ExposureInfo {
+            exposure_time: Some(4.0 / 125.0),
+            iso: None,
+            ..info
+        };
+        assert!((info.ev().unwrap() - same.ev().unwrap()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reads_exposure_from_tiff_based_raw_files() {
+        let dir = tempfile::tempdir().unwrap();
+        // ISO を LONG で書くカメラ
+        let path = dir.path().join("shot.cr2");
+        std::fs::write(
+            &path,
+            exif_tiff_with_iso((1, 30), (40, 10), TYPE_LONG, 3200),
+        )
+        .unwrap();
+        assert_eq!(read_exposure(&path).unwrap().iso, Some(3200.0));
+
+        let path = dir.path().join("shot.nef");
+        std::fs::write(&path, exif_tiff((1, 30), (40, 10), 800)).unwrap();
+        let info = read_exposure(&path).unwrap();
+        assert_eq!(info.exposure_time, Some(1.0 / 30.0));
+        assert_eq!(info.iso, Some(800.0));
+        // RAF はヘッダーが指す埋め込み JPEG の EXIF を読む
+        let tiff = exif_tiff((1, 250), (56, 10), 200);
+        let mut raf = b"FUJIFILMCCD-RAW 0201FF383501".to_vec();
+        raf.resize(84, 0);
+        raf.extend(100u32.to_be_bytes());
+        raf.extend((14 + tiff.len() as u32).to_be_bytes());
+        raf.resize(100, 0);
+        raf.extend([0xFF, 0xD8, 0xFF, 0xE1]);
+        raf.extend((8 + tiff.len() as u16).to_be_bytes());
+        raf.extend(b"Exif\0\0");
+        raf.extend(&tiff);
+        let path = dir.path().join("shot.raf");
+        std::fs::write(&path, &raf).unwrap();
+        let info = read_exposure(&path).unwrap();
+        assert_eq!(info.exposure_time, Some(1.0 / 250.0));
+        assert_eq!(info.iso, Some(200.0));
+        assert!(read_camera(&path).is_some());
+        // 拡張子ではなく中身で見分ける
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"not an image").unwrap();
+        assert!(read_exposure(&path).is_none());
+    }
+
+    #[test]
+    fn reads_camera_and_lens_for_profile_lookup() {
+        // IFD0 に Make（直接入る4バイト）、Exif IFD にレンズ名と焦点距離を書く
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        let entry = |tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+            let mut bytes = tag.to_le_bytes().to_vec();
+            bytes.extend(kind.to_le_bytes());
+            bytes.extend(count.to_le_bytes());
+            bytes.extend(value);
+            bytes
+        };
+        // IFD0 は 8 バイト目、Exif IFD は 38 バイト目、値は 68 バイト目から
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(entry(TAG_MAKE, TYPE_ASCII, 4, *b"Xyz\0"));
+        tiff.extend(entry(TAG_EXIF_IFD, TYPE_LONG, 1, 38u32.to_le_bytes()));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(entry(
+            TAG_FOCAL_LENGTH,
+            TYPE_RATIONAL,
+            1,
+            68u32.to_le_bytes(),
+        ));
+        tiff.extend(entry(TAG_LENS_MODEL, TYPE_ASCII, 9, 76u32.to_le_bytes()));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(35u32.to_le_bytes());
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(b"35mm F2\0\0");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shot.arw");
+        std::fs::write(&path, &tiff).unwrap();
+
+        let info = read_camera(&path).unwrap();
+
+        assert_eq!(info.make.as_deref(), Some("Xyz"));
+        assert_eq!(info.lens_model.as_deref(), Some("35mm F2"));
+        assert_eq!(info.focal_length, Some(35.0));
+        assert!(info.model.is_none() && info.f_number.is_none());
+    }
+
+    #[test]
+    fn scales_frames_to_the_median_exif_exposure() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = Rgb16Image::from_pixel(8, 8, Rgb([20000; 3]));
+        // 2 枚めだけ絞りがちらついて 1/3 段暗く写った
+        let f_numbers = [(80, 10), (90, 10), (80, 10)];
+        let paths: Vec<String> = f_numbers
+            .iter()
+            .enumerate()
+            .map(|(index, f_number)| {
+                let path = dir.path().join(format!("{}.png", index));
+                save_png_with_exif(&frame, &path, &exif_tiff((1, 60), *f_number, 100));
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        let mut images = vec![frame.clone(); 3];
+
+        let compensation = compensate(&paths, &mut images, &[vec![0, 1, 2]]).unwrap();
+
+        assert_eq!(compensation[0].correction_ev, 0.0);
+        assert!(compensation[1].correction_ev > 0.3);
+        let linear = |image: &Rgb16Image| srgb_to_linear(u16_to_unit(image.get_pixel(0, 0).0[0]));
+        assert!(linear(&images[1]) > linear(&images[0]) * 1.2);
+        assert_eq!(images[2], frame);
+
+        let mut without_exif = vec![frame.clone(); 2];
+        let plain = dir.path().join("plain.png");
+        frame.save(&plain).unwrap();
+        let paths = [paths[0].clone(), plain.to_string_lossy().to_string()];
+        assert!(compensate(&paths, &mut without_exif, &[vec![0, 1]]).is_err());
+    }
+
+    #[test]
+    fn matches_bracket_frames_to_the_first_bracket_by_rank() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = Rgb16Image::from_pixel(8, 8, Rgb([20000; 3]));
+        // 露出時間で段を変えたブラケット。入力の順は暗い順でなくてよい
+        let times = [(1, 60), (1, 250), (1, 15)];
+        let paths: Vec<String> = times
+            .iter()
+            .enumerate()
+            .map(|(index, time)| {
+                let path = dir.path().join(format!("{}.png", index));
+                save_png_with_exif(&frame, &path, &exif_tiff(*time, (80, 10), 100));
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        let mut images = vec![frame.clone(); 3];
+
+        let first = compensate_to_reference(&paths, &mut images, &[]).unwrap();
+        assert!(first.iter().all(|frame| frame.correction_ev == 0.0));
+        let mut reference: Vec<f64> = first.iter().map(|frame| frame.exif_ev).collect();
+        reference.sort_by(f64::total_cmp);
+
+        // 先頭のブラケットより 1/4 段暗い段を持ち上げる
+        reference[0] += 0.25;
+        let compensation = compensate_to_reference(&paths, &mut images, &reference).unwrap();
+        assert!((compensation[1].correction_ev - 0.25).abs() < 1e-9);
+        assert_eq!(compensation[0].correction_ev, 0.0);
+        assert_eq!(images[0], frame);
+        assert_ne!(images[1], frame);
+        assert!(compensate_to_reference(&paths, &mut images, &reference[..2]).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use exr::meta::attribute::Chromaticities;
+use exr::prelude::Vec2;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{srgb_to_linear, u16_to_unit};
+
+// EXR に書く線形の色空間。未指定なら従来どおり sRGB の値をそのまま書き、chromaticities 属性も付けない
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum ExrColorSpace {
+    LinearRec709,
+    Rec2020,
+    // ACES の作業用色空間（AP1 の原色・D60 の白色点）
+    AcesCg,
+}
+
+impl ExrColorSpace {
+    // 線形 Rec.709 からの変換行列。白（1, 1, 1）は白のまま移る（ACEScg は Bradford で D65 から D60 へ順応）
+    fn matrix_from_rec709(self) -> [[f32; 3]; 3] {
+        match self {
+            ExrColorSpace::LinearRec709 => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            ExrColorSpace::Rec2020 => [
+                [0.627_404, 0.329_283, 0.043_313],
+                [0.069_097, 0.919_540, 0.011_363],
+                [0.016_392, 0.088_013, 0.895_595],
+            ],
+            ExrColorSpace::AcesCg => [
+                [0.613_097, 0.339_523, 0.047_380],
+                [0.070_194, 0.916_356, 0.013_450],
+                [0.020_616, 0.109_570, 0.869_814],
+            ],
+        }
+    }
+
+    pub fn chromaticities(self) -> Chromaticities {
+        let d65 = Vec2(0.3127, 0.3290);
+        match self {
+            ExrColorSpace::LinearRec709 => Chromaticities {
+                red: Vec2(0.64, 0.33),
+                green: Vec2(0.30, 0.60),
+                blue: Vec2(0.15, 0.06),
+                white: d65,
+            },
+            ExrColorSpace::Rec2020 => Chromaticities {
+                red: Vec2(0.708, 0.292),
+                green: Vec2(0.170, 0.797),
+                blue: Vec2(0.131, 0.046),
+                white: d65,
+            },
+            ExrColorSpace::AcesCg => Chromaticities {
+                red: Vec2(0.713, 0.293),
+                green: Vec2(0.165, 0.830),
+                blue: Vec2(0.128, 0.044),
+                white: Vec2(0.32168, 0.33767),
+            },
+        }
+    }
+}
+
+// 合成結果（sRGB の 16bit）を EXR の画素値にする。逆ガンマは全階調ぶんの表を引く
+pub struct ExrPixels {
+    color_space: Option<ExrColorSpace>,
+    linear: Vec<f32>,
+}
+
+impl ExrPixels {
+    pub fn new(color_space: Option<ExrColorSpace>) -> Self {
+        let linear = match color_space {
+            Some(_) => (0..=u16::MAX)
+                .map(|value| srgb_to_linear(u16_to_unit(value)))
+                .collect(),
+            None => Vec::new(),
+        };
+        Self {
+            color_space,
+            linear,
+        }
+    }
+
+    pub fn chromaticities(&self) -> Option<Chromaticities> {
+        self.color_space.map(ExrColorSpace::chromaticities)
+    }
+
+    pub fn convert(&self, pixel: [u16; 3]) -> (f32, f32, f32) {
+        let Some(color_space) = self.color_space else {
+            return (
+                u16_to_unit(pixel[0]),
+                u16_to_unit(pixel[1]),
+                u16_to_unit(pixel[2]),
+            );
+        };
+        let rgb = pixel.map(|value| self.linear[value as usize]);
+        let [r, g, b] = color_space
+            .matrix_from_rec709()
+            .map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]);
+        (r, g, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_linear_primaries_keeping_white() {
+        let legacy = ExrPixels::new(None);
+        assert_eq!(legacy.convert([u16::MAX, 0, 0]), (1.0, 0.0, 0.0));
+        assert!(legacy.chromaticities().is_none());
+
+        let linear = ExrPixels::new(Some(ExrColorSpace::LinearRec709));
+        let (gray, _, _) = linear.convert([32768; 3]);
+        assert!((gray - 0.2140).abs() < 0.001);
+
+        for color_space in [ExrColorSpace::Rec2020, ExrColorSpace::AcesCg] {
+            let pixels = ExrPixels::new(Some(color_space));
+            let (r, g, b) = pixels.convert([u16::MAX; 3]);
+            assert!([r, g, b].iter().all(|v| (v - 1.0).abs() < 0.001));
+            // Rec.709 の純色は広い色域ではほかの原色も少し含む
+            let (r, g, b) = pixels.convert([u16::MAX, 0, 0]);
+            assert!(r < 0.7 && g > 0.0 && b > 0.0);
+        }
+    }
+}
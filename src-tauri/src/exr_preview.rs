@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use exr::meta::attribute::Preview;
+use exr::prelude::Vec2;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbImage};
+
+use crate::merge::Rgb16Image;
+
+// EXR のヘッダーに埋め込むプレビューの長辺。ヘッダーは毎回読まれるため小さく保つ
+const ATTRIBUTE_LONG_EDGE: u32 = 256;
+// EXR を表示できないエクスプローラー・Finder 向けのサイドカー JPEG の長辺
+const SIDECAR_LONG_EDGE: u32 = 512;
+const SIDECAR_QUALITY: u8 = 85;
+
+// 長辺が long_edge 以下になるよう縮小した 8bit 画像。小さい画像は拡大しない
+pub fn thumbnail(image: &Rgb16Image, long_edge: u32) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let scale = (long_edge as f64 / width.max(height) as f64).min(1.0);
+    let size = |length: u32| ((length as f64 * scale).round() as u32).max(1);
+    let eight_bit = DynamicImage::ImageRgb16(image.clone()).into_rgb8();
+    if scale == 1.0 {
+        return eight_bit;
+    }
+    imageops::resize(&eight_bit, size(width), size(height), FilterType::Triangle)
+}
+
+// EXR の preview 属性（8bit の RGBA を上の行から）。合成結果は表示用にガンマがかかっているため、そのまま 8bit にする
+pub fn preview_attribute(image: &Rgb16Image) -> Preview {
+    let small = thumbnail(image, ATTRIBUTE_LONG_EDGE);
+    let pixel_data = small
+        .pixels()
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], u8::MAX])
+        .map(|value| value as i8)
+        .collect();
+    Preview {
+        size: Vec2(small.width() as usize, small.height() as usize),
+        pixel_data,
+    }
+}
+
+pub fn write_sidecar_jpeg(image: &Rgb16Image, path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    JpegEncoder::new_with_quality(BufWriter::new(file), SIDECAR_QUALITY)
+        .encode_image(&thumbnail(image, SIDECAR_LONG_EDGE))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn previews_keep_the_aspect_ratio() {
+        let image = Rgb16Image::from_pixel(1024, 512, Rgb([u16::MAX, 0, 0]));
+
+        let preview = preview_attribute(&image);
+
+        assert_eq!(preview.size, Vec2(256, 128));
+        assert_eq!(preview.pixel_data.len(), 256 * 128 * 4);
+        assert_eq!(&preview.pixel_data[..4], &[-1, 0, 0, -1]);
+        assert_eq!(
+            thumbnail(&Rgb16Image::new(40, 30), SIDECAR_LONG_EDGE).dimensions(),
+            (40, 30)
+        );
+    }
+}
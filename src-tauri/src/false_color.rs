@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{linear_to_srgb, luminance};
+use crate::probe::LinearImage;
+
+// 18% グレーを 0 stop とする
+const MIDDLE_GRAY: f32 = 0.18;
+const STOPS_RANGE: i32 = 5;
+const ZEBRA_IRE: f32 = 95.0;
+const ZEBRA_STRIPE: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum FalseColorMode {
+    // 表示用（sRGB）に変換した輝度を 0〜100 IRE の帯で色分けする
+    Ire,
+    // リニア輝度を 18% グレーからの段数で色分けする
+    Stops,
+    // 95 IRE 以上を縞模様で示す
+    Zebra,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct FalseColorBand {
+    pub label: String,
+    pub color: [u8; 3],
+    // この帯に入った画素の割合
+    pub fraction: f64,
+}
+
+#[derive(Debug, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct FalseColorResult {
+    pub path: String,
+    pub mode: FalseColorMode,
+    pub width: u32,
+    pub height: u32,
+    pub bands: Vec<FalseColorBand>,
+}
+
+// (上限 IRE, ラベル, 色)。None の帯は元画像のグレースケールで表示する
+const IRE_BANDS: &[(f32, &str, Option<[u8; 3]>)] = &[
+    (2.0, "0-2 IRE", Some([96, 0, 128])),
+    (10.0, "2-10 IRE", Some([0, 64, 255])),
+    (20.0, "10-20 IRE", Some([0, 150, 160])),
+    (40.0, "20-40 IRE", None),
+    (45.0, "40-45 IRE", Some([0, 200, 0])),
+    (55.0, "45-55 IRE", None),
+    (60.0, "55-60 IRE", Some([255, 140, 180])),
+    (85.0, "60-85 IRE", None),
+    (97.0, "85-97 IRE", Some([255, 230, 0])),
+    (f32::INFINITY, "97-100 IRE", Some([255, 0, 0])),
+];
+
+// -5 stop 未満から +5 stop 以上まで、暗い側は寒色・明るい側は暖色
+const STOP_COLORS: [[u8; 3]; 11] = [
+    [48, 0, 96],
+    [64, 0, 160],
+    [0, 64, 255],
+    [0, 160, 255],
+    [0, 200, 120],
+    [128, 128, 128],
+    [200, 220, 0],
+    [255, 200, 0],
+    [255, 128, 0],
+    [255, 48, 0],
+    [255, 0, 0],
+];
+
+pub fn generate(
+    image: &LinearImage,
+    mode: FalseColorMode,
+    output_path: &Path,
+) -> Result<FalseColorResult, String> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err("画像が空です".to_string());
+    }
+
+    let labels = band_labels(mode);
+    let mut counts = vec![0u64; labels.len()];
+    let mut output = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(width, height);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let linear = luminance(pixel.0).max(0.0);
+        let display = linear_to_srgb(linear.min(1.0));
+        let gray = to_u8(display);
+        let (band, color) = match mode {
+            FalseColorMode::Ire => {
+                let ire = display * 100.0;
+                let band = IRE_BANDS
+                    .iter()
+                    .position(|(upper, _, _)| ire < *upper)
+                    .unwrap_or(IRE_BANDS.len() - 1);
+                (band, IRE_BANDS[band].2.unwrap_or([gray; 3]))
+            }
+            FalseColorMode::Stops => {
+                let stops = (linear.max(f32::MIN_POSITIVE) / MIDDLE_GRAY).log2();
+                let band = (stops.round() as i32).clamp(-STOPS_RANGE, STOPS_RANGE) + STOPS_RANGE;
+                (band as usize, STOP_COLORS[band as usize])
+            }
+            FalseColorMode::Zebra => {
+                if display * 100.0 >= ZEBRA_IRE {
+                    let stripe = ((x + y) / ZEBRA_STRIPE).is_multiple_of(2);
+                    (1, if stripe { [0, 0, 0] } else { [255, 255, 255] })
+                } else {
+                    let rgb = pixel
+                        .0
+                        .map(|value| to_u8(linear_to_srgb(value.clamp(0.0, 1.0))));
+                    (0, rgb)
+                }
+            }
+        };
+        counts[band] += 1;
+        output.put_pixel(x, y, Rgb(color));
+    }
+
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    output.save(output_path).map_err(|e| e.to_string())?;
+
+    let total = (width as u64 * height as u64) as f64;
+    Ok(FalseColorResult {
+        path: output_path.to_string_lossy().to_string(),
+        mode,
+        width,
+        height,
+        bands: labels
+            .into_iter()
+            .zip(counts)
+            .map(|((label, color), count)| FalseColorBand {
+                label,
+                color,
+                fraction: count as f64 / total,
+            })
+            .collect(),
+    })
+}
+
+fn band_labels(mode: FalseColorMode) -> Vec<(String, [u8; 3])> {
+    match mode {
+        FalseColorMode::Ire => IRE_BANDS
+            .iter()
+            .map(|(_, label, color)| (label.to_string(), color.unwrap_or([128; 3])))
+            .collect(),
+        FalseColorMode::Stops => (-STOPS_RANGE..=STOPS_RANGE)
+            .zip(STOP_COLORS)
+            .map(|(stop, color)| (format!("{:+} stop", stop), color))
+            .collect(),
+        FalseColorMode::Zebra => vec![
+            ("95 IRE 未満".to_string(), [128; 3]),
+            (format!("{} IRE 以上", ZEBRA_IRE), [255; 3]),
+        ],
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> LinearImage {
+        LinearImage::from_fn(16, 4, |x, _| {
+            let value = if x < 8 { MIDDLE_GRAY } else { 1.0 };
+            Rgb([value; 3])
+        })
+    }
+
+    #[test]
+    fn stops_mode_reports_band_fractions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stops.png");
+
+        let result = generate(&gradient(), FalseColorMode::Stops, &path).unwrap();
+
+        assert!(path.exists());
+        let zero = result.bands.iter().find(|b| b.label == "+0 stop").unwrap();
+        assert_eq!(zero.fraction, 0.5);
+        let total: f64 = result.bands.iter().map(|band| band.fraction).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zebra_marks_only_highlights() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("zebra.png");
+
+        let result = generate(&gradient(), FalseColorMode::Zebra, &path).unwrap();
+
+        assert_eq!(result.bands[1].fraction, 0.5);
+        let written = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(written.get_pixel(8, 0).0, [0, 0, 0]);
+        assert_eq!(written.get_pixel(12, 0).0, [255, 255, 255]);
+        assert_ne!(written.get_pixel(0, 0).0, [0, 0, 0]);
+    }
+}
@@ -0,0 +1,128 @@
+use image::imageops::FilterType;
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{u16_to_unit, unit_to_u16};
+use crate::merge::Rgb16Image;
+
+const DENOISE_RADIUS: i32 = 2;
+const DENOISE_SPATIAL_SIGMA: f32 = 1.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DenoiseParams {
+    // バイラテラルフィルタの値域シグマ（0〜1 のスケール）
+    pub strength: f32,
+}
+
+impl Default for DenoiseParams {
+    fn default() -> Self {
+        Self { strength: 0.05 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ResizeParams {
+    // 長辺の最大ピクセル数。元画像がこれより小さい場合は何もしない
+    pub max_size: u32,
+}
+
+impl Default for ResizeParams {
+    fn default() -> Self {
+        Self { max_size: 2048 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SharpenParams {
+    // ぼかしのシグマ（画素）
+    pub radius: f32,
+    // 元画像とぼかした画像の差を何倍して足すか
+    pub amount: f32,
+    // 差がこれ以下（0〜1 のスケール）の画素は強調しない。平坦な空のノイズを目立たせないため
+    pub threshold: f32,
+}
+
+impl Default for SharpenParams {
+    fn default() -> Self {
+        Self {
+            radius: 1.0,
+            amount: 0.5,
+            threshold: 0.0,
+        }
+    }
+}
+
+pub fn denoise(image: &Rgb16Image, params: &DenoiseParams) -> Rgb16Image {
+    if params.strength <= 0.0 {
+        return image.clone();
+    }
+
+    let width = image.width() as i32;
+    let height = image.height() as i32;
+    let range_denominator = 2.0 * params.strength * params.strength;
+    let spatial_denominator = 2.0 * DENOISE_SPATIAL_SIGMA * DENOISE_SPATIAL_SIGMA;
+
+    Rgb16Image::from_fn(image.width(), image.height(), |x, y| {
+        let center = image.get_pixel(x, y).0.map(u16_to_unit);
+        let mut total = [0.0f32; 3];
+        let mut weight_total = 0.0f32;
+
+        for dy in -DENOISE_RADIUS..=DENOISE_RADIUS {
+            for dx in -DENOISE_RADIUS..=DENOISE_RADIUS {
+                let sx = (x as i32 + dx).clamp(0, width - 1) as u32;
+                let sy = (y as i32 + dy).clamp(0, height - 1) as u32;
+                let sample = image.get_pixel(sx, sy).0.map(u16_to_unit);
+
+                let distance = sample
+                    .iter()
+                    .zip(&center)
+                    .map(|(a, b)| (a - b).powi(2))
+                    .sum::<f32>();
+                let weight = (-((dx * dx + dy * dy) as f32) / spatial_denominator).exp()
+                    * (-distance / range_denominator).exp();
+
+                for c in 0..3 {
+                    total[c] += sample[c] * weight;
+                }
+                weight_total += weight;
+            }
+        }
+
+        Rgb(total.map(|v| unit_to_u16(v / weight_total)))
+    })
+}
+
+pub fn resize(image: &Rgb16Image, params: &ResizeParams) -> Rgb16Image {
+    let longest = image.width().max(image.height());
+    if longest <= params.max_size {
+        return image.clone();
+    }
+
+    let scale = params.max_size as f64 / longest as f64;
+    let width = ((image.width() as f64 * scale).round() as u32).max(1);
+    let height = ((image.height() as f64 * scale).round() as u32).max(1);
+    image::imageops::resize(image, width, height, FilterType::Lanczos3)
+}
+
+// アンシャープマスク
+pub fn sharpen(image: &Rgb16Image, params: &SharpenParams) -> Rgb16Image {
+    if params.amount <= 0.0 || params.radius <= 0.0 {
+        return image.clone();
+    }
+    let blurred = image::imageops::blur(image, params.radius);
+    let threshold = params.threshold * u16::MAX as f32;
+    let mut sharpened = image.clone();
+    for (value, blurred) in sharpened.iter_mut().zip(blurred.iter()) {
+        let difference = *value as f32 - *blurred as f32;
+        if difference.abs() > threshold {
+            *value = (*value as f32 + difference * params.amount)
+                .round()
+                .clamp(0.0, u16::MAX as f32) as u16;
+        }
+    }
+    sharpened
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::formats::ExtensionMatcher;
+use crate::grouping::{self, GroupInput, GroupingRules};
+use crate::history::HistoryEntry;
+use crate::watch_filter;
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchFolderStats {
+    pub watch_folder: Option<String>,
+    // 監視対象の拡張子で、まだ合成の入力にも出力にも使われていないファイル
+    pub pending_files: usize,
+    // pending_files を現在のグループ分け規則でまとめたうち、2枚以上あるグループ
+    pub pending_groups: usize,
+    pub watch_folder_bytes: u64,
+    pub output_folder: Option<String>,
+    pub output_folder_bytes: u64,
+    pub last_detected_at: Option<String>,
+}
+
+pub struct StatsSources<'a> {
+    pub watch_folder: Option<&'a Path>,
+    pub output_folder: Option<&'a Path>,
+    pub matcher: &'a ExtensionMatcher,
+    pub ignore_dirs: &'a [PathBuf],
+    pub history: &'a [HistoryEntry],
+    pub rules: &'a GroupingRules,
+    pub last_detected_at: Option<String>,
+}
+
+pub fn collect(sources: &StatsSources) -> Result<WatchFolderStats, String> {
+    let mut used: HashSet<PathBuf> = HashSet::new();
+    for entry in sources.history {
+        used.extend(entry.input_paths.iter().map(PathBuf::from));
+        used.insert(PathBuf::from(&entry.output_png_path));
+        used.extend(entry.output_exr_path.iter().map(PathBuf::from));
+    }
+
+    let mut pending = Vec::new();
+    let mut watch_folder_bytes = 0;
+    if let Some(folder) = sources.watch_folder {
+        walk_files(folder, &mut |path, size| {
+            watch_folder_bytes += size;
+            if sources.matcher.matches(path)
+                && !used.contains(path)
+                && !watch_filter::is_ignored(path, sources.ignore_dirs)
+            {
+                pending.push(GroupInput {
+                    path: path.to_string_lossy().to_string(),
+                    detected_at: None,
+                });
+            }
+        })?;
+    }
+    let pending_groups = grouping::group_images(&pending, sources.rules)?
+        .iter()
+        .filter(|group| group.paths.len() >= 2)
+        .count();
+
+    let mut output_folder_bytes = 0;
+    if let Some(folder) = sources.output_folder {
+        walk_files(folder, &mut |_, size| output_folder_bytes += size)?;
+    }
+
+    Ok(WatchFolderStats {
+        watch_folder: sources
+            .watch_folder
+            .map(|folder| folder.to_string_lossy().to_string()),
+        pending_files: pending.len(),
+        pending_groups,
+        watch_folder_bytes,
+        output_folder: sources
+            .output_folder
+            .map(|folder| folder.to_string_lossy().to_string()),
+        output_folder_bytes,
+        last_detected_at: sources.last_detected_at.clone(),
+    })
+}
+
+// 存在しないフォルダは 0 件として扱う。シンボリックリンクはたどらない
+fn walk_files(dir: &Path, visit: &mut impl FnMut(&Path, u64)) -> Result<(), String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("{} を読み込めません: {}", dir.to_string_lossy(), e)),
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            walk_files(&path, visit)?;
+        } else if file_type.is_file() {
+            let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            visit(&path, size);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_pending_files_groups_and_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let watch = dir.path().join("watch");
+        let output = dir.path().join("output");
+        fs::create_dir_all(watch.join("day1")).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        for name in ["a.jpg", "b.jpg", "day1/c.jpg", "merged.jpg", "notes.txt"] {
+            fs::write(watch.join(name), b"12345").unwrap();
+        }
+        fs::write(output.join("hdr.png"), b"1234567890").unwrap();
+        let history = vec![HistoryEntry {
+            id: 1,
+            merged_at: String::new(),
+            input_paths: vec![watch.join("merged.jpg").to_string_lossy().to_string()],
+            output_png_path: output.join("hdr.png").to_string_lossy().to_string(),
+            output_exr_path: None,
+            algorithm: "average".to_string(),
+            algorithm_params: Default::default(),
+            stages: Vec::new(),
+            duration_ms: None,
+            width: 1,
+            height: 1,
+            rating: 0,
+            tags: Vec::new(),
+            preset: None,
+            stale: false,
+            request: None,
+            output_paths: Vec::new(),
+        }];
+        let rules = GroupingRules {
+            max_gap_secs: None,
+            max_images: 2,
+            ..Default::default()
+        };
+
+        let stats = collect(&StatsSources {
+            watch_folder: Some(&watch),
+            output_folder: Some(&output),
+            matcher: &ExtensionMatcher::default(),
+            ignore_dirs: &[],
+            history: &history,
+            rules: &rules,
+            last_detected_at: None,
+        })
+        .unwrap();
+
+        assert_eq!(stats.pending_files, 3);
+        assert_eq!(stats.pending_groups, 1);
+        assert_eq!(stats.watch_folder_bytes, 25);
+        assert_eq!(stats.output_folder_bytes, 10);
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+// このビルドでデコードして合成に使える形式
+pub const DECODABLE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tif", "tiff", "bmp", "webp"];
+// 検出・グループ分けの対象にはできるが、まだデコードできない形式
+pub const UNDECODABLE_EXTENSIONS: &[&str] = &[
+    "heic", "heif", "dng", "cr2", "cr3", "nef", "arw", "orf", "rw2", "raf", "pef", "srw",
+];
+
+// 監視・解析・合成入力の判定に使う拡張子の一覧。大文字小文字は区別しない
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExtensionMatcher {
+    extensions: Vec<String>,
+}
+
+impl Default for ExtensionMatcher {
+    fn default() -> Self {
+        Self::new(DECODABLE_EXTENSIONS.iter().map(|ext| ext.to_string()))
+    }
+}
+
+impl ExtensionMatcher {
+    // 先頭の "." は省略可能。空の一覧では既定の形式を使う
+    pub fn new(extensions: impl IntoIterator<Item = String>) -> Self {
+        let mut normalized: Vec<String> = extensions
+            .into_iter()
+            .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+        normalized.sort();
+        normalized.dedup();
+        if normalized.is_empty() {
+            return Self::default();
+        }
+        Self {
+            extensions: normalized,
+        }
+    }
+
+    pub fn from_settings(extensions: &[String]) -> Self {
+        Self::new(extensions.iter().cloned())
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        extension_of(path).is_some_and(|ext| self.extensions.contains(&ext))
+    }
+}
+
+pub fn extension_of(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+}
+
+pub fn is_decodable(path: &Path) -> bool {
+    extension_of(path).is_some_and(|ext| DECODABLE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+// フォルダ直下のデコードできる画像を名前順に返す。連番のファイル名が撮影順に並ぶ
+pub fn list_decodable(folder: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(folder)
+        .map_err(|e| format!("フォルダを読み込めません: {}: {}", folder.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && is_decodable(path))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+// デコードできない既知の形式なら、読み込み前に分かりやすいエラーを返す
+pub fn ensure_decodable(path: &Path) -> Result<(), String> {
+    match extension_of(path) {
+        Some(ext) if UNDECODABLE_EXTENSIONS.contains(&ext.as_str()) => Err(format!(
+            "{} 形式の読み込みには未対応です: {}",
+            ext.to_uppercase(),
+            path.to_string_lossy()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_extensions_case_insensitively() {
+        let matcher = ExtensionMatcher::default();
+
+        assert!(matcher.matches(Path::new("IMG_0001.JPG")));
+        assert!(matcher.matches(Path::new("scan.TiFf")));
+        assert!(!matcher.matches(Path::new("IMG_0001.CR3")));
+        assert!(!matcher.matches(Path::new("README")));
+    }
+
+    #[test]
+    fn custom_list_accepts_raw_formats_and_dotted_entries() {
+        let matcher = ExtensionMatcher::from_settings(&[".CR3".to_string(), "dng".to_string()]);
+
+        assert!(matcher.matches(Path::new("a.cr3")));
+        assert!(matcher.matches(Path::new("a.DNG")));
+        assert!(!matcher.matches(Path::new("a.png")));
+        assert!(ensure_decodable(Path::new("a.cr3")).is_err());
+        assert!(ensure_decodable(Path::new("a.PNG")).is_ok());
+    }
+}
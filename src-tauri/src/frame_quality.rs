@@ -0,0 +1,301 @@
+use std::ops::RangeInclusive;
+
+use image::imageops::{self, FilterType};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{luminance, srgb_to_linear, u16_to_unit};
+use crate::deghost;
+use crate::frame_select;
+use crate::merge::Rgb16Image;
+
+// 判定は縮小した画像で行う
+const ANALYSIS_SIZE: u32 = 1024;
+const WELL_EXPOSED: RangeInclusive<f32> = 0.05..=0.95;
+// 全フレームで適正露出の画素がこの割合未満なら、フレームごとの適正露出の画素で比べる
+const MIN_COMMON_FRACTION: f64 = 0.05;
+// 最もシャープなフレームに対する比がこれ未満ならブレとみなす
+const MIN_RELATIVE_SHARPNESS: f64 = 0.6;
+// 露出をそろえた輝度が基準フレームとこれ以上ずれる画素を食い違いとする
+const INCONSISTENT_DIFFERENCE: f32 = 0.35;
+const MAX_INCONSISTENCY: f64 = 0.15;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameScore {
+    pub path: String,
+    // 最も暗いフレームを 0 とした相対 EV
+    pub ev: f64,
+    // 最もシャープなフレームを 1 とした比
+    pub sharpness: f64,
+    // 露出の中央のフレームと食い違う画素の割合（動体・位置ずれ）
+    pub inconsistency: f64,
+    // 除外を勧める理由。勧めない場合は null
+    pub exclude_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameQuality {
+    pub scores: Vec<FrameScore>,
+    pub recommended_exclusions: Vec<String>,
+}
+
+struct Analysis {
+    luma: Vec<f32>,
+    exposed: Vec<bool>,
+}
+
+// 同じ大きさのフレームを採点し、除外を勧めるフレームを返す。
+// 基準フレームは除外せず、合成に必要な 2 枚は必ず残す
+pub fn score_frames(paths: &[String], images: &[Rgb16Image]) -> FrameQuality {
+    if images.is_empty() {
+        return FrameQuality {
+            scores: Vec::new(),
+            recommended_exclusions: Vec::new(),
+        };
+    }
+    let small: Vec<Rgb16Image> = images.iter().map(downscale).collect();
+    let (width, height) = small[0].dimensions();
+    let analyses: Vec<Analysis> = small.iter().map(analyze).collect();
+    let pixel_count = (width * height) as usize;
+    let common: Vec<bool> = (0..pixel_count)
+        .map(|i| analyses.iter().all(|analysis| analysis.exposed[i]))
+        .collect();
+    let use_common = common.iter().filter(|&&exposed| exposed).count() as f64
+        >= pixel_count as f64 * MIN_COMMON_FRACTION;
+
+    let raw_sharpness: Vec<f64> = analyses
+        .iter()
+        .map(|analysis| {
+            let mask = if use_common {
+                &common
+            } else {
+                &analysis.exposed
+            };
+            sharpness(&analysis.luma, mask, width, height)
+        })
+        .collect();
+    let sharpest = raw_sharpness.iter().cloned().fold(0.0, f64::max);
+
+    let evs: Vec<f64> = small.iter().map(frame_select::estimate_ev).collect();
+    let darkest = evs.iter().cloned().fold(f64::INFINITY, f64::min);
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by(|&a, &b| evs[a].total_cmp(&evs[b]));
+    let reference = order[order.len() / 2];
+
+    let mut scores: Vec<FrameScore> = (0..images.len())
+        .map(|index| {
+            let inconsistency = if index == reference {
+                0.0
+            } else {
+                inconsistency(
+                    &small[index],
+                    &small[reference],
+                    &analyses[index],
+                    &analyses[reference],
+                )
+            };
+            FrameScore {
+                path: paths[index].clone(),
+                ev: evs[index] - darkest,
+                sharpness: if sharpest > 0.0 {
+                    raw_sharpness[index] / sharpest
+                } else {
+                    1.0
+                },
+                inconsistency,
+                exclude_reason: None,
+            }
+        })
+        .collect();
+
+    // 悪い順に、2 枚残るところまで除外を勧める
+    let mut candidates: Vec<(usize, f64, String)> = scores
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != reference)
+        .filter_map(|(index, score)| {
+            let blur = (MIN_RELATIVE_SHARPNESS - score.sharpness) / MIN_RELATIVE_SHARPNESS;
+            let ghost = (score.inconsistency - MAX_INCONSISTENCY) / MAX_INCONSISTENCY;
+            // ブレたフレームは輪郭がずれて食い違いも大きくなるため、ブレを理由にする
+            if blur > 0.0 {
+                Some((
+                    index,
+                    blur.max(ghost),
+                    format!("ブレ（シャープネス {:.0}%）", score.sharpness * 100.0),
+                ))
+            } else if ghost > 0.0 {
+                Some((
+                    index,
+                    ghost,
+                    format!(
+                        "ほかのフレームとの食い違い（{:.0}%）",
+                        score.inconsistency * 100.0
+                    ),
+                ))
+            } else {
+                None
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+    candidates.truncate(images.len().saturating_sub(2));
+
+    let mut recommended = Vec::new();
+    for (index, _, reason) in candidates {
+        scores[index].exclude_reason = Some(reason);
+    }
+    for score in &scores {
+        if score.exclude_reason.is_some() {
+            recommended.push(score.path.clone());
+        }
+    }
+    FrameQuality {
+        scores,
+        recommended_exclusions: recommended,
+    }
+}
+
+fn downscale(image: &Rgb16Image) -> Rgb16Image {
+    let (width, height) = image.dimensions();
+    if width.max(height) <= ANALYSIS_SIZE {
+        return image.clone();
+    }
+    let scale = ANALYSIS_SIZE as f64 / width.max(height) as f64;
+    let target = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+    imageops::resize(image, target(width), target(height), FilterType::Triangle)
+}
+
+fn analyze(image: &Rgb16Image) -> Analysis {
+    let (luma, exposed) = image
+        .pixels()
+        .map(|pixel| {
+            let exposed = pixel
+                .0
+                .iter()
+                .all(|v| WELL_EXPOSED.contains(&u16_to_unit(*v)));
+            let linear = pixel.0.map(|v| srgb_to_linear(u16_to_unit(v)));
+            (luminance(linear), exposed)
+        })
+        .unzip();
+    Analysis { luma, exposed }
+}
+
+// 対数輝度のラプラシアンの平均。露出が変わっても対数では定数がずれるだけなので、露出の違うフレームを比べられる
+fn sharpness(luma: &[f32], mask: &[bool], width: u32, height: u32) -> f64 {
+    let (width, height) = (width as usize, height as usize);
+    let log = |index: usize| luma[index].max(1e-6).ln();
+    let mut total = 0.0f64;
+    let mut count = 0u64;
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width - 1 {
+            let index = y * width + x;
+            let neighbors = [index - 1, index + 1, index - width, index + width];
+            if !mask[index] || neighbors.iter().any(|&n| !mask[n]) {
+                continue;
+            }
+            let laplacian = 4.0 * log(index) - neighbors.iter().map(|&n| log(n)).sum::<f32>();
+            total += laplacian.abs() as f64;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        0.0
+    } else {
+        total / count as f64
+    }
+}
+
+fn inconsistency(
+    frame: &Rgb16Image,
+    reference: &Rgb16Image,
+    frame_analysis: &Analysis,
+    reference_analysis: &Analysis,
+) -> f64 {
+    let Some(ratio) = deghost::exposure_ratio(frame, reference) else {
+        return 0.0;
+    };
+    let mut compared = 0u64;
+    let mut differing = 0u64;
+    for index in 0..frame_analysis.luma.len() {
+        if !(frame_analysis.exposed[index] && reference_analysis.exposed[index]) {
+            continue;
+        }
+        let expected = reference_analysis.luma[index].max(1e-6);
+        let normalized = frame_analysis.luma[index] / ratio;
+        compared += 1;
+        if (normalized - expected).abs() / expected > INCONSISTENT_DIFFERENCE {
+            differing += 1;
+        }
+    }
+    if compared == 0 {
+        0.0
+    } else {
+        differing as f64 / compared as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{linear_to_srgb, unit_to_u16};
+    use image::Rgb;
+
+    // 4px の市松模様を 1EV ずつ露出を変えて写したブラケット
+    fn bracket() -> (Vec<String>, Vec<Rgb16Image>) {
+        let images: Vec<Rgb16Image> = [0.5f32, 1.0, 2.0]
+            .iter()
+            .map(|exposure| {
+                Rgb16Image::from_fn(96, 64, |x, y| {
+                    let base = if (x / 4 + y / 4) % 2 == 0 { 0.08 } else { 0.2 };
+                    Rgb([unit_to_u16(linear_to_srgb(base * exposure)); 3])
+                })
+            })
+            .collect();
+        let paths = (0..images.len()).map(|i| format!("{}.png", i)).collect();
+        (paths, images)
+    }
+
+    #[test]
+    fn clean_bracket_needs_no_exclusion() {
+        let (paths, images) = bracket();
+
+        let quality = score_frames(&paths, &images);
+
+        assert!(quality.recommended_exclusions.is_empty(), "{:?}", quality);
+        assert!(quality.scores.iter().all(|score| score.sharpness > 0.6));
+    }
+
+    #[test]
+    fn blurred_frame_is_recommended_for_exclusion() {
+        let (paths, mut images) = bracket();
+        let last = images.len() - 1;
+        images[last] = imageops::blur(&images[last], 3.0);
+
+        let quality = score_frames(&paths, &images);
+
+        assert_eq!(quality.recommended_exclusions, [paths[last].clone()]);
+        assert!(quality.scores[last]
+            .exclude_reason
+            .as_deref()
+            .unwrap()
+            .starts_with("ブレ"));
+    }
+
+    #[test]
+    fn moving_subject_is_reported_as_inconsistent() {
+        let (paths, mut images) = bracket();
+        for y in 0..32 {
+            for x in 0..48 {
+                images[0].put_pixel(x, y, Rgb([40000; 3]));
+            }
+        }
+
+        let quality = score_frames(&paths, &images);
+
+        assert!(quality.scores[0].inconsistency > 0.2);
+        assert_eq!(quality.scores[1].inconsistency, 0.0);
+        assert_eq!(quality.recommended_exclusions, [paths[0].clone()]);
+    }
+}
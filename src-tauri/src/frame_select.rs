@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::capabilities::MAX_MERGE_FRAMES;
+use crate::color::{luminance, srgb_to_linear, u16_to_unit};
+use crate::merge::Rgb16Image;
+
+// 露出の推定だけに使うため、全画素ではなく間引いて平均する
+const EV_SAMPLE_STEP: u32 = 4;
+// 露出を比べて選ぶ候補の最大枚数
+pub const MAX_SELECTION_CANDIDATES: usize = 16;
+// 真っ黒な画像でも log2 が発散しないようにする下限
+const MIN_MEAN_LUMINANCE: f32 = 1.0e-6;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FrameSelection {
+    pub max_frames: usize,
+    // 選択済みのフレームとの露出差がこれ未満のフレームは情報が増えないため使わない
+    pub min_ev_step: f64,
+}
+
+impl Default for FrameSelection {
+    fn default() -> Self {
+        Self {
+            max_frames: MAX_MERGE_FRAMES,
+            min_ev_step: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedFrame {
+    pub path: String,
+    // 入力の中で最も暗いフレームを 0 とした相対 EV
+    pub ev: f64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameExposure {
+    pub path: String,
+    // 最も暗いフレームを 0 とした相対 EV
+    pub ev: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub paths: Vec<String>,
+    pub skipped: Vec<SkippedFrame>,
+}
+
+pub fn select_frames(
+    paths: &[String],
+    evs: &[f64],
+    selection: &FrameSelection,
+) -> Result<Selection, String> {
+    if paths.len() < 2 {
+        return Err("合成には最低2枚必要です".to_string());
+    }
+    if paths.len() > MAX_SELECTION_CANDIDATES {
+        return Err(format!(
+            "フレーム選択の候補は最大{}枚までです",
+            MAX_SELECTION_CANDIDATES
+        ));
+    }
+    if !(2..=MAX_MERGE_FRAMES).contains(&selection.max_frames) {
+        return Err(format!(
+            "maxFrames は2〜{}の範囲で指定してください",
+            MAX_MERGE_FRAMES
+        ));
+    }
+    if !selection.min_ev_step.is_finite() || selection.min_ev_step < 0.0 {
+        return Err("minEvStep は0以上にしてください".to_string());
+    }
+
+    let darkest = evs.iter().cloned().fold(f64::INFINITY, f64::min);
+
+    let (chosen, rejected) = choose(evs, selection);
+    Ok(Selection {
+        paths: chosen.iter().map(|&index| paths[index].clone()).collect(),
+        skipped: rejected
+            .into_iter()
+            .map(|(index, reason)| SkippedFrame {
+                path: paths[index].clone(),
+                ev: evs[index] - darkest,
+                reason: reason.to_string(),
+            })
+            .collect(),
+    })
+}
+
+// 暗い順に並べ替える。同じ露出はパス順にして、渡された順序に結果が左右されないようにする
+pub fn sort_by_exposure(
+    paths: &[String],
+    images: Vec<Rgb16Image>,
+) -> (Vec<Rgb16Image>, Vec<FrameExposure>) {
+    let mut frames: Vec<(f64, &String, Rgb16Image)> = images
+        .into_iter()
+        .zip(paths)
+        .map(|(image, path)| (estimate_ev(&image), path, image))
+        .collect();
+    frames.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1)));
+    let darkest = frames.first().map(|frame| frame.0).unwrap_or(0.0);
+
+    let order = frames
+        .iter()
+        .map(|(ev, path, _)| FrameExposure {
+            path: path.to_string(),
+            ev: ev - darkest,
+        })
+        .collect();
+    (
+        frames.into_iter().map(|(_, _, image)| image).collect(),
+        order,
+    )
+}
+
+pub fn estimate_ev(image: &Rgb16Image) -> f64 {
+    let mut sum = 0.0f64;
+    let mut count = 0u64;
+    for y in (0..image.height()).step_by(EV_SAMPLE_STEP as usize) {
+        for x in (0..image.width()).step_by(EV_SAMPLE_STEP as usize) {
+            let pixel = image.get_pixel(x, y).0;
+            let rgb = pixel.map(|value| srgb_to_linear(u16_to_unit(value)));
+            sum += luminance(rgb) as f64;
+            count += 1;
+        }
+    }
+    let mean = (sum / count.max(1) as f64).max(MIN_MEAN_LUMINANCE as f64);
+    mean.log2()
+}
+
+// 最も暗いフレームと明るいフレームを必ず残し、残りは選択済みから EV が最も離れたものを順に加える。
+// 戻り値は入力順に並べた採用フレームと、理由付きの不採用フレーム
+fn choose(evs: &[f64], selection: &FrameSelection) -> (Vec<usize>, Vec<(usize, &'static str)>) {
+    let mut order: Vec<usize> = (0..evs.len()).collect();
+    order.sort_by(|&a, &b| evs[a].total_cmp(&evs[b]).then(a.cmp(&b)));
+    let mut chosen = vec![order[0], order[order.len() - 1]];
+    let mut remaining: Vec<usize> = order[1..order.len() - 1].to_vec();
+    let mut rejected = Vec::new();
+
+    let distance = |index: usize, chosen: &[usize]| {
+        chosen
+            .iter()
+            .map(|&other| (evs[index] - evs[other]).abs())
+            .fold(f64::INFINITY, f64::min)
+    };
+
+    while !remaining.is_empty() {
+        let (position, best) = remaining
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| (position, distance(index, &chosen)))
+            .fold((0, f64::NEG_INFINITY), |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            });
+        if best < selection.min_ev_step || chosen.len() >= selection.max_frames {
+            break;
+        }
+        chosen.push(remaining.remove(position));
+    }
+
+    for index in remaining {
+        let reason = if distance(index, &chosen) < selection.min_ev_step {
+            "露出の近いフレームを採用済み"
+        } else {
+            "最大枚数を超えたため"
+        };
+        rejected.push((index, reason));
+    }
+    chosen.sort();
+    rejected.sort_by_key(|(index, _)| *index);
+    (chosen, rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn keeps_extremes_and_spreads_remaining_frames() {
+        let evs = [0.0, 0.1, 1.0, 2.0, 2.05, 3.0, 4.0];
+        let selection = FrameSelection {
+            max_frames: 4,
+            ..Default::default()
+        };
+
+        let (chosen, rejected) = choose(&evs, &selection);
+
+        assert_eq!(chosen, vec![0, 2, 3, 6]);
+        assert_eq!(
+            rejected,
+            vec![
+                (1, "露出の近いフレームを採用済み"),
+                (4, "露出の近いフレームを採用済み"),
+                (5, "最大枚数を超えたため"),
+            ]
+        );
+    }
+
+    #[test]
+    fn burst_of_identical_exposures_keeps_two_frames() {
+        let evs = [1.0, 1.01, 0.99, 1.0, 1.02, 1.0, 1.0, 0.98, 1.0];
+
+        let (chosen, rejected) = choose(&evs, &FrameSelection::default());
+
+        assert_eq!(chosen, vec![4, 7]);
+        assert!(rejected
+            .iter()
+            .all(|(_, reason)| *reason == "露出の近いフレームを採用済み"));
+    }
+
+    #[test]
+    fn reports_skipped_frames_with_relative_ev() {
+        let paths: Vec<String> = (0..4).map(|index| format!("frame_{}.png", index)).collect();
+        let evs: Vec<f64> = [4000u16, 16000, 16100, 64000]
+            .iter()
+            .map(|&value| estimate_ev(&Rgb16Image::from_pixel(8, 8, Rgb([value; 3]))))
+            .collect();
+
+        let result = select_frames(&paths, &evs, &FrameSelection::default()).unwrap();
+
+        assert_eq!(
+            result.paths,
+            vec!["frame_0.png", "frame_2.png", "frame_3.png"]
+        );
+        assert_eq!(result.skipped.len(), 1);
+        assert_eq!(result.skipped[0].path, "frame_1.png");
+        assert!(result.skipped[0].ev > 0.0);
+    }
+
+    #[test]
+    fn sorts_frames_from_dark_to_bright() {
+        let paths: Vec<String> = ["b.png", "c.png", "a.png"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        let images = [60000u16, 8000, 8000]
+            .iter()
+            .map(|&value| Rgb16Image::from_pixel(4, 4, Rgb([value; 3])))
+            .collect();
+
+        let (images, order) = sort_by_exposure(&paths, images);
+
+        let sorted: Vec<&str> = order.iter().map(|frame| frame.path.as_str()).collect();
+        assert_eq!(sorted, vec!["a.png", "c.png", "b.png"]);
+        assert_eq!(order[0].ev, 0.0);
+        assert!(order[2].ev > 2.0);
+        assert_eq!(images[2].get_pixel(0, 0)[0], 60000);
+    }
+}
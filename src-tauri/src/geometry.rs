@@ -0,0 +1,403 @@
+use image::{ImageBuffer, Pixel, Rgb};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS)]
+pub struct NormalizedPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+// 座標はプレビュー上の位置をそのまま使えるよう画像サイズに対する 0〜1 の比率で受け取る
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct PerspectiveCorners {
+    pub top_left: NormalizedPoint,
+    pub top_right: NormalizedPoint,
+    pub bottom_right: NormalizedPoint,
+    pub bottom_left: NormalizedPoint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub fn crop_roi<P: Pixel + 'static>(
+    image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    roi: &Roi,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>, String> {
+    if roi.width == 0 || roi.height == 0 {
+        return Err("合成範囲のサイズが不正です".to_string());
+    }
+    let fits_x = roi
+        .x
+        .checked_add(roi.width)
+        .is_some_and(|right| right <= image.width());
+    let fits_y = roi
+        .y
+        .checked_add(roi.height)
+        .is_some_and(|bottom| bottom <= image.height());
+    if !fits_x || !fits_y {
+        return Err("合成範囲が画像の外にはみ出しています".to_string());
+    }
+
+    Ok(image::imageops::crop_imm(image, roi.x, roi.y, roi.width, roi.height).to_image())
+}
+
+pub fn apply_perspective(
+    image: &ImageBuffer<Rgb<u16>, Vec<u16>>,
+    corners: &PerspectiveCorners,
+) -> Result<ImageBuffer<Rgb<u16>, Vec<u16>>, String> {
+    let width = image.width() as f64;
+    let height = image.height() as f64;
+
+    let points = [
+        corners.top_left,
+        corners.top_right,
+        corners.bottom_right,
+        corners.bottom_left,
+    ];
+    for point in &points {
+        if !(0.0..=1.0).contains(&point.x) || !(0.0..=1.0).contains(&point.y) {
+            return Err("補正用の頂点が画像範囲外です".to_string());
+        }
+    }
+
+    let src: Vec<(f64, f64)> = points
+        .iter()
+        .map(|point| (point.x * (width - 1.0), point.y * (height - 1.0)))
+        .collect();
+
+    let out_width = distance(src[0], src[1])
+        .max(distance(src[3], src[2]))
+        .round() as u32
+        + 1;
+    let out_height = distance(src[0], src[3])
+        .max(distance(src[1], src[2]))
+        .round() as u32
+        + 1;
+    if out_width < 2 || out_height < 2 {
+        return Err("補正後の画像サイズが小さすぎます".to_string());
+    }
+
+    let dst = [
+        (0.0, 0.0),
+        ((out_width - 1) as f64, 0.0),
+        ((out_width - 1) as f64, (out_height - 1) as f64),
+        (0.0, (out_height - 1) as f64),
+    ];
+    let homography = solve_homography(&dst, &src).ok_or("補正用の頂点から変換を計算できません")?;
+
+    let mut output = ImageBuffer::<Rgb<u16>, Vec<u16>>::new(out_width, out_height);
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let (sx, sy) = project(&homography, x as f64, y as f64);
+        *pixel = sample_bilinear(image, sx, sy);
+    }
+
+    Ok(output)
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+fn project(h: &[f64; 9], x: f64, y: f64) -> (f64, f64) {
+    let w = h[6] * x + h[7] * y + h[8];
+    (
+        (h[0] * x + h[1] * y + h[2]) / w,
+        (h[3] * x + h[4] * y + h[5]) / w,
+    )
+}
+
+// from の4点を to の4点へ写す射影変換行列（h[8] = 1）を求める
+fn solve_homography(from: &[(f64, f64); 4], to: &[(f64, f64)]) -> Option<[f64; 9]> {
+    let mut a = [[0.0f64; 9]; 8];
+    for i in 0..4 {
+        let (x, y) = from[i];
+        let (u, v) = to[i];
+        a[i * 2] = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u];
+        a[i * 2 + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v];
+    }
+
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|&l, &r| a[l][col].abs().total_cmp(&a[r][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+
+        let pivot_row = a[col];
+        for (row, values) in a.iter_mut().enumerate() {
+            if row == col {
+                continue;
+            }
+            let factor = values[col] / pivot_row[col];
+            for (value, pivot_value) in values.iter_mut().zip(pivot_row.iter()).skip(col) {
+                *value -= factor * pivot_value;
+            }
+        }
+    }
+
+    let mut h = [0.0f64; 9];
+    for i in 0..8 {
+        h[i] = a[i][8] / a[i][i];
+    }
+    h[8] = 1.0;
+    Some(h)
+}
+
+pub fn sample_bilinear(image: &ImageBuffer<Rgb<u16>, Vec<u16>>, x: f64, y: f64) -> Rgb<u16> {
+    let max_x = (image.width() - 1) as f64;
+    let max_y = (image.height() - 1) as f64;
+    let x = x.clamp(0.0, max_x);
+    let y = y.clamp(0.0, max_y);
+
+    let x0 = x.floor() as u32;
+    let y0 = y.floor() as u32;
+    let x1 = (x0 + 1).min(image.width() - 1);
+    let y1 = (y0 + 1).min(image.height() - 1);
+    let fx = x - x0 as f64;
+    let fy = y - y0 as f64;
+
+    let p00 = image.get_pixel(x0, y0);
+    let p10 = image.get_pixel(x1, y0);
+    let p01 = image.get_pixel(x0, y1);
+    let p11 = image.get_pixel(x1, y1);
+
+    let mut out = [0u16; 3];
+    for (c, value) in out.iter_mut().enumerate() {
+        let top = p00[c] as f64 * (1.0 - fx) + p10[c] as f64 * fx;
+        let bottom = p01[c] as f64 * (1.0 - fx) + p11[c] as f64 * fx;
+        *value = (top * (1.0 - fy) + bottom * fy).round() as u16;
+    }
+    Rgb(out)
+}
+
+const STRAIGHTEN_MAX_DEGREES: f64 = 5.0;
+const STRAIGHTEN_BIN_DEGREES: f64 = 0.05;
+const STRAIGHTEN_ANALYSIS_SIZE: u32 = 1024;
+
+// 水平・垂直に近いエッジの傾きを勾配方向のヒストグラムから推定する（度、反時計回りを正とする画像座標系）
+pub fn estimate_straighten_angle(image: &ImageBuffer<Rgb<u16>, Vec<u16>>) -> Option<f64> {
+    let step = image
+        .width()
+        .max(image.height())
+        .div_ceil(STRAIGHTEN_ANALYSIS_SIZE)
+        .max(1);
+    let width = (image.width() / step) as usize;
+    let height = (image.height() / step) as usize;
+    if width < 8 || height < 8 {
+        return None;
+    }
+
+    let mut luma = vec![0.0f64; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let mut total = 0.0;
+            for dy in 0..step {
+                for dx in 0..step {
+                    let p = image.get_pixel(x as u32 * step + dx, y as u32 * step + dy);
+                    total += 0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64;
+                }
+            }
+            luma[y * width + x] = total / (step * step) as f64 / u16::MAX as f64;
+        }
+    }
+
+    let bins = (STRAIGHTEN_MAX_DEGREES * 2.0 / STRAIGHTEN_BIN_DEGREES).round() as usize + 1;
+    let mut histogram = vec![0.0f64; bins];
+    let mut total_weight = 0.0;
+    let at = |x: usize, y: usize| luma[y * width + x];
+
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x - 1, y) + at(x - 1, y + 1));
+            let gy = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x, y - 1) + at(x + 1, y - 1));
+            let magnitude = (gx * gx + gy * gy).sqrt();
+            if magnitude < 0.1 {
+                continue;
+            }
+
+            let angle = gy.atan2(gx).to_degrees();
+            let deviation = (angle + 45.0).rem_euclid(90.0) - 45.0;
+            if deviation.abs() > STRAIGHTEN_MAX_DEGREES {
+                continue;
+            }
+
+            let bin =
+                ((deviation + STRAIGHTEN_MAX_DEGREES) / STRAIGHTEN_BIN_DEGREES).round() as usize;
+            histogram[bin.min(bins - 1)] += magnitude;
+            total_weight += magnitude;
+        }
+    }
+
+    if total_weight <= 0.0 {
+        return None;
+    }
+
+    let smoothed: Vec<f64> = (0..bins)
+        .map(|i| {
+            let from = i.saturating_sub(2);
+            let to = (i + 2).min(bins - 1);
+            histogram[from..=to].iter().sum::<f64>()
+        })
+        .collect();
+    let (peak, peak_weight) = smoothed
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+
+    // 明確な直線が見つからない場合は回転しない
+    if *peak_weight < total_weight * 0.05 {
+        return None;
+    }
+
+    let angle = peak as f64 * STRAIGHTEN_BIN_DEGREES - STRAIGHTEN_MAX_DEGREES;
+    if angle.abs() < STRAIGHTEN_BIN_DEGREES {
+        return None;
+    }
+    Some(angle)
+}
+
+// degrees 分だけ逆回転し、余白が出ないよう元と同じ縦横比の最大矩形で切り抜く
+pub fn rotate_and_crop(
+    image: &ImageBuffer<Rgb<u16>, Vec<u16>>,
+    degrees: f64,
+) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+    let width = image.width() as f64;
+    let height = image.height() as f64;
+    let radians = degrees.to_radians();
+    let (sin, cos) = radians.sin_cos();
+
+    let scale = (width / (width * cos + height * sin.abs()))
+        .min(height / (width * sin.abs() + height * cos));
+    let out_width = ((width * scale).floor() as u32).max(1);
+    let out_height = ((height * scale).floor() as u32).max(1);
+
+    let src_cx = (width - 1.0) / 2.0;
+    let src_cy = (height - 1.0) / 2.0;
+    let dst_cx = (out_width - 1) as f64 / 2.0;
+    let dst_cy = (out_height - 1) as f64 / 2.0;
+
+    let mut output = ImageBuffer::<Rgb<u16>, Vec<u16>>::new(out_width, out_height);
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let dx = x as f64 - dst_cx;
+        let dy = y as f64 - dst_cy;
+        let sx = src_cx + dx * cos - dy * sin;
+        let sy = src_cy + dx * sin + dy * cos;
+        *pixel = sample_bilinear(image, sx, sy);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+        ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([x as u16 * 1000, y as u16 * 1000, 500])
+        })
+    }
+
+    fn point(x: f64, y: f64) -> NormalizedPoint {
+        NormalizedPoint { x, y }
+    }
+
+    #[test]
+    fn crops_inside_the_image_only() {
+        let image = gradient(10, 8);
+        let roi = Roi {
+            x: 2,
+            y: 1,
+            width: 4,
+            height: 3,
+        };
+
+        let cropped = crop_roi(&image, &roi).unwrap();
+
+        assert_eq!(cropped.dimensions(), (4, 3));
+        assert_eq!(cropped.get_pixel(0, 0), image.get_pixel(2, 1));
+        assert_eq!(cropped.get_pixel(3, 2), image.get_pixel(5, 3));
+        for roi in [
+            Roi { width: 0, ..roi },
+            Roi { x: 7, ..roi },
+            Roi { y: 6, ..roi },
+            Roi { x: u32::MAX, ..roi },
+        ] {
+            assert!(crop_roi(&image, &roi).is_err(), "{:?}", roi);
+        }
+    }
+
+    #[test]
+    fn perspective_maps_the_corners_to_the_output_corners() {
+        let image = gradient(21, 11);
+        // 右上だけ内側に寄った台形
+        let corners = PerspectiveCorners {
+            top_left: point(0.0, 0.0),
+            top_right: point(0.75, 0.2),
+            bottom_right: point(1.0, 1.0),
+            bottom_left: point(0.0, 1.0),
+        };
+
+        let corrected = apply_perspective(&image, &corners).unwrap();
+
+        let (width, height) = corrected.dimensions();
+        assert_eq!(*corrected.get_pixel(0, 0), *image.get_pixel(0, 0));
+        assert_eq!(*corrected.get_pixel(width - 1, 0), *image.get_pixel(15, 2));
+        assert_eq!(
+            *corrected.get_pixel(width - 1, height - 1),
+            *image.get_pixel(20, 10)
+        );
+        assert_eq!(*corrected.get_pixel(0, height - 1), *image.get_pixel(0, 10));
+    }
+
+    #[test]
+    fn full_frame_perspective_keeps_every_pixel() {
+        let image = gradient(21, 11);
+        let corners = PerspectiveCorners {
+            top_left: point(0.0, 0.0),
+            top_right: point(1.0, 0.0),
+            bottom_right: point(1.0, 1.0),
+            bottom_left: point(0.0, 1.0),
+        };
+
+        let corrected = apply_perspective(&image, &corners).unwrap();
+
+        // 頂点間の距離は画素数より 1 少ないため、1 を足さないと端の列・行が落ちる
+        assert_eq!(corrected.dimensions(), image.dimensions());
+        assert_eq!(corrected, image);
+    }
+
+    #[test]
+    fn rejects_corners_outside_or_collapsed() {
+        let image = gradient(16, 16);
+        let mut corners = PerspectiveCorners {
+            top_left: point(0.0, 0.0),
+            top_right: point(1.0, 0.0),
+            bottom_right: point(1.0, 1.0),
+            bottom_left: point(0.0, 1.2),
+        };
+        assert!(apply_perspective(&image, &corners).is_err());
+
+        corners.bottom_left = point(0.0, 0.0);
+        corners.bottom_right = point(0.0, 0.0);
+        corners.top_right = point(0.0, 0.0);
+        assert!(apply_perspective(&image, &corners).is_err());
+    }
+
+    #[test]
+    fn samples_between_pixels_and_clamps_at_the_edges() {
+        let image = gradient(4, 4);
+
+        assert_eq!(sample_bilinear(&image, 1.5, 2.0), Rgb([1500, 2000, 500]));
+        assert_eq!(sample_bilinear(&image, -3.0, 9.0), *image.get_pixel(0, 3));
+    }
+}
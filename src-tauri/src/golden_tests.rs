@@ -0,0 +1,212 @@
+// 固定フィクスチャに対する合成結果を tests/golden の期待画像と許容誤差付きで比較する。
+// アルゴリズムを意図的に変更した場合は VHDR_UPDATE_GOLDEN=1 cargo test golden で期待画像を更新する。
+use std::path::{Path, PathBuf};
+
+use crate::align::AlignParams;
+use crate::deghost::DeghostParams;
+use crate::filters::{DenoiseParams, ResizeParams};
+use crate::geometry::{NormalizedPoint, PerspectiveCorners, Roi};
+use crate::merge::{self, MergeRequest, Rgb16Image};
+use crate::pipeline::PipelineStage;
+use crate::synthetic::{self, TestBracketOptions};
+use crate::tonemap::TonemapParams;
+
+struct GoldenCase {
+    name: &'static str,
+    fixture: &'static str,
+    configure: fn(&mut MergeRequest),
+    max_abs_error: f64,
+    max_mean_error: f64,
+}
+
+const CASES: &[GoldenCase] = &[
+    GoldenCase {
+        name: "average",
+        fixture: "basic",
+        configure: |_| {},
+        max_abs_error: 1e-3,
+        max_mean_error: 1e-4,
+    },
+    GoldenCase {
+        name: "average_roi",
+        fixture: "basic",
+        configure: |request| {
+            request.roi = Some(Roi {
+                x: 10,
+                y: 8,
+                width: 32,
+                height: 24,
+            });
+        },
+        max_abs_error: 1e-3,
+        max_mean_error: 1e-4,
+    },
+    GoldenCase {
+        name: "average_perspective",
+        fixture: "basic",
+        configure: |request| {
+            request.perspective = Some(PerspectiveCorners {
+                top_left: NormalizedPoint { x: 0.1, y: 0.05 },
+                top_right: NormalizedPoint { x: 0.9, y: 0.0 },
+                bottom_right: NormalizedPoint { x: 1.0, y: 1.0 },
+                bottom_left: NormalizedPoint { x: 0.0, y: 0.95 },
+            });
+        },
+        max_abs_error: 2e-3,
+        max_mean_error: 2e-4,
+    },
+    GoldenCase {
+        name: "fusion",
+        fixture: "basic",
+        configure: |request| request.algorithm = Some("fusion".to_string()),
+        max_abs_error: 2e-3,
+        max_mean_error: 2e-4,
+    },
+    GoldenCase {
+        name: "average_straighten",
+        fixture: "basic",
+        configure: |request| request.auto_straighten = true,
+        max_abs_error: 2e-3,
+        max_mean_error: 2e-4,
+    },
+    GoldenCase {
+        name: "tonemap_reinhard",
+        fixture: "basic",
+        configure: |request| {
+            request.pipeline = Some(vec![
+                PipelineStage::Merge,
+                PipelineStage::Tonemap(TonemapParams {
+                    exposure: 1.0,
+                    white_point: 2.0,
+                    ..Default::default()
+                }),
+                PipelineStage::Encode(Default::default()),
+            ]);
+        },
+        max_abs_error: 2e-3,
+        max_mean_error: 2e-4,
+    },
+    GoldenCase {
+        name: "pipeline_full",
+        fixture: "basic",
+        configure: |request| {
+            request.algorithm = Some("fusion".to_string());
+            request.pipeline = Some(vec![
+                PipelineStage::Align(AlignParams {
+                    max_shift: 8,
+                    ..Default::default()
+                }),
+                PipelineStage::Deghost(DeghostParams::default()),
+                PipelineStage::Merge,
+                PipelineStage::Geometry,
+                PipelineStage::Denoise(DenoiseParams::default()),
+                PipelineStage::Tonemap(TonemapParams::default()),
+                PipelineStage::Resize(ResizeParams { max_size: 32 }),
+                PipelineStage::Encode(Default::default()),
+            ]);
+        },
+        max_abs_error: 2e-3,
+        max_mean_error: 2e-4,
+    },
+];
+
+fn fixture_options(fixture: &str) -> TestBracketOptions {
+    match fixture {
+        "basic" => TestBracketOptions {
+            frames: 3,
+            ev_spacing: 2.0,
+            width: 64,
+            height: 48,
+            noise: 0.01,
+            motion: 1.0,
+            seed: 380,
+        },
+        _ => panic!("unknown fixture: {}", fixture),
+    }
+}
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn update_requested() -> bool {
+    std::env::var("VHDR_UPDATE_GOLDEN").is_ok_and(|value| value == "1")
+}
+
+fn fixture_paths(fixture: &str) -> Vec<String> {
+    let dir = tests_dir().join("fixtures").join(fixture);
+    if update_requested() && !dir.exists() {
+        synthetic::generate_bracket(&dir, &fixture_options(fixture)).unwrap();
+    }
+
+    let mut paths: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|_| panic!("fixture not found: {}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn compare_to_golden(case: &GoldenCase, actual: &Rgb16Image) {
+    let golden_path = tests_dir()
+        .join("golden")
+        .join(format!("{}.png", case.name));
+    if update_requested() {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        merge::save_png(actual, &golden_path).unwrap();
+        return;
+    }
+
+    let expected = merge::load_rgb16(&golden_path.to_string_lossy())
+        .unwrap_or_else(|_| panic!("golden not found: {}", golden_path.display()));
+    assert_eq!(
+        actual.dimensions(),
+        expected.dimensions(),
+        "{}: size changed",
+        case.name
+    );
+
+    let mut max_error = 0.0f64;
+    let mut total_error = 0.0f64;
+    for (a, e) in actual.pixels().zip(expected.pixels()) {
+        for c in 0..3 {
+            let error = (a[c] as f64 - e[c] as f64).abs() / u16::MAX as f64;
+            max_error = max_error.max(error);
+            total_error += error;
+        }
+    }
+    let mean_error = total_error / (actual.width() as f64 * actual.height() as f64 * 3.0);
+
+    assert!(
+        max_error <= case.max_abs_error,
+        "{}: max error {:.6} exceeds {:.6}",
+        case.name,
+        max_error,
+        case.max_abs_error
+    );
+    assert!(
+        mean_error <= case.max_mean_error,
+        "{}: mean error {:.6} exceeds {:.6}",
+        case.name,
+        mean_error,
+        case.max_mean_error
+    );
+}
+
+#[test]
+fn golden_outputs_match() {
+    for case in CASES {
+        let paths = fixture_paths(case.fixture);
+        let mut request = MergeRequest {
+            paths: paths.clone(),
+            ..Default::default()
+        };
+        (case.configure)(&mut request);
+
+        let images = merge::load_inputs(&paths).unwrap();
+        let merged = merge::process(&images, &request).unwrap();
+        compare_to_golden(case, &merged.image);
+    }
+}
@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{linear_to_srgb, luminance, srgb_to_linear, u16_to_unit, unit_to_u16};
+use crate::merge::Rgb16Image;
+
+// 白飛び・黒つぶれとみなす値（sRGB の 0〜1）
+const USABLE: std::ops::RangeInclusive<f32> = 0.02..=0.98;
+// 領域のうち使える画素がこの割合未満なら測定しない
+const MIN_USABLE_FRACTION: f64 = 0.5;
+// これを超える補正は写っているものがグレーカードではないとみなす
+const MAX_GAIN: f32 = 16.0;
+
+// 座標はプレビュー上の位置をそのまま使えるよう画像サイズに対する 0〜1 の比率で受け取る
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct CardRegion {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct GrayCardSettings {
+    // グレーカード（ColorChecker のグレーのパッチ）が写っている範囲
+    pub region: CardRegion,
+    // 同じ照明で撮ったグレーカードの画像。未指定なら合成結果の region を測る
+    pub path: Option<String>,
+    // 補正後のグレーの線形輝度。未指定なら 18% グレー
+    pub target: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct GrayCardCorrection {
+    // 領域の線形 RGB の平均
+    pub measured: [f32; 3],
+    // 線形 RGB にかけた倍率。比が白バランス、輝度の比が露出の補正にあたる
+    pub gains: [f32; 3],
+    // 補正の前後の EV 差
+    pub exposure_ev: f64,
+}
+
+impl GrayCardSettings {
+    fn target(&self) -> f32 {
+        self.target.unwrap_or(0.18)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let region = &self.region;
+        let inside = |start: f64, size: f64| start >= 0.0 && size > 0.0 && start + size <= 1.0;
+        if !(inside(region.x, region.width) && inside(region.y, region.height)) {
+            return Err("グレーカードの範囲は画像内の 0〜1 の比率で指定してください".to_string());
+        }
+        if !(0.01..=1.0).contains(&self.target()) {
+            return Err("グレーカードの target は 0.01〜1 で指定してください".to_string());
+        }
+        Ok(())
+    }
+}
+
+// 領域の平均から、グレーを無彩色の target にする倍率を求める
+pub fn measure(
+    image: &Rgb16Image,
+    settings: &GrayCardSettings,
+) -> Result<GrayCardCorrection, String> {
+    let (width, height) = image.dimensions();
+    let region = &settings.region;
+    let to_pixels = |start: f64, size: f64, side: u32| {
+        let begin = ((start * side as f64).floor() as u32).min(side - 1);
+        let end = (((start + size) * side as f64).ceil() as u32).clamp(begin + 1, side);
+        begin..end
+    };
+    let columns = to_pixels(region.x, region.width, width);
+    let rows = to_pixels(region.y, region.height, height);
+
+    let mut sum = [0.0f64; 3];
+    let mut usable = 0u64;
+    for y in rows.clone() {
+        for x in columns.clone() {
+            let pixel = image.get_pixel(x, y).0.map(u16_to_unit);
+            if pixel.iter().all(|v| USABLE.contains(v)) {
+                for (total, v) in sum.iter_mut().zip(pixel) {
+                    *total += srgb_to_linear(v) as f64;
+                }
+                usable += 1;
+            }
+        }
+    }
+    let count = (rows.len() * columns.len()) as f64;
+    if (usable as f64) < count * MIN_USABLE_FRACTION {
+        return Err("グレーカードの範囲が白飛びまたは黒つぶれしています".to_string());
+    }
+
+    let measured = sum.map(|total| (total / usable as f64) as f32);
+    let target = settings.target();
+    let gains = measured.map(|mean| target / mean);
+    if gains
+        .iter()
+        .any(|gain| !(1.0 / MAX_GAIN..=MAX_GAIN).contains(gain))
+    {
+        return Err("グレーカードの範囲の色が偏りすぎています".to_string());
+    }
+    Ok(GrayCardCorrection {
+        measured,
+        gains,
+        exposure_ev: (target as f64 / luminance(measured) as f64).log2(),
+    })
+}
+
+pub fn apply(image: &Rgb16Image, correction: &GrayCardCorrection) -> Rgb16Image {
+    let mut corrected = image.clone();
+    for pixel in corrected.pixels_mut() {
+        for (value, gain) in pixel.0.iter_mut().zip(correction.gains) {
+            let linear = srgb_to_linear(u16_to_unit(*value)) * gain;
+            *value = unit_to_u16(linear_to_srgb(linear.min(1.0)));
+        }
+    }
+    corrected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn settings() -> GrayCardSettings {
+        GrayCardSettings {
+            region: CardRegion {
+                x: 0.25,
+                y: 0.25,
+                width: 0.5,
+                height: 0.5,
+            },
+            path: None,
+            target: None,
+        }
+    }
+
+    fn encode(linear: [f32; 3]) -> Rgb<u16> {
+        Rgb(linear.map(|v| unit_to_u16(linear_to_srgb(v))))
+    }
+
+    #[test]
+    fn neutralizes_and_normalizes_the_card() {
+        // 暗めで青みのある照明で撮ったグレーカード
+        let card = encode([0.06, 0.08, 0.12]);
+        let image = Rgb16Image::from_fn(40, 40, |x, y| {
+            if (10..30).contains(&x) && (10..30).contains(&y) {
+                card
+            } else {
+                encode([0.9, 0.9, 0.9])
+            }
+        });
+
+        let correction = measure(&image, &settings()).unwrap();
+        let corrected = apply(&image, &correction);
+
+        assert!(correction.exposure_ev > 1.0);
+        let patch = corrected
+            .get_pixel(20, 20)
+            .0
+            .map(|v| srgb_to_linear(u16_to_unit(v)));
+        assert!(
+            patch.iter().all(|v| (v - 0.18).abs() < 0.005),
+            "{:?}",
+            patch
+        );
+    }
+
+    #[test]
+    fn rejects_clipped_or_invalid_regions() {
+        let clipped = Rgb16Image::from_pixel(20, 20, Rgb([u16::MAX; 3]));
+        assert!(measure(&clipped, &settings()).is_err());
+
+        let mut outside = settings();
+        outside.region.x = 0.8;
+        assert!(outside.validate().is_err());
+        assert!(settings().validate().is_ok());
+    }
+}
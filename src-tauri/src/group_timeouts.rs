@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+use chrono::Local;
+
+use crate::grouping::{self, BracketGroup, GroupInput, GroupStatus, GroupingRules};
+
+#[derive(Default)]
+struct State {
+    // 最後に group_images で受け取った入力と規則
+    inputs: Vec<GroupInput>,
+    rules: Option<GroupingRules>,
+    // hdr://bracket-timeout を送ったグループの id と、そのファイル
+    notified: HashMap<String, Vec<String>>,
+    running: bool,
+}
+
+// 枚数待ちのグループの時間切れを、フロントエンドの問い合わせを待たずにバックエンドで判定する
+#[derive(Default)]
+pub struct GroupTimeouts {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl GroupTimeouts {
+    // 判定に使う入力を差し替える。判定のスレッドがまだなければ true を返す
+    pub fn track(&self, inputs: &[GroupInput], rules: &GroupingRules) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.inputs = inputs.to_vec();
+        state.rules = Some(rules.clone());
+        self.changed.notify_all();
+        !std::mem::replace(&mut state.running, true)
+    }
+
+    // 合成・削除したグループのファイルは判定から外し、通知済みの記録も消す
+    pub fn forget(&self, paths: &[String]) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.inputs.retain(|input| !paths.contains(&input.path));
+        state
+            .notified
+            .retain(|_, files| !files.iter().any(|file| paths.contains(file)));
+        self.changed.notify_all();
+    }
+
+    // now_ms の時点のグループと、初めて時間切れになったグループ。
+    // 入力から消えたグループの通知済みの記録は捨てる
+    pub fn evaluate(&self, now_ms: i64) -> Result<(Vec<BracketGroup>, Vec<BracketGroup>), String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(rules) = state.rules.clone() else {
+            return Ok((Vec::new(), Vec::new()));
+        };
+        let mut groups = grouping::group_images(&state.inputs, &rules)?;
+        grouping::apply_timeout(&mut groups, &rules, now_ms);
+        state
+            .notified
+            .retain(|id, _| groups.iter().any(|group| &group.id == id));
+        let mut timed_out = Vec::new();
+        for group in &groups {
+            if group.status == GroupStatus::TimedOut && !state.notified.contains_key(&group.id) {
+                state.notified.insert(group.id.clone(), group.paths.clone());
+                timed_out.push(group.clone());
+            }
+        }
+        Ok((groups, timed_out))
+    }
+
+    // 専用のスレッドで呼ぶ。次に時間切れになる時刻まで眠り、時間切れになったグループごとに emit を呼ぶ。
+    // 枚数待ちのグループがない間は、入力が変わるまで何もしない
+    pub fn run(&self, emit: impl Fn(&BracketGroup)) {
+        loop {
+            let now_ms = Local::now().timestamp_millis();
+            let next = match self.evaluate(now_ms) {
+                Ok((groups, timed_out)) => {
+                    for group in &timed_out {
+                        emit(group);
+                    }
+                    let rules = self
+                        .state
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .rules
+                        .clone();
+                    rules.and_then(|rules| grouping::next_timeout(&groups, &rules))
+                }
+                Err(_) => None,
+            };
+            let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let wait = next.map(|at| Duration::from_millis((at - now_ms).max(1) as u64));
+            // 待っている間に入力が変われば起きて判定し直す
+            let _state = match wait {
+                Some(wait) => {
+                    self.changed
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(files: &[(&str, i64)]) -> Vec<GroupInput> {
+        files
+            .iter()
+            .map(|(path, at)| GroupInput {
+                path: path.to_string(),
+                detected_at: Some(*at),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn notifies_each_bracket_once_and_forgets_merged_ones() {
+        let rules = GroupingRules {
+            frames_per_bracket: Some(3),
+            incomplete_timeout_secs: Some(30.0),
+            ..Default::default()
+        };
+        let timeouts = GroupTimeouts::default();
+        assert!(timeouts.track(&inputs(&[("a/IMG_0001.JPG", 0)]), &rules));
+        assert!(!timeouts.track(&inputs(&[("a/IMG_0001.JPG", 0)]), &rules));
+
+        assert!(timeouts.evaluate(10_000).unwrap().1.is_empty());
+        let (_, timed_out) = timeouts.evaluate(40_000).unwrap();
+        assert_eq!(timed_out.len(), 1);
+        assert!(timeouts.evaluate(50_000).unwrap().1.is_empty());
+
+        // 次のブラケットは同じ key になるが、id が違うので改めて通知する
+        let (first, _) = timeouts.evaluate(50_000).unwrap();
+        timeouts.track(&inputs(&[("b/IMG_0001.JPG", 300_000)]), &rules);
+        let (groups, timed_out) = timeouts.evaluate(400_000).unwrap();
+        assert_eq!(groups[0].key, first[0].key);
+        assert_eq!(timed_out.len(), 1);
+        assert_eq!(timed_out[0].paths, vec!["b/IMG_0001.JPG".to_string()]);
+
+        // 合成したグループは判定から外れ、同じファイルがまた届いたら改めて通知する
+        timeouts.forget(&["b/IMG_0001.JPG".to_string()]);
+        assert!(timeouts.evaluate(400_000).unwrap().0.is_empty());
+        timeouts.track(&inputs(&[("b/IMG_0001.JPG", 500_000)]), &rules);
+        assert_eq!(timeouts.evaluate(600_000).unwrap().1.len(), 1);
+    }
+}
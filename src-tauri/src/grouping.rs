@@ -0,0 +1,531 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::capabilities::MAX_MERGE_FRAMES;
+use crate::exif;
+use crate::merge::MergeMode;
+use crate::noise_stack::{self, MAX_HYBRID_FRAMES};
+
+// 連続撮影を1つのブラケットにまとめる規則。
+// pattern / sequence のどちらかで分けたうえで、maxGapSecs を超えて間が空いたら別グループにする
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GroupingRules {
+    pub max_gap_secs: Option<f64>,
+    // 名前付きキャプチャ base（ブラケットの共通部分）と任意の index（並び順）を持つ正規表現。
+    // 例: `^(?P<base>DSC_\d+)_(?P<index>\d+)\.`
+    pub pattern: Option<String>,
+    // ファイル名末尾の連番を、この枚数ずつ区切ってブラケットとみなす
+    pub frames_per_bracket: Option<usize>,
+    pub max_images: usize,
+    // 1ブラケットの枚数。未指定なら framesPerBracket を使い、どちらもなければ枚数を確かめない
+    pub expected_frames: Option<usize>,
+    // 枚数が足りないまま、最後の検出からこの秒数が過ぎたら incompletePolicy に従う
+    pub incomplete_timeout_secs: Option<f64>,
+    pub incomplete_policy: IncompletePolicy,
+    // 各露出で続けて撮る枚数。2 以上なら maxImages・framesPerBracket・expectedFrames を露出の段数として扱い、
+    // グループを mergeMode: hybrid（露出ごとにスタックしてから HDR 合成）にする。
+    // 未指定でも、EXIF の露出が同じフレームが段ごとに 2 枚以上あるグループは hybrid にする
+    pub frames_per_exposure: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum IncompletePolicy {
+    // 届いた分だけで合成する
+    MergePartial,
+    Discard,
+    // 手動で確認するまで合成しない
+    #[default]
+    Hold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum GroupStatus {
+    Complete,
+    // 残りのフレームを待っている
+    Waiting,
+    TimedOut,
+}
+
+impl Default for GroupingRules {
+    fn default() -> Self {
+        Self {
+            max_gap_secs: Some(120.0),
+            pattern: None,
+            frames_per_bracket: None,
+            max_images: MAX_MERGE_FRAMES,
+            expected_frames: None,
+            frames_per_exposure: None,
+            incomplete_timeout_secs: None,
+            incomplete_policy: IncompletePolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupInput {
+    pub path: String,
+    // 検出時刻（UNIX ミリ秒）。未指定ならファイルの更新日時を使う
+    pub detected_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct BracketGroup {
+    // 撮影ごとに変わらない名前の部分から作るため、別の日の同じ名前のブラケットでも同じになる
+    pub key: String,
+    // key と最初のファイル。1 つのファイルは 1 つのグループにしか入らないので、別のブラケットと重ならない
+    pub id: String,
+    pub paths: Vec<String>,
+    pub first_detected_at: i64,
+    pub last_detected_at: i64,
+    pub status: GroupStatus,
+    pub expected_frames: Option<usize>,
+    // 時間切れになったグループに適用する方針
+    pub timeout_action: Option<IncompletePolicy>,
+    // 合成するときに渡す mergeMode
+    pub merge_mode: MergeMode,
+}
+
+struct Item {
+    path: String,
+    time: i64,
+    order: i64,
+}
+
+pub fn group_images(
+    inputs: &[GroupInput],
+    rules: &GroupingRules,
+) -> Result<Vec<BracketGroup>, String> {
+    if rules.max_images == 0 {
+        return Err("maxImages は1以上にしてください".to_string());
+    }
+    if rules.expected_frames == Some(0) {
+        return Err("expectedFrames は1以上にしてください".to_string());
+    }
+    if rules.frames_per_exposure == Some(0) {
+        return Err("framesPerExposure は1以上にしてください".to_string());
+    }
+    let repeats = rules.frames_per_exposure.unwrap_or(1);
+    let merge_mode = if repeats > 1 {
+        MergeMode::Hybrid
+    } else {
+        MergeMode::Bracket
+    };
+    let frames_per_bracket = rules.frames_per_bracket.map(|frames| frames * repeats);
+    if rules
+        .incomplete_timeout_secs
+        .is_some_and(|secs| !(secs.is_finite() && secs > 0.0))
+    {
+        return Err("incompleteTimeoutSecs は 0 より大きい値にしてください".to_string());
+    }
+    let pattern = match &rules.pattern {
+        Some(pattern) => Some(
+            Regex::new(pattern).map_err(|e| format!("グループ分けの正規表現が不正です: {}", e))?,
+        ),
+        None => None,
+    };
+    if pattern
+        .as_ref()
+        .is_some_and(|regex| !regex.capture_names().any(|name| name == Some("base")))
+    {
+        return Err("グループ分けの正規表現には (?P<base>...) が必要です".to_string());
+    }
+
+    // 規則で決まるキーごとに分け、規則に合わないファイルは時刻だけでまとめる
+    let mut buckets: BTreeMap<String, Vec<Item>> = BTreeMap::new();
+    for input in inputs {
+        let time = input
+            .detected_at
+            .unwrap_or_else(|| modified_millis(&input.path));
+        let name = file_name(&input.path);
+        let (key, order) = match (&pattern, frames_per_bracket) {
+            (Some(regex), _) => match regex.captures(&name) {
+                Some(captures) => {
+                    let order = captures
+                        .name("index")
+                        .and_then(|m| m.as_str().parse().ok())
+                        .unwrap_or(time);
+                    (format!("pattern:{}", &captures["base"]), order)
+                }
+                None => (String::new(), time),
+            },
+            (None, Some(frames)) if frames > 0 => match trailing_number(&name) {
+                Some((prefix, number)) => (
+                    format!(
+                        "sequence:{}{}",
+                        prefix,
+                        (number - 1).div_euclid(frames as i64)
+                    ),
+                    number,
+                ),
+                None => (String::new(), time),
+            },
+            _ => (String::new(), time),
+        };
+        buckets.entry(key).or_default().push(Item {
+            path: input.path.clone(),
+            time,
+            order,
+        });
+    }
+
+    let mut groups = Vec::new();
+    for (key, mut items) in buckets {
+        items.sort_by_key(|item| (item.order, item.time));
+        groups.extend(split_bucket(&key, items, rules, rules.max_images * repeats));
+    }
+    groups.sort_by_key(|group| group.first_detected_at);
+    let expected = rules
+        .expected_frames
+        .map(|frames| frames * repeats)
+        .or(frames_per_bracket);
+    for group in &mut groups {
+        group.id = format!("{}:{}", group.key, group.paths[0]);
+        group.expected_frames = expected;
+        group.merge_mode = match rules.frames_per_exposure {
+            Some(_) => merge_mode,
+            None => detect_merge_mode(&group.paths),
+        };
+        if expected.is_some_and(|expected| group.paths.len() < expected) {
+            group.status = GroupStatus::Waiting;
+        }
+    }
+    Ok(groups)
+}
+
+// EXIF の露出で段に分け、2〜MAX_MERGE_FRAMES 段のどの段にも 2 枚以上あれば hybrid。
+// 露出が読めないフレームがあれば判断せず bracket のままにする
+pub fn detect_merge_mode(paths: &[String]) -> MergeMode {
+    if paths.len() < 4 || paths.len() > MAX_HYBRID_FRAMES {
+        return MergeMode::Bracket;
+    }
+    let evs: Option<Vec<f64>> = paths
+        .iter()
+        .map(|path| exif::read_exposure(Path::new(path)).and_then(|info| info.ev()))
+        .collect();
+    let Some(evs) = evs else {
+        return MergeMode::Bracket;
+    };
+    let levels = noise_stack::exposure_levels(&evs);
+    if (2..=MAX_MERGE_FRAMES).contains(&levels.len()) && levels.iter().all(|level| level.len() >= 2)
+    {
+        MergeMode::Hybrid
+    } else {
+        MergeMode::Bracket
+    }
+}
+
+// 枚数が足りないグループのうち、最後の検出から incompleteTimeoutSecs が過ぎたものを時間切れにする
+pub fn apply_timeout(groups: &mut [BracketGroup], rules: &GroupingRules, now_ms: i64) {
+    let Some(timeout_secs) = rules.incomplete_timeout_secs else {
+        return;
+    };
+    let timeout_ms = (timeout_secs * 1000.0) as i64;
+    for group in groups {
+        if group.status == GroupStatus::Waiting && now_ms - group.last_detected_at > timeout_ms {
+            group.status = GroupStatus::TimedOut;
+            group.timeout_action = Some(rules.incomplete_policy);
+        }
+    }
+}
+
+// 枚数待ちのグループが次に時間切れになる時刻（UNIX ミリ秒）
+pub fn next_timeout(groups: &[BracketGroup], rules: &GroupingRules) -> Option<i64> {
+    let timeout_ms = (rules.incomplete_timeout_secs? * 1000.0) as i64;
+    groups
+        .iter()
+        .filter(|group| group.status == GroupStatus::Waiting)
+        // apply_timeout は過ぎてから時間切れにする
+        .map(|group| group.last_detected_at + timeout_ms + 1)
+        .min()
+}
+
+// 時刻差と最大枚数でさらに分割する。規則で分けたグループは時刻順ではなく index / 連番順を保つ
+fn split_bucket(
+    key: &str,
+    items: Vec<Item>,
+    rules: &GroupingRules,
+    max_images: usize,
+) -> Vec<BracketGroup> {
+    let max_gap_ms = rules.max_gap_secs.map(|secs| (secs * 1000.0) as i64);
+    let mut groups: Vec<BracketGroup> = Vec::new();
+    let mut last_time: Option<i64> = None;
+
+    for item in items {
+        let gap_exceeded = match (max_gap_ms, last_time) {
+            (Some(max_gap), Some(last)) => (item.time - last).abs() > max_gap,
+            _ => false,
+        };
+        let start_new = match groups.last() {
+            Some(group) => gap_exceeded || group.paths.len() >= max_images,
+            None => true,
+        };
+        if start_new {
+            let suffix = groups.len();
+            groups.push(BracketGroup {
+                key: if key.is_empty() {
+                    format!("time:{}", item.time)
+                } else if suffix == 0 {
+                    key.to_string()
+                } else {
+                    format!("{}#{}", key, suffix)
+                },
+                id: String::new(),
+                paths: Vec::new(),
+                first_detected_at: item.time,
+                last_detected_at: item.time,
+                status: GroupStatus::Complete,
+                expected_frames: None,
+                timeout_action: None,
+                merge_mode: MergeMode::Bracket,
+            });
+        }
+        let group = groups.last_mut().expect("group was just pushed");
+        group.first_detected_at = group.first_detected_at.min(item.time);
+        group.last_detected_at = group.last_detected_at.max(item.time);
+        group.paths.push(item.path);
+        last_time = Some(item.time);
+    }
+    groups
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+// "IMG_0012.JPG" → ("IMG_", 12)
+fn trailing_number(name: &str) -> Option<(String, i64)> {
+    let stem = Path::new(name).file_stem()?.to_str()?;
+    let digits_start = stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let number = stem[digits_start..].parse().ok()?;
+    Some((stem[..digits_start].to_string(), number))
+}
+
+fn modified_millis(path: &str) -> i64 {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(files: &[(&str, i64)]) -> Vec<GroupInput> {
+        files
+            .iter()
+            .map(|(path, time)| GroupInput {
+                path: path.to_string(),
+                detected_at: Some(*time),
+            })
+            .collect()
+    }
+
+    fn paths(groups: &[BracketGroup]) -> Vec<Vec<&str>> {
+        groups
+            .iter()
+            .map(|group| group.paths.iter().map(String::as_str).collect())
+            .collect()
+    }
+
+    #[test]
+    fn groups_by_time_gap_and_max_images() {
+        let files = inputs(&[
+            ("a.jpg", 0),
+            ("b.jpg", 1_000),
+            ("c.jpg", 300_000),
+            ("d.jpg", 301_000),
+            ("e.jpg", 302_000),
+        ]);
+        let rules = GroupingRules {
+            max_images: 2,
+            ..Default::default()
+        };
+
+        let groups = group_images(&files, &rules).unwrap();
+
+        assert_eq!(
+            paths(&groups),
+            vec![
+                vec!["a.jpg", "b.jpg"],
+                vec!["c.jpg", "d.jpg"],
+                vec!["e.jpg"]
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_by_filename_pattern_in_index_order() {
+        // 書き込み順が入れ替わっても index の順に並べる
+        let files = inputs(&[
+            ("DSC_0001_1.jpg", 0),
+            ("DSC_0001_0.jpg", 10),
+            ("DSC_0002_0.jpg", 20),
+            ("DSC_0001_2.jpg", 30),
+            ("DSC_0002_1.jpg", 40),
+        ]);
+        let rules = GroupingRules {
+            pattern: Some(r"^(?P<base>DSC_\d+)_(?P<index>\d+)\.".to_string()),
+            ..Default::default()
+        };
+
+        let groups = group_images(&files, &rules).unwrap();
+
+        assert_eq!(
+            paths(&groups),
+            vec![
+                vec!["DSC_0001_0.jpg", "DSC_0001_1.jpg", "DSC_0001_2.jpg"],
+                vec!["DSC_0002_0.jpg", "DSC_0002_1.jpg"],
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_by_sequence_number() {
+        let files = inputs(&[
+            ("IMG_0001.JPG", 0),
+            ("IMG_0002.JPG", 0),
+            ("IMG_0003.JPG", 0),
+            ("IMG_0004.JPG", 0),
+            ("IMG_0005.JPG", 0),
+            ("IMG_0006.JPG", 0),
+        ]);
+        let rules = GroupingRules {
+            frames_per_bracket: Some(3),
+            ..Default::default()
+        };
+
+        let groups = group_images(&files, &rules).unwrap();
+
+        assert_eq!(
+            paths(&groups),
+            vec![
+                vec!["IMG_0001.JPG", "IMG_0002.JPG", "IMG_0003.JPG"],
+                vec!["IMG_0004.JPG", "IMG_0005.JPG", "IMG_0006.JPG"],
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_exposures_make_hybrid_groups() {
+        let files: Vec<(String, i64)> = (1..=7).map(|i| (format!("IMG_{:04}.JPG", i), 0)).collect();
+        let files: Vec<(&str, i64)> = files
+            .iter()
+            .map(|(name, time)| (name.as_str(), *time))
+            .collect();
+        let rules = GroupingRules {
+            frames_per_bracket: Some(3),
+            frames_per_exposure: Some(2),
+            ..Default::default()
+        };
+
+        let groups = group_images(&inputs(&files), &rules).unwrap();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].paths.len(), 6);
+        assert_eq!(groups[0].merge_mode, MergeMode::Hybrid);
+        assert_eq!(groups[0].expected_frames, Some(6));
+        assert_eq!(groups[1].status, GroupStatus::Waiting);
+    }
+
+    #[test]
+    fn detects_repeated_exposures_from_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, denominator: u32| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, exif::exif_tiff((1, denominator), (80, 10), 100)).unwrap();
+            (path.to_string_lossy().to_string(), 0)
+        };
+        // 3 段を 2 枚ずつ
+        let files: Vec<(String, i64)> = [500, 500, 125, 125, 30, 30]
+            .iter()
+            .enumerate()
+            .map(|(i, &denominator)| write(&format!("IMG_{:04}.TIF", i + 1), denominator))
+            .collect();
+        let files: Vec<(&str, i64)> = files
+            .iter()
+            .map(|(name, time)| (name.as_str(), *time))
+            .collect();
+        let rules = GroupingRules {
+            max_images: 6,
+            ..Default::default()
+        };
+
+        let groups = group_images(&inputs(&files), &rules).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].merge_mode, MergeMode::Hybrid);
+
+        // 1 枚しかない段があれば、ふつうのブラケット
+        let bracket: Vec<String> = [500, 500, 125, 30]
+            .iter()
+            .enumerate()
+            .map(|(i, &denominator)| write(&format!("B_{}.TIF", i), denominator).0)
+            .collect();
+        assert_eq!(detect_merge_mode(&bracket), MergeMode::Bracket);
+        // EXIF のないファイルは判断しない
+        assert_eq!(
+            detect_merge_mode(&["a.png", "b.png", "c.png", "d.png"].map(String::from)),
+            MergeMode::Bracket
+        );
+    }
+
+    #[test]
+    fn short_brackets_time_out_with_policy() {
+        let files = inputs(&[
+            ("IMG_0001.JPG", 0),
+            ("IMG_0002.JPG", 1_000),
+            ("IMG_0003.JPG", 2_000),
+            ("IMG_0004.JPG", 60_000),
+        ]);
+        let rules = GroupingRules {
+            frames_per_bracket: Some(3),
+            incomplete_timeout_secs: Some(30.0),
+            incomplete_policy: IncompletePolicy::MergePartial,
+            ..Default::default()
+        };
+
+        let mut groups = group_images(&files, &rules).unwrap();
+        apply_timeout(&mut groups, &rules, 80_000);
+        assert_eq!(groups[0].status, GroupStatus::Complete);
+        assert_eq!(groups[1].status, GroupStatus::Waiting);
+        assert_eq!(next_timeout(&groups, &rules), Some(90_001));
+        assert_eq!(
+            groups[1].id,
+            format!("{}:{}", groups[1].key, groups[1].paths[0])
+        );
+
+        apply_timeout(&mut groups, &rules, 100_000);
+        assert_eq!(groups[1].status, GroupStatus::TimedOut);
+        assert_eq!(
+            groups[1].timeout_action,
+            Some(IncompletePolicy::MergePartial)
+        );
+        assert_eq!(groups[0].timeout_action, None);
+        assert_eq!(next_timeout(&groups, &rules), None);
+    }
+
+    #[test]
+    fn rejects_pattern_without_base_capture() {
+        let rules = GroupingRules {
+            pattern: Some(r"DSC_(\d+)".to_string()),
+            ..Default::default()
+        };
+
+        assert!(group_images(&inputs(&[("DSC_1.jpg", 0)]), &rules).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::detection_log::DetectionKind;
+use crate::embedded_thumbnail::{read_at, Tiff};
+
+// 書き込み途中のファイルを確かめ直す間隔
+pub const RECHECK_INTERVAL: Duration = Duration::from_millis(250);
+// 最後に変化してからこれだけ待っても読めないファイルは壊れているとみなして諦める
+const GIVE_UP_AFTER: Duration = Duration::from_secs(120);
+
+// テザー撮影のツールは数秒かけて少しずつ書き込むため、ヘッダーと終端が揃っているかを安く確かめる。
+// 開けないファイルと形式が分からないファイルは判断できないので、従来どおり書き込み済みとして扱う
+pub fn is_complete(path: &Path) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return true;
+    };
+    let Ok(length) = file.metadata().map(|metadata| metadata.len()) else {
+        return true;
+    };
+    if length == 0 {
+        return false;
+    }
+    let Some(head) = read_at(&mut file, 0, length.min(16) as u32) else {
+        return false;
+    };
+    let tail = |file: &mut File, size: u64| {
+        read_at(file, length - size.min(length), size.min(length) as u32)
+    };
+
+    if head.starts_with(&[0xFF, 0xD8]) {
+        // EOI の後ろに 0 を詰めるカメラがある
+        return tail(&mut file, 1024).is_some_and(|bytes| {
+            let end = bytes
+                .iter()
+                .rposition(|&byte| byte != 0)
+                .map_or(0, |i| i + 1);
+            bytes[..end].ends_with(&[0xFF, 0xD9])
+        });
+    }
+    if head.starts_with(b"\x89PNG") {
+        // 最後の IEND チャンク（長さ・種類・CRC の 12 バイト）まで書かれていれば書き込み済み
+        return length >= 12 && tail(&mut file, 12).is_some_and(|bytes| &bytes[4..8] == b"IEND");
+    }
+    if head.starts_with(b"RIFF") && head.len() >= 12 && &head[8..12] == b"WEBP" {
+        let size = u32::from_le_bytes([head[4], head[5], head[6], head[7]]) as u64;
+        return length >= size + 8;
+    }
+    if head.starts_with(b"FUJIFILMCCD-RAW") {
+        // JPEG と CFA それぞれの位置・長さ
+        return read_at(&mut file, 84, 24).is_some_and(|header| {
+            let field = |at: usize| {
+                u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+                    as u64
+            };
+            [(0, 4), (8, 12), (16, 20)]
+                .iter()
+                .all(|&(offset, size)| field(offset) + field(size) <= length)
+        });
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return boxes_complete(&mut file, length);
+    }
+    match Tiff::open(&mut file, 0) {
+        Some(tiff) => tiff_complete(&tiff, &mut file, length),
+        None => true,
+    }
+}
+
+// CR3・HEIC などの ISO BMFF は、最上位のボックスがちょうどファイルの終わりまで並んでいれば書き込み済み
+fn boxes_complete(file: &mut File, length: u64) -> bool {
+    let mut offset = 0u64;
+    while offset < length {
+        let Some(header) = read_at(file, offset, 16.min(length - offset) as u32) else {
+            return false;
+        };
+        if header.len() < 8 {
+            return false;
+        }
+        let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // 0 は最後まで続くボックス
+            0 => return true,
+            1 if header.len() == 16 => {
+                u64::from_be_bytes(header[8..16].try_into().unwrap_or_default())
+            }
+            1 => return false,
+            size => size as u64,
+        };
+        if size < 8 {
+            return false;
+        }
+        let Some(next) = offset.checked_add(size) else {
+            return false;
+        };
+        offset = next;
+    }
+    offset == length
+}
+
+// すべての IFD が読め、ストリップ・タイル・埋め込み JPEG がファイルの中に収まっていれば書き込み済み
+fn tiff_complete(tiff: &Tiff, file: &mut File, length: u64) -> bool {
+    let Some(ifds) = tiff.ifds_strict(file) else {
+        return false;
+    };
+    for entries in &ifds {
+        for (offsets, sizes) in [(0x0111, 0x0117), (0x0144, 0x0145), (0x0201, 0x0202)] {
+            let find = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+            let (Some(offsets), Some(sizes)) = (find(offsets), find(sizes)) else {
+                continue;
+            };
+            let (Some(offsets), Some(sizes)) =
+                (tiff.values(file, offsets), tiff.values(file, sizes))
+            else {
+                return false;
+            };
+            if offsets
+                .iter()
+                .zip(&sizes)
+                .any(|(&offset, &size)| offset as u64 + size as u64 > length)
+            {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+struct Pending {
+    kind: DetectionKind,
+    // 検出まで待つ、大きさと更新日時が変わらない時間
+    stable: Duration,
+    snapshot: Option<(u64, Option<SystemTime>)>,
+    changed_at: Instant,
+}
+
+fn snapshot(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+// 書き込み途中か安定待ちのファイル。監視の判定を、読めて変化が止まるまで先送りする
+#[derive(Default)]
+pub struct GrowingFiles {
+    pending: Mutex<HashMap<PathBuf, Pending>>,
+}
+
+impl GrowingFiles {
+    pub fn defer(&self, path: &Path, kind: DetectionKind, stable: Duration) {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        pending.entry(path.to_path_buf()).or_insert(Pending {
+            kind,
+            stable,
+            snapshot: snapshot(path),
+            changed_at: Instant::now(),
+        });
+    }
+
+    pub fn is_pending(&self, path: &Path) -> bool {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    // 書き込みが終わったファイルを取り出す。消えたファイルと待ちすぎたファイルは捨てる
+    pub fn take_ready(&self, now: Instant) -> Vec<(PathBuf, DetectionKind)> {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut ready = Vec::new();
+        pending.retain(|path, entry| {
+            let Some(current) = snapshot(path) else {
+                return false;
+            };
+            if entry.snapshot != Some(current) {
+                entry.snapshot = Some(current);
+                entry.changed_at = now;
+            }
+            let unchanged = now.saturating_duration_since(entry.changed_at);
+            if unchanged >= entry.stable && is_complete(path) {
+                ready.push((path.clone(), entry.kind));
+                return false;
+            }
+            unchanged <= GIVE_UP_AFTER.max(entry.stable)
+        });
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn detects_files_that_are_still_being_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = RgbImage::from_pixel(64, 48, Rgb([10, 20, 30]));
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg).encode_image(&image).unwrap();
+        let tiff_path = dir.path().join("shot.tif");
+        image.save(&tiff_path).unwrap();
+        let tiff = std::fs::read(&tiff_path).unwrap();
+        let png_path = dir.path().join("shot.png");
+        image.save(&png_path).unwrap();
+        let png = std::fs::read(&png_path).unwrap();
+
+        for (name, bytes) in [("shot.jpg", &jpeg), ("shot.tif", &tiff), ("shot.png", &png)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+            assert!(!is_complete(&path), "{}", name);
+            std::fs::write(&path, bytes).unwrap();
+            assert!(is_complete(&path), "{}", name);
+        }
+        // シグネチャの途中までしか書かれていない PNG
+        let short = dir.path().join("short.png");
+        std::fs::write(&short, b"\x89PNG\r").unwrap();
+        assert!(!is_complete(&short));
+        // 64bit の大きさで桁あふれするボックス
+        let boxes = dir.path().join("huge.cr3");
+        let mut bmff = b"\0\0\0\x10ftypcrx \0\0\0\x01".to_vec();
+        bmff.extend(b"\0\0\0\x01mdat");
+        bmff.extend(u64::MAX.to_be_bytes());
+        std::fs::write(&boxes, &bmff).unwrap();
+        assert!(!is_complete(&boxes));
+        let empty = dir.path().join("empty.cr3");
+        std::fs::write(&empty, []).unwrap();
+        assert!(!is_complete(&empty));
+        assert!(is_complete(&dir.path().join("missing.jpg")));
+    }
+
+    #[test]
+    fn releases_deferred_files_once_they_are_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shot.png");
+        std::fs::write(&path, b"\x89PNG\r\n\x1a\n").unwrap();
+        let growing = GrowingFiles::default();
+        growing.defer(&path, DetectionKind::Create, Duration::ZERO);
+        growing.defer(
+            &dir.path().join("gone.png"),
+            DetectionKind::Create,
+            Duration::ZERO,
+        );
+
+        assert!(growing.take_ready(Instant::now()).is_empty());
+        assert!(growing.is_pending(&path));
+        assert!(!growing.is_empty());
+        RgbImage::new(4, 4).save(&path).unwrap();
+        assert_eq!(
+            growing.take_ready(Instant::now()),
+            vec![(path.clone(), DetectionKind::Create)]
+        );
+        assert!(!growing.is_pending(&path));
+
+        // 安定待ちは、読めても大きさが変わらない時間が過ぎるまで出さない
+        let stable = Duration::from_secs(2);
+        growing.defer(&path, DetectionKind::Modify, stable);
+        assert!(growing.take_ready(Instant::now()).is_empty());
+        assert_eq!(
+            growing.take_ready(Instant::now() + stable),
+            vec![(path.clone(), DetectionKind::Modify)]
+        );
+    }
+}
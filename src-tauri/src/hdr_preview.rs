@@ -0,0 +1,263 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{luminance, u16_to_unit, unit_to_u16};
+use crate::dng;
+use crate::merge::Rgb16Image;
+use crate::paths;
+use crate::probe::{self, LinearImage};
+
+// cICP チャンク: BT.2020 の原色・PQ（SMPTE ST 2084）・RGB・フルレンジ
+const CICP_BT2020_PQ: [u8; 4] = [9, 16, 0, 1];
+const PQ_MAX_NITS: f32 = 10000.0;
+
+// BT.709 のリニア RGB を BT.2020 に変換する行列（ITU-R BT.2087）
+const BT709_TO_BT2020: [[f32; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HdrPreviewParams {
+    // 長辺の最大画素数
+    pub max_size: u32,
+    // 放射輝度 1（基準フレームで白飛びする明るさ）を表示する明るさ
+    pub sdr_white_nits: f32,
+    // 最も明るいハイライトを表示する明るさの上限
+    pub peak_nits: f32,
+}
+
+impl Default for HdrPreviewParams {
+    fn default() -> Self {
+        Self {
+            max_size: 2048,
+            sdr_white_nits: 203.0,
+            peak_nits: 1000.0,
+        }
+    }
+}
+
+impl HdrPreviewParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(16..=8192).contains(&self.max_size) {
+            return Err("maxSize は 16〜8192 で指定してください".to_string());
+        }
+        if !(self.sdr_white_nits > 0.0 && self.sdr_white_nits <= self.peak_nits) {
+            return Err("sdrWhiteNits は 0 より大きく peakNits 以下にしてください".to_string());
+        }
+        if self.peak_nits > PQ_MAX_NITS {
+            return Err(format!("peakNits は {} 以下にしてください", PQ_MAX_NITS));
+        }
+        Ok(())
+    }
+}
+
+// 放射輝度（1 が SDR の白）を表示する明るさに割り当てる。画像の最大値 max が peak_nits に収まらないときだけ、
+// 暗部の傾きを保ったままハイライトを丸めて max をちょうど peak_nits にする
+fn compress_nits(radiance: f32, max: f32, params: &HdrPreviewParams) -> f32 {
+    let headroom = params.peak_nits / params.sdr_white_nits;
+    let shoulder = (1.0 / headroom - 1.0 / max.max(f32::MIN_POSITIVE)).max(0.0);
+    params.sdr_white_nits * radiance / (1.0 + radiance * shoulder)
+}
+
+// トーンマップ前の線形の値を読むファイル。DNG は write_dng で書いた放射輝度、EXR は f32 のまま使う。
+// それ以外（合成結果の PNG など）は、隣に同じ名前で書き出した DNG を使う
+pub fn linear_source(path: &str) -> Result<String, String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    if matches!(extension.as_deref(), Some("exr" | "dng")) {
+        return Ok(path.to_string());
+    }
+    paths::input_file(path)?;
+    let dng_path = Path::new(path)
+        .with_extension("dng")
+        .to_string_lossy()
+        .to_string();
+    match paths::input_file(&dng_path) {
+        Ok(_) => Ok(dng_path),
+        Err(_) => Err(format!(
+            "{} にはトーンマップ前の値がありません。outputDng で書き出した DNG か、EXR を指定してください",
+            path
+        )),
+    }
+}
+
+// linear_source で選んだファイルを線形の BT.709 として読む
+pub fn load(source: &str) -> Result<LinearImage, String> {
+    let is_exr = Path::new(source)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+    if is_exr {
+        return probe::load_linear(source);
+    }
+    load_radiance(&paths::input_file(source)?)
+}
+
+fn load_radiance(path: &Path) -> Result<LinearImage, String> {
+    let radiance = dng::read_radiance(path)?;
+    let restore = (radiance.baseline_exposure as f32).exp2();
+    let image = radiance.image;
+    Ok(LinearImage::from_fn(
+        image.width(),
+        image.height(),
+        |x, y| Rgb(image.get_pixel(x, y).0.map(|v| u16_to_unit(v) * restore)),
+    ))
+}
+
+pub fn pq_encode(nits: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+    let y = (nits / PQ_MAX_NITS).clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+// 線形 BT.709 の放射輝度を BT.2020・PQ の 16bit 画像に変換する。色比を保つよう輝度で明るさを決めて RGB に掛ける
+pub fn render(image: &LinearImage, params: &HdrPreviewParams) -> Rgb16Image {
+    let (width, height) = image.dimensions();
+    let resized;
+    let scaled = if width.max(height) > params.max_size {
+        let scale = params.max_size as f64 / width.max(height) as f64;
+        let target = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+        resized = imageops::resize(image, target(width), target(height), FilterType::Triangle);
+        &resized
+    } else {
+        image
+    };
+
+    let luma = |pixel: &Rgb<f32>| luminance(pixel.0.map(|v| v.max(0.0)));
+    let max = scaled.pixels().map(luma).fold(0.0f32, f32::max);
+    Rgb16Image::from_fn(scaled.width(), scaled.height(), |x, y| {
+        let pixel = scaled.get_pixel(x, y);
+        let linear = pixel.0.map(|v| v.max(0.0));
+        let luma = luma(pixel);
+        let gain = if luma > 0.0 {
+            compress_nits(luma, max, params) / luma
+        } else {
+            params.sdr_white_nits
+        };
+        let bt2020 = BT709_TO_BT2020.map(|row| {
+            row.iter()
+                .zip(linear)
+                .map(|(weight, value)| weight * value)
+                .sum::<f32>()
+        });
+        Rgb(bt2020.map(|value| unit_to_u16(pq_encode(value * gain))))
+    })
+}
+
+// PNG の cICP チャンクで PQ を指定すると、HDR 表示に対応した WebView はそのまま HDR で表示する
+pub fn write_pq_png(image: &Rgb16Image, path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_chunk(png::chunk::ChunkType(*b"cICP"), &CICP_BT2020_PQ)
+        .map_err(|e| e.to_string())?;
+    let bytes: Vec<u8> = image
+        .as_raw()
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect();
+    writer.write_image_data(&bytes).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pq_and_expansion_hit_reference_points() {
+        assert!(pq_encode(0.0).abs() < 1e-6);
+        assert!((pq_encode(100.0) - 0.5081).abs() < 1e-3);
+        assert!((pq_encode(10000.0) - 1.0).abs() < 1e-6);
+
+        let params = HdrPreviewParams::default();
+        // 最大値が peakNits に収まれば、放射輝度に比例した明るさのまま
+        assert!((compress_nits(1.0, 4.0, &params) - 203.0).abs() < 1e-2);
+        assert!((compress_nits(4.0, 4.0, &params) - 812.0).abs() < 1e-1);
+        // 収まらないときは最大値がちょうど peakNits になり、暗部はほぼ変わらない
+        assert!((compress_nits(16.0, 16.0, &params) - 1000.0).abs() < 1e-1);
+        assert!((compress_nits(0.01, 16.0, &params) - 2.03).abs() < 0.02);
+        assert!(HdrPreviewParams {
+            sdr_white_nits: 2000.0,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn writes_downscaled_png_tagged_as_pq() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preview.png");
+        let image = LinearImage::from_fn(300, 100, |x, _| Rgb([x as f32 / 30.0; 3]));
+        let params = HdrPreviewParams {
+            max_size: 150,
+            ..Default::default()
+        };
+
+        write_pq_png(&render(&image, &params), &path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let cicp = bytes.windows(4).position(|w| w == b"cICP").unwrap();
+        assert_eq!(bytes[cicp + 4..cicp + 8], CICP_BT2020_PQ);
+        assert!(cicp < bytes.windows(4).position(|w| w == b"IDAT").unwrap());
+        assert_eq!(image::image_dimensions(&path).unwrap(), (150, 50));
+    }
+
+    #[test]
+    fn keeps_highlights_above_sdr_white_from_the_radiance_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let png_path = dir.path().join("merged.png");
+        let dng_path = dir.path().join("merged.dng");
+        // 最も明るい値を白に縮め、BaselineExposure 2 で 4 倍に戻す放射輝度
+        let radiance = Rgb16Image::from_fn(4, 1, |x, _| Rgb([unit_to_u16(x as f32 / 3.0); 3]));
+        std::fs::write(&png_path, b"").unwrap();
+        dng::write_dng(
+            &radiance,
+            2.0,
+            &dng_path,
+            &Default::default(),
+            &crate::progress::ProgressReporter::default(),
+        )
+        .unwrap();
+
+        // PNG を指定しても隣の DNG から読む
+        let source = linear_source(&png_path.to_string_lossy()).unwrap();
+        assert_eq!(source, dng_path.to_string_lossy());
+        let linear = load(&source).unwrap();
+        assert!((linear.get_pixel(3, 0).0[0] - 4.0).abs() < 1e-3);
+        let preview = render(&linear, &HdrPreviewParams::default());
+
+        // 放射輝度 4 は SDR の白の 4 倍の明るさで表示され、逆トーンマップのように白の値で頭打ちにならない
+        let nits = |x: u32| {
+            let pq = u16_to_unit(preview.get_pixel(x, 0).0[1]);
+            (1..=10000)
+                .map(|nits| nits as f32)
+                .find(|nits| pq_encode(*nits) >= pq)
+                .unwrap()
+        };
+        assert!((nits(3) - 812.0).abs() < 10.0, "{}", nits(3));
+        assert!((nits(1) - 203.0 * 4.0 / 3.0).abs() < 5.0, "{}", nits(1));
+
+        std::fs::remove_file(&dng_path).unwrap();
+        assert!(linear_source(&png_path.to_string_lossy()).is_err());
+    }
+}
@@ -0,0 +1,519 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::algorithms::AlgorithmParams;
+use crate::merge::{MergeRequest, MergeResult};
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub merged_at: String,
+    pub input_paths: Vec<String>,
+    pub output_png_path: String,
+    pub output_exr_path: Option<String>,
+    pub algorithm: String,
+    #[serde(default)]
+    pub algorithm_params: AlgorithmParams,
+    #[serde(default)]
+    pub stages: Vec<String>,
+    // 古い履歴には所要時間がない
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    pub width: u32,
+    pub height: u32,
+    // 0〜5 の星の数（0 は未評価）
+    #[serde(default)]
+    pub rating: u8,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // 合成に使ったプリセット
+    #[serde(default)]
+    pub preset: Option<String>,
+    // 合成後にプリセットが編集され、出力が今のプリセットと合っていない
+    #[serde(default)]
+    pub stale: bool,
+    // 合成し直すときに使う元の合成要求（出力・ROI・deliverables・色空間など）。古い履歴にはない
+    #[serde(default)]
+    pub request: Option<MergeRequest>,
+    // 合成で書き出したすべてのファイル（DNG・プレビュー・白飛びマップ・deliverables なども含む）。古い履歴にはない
+    #[serde(default)]
+    pub output_paths: Vec<String>,
+}
+
+impl HistoryEntry {
+    // 合成し直すときに置き換える出力。古い履歴は PNG と EXR しか分からない
+    pub fn written_paths(&self) -> Vec<String> {
+        if !self.output_paths.is_empty() {
+            return self.output_paths.clone();
+        }
+        std::iter::once(&self.output_png_path)
+            .chain(&self.output_exr_path)
+            .cloned()
+            .collect()
+    }
+}
+
+// history_reprocess_stale の結果。失敗した履歴は stale のまま残る
+#[derive(Debug, Clone, Default, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct StaleReprocess {
+    pub updated: Vec<HistoryEntry>,
+    pub failed: Vec<ReprocessFailure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ReprocessFailure {
+    pub id: u64,
+    pub message: String,
+}
+
+pub const MAX_RATING: u8 = 5;
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryFilter {
+    pub min_rating: Option<u8>,
+    // 指定したタグをすべて持つ履歴だけを返す
+    pub tags: Vec<String>,
+    // 入力・出力のファイル名に含まれる文字列（大文字小文字は区別しない）
+    pub query: Option<String>,
+    // RFC 3339 の日時か YYYY-MM-DD。日付だけの場合 to はその日の終わりまでを含む
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage {
+    pub entries: Vec<HistoryEntry>,
+    // 条件に合う件数（ページングする前）
+    pub total: usize,
+    pub offset: usize,
+}
+
+impl HistoryFilter {
+    // 条件に合う履歴を記録順のまま返す
+    pub fn apply<'a>(&self, entries: &'a [HistoryEntry]) -> Result<Vec<&'a HistoryEntry>, String> {
+        let from = self
+            .from
+            .as_deref()
+            .map(|text| parse_bound(text, false))
+            .transpose()?;
+        let to = self
+            .to
+            .as_deref()
+            .map(|text| parse_bound(text, true))
+            .transpose()?;
+        let query = self
+            .query
+            .as_deref()
+            .map(str::trim)
+            .filter(|query| !query.is_empty())
+            .map(str::to_lowercase);
+
+        Ok(entries
+            .iter()
+            .filter(|entry| {
+                self.min_rating.is_none_or(|min| entry.rating >= min)
+                    && self.tags.iter().all(|tag| entry.tags.contains(tag))
+                    && query
+                        .as_ref()
+                        .is_none_or(|query| mentions_file(entry, query))
+                    && within(entry, from, to)
+            })
+            .collect())
+    }
+}
+
+// 新しい順に並べて offset から limit 件を返す
+pub fn search(
+    entries: &[HistoryEntry],
+    filter: &HistoryFilter,
+    offset: usize,
+    limit: Option<usize>,
+) -> Result<HistoryPage, String> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let matched = filter.apply(entries)?;
+    Ok(HistoryPage {
+        total: matched.len(),
+        entries: matched
+            .into_iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect(),
+        offset,
+    })
+}
+
+fn mentions_file(entry: &HistoryEntry, query: &str) -> bool {
+    entry
+        .input_paths
+        .iter()
+        .chain(std::iter::once(&entry.output_png_path))
+        .chain(entry.output_exr_path.iter())
+        .any(|path| {
+            Path::new(path)
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().to_lowercase().contains(query))
+        })
+}
+
+fn within(
+    entry: &HistoryEntry,
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+) -> bool {
+    if from.is_none() && to.is_none() {
+        return true;
+    }
+    match DateTime::parse_from_rfc3339(&entry.merged_at) {
+        Ok(merged_at) => {
+            from.is_none_or(|from| merged_at >= from) && to.is_none_or(|to| merged_at <= to)
+        }
+        Err(_) => false,
+    }
+}
+
+fn parse_bound(text: &str, end_of_day: bool) -> Result<DateTime<FixedOffset>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time);
+    }
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map_err(|_| format!("日付の形式が不正です: {}", text))?;
+    let start = date.and_hms_opt(0, 0, 0).expect("midnight is valid");
+    let time = if end_of_day {
+        start + Duration::days(1) - Duration::milliseconds(1)
+    } else {
+        start
+    };
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|time| time.fixed_offset())
+        .ok_or_else(|| format!("日付の形式が不正です: {}", text))
+}
+
+// 合成履歴。プロジェクトごと（未選択時は全体で1つ）に JSON ファイルへ保存する
+pub struct HistoryStore {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+}
+
+impl HistoryStore {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn record(
+        &mut self,
+        request: &MergeRequest,
+        result: &MergeResult,
+    ) -> Result<HistoryEntry, String> {
+        let id = self.entries.iter().map(|entry| entry.id).max().unwrap_or(0) + 1;
+        let entry = HistoryEntry {
+            id,
+            merged_at: result.merged_at.clone(),
+            input_paths: request.paths.clone(),
+            output_png_path: result.output_png_path.clone(),
+            output_exr_path: result.output_exr_path.clone(),
+            algorithm: result.algorithm.clone(),
+            algorithm_params: request.algorithm_params.clone(),
+            stages: result.stages.clone(),
+            duration_ms: Some(result.duration_ms),
+            width: result.width,
+            height: result.height,
+            rating: 0,
+            tags: Vec::new(),
+            preset: request.preset.clone(),
+            stale: false,
+            request: Some(request.clone()),
+            output_paths: result.output_paths(),
+        };
+        self.entries.push(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    // プリセットを使った履歴を更新が必要な状態にし、その件数を返す
+    pub fn mark_stale(&mut self, preset: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for entry in &mut self.entries {
+            if entry.preset.as_deref() == Some(preset) && !entry.stale {
+                entry.stale = true;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.save()?;
+        }
+        Ok(count)
+    }
+
+    pub fn stale_entries(&self, preset: &str) -> Vec<HistoryEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.stale && entry.preset.as_deref() == Some(preset))
+            .cloned()
+            .collect()
+    }
+
+    // 合成し直した結果で出力と設定を置き換える。評価・タグは残す
+    pub fn replace_outputs(
+        &mut self,
+        id: u64,
+        request: &MergeRequest,
+        result: &MergeResult,
+    ) -> Result<HistoryEntry, String> {
+        self.update(id, |entry| {
+            entry.merged_at = result.merged_at.clone();
+            entry.output_png_path = result.output_png_path.clone();
+            entry.output_exr_path = result.output_exr_path.clone();
+            entry.algorithm = result.algorithm.clone();
+            entry.algorithm_params = request.algorithm_params.clone();
+            entry.stages = result.stages.clone();
+            entry.duration_ms = Some(result.duration_ms);
+            entry.width = result.width;
+            entry.height = result.height;
+            entry.stale = false;
+            entry.request = Some(request.clone());
+            entry.output_paths = result.output_paths();
+        })
+    }
+
+    pub fn set_rating(&mut self, id: u64, stars: u8) -> Result<HistoryEntry, String> {
+        if stars > MAX_RATING {
+            return Err(format!("評価は0〜{}で指定してください", MAX_RATING));
+        }
+        self.update(id, |entry| entry.rating = stars)
+    }
+
+    // 前後の空白を除き、空のタグと重複は取り除く
+    pub fn set_tags(&mut self, id: u64, tags: Vec<String>) -> Result<HistoryEntry, String> {
+        let mut normalized: Vec<String> = Vec::new();
+        for tag in tags {
+            let tag = tag.trim().to_string();
+            if !tag.is_empty() && !normalized.contains(&tag) {
+                normalized.push(tag);
+            }
+        }
+        self.update(id, |entry| entry.tags = normalized)
+    }
+
+    fn update(
+        &mut self,
+        id: u64,
+        apply: impl FnOnce(&mut HistoryEntry),
+    ) -> Result<HistoryEntry, String> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| format!("履歴が見つかりません: {}", id))?;
+        apply(entry);
+        let updated = entry.clone();
+        self.save()?;
+        Ok(updated)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let text = serde_json::to_string_pretty(&self.entries).map_err(|e| e.to_string())?;
+        fs::write(&self.path, text).map_err(|e| format!("履歴を保存できません: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge_result() -> MergeResult {
+        MergeResult {
+            output_png_path: String::new(),
+            output_exr_path: None,
+            output_layered_exr_path: None,
+            transforms_path: None,
+            output_short_reference_path: None,
+            output_preview_path: None,
+            output_dng_path: None,
+            sent_to: Vec::new(),
+            width: 4,
+            height: 4,
+            merged_at: Local::now().to_rfc3339(),
+            algorithm: "average".to_string(),
+            stages: Vec::new(),
+            alignment_offsets: Vec::new(),
+            alignment_transforms: Vec::new(),
+            straighten_angle: None,
+            skipped_frames: Vec::new(),
+            exposure_order: Vec::new(),
+            excluded_inputs: Vec::new(),
+            clipping: None,
+            duration_ms: 0,
+            memory_fallback: None,
+            deliverables: Vec::new(),
+            frame_quality: Vec::new(),
+            gray_card: None,
+            auto_levels: None,
+            exposure_compensation: Vec::new(),
+        }
+    }
+
+    fn store_with_entries(dir: &std::path::Path, count: usize) -> HistoryStore {
+        let mut store = HistoryStore::load(dir.join("history.json"));
+        for index in 0..count {
+            let result = MergeResult {
+                output_png_path: format!("hdr_{}.png", index),
+                ..merge_result()
+            };
+            store.record(&MergeRequest::default(), &result).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn rating_and_tags_persist_and_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = store_with_entries(dir.path(), 3);
+
+        store.set_rating(1, 5).unwrap();
+        store.set_rating(2, 3).unwrap();
+        store
+            .set_tags(
+                1,
+                vec![
+                    " keeper ".to_string(),
+                    "night".to_string(),
+                    "keeper".to_string(),
+                ],
+            )
+            .unwrap();
+        store.set_tags(2, vec!["night".to_string()]).unwrap();
+
+        let reloaded = HistoryStore::load(dir.path().join("history.json"));
+        assert_eq!(reloaded.entries()[0].tags, vec!["keeper", "night"]);
+        let filter = HistoryFilter {
+            min_rating: Some(3),
+            tags: vec!["night".to_string()],
+            ..Default::default()
+        };
+        let ids: Vec<u64> = filter
+            .apply(reloaded.entries())
+            .unwrap()
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn searches_file_names_and_dates_with_pagination() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store_with_entries(dir.path(), 5);
+        let mut entries = store.entries().to_vec();
+        entries[0].merged_at = "2024-03-01T10:00:00+09:00".to_string();
+        entries[1].merged_at = "2024-03-02T23:30:00+09:00".to_string();
+
+        let by_name = HistoryFilter {
+            query: Some("HDR_3".to_string()),
+            ..Default::default()
+        };
+        let page = search(&entries, &by_name, 0, None).unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].output_png_path, "hdr_3.png");
+
+        let by_date = HistoryFilter {
+            from: Some("2024-03-01T00:00:00+09:00".to_string()),
+            to: Some("2024-03-02T23:59:59+09:00".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search(&entries, &by_date, 0, None).unwrap().total, 2);
+
+        let page = search(&entries, &HistoryFilter::default(), 1, Some(2)).unwrap();
+        let ids: Vec<u64> = page.entries.iter().map(|entry| entry.id).collect();
+        assert_eq!((page.total, ids), (5, vec![4, 3]));
+
+        let invalid = HistoryFilter {
+            from: Some("yesterday".to_string()),
+            ..Default::default()
+        };
+        assert!(search(&entries, &invalid, 0, None).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_rating_and_unknown_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = store_with_entries(dir.path(), 1);
+
+        assert!(store.set_rating(1, 6).is_err());
+        assert!(store.set_rating(9, 1).is_err());
+    }
+
+    #[test]
+    fn preset_edits_mark_entries_stale_until_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = store_with_entries(dir.path(), 1);
+        let request = MergeRequest {
+            preset: Some("interior".to_string()),
+            output_dng: true,
+            clipping_map: true,
+            ..Default::default()
+        };
+        let mut result = MergeResult {
+            output_png_path: "interior.png".to_string(),
+            output_dng_path: Some("interior.dng".to_string()),
+            ..merge_result()
+        };
+        let entry = store.record(&request, &result).unwrap();
+        store.set_rating(entry.id, 4).unwrap();
+        assert_eq!(entry.written_paths(), ["interior.png", "interior.dng"]);
+
+        assert_eq!(store.mark_stale("interior").unwrap(), 1);
+        assert_eq!(store.mark_stale("interior").unwrap(), 0);
+        let stale = HistoryStore::load(dir.path().join("history.json")).stale_entries("interior");
+        assert_eq!(stale.len(), 1);
+        // 合成し直すときに元の出力の指定を使えるよう、合成要求ごと残す
+        let stored = stale[0].request.as_ref().unwrap();
+        assert!(stored.output_dng && stored.clipping_map);
+        assert_eq!(stored.preset.as_deref(), Some("interior"));
+
+        result.output_png_path = "interior_v2.png".to_string();
+        let replaced = store.replace_outputs(entry.id, &request, &result).unwrap();
+        assert_eq!(
+            (
+                replaced.output_png_path.as_str(),
+                replaced.stale,
+                replaced.rating
+            ),
+            ("interior_v2.png", false, 4)
+        );
+        assert_eq!(replaced.output_paths, ["interior_v2.png", "interior.dng"]);
+        assert!(store.stale_entries("interior").is_empty());
+        // 出力の一覧がない古い履歴は PNG と EXR だけを置き換える
+        let old = HistoryEntry {
+            output_paths: Vec::new(),
+            ..replaced
+        };
+        assert_eq!(old.written_paths(), ["interior_v2.png"]);
+    }
+}
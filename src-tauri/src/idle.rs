@@ -0,0 +1,192 @@
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+// 無効のときに設定の変更を確かめ直す間隔
+const DISABLED_RECHECK: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct IdleSettings {
+    pub enabled: bool,
+    // 最後の検出・合成からこの時間が過ぎたら待機状態にする
+    pub after_minutes: u64,
+}
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            after_minutes: 10,
+        }
+    }
+}
+
+impl IdleSettings {
+    pub fn after(&self) -> Option<Duration> {
+        (self.enabled && self.after_minutes > 0)
+            .then(|| Duration::from_secs(self.after_minutes * 60))
+    }
+}
+
+struct State {
+    last_activity: Instant,
+    idle: bool,
+}
+
+// 検出や合成がしばらくない間はメモリ上の状態を手放し、次の活動まで何もせずに待つ
+pub struct IdleMonitor {
+    state: Mutex<State>,
+    activity: Condvar,
+}
+
+impl Default for IdleMonitor {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                last_activity: Instant::now(),
+                idle: false,
+            }),
+            activity: Condvar::new(),
+        }
+    }
+}
+
+impl IdleMonitor {
+    // 待機状態から戻ったときだけ true を返す
+    pub fn touch(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.last_activity = Instant::now();
+        let woke = std::mem::replace(&mut state.idle, false);
+        self.activity.notify_all();
+        woke
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .idle
+    }
+
+    // 待機状態の間は次の活動（touch）まで戻らない
+    pub fn wait_until_active(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        while state.idle {
+            state = self
+                .activity
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // 専用のスレッドで呼ぶ。after が None の間は待機状態にしない。
+    // 合成中（busy）は活動中として扱い、待機状態に入るときに enter_idle を呼ぶ
+    pub fn run(
+        &self,
+        after: impl Fn() -> Option<Duration>,
+        busy: impl Fn() -> bool,
+        enter_idle: impl Fn(),
+    ) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if state.idle {
+                state = self
+                    .activity
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+                continue;
+            }
+            let timeout = match after() {
+                Some(after) => {
+                    let elapsed = state.last_activity.elapsed();
+                    if elapsed >= after {
+                        if busy() {
+                            state.last_activity = Instant::now();
+                            continue;
+                        }
+                        state.idle = true;
+                        drop(state);
+                        enter_idle();
+                        state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+                        continue;
+                    }
+                    after - elapsed
+                }
+                None => DISABLED_RECHECK,
+            };
+            state = self
+                .activity
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn enters_idle_after_inactivity_and_wakes_on_touch() {
+        let monitor = Arc::new(IdleMonitor::default());
+        let entered = Arc::new(AtomicUsize::new(0));
+        {
+            let monitor = monitor.clone();
+            let entered = entered.clone();
+            std::thread::spawn(move || {
+                monitor.run(
+                    || Some(Duration::from_millis(50)),
+                    || false,
+                    || {
+                        entered.fetch_add(1, Ordering::SeqCst);
+                    },
+                )
+            });
+        }
+
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(monitor.is_idle());
+        assert_eq!(entered.load(Ordering::SeqCst), 1);
+
+        let waiter = {
+            let monitor = monitor.clone();
+            std::thread::spawn(move || monitor.wait_until_active())
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!waiter.is_finished());
+        assert!(monitor.touch());
+        waiter.join().unwrap();
+        assert!(!monitor.is_idle());
+        assert!(!monitor.touch());
+        // 待機状態でなければすぐ戻る
+        monitor.wait_until_active();
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(entered.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn busy_jobs_keep_it_awake() {
+        let monitor = Arc::new(IdleMonitor::default());
+        {
+            let monitor = monitor.clone();
+            std::thread::spawn(move || {
+                monitor.run(|| Some(Duration::from_millis(20)), || true, || {})
+            });
+        }
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!monitor.is_idle());
+        assert!(IdleSettings {
+            enabled: false,
+            ..Default::default()
+        }
+        .after()
+        .is_none());
+    }
+}
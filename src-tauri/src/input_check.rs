@@ -0,0 +1,288 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::decode::DecodeError;
+use crate::frame_select;
+use crate::merge::{self, Rgb16Image};
+use crate::prefetch::Prefetcher;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum InputStatus {
+    Ok,
+    #[serde(rename_all = "camelCase")]
+    DecodeError {
+        message: String,
+    },
+    // 設定の imageLimits を超える大きさ。デコードせずに除いた
+    #[serde(rename_all = "camelCase")]
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_width: u32,
+        max_height: u32,
+        max_pixels: u64,
+    },
+    // 多数派と異なるサイズ
+    #[serde(rename_all = "camelCase")]
+    SizeMismatch {
+        width: u32,
+        height: u32,
+        expected_width: u32,
+        expected_height: u32,
+    },
+    // 先に出てきた入力と画素がまったく同じ
+    #[serde(rename_all = "camelCase")]
+    Duplicate {
+        of: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct InputCheck {
+    pub path: String,
+    #[serde(flatten)]
+    pub status: InputStatus,
+}
+
+pub struct ValidFrame {
+    pub path: String,
+    pub ev: f64,
+    // keep_images が false のときは露出だけ測って画像は破棄する
+    pub image: Option<Rgb16Image>,
+}
+
+pub struct ValidatedInputs {
+    pub checks: Vec<InputCheck>,
+    pub frames: Vec<ValidFrame>,
+}
+
+impl ValidatedInputs {
+    pub fn problems(&self) -> Vec<InputCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.status != InputStatus::Ok)
+            .cloned()
+            .collect()
+    }
+}
+
+struct Decoded {
+    size: (u32, u32),
+    fingerprint: u64,
+    ev: f64,
+    image: Option<Rgb16Image>,
+}
+
+// すべての入力を読み込み、1つ目の失敗で止めずにファイルごとの状態を返す
+pub fn validate_inputs(
+    paths: &[String],
+    keep_images: bool,
+    prefetched: Option<&Prefetcher>,
+) -> ValidatedInputs {
+    let decoded: Vec<Result<Decoded, DecodeError>> = paths
+        .iter()
+        .map(|path| {
+            let image = match prefetched.and_then(|prefetched| prefetched.take(path)) {
+                Some(image) => Ok(image),
+                None => merge::load_rgb16(path),
+            };
+            image.map(|image| Decoded {
+                size: image.dimensions(),
+                fingerprint: fingerprint(&image),
+                ev: frame_select::estimate_ev(&image),
+                image: keep_images.then_some(image),
+            })
+        })
+        .collect();
+    let expected = majority_size(&decoded);
+
+    let mut checks = Vec::new();
+    let mut frames = Vec::new();
+    let mut seen: HashMap<u64, &String> = HashMap::new();
+    for (path, result) in paths.iter().zip(decoded) {
+        let status = match result {
+            Err(DecodeError::ImageTooLarge {
+                width,
+                height,
+                limits,
+                ..
+            }) => InputStatus::TooLarge {
+                width,
+                height,
+                max_width: limits.max_width,
+                max_height: limits.max_height,
+                max_pixels: limits.max_pixels,
+            },
+            Err(DecodeError::Unreadable(message)) => InputStatus::DecodeError { message },
+            Ok(decoded) if Some(decoded.size) != expected => {
+                let expected = expected.unwrap_or_default();
+                InputStatus::SizeMismatch {
+                    width: decoded.size.0,
+                    height: decoded.size.1,
+                    expected_width: expected.0,
+                    expected_height: expected.1,
+                }
+            }
+            Ok(decoded) => match seen.get(&decoded.fingerprint) {
+                Some(original) => InputStatus::Duplicate {
+                    of: original.to_string(),
+                },
+                None => {
+                    seen.insert(decoded.fingerprint, path);
+                    frames.push(ValidFrame {
+                        path: path.clone(),
+                        ev: decoded.ev,
+                        image: decoded.image,
+                    });
+                    InputStatus::Ok
+                }
+            },
+        };
+        checks.push(InputCheck {
+            path: path.clone(),
+            status,
+        });
+    }
+    ValidatedInputs { checks, frames }
+}
+
+pub fn describe(problems: &[InputCheck]) -> String {
+    let lines: Vec<String> = problems
+        .iter()
+        .map(|check| match &check.status {
+            InputStatus::Ok => format!("{}: 問題なし", check.path),
+            InputStatus::DecodeError { message } => {
+                format!("{}: 読み込めません（{}）", check.path, message)
+            }
+            InputStatus::TooLarge {
+                width,
+                height,
+                max_width,
+                max_height,
+                max_pixels,
+            } => format!(
+                "{}: 大きすぎるため読み込めません（{}×{}、上限 {}×{}・{} 画素）",
+                check.path, width, height, max_width, max_height, max_pixels
+            ),
+            InputStatus::SizeMismatch {
+                width,
+                height,
+                expected_width,
+                expected_height,
+            } => format!(
+                "{}: 画像サイズが一致しません（{}x{}、他の入力は {}x{}）",
+                check.path, width, height, expected_width, expected_height
+            ),
+            InputStatus::Duplicate { of } => {
+                format!("{}: {} と同じ画像です", check.path, of)
+            }
+        })
+        .collect();
+    format!("入力に問題があります:\n{}", lines.join("\n"))
+}
+
+// 最も多いサイズを基準にする。同数なら先に出てきたサイズ
+fn majority_size(decoded: &[Result<Decoded, DecodeError>]) -> Option<(u32, u32)> {
+    let sizes: Vec<(u32, u32)> = decoded
+        .iter()
+        .filter_map(|result| result.as_ref().ok().map(|decoded| decoded.size))
+        .collect();
+    let count = |size: &(u32, u32)| sizes.iter().filter(|other| *other == size).count();
+    let mut best: Option<((u32, u32), usize)> = None;
+    for size in &sizes {
+        let n = count(size);
+        if best.is_none_or(|(_, best_n)| n > best_n) {
+            best = Some((*size, n));
+        }
+    }
+    best.map(|(size, _)| size)
+}
+
+fn fingerprint(image: &Rgb16Image) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.dimensions().hash(&mut hasher);
+    image.as_raw().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use std::path::Path;
+
+    fn write(dir: &Path, name: &str, size: (u32, u32), value: u16) -> String {
+        let path = dir.join(name);
+        Rgb16Image::from_pixel(size.0, size.1, Rgb([value; 3]))
+            .save(&path)
+            .unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn reports_each_file_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let dark = write(dir.path(), "dark.png", (8, 8), 4000);
+        let bright = write(dir.path(), "bright.png", (8, 8), 50000);
+        let copy = write(dir.path(), "copy.png", (8, 8), 4000);
+        let small = write(dir.path(), "small.png", (4, 4), 20000);
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, b"not a png").unwrap();
+        let broken = broken.to_string_lossy().to_string();
+        // 既定の imageLimits（幅 65535）を超える
+        let huge = dir.path().join("huge.png");
+        image::GrayImage::new(65536, 1).save(&huge).unwrap();
+        let huge = huge.to_string_lossy().to_string();
+        let paths = vec![dark.clone(), bright, copy, small, broken, huge];
+
+        let validated = validate_inputs(&paths, false, None);
+
+        let statuses: Vec<&InputStatus> =
+            validated.checks.iter().map(|check| &check.status).collect();
+        assert_eq!(statuses[0], &InputStatus::Ok);
+        assert_eq!(statuses[1], &InputStatus::Ok);
+        assert_eq!(statuses[2], &InputStatus::Duplicate { of: dark });
+        assert_eq!(
+            statuses[3],
+            &InputStatus::SizeMismatch {
+                width: 4,
+                height: 4,
+                expected_width: 8,
+                expected_height: 8,
+            }
+        );
+        assert!(matches!(statuses[4], InputStatus::DecodeError { .. }));
+        assert!(matches!(
+            statuses[5],
+            InputStatus::TooLarge {
+                width: 65536,
+                height: 1,
+                ..
+            }
+        ));
+        assert_eq!(validated.frames.len(), 2);
+        assert!(validated.frames.iter().all(|frame| frame.image.is_none()));
+        assert_eq!(validated.problems().len(), 4);
+    }
+
+    #[test]
+    fn serializes_status_with_tag() {
+        let check = InputCheck {
+            path: "a.png".to_string(),
+            status: InputStatus::Duplicate {
+                of: "b.png".to_string(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&check).unwrap(),
+            serde_json::json!({"path": "a.png", "status": "duplicate", "of": "b.png"})
+        );
+    }
+}
@@ -0,0 +1,825 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use tokio::sync::Notify;
+
+use crate::gray_card::GrayCardCorrection;
+use crate::levels::AppliedLevels;
+use crate::merge::MergeRequest;
+use crate::prefetch::Prefetcher;
+use crate::send_to::SendTarget;
+use crate::workdir::write_atomically;
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+pub const DEFAULT_MAX_CONCURRENT: usize = 2;
+
+// 実行中と、同時実行数の上限で待っている合成の数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeBacklog {
+    pub running: usize,
+    pub pending: usize,
+}
+
+type BacklogListener = Box<dyn Fn(MergeBacklog) + Send + Sync>;
+
+#[derive(Default)]
+struct Slots {
+    running: usize,
+    // 待っている合成。id の小さい（先に並んだ）ものから枠を渡す
+    queued: BTreeMap<u64, MergeRequest>,
+}
+
+// 終了時に完了できなかった合成ジョブ。次回起動時に jobs_resume で再実行できるよう保存する
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingJob {
+    pub request: MergeRequest,
+    #[serde(default)]
+    pub fixed: FixedSettings,
+    pub interrupted_at: String,
+}
+
+// MergeRequest がフロントエンドから受け取らないため保存しない項目のうち、再実行で同じ出力にするのに要るもの。
+// タイムラプスの連番の出力名や、先頭のフレームで固定した補正・プリセット
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FixedSettings {
+    pub output_name: Option<String>,
+    pub exif_reference_evs: Option<Vec<f64>>,
+    pub gray_card_correction: Option<GrayCardCorrection>,
+    pub levels_correction: Option<AppliedLevels>,
+    pub preset_applied: bool,
+    pub send_targets: Vec<SendTarget>,
+}
+
+impl PendingJob {
+    pub fn new(request: MergeRequest, interrupted_at: String) -> Self {
+        let fixed = FixedSettings {
+            output_name: request.output_name.clone(),
+            exif_reference_evs: request.exif_reference_evs.clone(),
+            gray_card_correction: request.gray_card_correction.clone(),
+            levels_correction: request.levels_correction,
+            preset_applied: request.preset_applied,
+            send_targets: request.send_targets.clone(),
+        };
+        Self {
+            request,
+            fixed,
+            interrupted_at,
+        }
+    }
+
+    // 保存した項目を戻した合成要求。出力先の制限などの設定は実行時に埋め直す
+    pub fn into_request(self) -> MergeRequest {
+        let fixed = self.fixed;
+        MergeRequest {
+            output_name: fixed.output_name,
+            exif_reference_evs: fixed.exif_reference_evs,
+            gray_card_correction: fixed.gray_card_correction,
+            levels_correction: fixed.levels_correction,
+            preset_applied: fixed.preset_applied,
+            send_targets: fixed.send_targets,
+            ..self.request
+        }
+    }
+}
+
+pub struct JobTracker {
+    path: PathBuf,
+    next_id: AtomicU64,
+    in_flight: Mutex<BTreeMap<u64, MergeRequest>>,
+    shutting_down: AtomicBool,
+    prefetcher: Arc<Prefetcher>,
+    slots: Mutex<Slots>,
+    slot_freed: Notify,
+    backlog_listener: Mutex<Option<BacklogListener>>,
+    // 自動合成を止めているアプリ（設定の pauseWhileRunning のうち動いているもの）
+    paused_by: Mutex<Vec<String>>,
+}
+
+// 同時実行数の枠。破棄すると待っている合成に枠を譲る
+pub struct SlotGuard<'a> {
+    tracker: &'a JobTracker,
+}
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        self.tracker
+            .slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .running -= 1;
+        self.tracker.slot_freed.notify_waiters();
+        self.tracker.emit_backlog();
+    }
+}
+
+// 待っている間に呼び出し元が破棄された場合も待ち行列から外す
+struct QueueEntry<'a> {
+    tracker: &'a JobTracker,
+    id: u64,
+}
+
+impl Drop for QueueEntry<'_> {
+    fn drop(&mut self) {
+        self.tracker
+            .slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .queued
+            .remove(&self.id);
+        // 後ろに並んでいた合成が先頭になる
+        self.tracker.slot_freed.notify_waiters();
+        self.tracker.emit_backlog();
+    }
+}
+
+pub struct JobGuard<'a> {
+    tracker: &'a JobTracker,
+    id: u64,
+}
+
+impl Drop for JobGuard<'_> {
+    fn drop(&mut self) {
+        self.tracker
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+impl JobTracker {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            next_id: AtomicU64::new(1),
+            in_flight: Mutex::new(BTreeMap::new()),
+            shutting_down: AtomicBool::new(false),
+            prefetcher: Arc::default(),
+            slots: Mutex::default(),
+            slot_freed: Notify::new(),
+            backlog_listener: Mutex::new(None),
+            paused_by: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn set_backlog_listener(&self, listener: impl Fn(MergeBacklog) + Send + Sync + 'static) {
+        *self
+            .backlog_listener
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Box::new(listener));
+    }
+
+    // 実行中の合成が max 件未満になるまで待つ。automatic の合成は自動合成を止めている間も待つ。
+    // 枠は並んだ順に渡し、止めている自動合成と中止された合成は飛ばす。
+    // 待っている間に中止・終了されたらエラーにする。終了時に待っていた合成は保留ジョブとして保存する
+    pub async fn wait_for_slot(
+        &self,
+        request: &MergeRequest,
+        max: usize,
+    ) -> Result<SlotGuard<'_>, String> {
+        let progress = &request.progress;
+        let automatic = request.automatic;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .queued
+            .insert(id, request.clone());
+        let entry = QueueEntry { tracker: self, id };
+        self.emit_backlog();
+
+        loop {
+            // 判定より先に通知を受け取れる状態にし、判定と待機の間の通知を取りこぼさない
+            let freed = self.slot_freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+
+            if self.is_shutting_down() {
+                return Err("終了処理中のため合成を開始できません".to_string());
+            }
+            progress.check_cancelled()?;
+            let paused = !self.paused_by().is_empty();
+            if !automatic || !paused {
+                let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+                let first = slots.queued.range(..id).all(|(_, queued)| {
+                    queued.progress.is_cancelled() || queued.automatic && paused
+                });
+                // 終了処理は同じロックの中で待ち行列を保存するので、保存した合成には枠を渡さない
+                if self.is_shutting_down() {
+                    return Err("終了処理中のため合成を開始できません".to_string());
+                }
+                if first && slots.running < max.max(1) {
+                    slots.running += 1;
+                    drop(slots);
+                    drop(entry);
+                    return Ok(SlotGuard { tracker: self });
+                }
+            }
+            freed.await;
+        }
+    }
+
+    // 変わったら true を返す。空になると待っていた自動合成を再開する
+    pub fn set_paused_by(&self, apps: Vec<String>) -> bool {
+        let mut paused_by = self
+            .paused_by
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if *paused_by == apps {
+            return false;
+        }
+        *paused_by = apps;
+        drop(paused_by);
+        self.slot_freed.notify_waiters();
+        true
+    }
+
+    pub fn paused_by(&self) -> Vec<String> {
+        self.paused_by
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn backlog(&self) -> MergeBacklog {
+        let slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        MergeBacklog {
+            running: slots.running,
+            pending: slots.queued.len(),
+        }
+    }
+
+    fn emit_backlog(&self) {
+        let backlog = self.backlog();
+        let listener = self
+            .backlog_listener
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(listener) = listener.as_ref() {
+            listener(backlog);
+        }
+    }
+
+    pub fn begin(&self, request: &MergeRequest) -> Result<JobGuard<'_>, String> {
+        if self.is_shutting_down() {
+            return Err("終了処理中のため合成を開始できません".to_string());
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        in_flight.insert(id, request.clone());
+        Ok(JobGuard { tracker: self, id })
+    }
+
+    // 実行中・待機中の合成が見つからなければ false を返す
+    pub fn cancel(&self, job_id: &str) -> Result<bool, String> {
+        let in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let job = in_flight
+            .values()
+            .find(|request| request.progress.job_id() == Some(job_id));
+        if let Some(request) = job {
+            request.progress.cancel();
+            return Ok(true);
+        }
+        drop(in_flight);
+
+        let slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        let queued = slots
+            .queued
+            .values()
+            .find(|queued| queued.progress.job_id() == Some(job_id));
+        if let Some(queued) = queued {
+            queued.progress.cancel();
+            drop(slots);
+            self.slot_freed.notify_waiters();
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // 枠を待っている合成のうち次に枠を受け取るものの入力。中止された合成と止めている自動合成は飛ばす
+    pub fn next_queued_paths(&self) -> Option<Vec<String>> {
+        let paused = !self.paused_by().is_empty();
+        let slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        slots
+            .queued
+            .values()
+            .find(|queued| !queued.progress.is_cancelled() && !(queued.automatic && paused))
+            .map(|queued| queued.paths.clone())
+    }
+
+    // 次のジョブの入力の先読み先。先読みを無効にしても同じものを返す
+    pub fn prefetcher(&self) -> Arc<Prefetcher> {
+        self.prefetcher.clone()
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    // 実行中と、枠を待っている合成の合計。終了時にはどちらも保留ジョブとして扱う
+    pub fn unfinished_count(&self) -> usize {
+        self.in_flight_count() + self.backlog().pending
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    // 新しいジョブの受け付けを止め、実行中のジョブが終わるまで最大 grace だけ待つ。
+    // 間に合わなかったジョブは中止し、一時ファイルと出力名の予約を片付け終わるまで最大 cancel_grace だけ待つ。
+    // 中止したジョブと枠を待っていたジョブは保留ジョブとして保存し、その数を返す
+    pub fn shutdown(&self, grace: Duration, cancel_grace: Duration) -> Result<usize, String> {
+        let queued: Vec<MergeRequest> = {
+            let slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+            self.shutting_down.store(true, Ordering::SeqCst);
+            slots
+                .queued
+                .values()
+                .filter(|request| !request.progress.is_cancelled())
+                .cloned()
+                .collect()
+        };
+        self.slot_freed.notify_waiters();
+        let deadline = Instant::now() + grace;
+        while self.in_flight_count() > 0 && Instant::now() < deadline {
+            std::thread::sleep(WAIT_INTERVAL);
+        }
+
+        let mut remaining: Vec<MergeRequest> = {
+            let in_flight = self
+                .in_flight
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            in_flight.values().cloned().collect()
+        };
+        for request in &remaining {
+            request.progress.cancel();
+        }
+        let deadline = Instant::now() + cancel_grace;
+        while self.in_flight_count() > 0 && Instant::now() < deadline {
+            std::thread::sleep(WAIT_INTERVAL);
+        }
+        remaining.extend(queued);
+        if remaining.is_empty() {
+            return Ok(0);
+        }
+
+        let interrupted_at = Local::now().to_rfc3339();
+        let mut pending = self.pending()?;
+        pending.extend(
+            remaining
+                .into_iter()
+                .map(|request| PendingJob::new(request, interrupted_at.clone())),
+        );
+        let count = pending.len();
+        self.save_pending(&pending)?;
+        Ok(count)
+    }
+
+    pub fn pending(&self) -> Result<Vec<PendingJob>, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("保留ジョブの読み込みに失敗しました: {}", e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(format!("保留ジョブの読み込みに失敗しました: {}", e)),
+        }
+    }
+
+    // 再実行する保留ジョブを一覧から外して返す。実行中に終了したら、その時点でまた保存される
+    pub fn take_pending(&self, index: usize) -> Result<PendingJob, String> {
+        let mut pending = self.pending()?;
+        if index >= pending.len() {
+            return Err("保留ジョブが見つかりません".to_string());
+        }
+        let job = pending.remove(index);
+        if pending.is_empty() {
+            self.clear_pending()?;
+        } else {
+            self.save_pending(&pending)?;
+        }
+        Ok(job)
+    }
+
+    pub fn clear_pending(&self) -> Result<(), String> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn save_pending(&self, pending: &[PendingJob]) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let text = serde_json::to_string_pretty(pending).map_err(|e| e.to_string())?;
+        // 終了間際に書くため、途中で落ちても前の保留ジョブの一覧を壊さない
+        write_atomically(None, &self.path, |partial| {
+            fs::write(partial, text).map_err(|e| e.to_string())
+        })
+        .map_err(|e| format!("保留ジョブを保存できません: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::ProgressReporter;
+
+    fn request(path: &str) -> MergeRequest {
+        MergeRequest {
+            paths: vec![path.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn automatic() -> MergeRequest {
+        MergeRequest {
+            automatic: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn queues_merges_beyond_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = Arc::new(JobTracker::new(dir.path().join("pending_jobs.json")));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        tracker.set_backlog_listener(move |backlog| recorded.lock().unwrap().push(backlog));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let first = tracker
+                .wait_for_slot(&MergeRequest::default(), 1)
+                .await
+                .unwrap();
+            let waiting = {
+                let tracker = tracker.clone();
+                tokio::spawn(async move {
+                    let _slot = tracker
+                        .wait_for_slot(&MergeRequest::default(), 1)
+                        .await
+                        .unwrap();
+                    tracker.backlog()
+                })
+            };
+            let cancelled = MergeRequest {
+                progress: ProgressReporter::new(Some("late".to_string()), |_| {}),
+                ..Default::default()
+            };
+            let late = {
+                let tracker = tracker.clone();
+                tokio::spawn(async move { tracker.wait_for_slot(&cancelled, 1).await.is_err() })
+            };
+            tokio::task::yield_now().await;
+            assert_eq!(
+                tracker.backlog(),
+                MergeBacklog {
+                    running: 1,
+                    pending: 2
+                }
+            );
+
+            assert!(tracker.cancel("late").unwrap());
+            assert!(late.await.unwrap());
+            drop(first);
+            let during = waiting.await.unwrap();
+            assert_eq!(
+                during,
+                MergeBacklog {
+                    running: 1,
+                    pending: 0
+                }
+            );
+        });
+        assert_eq!(tracker.backlog(), MergeBacklog::default());
+        assert!(events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|backlog| backlog.pending == 2));
+    }
+
+    #[test]
+    fn admits_queued_merges_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = Arc::new(JobTracker::new(dir.path().join("pending_jobs.json")));
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let first = tracker
+                .wait_for_slot(&MergeRequest::default(), 1)
+                .await
+                .unwrap();
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let mut waiters = Vec::new();
+            for i in 0..8 {
+                let queued = tracker.clone();
+                let order = order.clone();
+                waiters.push(tokio::spawn(async move {
+                    let tracker = queued;
+                    let _slot = tracker
+                        .wait_for_slot(&MergeRequest::default(), 1)
+                        .await
+                        .unwrap();
+                    order.lock().unwrap().push(i);
+                    tokio::time::sleep(Duration::from_millis(2)).await;
+                }));
+                // 並ぶ順番を決める
+                while tracker.backlog().pending < i + 1 {
+                    tokio::task::yield_now().await;
+                }
+            }
+            drop(first);
+            for waiter in waiters {
+                waiter.await.unwrap();
+            }
+            assert_eq!(*order.lock().unwrap(), (0..8).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn holds_automatic_merges_while_paused() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = Arc::new(JobTracker::new(dir.path().join("pending_jobs.json")));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            assert!(tracker.set_paused_by(vec!["obs64.exe".to_string()]));
+            assert!(!tracker.set_paused_by(vec!["obs64.exe".to_string()]));
+            let automatic = {
+                let tracker = tracker.clone();
+                tokio::spawn(async move {
+                    let _slot = tracker.wait_for_slot(&automatic(), 2).await.unwrap();
+                })
+            };
+            // 手動の合成は止めない
+            let manual = tracker
+                .wait_for_slot(&MergeRequest::default(), 2)
+                .await
+                .unwrap();
+            tokio::task::yield_now().await;
+            assert_eq!(
+                tracker.backlog(),
+                MergeBacklog {
+                    running: 1,
+                    pending: 1
+                }
+            );
+            drop(manual);
+            tokio::task::yield_now().await;
+            assert_eq!(tracker.backlog().pending, 1);
+
+            assert!(tracker.set_paused_by(Vec::new()));
+            automatic.await.unwrap();
+        });
+        assert_eq!(tracker.backlog(), MergeBacklog::default());
+    }
+
+    #[test]
+    fn reports_the_next_queued_inputs_for_prefetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = Arc::new(JobTracker::new(dir.path().join("pending_jobs.json")));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let running = tracker.wait_for_slot(&request("a.png"), 1).await.unwrap();
+            assert_eq!(tracker.next_queued_paths(), None);
+            let queue = |queued: MergeRequest| {
+                let tracker = tracker.clone();
+                tokio::spawn(async move {
+                    let _slot = tracker.wait_for_slot(&queued, 1).await;
+                })
+            };
+            let automatic = queue(MergeRequest {
+                paths: vec!["b.png".to_string()],
+                ..automatic()
+            });
+            let manual = queue(request("c.png"));
+            while tracker.backlog().pending < 2 {
+                tokio::task::yield_now().await;
+            }
+            assert_eq!(tracker.next_queued_paths(), Some(vec!["b.png".to_string()]));
+
+            // 止めている自動合成は飛ばし、次に枠を受け取る手動の合成を返す
+            tracker.set_paused_by(vec!["obs64.exe".to_string()]);
+            assert_eq!(tracker.next_queued_paths(), Some(vec!["c.png".to_string()]));
+            tracker.set_paused_by(Vec::new());
+            drop(running);
+            automatic.await.unwrap();
+            manual.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn shutdown_persists_unfinished_jobs_and_rejects_new_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = JobTracker::new(dir.path().join("pending_jobs.json"));
+        let finished = tracker.begin(&request("done.png")).unwrap();
+        drop(finished);
+        let _running = tracker.begin(&request("running.png")).unwrap();
+
+        let persisted = tracker
+            .shutdown(Duration::from_millis(10), Duration::from_millis(10))
+            .unwrap();
+
+        assert_eq!(persisted, 1);
+        assert_eq!(tracker.pending().unwrap()[0].request.paths, ["running.png"]);
+        assert!(tracker.begin(&request("late.png")).is_err());
+
+        tracker.clear_pending().unwrap();
+        assert!(tracker.pending().unwrap().is_empty());
+    }
+
+    // 保存しない項目も、タイムラプスの連番や固定した補正のまま再実行できるよう保留ジョブに残す
+    #[test]
+    fn pending_jobs_keep_fixed_sequence_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = JobTracker::new(dir.path().join("pending_jobs.json"));
+        let frame = MergeRequest {
+            output_name: Some("sunset_0003".to_string()),
+            exif_reference_evs: Some(vec![-2.0, 0.0, 2.0]),
+            gray_card_correction: Some(GrayCardCorrection {
+                measured: [0.09, 0.18, 0.18],
+                gains: [2.0, 1.0, 1.0],
+                exposure_ev: 0.5,
+            }),
+            levels_correction: Some(AppliedLevels {
+                black_percentile: 0.1,
+                white_percentile: 99.9,
+                black_point: 0.02,
+                white_point: 0.95,
+            }),
+            preset: Some("timelapse".to_string()),
+            preset_applied: true,
+            send_targets: vec![SendTarget {
+                name: "blender".to_string(),
+                folder: "/projects/scene".to_string(),
+                ..Default::default()
+            }],
+            ..request("frame3.png")
+        };
+        let _running = tracker.begin(&frame).unwrap();
+
+        tracker
+            .shutdown(Duration::from_millis(10), Duration::from_millis(10))
+            .unwrap();
+
+        let resumed = tracker.take_pending(0).unwrap().into_request();
+        assert_eq!(resumed.paths, ["frame3.png"]);
+        assert_eq!(resumed.output_name, frame.output_name);
+        assert_eq!(resumed.exif_reference_evs, frame.exif_reference_evs);
+        assert_eq!(resumed.gray_card_correction, frame.gray_card_correction);
+        assert_eq!(resumed.levels_correction, frame.levels_correction);
+        assert!(resumed.preset_applied);
+        assert_eq!(resumed.send_targets, frame.send_targets);
+        assert!(tracker.pending().unwrap().is_empty());
+        assert!(tracker.take_pending(0).is_err());
+    }
+
+    #[test]
+    fn shutdown_persists_queued_merges() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = Arc::new(JobTracker::new(dir.path().join("pending_jobs.json")));
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+        let _running = tracker.begin(&request("running.png")).unwrap();
+
+        runtime.block_on(async {
+            let _slot = tracker
+                .wait_for_slot(&MergeRequest::default(), 1)
+                .await
+                .unwrap();
+            let queued = {
+                let tracker = tracker.clone();
+                tokio::spawn(async move {
+                    tracker
+                        .wait_for_slot(&request("queued.png"), 1)
+                        .await
+                        .is_err()
+                })
+            };
+            while tracker.unfinished_count() < 2 {
+                tokio::task::yield_now().await;
+            }
+            let shutdown = {
+                let tracker = tracker.clone();
+                tokio::task::spawn_blocking(move || {
+                    tracker.shutdown(Duration::from_millis(10), Duration::from_millis(10))
+                })
+            };
+            // 枠を待っていた合成は始めずに、保留ジョブとして保存する
+            assert!(queued.await.unwrap());
+            assert_eq!(shutdown.await.unwrap().unwrap(), 2);
+        });
+        let paths: Vec<Vec<String>> = tracker
+            .pending()
+            .unwrap()
+            .into_iter()
+            .map(|job| job.request.paths)
+            .collect();
+        assert_eq!(paths, [["running.png"], ["queued.png"]]);
+    }
+
+    #[test]
+    fn shutdown_cancels_running_jobs_and_persists_queued_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = Arc::new(JobTracker::new(dir.path().join("pending_jobs.json")));
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+        // 中止されるまで書き出しを続け、中止されたら後片付けをしてから終わるジョブ
+        let running = request("running.png");
+        let worker = {
+            let tracker = tracker.clone();
+            let running = running.clone();
+            std::thread::spawn(move || {
+                let _job = tracker.begin(&running).unwrap();
+                while !running.progress.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            })
+        };
+
+        runtime.block_on(async {
+            let _slot = tracker
+                .wait_for_slot(&MergeRequest::default(), 1)
+                .await
+                .unwrap();
+            let queued = {
+                let tracker = tracker.clone();
+                tokio::spawn(async move {
+                    tracker
+                        .wait_for_slot(&request("queued.png"), 1)
+                        .await
+                        .is_err()
+                })
+            };
+            while tracker.unfinished_count() < 2 {
+                tokio::task::yield_now().await;
+            }
+            let shutdown = {
+                let tracker = tracker.clone();
+                tokio::task::spawn_blocking(move || {
+                    let persisted =
+                        tracker.shutdown(Duration::from_millis(10), Duration::from_secs(5));
+                    // 中止したジョブの後片付けが終わってから保存する
+                    (persisted, tracker.in_flight_count())
+                })
+            };
+            assert!(queued.await.unwrap());
+            let (persisted, in_flight) = shutdown.await.unwrap();
+            assert_eq!(persisted.unwrap(), 2);
+            assert_eq!(in_flight, 0);
+        });
+        worker.join().unwrap();
+        assert!(running.progress.is_cancelled());
+        let paths: Vec<Vec<String>> = tracker
+            .pending()
+            .unwrap()
+            .into_iter()
+            .map(|job| job.request.paths)
+            .collect();
+        assert_eq!(paths, [["running.png"], ["queued.png"]]);
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::capabilities::MAX_MERGE_FRAMES;
+use crate::formats;
+use crate::frame_select::FrameSelection;
+
+pub const MIN_LAUNCH_FILES: usize = 2;
+// 合成できる枚数を超えた分は frameSelection で選ばせるため、候補として渡せる枚数より少なくしておく
+pub const MAX_LAUNCH_FILES: usize = 9;
+const HDR_FORMATS: &[&str] = &["exr"];
+
+// コマンドラインや「プログラムから開く」で渡された画像から作る合成ジョブの下書き
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchRequest {
+    pub paths: Vec<String>,
+    // 一度に合成できる枚数を超えるときだけ指定し、露出の違うフレームを選ばせる
+    pub frame_selection: Option<FrameSelection>,
+}
+
+impl LaunchRequest {
+    fn new(paths: Vec<String>) -> Self {
+        let frame_selection = (paths.len() > MAX_MERGE_FRAMES).then(FrameSelection::default);
+        Self {
+            paths,
+            frame_selection,
+        }
+    }
+}
+
+// 画像は渡されたが、枚数が合わず下書きを作れなかったこと
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchRejected {
+    pub paths: Vec<String>,
+    pub reason: String,
+}
+
+// 先頭（実行ファイル自身）とオプション引数を除き、存在する画像ファイルだけを拾う。
+// 相対パスは起動時のカレントディレクトリを基準に解決する。画像が1枚もなければ Ok(None)
+pub fn parse_args(args: &[String], cwd: &Path) -> Result<Option<LaunchRequest>, LaunchRejected> {
+    let paths: Vec<String> = args
+        .iter()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| resolve(arg, cwd))
+        .filter(|path| path.is_file() && is_image(path))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+
+    if paths.is_empty() {
+        return Ok(None);
+    }
+    match count_error(paths.len()) {
+        Some(reason) => Err(LaunchRejected { paths, reason }),
+        None => Ok(Some(LaunchRequest::new(paths))),
+    }
+}
+
+fn count_error(count: usize) -> Option<String> {
+    if count < MIN_LAUNCH_FILES {
+        return Some("合成には2枚以上の画像が必要です".to_string());
+    }
+    if count > MAX_LAUNCH_FILES {
+        return Some(format!(
+            "一度に合成できるのは{}枚までです",
+            MAX_LAUNCH_FILES
+        ));
+    }
+    None
+}
+
+// ドロップされた項目の種類に応じて、フロントエンドに提案する操作
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DropSuggestion {
+    // フォルダは監視対象に登録するか、中の画像をまとめて処理する
+    #[serde(rename_all = "camelCase")]
+    Folder {
+        path: String,
+        image_count: usize,
+    },
+    Merge(LaunchRequest),
+    Preview {
+        path: String,
+    },
+    Unsupported {
+        reason: String,
+    },
+}
+
+pub fn classify_dropped(paths: &[String]) -> DropSuggestion {
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+
+    if let [folder] = paths.as_slice() {
+        if folder.is_dir() {
+            return DropSuggestion::Folder {
+                path: folder.to_string_lossy().to_string(),
+                image_count: count_images(folder),
+            };
+        }
+        if folder.is_file() && has_extension(folder, HDR_FORMATS) {
+            return DropSuggestion::Preview {
+                path: folder.to_string_lossy().to_string(),
+            };
+        }
+    }
+
+    if paths.iter().any(|path| path.is_dir()) {
+        return unsupported("フォルダは1つずつドロップしてください");
+    }
+    if paths.iter().any(|path| !path.is_file() || !is_image(path)) {
+        return unsupported("対応していないファイルが含まれています");
+    }
+    if let Some(reason) = count_error(paths.len()) {
+        return unsupported(&reason);
+    }
+
+    DropSuggestion::Merge(LaunchRequest::new(
+        paths
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect(),
+    ))
+}
+
+fn unsupported(reason: &str) -> DropSuggestion {
+    DropSuggestion::Unsupported {
+        reason: reason.to_string(),
+    }
+}
+
+fn count_images(folder: &Path) -> usize {
+    std::fs::read_dir(folder)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file() && is_image(&entry.path()))
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+fn resolve(arg: &str, cwd: &Path) -> PathBuf {
+    let path = PathBuf::from(arg);
+    if path.is_absolute() {
+        path
+    } else {
+        cwd.join(path)
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    formats::is_decodable(path)
+}
+
+fn has_extension(path: &Path, formats: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| formats.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn collects_existing_images_relative_to_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.JPG", "notes.txt"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let args: Vec<String> = [
+            "vhdr",
+            "--flag",
+            "a.png",
+            "b.JPG",
+            "notes.txt",
+            "missing.png",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+
+        let request = parse_args(&args, dir.path()).unwrap().unwrap();
+
+        assert_eq!(
+            request.paths,
+            vec![
+                dir.path().join("a.png").to_string_lossy().to_string(),
+                dir.path().join("b.JPG").to_string_lossy().to_string(),
+            ]
+        );
+        assert_eq!(request.frame_selection, None);
+    }
+
+    #[test]
+    fn classifies_dropped_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, b"").unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let images = vec![file("a.png"), file("b.png")];
+        let exr = file("merged.exr");
+        let text = file("notes.txt");
+        let folder = dir.path().to_string_lossy().to_string();
+
+        assert_eq!(
+            classify_dropped(std::slice::from_ref(&folder)),
+            DropSuggestion::Folder {
+                path: folder,
+                image_count: 2
+            }
+        );
+        assert_eq!(
+            classify_dropped(&images),
+            DropSuggestion::Merge(LaunchRequest {
+                paths: images.clone(),
+                frame_selection: None,
+            })
+        );
+        assert_eq!(
+            classify_dropped(std::slice::from_ref(&exr)),
+            DropSuggestion::Preview { path: exr }
+        );
+        assert!(matches!(
+            classify_dropped(&[images[0].clone(), text]),
+            DropSuggestion::Unsupported { .. }
+        ));
+        assert!(matches!(
+            classify_dropped(&images[..1]),
+            DropSuggestion::Unsupported { .. }
+        ));
+    }
+
+    #[test]
+    fn rejects_single_file_with_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.png"), b"").unwrap();
+        let args = vec!["vhdr".to_string(), "a.png".to_string()];
+
+        let rejected = parse_args(&args, dir.path()).unwrap_err();
+
+        assert_eq!(rejected.paths.len(), 1);
+        assert!(!rejected.reason.is_empty());
+    }
+
+    #[test]
+    fn ignores_launch_without_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = vec!["vhdr".to_string(), "--minimized".to_string()];
+
+        assert_eq!(parse_args(&args, dir.path()), Ok(None));
+    }
+
+    #[test]
+    fn selects_frames_when_more_files_than_a_merge_accepts() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = (0..=MAX_LAUNCH_FILES)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.png", i));
+                fs::write(&path, b"").unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        let args: Vec<String> = std::iter::once("vhdr".to_string())
+            .chain(paths.iter().cloned())
+            .collect();
+
+        assert!(MAX_LAUNCH_FILES <= crate::frame_select::MAX_SELECTION_CANDIDATES);
+        let request = parse_args(&args[..=MAX_LAUNCH_FILES], dir.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.paths.len(), MAX_LAUNCH_FILES);
+        assert_eq!(request.frame_selection, Some(FrameSelection::default()));
+        let request = parse_args(&args[..=MAX_MERGE_FRAMES], dir.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.frame_selection, None);
+
+        assert!(parse_args(&args, dir.path()).is_err());
+        assert!(matches!(
+            classify_dropped(&paths),
+            DropSuggestion::Unsupported { .. }
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{luminance, u16_to_unit, unit_to_u16};
+use crate::merge::Rgb16Image;
+
+// 黒点と白点がこれより近い（ほぼ一様な）画像は伸ばさない
+const MIN_RANGE: f32 = 1.0 / 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AutoLevels {
+    // 輝度のヒストグラムでこの割合（%）以下を黒にする
+    pub black_percentile: f64,
+    // この割合（%）以上を白にする
+    pub white_percentile: f64,
+}
+
+impl Default for AutoLevels {
+    fn default() -> Self {
+        Self {
+            black_percentile: 0.1,
+            white_percentile: 99.9,
+        }
+    }
+}
+
+// 合成結果にかけたレベル補正。blackPoint・whitePoint は sRGB の 0〜1 で、同じ値をかければ再現できる
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedLevels {
+    pub black_percentile: f64,
+    pub white_percentile: f64,
+    pub black_point: f32,
+    pub white_point: f32,
+}
+
+impl AutoLevels {
+    pub fn validate(&self) -> Result<(), String> {
+        let (black, white) = (self.black_percentile, self.white_percentile);
+        if !(0.0..=100.0).contains(&black) || !(0.0..=100.0).contains(&white) || black >= white {
+            return Err(
+                "autoLevels は 0 ≤ blackPercentile < whitePercentile ≤ 100 で指定してください"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+// sRGB の輝度のヒストグラムから黒点と白点を求める
+pub fn measure(image: &Rgb16Image, settings: &AutoLevels) -> AppliedLevels {
+    let mut histogram = vec![0u64; u16::MAX as usize + 1];
+    for pixel in image.pixels() {
+        let luma = luminance(pixel.0.map(u16_to_unit));
+        histogram[unit_to_u16(luma) as usize] += 1;
+    }
+    let total = image.pixels().len() as f64;
+    let point = |percentile: f64| {
+        let rank = (total * percentile / 100.0).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        let bin = histogram
+            .iter()
+            .position(|count| {
+                seen += count;
+                seen >= rank
+            })
+            .unwrap_or(histogram.len() - 1);
+        u16_to_unit(bin as u16)
+    };
+    let (mut black_point, mut white_point) = (
+        point(settings.black_percentile),
+        point(settings.white_percentile),
+    );
+    if white_point - black_point < MIN_RANGE {
+        (black_point, white_point) = (0.0, 1.0);
+    }
+    AppliedLevels {
+        black_percentile: settings.black_percentile,
+        white_percentile: settings.white_percentile,
+        black_point,
+        white_point,
+    }
+}
+
+// 色相が変わらないよう、3チャンネルに同じ直線の伸ばしをかける
+pub fn apply(image: &Rgb16Image, levels: &AppliedLevels) -> Rgb16Image {
+    let scale = 1.0 / (levels.white_point - levels.black_point);
+    let mut stretched = image.clone();
+    for pixel in stretched.pixels_mut() {
+        for value in pixel.0.iter_mut() {
+            let unit = (u16_to_unit(*value) - levels.black_point) * scale;
+            *value = unit_to_u16(unit.clamp(0.0, 1.0));
+        }
+    }
+    stretched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn stretches_to_the_percentiles() {
+        // 0.25〜0.75 の灰色の横グラデーション
+        let image =
+            Rgb16Image::from_fn(
+                101,
+                4,
+                |x, _| Rgb([unit_to_u16(0.25 + x as f32 * 0.005); 3]),
+            );
+
+        let levels = measure(&image, &AutoLevels::default());
+        let stretched = apply(&image, &levels);
+
+        assert!((levels.black_point - 0.25).abs() < 0.001);
+        assert!((levels.white_point - 0.75).abs() < 0.001);
+        assert_eq!(stretched.get_pixel(0, 0).0, [0; 3]);
+        assert_eq!(stretched.get_pixel(100, 0).0, [u16::MAX; 3]);
+        assert!((u16_to_unit(stretched.get_pixel(50, 0).0[0]) - 0.5).abs() < 0.01);
+
+        let flat = Rgb16Image::from_pixel(8, 8, Rgb([30000; 3]));
+        assert_eq!(apply(&flat, &measure(&flat, &AutoLevels::default())), flat);
+        assert!(AutoLevels {
+            black_percentile: 50.0,
+            white_percentile: 50.0,
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -1,14 +1,158 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::{Duration, Instant};
 
 use chrono::Local;
-use exr::image::write::write_rgb_file;
 use image::{ImageBuffer, Rgb};
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Emitter, State};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, ExitRequestApi, Manager, RunEvent, State};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+use ts_rs::TS;
+
+mod algorithms;
+mod align;
+mod align_sidecar;
+mod analysis_stream;
+mod api_schema;
+mod api_version;
+mod app_pause;
+mod batch;
+mod bracket_recommend;
+mod capabilities;
+mod clipboard;
+mod clipping;
+mod color;
+mod compare;
+mod compare_tiles;
+mod config;
+mod contact_sheet;
+mod dashboard;
+mod decode;
+mod deflicker;
+mod deghost;
+mod deliverables;
+mod detection_log;
+mod disk_cache;
+mod dng;
+mod embedded_thumbnail;
+mod encode;
+mod exif;
+mod exr_color;
+mod exr_preview;
+mod false_color;
+mod filters;
+mod folder_stats;
+mod formats;
+mod frame_quality;
+mod frame_select;
+mod geometry;
+#[cfg(test)]
+mod golden_tests;
+mod gray_card;
+mod group_timeouts;
+mod grouping;
+mod growing_file;
+mod hdr_preview;
+mod history;
+mod idle;
+mod input_check;
+mod jobs;
+mod launch;
+mod levels;
+mod long_exposure;
+mod maintenance;
+mod merge;
+mod noise_stack;
+mod output_color;
+mod output_lock;
+mod output_path;
+mod panic_report;
+mod paths;
+mod pending_groups;
+mod pipeline;
+mod plugin;
+mod prefetch;
+mod print_output;
+mod probe;
+mod progress;
+mod projects;
+mod radiance;
+mod recycle;
+mod report;
+mod resources;
+mod send_to;
+mod simd;
+mod social_export;
+mod soft_proof;
+mod stats;
+mod sweep;
+mod synthetic;
+mod tiled;
+mod timelapse;
+mod tonemap;
+mod watch_filter;
+mod workdir;
+mod worker_priority;
+
+use algorithms::AlgorithmInfo;
+use analysis_stream::AnalysisJobs;
+use api_schema::{
+    ANALYSIS_PROGRESS_EVENT, AUTO_PAUSE_EVENT, BACKLOG_EVENT, BRACKET_TIMEOUT_EVENT,
+    DASHBOARD_EVENT, FILE_DETECTED_EVENT, IDLE_EVENT, JOB_FAILED_EVENT, LAUNCH_REJECTED_EVENT,
+    MERGE_PROGRESS_EVENT, OPEN_FILES_EVENT, RESOURCE_USAGE_EVENT, SECOND_INSTANCE_EVENT,
+    SHUTDOWN_WAITING_EVENT,
+};
+use api_version::{ApiNegotiation, MergeRequestV2};
+use batch::{Batch, BatchRequest, BatchResult};
+use bracket_recommend::BracketRecommendation;
+use capabilities::Capabilities;
+use clipboard::ClipboardCopy;
+use compare::CompareResult;
+use compare_tiles::{CompareTiles, TileSpec};
+use config::{ConfigStore, ImportSummary, Preset, Settings};
+use dashboard::{Dashboard, DashboardSummary, DASHBOARD_INTERVAL};
+use deflicker::{DeflickerOptions, DeflickerResult};
+use detection_log::{Detection, DetectionKind, DetectionLog, DetectionOutcome};
+use disk_cache::{CacheUsage, DiskCache};
+use false_color::{FalseColorMode, FalseColorResult};
+use folder_stats::{StatsSources, WatchFolderStats};
+use formats::ExtensionMatcher;
+use frame_quality::FrameQuality;
+use group_timeouts::GroupTimeouts;
+use grouping::{BracketGroup, GroupInput, GroupingRules};
+use growing_file::GrowingFiles;
+use hdr_preview::HdrPreviewParams;
+use history::{HistoryEntry, HistoryFilter, HistoryPage, ReprocessFailure, StaleReprocess};
+use idle::IdleMonitor;
+use input_check::InputCheck;
+use jobs::{JobTracker, MergeBacklog, PendingJob};
+use launch::{DropSuggestion, LaunchRejected, LaunchRequest};
+use maintenance::{CleanupPolicy, CleanupReport, CleanupTargets};
+use merge::{load_rgb16, MergeMode, MergeRequest, MergeResult};
+use panic_report::CaughtPanic;
+use pending_groups::{GroupStore, PendingGroup};
+use probe::ProbeResult;
+use progress::{ProgressReporter, CANCELLED};
+use projects::{Project, Workspace};
+use recycle::DeleteReport;
+use report::ReportFormat;
+use resources::{ResourceMonitor, ResourceUsage, SAMPLE_INTERVAL};
+use send_to::{SendContext, SentOutput};
+use social_export::{ExportTarget, SocialExport, SocialPlatform};
+use soft_proof::{DisplayProfile, SoftProofParams, SoftProofResult};
+use stats::{StatsStore, UsageStats};
+use sweep::SweepPreview;
+use synthetic::{TestBracket, TestBracketOptions};
+use timelapse::{Sequence, SidecarTarget, TimelapseRequest, TimelapseResult};
+use watch_filter::{OwnOutputs, WatchTiming};
+use workdir::JobWorkdir;
+
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+// 間に合わなかったジョブを中止してから、一時ファイルの片付けを待つ時間
+const SHUTDOWN_CANCEL_GRACE: Duration = Duration::from_secs(10);
 
 #[derive(Default)]
 struct WatcherState {
@@ -16,105 +160,305 @@ struct WatcherState {
     folder: Arc<Mutex<Option<PathBuf>>>,
     is_watching: Arc<Mutex<bool>>,
     recent_events: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+    matcher: Arc<Mutex<ExtensionMatcher>>,
+    timing: Arc<Mutex<WatchTiming>>,
+    own_outputs: Arc<OwnOutputs>,
+    ignore_dirs: Arc<Mutex<Vec<PathBuf>>>,
+    last_detected_at: Arc<Mutex<Option<String>>>,
+    growing_files: Arc<GrowingFiles>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct MergeRequest {
-    paths: Vec<String>,
-    output_dir: Option<String>,
-    output_exr: bool,
+// 監視スレッドに渡す、検出の判定に使う状態
+#[derive(Clone)]
+struct WatchFilters {
+    recent_events: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+    matcher: Arc<Mutex<ExtensionMatcher>>,
+    timing: Arc<Mutex<WatchTiming>>,
+    own_outputs: Arc<OwnOutputs>,
+    ignore_dirs: Arc<Mutex<Vec<PathBuf>>>,
+    last_detected_at: Arc<Mutex<Option<String>>>,
+    growing_files: Arc<GrowingFiles>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct MergeResult {
-    output_png_path: String,
-    output_exr_path: Option<String>,
-    width: u32,
-    height: u32,
-    merged_at: String,
+impl WatcherState {
+    fn filters(&self) -> WatchFilters {
+        WatchFilters {
+            recent_events: self.recent_events.clone(),
+            matcher: self.matcher.clone(),
+            timing: self.timing.clone(),
+            own_outputs: self.own_outputs.clone(),
+            ignore_dirs: self.ignore_dirs.clone(),
+            last_detected_at: self.last_detected_at.clone(),
+            growing_files: self.growing_files.clone(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[serde(rename_all = "camelCase")]
 struct ImageStat {
     path: String,
     average_luma: f32,
 }
 
+struct LaunchState(Mutex<Option<Result<LaunchRequest, LaunchRejected>>>);
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+struct SecondInstance {
+    args: Vec<String>,
+    cwd: String,
+}
+
 pub fn run() {
     tauri::Builder::default()
+        // 二重起動すると同じフォルダを重複して監視するため、後から起動した側は引数を渡して終了する
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            match launch::parse_args(&args, Path::new(&cwd)) {
+                Ok(Some(request)) => {
+                    let _ = app.emit(OPEN_FILES_EVENT, request);
+                }
+                Ok(None) => {}
+                Err(rejected) => {
+                    let _ = app.emit(LAUNCH_REJECTED_EVENT, rejected);
+                }
+            }
+            let _ = app.emit(SECOND_INSTANCE_EVENT, SecondInstance { args, cwd });
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.unminimize();
+                let _ = window.set_focus();
+            }
+        }))
         .plugin(tauri_plugin_dialog::init())
         .manage(WatcherState::default())
+        .manage(ResourceMonitor::default())
+        .manage(IdleMonitor::default())
+        .manage(GroupStore::default())
+        .manage(GroupTimeouts::default())
+        .manage(AnalysisJobs::default())
+        .manage(Dashboard::default())
+        .setup(|app| {
+            // 起動直後はフロントエンドがまだイベントを受け取れないため、取りに来るまで保持する
+            panic_report::install_hook(app.path().app_log_dir()?);
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let args: Vec<String> = std::env::args().collect();
+            app.manage(LaunchState(Mutex::new(
+                launch::parse_args(&args, &cwd).transpose(),
+            )));
+            let config_dir = app.path().app_config_dir()?;
+            let config = ConfigStore::load(config_dir.join("config.json"));
+            decode::set_limits(config.snapshot()?.settings.image_limits);
+            let data_dir = app.path().app_data_dir()?;
+            let workspace = Workspace::new(data_dir.clone());
+            if let Some(id) = config.snapshot()?.settings.active_project {
+                let _ = workspace.open(&id);
+            }
+            app.manage(config);
+            app.manage(workspace);
+            app.manage(StatsStore::load(data_dir.join("stats.json")));
+            app.manage(DetectionLog::open(data_dir.join("detections.jsonl")));
+            let jobs = JobTracker::new(data_dir.join("pending_jobs.json"));
+            let backlog_handle = app.handle().clone();
+            jobs.set_backlog_listener(move |backlog| {
+                let _ = backlog_handle.emit(BACKLOG_EVENT, backlog);
+            });
+            app.manage(jobs);
+            start_idle_monitor(app.handle());
+            start_dashboard(app.handle());
+            start_growing_file_monitor(app.handle());
+            start_app_pause_monitor(app.handle());
+            // 前回の異常終了で残った一時ファイルと古いキャッシュを、起動を待たせずに片付ける
+            let project = app.state::<Workspace>().active()?;
+            let settings = app.state::<ConfigStore>().snapshot()?.settings;
+            let targets = cleanup_targets(app.handle(), &settings, project.as_ref(), true)?;
+            std::thread::spawn(move || {
+                let now = std::time::SystemTime::now();
+                maintenance::cleanup(&targets, CleanupPolicy::STARTUP, now);
+                if let Some(cache_dir) = &targets.cache_dir {
+                    workdir::prune(cache_dir, now);
+                }
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             watcher_set_folder,
             watcher_start,
             watcher_stop,
             watcher_is_running,
+            watcher_is_idle,
+            watcher_inject_event,
+            watch_folder_stats,
+            detection_log_entries,
+            detection_log_export,
             analyze_images,
+            analyze_images_stream,
+            analyze_images_cancel,
+            recommend_bracket,
+            get_thumbnail,
+            copy_result_to_clipboard,
+            get_hdr_preview,
+            get_soft_proof,
+            cache_stats,
+            validate_merge_inputs,
+            suggest_frame_exclusions,
+            group_images,
+            settings_get,
+            settings_set,
+            write_root_add,
+            write_root_remove,
+            plugin_allow_add,
+            plugin_allow_remove,
+            presets_list,
+            preset_save,
+            preset_delete,
+            config_export,
+            config_import,
+            recent_folders_list,
+            project_create,
+            project_list,
+            project_open,
+            project_close,
+            project_current,
+            history_list,
+            history_search,
+            history_export,
+            history_set_rating,
+            history_set_tags,
+            history_send_to,
+            export_social,
+            history_reprocess_stale,
+            recent_outputs_list,
+            stats_get,
+            stats_reset,
+            take_launch_request,
+            handle_dropped_paths,
+            delete_to_recycle,
+            maintenance_cleanup,
+            jobs_pending,
+            jobs_resume,
+            jobs_clear_pending,
+            list_merge_algorithms,
+            get_capabilities,
+            api_negotiate,
             merge_hdr,
+            merge_hdr_v2,
+            merge_cancel,
+            merge_backlog,
+            dashboard_summary,
+            auto_pause_status,
+            group_create,
+            group_add_file,
+            group_remove_file,
+            group_list,
+            group_delete,
+            group_merge,
+            merge_prefetch,
+            merge_sweep,
+            merge_timelapse,
+            merge_batch,
+            deflicker_sequence,
+            compare_images,
+            generate_compare_tiles,
+            generate_false_color,
+            probe_pixels,
+            generate_test_bracket,
         ])
-        .run(tauri::generate_context!())
-        .expect("error running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error running tauri application")
+        .run(|app_handle, event| {
+            if let RunEvent::ExitRequested { api, .. } = event {
+                handle_exit_requested(app_handle, api);
+            }
+        });
 }
 
-#[tauri::command]
-async fn watcher_set_folder(state: State<'_, WatcherState>, folder: String) -> Result<(), String> {
-    let path = PathBuf::from(folder);
-    if !path.exists() || !path.is_dir() {
-        return Err("フォルダが存在しません".to_string());
+// 合成中・合成待ちのときに終了が要求された場合は書き出しの完了を待ち、
+// 間に合わなければ中止して後片付けを待ち、枠を待っていたジョブとともに保留ジョブとして保存してから終了する
+fn handle_exit_requested(app_handle: &AppHandle, api: ExitRequestApi) {
+    let jobs = app_handle.state::<JobTracker>();
+    if jobs.is_shutting_down() || jobs.unfinished_count() == 0 {
+        return;
     }
 
-    let mut folder_state = state.folder.lock().map_err(|_| "lock error")?;
+    api.prevent_exit();
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || {
+        let jobs = app_handle.state::<JobTracker>();
+        let _ = app_handle.emit(SHUTDOWN_WAITING_EVENT, jobs.unfinished_count());
+        let _ = jobs.shutdown(SHUTDOWN_GRACE, SHUTDOWN_CANCEL_GRACE);
+        app_handle.exit(0);
+    });
+}
+
+#[tauri::command]
+async fn watcher_set_folder(
+    app_handle: AppHandle,
+    state: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
+    folder: String,
+) -> Result<(), String> {
+    let path = paths::input_dir(&folder)?;
+    record_activity(&app_handle);
+
+    let mut folder_state = state.folder.lock().unwrap_or_else(PoisonError::into_inner);
     *folder_state = Some(path);
+    let _ = config.update(|data| {
+        config::push_recent(&mut data.settings.recent_folders, &folder);
+        Ok(())
+    });
     Ok(())
 }
 
 #[tauri::command]
 async fn watcher_start(
     state: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
     app_handle: AppHandle,
 ) -> Result<(), String> {
     let folder = {
-        let folder_state = state.folder.lock().map_err(|_| "lock error")?;
+        let folder_state = state.folder.lock().unwrap_or_else(PoisonError::into_inner);
         folder_state.clone().ok_or("監視フォルダが未設定です")?
     };
+    record_activity(&app_handle);
 
-    let mut is_watching = state.is_watching.lock().map_err(|_| "lock error")?;
+    let mut is_watching = state
+        .is_watching
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
     if *is_watching {
         return Err("既に監視中です".to_string());
     }
 
-    let recent_events = state.recent_events.clone();
+    let settings = config.snapshot()?.settings;
+    *state.matcher.lock().unwrap_or_else(PoisonError::into_inner) =
+        ExtensionMatcher::from_settings(&settings.watch_extensions);
+    *state.timing.lock().unwrap_or_else(PoisonError::into_inner) = settings.watch_timing.clone();
+    *state
+        .ignore_dirs
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) =
+        watch_filter::ignore_dirs(&settings.watch_ignore_dirs);
+
+    let filters = state.filters();
     let app_handle_clone = app_handle.clone();
 
     let mut watcher = RecommendedWatcher::new(
         move |res: Result<Event, notify::Error>| {
-            if let Ok(event) = res {
-                let should_emit = matches!(
-                    event.kind,
-                    EventKind::Create(_) | EventKind::Modify(_)
-                );
-
-                if !should_emit {
+            let event = match res {
+                Ok(event) => event,
+                Err(_) => {
+                    app_handle_clone.state::<Dashboard>().record_error();
                     return;
                 }
+            };
+            let kind = match event.kind {
+                EventKind::Create(_) => DetectionKind::Create,
+                EventKind::Modify(_) => DetectionKind::Modify,
+                _ => return,
+            };
 
-                for path in event.paths {
-                    if !should_process_file(&path) {
-                        continue;
-                    }
-
-                    if !debounce_check(&path, &recent_events) {
-                        continue;
-                    }
-
-                    let _ = app_handle_clone.emit(
-                        "hdr://file-detected",
-                        path.to_string_lossy().to_string(),
-                    );
-                }
+            for path in event.paths {
+                handle_detection(&app_handle_clone, &filters, kind, &path);
             }
         },
         notify::Config::default(),
@@ -125,7 +469,7 @@ async fn watcher_start(
         .watch(&folder, RecursiveMode::Recursive)
         .map_err(|e| e.to_string())?;
 
-    let mut watcher_state = state.watcher.lock().map_err(|_| "lock error")?;
+    let mut watcher_state = state.watcher.lock().unwrap_or_else(PoisonError::into_inner);
     *watcher_state = Some(watcher);
     *is_watching = true;
 
@@ -133,9 +477,13 @@ async fn watcher_start(
 }
 
 #[tauri::command]
-async fn watcher_stop(state: State<'_, WatcherState>) -> Result<(), String> {
-    let mut watcher_state = state.watcher.lock().map_err(|_| "lock error")?;
-    let mut is_watching = state.is_watching.lock().map_err(|_| "lock error")?;
+async fn watcher_stop(app_handle: AppHandle, state: State<'_, WatcherState>) -> Result<(), String> {
+    record_activity(&app_handle);
+    let mut watcher_state = state.watcher.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut is_watching = state
+        .is_watching
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
     *watcher_state = None;
     *is_watching = false;
     Ok(())
@@ -143,140 +491,1637 @@ async fn watcher_stop(state: State<'_, WatcherState>) -> Result<(), String> {
 
 #[tauri::command]
 async fn watcher_is_running(state: State<'_, WatcherState>) -> Result<bool, String> {
-    let is_watching = state.is_watching.lock().map_err(|_| "lock error")?;
+    let is_watching = state
+        .is_watching
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
     Ok(*is_watching)
 }
 
 #[tauri::command]
-async fn analyze_images(paths: Vec<String>) -> Result<Vec<ImageStat>, String> {
+async fn watcher_is_idle(idle: State<'_, IdleMonitor>) -> Result<bool, String> {
+    Ok(idle.is_idle())
+}
+
+// フロントエンドの E2E テスト用。ファイルを作らずに、監視で受け取ったのと同じ判定・通知を行う
+#[tauri::command]
+async fn watcher_inject_event(
+    state: State<'_, WatcherState>,
+    app_handle: AppHandle,
+    path: String,
+    kind: DetectionKind,
+) -> Result<DetectionOutcome, String> {
+    if !cfg!(debug_assertions) {
+        return Err("watcher_inject_event は開発ビルドでのみ使えます".to_string());
+    }
+    let path = PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err("path には絶対パスを指定してください".to_string());
+    }
+    Ok(handle_detection(&app_handle, &state.filters(), kind, &path))
+}
+
+#[tauri::command]
+async fn watch_folder_stats(
+    watcher: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+) -> Result<WatchFolderStats, String> {
+    let settings = config.snapshot()?.settings;
+    let project = workspace.active()?;
+    let watch_folder = match watcher
+        .folder
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    {
+        Some(folder) => Some(folder),
+        None => project
+            .as_ref()
+            .and_then(|project| project.watch_folder.clone())
+            .or_else(|| settings.watch_folder.clone())
+            .map(PathBuf::from),
+    };
+    let output_folder = project
+        .as_ref()
+        .and_then(|project| project.output_dir.clone())
+        .or_else(|| settings.output_dir.clone())
+        .map(PathBuf::from);
+    let rules = project
+        .and_then(|project| project.grouping)
+        .unwrap_or_else(|| settings.grouping.clone());
+    let history = workspace.with_history(|history| history.entries().to_vec())?;
+    let last_detected_at = watcher
+        .last_detected_at
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+
+    folder_stats::collect(&StatsSources {
+        watch_folder: watch_folder.as_deref(),
+        output_folder: output_folder.as_deref(),
+        matcher: &ExtensionMatcher::from_settings(&settings.watch_extensions),
+        ignore_dirs: &watch_filter::ignore_dirs(&settings.watch_ignore_dirs),
+        history: &history,
+        rules: &rules,
+        last_detected_at,
+    })
+}
+
+#[tauri::command]
+async fn detection_log_entries(
+    log: State<'_, DetectionLog>,
+    limit: Option<usize>,
+) -> Result<Vec<Detection>, String> {
+    log.entries(limit)
+}
+
+#[tauri::command]
+async fn detection_log_export(
+    log: State<'_, DetectionLog>,
+    config: State<'_, ConfigStore>,
+    path: String,
+) -> Result<usize, String> {
+    let path = writable_path(&config, &path)?;
+    log.export(&path)
+}
+
+#[tauri::command]
+async fn analyze_images(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    paths: Vec<String>,
+) -> Result<Vec<ImageStat>, String> {
+    if paths.is_empty() {
+        return Err("解析対象がありません".to_string());
+    }
+    let cache = open_cache(&app_handle, &config, disk_cache::ANALYSIS_CACHE)?;
+
+    // 画像をすべてデコードするため、非同期ランタイムのスレッドを塞がないよう別スレッドで行う
+    tauri::async_runtime::spawn_blocking(move || {
+        paths
+            .iter()
+            .map(|path| analyze_image(&cache, path))
+            .collect()
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// 結果を hdr://analysis-progress で少しずつ通知し、すぐにジョブ ID を返す。
+// 読み込めないファイルがあっても止めず、その通知の errors に入れる
+#[tauri::command]
+async fn analyze_images_stream(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    jobs: State<'_, AnalysisJobs>,
+    paths: Vec<String>,
+) -> Result<String, String> {
     if paths.is_empty() {
         return Err("解析対象がありません".to_string());
     }
+    let cache = open_cache(&app_handle, &config, disk_cache::ANALYSIS_CACHE)?;
+    let (job_id, cancelled) = jobs.start()?;
+
+    let id = job_id.clone();
+    std::thread::spawn(move || {
+        let analyzed = panic_report::catch(|| {
+            analysis_stream::run(
+                &id,
+                &paths,
+                &cancelled,
+                |path| analyze_image(&cache, path),
+                |batch| {
+                    let _ = app_handle.emit(ANALYSIS_PROGRESS_EVENT, batch);
+                },
+            )
+        });
+        if let Err(panic) = analyzed {
+            report_panic(&app_handle, &panic, Some(id.clone()), "analysis");
+        }
+        app_handle.state::<AnalysisJobs>().finish(&id);
+    });
+    Ok(job_id)
+}
+
+#[tauri::command]
+async fn analyze_images_cancel(
+    jobs: State<'_, AnalysisJobs>,
+    job_id: String,
+) -> Result<bool, String> {
+    jobs.cancel(&job_id)
+}
 
-    let mut stats = Vec::new();
-    for path in paths {
-        let image = load_rgb16(&path)?;
-        let average_luma = calculate_average_luma(&image);
-        stats.push(ImageStat { path, average_luma });
+// 試し撮りの 1 枚から、場面の明るさの幅を覆うブラケットの枚数と間隔を勧める
+#[tauri::command]
+async fn recommend_bracket(path: String) -> Result<BracketRecommendation, String> {
+    paths::input_file(&path)?;
+    Ok(bracket_recommend::recommend(&load_rgb16(&path)?))
+}
+
+fn analyze_image(cache: &DiskCache, path: &str) -> Result<ImageStat, String> {
+    let source = paths::input_file(path)?;
+    let entry = disk_cache::entry_name(&source, "averageLuma", "json")?;
+    let cached = cache
+        .get(&entry)
+        .and_then(|cached| std::fs::read_to_string(cached).ok())
+        .and_then(|text| serde_json::from_str::<f32>(&text).ok());
+    let average_luma = match cached {
+        Some(average_luma) => average_luma,
+        None => {
+            let average_luma = calculate_average_luma(&load_rgb16(path)?);
+            let _ = cache.put(&entry, &source, |partial| {
+                std::fs::write(partial, average_luma.to_string()).map_err(|e| e.to_string())
+            });
+            average_luma
+        }
+    };
+    Ok(ImageStat {
+        path: path.to_string(),
+        average_luma,
+    })
+}
+
+#[tauri::command]
+async fn get_thumbnail(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    path: String,
+    max_size: Option<u32>,
+) -> Result<String, String> {
+    let max_size = max_size.unwrap_or(256).clamp(16, 2048);
+    let cache = open_cache(&app_handle, &config, disk_cache::THUMBNAIL_CACHE)?;
+    let source = paths::input_file(&path)?;
+    let entry = disk_cache::entry_name(&source, &max_size.to_string(), "png")?;
+    if let Some(cached) = cache.get(&entry) {
+        return Ok(cached.to_string_lossy().to_string());
     }
 
-    Ok(stats)
+    // 埋め込みのサムネイルがなければ原寸でデコードするため、別スレッドで作る
+    tauri::async_runtime::spawn_blocking(move || {
+        let image = match embedded_thumbnail::read(&paths::extended(Path::new(&path)), max_size) {
+            Some(embedded) => embedded,
+            None => image::DynamicImage::ImageRgb16(load_rgb16(&path)?),
+        };
+        let thumbnail = image.thumbnail(max_size, max_size).to_rgb8();
+        let written = cache.put(&entry, &source, |partial| {
+            thumbnail
+                .save_with_format(partial, image::ImageFormat::Png)
+                .map_err(|e| e.to_string())
+        })?;
+        Ok(written.to_string_lossy().to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
+// 履歴の id か画像のパスのどちらか一方で指定した合成結果を、縮小した 8bit の画像としてクリップボードに置く
 #[tauri::command]
-async fn merge_hdr(request: MergeRequest) -> Result<MergeResult, String> {
-    if request.paths.len() < 2 {
-        return Err("合成には最低2枚必要です".to_string());
+async fn copy_result_to_clipboard(
+    app_handle: AppHandle,
+    workspace: State<'_, Workspace>,
+    id: Option<u64>,
+    path: Option<String>,
+) -> Result<ClipboardCopy, String> {
+    let source_path = match (id, path) {
+        (Some(id), None) => workspace.with_history(|history| {
+            history
+                .entries()
+                .iter()
+                .find(|entry| entry.id == id)
+                .map(|entry| entry.output_png_path.clone())
+                .ok_or_else(|| format!("履歴が見つかりません: {}", id))
+        })??,
+        (None, Some(path)) => path,
+        _ => return Err("id と path のどちらか一方を指定してください".to_string()),
+    };
+    let temp_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| e.to_string())?
+        .join("clipboard");
+    let source = source_path.clone();
+    let (width, height) = tokio::task::spawn_blocking(move || {
+        clipboard::copy_image(&load_rgb16(&source)?, &temp_dir)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    Ok(ClipboardCopy {
+        source_path,
+        width,
+        height,
+    })
+}
+
+// HDR 対応ディスプレイ向けに、BT.2020・PQ の 16bit PNG（cICP 付き）を作ってパスを返す
+#[tauri::command]
+async fn get_hdr_preview(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    path: String,
+    params: Option<HdrPreviewParams>,
+) -> Result<String, String> {
+    let params = params.unwrap_or_default();
+    params.validate()?;
+    let cache = open_cache(&app_handle, &config, disk_cache::THUMBNAIL_CACHE)?;
+    let variant = format!(
+        "hdr:{}:{}:{}",
+        params.max_size, params.sdr_white_nits, params.peak_nits
+    );
+    // 合成結果の PNG を逆にトーンマップせず、トーンマップ前の放射輝度（隣の DNG）か EXR から作る
+    let linear_path = hdr_preview::linear_source(&path)?;
+    let source = paths::input_file(&linear_path)?;
+    let entry = disk_cache::entry_name(&source, &variant, "png")?;
+    if let Some(cached) = cache.get(&entry) {
+        return Ok(cached.to_string_lossy().to_string());
     }
-    if request.paths.len() > 5 {
-        return Err("合成は最大5枚までです".to_string());
+
+    let preview = hdr_preview::render(&hdr_preview::load(&linear_path)?, &params);
+    let written = cache.put(&entry, &source, |partial| {
+        hdr_preview::write_pq_png(&preview, partial)
+    })?;
+    Ok(written.to_string_lossy().to_string())
+}
+
+// 書き出した SDR の画像が、別の表示環境（カラーマネジメントの有無・色域）でどう見えるかを再現したプレビューを作る
+#[tauri::command]
+async fn get_soft_proof(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    path: String,
+    params: Option<SoftProofParams>,
+) -> Result<SoftProofResult, String> {
+    let params = params.unwrap_or_default();
+    if !(16..=8192).contains(&params.max_size) {
+        return Err("maxSize は 16〜8192 で指定してください".to_string());
     }
+    let profile = match &params.icc_path {
+        Some(icc_path) => DisplayProfile::load_icc(&paths::input_file(icc_path)?)?,
+        None => DisplayProfile::builtin(params.target),
+    };
+    let (preview, out_of_gamut_fraction) = soft_proof::proof(
+        &load_rgb16(&path)?,
+        &profile,
+        params.color_managed,
+        params.max_size,
+    );
 
-    let images: Vec<ImageBuffer<Rgb<u16>, Vec<u16>>> = request
-        .paths
+    let cache = open_cache(&app_handle, &config, disk_cache::THUMBNAIL_CACHE)?;
+    let variant = serde_json::to_string(&params).map_err(|e| e.to_string())?;
+    let source = paths::input_file(&path)?;
+    let entry = disk_cache::entry_name(&source, &variant, "png")?;
+    let written = cache.put(&entry, &source, |partial| {
+        preview
+            .save_with_format(partial, image::ImageFormat::Png)
+            .map_err(|e| e.to_string())
+    })?;
+    Ok(SoftProofResult {
+        path: written.to_string_lossy().to_string(),
+        width: preview.width(),
+        height: preview.height(),
+        out_of_gamut_fraction,
+    })
+}
+
+#[tauri::command]
+async fn cache_stats(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+) -> Result<Vec<CacheUsage>, String> {
+    disk_cache::MANAGED_CACHES
         .iter()
-        .map(|path| load_rgb16(path))
-        .collect::<Result<_, _>>()?;
+        .map(|name| open_cache(&app_handle, &config, name).map(|cache| cache.usage()))
+        .collect()
+}
 
-    let width = images[0].width();
-    let height = images[0].height();
+fn open_cache(
+    app_handle: &AppHandle,
+    config: &ConfigStore,
+    name: &str,
+) -> Result<DiskCache, String> {
+    let cache_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| e.to_string())?;
+    let limits = config.snapshot()?.settings.cache_limits;
+    Ok(DiskCache::new(&cache_dir, name, &limits))
+}
 
-    for image in &images {
-        if image.width() != width || image.height() != height {
-            return Err("画像サイズが一致しません".to_string());
-        }
+#[tauri::command]
+async fn validate_merge_inputs(paths: Vec<String>) -> Result<Vec<InputCheck>, String> {
+    if paths.is_empty() {
+        return Err("検証対象がありません".to_string());
+    }
+    Ok(input_check::validate_inputs(&paths, false, None).checks)
+}
+
+// 有効な入力をブレと食い違いで採点する。勧められたフレームは autoExcludeBadFrames で除いて合成できる
+#[tauri::command]
+async fn suggest_frame_exclusions(paths: Vec<String>) -> Result<FrameQuality, String> {
+    if paths.is_empty() {
+        return Err("検証対象がありません".to_string());
+    }
+    let validated = input_check::validate_inputs(&paths, true, None);
+    let (paths, images): (Vec<String>, Vec<_>) = validated
+        .frames
+        .into_iter()
+        .filter_map(|frame| frame.image.map(|image| (frame.path, image)))
+        .unzip();
+    Ok(frame_quality::score_frames(&paths, &images))
+}
+
+// 枚数が足りないまま時間切れになったグループは、初めて時間切れになったときに hdr://bracket-timeout で通知する。
+// 最後に受け取った inputs で時間切れを待ち続けるので、問い合わせ直さなくても通知が届く
+#[tauri::command]
+async fn group_images(
+    app_handle: AppHandle,
+    timeouts: State<'_, GroupTimeouts>,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    inputs: Vec<GroupInput>,
+    rules: Option<GroupingRules>,
+) -> Result<Vec<BracketGroup>, String> {
+    let rules = match rules {
+        Some(rules) => rules,
+        None => match workspace.active()?.and_then(|project| project.grouping) {
+            Some(rules) => rules,
+            None => config.snapshot()?.settings.grouping,
+        },
+    };
+    // 規則の誤りは保持する前に返す
+    grouping::group_images(&inputs, &rules)?;
+    let start = timeouts.track(&inputs, &rules);
+    let (groups, timed_out) = timeouts.evaluate(Local::now().timestamp_millis())?;
+    for group in &timed_out {
+        let _ = app_handle.emit(BRACKET_TIMEOUT_EVENT, group);
     }
+    if start {
+        let app_handle = app_handle.clone();
+        std::thread::spawn(move || {
+            app_handle.state::<GroupTimeouts>().run(|group| {
+                let _ = app_handle.emit(BRACKET_TIMEOUT_EVENT, group);
+            });
+        });
+    }
+    Ok(groups)
+}
 
-    let mut merged = ImageBuffer::<Rgb<u16>, Vec<u16>>::new(width, height);
+#[tauri::command]
+async fn settings_get(config: State<'_, ConfigStore>) -> Result<Settings, String> {
+    Ok(config.snapshot()?.settings)
+}
 
-    for (x, y, pixel) in merged.enumerate_pixels_mut() {
-        let mut sum_r: u64 = 0;
-        let mut sum_g: u64 = 0;
-        let mut sum_b: u64 = 0;
+#[tauri::command]
+async fn settings_set(config: State<'_, ConfigStore>, settings: Settings) -> Result<(), String> {
+    let limits = settings.image_limits;
+    config.update(|data| config::replace_settings(&mut data.settings, settings))?;
+    decode::set_limits(limits);
+    Ok(())
+}
 
-        for image in &images {
-            let p = image.get_pixel(x, y);
-            sum_r += p[0] as u64;
-            sum_g += p[1] as u64;
-            sum_b += p[2] as u64;
+// 書き込み先の制限に加えるフォルダを OS のダイアログで選ばせる。キャンセルしたら変えない
+#[tauri::command]
+async fn write_root_add(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+) -> Result<Vec<String>, String> {
+    let Some(folder) = app_handle
+        .dialog()
+        .file()
+        .set_title("書き込みを許可するフォルダ")
+        .blocking_pick_folder()
+    else {
+        return Ok(config.snapshot()?.settings.allowed_write_roots);
+    };
+    let folder = folder
+        .into_path()
+        .map_err(|e| e.to_string())?
+        .to_string_lossy()
+        .to_string();
+    config.update(|data| {
+        let roots = &mut data.settings.allowed_write_roots;
+        if !roots.contains(&folder) {
+            roots.push(folder);
         }
+        Ok(roots.clone())
+    })
+}
 
-        let count = images.len() as u64;
-        let avg_r = (sum_r / count) as u16;
-        let avg_g = (sum_g / count) as u16;
-        let avg_b = (sum_b / count) as u16;
+// 書き込み先の制限からフォルダを外す。最後の 1 つを外すと制限がなくなるため、OS のダイアログで確かめる
+#[tauri::command]
+async fn write_root_remove(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    path: String,
+) -> Result<Vec<String>, String> {
+    let confirmed = app_handle
+        .dialog()
+        .message(format!("{} への書き込みの許可を取り消しますか？", path))
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::OkCancel)
+        .blocking_show();
+    config.update(|data| {
+        let roots = &mut data.settings.allowed_write_roots;
+        if confirmed {
+            roots.retain(|root| root != &path);
+        }
+        Ok(roots.clone())
+    })
+}
 
-        *pixel = Rgb([avg_r, avg_g, avg_b]);
+// external ステージで実行してよい実行ファイルを OS のダイアログで選ばせる。キャンセルしたら変えない
+#[tauri::command]
+async fn plugin_allow_add(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+) -> Result<Vec<String>, String> {
+    let Some(file) = app_handle
+        .dialog()
+        .file()
+        .set_title("実行を許可する外部コマンド")
+        .blocking_pick_file()
+    else {
+        return Ok(config.snapshot()?.settings.allowed_plugins);
+    };
+    let file = file
+        .into_path()
+        .map_err(|e| e.to_string())?
+        .to_string_lossy()
+        .to_string();
+    config.update(|data| {
+        let plugins = &mut data.settings.allowed_plugins;
+        if !plugins.contains(&file) {
+            plugins.push(file);
+        }
+        Ok(plugins.clone())
+    })
+}
+
+#[tauri::command]
+async fn plugin_allow_remove(
+    config: State<'_, ConfigStore>,
+    path: String,
+) -> Result<Vec<String>, String> {
+    config.update(|data| {
+        let plugins = &mut data.settings.allowed_plugins;
+        plugins.retain(|plugin| plugin != &path);
+        Ok(plugins.clone())
+    })
+}
+
+// プロジェクトを開いている間、プリセットはそのプロジェクトのものを扱う
+#[tauri::command]
+async fn presets_list(
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+) -> Result<Vec<Preset>, String> {
+    match workspace.active()? {
+        Some(project) => Ok(project.presets),
+        None => Ok(config.snapshot()?.presets),
+    }
+}
+
+#[tauri::command]
+async fn preset_save(
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    preset: Preset,
+) -> Result<(), String> {
+    let name = preset.name.clone();
+    if workspace.active()?.is_some() {
+        workspace.update_active(|project| config::save_preset(&mut project.presets, preset))?;
+    } else {
+        config.update(|data| config::save_preset(&mut data.presets, preset))?;
+    }
+    if config.snapshot()?.settings.reprocess_on_preset_change {
+        workspace.with_history(|history| history.mark_stale(&name))??;
     }
+    Ok(())
+}
+
+#[tauri::command]
+async fn preset_delete(
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    name: String,
+) -> Result<(), String> {
+    if workspace.active()?.is_some() {
+        workspace.update_active(|project| config::remove_preset(&mut project.presets, &name))?;
+        return Ok(());
+    }
+    config.update(|data| config::delete_preset(data, &name))
+}
+
+#[tauri::command]
+async fn project_create(
+    workspace: State<'_, Workspace>,
+    name: String,
+    watch_folder: Option<String>,
+    output_dir: Option<String>,
+) -> Result<Project, String> {
+    workspace.create(&name, watch_folder, output_dir)
+}
+
+#[tauri::command]
+async fn project_list(workspace: State<'_, Workspace>) -> Result<Vec<Project>, String> {
+    workspace.list()
+}
+
+#[tauri::command]
+async fn project_open(
+    workspace: State<'_, Workspace>,
+    config: State<'_, ConfigStore>,
+    id: String,
+) -> Result<Project, String> {
+    let project = workspace.open(&id)?;
+    let _ = config.update(|data| {
+        data.settings.active_project = Some(project.id.clone());
+        Ok(())
+    });
+    Ok(project)
+}
+
+#[tauri::command]
+async fn project_close(
+    workspace: State<'_, Workspace>,
+    config: State<'_, ConfigStore>,
+) -> Result<(), String> {
+    workspace.close()?;
+    let _ = config.update(|data| {
+        data.settings.active_project = None;
+        Ok(())
+    });
+    Ok(())
+}
+
+#[tauri::command]
+async fn project_current(workspace: State<'_, Workspace>) -> Result<Option<Project>, String> {
+    workspace.active()
+}
+
+#[tauri::command]
+async fn history_list(
+    workspace: State<'_, Workspace>,
+    filter: Option<HistoryFilter>,
+) -> Result<Vec<HistoryEntry>, String> {
+    let filter = filter.unwrap_or_default();
+    workspace.with_history(|history| {
+        Ok(filter
+            .apply(history.entries())?
+            .into_iter()
+            .cloned()
+            .collect())
+    })?
+}
 
-    let output_dir = if let Some(dir) = request.output_dir {
-        PathBuf::from(dir)
+#[tauri::command]
+async fn history_search(
+    workspace: State<'_, Workspace>,
+    query: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    tags: Option<Vec<String>>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<HistoryPage, String> {
+    let filter = HistoryFilter {
+        query,
+        from,
+        to,
+        tags: tags.unwrap_or_default(),
+        ..Default::default()
+    };
+    workspace.with_history(|history| {
+        history::search(history.entries(), &filter, offset.unwrap_or(0), limit)
+    })?
+}
+
+#[tauri::command]
+async fn history_export(
+    workspace: State<'_, Workspace>,
+    config: State<'_, ConfigStore>,
+    format: ReportFormat,
+    path: String,
+    filter: Option<HistoryFilter>,
+) -> Result<usize, String> {
+    let path = writable_path(&config, &path)?;
+    let filter = filter.unwrap_or_default();
+    workspace.with_history(|history| {
+        report::export_history(&filter.apply(history.entries())?, format, &path)
+    })?
+}
+
+#[tauri::command]
+async fn history_set_rating(
+    workspace: State<'_, Workspace>,
+    id: u64,
+    stars: u8,
+) -> Result<HistoryEntry, String> {
+    workspace.with_history(|history| history.set_rating(id, stars))?
+}
+
+#[tauri::command]
+async fn history_set_tags(
+    workspace: State<'_, Workspace>,
+    id: u64,
+    tags: Vec<String>,
+) -> Result<HistoryEntry, String> {
+    workspace.with_history(|history| history.set_tags(id, tags))?
+}
+
+// 履歴の合成結果を送り先へ書き出す。送り先のファイル名の {groupName} は先頭の入力ファイル名
+#[tauri::command]
+async fn history_send_to(
+    watcher: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    id: u64,
+    target: String,
+) -> Result<SentOutput, String> {
+    let entry = workspace.with_history(|history| {
+        history
+            .entries()
+            .iter()
+            .find(|entry| entry.id == id)
+            .cloned()
+            .ok_or_else(|| format!("履歴が見つかりません: {}", id))
+    })??;
+    let settings = config.snapshot()?.settings;
+    let target = send_to::find_targets(&settings.send_targets, &[target])?.remove(0);
+    let protected = if settings.protect_watch_folder {
+        protected_folders(&watcher, &settings, &workspace)?
     } else {
-        let first_path = PathBuf::from(&request.paths[0]);
-        first_path
+        Vec::new()
+    };
+    let image = load_rgb16(&entry.output_png_path)?;
+    let group_name = output_path::default_group_name(&entry.input_paths);
+    send_to::send(
+        &image,
+        &target,
+        &SendContext {
+            group_name: &group_name,
+            algorithm: &entry.algorithm,
+            protected_dirs: &protected,
+            write_roots: &write_roots(&config)?,
+            own_outputs: Some(&watcher.own_outputs),
+        },
+    )
+}
+
+// 履歴の合成結果から、サービスごとの大きさにそろえてシャープをかけた JPEG を書き出す。
+// outputDir が未指定なら合成結果と同じフォルダ
+#[tauri::command]
+async fn export_social(
+    watcher: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    history_id: u64,
+    platform: SocialPlatform,
+    output_dir: Option<String>,
+) -> Result<SocialExport, String> {
+    let entry = workspace.with_history(|history| {
+        history
+            .entries()
+            .iter()
+            .find(|entry| entry.id == history_id)
+            .cloned()
+            .ok_or_else(|| format!("履歴が見つかりません: {}", history_id))
+    })??;
+    let settings = config.snapshot()?.settings;
+    let protected = if settings.protect_watch_folder {
+        protected_folders(&watcher, &settings, &workspace)?
+    } else {
+        Vec::new()
+    };
+    let source = Path::new(&entry.output_png_path);
+    let dir = match output_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => source
             .parent()
             .ok_or("出力先の決定に失敗しました")?
-            .to_path_buf()
+            .to_path_buf(),
     };
+    let base_name = source
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let image = load_rgb16(&entry.output_png_path)?;
+    social_export::export(
+        &image,
+        platform,
+        &ExportTarget {
+            dir: &dir,
+            base_name: &base_name,
+            protected_dirs: &protected,
+            write_roots: &write_roots(&config)?,
+            own_outputs: Some(&watcher.own_outputs),
+        },
+    )
+}
+
+#[tauri::command]
+async fn config_export(config: State<'_, ConfigStore>, path: String) -> Result<(), String> {
+    config::export_bundle(&config.snapshot()?, &writable_path(&config, &path)?)
+}
+
+#[tauri::command]
+async fn config_import(
+    config: State<'_, ConfigStore>,
+    path: String,
+) -> Result<ImportSummary, String> {
+    let imported = config::read_bundle(&paths::input_file(&path)?)?;
+    let summary = config.update(|data| config::apply_bundle(data, imported))?;
+    decode::set_limits(config.snapshot()?.settings.image_limits);
+    Ok(summary)
+}
+
+#[tauri::command]
+async fn list_merge_algorithms() -> Result<Vec<AlgorithmInfo>, String> {
+    Ok(algorithms::list())
+}
 
-    if !output_dir.exists() {
-        std::fs::create_dir_all(&output_dir).map_err(|e| e.to_string())?;
+// フロントエンドが扱える API の版を受け取り、使う版と追加の機能を返す
+#[tauri::command]
+async fn api_negotiate(client_version: u32) -> Result<ApiNegotiation, String> {
+    api_version::negotiate(client_version)
+}
+
+#[tauri::command]
+async fn get_capabilities() -> Result<Capabilities, String> {
+    Ok(capabilities::get())
+}
+
+#[tauri::command]
+async fn merge_hdr(app_handle: AppHandle, request: MergeRequest) -> Result<MergeResult, String> {
+    run_merge_job(&app_handle, request).await
+}
+
+#[tauri::command]
+async fn merge_hdr_v2(
+    app_handle: AppHandle,
+    request: MergeRequestV2,
+) -> Result<MergeResult, String> {
+    run_merge_job(&app_handle, request.into_request()?).await
+}
+
+// 設定を反映して順番待ちのあと合成し、統計・履歴・最近の出力先に記録する
+async fn run_merge_job(
+    app_handle: &AppHandle,
+    mut request: MergeRequest,
+) -> Result<MergeResult, String> {
+    let watcher = app_handle.state::<WatcherState>();
+    let jobs = app_handle.state::<JobTracker>();
+    let config = app_handle.state::<ConfigStore>();
+    let workspace = app_handle.state::<Workspace>();
+    prepare_request(&mut request, &config, &watcher, &workspace, &jobs)?;
+    record_activity(app_handle);
+    let progress_handle = app_handle.clone();
+    request.progress = ProgressReporter::new(request.job_id.clone(), move |progress| {
+        let _ = progress_handle.emit(MERGE_PROGRESS_EVENT, progress);
+    });
+    let _slot = jobs
+        .wait_for_slot(&request, max_concurrent_jobs(&config)?)
+        .await?;
+    // 枠を待っている次の合成の入力を、この合成と並行して読んでおく
+    if let Some(next) = jobs.next_queued_paths() {
+        prefetch_next(app_handle, &request.paths, next)?;
+    }
+    let _job = jobs.begin(&request)?;
+    start_resource_monitor(app_handle);
+    let started = Instant::now();
+    let (request, result) = merge_on_worker(app_handle, request).await?;
+    record_activity(app_handle);
+    record_outcome(app_handle, &request, started.elapsed(), &result);
+    if let Ok(merged) = &result {
+        let _ = workspace.with_history(|history| history.record(&request, merged));
+        if let Some(output_dir) = Path::new(&merged.output_png_path).parent() {
+            let _ = config.update(|data| {
+                config::push_recent(
+                    &mut data.settings.recent_outputs,
+                    &output_dir.to_string_lossy(),
+                );
+                Ok(())
+            });
+        }
+    }
+    result
+}
+
+// 合成の成否と所要時間を統計とダッシュボードに記録する。中止はダッシュボードの失敗に数えない
+fn record_outcome(
+    app_handle: &AppHandle,
+    request: &MergeRequest,
+    elapsed: Duration,
+    result: &Result<MergeResult, String>,
+) {
+    let outcome = result.as_ref().map(|_| ()).map_err(|e| e.as_str());
+    let _ = app_handle
+        .state::<StatsStore>()
+        .record(request.paths.len(), elapsed, outcome);
+    let dashboard = app_handle.state::<Dashboard>();
+    match result {
+        Ok(merged) => dashboard.record_result(&merged.output_png_path),
+        Err(e) if e != CANCELLED => dashboard.record_error(),
+        Err(_) => {}
     }
+}
 
-    let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let base_name = format!("hdr_merge_{}", timestamp);
-    let png_path = output_dir.join(format!("{}.png", base_name));
-    let exr_path = output_dir.join(format!("{}.exr", base_name));
+// 合成は重い同期処理なので、非同期ランタイムのスレッドを塞がないよう spawn_blocking のスレッドで行う。
+// 作業フォルダを書き込んだ要求も返す
+async fn merge_on_worker(
+    app_handle: &AppHandle,
+    mut request: MergeRequest,
+) -> Result<(MergeRequest, Result<MergeResult, String>), String> {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let result = merge_in_workdir(&app_handle, &mut request);
+        (request, result)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
 
-    image::DynamicImage::ImageRgb16(merged.clone())
-        .save(&png_path)
+// ジョブごとの作業フォルダで合成し、パニックは hdr://job-failed で通知してエラーにする。
+// 失敗したら作業フォルダを残し、その場所をエラーに添える
+fn merge_in_workdir(
+    app_handle: &AppHandle,
+    request: &mut MergeRequest,
+) -> Result<MergeResult, String> {
+    let job_id = request.job_id.clone();
+    let cache_dir = app_handle
+        .path()
+        .app_cache_dir()
         .map_err(|e| e.to_string())?;
+    let job_workdir = JobWorkdir::create(&cache_dir, job_id.as_deref())?;
+    request.workdir = Some(job_workdir.path().to_path_buf());
+    let priority = app_handle
+        .state::<ConfigStore>()
+        .snapshot()?
+        .settings
+        .worker_priority;
+    let request = &*request;
+    let result = worker_priority::run(priority, || {
+        panic_report::catch(|| merge::run_merge(request))
+    })
+    .unwrap_or_else(|panic| Err(report_panic(app_handle, &panic, job_id, "merge")));
+    let failure = result.as_ref().err().filter(|e| *e != CANCELLED);
+    match job_workdir.finish(failure.map(String::as_str)) {
+        Some(kept) => {
+            let kept = kept.to_string_lossy().to_string();
+            result.map_err(|e| format!("{}（調査用の作業フォルダ: {}）", e, kept))
+        }
+        None => result,
+    }
+}
 
-    let mut output_exr_path = None;
-    if request.output_exr {
-        let merged_ref = &merged;
-        write_rgb_file(
-            &exr_path,
-            width as usize,
-            height as usize,
-            |x, y| {
-                let pixel = merged_ref.get_pixel(x as u32, y as u32);
-                (
-                    pixel[0] as f32 / u16::MAX as f32,
-                    pixel[1] as f32 / u16::MAX as f32,
-                    pixel[2] as f32 / u16::MAX as f32,
-                )
-            },
-        )
+// パニックしたジョブを hdr://job-failed で通知し、ジョブの結果として返すエラー文を作る
+fn report_panic(
+    app_handle: &AppHandle,
+    panic: &CaughtPanic,
+    job_id: Option<String>,
+    kind: &'static str,
+) -> String {
+    let _ = app_handle.emit(JOB_FAILED_EVENT, panic.failure(job_id, kind));
+    format!("内部エラーで処理を中断しました: {}", panic.message)
+}
+
+fn max_concurrent_jobs(config: &ConfigStore) -> Result<usize, String> {
+    Ok(config
+        .snapshot()?
+        .settings
+        .max_concurrent_jobs
+        .unwrap_or(jobs::DEFAULT_MAX_CONCURRENT))
+}
+
+// group_images のグループから作るときは、そのグループの mergeMode を渡す
+#[tauri::command]
+async fn group_create(
+    app_handle: AppHandle,
+    groups: State<'_, GroupStore>,
+    paths: Vec<String>,
+    merge_mode: Option<MergeMode>,
+) -> Result<PendingGroup, String> {
+    record_activity(&app_handle);
+    groups.create(&paths, merge_mode)
+}
+
+#[tauri::command]
+async fn group_add_file(
+    app_handle: AppHandle,
+    groups: State<'_, GroupStore>,
+    group_id: u64,
+    path: String,
+) -> Result<PendingGroup, String> {
+    record_activity(&app_handle);
+    groups.add_file(group_id, &path)
+}
+
+#[tauri::command]
+async fn group_remove_file(
+    app_handle: AppHandle,
+    groups: State<'_, GroupStore>,
+    group_id: u64,
+    path: String,
+) -> Result<PendingGroup, String> {
+    record_activity(&app_handle);
+    groups.remove_file(group_id, &path)
+}
+
+#[tauri::command]
+async fn group_list(groups: State<'_, GroupStore>) -> Result<Vec<PendingGroup>, String> {
+    groups.list()
+}
+
+#[tauri::command]
+async fn group_delete(
+    app_handle: AppHandle,
+    groups: State<'_, GroupStore>,
+    timeouts: State<'_, GroupTimeouts>,
+    group_id: u64,
+) -> Result<PendingGroup, String> {
+    record_activity(&app_handle);
+    let group = groups.delete(group_id)?;
+    timeouts.forget(&group.paths);
+    Ok(group)
+}
+
+// 合成に成功したグループは一覧から取り除く。失敗した場合は編集を続けられるよう残す。
+// ボタンからの手動の合成が既定で、hdr://bracket-timeout の mergePartial など自動で合成するときは automatic: true を渡す
+#[tauri::command]
+async fn group_merge(
+    app_handle: AppHandle,
+    group_id: u64,
+    preset: Option<String>,
+    automatic: Option<bool>,
+) -> Result<MergeResult, String> {
+    let groups = app_handle.state::<GroupStore>();
+    let request = groups
+        .get(group_id)?
+        .merge_request(preset, automatic.unwrap_or(false));
+    let result = run_merge_job(&app_handle, request).await?;
+    if let Ok(group) = groups.delete(group_id) {
+        app_handle.state::<GroupTimeouts>().forget(&group.paths);
+    }
+    Ok(result)
+}
+
+#[tauri::command]
+async fn merge_backlog(jobs: State<'_, JobTracker>) -> Result<MergeBacklog, String> {
+    Ok(jobs.backlog())
+}
+
+#[tauri::command]
+async fn merge_cancel(jobs: State<'_, JobTracker>, job_id: String) -> Result<bool, String> {
+    jobs.cancel(&job_id)
+}
+
+// ジョブの実行中、メモリ・キャッシュの使用量を hdr://resource-usage で定期的に通知する
+fn start_resource_monitor(app_handle: &AppHandle) {
+    if !app_handle.state::<ResourceMonitor>().try_start() {
+        return;
+    }
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || {
+        let jobs = app_handle.state::<JobTracker>();
+        let config = app_handle.state::<ConfigStore>();
+        let sample = |queue_depth| {
+            let caches = disk_cache::MANAGED_CACHES
+                .iter()
+                .filter_map(|name| open_cache(&app_handle, &config, name).ok())
+                .map(|cache| cache.usage())
+                .collect();
+            let usage = ResourceUsage {
+                rss_bytes: resources::rss_bytes(),
+                caches,
+                prefetch_bytes: jobs.prefetcher().used_bytes(),
+                queue_depth,
+            };
+            let _ = app_handle.emit(RESOURCE_USAGE_EVENT, &usage);
+        };
+        app_handle.state::<ResourceMonitor>().run(
+            SAMPLE_INTERVAL,
+            || jobs.in_flight_count() + jobs.backlog().pending,
+            sample,
+        );
+    });
+}
+
+// 実行中の合成と並行して、次に合成するジョブの入力をデコードしておく。無効なら何もせず false を返す
+#[tauri::command]
+async fn merge_prefetch(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    jobs: State<'_, JobTracker>,
+    paths: Vec<String>,
+) -> Result<bool, String> {
+    record_activity(&app_handle);
+    let settings = config.snapshot()?.settings.prefetch;
+    let prefetcher = jobs.prefetcher();
+    if !settings.enabled {
+        prefetcher.clear();
+        return Ok(false);
+    }
+    prefetcher.prefetch(paths, settings.max_bytes());
+    Ok(true)
+}
+
+// 先読みが有効なら、これから合成する current の先読みは残したまま next の入力を先読みする
+fn prefetch_next(
+    app_handle: &AppHandle,
+    current: &[String],
+    next: Vec<String>,
+) -> Result<(), String> {
+    let settings = app_handle
+        .state::<ConfigStore>()
+        .snapshot()?
+        .settings
+        .prefetch;
+    if settings.enabled {
+        app_handle
+            .state::<JobTracker>()
+            .prefetcher()
+            .prefetch_after(current, next, settings.max_bytes());
+    }
+    Ok(())
+}
+
+// 設定・プロジェクトの既定値とプリセットを反映し、フロントエンドから受け取らない項目を埋める
+// request.preset のプリセット（作業中のプロジェクトがあればそのプリセット）の合成設定と送り先を反映する
+fn apply_named_preset(
+    request: &mut MergeRequest,
+    config: &ConfigStore,
+    workspace: &Workspace,
+) -> Result<(), String> {
+    let Some(name) = request.preset.clone() else {
+        return Ok(());
+    };
+    let project = workspace.active()?;
+    let data = config.snapshot()?;
+    let presets = match &project {
+        Some(project) => &project.presets,
+        None => &data.presets,
+    };
+    let preset = presets
+        .iter()
+        .find(|preset| preset.name == name)
+        .ok_or_else(|| format!("プリセットが見つかりません: {}", name))?;
+    request.apply_preset(preset);
+    request.send_targets = send_to::find_targets(&data.settings.send_targets, &preset.send_to)?;
+    request.preset_applied = true;
+    Ok(())
+}
+
+fn prepare_request(
+    request: &mut MergeRequest,
+    config: &ConfigStore,
+    watcher: &WatcherState,
+    workspace: &Workspace,
+    jobs: &JobTracker,
+) -> Result<(), String> {
+    if !request.preset_applied {
+        apply_named_preset(request, config, workspace)?;
+    }
+    let project = workspace.active()?;
+    let data = config.snapshot()?;
+    if request.output_dir.is_none() {
+        request.output_dir = project.and_then(|project| project.output_dir);
+    }
+    let settings = data.settings;
+    if request.output_template.is_none() {
+        request.output_template = settings.output_template.clone();
+    }
+    request.default_output_dir = settings.default_output_dir.clone();
+    if settings.protect_watch_folder {
+        request.protected_dirs = protected_folders(watcher, &settings, workspace)?;
+    }
+    request.write_roots = settings
+        .allowed_write_roots
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    request.allowed_plugins = settings.allowed_plugins.iter().map(PathBuf::from).collect();
+    request.own_outputs = Some(watcher.own_outputs.clone());
+    if request.memory_budget_mb.is_none() {
+        request.memory_budget_mb = settings.memory_budget_mb;
+    }
+    if settings.prefetch.enabled {
+        request.prefetched = Some(jobs.prefetcher());
+    }
+    Ok(())
+}
+
+// プリセットの編集で更新が必要になった履歴を、今のプリセットで合成し直す。
+// 出力は元と同じフォルダに書き、置き換えた古い出力はごみ箱へ移す
+#[tauri::command]
+async fn history_reprocess_stale(
+    app_handle: AppHandle,
+    preset_name: String,
+) -> Result<StaleReprocess, String> {
+    let workspace = app_handle.state::<Workspace>();
+    let stale = workspace.with_history(|history| history.stale_entries(&preset_name))?;
+    let mut outcome = StaleReprocess::default();
+    for entry in stale {
+        // 1件が失敗しても残りは合成し直し、失敗した履歴は stale のまま残す
+        match reprocess_entry(&app_handle, &entry, &preset_name).await {
+            Ok(updated) => outcome.updated.push(updated),
+            Err(message) => outcome.failed.push(ReprocessFailure {
+                id: entry.id,
+                message,
+            }),
+        }
+    }
+    Ok(outcome)
+}
+
+async fn reprocess_entry(
+    app_handle: &AppHandle,
+    entry: &HistoryEntry,
+    preset_name: &str,
+) -> Result<HistoryEntry, String> {
+    let watcher = app_handle.state::<WatcherState>();
+    let jobs = app_handle.state::<JobTracker>();
+    let config = app_handle.state::<ConfigStore>();
+    let workspace = app_handle.state::<Workspace>();
+    // 元の合成要求の出力・ROI・deliverables などはそのままに、合成設定だけ今のプリセットにする
+    let mut request = MergeRequest {
+        paths: entry.input_paths.clone(),
+        output_dir: Path::new(&entry.output_png_path)
+            .parent()
+            .map(|dir| dir.to_string_lossy().to_string()),
+        output_template: Some("{outputRoot}".to_string()),
+        preset: Some(preset_name.to_string()),
+        job_id: None,
+        // プリセットの変更をきっかけに裏でまとめて合成し直す
+        automatic: true,
+        ..entry.request.clone().unwrap_or_default()
+    };
+    prepare_request(&mut request, &config, &watcher, &workspace, &jobs)?;
+    let _slot = jobs
+        .wait_for_slot(&request, max_concurrent_jobs(&config)?)
+        .await?;
+    let _job = jobs.begin(&request)?;
+    start_resource_monitor(app_handle);
+    let started = Instant::now();
+    let (request, result) = merge_on_worker(app_handle, request).await?;
+    record_outcome(app_handle, &request, started.elapsed(), &result);
+    let result = result?;
+    // 前の合成で書き出したもののうち、今回の合成で同じ名前に書き直さなかったものを片付ける
+    let written = result.output_paths();
+    let replaced: Vec<String> = entry
+        .written_paths()
+        .into_iter()
+        .filter(|path| !written.contains(path))
+        .collect();
+    let entry = workspace
+        .with_history(|history| history.replace_outputs(entry.id, &request, &result))??;
+    recycle::delete_to_recycle(&replaced, &request.protected_dirs);
+    Ok(entry)
+}
+
+// 読み取り専用モードで守る監視フォルダ（監視中・設定・プロジェクトのもの）
+fn protected_folders(
+    watcher: &WatcherState,
+    settings: &Settings,
+    workspace: &Workspace,
+) -> Result<Vec<PathBuf>, String> {
+    let mut folders: Vec<PathBuf> = Vec::new();
+    if let Some(folder) = watcher
+        .folder
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+    {
+        folders.push(folder);
+    }
+    if let Some(folder) = &settings.watch_folder {
+        folders.push(PathBuf::from(folder));
+    }
+    if let Some(folder) = workspace.active()?.and_then(|project| project.watch_folder) {
+        folders.push(PathBuf::from(folder));
+    }
+    Ok(folders)
+}
+
+#[tauri::command]
+async fn recent_folders_list(config: State<'_, ConfigStore>) -> Result<Vec<String>, String> {
+    recent_list(&config, |settings| &mut settings.recent_folders)
+}
+
+#[tauri::command]
+async fn recent_outputs_list(config: State<'_, ConfigStore>) -> Result<Vec<String>, String> {
+    recent_list(&config, |settings| &mut settings.recent_outputs)
+}
+
+fn recent_list(
+    config: &ConfigStore,
+    select: fn(&mut Settings) -> &mut Vec<String>,
+) -> Result<Vec<String>, String> {
+    let mut settings = config.snapshot()?.settings;
+    if config::prune_recent(select(&mut settings)) {
+        let _ = config.update(|data| {
+            config::prune_recent(select(&mut data.settings));
+            Ok(())
+        });
+    }
+    Ok(select(&mut settings).clone())
+}
+
+#[tauri::command]
+async fn take_launch_request(
+    launch: State<'_, LaunchState>,
+) -> Result<Option<LaunchRequest>, String> {
+    // 枚数が合わず下書きを作れなかったときは、その理由をエラーとして返す
+    let mut pending = launch.0.lock().unwrap_or_else(PoisonError::into_inner);
+    pending
+        .take()
+        .transpose()
+        .map_err(|rejected| rejected.reason)
+}
+
+#[tauri::command]
+async fn handle_dropped_paths(paths: Vec<String>) -> Result<DropSuggestion, String> {
+    if paths.is_empty() {
+        return Err("ドロップされた項目がありません".to_string());
+    }
+    Ok(launch::classify_dropped(&paths))
+}
+
+#[tauri::command]
+async fn delete_to_recycle(
+    watcher: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    paths: Vec<String>,
+) -> Result<DeleteReport, String> {
+    let settings = config.snapshot()?.settings;
+    let protected = if settings.protect_watch_folder {
+        protected_folders(&watcher, &settings, &workspace)?
+    } else {
+        Vec::new()
+    };
+    for path in &paths {
+        writable_path(&config, path)?;
+    }
+    Ok(recycle::delete_to_recycle(&paths, &protected))
+}
+
+// フロントエンドから受け取った書き込み先を検証する。allowedWriteRoots を設定していればその中に限る
+fn writable_path(config: &ConfigStore, path: &str) -> Result<PathBuf, String> {
+    let checked = paths::output_file(path)?;
+    paths::ensure_writable(&checked, &write_roots(config)?)?;
+    Ok(checked)
+}
+
+fn write_roots(config: &ConfigStore) -> Result<Vec<PathBuf>, String> {
+    Ok(config
+        .snapshot()?
+        .settings
+        .allowed_write_roots
+        .iter()
+        .map(PathBuf::from)
+        .collect())
+}
+
+#[tauri::command]
+async fn maintenance_cleanup(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    jobs: State<'_, JobTracker>,
+) -> Result<CleanupReport, String> {
+    let settings = config.snapshot()?.settings;
+    let project = workspace.active()?;
+    // 合成中は書き込み途中の .partial を消さないよう、出力フォルダには触れない
+    let include_outputs = jobs.in_flight_count() == 0;
+    let targets = cleanup_targets(&app_handle, &settings, project.as_ref(), include_outputs)?;
+    Ok(maintenance::cleanup(
+        &targets,
+        CleanupPolicy::FULL,
+        std::time::SystemTime::now(),
+    ))
+}
+
+fn cleanup_targets(
+    app_handle: &AppHandle,
+    settings: &Settings,
+    project: Option<&Project>,
+    include_outputs: bool,
+) -> Result<CleanupTargets, String> {
+    let path = app_handle.path();
+    let mut output_dirs: Vec<PathBuf> = Vec::new();
+    if include_outputs {
+        let configured = project
+            .and_then(|project| project.output_dir.as_ref())
+            .into_iter()
+            .chain(settings.output_dir.as_ref())
+            .chain(settings.recent_outputs.iter());
+        for dir in configured.map(PathBuf::from) {
+            if !output_dirs.contains(&dir) {
+                output_dirs.push(dir);
+            }
+        }
+    }
+    Ok(CleanupTargets {
+        cache_dir: Some(path.app_cache_dir().map_err(|e| e.to_string())?),
+        output_dirs,
+        app_dirs: vec![
+            path.app_config_dir().map_err(|e| e.to_string())?,
+            path.app_data_dir().map_err(|e| e.to_string())?,
+        ],
+    })
+}
+
+#[tauri::command]
+async fn jobs_pending(jobs: State<'_, JobTracker>) -> Result<Vec<PendingJob>, String> {
+    jobs.pending()
+}
+
+// 保留ジョブを一覧から外し、保存した出力名や固定した補正のまま合成し直す
+#[tauri::command]
+async fn jobs_resume(app_handle: AppHandle, index: usize) -> Result<MergeResult, String> {
+    let job = app_handle.state::<JobTracker>().take_pending(index)?;
+    run_merge_job(&app_handle, job.into_request()).await
+}
+
+#[tauri::command]
+async fn jobs_clear_pending(jobs: State<'_, JobTracker>) -> Result<(), String> {
+    jobs.clear_pending()
+}
+
+#[tauri::command]
+async fn stats_get(stats: State<'_, StatsStore>) -> Result<UsageStats, String> {
+    stats.snapshot()
+}
+
+#[tauri::command]
+async fn stats_reset(stats: State<'_, StatsStore>) -> Result<(), String> {
+    stats.reset()
+}
+
+#[tauri::command]
+async fn merge_sweep(
+    app_handle: AppHandle,
+    request: MergeRequest,
+    parameter_grid: BTreeMap<String, Vec<Value>>,
+) -> Result<Vec<SweepPreview>, String> {
+    let cache_dir = app_handle
+        .path()
+        .app_cache_dir()
         .map_err(|e| e.to_string())?;
+    let timestamp = Local::now().format("%Y%m%d_%H%M%S%3f").to_string();
+    let output_dir = cache_dir.join("sweep").join(timestamp);
+
+    // 組み合わせの数だけ合成するため、別スレッドで行う
+    tauri::async_runtime::spawn_blocking(move || {
+        sweep::run_sweep(&request, &parameter_grid, &output_dir)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
 
-        output_exr_path = Some(exr_path.to_string_lossy().to_string());
+// ブラケットを 1 つずつ通常の合成ジョブとして順に実行する。途中で失敗・中止したらそこで止める。
+// プリセットは先頭で一度だけ読み、すべてのフレームを同じトーンマップ・合成設定で合成する
+#[tauri::command]
+async fn merge_timelapse(
+    app_handle: AppHandle,
+    watcher: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    mut request: TimelapseRequest,
+) -> Result<TimelapseResult, String> {
+    // 連番を列挙する前に、撮影フォルダが読めるフォルダか確かめる
+    request.folder = paths::input_dir(&request.folder)?
+        .to_string_lossy()
+        .to_string();
+    apply_named_preset(&mut request.settings, &config, &workspace)?;
+    // 連番の列挙と EXIF の読み込み、最後のデフリッカーは重いため、非同期ランタイムの外で行う
+    let mut sequence = tauri::async_runtime::spawn_blocking(move || Sequence::plan(&request))
+        .await
+        .map_err(|e| e.to_string())??;
+    while let Some(frame) = sequence.next_request() {
+        if let Some(next) = sequence.upcoming_paths() {
+            prefetch_next(&app_handle, &frame.paths, next)?;
+        }
+        let result = run_merge_job(&app_handle, frame).await?;
+        sequence.record(&result);
     }
+    let settings = config.snapshot()?.settings;
+    let protected_dirs = if settings.protect_watch_folder {
+        protected_folders(&watcher, &settings, &workspace)?
+    } else {
+        Vec::new()
+    };
+    let write_roots = write_roots(&config)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let watcher = app_handle.state::<WatcherState>();
+        sequence.finish(&SidecarTarget {
+            protected_dirs: &protected_dirs,
+            write_roots: &write_roots,
+            own_outputs: Some(&watcher.own_outputs),
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
 
-    Ok(MergeResult {
-        output_png_path: png_path.to_string_lossy().to_string(),
-        output_exr_path,
-        width,
-        height,
-        merged_at: Local::now().to_rfc3339(),
+// ブラケットを順に通常の合成ジョブとして実行する。失敗しても残りを続け、中止したらそこで止める。
+// 最後に合成結果の一覧画像とまとめの JSON を保存する
+#[tauri::command]
+async fn merge_batch(app_handle: AppHandle, request: BatchRequest) -> Result<BatchResult, String> {
+    if request.requests.is_empty() {
+        return Err("合成するブラケットがありません".to_string());
+    }
+    let config = app_handle.state::<ConfigStore>();
+    let report_dir = match &request.report_dir {
+        Some(dir) => Some(writable_path(&config, dir)?),
+        None => None,
+    };
+    let mut batch = Batch::new();
+    let mut requests = request.requests.into_iter().peekable();
+    while let Some(merge_request) = requests.next() {
+        let paths = merge_request.paths.clone();
+        if let Some(next) = requests.peek() {
+            prefetch_next(&app_handle, &paths, next.paths.clone())?;
+        }
+        let result = run_merge_job(&app_handle, merge_request).await;
+        batch.record(paths, &result);
+        if batch.is_cancelled() {
+            break;
+        }
+    }
+    let watcher = app_handle.state::<WatcherState>();
+    batch.finish(
+        report_dir.as_deref(),
+        &write_roots(&config)?,
+        Some(&watcher.own_outputs),
+    )
+}
+
+#[tauri::command]
+async fn deflicker_sequence(
+    watcher: State<'_, WatcherState>,
+    config: State<'_, ConfigStore>,
+    workspace: State<'_, Workspace>,
+    folder: String,
+    options: Option<DeflickerOptions>,
+) -> Result<DeflickerResult, String> {
+    let folder = paths::input_dir(&folder)?;
+    let mut options = options.unwrap_or_default();
+    let output_dir = match &options.output_dir {
+        Some(dir) => writable_path(&config, dir)?,
+        None => writable_path(
+            &config,
+            &deflicker::default_output_dir(&folder).to_string_lossy(),
+        )?,
+    };
+    let settings = config.snapshot()?.settings;
+    if settings.protect_watch_folder {
+        output_path::ensure_outside_protected(
+            &output_dir,
+            &protected_folders(&watcher, &settings, &workspace)?,
+        )?;
+    }
+    options.output_dir = Some(output_dir.to_string_lossy().to_string());
+    // 連番をすべて読み書きするため、非同期ランタイムのスレッドを塞がないよう別スレッドで行う
+    tauri::async_runtime::spawn_blocking(move || deflicker::deflicker_sequence(&folder, &options))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn compare_images(
+    app_handle: AppHandle,
+    path_a: String,
+    path_b: String,
+) -> Result<CompareResult, String> {
+    let cache_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| e.to_string())?;
+    let timestamp = Local::now().format("%Y%m%d_%H%M%S%3f").to_string();
+    let heatmap_path = cache_dir
+        .join("compare")
+        .join(format!("compare_{}.png", timestamp));
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let image_a = load_rgb16(&path_a)?;
+        let image_b = load_rgb16(&path_b)?;
+        compare::compare(&image_a, &image_b, &heatmap_path)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// ワイプ比較用に、合成前後で同じ位置・縮小率のタイルの組を作る。WebView で原寸の画像を扱わずに済む
+#[tauri::command]
+async fn generate_compare_tiles(
+    app_handle: AppHandle,
+    config: State<'_, ConfigStore>,
+    source_path: String,
+    merged_path: String,
+    tile_spec: Option<TileSpec>,
+) -> Result<CompareTiles, String> {
+    let cache = open_cache(&app_handle, &config, disk_cache::THUMBNAIL_CACHE)?;
+    compare_tiles::generate(
+        &cache,
+        &source_path,
+        &merged_path,
+        &tile_spec.unwrap_or_default(),
+    )
+}
+
+#[tauri::command]
+async fn generate_false_color(
+    app_handle: AppHandle,
+    path: String,
+    mode: FalseColorMode,
+) -> Result<FalseColorResult, String> {
+    let cache_dir = app_handle
+        .path()
+        .app_cache_dir()
+        .map_err(|e| e.to_string())?;
+    let timestamp = Local::now().format("%Y%m%d_%H%M%S%3f").to_string();
+    let output_path = cache_dir
+        .join("false_color")
+        .join(format!("false_color_{}.png", timestamp));
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let image = probe::load_linear(&path)?;
+        false_color::generate(&image, mode, &output_path)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn probe_pixels(
+    path: String,
+    x: u32,
+    y: u32,
+    radius: u32,
+    source_paths: Option<Vec<String>>,
+) -> Result<ProbeResult, String> {
+    // 合成結果と入力をデコードするため、非同期ランタイムのスレッドを塞がないよう別スレッドで行う
+    tauri::async_runtime::spawn_blocking(move || {
+        probe::probe(&path, x, y, radius, &source_paths.unwrap_or_default())
     })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-fn load_rgb16(path: &str) -> Result<ImageBuffer<Rgb<u16>, Vec<u16>>, String> {
-    let image = image::open(path).map_err(|e| e.to_string())?;
-    Ok(image.to_rgb16())
+#[tauri::command]
+async fn generate_test_bracket(
+    config: State<'_, ConfigStore>,
+    output_dir: String,
+    options: Option<TestBracketOptions>,
+) -> Result<TestBracket, String> {
+    let output_dir = writable_path(&config, &output_dir)?;
+    synthetic::generate_bracket(&output_dir, &options.unwrap_or_default())
 }
 
 fn calculate_average_luma(image: &ImageBuffer<Rgb<u16>, Vec<u16>>) -> f32 {
-    let mut total = 0.0f64;
     let pixel_count = (image.width() as f64) * (image.height() as f64);
-
-    for pixel in image.pixels() {
-        let r = pixel[0] as f64 / u16::MAX as f64;
-        let g = pixel[1] as f64 / u16::MAX as f64;
-        let b = pixel[2] as f64 / u16::MAX as f64;
-        total += 0.2126 * r + 0.7152 * g + 0.0722 * b;
-    }
+    let total = simd::luma_sum(image.as_raw());
 
     if pixel_count == 0.0 {
         return 0.0;
@@ -285,24 +2130,221 @@ fn calculate_average_luma(image: &ImageBuffer<Rgb<u16>, Vec<u16>>) -> f32 {
     (total / pixel_count) as f32
 }
 
-fn should_process_file(path: &Path) -> bool {
-    let ext = match path.extension().and_then(|ext| ext.to_str()) {
-        Some(ext) => ext.to_lowercase(),
-        None => return false,
-    };
+// 検出・合成がない間は、重複判定の記録と先読みした画像を手放してから次の活動まで眠る
+fn start_idle_monitor(app_handle: &AppHandle) {
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || {
+        let jobs = app_handle.state::<JobTracker>();
+        app_handle.state::<IdleMonitor>().run(
+            || {
+                app_handle
+                    .state::<ConfigStore>()
+                    .snapshot()
+                    .ok()
+                    .and_then(|config| config.settings.idle.after())
+            },
+            || jobs.in_flight_count() + jobs.backlog().pending > 0,
+            || {
+                *app_handle
+                    .state::<WatcherState>()
+                    .recent_events
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) = HashMap::new();
+                jobs.prefetcher().clear();
+                let _ = app_handle.emit(IDLE_EVENT, true);
+            },
+        );
+    });
+}
+
+// 状態表示用の集計を定期的に作り、変化があれば hdr://dashboard で通知する。
+// 待機状態の間は集計が変わらないので、待機状態になったことを通知したら次の活動まで止まる
+fn start_dashboard(app_handle: &AppHandle) {
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || loop {
+        let summary = dashboard_snapshot(&app_handle);
+        if app_handle.state::<Dashboard>().should_emit(&summary) {
+            let _ = app_handle.emit(DASHBOARD_EVENT, &summary);
+        }
+        if summary.idle {
+            app_handle.state::<IdleMonitor>().wait_until_active();
+        } else {
+            std::thread::sleep(DASHBOARD_INTERVAL);
+        }
+    });
+}
+
+// 書き込み途中で先送りしたファイルを確かめ直し、読めるようになったものを改めて判定する。
+// 待機状態で先送りしたファイルもなければ、次の検出（先送りは検出の後に行う）まで止まる
+fn start_growing_file_monitor(app_handle: &AppHandle) {
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(growing_file::RECHECK_INTERVAL);
+        let filters = app_handle.state::<WatcherState>().filters();
+        if filters.growing_files.is_empty() {
+            app_handle.state::<IdleMonitor>().wait_until_active();
+            continue;
+        }
+        for (path, kind) in filters.growing_files.take_ready(Instant::now()) {
+            // 書き込み中に届いたイベントで重複扱いにならないよう、直前の記録を消してから判定する
+            filters
+                .recent_events
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&path);
+            handle_detection(&app_handle, &filters, kind, &path);
+        }
+    });
+}
+
+fn dashboard_snapshot(app_handle: &AppHandle) -> DashboardSummary {
+    let watcher = app_handle.state::<WatcherState>();
+    let jobs = app_handle.state::<JobTracker>();
+    let dashboard = app_handle.state::<Dashboard>();
+    let watching = *watcher
+        .is_watching
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let folder = watcher
+        .folder
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .map(|f| f.to_string_lossy().to_string());
+    DashboardSummary {
+        watching,
+        folder,
+        pending_files: app_handle
+            .state::<GroupStore>()
+            .list()
+            .map(|groups| groups.iter().map(|group| group.paths.len()).sum())
+            .unwrap_or(0),
+        queue_depth: jobs.in_flight_count() + jobs.backlog().pending,
+        last_result_path: dashboard.last_result_path(),
+        error_count: dashboard.error_count(),
+        idle: app_handle.state::<IdleMonitor>().is_idle(),
+    }
+}
+
+// 設定の pauseWhileRunning のアプリが動いている間は自動合成を順番待ちのままにし、変わったら hdr://auto-pause で通知する。
+// 待機状態では自動合成が始まらないので、次の活動まで確かめるのを止め、戻ったらすぐ確かめ直す
+// （検出から自動合成までにはグループのタイムアウトを待つため、それまでに止めるかどうかが決まる）
+fn start_app_pause_monitor(app_handle: &AppHandle) {
+    let app_handle = app_handle.clone();
+    std::thread::spawn(move || loop {
+        app_handle.state::<IdleMonitor>().wait_until_active();
+        let configured = app_handle
+            .state::<ConfigStore>()
+            .snapshot()
+            .map(|config| config.settings.pause_while_running)
+            .unwrap_or_default();
+        let blockers = if configured.is_empty() {
+            Vec::new()
+        } else {
+            app_pause::blockers(&configured, &app_pause::running_processes())
+        };
+        if app_handle
+            .state::<JobTracker>()
+            .set_paused_by(blockers.clone())
+        {
+            let _ = app_handle.emit(AUTO_PAUSE_EVENT, &blockers);
+        }
+        std::thread::sleep(app_pause::CHECK_INTERVAL);
+    });
+}
+
+// 自動合成を止めているアプリ。空なら止めていない
+#[tauri::command]
+async fn auto_pause_status(jobs: State<'_, JobTracker>) -> Result<Vec<String>, String> {
+    Ok(jobs.paused_by())
+}
 
-    matches!(ext.as_str(), "png" | "jpg" | "jpeg")
+// 起動直後の表示用。以降は hdr://dashboard を待つ
+#[tauri::command]
+async fn dashboard_summary(app_handle: AppHandle) -> Result<DashboardSummary, String> {
+    Ok(dashboard_snapshot(&app_handle))
+}
+
+fn record_activity(app_handle: &AppHandle) {
+    if app_handle.state::<IdleMonitor>().touch() {
+        let _ = app_handle.emit(IDLE_EVENT, false);
+    }
 }
 
-fn debounce_check(path: &Path, recent_events: &Arc<Mutex<HashMap<PathBuf, Instant>>>) -> bool {
-    let mut map = match recent_events.lock() {
-        Ok(guard) => guard,
-        Err(_) => return false,
+// 検出したファイルを判定し、対象ならフロントエンドに通知する。結果は理由とともに検出ログに残す
+fn handle_detection(
+    app_handle: &AppHandle,
+    filters: &WatchFilters,
+    kind: DetectionKind,
+    path: &Path,
+) -> DetectionOutcome {
+    record_activity(app_handle);
+    let outcome = if !should_process_file(path, &filters.matcher) {
+        DetectionOutcome::UnsupportedExtension
+    } else if filters.own_outputs.contains(path) {
+        DetectionOutcome::OwnOutput
+    } else if is_ignored_dir(path, &filters.ignore_dirs) {
+        DetectionOutcome::IgnoredDir
+    } else if filters.growing_files.is_pending(path) {
+        DetectionOutcome::Incomplete
+    } else {
+        let (debounce, stable) = filters
+            .timing
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .for_path(path);
+        if !debounce_check(path, &filters.recent_events, debounce) {
+            DetectionOutcome::Debounced
+        } else if !stable.is_zero() || !growing_file::is_complete(path) {
+            filters.growing_files.defer(path, kind, stable);
+            DetectionOutcome::Incomplete
+        } else {
+            DetectionOutcome::Detected
+        }
     };
 
+    let detected_at = Local::now().to_rfc3339();
+    if outcome == DetectionOutcome::Detected {
+        *filters
+            .last_detected_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(detected_at.clone());
+        let _ = app_handle.emit(FILE_DETECTED_EVENT, path.to_string_lossy().to_string());
+    }
+    let _ = app_handle.state::<DetectionLog>().record(Detection {
+        path: path.to_string_lossy().to_string(),
+        detected_at,
+        event_kind: kind,
+        outcome,
+    });
+    outcome
+}
+
+fn should_process_file(path: &Path, matcher: &Arc<Mutex<ExtensionMatcher>>) -> bool {
+    matcher
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .matches(path)
+}
+
+// 除外設定した出力フォルダ配下のファイルは検出しない
+fn is_ignored_dir(path: &Path, ignore_dirs: &Arc<Mutex<Vec<PathBuf>>>) -> bool {
+    watch_filter::is_ignored(
+        path,
+        &ignore_dirs.lock().unwrap_or_else(PoisonError::into_inner),
+    )
+}
+
+fn debounce_check(
+    path: &Path,
+    recent_events: &Arc<Mutex<HashMap<PathBuf, Instant>>>,
+    debounce: Duration,
+) -> bool {
+    let mut map = recent_events.lock().unwrap_or_else(PoisonError::into_inner);
+
     let now = Instant::now();
     if let Some(last) = map.get(path) {
-        if now.duration_since(*last) < Duration::from_millis(500) {
+        if now.duration_since(*last) < debounce {
             return false;
         }
     }
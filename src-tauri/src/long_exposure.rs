@@ -0,0 +1,122 @@
+use serde_json::Value;
+
+use crate::algorithms::{AlgorithmParams, MergeAlgorithm, ParameterKind, ParameterSchema};
+use crate::color::{linear_to_srgb, srgb_to_linear, u16_to_unit, unit_to_u16};
+use crate::merge::{MergeRequest, Rgb16Image};
+
+// 滝や雲の流れは枚数が多いほど滑らかになるため、noiseStack より多く受け付ける
+pub const MAX_LONG_EXPOSURE_FRAMES: usize = 32;
+
+// mergeMode が longExposure のときに使う。REGISTRY には入れず、algorithm では選べない
+pub struct LongExposure;
+
+impl MergeAlgorithm for LongExposure {
+    fn name(&self) -> &'static str {
+        "longExposure"
+    }
+
+    fn label(&self) -> &'static str {
+        "長秒露光シミュレーション"
+    }
+
+    fn description(&self) -> &'static str {
+        "連写したフレームを外れ値を除かずに平均し、動く被写体の軌跡を残します"
+    }
+
+    fn parameters(&self) -> Vec<ParameterSchema> {
+        vec![ParameterSchema {
+            name: "linearBlend",
+            label: "線形空間で平均する",
+            kind: ParameterKind::Boolean,
+            default: Value::from(true),
+        }]
+    }
+
+    fn merge(&self, frames: &[Rgb16Image], params: &AlgorithmParams) -> Result<Rgb16Image, String> {
+        let linear = params
+            .get("linearBlend")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+        Ok(average(frames, linear))
+    }
+}
+
+// 露出の違うフレームも軌跡の一部として平均するため、noiseStack と違って EV の差は確かめない
+pub fn validate(request: &MergeRequest) -> Result<(), String> {
+    if request.algorithm.is_some() {
+        return Err("longExposure では algorithm を指定できません".to_string());
+    }
+    if request.frame_selection.is_some() {
+        return Err("longExposure では frameSelection を指定できません".to_string());
+    }
+    Ok(())
+}
+
+// linear のときは実際のシャッターと同じく光の量で平均する。false ならガンマのかかった値をそのまま平均し、
+// 明るい軌跡が暗めに写る
+pub fn average(frames: &[Rgb16Image], linear: bool) -> Rgb16Image {
+    let (width, height) = frames[0].dimensions();
+    let count = frames.len() as f32;
+    let to_linear: Vec<f32> = (0..=u16::MAX)
+        .map(|value| srgb_to_linear(u16_to_unit(value)))
+        .collect();
+    let sources: Vec<&[u16]> = frames
+        .iter()
+        .map(|frame| frame.as_raw().as_slice())
+        .collect();
+
+    let data = (0..sources[0].len())
+        .map(|i| {
+            if linear {
+                let sum: f32 = sources
+                    .iter()
+                    .map(|source| to_linear[source[i] as usize])
+                    .sum();
+                unit_to_u16(linear_to_srgb(sum / count))
+            } else {
+                let sum: u64 = sources.iter().map(|source| source[i] as u64).sum();
+                (sum as f32 / count).round() as u16
+            }
+        })
+        .collect();
+    Rgb16Image::from_raw(width, height, data).expect("バッファの長さは画素数と一致する")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    // 明るい点が 1 画素ずつ右へ動くフレーム
+    fn moving_light(count: u32) -> Vec<Rgb16Image> {
+        (0..count)
+            .map(|i| {
+                Rgb16Image::from_fn(8, 1, |x, _| Rgb([if x == i { u16::MAX } else { 1000 }; 3]))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_trail_of_moving_subjects() {
+        let frames = moving_light(4);
+
+        let blended = average(&frames, true);
+
+        // 点が通った画素はすべて同じ明るさの軌跡になり、通らなかった画素は背景のまま
+        let trail = blended.get_pixel(0, 0).0[0];
+        assert!(trail > 1000);
+        assert!((1..4).all(|x| blended.get_pixel(x, 0).0[0] == trail));
+        assert_eq!(blended.get_pixel(6, 0).0[0], 1000);
+    }
+
+    #[test]
+    fn linear_blending_keeps_highlights_brighter() {
+        let frames = moving_light(4);
+
+        let linear = average(&frames, true).get_pixel(0, 0).0[0];
+        let gamma = average(&frames, false).get_pixel(0, 0).0[0];
+
+        assert_eq!(gamma, 17134);
+        assert!(linear > gamma);
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::disk_cache::{self, MANAGED_CACHES};
+use crate::merge::PARTIAL_SUFFIX;
+use crate::workdir::WORKDIR_CACHE;
+
+const TEMP_SUFFIX: &str = ".tmp";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CleanupPolicy {
+    // これより古いキャッシュ（比較ヒートマップ・フォールスカラー・スイープのプレビュー）を消す
+    pub cache_max_age: Duration,
+    // 書き込み中のファイルを消さないよう、これより新しい一時ファイルは残す
+    pub temp_min_age: Duration,
+}
+
+impl CleanupPolicy {
+    pub const FULL: CleanupPolicy = CleanupPolicy {
+        cache_max_age: Duration::from_secs(24 * 60 * 60),
+        temp_min_age: Duration::from_secs(10 * 60),
+    };
+    // 起動時は軽く済ませるため、1週間以上前のキャッシュだけを対象にする
+    pub const STARTUP: CleanupPolicy = CleanupPolicy {
+        cache_max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        temp_min_age: Duration::from_secs(10 * 60),
+    };
+}
+
+#[derive(Debug, Default)]
+pub struct CleanupTargets {
+    pub cache_dir: Option<PathBuf>,
+    // 出力フォルダは利用者のファイルもあるため、合成が残した .partial だけを消す
+    pub output_dirs: Vec<PathBuf>,
+    // 設定・データフォルダでは .partial と保存途中の .tmp を消す
+    pub app_dirs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct CleanupReport {
+    pub removed_files: usize,
+    pub bytes_reclaimed: u64,
+    pub errors: Vec<String>,
+}
+
+pub fn cleanup(targets: &CleanupTargets, policy: CleanupPolicy, now: SystemTime) -> CleanupReport {
+    let mut report = CleanupReport::default();
+    if let Some(cache_dir) = &targets.cache_dir {
+        // サムネイル・解析結果は容量の上限で管理しているため、経過時間では消さず、元ファイルが消えたエントリだけを消す。
+        // 作業フォルダは合成中のものがあるため、起動時に workdir::prune でフォルダごと片付ける
+        let entries = fs::read_dir(cache_dir).into_iter().flatten();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !MANAGED_CACHES.contains(&name.as_str()) && name != WORKDIR_CACHE {
+                clean_cache_entry(&entry, policy.cache_max_age, now, &mut report);
+            }
+        }
+        for name in MANAGED_CACHES {
+            for path in disk_cache::orphaned_files(&cache_dir.join(name)) {
+                remove_path(&path, &mut report);
+            }
+        }
+    }
+    for dir in &targets.output_dirs {
+        clean_temp(
+            dir,
+            &[PARTIAL_SUFFIX],
+            policy.temp_min_age,
+            now,
+            &mut report,
+        );
+    }
+    for dir in &targets.app_dirs {
+        clean_temp(
+            dir,
+            &[PARTIAL_SUFFIX, TEMP_SUFFIX],
+            policy.temp_min_age,
+            now,
+            &mut report,
+        );
+    }
+    report
+}
+
+// キャッシュは配下をすべて対象にし、空になったフォルダも消す
+fn clean_cache(dir: &Path, max_age: Duration, now: SystemTime, report: &mut CleanupReport) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        clean_cache_entry(&entry, max_age, now, report);
+    }
+}
+
+fn clean_cache_entry(
+    entry: &fs::DirEntry,
+    max_age: Duration,
+    now: SystemTime,
+    report: &mut CleanupReport,
+) {
+    let Ok(file_type) = entry.file_type() else {
+        return;
+    };
+    let path = entry.path();
+    if file_type.is_dir() {
+        clean_cache(&path, max_age, now, report);
+        let _ = fs::remove_dir(&path);
+    } else if file_type.is_file() && is_older(entry, max_age, now) {
+        remove(entry, report);
+    }
+}
+
+// サブフォルダはたどらない
+fn clean_temp(
+    dir: &Path,
+    suffixes: &[&str],
+    min_age: Duration,
+    now: SystemTime,
+    report: &mut CleanupReport,
+) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_file = entry.file_type().is_ok_and(|file_type| file_type.is_file());
+        if is_file
+            && suffixes.iter().any(|suffix| name.ends_with(suffix))
+            && is_older(&entry, min_age, now)
+        {
+            remove(&entry, report);
+        }
+    }
+}
+
+fn is_older(entry: &fs::DirEntry, age: Duration, now: SystemTime) -> bool {
+    entry
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| now.duration_since(modified).ok())
+        .is_some_and(|elapsed| elapsed >= age)
+}
+
+fn remove(entry: &fs::DirEntry, report: &mut CleanupReport) {
+    remove_path(&entry.path(), report);
+}
+
+fn remove_path(path: &Path, report: &mut CleanupReport) {
+    let size = fs::symlink_metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    match fs::remove_file(path) {
+        Ok(()) => {
+            report.removed_files += 1;
+            report.bytes_reclaimed += size;
+        }
+        Err(e) => report.errors.push(format!(
+            "{} を削除できません: {}",
+            path.to_string_lossy(),
+            e
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_aged(path: &Path, bytes: &[u8], age: Duration) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn removes_stale_cache_and_orphaned_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        let output = dir.path().join("output");
+        let config = dir.path().join("config");
+        let day = Duration::from_secs(2 * 24 * 60 * 60);
+        let fresh = Duration::from_secs(60);
+        write_aged(&cache.join("compare/old.png"), b"1234", day);
+        write_aged(&cache.join("false_color/new.png"), b"1234", fresh);
+        write_aged(&cache.join("thumbnails/lru.png"), b"1234", day);
+        write_aged(&cache.join("jobs/running/hdr.png.partial"), b"1234", day);
+        write_aged(&output.join("hdr.png.partial"), b"123456", day);
+        write_aged(&output.join("writing.png.partial"), b"123456", fresh);
+        write_aged(&output.join("other.tmp"), b"1", day);
+        write_aged(&config.join("config.json.tmp"), b"12", day);
+
+        let report = cleanup(
+            &CleanupTargets {
+                cache_dir: Some(cache.clone()),
+                output_dirs: vec![output.clone()],
+                app_dirs: vec![config.clone()],
+            },
+            CleanupPolicy::FULL,
+            SystemTime::now(),
+        );
+
+        assert_eq!(report.removed_files, 3);
+        assert_eq!(report.bytes_reclaimed, 12);
+        assert!(!cache.join("compare").exists());
+        assert!(cache.join("false_color/new.png").exists());
+        assert!(cache.join("thumbnails/lru.png").exists());
+        assert!(cache.join("jobs/running/hdr.png.partial").exists());
+        assert!(output.join("writing.png.partial").exists());
+        assert!(output.join("other.tmp").exists());
+        assert!(!config.join("config.json.tmp").exists());
+    }
+
+    #[test]
+    fn removes_managed_cache_entries_whose_source_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        let thumbnails = cache.join(disk_cache::THUMBNAIL_CACHE);
+        let kept = dir.path().join("kept.jpg");
+        write_aged(&kept, b"jpeg", Duration::ZERO);
+        let removed = dir.path().join("removed.jpg");
+        write_aged(&thumbnails.join("a.png"), b"1234", Duration::ZERO);
+        write_aged(
+            &thumbnails.join("a.png.source"),
+            removed.to_string_lossy().as_bytes(),
+            Duration::ZERO,
+        );
+        write_aged(&thumbnails.join("b.png"), b"1234", Duration::ZERO);
+        write_aged(
+            &thumbnails.join("b.png.source"),
+            kept.to_string_lossy().as_bytes(),
+            Duration::ZERO,
+        );
+
+        let report = cleanup(
+            &CleanupTargets {
+                cache_dir: Some(cache.clone()),
+                ..Default::default()
+            },
+            CleanupPolicy::STARTUP,
+            SystemTime::now(),
+        );
+
+        assert_eq!(report.removed_files, 2);
+        assert_eq!(
+            report.bytes_reclaimed,
+            4 + removed.to_string_lossy().len() as u64
+        );
+        assert!(!thumbnails.join("a.png").exists());
+        assert!(!thumbnails.join("a.png.source").exists());
+        assert!(thumbnails.join("b.png").exists());
+        assert!(thumbnails.join("b.png.source").exists());
+    }
+
+    #[test]
+    fn startup_policy_keeps_recent_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        write_aged(
+            &cache.join("sweep/1/preview.png"),
+            b"1",
+            Duration::from_secs(2 * 24 * 60 * 60),
+        );
+
+        let report = cleanup(
+            &CleanupTargets {
+                cache_dir: Some(cache.clone()),
+                ..Default::default()
+            },
+            CleanupPolicy::STARTUP,
+            SystemTime::now(),
+        );
+
+        assert_eq!(report.removed_files, 0);
+        assert!(cache.join("sweep/1/preview.png").exists());
+    }
+}
@@ -0,0 +1,1702 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Local;
+use image::{ImageBuffer, ImageFormat, Rgb};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::algorithms::AlgorithmParams;
+use crate::align::AlignTransform;
+use crate::align_sidecar::TransformSidecar;
+use crate::capabilities::MAX_MERGE_FRAMES;
+use crate::clipping::{self, ClippingMap, ClippingSummary};
+use crate::config::Preset;
+use crate::decode::{self, DecodeError};
+use crate::deliverables::{self, Deliverable, DeliverableFormat, DeliverableOutput};
+use crate::dng;
+use crate::encode;
+use crate::exif::{self, ExposureCompensation};
+use crate::exr_color::ExrColorSpace;
+use crate::exr_preview;
+use crate::filters::{self, SharpenParams};
+use crate::formats;
+use crate::frame_quality::{self, FrameScore};
+use crate::frame_select::{self, FrameExposure, FrameSelection, SkippedFrame};
+use crate::geometry::{PerspectiveCorners, Roi};
+use crate::gray_card::{GrayCardCorrection, GrayCardSettings};
+use crate::input_check::{self, InputCheck};
+use crate::levels::{self, AppliedLevels, AutoLevels};
+use crate::long_exposure;
+use crate::noise_stack;
+use crate::output_color::{self, OutputColorSpace};
+use crate::output_lock::OutputReservation;
+use crate::output_path::{self, DefaultOutputDir, TemplateContext};
+use crate::paths;
+use crate::pipeline::{self, PipelineStage};
+use crate::prefetch::Prefetcher;
+use crate::print_output;
+use crate::progress::ProgressReporter;
+use crate::radiance::Radiance;
+use crate::send_to::{self, SendContext, SendTarget, SentOutput};
+use crate::tiled::MemoryFallback;
+use crate::watch_filter::OwnOutputs;
+use crate::workdir::write_atomically;
+
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+pub type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+// 位置合わせ・動体検出のパラメータをどの解像度で推定するか
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeQuality {
+    // 1/4 解像度で推定した結果をそのまま原寸に適用する
+    Fast,
+    // 1/4 解像度で推定し、原寸では間引いた画素や候補の画素だけで詰める
+    Balanced,
+    // すべて原寸の全画素で推定する
+    #[default]
+    Best,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeMode {
+    // 露出を変えたブラケットから階調を広げる
+    #[default]
+    Bracket,
+    // 同じ露出で撮ったフレームを位置合わせして平均し、ノイズだけを減らす
+    NoiseStack,
+    // 露出ごとに複数枚撮ったブラケット。露出の段ごとに noiseStack してから段の間で HDR 合成する
+    Hybrid,
+    // 連写を外れ値を除かずに平均し、滝や雲の流れを ND フィルターで撮ったような軌跡にする
+    LongExposure,
+}
+
+impl MergeQuality {
+    // 推定を始めるピラミッドの段（2 なら 1/4 解像度）
+    pub fn estimation_level(self) -> usize {
+        match self {
+            MergeQuality::Best => 0,
+            MergeQuality::Fast | MergeQuality::Balanced => 2,
+        }
+    }
+
+    pub fn refines_at_full(self) -> bool {
+        self != MergeQuality::Fast
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeRequest {
+    pub paths: Vec<String>,
+    pub output_dir: Option<String>,
+    // 例: `{outputRoot}/{yyyy}/{MM}/{dd}/{groupName}/`。{outputRoot} は output_dir（未指定なら入力フォルダ）
+    pub output_template: Option<String>,
+    // 未指定の場合は先頭の入力ファイル名
+    pub group_name: Option<String>,
+    pub output_exr: bool,
+    // 合成結果と位置合わせ後の各フレームを別レイヤーにした EXR（*_layers.exr）も書き出す
+    #[serde(default)]
+    pub output_layered_exr: bool,
+    // EXR のヘッダーに縮小プレビューを埋め込み、EXR を表示できないエクスプローラー・Finder 向けに *_preview.jpg も書き出す。
+    // outputExr のときだけ使う
+    #[serde(default)]
+    pub exr_preview: bool,
+    // トーンマップ前の放射輝度マップを DNG（LinearRaw）でも書き出す。カメラ・レンズの EXIF は先頭のフレームから写す。
+    // 最も明るい値が 1 になるよう縮めて保存し、基準フレームの明るさに戻す EV を BaselineExposure に書くため、
+    // 基準フレームで白飛びしたハイライトも 1 を超える値として現像ソフトで戻せる
+    #[serde(default)]
+    pub output_dng: bool,
+    // EXR（*_layers.exr を含む）を線形化してこの色空間で書き、chromaticities 属性を付ける。未指定なら sRGB の値のまま
+    pub exr_color_space: Option<ExrColorSpace>,
+    // 主出力の PNG と deliverables の PNG・JPEG の色空間。sRGB 以外は色域を変換して ICC プロファイルを埋め込む
+    #[serde(default)]
+    pub output_color_space: OutputColorSpace,
+    // align ステージで推定した変換を *_transforms.json に保存する
+    #[serde(default)]
+    pub save_transforms: bool,
+    // 保存しておいた変換ファイル。指定すると align ステージは推定せずにこの変換で揃える
+    pub transforms_path: Option<String>,
+    // 主出力の PNG（と EXR）に加えて、同じ合成結果から書き出す出力
+    #[serde(default)]
+    pub deliverables: Vec<Deliverable>,
+    // 未指定の場合は平均合成
+    pub algorithm: Option<String>,
+    #[serde(default)]
+    pub algorithm_params: AlgorithmParams,
+    // 未指定の場合は merge → geometry → encode
+    pub pipeline: Option<Vec<PipelineStage>>,
+    pub roi: Option<Roi>,
+    pub perspective: Option<PerspectiveCorners>,
+    #[serde(default)]
+    pub auto_straighten: bool,
+    // 再実行時にバイト単位で同一の出力を得るため、並列書き出しなど順序が揺れる処理を逐次化する
+    #[serde(default)]
+    pub deterministic: bool,
+    // 指定すると、露出の近いフレームを除いて EV の幅が広くなるよう最大 maxFrames 枚を選んで合成する
+    pub frame_selection: Option<FrameSelection>,
+    // 読み込めない・サイズが違う・重複した入力を除いて残りで合成する
+    #[serde(default)]
+    pub allow_partial: bool,
+    // ブレや動体で食い違うフレームを採点し、除外を勧めるフレームを除いて合成する
+    #[serde(default)]
+    pub auto_exclude_bad_frames: bool,
+    // 合成結果の横に白飛びの復元状況を示す透過 PNG（*_clipping.png）を出力する
+    #[serde(default)]
+    pub clipping_map: bool,
+    // 合成に使った基準フレームを合成結果と同じ変形で *_short.png に書き出す。longExposure で軌跡のない版と見比べる
+    #[serde(default)]
+    pub output_short_reference: bool,
+    #[serde(default)]
+    pub quality: MergeQuality,
+    #[serde(default)]
+    pub merge_mode: MergeMode,
+    // 同じ設定で撮ったフレームの露出のずれ（絞りのちらつきなど）を、画像から推定せず EXIF の露出時間・絞り・ISO で補正する。
+    // noiseStack・hybrid（露出の段ごと）・longExposure で使える
+    #[serde(default)]
+    pub exif_exposure_compensation: bool,
+    // bracket のタイムラプスで、各フレームを合わせる先頭のブラケットの EXIF の露出（暗い順）。
+    // 空なら先頭のブラケット自身。Some のときだけ bracket で exifExposureCompensation を使える
+    #[serde(skip)]
+    pub exif_reference_evs: Option<Vec<f64>>,
+    // グレーカードを測って、合成結果の露出と白バランスをそろえる
+    pub gray_card: Option<GrayCardSettings>,
+    // 測り済みのグレーカードの補正。指定すると gray_card を測らずにこの補正をかける（タイムラプスで固定する）
+    #[serde(skip)]
+    pub gray_card_correction: Option<GrayCardCorrection>,
+    // 合成結果の輝度のヒストグラムを指定の割合で黒点・白点まで伸ばしてから書き出す（グレーカードの補正の後）
+    pub auto_levels: Option<AutoLevels>,
+    // 求め済みの黒点・白点。指定すると autoLevels を測らずにこの補正をかける（タイムラプスで固定する）
+    #[serde(skip)]
+    pub levels_correction: Option<AppliedLevels>,
+    // 指定するとそのプリセットの合成設定で上書きし、履歴にプリセット名を記録する
+    pub preset: Option<String>,
+    // preset を反映済み。タイムラプスでは先頭で一度だけ読み、途中でプリセットを編集してもフレームの間で設定を変えない
+    #[serde(skip)]
+    pub preset_applied: bool,
+    // 指定すると進捗イベントに含まれ、merge_cancel で中止できる
+    pub job_id: Option<String>,
+    // 監視の検出から自動で始めた合成。設定の pauseWhileRunning のアプリが動いている間は順番待ちのままにする
+    #[serde(default)]
+    pub automatic: bool,
+    // 合成の作業領域の上限。超える場合や確保できない場合はタイルに分けて合成する（未指定なら設定値）
+    pub memory_budget_mb: Option<u64>,
+    // outputDir が未指定のときの出力先。設定の defaultOutputDir を入れる
+    #[serde(skip)]
+    pub default_output_dir: DefaultOutputDir,
+    // 合成後に結果を送る先。プリセットの sendTo から埋める
+    #[serde(skip)]
+    pub send_targets: Vec<SendTarget>,
+    // external ステージで実行してよい実行ファイル（設定の allowedPlugins）。フロントエンドからは指定しない
+    #[serde(skip)]
+    pub allowed_plugins: Vec<PathBuf>,
+    // 書き込みを禁止するフォルダ（読み取り専用モードの監視フォルダ）。フロントエンドからは指定しない
+    #[serde(skip)]
+    pub protected_dirs: Vec<PathBuf>,
+    // 設定の allowedWriteRoots。空なら出力先を制限しない
+    #[serde(skip)]
+    pub write_roots: Vec<PathBuf>,
+    // 書き出すファイルを監視の検出対象から外すための記録先。フロントエンドからは指定しない
+    #[serde(skip)]
+    pub own_outputs: Option<Arc<OwnOutputs>>,
+    // 書き込み途中のファイルを置くジョブごとの作業フォルダ。未指定なら出力の隣に置く
+    #[serde(skip)]
+    pub workdir: Option<PathBuf>,
+    // 拡張子を除いた出力名。未指定なら hdr_merge_<日時>
+    #[serde(skip)]
+    pub output_name: Option<String>,
+    #[serde(skip)]
+    pub progress: ProgressReporter,
+    // 先読み済みの入力。該当する画像があればデコードせずに使う
+    #[serde(skip)]
+    pub prefetched: Option<Arc<Prefetcher>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    pub output_png_path: String,
+    pub output_exr_path: Option<String>,
+    // レイヤー名は merged と、exposureOrder の順に frame1, frame2, ...
+    #[serde(default)]
+    pub output_layered_exr_path: Option<String>,
+    // saveTransforms のときの変換ファイル
+    #[serde(default)]
+    pub transforms_path: Option<String>,
+    // outputShortReference のときの基準フレーム
+    #[serde(default)]
+    pub output_short_reference_path: Option<String>,
+    // プリセットの sendTo で送った結果
+    #[serde(default)]
+    pub sent_to: Vec<SentOutput>,
+    // exrPreview のときのサイドカー JPEG
+    #[serde(default)]
+    pub output_preview_path: Option<String>,
+    #[serde(default)]
+    pub output_dng_path: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub merged_at: String,
+    pub algorithm: String,
+    pub stages: Vec<String>,
+    pub alignment_offsets: Vec<[i32; 2]>,
+    // align ステージで推定した各フレームの変換（translation では回転 0・拡大率 1）
+    #[serde(default)]
+    pub alignment_transforms: Vec<AlignTransform>,
+    pub straighten_angle: Option<f64>,
+    // frameSelection・autoExcludeBadFrames で合成に使わなかったフレーム
+    #[serde(default)]
+    pub skipped_frames: Vec<SkippedFrame>,
+    // 合成に使った順序（暗い順）と各フレームの相対 EV
+    #[serde(default)]
+    pub exposure_order: Vec<FrameExposure>,
+    // allowPartial で除外した入力
+    #[serde(default)]
+    pub excluded_inputs: Vec<InputCheck>,
+    #[serde(default)]
+    pub clipping: Option<ClippingSummary>,
+    // 読み込みから書き出しまでの所要時間
+    #[serde(default)]
+    pub duration_ms: u64,
+    // メモリが足りずタイルに分けて合成した場合のみ
+    #[serde(default)]
+    pub memory_fallback: Option<MemoryFallback>,
+    #[serde(default)]
+    pub deliverables: Vec<DeliverableOutput>,
+    // autoExcludeBadFrames のときの各フレームの採点
+    #[serde(default)]
+    pub frame_quality: Vec<FrameScore>,
+    // grayCard を指定したときに合成結果へかけた補正
+    #[serde(default)]
+    pub gray_card: Option<GrayCardCorrection>,
+    // autoLevels を指定したときに求めた黒点・白点
+    #[serde(default)]
+    pub auto_levels: Option<AppliedLevels>,
+    // exifExposureCompensation のときの各フレームの補正
+    #[serde(default)]
+    pub exposure_compensation: Vec<ExposureCompensation>,
+}
+
+pub struct MergedImage {
+    pub image: Rgb16Image,
+    pub algorithm: String,
+    pub stages: Vec<String>,
+    pub alignment_offsets: Vec<[i32; 2]>,
+    pub alignment_transforms: Vec<AlignTransform>,
+    pub straighten_angle: Option<f64>,
+    pub memory_fallback: Option<MemoryFallback>,
+    // outputLayeredExr のときだけ、合成結果と同じ大きさの各フレーム
+    pub frame_layers: Vec<Rgb16Image>,
+    // outputShortReference のときだけ、合成結果と同じ大きさの基準フレーム
+    pub short_reference: Option<Rgb16Image>,
+    // align ステージを実行したときだけ
+    pub alignment_sidecar: Option<TransformSidecar>,
+    // encode ステージの出力シャープ
+    pub sharpen: Option<SharpenParams>,
+    // autoLevels の黒点・白点。image には適用せず、SDR の出力を書き出すときにだけかける
+    pub levels: Option<AppliedLevels>,
+    // merge ステージの直後に測ったグレーカードの補正
+    pub gray_card: Option<GrayCardCorrection>,
+    // outputDng のときだけ、トーンマップ前の線形の放射輝度
+    pub radiance: Option<Radiance>,
+    // clippingMap のときだけ、入力のフレームから作った白飛びの重ね合わせ。ほかの出力と一緒に書き出す
+    pub clipping: Option<ClippingMap>,
+}
+
+impl MergeResult {
+    // 合成で書き出したすべてのファイル。sendTo で送り先にコピーしたものは含めない
+    pub fn output_paths(&self) -> Vec<String> {
+        std::iter::once(&self.output_png_path)
+            .chain(&self.output_exr_path)
+            .chain(&self.output_layered_exr_path)
+            .chain(&self.transforms_path)
+            .chain(&self.output_short_reference_path)
+            .chain(&self.output_preview_path)
+            .chain(&self.output_dng_path)
+            .chain(self.clipping.as_ref().map(|clipping| &clipping.path))
+            .chain(
+                self.deliverables
+                    .iter()
+                    .map(|deliverable| &deliverable.path),
+            )
+            .cloned()
+            .collect()
+    }
+}
+
+impl MergedImage {
+    // PNG・プレビュー・SDR の納品用に書き出す、レベル補正後の画像
+    pub fn sdr_image(&self) -> Cow<'_, Rgb16Image> {
+        match &self.levels {
+            Some(levels) => Cow::Owned(levels::apply(&self.image, levels)),
+            None => Cow::Borrowed(&self.image),
+        }
+    }
+}
+
+impl MergeRequest {
+    pub fn apply_preset(&mut self, preset: &Preset) {
+        self.algorithm = preset.algorithm.clone();
+        self.algorithm_params = preset.algorithm_params.clone();
+        self.pipeline = preset.pipeline.clone();
+        self.output_exr = preset.output_exr;
+        self.deterministic = preset.deterministic;
+        self.preset = Some(preset.name.clone());
+    }
+}
+
+pub fn run_merge(request: &MergeRequest) -> Result<MergeResult, String> {
+    let started = Instant::now();
+    let max_inputs = match (request.merge_mode, &request.frame_selection) {
+        (MergeMode::NoiseStack, _) => noise_stack::MAX_STACK_FRAMES,
+        (MergeMode::Hybrid, _) => noise_stack::MAX_HYBRID_FRAMES,
+        (MergeMode::LongExposure, _) => long_exposure::MAX_LONG_EXPOSURE_FRAMES,
+        (MergeMode::Bracket, Some(_)) => frame_select::MAX_SELECTION_CANDIDATES,
+        (MergeMode::Bracket, None) => MAX_MERGE_FRAMES,
+    };
+    if request.paths.len() < 2 {
+        return Err("合成には最低2枚必要です".to_string());
+    }
+    if request.paths.len() > max_inputs {
+        return Err(format!("合成は最大{}枚までです", max_inputs));
+    }
+    deliverables::validate(&request.deliverables)?;
+    if let Some(settings) = &request.gray_card {
+        settings.validate()?;
+    }
+    if let Some(settings) = &request.auto_levels {
+        settings.validate()?;
+    }
+
+    // フレームを選ぶ場合は候補をすべて保持しないよう、検証では露出だけ測って画像を破棄する
+    let validated = input_check::validate_inputs(
+        &request.paths,
+        request.frame_selection.is_none(),
+        request.prefetched.as_deref(),
+    );
+    let excluded_inputs = validated.problems();
+    if !excluded_inputs.is_empty() && !request.allow_partial {
+        return Err(input_check::describe(&excluded_inputs));
+    }
+    if validated.frames.len() < 2 {
+        return Err(format!(
+            "有効な入力が2枚未満のため合成できません\n{}",
+            input_check::describe(&excluded_inputs)
+        ));
+    }
+    if request.merge_mode == MergeMode::NoiseStack {
+        let evs: Vec<f64> = validated.frames.iter().map(|frame| frame.ev).collect();
+        noise_stack::validate(request, &evs)?;
+    }
+    if request.merge_mode == MergeMode::LongExposure {
+        long_exposure::validate(request)?;
+    }
+    if request.merge_mode == MergeMode::Hybrid && request.frame_selection.is_some() {
+        return Err("hybrid では frameSelection を指定できません".to_string());
+    }
+    if request.exif_exposure_compensation
+        && request.merge_mode == MergeMode::Bracket
+        && request.exif_reference_evs.is_none()
+    {
+        return Err(
+            "exifExposureCompensation は noiseStack・hybrid・longExposure とタイムラプスで指定してください"
+                .to_string(),
+        );
+    }
+    let frame_evs: Vec<(String, f64)> = validated
+        .frames
+        .iter()
+        .map(|frame| (frame.path.clone(), frame.ev))
+        .collect();
+
+    let (mut paths, mut images, mut skipped_frames) = match &request.frame_selection {
+        Some(selection) => {
+            let paths: Vec<String> = validated.frames.iter().map(|f| f.path.clone()).collect();
+            let evs: Vec<f64> = validated.frames.iter().map(|f| f.ev).collect();
+            let selection = frame_select::select_frames(&paths, &evs, selection)?;
+            let images = load_inputs(&selection.paths)?;
+            (selection.paths, images, selection.skipped)
+        }
+        None => {
+            let (paths, images) = validated
+                .frames
+                .into_iter()
+                .map(|frame| {
+                    let image = frame
+                        .image
+                        .expect("images are kept without frame selection");
+                    (frame.path, image)
+                })
+                .unzip();
+            (paths, images, Vec::new())
+        }
+    };
+    let mut frame_scores = Vec::new();
+    if request.auto_exclude_bad_frames {
+        let quality = frame_quality::score_frames(&paths, &images);
+        let (kept_paths, kept_images) = paths
+            .into_iter()
+            .zip(images)
+            .filter(|(path, _)| !quality.recommended_exclusions.contains(path))
+            .unzip();
+        (paths, images) = (kept_paths, kept_images);
+        skipped_frames.extend(quality.scores.iter().filter_map(|score| {
+            score.exclude_reason.as_ref().map(|reason| SkippedFrame {
+                path: score.path.clone(),
+                ev: score.ev,
+                reason: reason.clone(),
+            })
+        }));
+        frame_scores = quality.scores;
+    }
+    let evs: Vec<f64> = paths
+        .iter()
+        .map(|path| {
+            frame_evs
+                .iter()
+                .find(|(frame, _)| frame == path)
+                .map_or(0.0, |(_, ev)| *ev)
+        })
+        .collect();
+    let mut exposure_compensation = Vec::new();
+    if request.exif_exposure_compensation {
+        // 画像から測った EV は段を分けるためだけに使い、段の中の明るさは EXIF でそろえる
+        exposure_compensation = match (request.merge_mode, &request.exif_reference_evs) {
+            (MergeMode::Bracket, Some(reference)) => {
+                exif::compensate_to_reference(&paths, &mut images, reference)?
+            }
+            (MergeMode::Hybrid, _) => {
+                let levels = noise_stack::exposure_levels(&evs);
+                exif::compensate(&paths, &mut images, &levels)?
+            }
+            _ => exif::compensate(&paths, &mut images, &[(0..paths.len()).collect()])?,
+        };
+    }
+    if request.merge_mode == MergeMode::Hybrid {
+        (paths, images) = noise_stack::stack_levels(paths, images, &evs, request.quality)?;
+    }
+    // 入力の順序に依存しないよう暗い順に並べ、出力先や基準フレームもその順で決める
+    let (images, exposure_order) = frame_select::sort_by_exposure(&paths, images);
+    let sorted = MergeRequest {
+        paths: exposure_order
+            .iter()
+            .map(|frame| frame.path.clone())
+            .collect(),
+        ..request.clone()
+    };
+    request.progress.report("decode", 1.0);
+    request.progress.check_cancelled()?;
+    request.progress.report("merge", 0.0);
+    let mut merged = process(&images, &sorted)?;
+    if request.merge_mode == MergeMode::Hybrid {
+        merged.stages.insert(0, "stack".to_string());
+    }
+    let gray_card = merged.gray_card.clone();
+    merged.levels = request.auto_levels.map(|settings| {
+        request
+            .levels_correction
+            .unwrap_or_else(|| levels::measure(&merged.image, &settings))
+    });
+    if request.clipping_map {
+        merged.clipping = Some(clipping::clipping_map(
+            &images,
+            sorted.roi.as_ref(),
+            merged.image.dimensions(),
+        )?);
+    }
+    request.progress.report("merge", 1.0);
+    request.progress.check_cancelled()?;
+    // 送り先への書き出しが終わるまで、予約した出力名を手放さない
+    let (mut result, _reservation) = write_outputs(&merged, &sorted)?;
+    if !request.send_targets.is_empty() {
+        let group_name = request
+            .group_name
+            .clone()
+            .unwrap_or_else(|| output_path::default_group_name(&request.paths));
+        let context = SendContext {
+            group_name: &group_name,
+            algorithm: &merged.algorithm,
+            protected_dirs: &request.protected_dirs,
+            write_roots: &request.write_roots,
+            own_outputs: request.own_outputs.as_deref(),
+        };
+        result.sent_to = send_to::send_all(
+            &merged.image,
+            &merged.sdr_image(),
+            &request.send_targets,
+            &context,
+        );
+    }
+    result.skipped_frames = skipped_frames;
+    result.frame_quality = frame_scores;
+    result.gray_card = gray_card;
+    result.auto_levels = merged.levels;
+    result.exposure_compensation = exposure_compensation;
+    result.exposure_order = exposure_order;
+    result.excluded_inputs = excluded_inputs;
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    Ok(result)
+}
+
+pub fn load_inputs(paths: &[String]) -> Result<Vec<Rgb16Image>, String> {
+    if paths.len() < 2 {
+        return Err("合成には最低2枚必要です".to_string());
+    }
+    if paths.len() > MAX_MERGE_FRAMES {
+        return Err(format!("合成は最大{}枚までです", MAX_MERGE_FRAMES));
+    }
+
+    let images: Vec<Rgb16Image> = paths
+        .iter()
+        .map(|path| load_rgb16(path))
+        .collect::<Result<_, _>>()?;
+
+    let width = images[0].width();
+    let height = images[0].height();
+
+    for image in &images {
+        if image.width() != width || image.height() != height {
+            return Err("画像サイズが一致しません".to_string());
+        }
+    }
+
+    Ok(images)
+}
+
+pub fn process(images: &[Rgb16Image], request: &MergeRequest) -> Result<MergedImage, String> {
+    pipeline::run(images, request)
+}
+
+// 書き出した結果と出力名の予約を返す。後から同じ名前の付随ファイルを書く間は予約を持っておく
+pub fn write_outputs(
+    merged: &MergedImage,
+    request: &MergeRequest,
+) -> Result<(MergeResult, OutputReservation), String> {
+    let output_root = match &request.output_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => output_path::default_output_root(
+            Path::new(&request.paths[0]),
+            &request.default_output_dir,
+        )?,
+    };
+    let output_dir = match &request.output_template {
+        Some(template) => {
+            let group_name = request
+                .group_name
+                .clone()
+                .unwrap_or_else(|| output_path::default_group_name(&request.paths));
+            output_path::resolve_template(
+                template,
+                &TemplateContext {
+                    output_root: &output_root,
+                    now: Local::now(),
+                    group_name: &group_name,
+                    algorithm: &merged.algorithm,
+                },
+            )?
+        }
+        None => output_root,
+    };
+
+    output_path::ensure_outside_protected(&output_dir, &request.protected_dirs)?;
+    paths::ensure_writable(&output_dir, &request.write_roots)?;
+    let os_output_dir = paths::extended(&output_dir);
+    if !os_output_dir.exists() {
+        std::fs::create_dir_all(&os_output_dir)
+            .map_err(|e| format!("出力先フォルダを作成できません: {}", e))?;
+    }
+
+    let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let base_name = match (&request.output_name, &request.roi) {
+        (Some(name), _) => name.clone(),
+        (None, Some(_)) => format!("hdr_merge_{}_roi", timestamp),
+        (None, None) => format!("hdr_merge_{}", timestamp),
+    };
+    // 同じ秒に同じフォルダへ書き出すジョブがあっても互いに上書きしないよう、書き終えるまで
+    // このジョブが書くファイルをすべて予約する
+    let output_files = |name: &str| {
+        let mut files = vec![format!("{}.png", name)];
+        let optional = [
+            (request.output_exr, ".exr"),
+            (request.output_exr && request.exr_preview, "_preview.jpg"),
+            (request.output_layered_exr, "_layers.exr"),
+            (
+                request.save_transforms && merged.alignment_sidecar.is_some(),
+                "_transforms.json",
+            ),
+            (merged.short_reference.is_some(), "_short.png"),
+            (request.output_dng, ".dng"),
+            (merged.clipping.is_some(), "_clipping.png"),
+        ];
+        files.extend(
+            optional
+                .iter()
+                .filter(|(written, _)| *written)
+                .map(|(_, suffix)| format!("{}{}", name, suffix)),
+        );
+        files.extend(
+            request
+                .deliverables
+                .iter()
+                .map(|deliverable| deliverable.file_name(name)),
+        );
+        files
+    };
+    let reservation = OutputReservation::reserve(
+        &output_dir,
+        &base_name,
+        request.output_name.is_some(),
+        output_files,
+    )?;
+    let base_name = reservation.name();
+    let png_path = output_dir.join(format!("{}.png", base_name));
+    let exr_path = output_dir.join(format!("{}.exr", base_name));
+    let layered_exr_path = output_dir.join(format!("{}_layers.exr", base_name));
+    let transforms_path = output_dir.join(format!("{}_transforms.json", base_name));
+    let short_reference_path = output_dir.join(format!("{}_short.png", base_name));
+    let preview_path = output_dir.join(format!("{}_preview.jpg", base_name));
+    let dng_path = output_dir.join(format!("{}.dng", base_name));
+    let clipping_path = output_dir.join(format!("{}_clipping.png", base_name));
+    let write_preview = request.output_exr && request.exr_preview;
+    let deliverable_paths: Vec<PathBuf> = request
+        .deliverables
+        .iter()
+        .map(|deliverable| output_dir.join(deliverable.file_name(base_name)))
+        .collect();
+    if let Some(own_outputs) = &request.own_outputs {
+        own_outputs.record(&png_path);
+        if request.output_exr {
+            own_outputs.record(&exr_path);
+        }
+        if write_preview {
+            own_outputs.record(&preview_path);
+        }
+        if request.output_dng {
+            own_outputs.record(&dng_path);
+        }
+        if request.output_layered_exr {
+            own_outputs.record(&layered_exr_path);
+        }
+        if request.save_transforms && merged.alignment_sidecar.is_some() {
+            own_outputs.record(&transforms_path);
+        }
+        if merged.short_reference.is_some() {
+            own_outputs.record(&short_reference_path);
+        }
+        if merged.clipping.is_some() {
+            own_outputs.record(&clipping_path);
+        }
+        for path in &deliverable_paths {
+            own_outputs.record(path);
+        }
+    }
+
+    // 自動レベル補正と出力シャープは SDR の出力だけにかけ、EXR・DNG には線形のまま書き出す
+    let leveled = merged.sdr_image();
+    let sharpened = merged
+        .sharpen
+        .as_ref()
+        .map(|params| filters::sharpen(&leveled, params));
+    let image = sharpened.as_ref().unwrap_or(&leveled);
+    write_atomically(request.workdir.as_deref(), &png_path, |path| {
+        let converted = output_color::convert(image, request.output_color_space);
+        encode::write_png_as(
+            converted.as_ref().unwrap_or(image),
+            path,
+            request.output_color_space,
+            &request.progress,
+        )
+    })?;
+
+    let mut output_exr_path = None;
+    if request.output_exr {
+        // 出力シャープは SDR の出力だけにかけ、線形のマスターにはかけない（埋め込みプレビューは SDR）
+        write_atomically(request.workdir.as_deref(), &exr_path, |path| {
+            let preview = write_preview.then(|| exr_preview::preview_attribute(image));
+            encode::write_exr_with_preview(
+                &merged.image,
+                path,
+                request.deterministic,
+                preview,
+                request.exr_color_space,
+                &request.progress,
+            )
+        })
+        // PNG だけ残ると出力が揃わないため、EXR を中止したときは PNG も消す
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(paths::extended(&png_path));
+        })?;
+
+        output_exr_path = Some(exr_path.to_string_lossy().to_string());
+    }
+
+    let mut written = vec![png_path.clone()];
+    written.extend(request.output_exr.then(|| exr_path.clone()));
+    let mut output_layered_exr_path = None;
+    if request.output_layered_exr {
+        // 合成し直すためのレイヤーなので、各フレームと同じくシャープをかけない
+        let mut layers = vec![("merged".to_string(), &merged.image)];
+        layers.extend(
+            merged
+                .frame_layers
+                .iter()
+                .enumerate()
+                .map(|(index, frame)| (format!("frame{}", index + 1), frame)),
+        );
+        write_atomically(request.workdir.as_deref(), &layered_exr_path, |path| {
+            encode::write_layered_exr(
+                &layers,
+                path,
+                request.deterministic,
+                request.exr_color_space,
+                &request.progress,
+            )
+        })
+        .inspect_err(|_| {
+            for path in &written {
+                let _ = std::fs::remove_file(paths::extended(path));
+            }
+        })?;
+        written.push(layered_exr_path.clone());
+        output_layered_exr_path = Some(layered_exr_path.to_string_lossy().to_string());
+    }
+    let mut saved_transforms_path = None;
+    if let (true, Some(sidecar)) = (request.save_transforms, &merged.alignment_sidecar) {
+        write_atomically(request.workdir.as_deref(), &transforms_path, |path| {
+            sidecar.save(path)
+        })
+        .inspect_err(|_| {
+            for path in &written {
+                let _ = std::fs::remove_file(paths::extended(path));
+            }
+        })?;
+        written.push(transforms_path.clone());
+        saved_transforms_path = Some(transforms_path.to_string_lossy().to_string());
+    }
+    let mut output_short_reference_path = None;
+    if let Some(reference) = &merged.short_reference {
+        write_atomically(request.workdir.as_deref(), &short_reference_path, |path| {
+            encode::write_png(reference, path, &request.progress)
+        })
+        .inspect_err(|_| {
+            for path in &written {
+                let _ = std::fs::remove_file(paths::extended(path));
+            }
+        })?;
+        written.push(short_reference_path.clone());
+        output_short_reference_path = Some(short_reference_path.to_string_lossy().to_string());
+    }
+    let mut output_preview_path = None;
+    if write_preview {
+        write_atomically(request.workdir.as_deref(), &preview_path, |path| {
+            exr_preview::write_sidecar_jpeg(image, path)
+        })
+        .inspect_err(|_| {
+            for path in &written {
+                let _ = std::fs::remove_file(paths::extended(path));
+            }
+        })?;
+        written.push(preview_path.clone());
+        output_preview_path = Some(preview_path.to_string_lossy().to_string());
+    }
+    let mut output_dng_path = None;
+    if request.output_dng {
+        let camera = exif::read_camera(Path::new(&request.paths[0])).unwrap_or_default();
+        // 現像ソフトで仕上げる元データなので、トーンマップ前の放射輝度をそのまま書き、
+        // トーンマップ・レベル補正・出力シャープ・色空間の変換はかけない
+        write_atomically(request.workdir.as_deref(), &dng_path, |path| {
+            let radiance = merged
+                .radiance
+                .as_ref()
+                .ok_or("DNG に書き出す放射輝度がありません")?;
+            dng::write_dng(
+                &radiance.image,
+                radiance.baseline_exposure,
+                path,
+                &camera,
+                &request.progress,
+            )
+        })
+        .inspect_err(|_| {
+            for path in &written {
+                let _ = std::fs::remove_file(paths::extended(path));
+            }
+        })?;
+        written.push(dng_path.clone());
+        output_dng_path = Some(dng_path.to_string_lossy().to_string());
+    }
+    let mut deliverable_outputs = Vec::new();
+    for (deliverable, path) in request.deliverables.iter().zip(&deliverable_paths) {
+        // 縮小でぼけた分を戻せるよう、シャープ前の画像を縮小してからかける。EXR には主出力と同じくかけない
+        let (sharpen, base, source) = match deliverable.format {
+            DeliverableFormat::Exr => (None, &merged.image, &merged.image),
+            _ => (merged.sharpen.as_ref(), leveled.as_ref(), image),
+        };
+        let resized = deliverables::resize_for(base, deliverable).map(|resized| match sharpen {
+            Some(params) => filters::sharpen(&resized, params),
+            None => resized,
+        });
+        let output = resized.as_ref().unwrap_or(source);
+        let print = deliverable
+            .print
+            .as_ref()
+            .map(|print| print.layout(output.width(), output.height()));
+        write_atomically(request.workdir.as_deref(), path, |partial| {
+            deliverables::write(
+                output,
+                deliverable,
+                partial,
+                print.as_ref(),
+                request.output_color_space,
+                request.deterministic,
+                &request.progress,
+            )
+        })
+        // 一部の出力だけが残らないよう、書き出し済みのものも消す
+        .inspect_err(|_| {
+            for path in &written {
+                let _ = std::fs::remove_file(paths::extended(path));
+            }
+        })?;
+        written.push(path.clone());
+        deliverable_outputs.push(DeliverableOutput {
+            suffix: deliverable.suffix.clone(),
+            format: deliverable.format,
+            path: path.to_string_lossy().to_string(),
+            width: output.width(),
+            height: output.height(),
+            print,
+            ink_warning: deliverable
+                .print
+                .as_ref()
+                .and_then(|print| print.ink_limit)
+                .and_then(|limit| print_output::ink_warning(output, limit)),
+        });
+    }
+    let mut clipping = None;
+    if let Some(map) = &merged.clipping {
+        write_atomically(request.workdir.as_deref(), &clipping_path, |path| {
+            map.overlay
+                .save_with_format(path, ImageFormat::Png)
+                .map_err(|e| e.to_string())
+        })
+        .inspect_err(|_| {
+            for path in &written {
+                let _ = std::fs::remove_file(paths::extended(path));
+            }
+        })?;
+        written.push(clipping_path.clone());
+        clipping = Some(ClippingSummary {
+            path: clipping_path.to_string_lossy().to_string(),
+            unrecoverable_fraction: map.unrecoverable_fraction,
+            recovered_fraction: map.recovered_fraction,
+        });
+    }
+
+    let result = MergeResult {
+        output_png_path: png_path.to_string_lossy().to_string(),
+        output_exr_path,
+        output_layered_exr_path,
+        transforms_path: saved_transforms_path,
+        output_short_reference_path,
+        output_preview_path,
+        output_dng_path,
+        sent_to: Vec::new(),
+        width: image.width(),
+        height: image.height(),
+        merged_at: Local::now().to_rfc3339(),
+        algorithm: merged.algorithm.clone(),
+        stages: merged.stages.clone(),
+        alignment_offsets: merged.alignment_offsets.clone(),
+        alignment_transforms: merged.alignment_transforms.clone(),
+        straighten_angle: merged.straighten_angle,
+        skipped_frames: Vec::new(),
+        exposure_order: Vec::new(),
+        excluded_inputs: Vec::new(),
+        clipping,
+        duration_ms: 0,
+        memory_fallback: merged.memory_fallback.clone(),
+        deliverables: deliverable_outputs,
+        frame_quality: Vec::new(),
+        gray_card: None,
+        auto_levels: None,
+        exposure_compensation: Vec::new(),
+    };
+    Ok((result, reservation))
+}
+
+pub fn save_png(image: &Rgb16Image, path: &Path) -> Result<(), String> {
+    image.save(path).map_err(|e| e.to_string())
+}
+
+// 上限を超える大きさは DecodeError::ImageTooLarge で返すので、呼び出し側で読めないファイルと区別できる
+pub fn load_rgb16(path: &str) -> Result<Rgb16Image, DecodeError> {
+    let os_path = paths::input_file(path).map_err(|e| DecodeError::Unreadable(e.to_string()))?;
+    formats::ensure_decodable(&os_path).map_err(DecodeError::Unreadable)?;
+    decode::decode_rgb16(&os_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::NormalizedPoint;
+    use crate::print_output::PrintSettings;
+    use crate::synthetic::{self, TestBracketOptions};
+
+    fn bracket_request(dir: &Path, options: &TestBracketOptions) -> MergeRequest {
+        let bracket = synthetic::generate_bracket(&dir.join("input"), options).unwrap();
+        MergeRequest {
+            paths: bracket.paths,
+            output_dir: Some(dir.join("output").to_string_lossy().to_string()),
+            output_exr: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merges_synthetic_bracket_to_png_and_exr() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let request = bracket_request(dir.path(), &options);
+
+        let result = run_merge(&request).unwrap();
+
+        assert_eq!((result.width, result.height), (64, 48));
+        assert!(Path::new(&result.output_png_path).exists());
+        assert!(Path::new(result.output_exr_path.as_ref().unwrap()).exists());
+        assert_eq!(
+            load_rgb16(&result.output_png_path).unwrap().dimensions(),
+            (64, 48)
+        );
+        let leftovers = std::fs::read_dir(dir.path().join("output"))
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(PARTIAL_SUFFIX)
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn exr_preview_is_embedded_and_written_beside_the_exr() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut request = bracket_request(dir.path(), &TestBracketOptions::default());
+        request.exr_preview = true;
+
+        let result = run_merge(&request).unwrap();
+
+        let sidecar = result.output_preview_path.unwrap();
+        assert!(sidecar.ends_with("_preview.jpg"));
+        // 640x426 の合成結果を長辺 512 に縮小する
+        assert_eq!(image::image_dimensions(&sidecar).unwrap(), (512, 341));
+        let exr =
+            exr::prelude::read_all_flat_layers_from_file(result.output_exr_path.unwrap()).unwrap();
+        let preview = exr.layer_data[0].attributes.preview.as_ref().unwrap();
+        assert_eq!((preview.size.0, preview.size.1), (256, 170));
+
+        request.output_exr = false;
+        assert!(run_merge(&request).unwrap().output_preview_path.is_none());
+    }
+
+    #[test]
+    fn writes_the_radiance_map_to_dng() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_dng = true;
+
+        let result = run_merge(&request).unwrap();
+
+        let dng = std::fs::read(result.output_dng_path.unwrap()).unwrap();
+        assert_eq!(&dng[..4], b"II*\0");
+        assert_eq!(dng.len() % 2, 0);
+        let u16_at = |at: usize| u16::from_le_bytes([dng[at], dng[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(dng[at..at + 4].try_into().unwrap());
+        let ifd = u32_at(4) as usize;
+        let find = |tag: u16| {
+            (0..u16_at(ifd) as usize)
+                .map(|i| ifd + 2 + i * 12)
+                .find(|entry| u16_at(*entry) == tag)
+                .map(|entry| (u32_at(entry + 4), u32_at(entry + 8)))
+                .unwrap()
+        };
+        // 48 行は 1 つのストリップに収まり、StripOffsets の値がそのまま画素の位置になる
+        let (strips, start) = find(273);
+        assert_eq!(strips, 1);
+        let start = start as usize;
+        assert_eq!(dng.len(), start + 64 * 48 * 6);
+        let (_, at) = find(50730);
+        let at = at as usize;
+        let baseline = i32::from_le_bytes(dng[at..at + 4].try_into().unwrap()) as f64
+            / i32::from_le_bytes(dng[at + 4..at + 8].try_into().unwrap()) as f64;
+        let brightest = (start..dng.len()).step_by(2).map(u16_at).max().unwrap();
+        // 基準フレームで白飛びする明るい円やグラデーションの右端が、1 を超える値のまま残る
+        let restored = brightest as f64 / u16::MAX as f64 * baseline.exp2();
+        assert!(baseline > 0.5, "{}", baseline);
+        assert!(restored > 1.5, "{}", restored);
+    }
+
+    #[test]
+    fn auto_levels_stretches_the_output_and_reports_the_points() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.deterministic = true;
+        let plain = run_merge(&request).unwrap();
+        request.auto_levels = Some(AutoLevels {
+            black_percentile: 1.0,
+            white_percentile: 99.0,
+        });
+
+        let result = run_merge(&request).unwrap();
+
+        let applied = result.auto_levels.unwrap();
+        assert!(applied.black_point < applied.white_point);
+        let output = load_rgb16(&result.output_png_path).unwrap();
+        let lumas: Vec<f32> = output
+            .pixels()
+            .map(|pixel| crate::color::luminance(pixel.0.map(crate::color::u16_to_unit)))
+            .collect();
+        let darkest = lumas.iter().cloned().fold(f32::MAX, f32::min);
+        let brightest = lumas.iter().cloned().fold(0.0, f32::max);
+        assert!(darkest < 0.02 && brightest > 0.98);
+        // 線形の EXR には伸ばしをかけない
+        let read = |path: &Option<String>| std::fs::read(path.as_ref().unwrap()).unwrap();
+        assert_eq!(read(&result.output_exr_path), read(&plain.output_exr_path));
+
+        // 求め済みの補正を渡すと測り直さずにそのままかける
+        let fixed = AppliedLevels {
+            black_point: 0.1,
+            white_point: 0.9,
+            ..applied
+        };
+        request.levels_correction = Some(fixed);
+        assert_eq!(run_merge(&request).unwrap().auto_levels, Some(fixed));
+    }
+
+    #[test]
+    fn sends_the_result_to_preset_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut request = bracket_request(dir.path(), &TestBracketOptions::default());
+        let project = dir.path().join("Assets");
+        request.group_name = Some("sky".to_string());
+        request.send_targets = vec![SendTarget {
+            name: "unity".to_string(),
+            folder: project.to_string_lossy().to_string(),
+            file_name: "hdri_{groupName}".to_string(),
+            max_size: Some(128),
+            ..Default::default()
+        }];
+
+        let result = run_merge(&request).unwrap();
+
+        let sent = &result.sent_to[0];
+        assert_eq!(sent.error, None);
+        assert_eq!(
+            PathBuf::from(sent.path.as_ref().unwrap()),
+            project.join("hdri_sky.exr")
+        );
+        assert_eq!((sent.width, sent.height), (128, 85));
+    }
+
+    #[test]
+    fn encode_stage_sharpens_outputs_after_resizing() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 96,
+            height: 64,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.deterministic = true;
+        let deliverable = |format, suffix: &str| Deliverable {
+            format,
+            suffix: suffix.to_string(),
+            max_size: Some(48),
+            bit_depth: None,
+            quality: None,
+            print: None,
+        };
+        request.deliverables = vec![
+            deliverable(DeliverableFormat::Png, "_small"),
+            deliverable(DeliverableFormat::Exr, "_small_hdr"),
+        ];
+        // 隣り合う画素の差の合計。シャープをかけると大きくなる
+        let edges = |path: &str| {
+            let image = load_rgb16(path).unwrap();
+            image
+                .as_raw()
+                .windows(4)
+                .map(|pair| (pair[0] as i64 - pair[3] as i64).abs())
+                .sum::<i64>()
+        };
+        let plain = run_merge(&request).unwrap();
+        request.pipeline = Some(vec![
+            PipelineStage::Merge,
+            PipelineStage::Encode(crate::encode::EncodeParams {
+                sharpen: Some(SharpenParams {
+                    amount: 1.0,
+                    ..Default::default()
+                }),
+            }),
+        ]);
+
+        let sharpened = run_merge(&request).unwrap();
+
+        assert!(edges(&sharpened.output_png_path) > edges(&plain.output_png_path));
+        assert_eq!(sharpened.deliverables[0].width, 48);
+        assert!(edges(&sharpened.deliverables[0].path) > edges(&plain.deliverables[0].path));
+        // 線形の EXR にはかけない
+        let read = |path: &str| std::fs::read(path).unwrap();
+        assert_eq!(
+            read(sharpened.output_exr_path.as_ref().unwrap()),
+            read(plain.output_exr_path.as_ref().unwrap())
+        );
+        assert_eq!(
+            read(&sharpened.deliverables[1].path),
+            read(&plain.deliverables[1].path)
+        );
+    }
+
+    #[test]
+    fn layered_exr_has_merged_and_resized_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        request.output_layered_exr = true;
+        request.pipeline = Some(vec![
+            PipelineStage::Align(Default::default()),
+            PipelineStage::Merge,
+            PipelineStage::Resize(crate::filters::ResizeParams { max_size: 32 }),
+            PipelineStage::Encode(Default::default()),
+        ]);
+
+        let result = run_merge(&request).unwrap();
+
+        let path = result.output_layered_exr_path.unwrap();
+        assert!(path.ends_with("_layers.exr"));
+        let image = exr::prelude::read_all_flat_layers_from_file(&path).unwrap();
+        assert_eq!(image.layer_data.len(), result.exposure_order.len() + 1);
+        assert!(image
+            .layer_data
+            .iter()
+            .all(|layer| (layer.size.0, layer.size.1) == (32, 24)));
+        assert_eq!(
+            image.layer_data[0]
+                .attributes
+                .layer_name
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "merged"
+        );
+    }
+
+    #[test]
+    fn saved_transforms_are_reused_on_remerge() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        request.save_transforms = true;
+        request.pipeline = Some(vec![
+            PipelineStage::Align(Default::default()),
+            PipelineStage::Merge,
+            PipelineStage::Encode(Default::default()),
+        ]);
+
+        let first = run_merge(&request).unwrap();
+        let transforms_path = first.transforms_path.unwrap();
+        assert!(transforms_path.ends_with("_transforms.json"));
+
+        request.save_transforms = false;
+        request.transforms_path = Some(transforms_path);
+        request.algorithm = Some("fusion".to_string());
+        request.output_dir = Some(dir.path().join("remerge").to_string_lossy().to_string());
+        let second = run_merge(&request).unwrap();
+
+        assert_eq!(second.alignment_transforms, first.alignment_transforms);
+        assert!(second.transforms_path.is_none());
+
+        request.paths.pop();
+        assert!(run_merge(&request).is_err());
+    }
+
+    #[test]
+    fn result_does_not_depend_on_input_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 24,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        let mut reversed = request.clone();
+        reversed.paths.reverse();
+        reversed.output_dir = Some(dir.path().join("reversed").to_string_lossy().to_string());
+
+        let forward = run_merge(&request).unwrap();
+        let backward = run_merge(&reversed).unwrap();
+
+        assert_eq!(forward.exposure_order, backward.exposure_order);
+        assert!(forward
+            .exposure_order
+            .windows(2)
+            .all(|pair| pair[0].ev <= pair[1].ev));
+        assert_eq!(
+            load_rgb16(&forward.output_png_path).unwrap(),
+            load_rgb16(&backward.output_png_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn allow_partial_skips_corrupt_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 24,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        std::fs::write(&request.paths[1], b"broken").unwrap();
+
+        let error = run_merge(&request).unwrap_err();
+        assert!(error.contains(&request.paths[1]));
+
+        request.allow_partial = true;
+        let result = run_merge(&request).unwrap();
+        assert_eq!(result.excluded_inputs.len(), 1);
+        assert_eq!(result.exposure_order.len(), options.frames as usize - 1);
+    }
+
+    #[test]
+    fn clipping_map_is_written_next_to_the_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 24,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        request.clipping_map = true;
+
+        let result = run_merge(&request).unwrap();
+
+        let clipping = result.clipping.unwrap();
+        assert!(clipping.path.ends_with("_clipping.png"));
+        let overlay = image::open(&clipping.path).unwrap();
+        assert_eq!((overlay.width(), overlay.height()), (32, 24));
+        assert!(clipping.unrecoverable_fraction + clipping.recovered_fraction <= 1.0);
+    }
+
+    // 送り先への書き出しが終わるまで、*_clipping.png を含めてほかのジョブに取られないよう予約したままにする
+    #[test]
+    fn outputs_stay_reserved_until_the_reservation_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 24,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        request.clipping_map = true;
+        request.output_name = Some("frame_0001".to_string());
+        let images = load_inputs(&request.paths).unwrap();
+        let mut merged = process(&images, &request).unwrap();
+        merged.clipping =
+            Some(clipping::clipping_map(&images, None, merged.image.dimensions()).unwrap());
+
+        let (result, reservation) = write_outputs(&merged, &request).unwrap();
+
+        let clipping = PathBuf::from(&result.clipping.unwrap().path);
+        assert!(clipping.ends_with("frame_0001_clipping.png"));
+        assert!(clipping.exists());
+        assert!(OutputReservation::reserve_files(&[clipping.clone()]).is_err());
+        drop(reservation);
+        assert!(OutputReservation::reserve_files(&[clipping]).is_ok());
+    }
+
+    #[test]
+    fn deliverables_are_written_from_one_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.deliverables = vec![
+            Deliverable {
+                format: deliverables::DeliverableFormat::Jpeg,
+                suffix: "_web".to_string(),
+                max_size: Some(32),
+                bit_depth: None,
+                quality: None,
+                print: None,
+            },
+            Deliverable {
+                format: deliverables::DeliverableFormat::Tiff,
+                suffix: "_print".to_string(),
+                max_size: None,
+                bit_depth: None,
+                quality: None,
+                print: Some(PrintSettings {
+                    dpi: Some(300),
+                    ..Default::default()
+                }),
+            },
+        ];
+
+        let result = run_merge(&request).unwrap();
+
+        let stem = Path::new(&result.output_png_path).file_stem().unwrap();
+        let sizes: Vec<(String, u32, u32)> = result
+            .deliverables
+            .iter()
+            .map(|output| {
+                assert_eq!(
+                    image::image_dimensions(&output.path).unwrap(),
+                    (output.width, output.height)
+                );
+                let name = Path::new(&output.path)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy();
+                (
+                    name.replace(&*stem.to_string_lossy(), ""),
+                    output.width,
+                    output.height,
+                )
+            })
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                ("_web.jpg".to_string(), 32, 24),
+                ("_print.tif".to_string(), 64, 48)
+            ]
+        );
+        assert_eq!(result.deliverables[1].print.unwrap().dpi, 300);
+        assert!(result.deliverables[0].print.is_none());
+    }
+
+    #[test]
+    fn output_template_creates_nested_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 24,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        request.output_template = Some("{outputRoot}/{yyyy}/{groupName}/".to_string());
+        request.group_name = Some("living room".to_string());
+
+        let result = run_merge(&request).unwrap();
+
+        let expected_dir = dir
+            .path()
+            .join("output")
+            .join(Local::now().format("%Y").to_string())
+            .join("living room");
+        assert_eq!(
+            Path::new(&result.output_png_path).parent().unwrap(),
+            expected_dir
+        );
+    }
+
+    #[test]
+    fn protected_input_folder_rejects_default_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 24,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_dir = None;
+        request.protected_dirs = vec![dir.path().join("input")];
+
+        assert!(run_merge(&request).is_err());
+        let written = std::fs::read_dir(dir.path().join("input")).unwrap().count();
+        assert_eq!(written, options.frames as usize);
+    }
+
+    #[test]
+    fn accepts_upper_case_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 24,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        for path in request.paths.iter_mut() {
+            let upper = path.replace(".png", ".PNG");
+            std::fs::rename(&*path, &upper).unwrap();
+            *path = upper;
+        }
+
+        assert!(run_merge(&request).is_ok());
+    }
+
+    #[test]
+    fn merge_with_roi_outputs_only_the_region() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 64,
+            height: 48,
+            motion: 2.0,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.output_exr = false;
+        request.roi = Some(Roi {
+            x: 8,
+            y: 4,
+            width: 20,
+            height: 10,
+        });
+
+        let result = run_merge(&request).unwrap();
+
+        assert_eq!((result.width, result.height), (20, 10));
+        assert!(result.output_png_path.ends_with("_roi.png"));
+    }
+
+    #[test]
+    fn merge_rejects_roi_outside_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 32,
+            height: 32,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.roi = Some(Roi {
+            x: 20,
+            y: 0,
+            width: 20,
+            height: 10,
+        });
+
+        assert!(run_merge(&request).is_err());
+    }
+
+    #[test]
+    fn perspective_with_full_frame_corners_keeps_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 40,
+            height: 30,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.perspective = Some(PerspectiveCorners {
+            top_left: NormalizedPoint { x: 0.0, y: 0.0 },
+            top_right: NormalizedPoint { x: 1.0, y: 0.0 },
+            bottom_right: NormalizedPoint { x: 1.0, y: 1.0 },
+            bottom_left: NormalizedPoint { x: 0.0, y: 1.0 },
+        });
+
+        let images = load_inputs(&request.paths).unwrap();
+        let plain = process(&images, &MergeRequest::default()).unwrap().image;
+        let merged = process(&images, &request).unwrap();
+
+        assert_eq!(merged.image.dimensions(), plain.dimensions());
+        let max_diff = merged
+            .image
+            .pixels()
+            .zip(plain.pixels())
+            .flat_map(|(a, b)| (0..3).map(move |c| (a[c] as i32 - b[c] as i32).abs()))
+            .max()
+            .unwrap();
+        assert!(max_diff <= 1);
+    }
+
+    #[test]
+    fn noise_stack_merges_more_frames_of_one_exposure() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 48,
+            height: 32,
+            ..Default::default()
+        };
+        let bracket = bracket_request(dir.path(), &options);
+        let base = load_rgb16(&bracket.paths[0]).unwrap();
+        // 重複とみなされないよう、1 画素ずつ変えた同じ露出のフレーム
+        let paths: Vec<String> = (0..8u32)
+            .map(|i| {
+                let mut frame = base.clone();
+                frame.put_pixel(i, 0, Rgb([i as u16 * 100; 3]));
+                let path = dir.path().join(format!("stack_{}.png", i));
+                save_png(&frame, &path).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+
+        let stacked = MergeRequest {
+            paths: paths.clone(),
+            merge_mode: MergeMode::NoiseStack,
+            ..bracket.clone()
+        };
+        let result = run_merge(&stacked).unwrap();
+        assert_eq!(result.algorithm, "noiseStack");
+        assert!(result.stages.iter().any(|stage| stage == "align"));
+
+        assert!(run_merge(&MergeRequest {
+            paths,
+            ..bracket.clone()
+        })
+        .is_err());
+        let mixed = MergeRequest {
+            merge_mode: MergeMode::NoiseStack,
+            ..bracket
+        };
+        assert!(run_merge(&mixed)
+            .unwrap_err()
+            .contains("同じ露出のフレームだけ"));
+    }
+
+    #[test]
+    fn long_exposure_writes_the_short_reference_beside_the_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let bracket = bracket_request(dir.path(), &TestBracketOptions::default());
+        let request = MergeRequest {
+            merge_mode: MergeMode::LongExposure,
+            output_short_reference: true,
+            ..bracket.clone()
+        };
+
+        let result = run_merge(&request).unwrap();
+
+        assert_eq!(result.algorithm, "longExposure");
+        let reference = result.output_short_reference_path.unwrap();
+        assert!(reference.ends_with("_short.png"));
+        let reference = load_rgb16(&reference).unwrap();
+        assert_eq!(reference.dimensions(), (result.width, result.height));
+        assert!(run_merge(&MergeRequest {
+            algorithm: Some("fusion".to_string()),
+            ..request
+        })
+        .is_err());
+        assert!(run_merge(&bracket)
+            .unwrap()
+            .output_short_reference_path
+            .is_none());
+    }
+
+    #[test]
+    fn repeated_merges_do_not_overwrite_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 48,
+            height: 32,
+            ..Default::default()
+        };
+        let request = bracket_request(dir.path(), &options);
+
+        let first = run_merge(&request).unwrap();
+        let second = run_merge(&request).unwrap();
+
+        assert_ne!(first.output_png_path, second.output_png_path);
+        assert!(Path::new(&first.output_png_path).exists());
+        assert!(Path::new(&second.output_png_path).exists());
+    }
+
+    #[test]
+    fn load_inputs_rejects_mismatched_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = synthetic::generate_bracket(
+            &dir.path().join("small"),
+            &TestBracketOptions {
+                width: 32,
+                height: 32,
+                frames: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let large = synthetic::generate_bracket(
+            &dir.path().join("large"),
+            &TestBracketOptions {
+                width: 48,
+                height: 32,
+                frames: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let paths = vec![small.paths[0].clone(), large.paths[0].clone()];
+        assert!(load_inputs(&paths).is_err());
+    }
+
+    #[test]
+    fn deterministic_merges_are_byte_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            width: 160,
+            height: 120,
+            motion: 3.0,
+            ..Default::default()
+        };
+        let mut request = bracket_request(dir.path(), &options);
+        request.deterministic = true;
+        request.auto_straighten = true;
+
+        let mut outputs = Vec::new();
+        for run in 0..2 {
+            request.output_dir = Some(
+                dir.path()
+                    .join(format!("run{}", run))
+                    .to_string_lossy()
+                    .to_string(),
+            );
+            let result = run_merge(&request).unwrap();
+            outputs.push((
+                std::fs::read(&result.output_png_path).unwrap(),
+                std::fs::read(result.output_exr_path.unwrap()).unwrap(),
+            ));
+        }
+
+        assert_eq!(outputs[0].0, outputs[1].0);
+        assert_eq!(outputs[0].1, outputs[1].1);
+    }
+}
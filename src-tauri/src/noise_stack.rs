@@ -0,0 +1,275 @@
+use serde_json::Value;
+
+use crate::algorithms::{AlgorithmParams, MergeAlgorithm, ParameterKind, ParameterSchema};
+use crate::align::{self, AlignParams};
+use crate::capabilities::MAX_MERGE_FRAMES;
+use crate::color::{linear_to_srgb, srgb_to_linear, u16_to_unit, unit_to_u16};
+use crate::merge::{MergeQuality, MergeRequest, Rgb16Image};
+
+// 夜景では同じ露出で 8 枚前後を撮ることが多いため、ブラケットより多く受け付ける
+pub const MAX_STACK_FRAMES: usize = 16;
+// hybrid は露出ごとに数枚ずつ撮るため、段数の上限（MAX_MERGE_FRAMES）の 4 倍まで受け付ける
+pub const MAX_HYBRID_FRAMES: usize = MAX_MERGE_FRAMES * 4;
+const DEFAULT_REJECT_SIGMA: f32 = 2.5;
+// これを超えて露出がばらつく入力はブラケットとみなす
+const MAX_EV_SPREAD: f64 = 0.5;
+// 中央絶対偏差から標準偏差への換算
+const MAD_TO_SIGMA: f32 = 1.4826;
+// 偏差がほぼ 0 の画素でも、量子化の誤差程度の違いは外れ値にしない
+const MIN_THRESHOLD: f32 = 1e-4;
+
+// mergeMode が noiseStack のときに使う。REGISTRY には入れず、algorithm では選べない
+pub struct NoiseStack;
+
+impl MergeAlgorithm for NoiseStack {
+    fn name(&self) -> &'static str {
+        "noiseStack"
+    }
+
+    fn label(&self) -> &'static str {
+        "ノイズ低減スタック"
+    }
+
+    fn description(&self) -> &'static str {
+        "同じ露出のフレームを線形空間で平均し、外れ値を除いてノイズを減らします"
+    }
+
+    fn parameters(&self) -> Vec<ParameterSchema> {
+        vec![ParameterSchema {
+            name: "rejectSigma",
+            label: "外れ値とみなす偏差（σ）",
+            kind: ParameterKind::Number {
+                min: 1.0,
+                max: 5.0,
+                step: 0.5,
+            },
+            default: Value::from(DEFAULT_REJECT_SIGMA),
+        }]
+    }
+
+    fn merge(&self, frames: &[Rgb16Image], params: &AlgorithmParams) -> Result<Rgb16Image, String> {
+        let reject_sigma = params
+            .get("rejectSigma")
+            .and_then(Value::as_f64)
+            .map_or(DEFAULT_REJECT_SIGMA, |sigma| sigma as f32);
+        Ok(stack(frames, reject_sigma))
+    }
+}
+
+// 露出の揃ったフレームだけを重ねる。evs は input_check で測った各入力の相対 EV
+pub fn validate(request: &MergeRequest, evs: &[f64]) -> Result<(), String> {
+    if request.algorithm.is_some() {
+        return Err("noiseStack では algorithm を指定できません".to_string());
+    }
+    if request.frame_selection.is_some() {
+        return Err("noiseStack では frameSelection を指定できません".to_string());
+    }
+    let low = evs.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = evs.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if high - low > MAX_EV_SPREAD {
+        return Err(format!(
+            "noiseStack は同じ露出のフレームだけを重ねます（露出の差 {:.1}EV）",
+            high - low
+        ));
+    }
+    Ok(())
+}
+
+// 露出の近い順に並べ、直前のフレームと MAX_EV_SPREAD を超えて離れたところで段を分ける。
+// 戻り値は段ごとの入力の位置で、暗い段から並ぶ
+pub fn exposure_levels(evs: &[f64]) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..evs.len()).collect();
+    order.sort_by(|&a, &b| evs[a].total_cmp(&evs[b]));
+    let mut levels: Vec<Vec<usize>> = Vec::new();
+    for index in order {
+        match levels.last_mut() {
+            Some(level) if evs[index] - evs[level[0]] <= MAX_EV_SPREAD => level.push(index),
+            _ => levels.push(vec![index]),
+        }
+    }
+    levels
+}
+
+// hybrid で露出の段ごとに位置を揃えてスタックし、段ごとに 1 枚にする。
+// 段の代表には最初のパスを使い、出力名や exposureOrder もそのパスで表す
+pub fn stack_levels(
+    paths: Vec<String>,
+    images: Vec<Rgb16Image>,
+    evs: &[f64],
+    quality: MergeQuality,
+) -> Result<(Vec<String>, Vec<Rgb16Image>), String> {
+    let levels = exposure_levels(evs);
+    if !(2..=MAX_MERGE_FRAMES).contains(&levels.len()) {
+        return Err(format!(
+            "hybrid では露出の段を 2〜{} 段にしてください（{} 段）",
+            MAX_MERGE_FRAMES,
+            levels.len()
+        ));
+    }
+    let mut stacked_paths = Vec::new();
+    let mut stacked = Vec::new();
+    for level in levels {
+        let frames: Vec<Rgb16Image> = level.iter().map(|&i| images[i].clone()).collect();
+        let image = match frames.len() {
+            1 => frames.into_iter().next().expect("段には 1 枚以上ある"),
+            _ => {
+                let (aligned, _) =
+                    align::align_frames(&frames, 0, &AlignParams::default(), quality);
+                stack(&aligned, DEFAULT_REJECT_SIGMA)
+            }
+        };
+        stacked_paths.push(paths[level[0]].clone());
+        stacked.push(image);
+    }
+    Ok((stacked_paths, stacked))
+}
+
+// 画素ごとに中央値から rejectSigma 以上離れた値（飛行機・人工衛星・ホットピクセルなど）を除いて平均する
+pub fn stack(frames: &[Rgb16Image], reject_sigma: f32) -> Rgb16Image {
+    let (width, height) = frames[0].dimensions();
+    let to_linear: Vec<f32> = (0..=u16::MAX)
+        .map(|value| srgb_to_linear(u16_to_unit(value)))
+        .collect();
+    let sources: Vec<&[u16]> = frames
+        .iter()
+        .map(|frame| frame.as_raw().as_slice())
+        .collect();
+    let mut values = vec![0.0f32; frames.len()];
+    let mut deviations = vec![0.0f32; frames.len()];
+
+    let data = (0..sources[0].len())
+        .map(|i| {
+            for (value, source) in values.iter_mut().zip(&sources) {
+                *value = to_linear[source[i] as usize];
+            }
+            unit_to_u16(linear_to_srgb(robust_mean(
+                &mut values,
+                &mut deviations,
+                reject_sigma,
+            )))
+        })
+        .collect();
+    Rgb16Image::from_raw(width, height, data).expect("バッファの長さは画素数と一致する")
+}
+
+// 2 枚では中央値が決まらないため、3 枚以上のときだけ外れ値を除く
+fn robust_mean(values: &mut [f32], deviations: &mut [f32], reject_sigma: f32) -> f32 {
+    if values.len() < 3 {
+        return values.iter().sum::<f32>() / values.len() as f32;
+    }
+    let center = median(values);
+    for (deviation, value) in deviations.iter_mut().zip(values.iter()) {
+        *deviation = (value - center).abs();
+    }
+    let threshold = (reject_sigma * MAD_TO_SIGMA * median(deviations)).max(MIN_THRESHOLD);
+    let (sum, count) = values
+        .iter()
+        .filter(|value| (*value - center).abs() <= threshold)
+        .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    sum / count as f32
+}
+
+// 並べ替えるため、values の順序は変わる
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_select::FrameSelection;
+    use image::Rgb;
+
+    fn truth(x: u32, y: u32) -> f32 {
+        0.02 + 0.3 * (x + y) as f32 / 60.0
+    }
+
+    // 決まった乱数でノイズを乗せた同じ露出のフレーム
+    fn noisy_frames(count: usize) -> Vec<Rgb16Image> {
+        let mut seed = 12345u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        (0..count)
+            .map(|_| {
+                Rgb16Image::from_fn(30, 30, |x, y| {
+                    Rgb([(); 3].map(|_| {
+                        let linear = truth(x, y) * (1.0 + 0.3 * noise());
+                        unit_to_u16(linear_to_srgb(linear))
+                    }))
+                })
+            })
+            .collect()
+    }
+
+    fn error(image: &Rgb16Image) -> f32 {
+        let total: f32 = image
+            .enumerate_pixels()
+            .map(|(x, y, pixel)| (srgb_to_linear(u16_to_unit(pixel.0[0])) - truth(x, y)).abs())
+            .sum();
+        total / (image.width() * image.height()) as f32
+    }
+
+    #[test]
+    fn reduces_noise_and_rejects_outliers() {
+        let mut frames = noisy_frames(8);
+        // 1 枚だけに写り込んだ明るい線（飛行機の光跡など）
+        for x in 0..30 {
+            frames[3].put_pixel(x, 10, Rgb([u16::MAX; 3]));
+        }
+
+        let stacked = stack(&frames, 2.5);
+
+        assert!(error(&stacked) < error(&frames[0]) / 2.0);
+        let trail = srgb_to_linear(u16_to_unit(stacked.get_pixel(15, 10).0[0]));
+        assert!((trail - truth(15, 10)).abs() < 0.05, "{}", trail);
+    }
+
+    #[test]
+    fn splits_repeated_exposures_into_levels() {
+        let levels = exposure_levels(&[2.0, 0.0, 2.1, 0.1, 4.0, 4.2]);
+        assert_eq!(levels, vec![vec![1, 3], vec![0, 2], vec![4, 5]]);
+    }
+
+    #[test]
+    fn stacks_each_level_before_merging() {
+        let mut frames = noisy_frames(4);
+        let bright: Vec<Rgb16Image> = noisy_frames(3)
+            .into_iter()
+            .map(|frame| {
+                Rgb16Image::from_fn(30, 30, |x, y| {
+                    Rgb(frame.get_pixel(x, y).0.map(|v| v.saturating_mul(2)))
+                })
+            })
+            .collect();
+        frames.extend(bright);
+        let paths: Vec<String> = (0..frames.len()).map(|i| format!("{}.png", i)).collect();
+        let evs = [0.0, 0.1, 0.0, 0.1, 1.0, 1.1, 1.0];
+
+        let (paths, stacked) = stack_levels(paths, frames, &evs, MergeQuality::Fast).unwrap();
+
+        assert_eq!(paths, ["0.png", "4.png"]);
+        assert_eq!(stacked.len(), 2);
+        assert!(stack_levels(Vec::new(), Vec::new(), &[], MergeQuality::Fast).is_err());
+    }
+
+    #[test]
+    fn accepts_only_matching_exposures() {
+        let request = MergeRequest::default();
+        assert!(validate(&request, &[0.0, 0.1, 0.2]).is_ok());
+        assert!(validate(&request, &[0.0, 1.0, 2.0]).is_err());
+
+        let with_selection = MergeRequest {
+            frame_selection: Some(FrameSelection::default()),
+            ..MergeRequest::default()
+        };
+        assert!(validate(&with_selection, &[0.0, 0.0]).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{linear_to_srgb, srgb_to_linear, u16_to_unit, unit_to_u16};
+use crate::merge::Rgb16Image;
+
+const D65: [f64; 2] = [0.3127, 0.3290];
+// ICC の接続空間（D50）へ移す Bradford の順応行列
+const BRADFORD_D65_TO_D50: [[f64; 3]; 3] = [
+    [1.047_811_2, 0.022_886_6, -0.050_127_0],
+    [0.029_542_4, 0.990_484_4, -0.017_049_1],
+    [-0.009_234_5, 0.015_043_6, 0.752_131_6],
+];
+const D50_XYZ: [f64; 3] = [0.9642, 1.0, 0.8249];
+// sRGB の階調曲線を ICC v2 の curv で表す点数
+const CURVE_POINTS: usize = 1024;
+const APP2: [u8; 2] = [0xFF, 0xE2];
+const ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+
+// PNG・JPEG の出力の色空間。sRGB 以外は色域を変換し、ICC プロファイルを埋め込む
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputColorSpace {
+    #[default]
+    Srgb,
+    // 階調は sRGB と同じ
+    DisplayP3,
+    // 階調はガンマ 2.4（BT.1886）
+    Rec2020,
+}
+
+impl OutputColorSpace {
+    fn primaries(self) -> [[f64; 2]; 3] {
+        match self {
+            OutputColorSpace::Srgb => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]],
+            OutputColorSpace::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+            OutputColorSpace::Rec2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputColorSpace::Srgb => "sRGB",
+            OutputColorSpace::DisplayP3 => "Display P3",
+            OutputColorSpace::Rec2020 => "Rec. 2020",
+        }
+    }
+
+    fn encode(self, linear: f32) -> f32 {
+        match self {
+            OutputColorSpace::Rec2020 => linear.max(0.0).powf(1.0 / 2.4),
+            _ => linear_to_srgb(linear),
+        }
+    }
+
+    fn decode(self, value: f32) -> f32 {
+        match self {
+            OutputColorSpace::Rec2020 => value.powf(2.4),
+            _ => srgb_to_linear(value),
+        }
+    }
+}
+
+// 原色と白色点から線形 RGB → XYZ の行列を求める
+fn rgb_to_xyz(primaries: [[f64; 2]; 3], white: [f64; 2]) -> [[f64; 3]; 3] {
+    let xyz = |[x, y]: [f64; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+    let columns = primaries.map(xyz);
+    let matrix = [0, 1, 2].map(|row| columns.map(|column| column[row]));
+    let white = xyz(white);
+    let scale = multiply_vector(&invert(&matrix), white);
+    matrix.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]])
+}
+
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    a.map(|row| [0, 1, 2].map(|column| (0..3).map(|k| row[k] * b[k][column]).sum()))
+}
+
+fn multiply_vector(matrix: &[[f64; 3]; 3], vector: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    let determinant =
+        m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+    adjugate.map(|row| row.map(|value| value / determinant))
+}
+
+fn transform(image: &Rgb16Image, from: OutputColorSpace, to: OutputColorSpace) -> Rgb16Image {
+    let source = rgb_to_xyz(from.primaries(), D65);
+    let target = rgb_to_xyz(to.primaries(), D65);
+    let matrix = multiply(&invert(&target), &source).map(|row| row.map(|value| value as f32));
+    let linear: Vec<f32> = (0..=u16::MAX)
+        .map(|value| from.decode(u16_to_unit(value)))
+        .collect();
+    let mut converted = image.clone();
+    for pixel in converted.pixels_mut() {
+        let rgb = pixel.0.map(|value| linear[value as usize]);
+        pixel.0 = matrix.map(|row| {
+            let value = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+            unit_to_u16(to.encode(value.clamp(0.0, 1.0)))
+        });
+    }
+    converted
+}
+
+// 合成結果（sRGB）を指定の色空間の値にする。sRGB なら変換しないので None
+pub fn convert(image: &Rgb16Image, color_space: OutputColorSpace) -> Option<Rgb16Image> {
+    (color_space != OutputColorSpace::Srgb)
+        .then(|| transform(image, OutputColorSpace::Srgb, color_space))
+}
+
+// 広色域で書き出した画像を、作業用の sRGB の値へ戻す
+pub fn to_srgb(image: &Rgb16Image, color_space: OutputColorSpace) -> Rgb16Image {
+    transform(image, color_space, OutputColorSpace::Srgb)
+}
+
+// 埋め込まれていたプロファイルが、このアプリが書き出した広色域のものならその色空間
+pub fn written_color_space(profile: &[u8]) -> Option<OutputColorSpace> {
+    [OutputColorSpace::DisplayP3, OutputColorSpace::Rec2020]
+        .into_iter()
+        .find(|&color_space| icc_profile(color_space).as_deref() == Some(profile))
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in xyz {
+        tag.extend(s15_fixed16(value));
+    }
+    tag
+}
+
+fn curve_tag(color_space: OutputColorSpace) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend((CURVE_POINTS as u32).to_be_bytes());
+    for index in 0..CURVE_POINTS {
+        let value = color_space.decode(index as f32 / (CURVE_POINTS - 1) as f32);
+        tag.extend(unit_to_u16(value).to_be_bytes());
+    }
+    tag
+}
+
+// ICC v2 の textDescriptionType。Unicode・ScriptCode の説明は空にする
+fn description_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend((text.len() as u32 + 1).to_be_bytes());
+    tag.extend(text.as_bytes());
+    tag.push(0);
+    tag.extend([0; 4 + 4 + 2 + 1 + 67]);
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+// 表示用の行列・階調曲線型の ICC v2 プロファイル。原色は D50 へ順応した値で書く。sRGB では埋め込まないので None
+pub fn icc_profile(color_space: OutputColorSpace) -> Option<Vec<u8>> {
+    if color_space == OutputColorSpace::Srgb {
+        return None;
+    }
+    let matrix = multiply(
+        &BRADFORD_D65_TO_D50,
+        &rgb_to_xyz(color_space.primaries(), D65),
+    );
+    let colorant = |column: usize| xyz_tag([0, 1, 2].map(|row| matrix[row][column]));
+    let curve = curve_tag(color_space);
+    let tags: [(&[u8; 4], Vec<u8>); 9] = [
+        (b"desc", description_tag(color_space.name())),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(D50_XYZ)),
+        (b"rXYZ", colorant(0)),
+        (b"gXYZ", colorant(1)),
+        (b"bXYZ", colorant(2)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let mut data = Vec::new();
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let data_start = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend(*signature);
+        table.extend(((data_start + data.len()) as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+        data.extend(tag);
+        // 各タグは4バイト境界から始める
+        data.resize(data.len().div_ceil(4) * 4, 0);
+    }
+
+    let size = data_start + data.len();
+    let mut header = Vec::with_capacity(128);
+    header.extend((size as u32).to_be_bytes());
+    header.extend([0; 4]);
+    header.extend([0x02, 0x10, 0, 0]);
+    header.extend(b"mntrRGB XYZ ");
+    // 作成日時。同じ設定から同じバイト列になるよう固定する
+    for value in [2024u16, 1, 1, 0, 0, 0] {
+        header.extend(value.to_be_bytes());
+    }
+    header.extend(b"acsp");
+    header.extend([0; 24]);
+    header.extend([0; 4]);
+    for value in D50_XYZ {
+        header.extend(s15_fixed16(value));
+    }
+    header.resize(128, 0);
+
+    let mut profile = header;
+    profile.extend(table);
+    profile.extend(data);
+    Some(profile)
+}
+
+// JPEG の SOI（と JFIF の APP0）の直後に ICC プロファイルの APP2 を差し込む。1つのセグメントに収まる大きさのみ
+pub fn embed_jpeg_icc(jpeg: &[u8], profile: &[u8]) -> Result<Vec<u8>, String> {
+    if jpeg.get(..2) != Some(&[0xFF, 0xD8]) {
+        return Err("JPEG のデータではありません".to_string());
+    }
+    let mut insert_at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) {
+        let length = jpeg
+            .get(4..6)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+            .ok_or("JPEG のデータが壊れています")?;
+        insert_at = 4 + length;
+    }
+    let length = 2 + ICC_MARKER.len() + 2 + profile.len();
+    let length = u16::try_from(length).map_err(|_| "ICC プロファイルが大きすぎます")?;
+    let mut output = Vec::with_capacity(jpeg.len() + length as usize + 2);
+    output.extend(&jpeg[..insert_at]);
+    output.extend(APP2);
+    output.extend(length.to_be_bytes());
+    output.extend(ICC_MARKER);
+    // 分割しないので 1/1
+    output.extend([1, 1]);
+    output.extend(profile);
+    output.extend(&jpeg[insert_at..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn converts_into_wider_gamuts_keeping_white() {
+        let image = Rgb16Image::from_fn(2, 1, |x, _| match x {
+            0 => Rgb([u16::MAX; 3]),
+            _ => Rgb([u16::MAX, 0, 0]),
+        });
+        assert!(convert(&image, OutputColorSpace::Srgb).is_none());
+
+        for color_space in [OutputColorSpace::DisplayP3, OutputColorSpace::Rec2020] {
+            let converted = convert(&image, color_space).unwrap();
+            assert!(converted
+                .get_pixel(0, 0)
+                .0
+                .iter()
+                .all(|value| *value >= u16::MAX - 1));
+            // sRGB の純色の赤は広い色域では内側に入る
+            let [r, g, b] = converted.get_pixel(1, 0).0;
+            assert!(r < u16::MAX && g > 0 && b > 0);
+        }
+    }
+
+    #[test]
+    fn builds_icc_profiles_and_embeds_them_in_jpeg() {
+        assert!(icc_profile(OutputColorSpace::Srgb).is_none());
+        let profile = icc_profile(OutputColorSpace::DisplayP3).unwrap();
+        assert_eq!(
+            u32::from_be_bytes(profile[..4].try_into().unwrap()) as usize,
+            profile.len()
+        );
+        assert_eq!(&profile[36..40], b"acsp");
+        assert!(profile.windows(10).any(|w| w == b"Display P3"));
+
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0, 4, 0, 0, 0xFF, 0xD9];
+        let embedded = embed_jpeg_icc(&jpeg, &profile).unwrap();
+        assert_eq!(&embedded[..8], &jpeg[..8]);
+        assert_eq!(&embedded[8..10], &APP2);
+        assert_eq!(&embedded[12..24], ICC_MARKER);
+        assert!(embedded.ends_with(&[0xFF, 0xD9]));
+        assert!(embed_jpeg_icc(b"not a jpeg", &profile).is_err());
+    }
+}
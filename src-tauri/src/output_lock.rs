@@ -0,0 +1,185 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use crate::paths;
+
+// 空いている名前を探す上限。同じ秒に同じフォルダへこれだけ書き出すことはない
+const MAX_SUFFIX: u32 = 1000;
+
+// 書き出し中のジョブが書くファイル（フォルダ + ファイル名）。主出力・EXR・付随ファイルをすべて予約する
+static RESERVED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+// 出力名の予約。書き出しが終わって drop したら、ほかのジョブがそのファイルを使えるようになる
+#[derive(Debug)]
+pub struct OutputReservation {
+    keys: Vec<PathBuf>,
+    name: String,
+}
+
+impl OutputReservation {
+    // files は名前から作る出力ファイル名の一覧。日時から作った名前は、書き出し中のジョブや既存のファイルと
+    // 1 つでも重なれば _2, _3 … を付けて予約する。fixed（タイムラプスの連番など）は名前を変えられないため、
+    // 書き出し中のジョブと重なればエラーにし、既存のファイルは上書きする
+    pub fn reserve(
+        dir: &Path,
+        base_name: &str,
+        fixed: bool,
+        files: impl Fn(&str) -> Vec<String>,
+    ) -> Result<Self, String> {
+        let dir = paths::canonical(dir);
+        let mut reserved = RESERVED.lock().unwrap_or_else(PoisonError::into_inner);
+        if fixed {
+            let keys: Vec<PathBuf> = files(base_name).iter().map(|file| dir.join(file)).collect();
+            insert(&mut reserved, &keys)?;
+            return Ok(Self {
+                keys,
+                name: base_name.to_string(),
+            });
+        }
+        for suffix in 1..=MAX_SUFFIX {
+            let name = match suffix {
+                1 => base_name.to_string(),
+                _ => format!("{}_{}", base_name, suffix),
+            };
+            let keys: Vec<PathBuf> = files(&name).iter().map(|file| dir.join(file)).collect();
+            if keys
+                .iter()
+                .all(|key| !reserved.contains(key) && !paths::extended(key).exists())
+            {
+                reserved.extend(keys.iter().cloned());
+                return Ok(Self { keys, name });
+            }
+        }
+        Err(format!("空いている出力名が見つかりません: {}", base_name))
+    }
+
+    // 名前を変えられない出力（デフリッカーで書き直すフレームなど）をまとめて予約する
+    pub fn reserve_files(files: &[PathBuf]) -> Result<Self, String> {
+        let keys: Vec<PathBuf> = files
+            .iter()
+            .map(|file| match (file.parent(), file.file_name()) {
+                (Some(dir), Some(name)) => paths::canonical(dir).join(name),
+                _ => file.clone(),
+            })
+            .collect();
+        let mut reserved = RESERVED.lock().unwrap_or_else(PoisonError::into_inner);
+        insert(&mut reserved, &keys)?;
+        Ok(Self {
+            keys,
+            name: String::new(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// 1 つでも書き出し中なら何も予約しない
+fn insert(reserved: &mut BTreeSet<PathBuf>, keys: &[PathBuf]) -> Result<(), String> {
+    if let Some(key) = keys.iter().find(|key| reserved.contains(*key)) {
+        return Err(format!(
+            "別のジョブが同じ出力先に書き出し中です: {}",
+            key.to_string_lossy()
+        ));
+    }
+    reserved.extend(keys.iter().cloned());
+    Ok(())
+}
+
+impl Drop for OutputReservation {
+    fn drop(&mut self) {
+        let mut reserved = RESERVED.lock().unwrap_or_else(PoisonError::into_inner);
+        for key in &self.keys {
+            reserved.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+
+    fn png(name: &str) -> Vec<String> {
+        vec![format!("{}.png", name)]
+    }
+
+    #[test]
+    fn concurrent_jobs_get_distinct_names() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let first = OutputReservation::reserve(dir.path(), "hdr_merge_1", false, png).unwrap();
+        let second = OutputReservation::reserve(dir.path(), "hdr_merge_1", false, png).unwrap();
+        assert_eq!(first.name(), "hdr_merge_1");
+        assert_eq!(second.name(), "hdr_merge_1_2");
+
+        drop(first);
+        let third = OutputReservation::reserve(dir.path(), "hdr_merge_1", false, png).unwrap();
+        assert_eq!(third.name(), "hdr_merge_1");
+
+        // 主出力がなくても、付随ファイルのどれかが既にあれば別の名前にする
+        std::fs::write(dir.path().join("hdr_merge_2.exr"), b"").unwrap();
+        let existing = OutputReservation::reserve(dir.path(), "hdr_merge_2", false, |name| {
+            vec![format!("{}.png", name), format!("{}.exr", name)]
+        })
+        .unwrap();
+        assert_eq!(existing.name(), "hdr_merge_2_2");
+    }
+
+    #[test]
+    fn jobs_started_at_once_never_share_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = 8;
+        let barrier = Arc::new(Barrier::new(jobs));
+        let reservations: Vec<OutputReservation> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..jobs)
+                .map(|_| {
+                    let barrier = Arc::clone(&barrier);
+                    let dir = dir.path();
+                    scope.spawn(move || {
+                        barrier.wait();
+                        OutputReservation::reserve(dir, "hdr_merge_1", false, |name| {
+                            vec![format!("{}.png", name), format!("{}_short.png", name)]
+                        })
+                        .unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let names: BTreeSet<&str> = reservations.iter().map(|r| r.name()).collect();
+        assert_eq!(names.len(), jobs);
+
+        // 別の名前でも、書くファイルが重なれば同じ出力とみなす
+        let short =
+            OutputReservation::reserve(dir.path(), "hdr_merge_1_short", false, png).unwrap();
+        assert_eq!(short.name(), "hdr_merge_1_short_2");
+        assert!(OutputReservation::reserve(dir.path(), "hdr_merge_1_short", true, png).is_err());
+        assert!(
+            OutputReservation::reserve_files(&[dir.path().join("hdr_merge_1_3_short.png")])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn fixed_names_are_not_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("frame_00001.png"), b"").unwrap();
+
+        let frame = OutputReservation::reserve(dir.path(), "frame_00001", true, png).unwrap();
+        assert_eq!(frame.name(), "frame_00001");
+        assert!(OutputReservation::reserve(dir.path(), "frame_00001", true, png).is_err());
+        assert!(OutputReservation::reserve_files(&[dir.path().join("frame_00001.png")]).is_err());
+        // 別のフォルダの同じ名前とは重ならない
+        let other = tempfile::tempdir().unwrap();
+        assert!(OutputReservation::reserve(other.path(), "frame_00001", true, png).is_ok());
+
+        drop(frame);
+        let rewrite =
+            OutputReservation::reserve_files(&[dir.path().join("frame_00001.png")]).unwrap();
+        assert!(OutputReservation::reserve(dir.path(), "frame_00001", true, png).is_err());
+        drop(rewrite);
+    }
+}
@@ -0,0 +1,257 @@
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::paths;
+
+// 出力先テンプレート（例: `{outputRoot}/{yyyy}/{MM}/{dd}/{groupName}/`）の展開に使う値
+pub struct TemplateContext<'a> {
+    pub output_root: &'a Path,
+    pub now: DateTime<Local>,
+    pub group_name: &'a str,
+    pub algorithm: &'a str,
+}
+
+const DEFAULT_SUBFOLDER: &str = "merged";
+
+// outputDir が未指定のときの出力先（{outputRoot}）の決め方
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DefaultOutputDir {
+    // 先頭の入力と同じフォルダ
+    #[default]
+    InputFolder,
+    // 先頭の入力のフォルダの中に作るフォルダ（未指定なら merged）
+    #[serde(rename_all = "camelCase")]
+    Subfolder { name: Option<String> },
+    // root の下に入力のフォルダ構成を写す。sourceRoot の中の入力はその中の相対パスだけを写す
+    #[serde(rename_all = "camelCase")]
+    MirroredTree {
+        root: String,
+        source_root: Option<String>,
+    },
+}
+
+pub const PLACEHOLDERS: &[&str] = &[
+    "outputRoot",
+    "yyyy",
+    "MM",
+    "dd",
+    "HH",
+    "mm",
+    "groupName",
+    "algorithm",
+];
+
+pub fn resolve_template(template: &str, context: &TemplateContext) -> Result<PathBuf, String> {
+    let mut resolved = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if rest[..start].contains('}') {
+            return Err("出力先テンプレートに対応する { がない } があります".to_string());
+        }
+        resolved.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or("出力先テンプレートの { が閉じられていません")?;
+        let key = &rest[start + 1..start + end];
+        resolved.push_str(&placeholder_value(key, context)?);
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err("出力先テンプレートに対応する { がない } があります".to_string());
+    }
+    resolved.push_str(rest);
+
+    let path = PathBuf::from(resolved.trim_end_matches(['/', '\\']));
+    if path.as_os_str().is_empty() {
+        return Err("出力先テンプレートが空です".to_string());
+    }
+    Ok(path)
+}
+
+fn placeholder_value(key: &str, context: &TemplateContext) -> Result<String, String> {
+    let value = match key {
+        "outputRoot" => return Ok(context.output_root.to_string_lossy().to_string()),
+        "yyyy" => context.now.format("%Y").to_string(),
+        "MM" => context.now.format("%m").to_string(),
+        "dd" => context.now.format("%d").to_string(),
+        "HH" => context.now.format("%H").to_string(),
+        "mm" => context.now.format("%M").to_string(),
+        "groupName" => context.group_name.to_string(),
+        "algorithm" => context.algorithm.to_string(),
+        _ => {
+            return Err(format!(
+                "出力先テンプレートに不明な項目があります: {{{}}}",
+                key
+            ))
+        }
+    };
+    Ok(sanitize_component(&value))
+}
+
+// 差し込む値がフォルダ階層を変えないよう、区切り文字や使えない文字を置き換える
+pub fn sanitize_component(value: &str) -> String {
+    let sanitized: String = value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match sanitized.trim_matches('.') {
+        "" => "_".to_string(),
+        _ => sanitized,
+    }
+}
+
+// 監視フォルダ（撮影機材のテザー出力先など）を書き込みから守る。
+// 出力先がまだ存在しない場合は、存在する親フォルダで比較する
+pub fn ensure_outside_protected(dir: &Path, protected: &[PathBuf]) -> Result<(), String> {
+    match protected_root(dir, protected) {
+        Some(root) => Err(format!(
+            "監視フォルダ {} には書き込めません。出力先に別のフォルダを指定してください",
+            root.to_string_lossy()
+        )),
+        None => Ok(()),
+    }
+}
+
+// path が含まれる保護フォルダを返す
+pub fn protected_root<'a>(path: &Path, protected: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    let target = paths::canonical(path);
+    protected
+        .iter()
+        .find(|root| target.starts_with(paths::canonical(root)))
+}
+
+pub fn default_output_root(first_input: &Path, rule: &DefaultOutputDir) -> Result<PathBuf, String> {
+    let input_dir = first_input.parent().ok_or("出力先の決定に失敗しました")?;
+    Ok(match rule {
+        DefaultOutputDir::InputFolder => input_dir.to_path_buf(),
+        DefaultOutputDir::Subfolder { name } => input_dir.join(sanitize_component(
+            name.as_deref().unwrap_or(DEFAULT_SUBFOLDER),
+        )),
+        DefaultOutputDir::MirroredTree { root, source_root } => {
+            let relative = source_root
+                .as_ref()
+                .and_then(|source| input_dir.strip_prefix(source).ok())
+                .unwrap_or(input_dir);
+            // ドライブ名やルートは区切り文字を含まない名前にし、.. は階層を上がらないよう捨てる
+            let mut mirrored = PathBuf::from(root);
+            for component in relative.components() {
+                match component {
+                    Component::Normal(name) => mirrored.push(name),
+                    Component::Prefix(prefix) => {
+                        mirrored.push(sanitize_component(&prefix.as_os_str().to_string_lossy()))
+                    }
+                    Component::RootDir | Component::CurDir | Component::ParentDir => {}
+                }
+            }
+            mirrored
+        }
+    })
+}
+
+// グループ名の指定がなければ、先頭の入力ファイル名（拡張子なし）を使う
+pub fn default_group_name(paths: &[String]) -> String {
+    paths
+        .first()
+        .and_then(|path| Path::new(path).file_stem())
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "group".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context<'a>(root: &'a Path, group_name: &'a str) -> TemplateContext<'a> {
+        TemplateContext {
+            output_root: root,
+            now: Local.with_ymd_and_hms(2024, 3, 9, 14, 5, 0).unwrap(),
+            group_name,
+            algorithm: "fusion",
+        }
+    }
+
+    #[test]
+    fn expands_date_and_group_placeholders() {
+        let root = Path::new("/shots");
+        let path = resolve_template(
+            "{outputRoot}/{yyyy}/{MM}/{dd}/{groupName}_{algorithm}/",
+            &context(root, "IMG_0001"),
+        )
+        .unwrap();
+
+        assert_eq!(path, root.join("2024/03/09/IMG_0001_fusion"));
+    }
+
+    #[test]
+    fn group_name_cannot_escape_the_template() {
+        let path = resolve_template(
+            "{outputRoot}/{groupName}",
+            &context(Path::new("/shots"), "../etc"),
+        )
+        .unwrap();
+
+        assert_eq!(path, Path::new("/shots/.._etc"));
+    }
+
+    #[test]
+    fn protected_folder_blocks_nested_outputs_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let watched = dir.path().join("tether");
+        std::fs::create_dir_all(&watched).unwrap();
+        let protected = vec![watched.clone()];
+
+        assert!(ensure_outside_protected(&watched, &protected).is_err());
+        assert!(ensure_outside_protected(&watched.join("new/sub"), &protected).is_err());
+        assert!(ensure_outside_protected(&dir.path().join("tether/../out"), &protected).is_ok());
+        assert!(ensure_outside_protected(&dir.path().join("tether_out"), &protected).is_ok());
+    }
+
+    #[test]
+    fn default_output_keeps_captures_separate() {
+        let input = Path::new("/shots/2024/trip/IMG_0001.jpg");
+        let root = |rule| default_output_root(input, &rule).unwrap();
+
+        assert_eq!(
+            root(DefaultOutputDir::InputFolder),
+            Path::new("/shots/2024/trip")
+        );
+        assert_eq!(
+            root(DefaultOutputDir::Subfolder { name: None }),
+            Path::new("/shots/2024/trip/merged")
+        );
+        assert_eq!(
+            root(DefaultOutputDir::MirroredTree {
+                root: "/hdr".to_string(),
+                source_root: Some("/shots".to_string()),
+            }),
+            Path::new("/hdr/2024/trip")
+        );
+        assert_eq!(
+            root(DefaultOutputDir::MirroredTree {
+                root: "/hdr".to_string(),
+                source_root: Some("/elsewhere".to_string()),
+            }),
+            Path::new("/hdr/shots/2024/trip")
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_unbalanced_placeholders() {
+        let ctx = context(Path::new("/shots"), "g");
+
+        assert!(resolve_template("{outputRoot}/{camera}", &ctx).is_err());
+        assert!(resolve_template("{outputRoot}/{yyyy", &ctx).is_err());
+        assert!(resolve_template("{outputRoot}/yyyy}", &ctx).is_err());
+        assert!(resolve_template("{outputRoot}/a}/{dd}", &ctx).is_err());
+    }
+}
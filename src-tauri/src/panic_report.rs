@@ -0,0 +1,129 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::Serialize;
+use ts_rs::TS;
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct JobFailure {
+    pub job_id: Option<String>,
+    // merge / analysis
+    pub kind: &'static str,
+    pub message: String,
+    // バックトレースを書き出したファイル。書き出せなかった場合は null
+    pub report_path: Option<String>,
+    pub failed_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaughtPanic {
+    pub message: String,
+    pub report_path: Option<PathBuf>,
+}
+
+thread_local! {
+    // パニックしたスレッドで、フックが書いたレポートを catch に渡す
+    static LAST_REPORT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+// パニックの内容とバックトレースを log_dir に書き出すフックを入れる。標準のフックもそのまま呼ぶ
+pub fn install_hook(log_dir: PathBuf) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+        let message = payload_message(info.payload());
+        let report = write_report(
+            &log_dir,
+            &message,
+            &location,
+            &Backtrace::force_capture().to_string(),
+        );
+        LAST_REPORT.with(|last| *last.borrow_mut() = report);
+        default_hook(info);
+    }));
+}
+
+// ジョブのパニックを受け止めてエラーとして返す。ロックを持ったままパニックした場合、そのロックは以後エラーになる
+pub fn catch<T>(job: impl FnOnce() -> T) -> Result<T, CaughtPanic> {
+    LAST_REPORT.with(|last| *last.borrow_mut() = None);
+    panic::catch_unwind(AssertUnwindSafe(job)).map_err(|payload| CaughtPanic {
+        message: payload_message(payload.as_ref()),
+        report_path: LAST_REPORT.with(|last| last.borrow_mut().take()),
+    })
+}
+
+impl CaughtPanic {
+    pub fn failure(&self, job_id: Option<String>, kind: &'static str) -> JobFailure {
+        JobFailure {
+            job_id,
+            kind,
+            message: self.message.clone(),
+            report_path: self
+                .report_path
+                .as_ref()
+                .map(|path| path.to_string_lossy().to_string()),
+            failed_at: Local::now().to_rfc3339(),
+        }
+    }
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "不明なパニック".to_string())
+}
+
+fn write_report(dir: &Path, message: &str, location: &str, backtrace: &str) -> Option<PathBuf> {
+    fs::create_dir_all(dir).ok()?;
+    let now = Local::now();
+    let thread = std::thread::current();
+    let path = dir.join(format!("panic_{}.log", now.format("%Y%m%d_%H%M%S_%3f")));
+    let text = format!(
+        "time: {}\nthread: {}\nlocation: {}\nmessage: {}\n\n{}\n",
+        now.to_rfc3339(),
+        thread.name().unwrap_or("<unnamed>"),
+        location,
+        message,
+        backtrace
+    );
+    fs::write(&path, text).ok()?;
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catches_panics_as_errors() {
+        assert_eq!(catch(|| 42), Ok(42));
+
+        let caught = catch(|| -> u32 { panic!("frame {} is broken", 3) }).unwrap_err();
+        assert_eq!(caught.message, "frame 3 is broken");
+        let failure = caught.failure(Some("job-1".to_string()), "merge");
+        assert_eq!(failure.job_id.as_deref(), Some("job-1"));
+    }
+
+    #[test]
+    fn report_contains_location_and_backtrace() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = write_report(dir.path(), "boom", "src/merge.rs:1:1", "0: run_merge").unwrap();
+
+        let text = fs::read_to_string(path).unwrap();
+        assert!(text.contains("message: boom"));
+        assert!(text.contains("location: src/merge.rs:1:1"));
+        assert!(text.ends_with("0: run_merge\n"));
+    }
+}
@@ -0,0 +1,287 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Windows の MAX_PATH（260）からファイル名の余裕を引いた長さ。これを超えるパスは拡張長形式にする
+const EXTENDED_PATH_THRESHOLD: usize = 240;
+const EXTENDED_PREFIX: &str = r"\\?\";
+const EXTENDED_UNC_PREFIX: &str = r"\\?\UNC\";
+// デバイスや名前付きパイプ、ボリューム GUID を指すパス
+const DEVICE_PREFIXES: [&str; 4] = [r"\\.\", "//./", r"\??\", "//?/"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathError {
+    Empty,
+    // フロントエンドから届く時点で壊れた文字（対になっていないサロゲートなど）を含む
+    InvalidCharacters(String),
+    NotFound(String),
+    NotAFile(String),
+    NotADirectory(String),
+    DevicePath(String),
+    Relative(String),
+    // 設定の allowedWriteRoots の外への書き込み
+    OutsideAllowedRoots(String),
+    Io { path: String, message: String },
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "パスが指定されていません"),
+            PathError::InvalidCharacters(path) => {
+                write!(f, "パスに扱えない文字が含まれています: {}", path)
+            }
+            PathError::NotFound(path) => write!(f, "ファイルが見つかりません: {}", path),
+            PathError::NotAFile(path) => write!(f, "ファイルではありません: {}", path),
+            PathError::NotADirectory(path) => write!(f, "フォルダではありません: {}", path),
+            PathError::DevicePath(path) => write!(f, "デバイスのパスは扱えません: {}", path),
+            PathError::Relative(path) => write!(f, "絶対パスで指定してください: {}", path),
+            PathError::OutsideAllowedRoots(path) => {
+                write!(f, "書き込みが許可されたフォルダの外です: {}", path)
+            }
+            PathError::Io { path, message } => {
+                write!(f, "{} にアクセスできません: {}", path, message)
+            }
+        }
+    }
+}
+
+impl From<PathError> for String {
+    fn from(error: PathError) -> Self {
+        error.to_string()
+    }
+}
+
+fn validate(path: &str) -> Result<PathBuf, PathError> {
+    if path.trim().is_empty() {
+        return Err(PathError::Empty);
+    }
+    // 不正なサロゲートは JSON の読み込み時に U+FFFD へ置き換わっている
+    if path.contains('\u{FFFD}') || path.contains('\0') {
+        return Err(PathError::InvalidCharacters(path.to_string()));
+    }
+    if is_device_path(path) {
+        return Err(PathError::DevicePath(path.to_string()));
+    }
+    let checked = PathBuf::from(path);
+    if !checked.is_absolute() {
+        return Err(PathError::Relative(path.to_string()));
+    }
+    Ok(checked)
+}
+
+// 拡張長形式はドライブ（\\?\C:\）と共有（\\?\UNC\）だけを通す
+fn is_device_path(path: &str) -> bool {
+    let lower = path.to_ascii_lowercase();
+    if DEVICE_PREFIXES
+        .iter()
+        .any(|prefix| lower.starts_with(prefix))
+    {
+        return true;
+    }
+    match lower.strip_prefix(EXTENDED_PREFIX) {
+        Some(rest) => {
+            let drive = rest.as_bytes();
+            let is_drive = drive.len() >= 2 && drive[0].is_ascii_alphabetic() && drive[1] == b':';
+            !(is_drive || rest.starts_with("unc\\"))
+        }
+        None => false,
+    }
+}
+
+// 書き込み先が roots のどれかの中にあるか確かめる。roots が空なら制限しない
+pub fn ensure_writable(path: &Path, roots: &[PathBuf]) -> Result<(), PathError> {
+    if roots.is_empty() {
+        return Ok(());
+    }
+    // まだないフォルダをまたぐ ".." は解決できないため、".." を含むパスは許可しない
+    let has_parent = path
+        .components()
+        .any(|component| component == std::path::Component::ParentDir);
+    let target = canonical(path);
+    if !has_parent && roots.iter().any(|root| target.starts_with(canonical(root))) {
+        return Ok(());
+    }
+    Err(PathError::OutsideAllowedRoots(
+        path.to_string_lossy().to_string(),
+    ))
+}
+
+// シンボリックリンクや ".." を解決する。まだないパスは、存在する親までを解決して残りをつなげる
+pub fn canonical(path: &Path) -> PathBuf {
+    let mut missing = Vec::new();
+    let mut current = path;
+    loop {
+        if let Ok(canonical) = current.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |acc: PathBuf, name| acc.join(name));
+        }
+        match (current.parent(), current.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name.to_os_string());
+                current = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+pub fn input_file(path: &str) -> Result<PathBuf, PathError> {
+    let checked = validate(path)?;
+    let os_path = extended(&checked);
+    match os_path.metadata() {
+        Ok(metadata) if metadata.is_file() => Ok(os_path),
+        Ok(_) => Err(PathError::NotAFile(path.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(PathError::NotFound(path.to_string()))
+        }
+        Err(e) => Err(PathError::Io {
+            path: path.to_string(),
+            message: e.to_string(),
+        }),
+    }
+}
+
+// 監視フォルダは画面表示や比較にも使うため、拡張長形式にせず元の形で返す
+pub fn input_dir(path: &str) -> Result<PathBuf, PathError> {
+    let checked = validate(path)?;
+    let os_path = extended(&checked);
+    match os_path.metadata() {
+        Ok(metadata) if metadata.is_dir() => Ok(checked),
+        Ok(_) => Err(PathError::NotADirectory(path.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(PathError::NotFound(path.to_string()))
+        }
+        Err(e) => Err(PathError::Io {
+            path: path.to_string(),
+            message: e.to_string(),
+        }),
+    }
+}
+
+pub fn output_file(path: &str) -> Result<PathBuf, PathError> {
+    validate(path).map(|path| extended(&path))
+}
+
+// OS のファイル操作に渡す直前に使う。長いパスは Windows で拡張長形式（\\?\）に変換する
+pub fn extended(path: &Path) -> PathBuf {
+    if !cfg!(windows) || path.as_os_str().len() <= EXTENDED_PATH_THRESHOLD {
+        return path.to_path_buf();
+    }
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match absolute.to_str() {
+        Some(text) => PathBuf::from(to_extended(text)),
+        None => absolute,
+    }
+}
+
+// 拡張長形式では "/" や "." / ".." が解釈されないため、区切りを揃えて字句的に正規化する
+fn to_extended(path: &str) -> String {
+    if path.starts_with(EXTENDED_PREFIX) {
+        return path.to_string();
+    }
+    let path = path.replace('/', r"\");
+    let (prefix, rest) = match path.strip_prefix(r"\\") {
+        Some(unc) => (EXTENDED_UNC_PREFIX, unc.to_string()),
+        None => (EXTENDED_PREFIX, path),
+    };
+
+    let mut components: Vec<&str> = Vec::new();
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                // ドライブや共有名より上には戻らない
+                if components.len() > 1 {
+                    components.pop();
+                }
+            }
+            _ => components.push(component),
+        }
+    }
+    format!("{}{}", prefix, components.join(r"\"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_drive_and_unc_paths_to_extended_form() {
+        assert_eq!(
+            to_extended(r"C:\shots\..\shots\.\day1/IMG_0001.JPG"),
+            r"\\?\C:\shots\day1\IMG_0001.JPG"
+        );
+        assert_eq!(
+            to_extended(r"\\nas\share\shots\..\a.png"),
+            r"\\?\UNC\nas\share\a.png"
+        );
+        assert_eq!(to_extended(r"\\?\C:\already"), r"\\?\C:\already");
+    }
+
+    #[test]
+    fn reports_typed_errors_for_invalid_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().to_string_lossy().to_string();
+        let missing = dir.path().join("missing.png").to_string_lossy().to_string();
+
+        assert_eq!(input_file(""), Err(PathError::Empty));
+        assert_eq!(
+            input_file("broken\u{FFFD}.png"),
+            Err(PathError::InvalidCharacters(
+                "broken\u{FFFD}.png".to_string()
+            ))
+        );
+        assert_eq!(input_file(&missing), Err(PathError::NotFound(missing)));
+        assert_eq!(
+            input_file(&folder),
+            Err(PathError::NotAFile(folder.clone()))
+        );
+        assert!(input_dir(&folder).is_ok());
+    }
+
+    #[test]
+    fn rejects_device_and_relative_paths() {
+        for device in [
+            r"\\.\PhysicalDrive0",
+            "//./pipe/hdr",
+            r"\\?\GLOBALROOT\Device\HarddiskVolume1",
+            r"\\?\Volume{0000}\a.png",
+            r"\??\C:\a.png",
+        ] {
+            assert_eq!(
+                output_file(device),
+                Err(PathError::DevicePath(device.to_string()))
+            );
+        }
+        assert!(!is_device_path(r"\\?\C:\shots\a.png"));
+        assert!(!is_device_path(r"\\?\UNC\nas\share\a.png"));
+        assert_eq!(
+            output_file("shots/a.png"),
+            Err(PathError::Relative("shots/a.png".to_string()))
+        );
+    }
+
+    #[test]
+    fn restricts_writes_to_allowed_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let allowed = dir.path().join("exports");
+        std::fs::create_dir(&allowed).unwrap();
+        let roots = vec![allowed.clone()];
+
+        assert!(ensure_writable(&allowed.join("new/hdr.png"), &roots).is_ok());
+        assert!(ensure_writable(&allowed.join("../outside.png"), &roots).is_err());
+        assert!(ensure_writable(&allowed.join("new/../../outside.png"), &roots).is_err());
+        assert!(ensure_writable(&dir.path().join("outside.png"), &[]).is_ok());
+    }
+
+    #[test]
+    fn accepts_unicode_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("夕景_ブラケット_01.png");
+        std::fs::write(&path, b"").unwrap();
+
+        assert!(input_file(&path.to_string_lossy()).is_ok());
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::capabilities::MAX_MERGE_FRAMES;
+use crate::formats;
+use crate::grouping;
+use crate::merge::{MergeMode, MergeRequest};
+use crate::noise_stack::MAX_HYBRID_FRAMES;
+use crate::paths;
+
+// 手動で組み立てている合成待ちのブラケット
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingGroup {
+    pub id: u64,
+    pub paths: Vec<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    // 自動のグループ分けが決めた mergeMode。未指定なら合成するときに EXIF の露出から決める
+    #[serde(default)]
+    pub merge_mode: Option<MergeMode>,
+}
+
+impl PendingGroup {
+    // automatic は監視から組んだグループを自動で合成するとき。pauseWhileRunning のアプリが動いている間は待たせる
+    pub fn merge_request(&self, preset: Option<String>, automatic: bool) -> MergeRequest {
+        MergeRequest {
+            paths: self.paths.clone(),
+            preset,
+            job_id: Some(format!("group-{}", self.id)),
+            merge_mode: self
+                .merge_mode
+                .unwrap_or_else(|| grouping::detect_merge_mode(&self.paths)),
+            automatic,
+            ..Default::default()
+        }
+    }
+}
+
+struct State {
+    next_id: u64,
+    groups: BTreeMap<u64, PendingGroup>,
+}
+
+// 1つのファイルは1つのグループにだけ入れられる。合成するまでメモリ上に保持する
+pub struct GroupStore {
+    state: Mutex<State>,
+}
+
+impl Default for GroupStore {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                next_id: 1,
+                groups: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl GroupStore {
+    pub fn create(
+        &self,
+        paths: &[String],
+        merge_mode: Option<MergeMode>,
+    ) -> Result<PendingGroup, String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut added: Vec<String> = Vec::new();
+        for path in paths {
+            check_file(path)?;
+            if added.contains(path) {
+                return Err(format!("同じファイルが重複しています: {}", path));
+            }
+            check_unassigned(&state.groups, path)?;
+            added.push(path.clone());
+        }
+        check_count(added.len(), merge_mode)?;
+
+        let now = Local::now().to_rfc3339();
+        let group = PendingGroup {
+            id: state.next_id,
+            paths: added,
+            created_at: now.clone(),
+            updated_at: now,
+            merge_mode,
+        };
+        state.next_id += 1;
+        state.groups.insert(group.id, group.clone());
+        Ok(group)
+    }
+
+    pub fn add_file(&self, id: u64, path: &str) -> Result<PendingGroup, String> {
+        check_file(path)?;
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        check_unassigned(&state.groups, path)?;
+        let group = group_mut(&mut state.groups, id)?;
+        check_count(group.paths.len() + 1, group.merge_mode)?;
+        group.paths.push(path.to_string());
+        group.updated_at = Local::now().to_rfc3339();
+        Ok(group.clone())
+    }
+
+    pub fn remove_file(&self, id: u64, path: &str) -> Result<PendingGroup, String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let group = group_mut(&mut state.groups, id)?;
+        let index = group
+            .paths
+            .iter()
+            .position(|existing| existing == path)
+            .ok_or_else(|| format!("グループに含まれていないファイルです: {}", path))?;
+        group.paths.remove(index);
+        group.updated_at = Local::now().to_rfc3339();
+        Ok(group.clone())
+    }
+
+    pub fn get(&self, id: u64) -> Result<PendingGroup, String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        group_mut(&mut state.groups, id).map(|group| group.clone())
+    }
+
+    pub fn list(&self) -> Result<Vec<PendingGroup>, String> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(state.groups.values().cloned().collect())
+    }
+
+    pub fn delete(&self, id: u64) -> Result<PendingGroup, String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state
+            .groups
+            .remove(&id)
+            .ok_or_else(|| format!("グループが見つかりません: {}", id))
+    }
+}
+
+fn group_mut(
+    groups: &mut BTreeMap<u64, PendingGroup>,
+    id: u64,
+) -> Result<&mut PendingGroup, String> {
+    groups
+        .get_mut(&id)
+        .ok_or_else(|| format!("グループが見つかりません: {}", id))
+}
+
+fn check_file(path: &str) -> Result<(), String> {
+    let file = paths::input_file(path)?;
+    formats::ensure_decodable(&file)?;
+    if !formats::is_decodable(Path::new(path)) {
+        return Err(format!("合成に使えない形式です: {}", path));
+    }
+    Ok(())
+}
+
+fn check_unassigned(groups: &BTreeMap<u64, PendingGroup>, path: &str) -> Result<(), String> {
+    match groups
+        .values()
+        .find(|group| group.paths.iter().any(|p| p == path))
+    {
+        Some(group) => Err(format!(
+            "すでにグループ {} に含まれています: {}",
+            group.id, path
+        )),
+        None => Ok(()),
+    }
+}
+
+// hybrid は露出ごとに数枚ずつ撮るため多く受け付ける
+fn check_count(count: usize, merge_mode: Option<MergeMode>) -> Result<(), String> {
+    let max = match merge_mode {
+        Some(MergeMode::Hybrid) => MAX_HYBRID_FRAMES,
+        _ => MAX_MERGE_FRAMES,
+    };
+    if count > max {
+        return Err(format!("合成は最大{}枚までです", max));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(dir: &Path, names: &[&str]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                std::fs::write(&path, b"").unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_groups_and_keeps_files_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = files(dir.path(), &["a.jpg", "b.jpg", "c.jpg"]);
+        let store = GroupStore::default();
+
+        let group = store.create(&paths[..2], None).unwrap();
+        assert!(store.create(&paths[1..], None).is_err());
+        let second = store.create(&paths[2..], None).unwrap();
+        assert!(store.add_file(group.id, &paths[2]).is_err());
+
+        store.delete(second.id).unwrap();
+        let group = store.add_file(group.id, &paths[2]).unwrap();
+        assert_eq!(group.paths, paths);
+        let group = store.remove_file(group.id, &paths[0]).unwrap();
+        assert_eq!(group.paths, &paths[1..]);
+        assert!(store.remove_file(group.id, &paths[0]).is_err());
+        assert_eq!(store.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn keeps_working_after_a_panic_while_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = files(dir.path(), &["a.jpg", "b.jpg"]);
+        let store = GroupStore::default();
+        let group = store.create(&paths[..1], None).unwrap();
+
+        // ロックを持ったまま panic したスレッドがあっても、グループの編集を続けられる
+        let poisoned = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _state = store.state.lock().unwrap();
+                    panic!("poison");
+                })
+                .join()
+        });
+        assert!(poisoned.is_err());
+        assert!(store.state.is_poisoned());
+        let group = store.add_file(group.id, &paths[1]).unwrap();
+        assert_eq!(group.paths, paths);
+        assert_eq!(store.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejects_missing_duplicate_or_unsupported_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = files(dir.path(), &["a.jpg", "notes.txt"]);
+        let store = GroupStore::default();
+
+        assert!(store
+            .create(&[paths[0].clone(), paths[0].clone()], None)
+            .is_err());
+        assert!(store.create(&paths[1..], None).is_err());
+        let missing = dir.path().join("missing.jpg").to_string_lossy().to_string();
+        assert!(store.create(&[missing], None).is_err());
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn automatic_group_merges_wait_while_paused() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = files(dir.path(), &["a.jpg", "b.jpg"]);
+        let store = GroupStore::default();
+        let group = store.create(&paths, None).unwrap();
+        let request = group.merge_request(None, true);
+        assert!(request.automatic);
+        assert_eq!(request.job_id, Some(format!("group-{}", group.id)));
+        assert!(!group.merge_request(None, false).automatic);
+        // グループ分けが決めた mergeMode はそのまま渡し、なければ EXIF の露出から決める
+        assert_eq!(request.merge_mode, MergeMode::Bracket);
+        let hybrid = store
+            .create(&files(dir.path(), &["c.jpg"]), Some(MergeMode::Hybrid))
+            .unwrap();
+        assert_eq!(
+            hybrid.merge_request(None, true).merge_mode,
+            MergeMode::Hybrid
+        );
+
+        let jobs = crate::jobs::JobTracker::new(dir.path().join("pending_jobs.json"));
+        jobs.set_paused_by(vec!["obs64.exe".to_string()]);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let waiting = jobs.wait_for_slot(&request, 2);
+            let timeout = tokio::time::timeout(std::time::Duration::from_millis(50), waiting);
+            assert!(timeout.await.is_err());
+        });
+    }
+}
@@ -0,0 +1,551 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::algorithms::{self, MergeAlgorithm, DEFAULT_ALGORITHM};
+use crate::align::{self, AlignParams};
+use crate::align_sidecar::{self, TransformSidecar};
+use crate::deghost::{self, DeghostParams};
+use crate::encode::EncodeParams;
+use crate::filters::{self, DenoiseParams, ResizeParams};
+use crate::geometry;
+use crate::gray_card::{self, GrayCardCorrection};
+use crate::long_exposure::LongExposure;
+use crate::merge::{self, MergeMode, MergeRequest, MergedImage, Rgb16Image};
+use crate::noise_stack::NoiseStack;
+use crate::plugin::{self, ExternalParams};
+use crate::radiance::{self, Radiance};
+use crate::tiled;
+use crate::tonemap::{self, TonemapParams};
+
+const NOT_MERGED: &str = "merge ステージが実行されていません";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(tag = "stage", rename_all = "camelCase")]
+pub enum PipelineStage {
+    Align(AlignParams),
+    Deghost(DeghostParams),
+    Merge,
+    // 台形補正と自動水平補正（MergeRequest の perspective / autoStraighten）
+    Geometry,
+    Denoise(DenoiseParams),
+    Tonemap(TonemapParams),
+    Resize(ResizeParams),
+    // 外部の実行ファイルによる処理（独自のノイズ除去など）。複数配置できる
+    External(ExternalParams),
+    Encode(EncodeParams),
+}
+
+impl PipelineStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PipelineStage::Align(_) => "align",
+            PipelineStage::Deghost(_) => "deghost",
+            PipelineStage::Merge => "merge",
+            PipelineStage::Geometry => "geometry",
+            PipelineStage::Denoise(_) => "denoise",
+            PipelineStage::Tonemap(_) => "tonemap",
+            PipelineStage::Resize(_) => "resize",
+            PipelineStage::External(_) => "external",
+            PipelineStage::Encode(_) => "encode",
+        }
+    }
+
+    // 合成前の各フレームに対して行うステージか
+    fn is_frame_stage(&self) -> bool {
+        matches!(self, PipelineStage::Align(_) | PipelineStage::Deghost(_))
+    }
+}
+
+pub const STAGE_NAMES: &[&str] = &[
+    "align", "deghost", "merge", "geometry", "denoise", "tonemap", "resize", "external", "encode",
+];
+
+pub fn default_pipeline() -> Vec<PipelineStage> {
+    vec![
+        PipelineStage::Merge,
+        PipelineStage::Geometry,
+        PipelineStage::Encode(Default::default()),
+    ]
+}
+
+pub fn resolve(request: &MergeRequest) -> Result<Vec<PipelineStage>, String> {
+    let stages = request
+        .pipeline
+        .clone()
+        .unwrap_or_else(|| match request.merge_mode {
+            // 手持ちで撮った枚数の多いスタックは位置を揃えないとぼける。
+            // 揃えるのは画面全体の動きだけなので、longExposure の被写体の軌跡は残る
+            MergeMode::NoiseStack | MergeMode::LongExposure => vec![
+                PipelineStage::Align(AlignParams::default()),
+                PipelineStage::Merge,
+                PipelineStage::Geometry,
+                PipelineStage::Encode(Default::default()),
+            ],
+            MergeMode::Bracket | MergeMode::Hybrid => default_pipeline(),
+        });
+    validate(&stages, request)?;
+    Ok(stages)
+}
+
+pub fn validate(stages: &[PipelineStage], request: &MergeRequest) -> Result<(), String> {
+    for (index, stage) in stages.iter().enumerate() {
+        if matches!(stage, PipelineStage::External(_)) {
+            continue;
+        }
+        if stages[..index]
+            .iter()
+            .any(|other| other.name() == stage.name())
+        {
+            return Err(format!("ステージ {} が重複しています", stage.name()));
+        }
+    }
+
+    let merge_index = stages
+        .iter()
+        .position(|stage| *stage == PipelineStage::Merge)
+        .ok_or("merge ステージは省略できません")?;
+    if !matches!(stages.last(), Some(PipelineStage::Encode(_))) {
+        return Err("encode ステージは最後に1つだけ配置してください".to_string());
+    }
+
+    for (index, stage) in stages.iter().enumerate() {
+        if stage.is_frame_stage() && index > merge_index {
+            return Err(format!(
+                "{} ステージは merge より前に配置してください",
+                stage.name()
+            ));
+        }
+        if !stage.is_frame_stage() && *stage != PipelineStage::Merge && index < merge_index {
+            return Err(format!(
+                "{} ステージは merge より後に配置してください",
+                stage.name()
+            ));
+        }
+        validate_params(stage, request)?;
+    }
+
+    let has_geometry = stages.contains(&PipelineStage::Geometry);
+    if !has_geometry && (request.perspective.is_some() || request.auto_straighten) {
+        return Err("台形補正・水平補正には geometry ステージが必要です".to_string());
+    }
+    let has_align = stages
+        .iter()
+        .any(|stage| matches!(stage, PipelineStage::Align(_)));
+    if !has_align && request.transforms_path.is_some() {
+        return Err("transformsPath には align ステージが必要です".to_string());
+    }
+
+    Ok(())
+}
+
+fn validate_params(stage: &PipelineStage, request: &MergeRequest) -> Result<(), String> {
+    if let PipelineStage::External(params) = stage {
+        return plugin::validate(params, &request.allowed_plugins);
+    }
+    let valid = match stage {
+        PipelineStage::Align(params) => {
+            (1..=256).contains(&params.max_shift)
+                && (0.0..=45.0).contains(&params.max_rotation)
+                && (0.0..=0.5).contains(&params.max_scale)
+        }
+        PipelineStage::Deghost(params) => (0.05..=4.0).contains(&params.threshold),
+        PipelineStage::Denoise(params) => (0.0..=1.0).contains(&params.strength),
+        PipelineStage::Tonemap(params) => {
+            (-10.0..=10.0).contains(&params.exposure)
+                && (1.0..=1000.0).contains(&params.white_point)
+                && (0.0..=1.0).contains(&params.clarity)
+        }
+        PipelineStage::Resize(params) => (16..=65536).contains(&params.max_size),
+        PipelineStage::Encode(params) => params.sharpen.as_ref().is_none_or(|sharpen| {
+            (0.1..=10.0).contains(&sharpen.radius)
+                && (0.0..=5.0).contains(&sharpen.amount)
+                && (0.0..=1.0).contains(&sharpen.threshold)
+        }),
+        PipelineStage::Merge | PipelineStage::Geometry | PipelineStage::External(_) => true,
+    };
+    if !valid {
+        return Err(format!("{} ステージのパラメータが不正です", stage.name()));
+    }
+    Ok(())
+}
+
+pub fn run(images: &[Rgb16Image], request: &MergeRequest) -> Result<MergedImage, String> {
+    let stages = resolve(request)?;
+
+    let mut frames: Vec<Rgb16Image> = match &request.roi {
+        Some(roi) => images
+            .iter()
+            .map(|image| geometry::crop_roi(image, roi))
+            .collect::<Result<_, _>>()?,
+        None => images.to_vec(),
+    };
+    let reference = frames.len() / 2;
+
+    let algorithm: &dyn MergeAlgorithm = match request.merge_mode {
+        MergeMode::NoiseStack => &NoiseStack,
+        MergeMode::LongExposure => &LongExposure,
+        MergeMode::Bracket | MergeMode::Hybrid => {
+            algorithms::find(request.algorithm.as_deref().unwrap_or(DEFAULT_ALGORITHM))?
+        }
+    };
+    let params = algorithms::resolve_params(algorithm, &request.algorithm_params)?;
+
+    let mut merged: Option<Rgb16Image> = None;
+    // outputLayeredExr のとき、合成に使った位置合わせ後のフレームに合成後と同じ変形をかけて残す
+    let mut frame_layers: Vec<Rgb16Image> = Vec::new();
+    let mut short_reference: Option<Rgb16Image> = None;
+    // outputDng のとき、トーンマップ前の線形の放射輝度。位置を変える処理だけをかけ、見た目の調整はかけない
+    let mut radiance: Option<Radiance> = None;
+    let mut alignment_transforms = Vec::new();
+    let mut alignment_sidecar = None;
+    let mut sharpen = None;
+    let mut straighten_angle = None;
+    let mut memory_fallback = None;
+    let budget_bytes = request.memory_budget_mb.map(|mb| mb * 1024 * 1024);
+    // グレーカードはトーンマップ前の合成結果で測り、tonemap ステージがあれば圧縮の前にかける
+    let mut gray_card = None;
+    let tonemapped = stages
+        .iter()
+        .any(|stage| matches!(stage, PipelineStage::Tonemap(_)));
+
+    for stage in &stages {
+        match stage {
+            PipelineStage::Align(params) => {
+                let size = frames[0].dimensions();
+                let (aligned, transforms) = match &request.transforms_path {
+                    Some(path) => {
+                        let transforms =
+                            align_sidecar::load(path, &request.paths, size, request.roi)?;
+                        let aligned =
+                            align::apply_transforms(&frames, &transforms, params.align_precision);
+                        (aligned, transforms)
+                    }
+                    None => align::align_frames(&frames, reference, params, request.quality),
+                };
+                alignment_sidecar = Some(TransformSidecar::new(
+                    &request.paths,
+                    size,
+                    request.roi,
+                    transforms.clone(),
+                ));
+                frames = aligned;
+                alignment_transforms = transforms;
+            }
+            PipelineStage::Deghost(params) => {
+                frames = deghost::deghost_frames(&frames, reference, params, request.quality);
+            }
+            PipelineStage::Merge => {
+                let (mut image, fallback) =
+                    tiled::merge_frames(algorithm, &frames, &params, budget_bytes)?;
+                gray_card = measure_gray_card(request, &image)?;
+                if let (Some(correction), false) = (&gray_card, tonemapped) {
+                    image = gray_card::apply(&image, correction);
+                }
+                merged = Some(image);
+                memory_fallback = fallback;
+                if request.output_short_reference {
+                    short_reference = Some(frames[reference].clone());
+                }
+                if request.output_dng {
+                    radiance = radiance::merge(&frames);
+                }
+                if request.output_layered_exr {
+                    frame_layers = std::mem::take(&mut frames);
+                }
+                frames.clear();
+            }
+            PipelineStage::Geometry => {
+                let image = merged_image(&mut merged)?;
+                if let Some(corners) = &request.perspective {
+                    *image = geometry::apply_perspective(image, corners)?;
+                    for layer in frame_layers
+                        .iter_mut()
+                        .chain(&mut short_reference)
+                        .chain(radiance.as_mut().map(|radiance| &mut radiance.image))
+                    {
+                        *layer = geometry::apply_perspective(layer, corners)?;
+                    }
+                }
+                if request.auto_straighten {
+                    if let Some(angle) = geometry::estimate_straighten_angle(image) {
+                        *image = geometry::rotate_and_crop(image, angle);
+                        for layer in frame_layers
+                            .iter_mut()
+                            .chain(&mut short_reference)
+                            .chain(radiance.as_mut().map(|radiance| &mut radiance.image))
+                        {
+                            *layer = geometry::rotate_and_crop(layer, angle);
+                        }
+                        straighten_angle = Some(angle);
+                    }
+                }
+            }
+            PipelineStage::Denoise(params) => {
+                let image = merged_image(&mut merged)?;
+                *image = filters::denoise(image, params);
+            }
+            PipelineStage::Tonemap(params) => {
+                let image = merged_image(&mut merged)?;
+                let gains = gray_card
+                    .as_ref()
+                    .map_or([1.0; 3], |correction: &GrayCardCorrection| correction.gains);
+                *image = tonemap::tonemap(image, params, gains);
+            }
+            PipelineStage::Resize(params) => {
+                let image = merged_image(&mut merged)?;
+                *image = filters::resize(image, params);
+                for layer in frame_layers
+                    .iter_mut()
+                    .chain(&mut short_reference)
+                    .chain(radiance.as_mut().map(|radiance| &mut radiance.image))
+                {
+                    *layer = filters::resize(layer, params);
+                }
+            }
+            PipelineStage::External(params) => {
+                let workdir = request
+                    .workdir
+                    .as_deref()
+                    .ok_or("external ステージはジョブの作業フォルダがある合成でだけ実行できます")?;
+                let image = merged_image(&mut merged)?;
+                *image = plugin::run_external(image, params, workdir, &request.progress)?;
+            }
+            PipelineStage::Encode(params) => {
+                sharpen = params.sharpen.clone();
+            }
+        }
+    }
+
+    Ok(MergedImage {
+        image: merged.ok_or(NOT_MERGED)?,
+        algorithm: algorithm.name().to_string(),
+        stages: stages
+            .iter()
+            .map(|stage| stage.name().to_string())
+            .collect(),
+        alignment_offsets: alignment_transforms
+            .iter()
+            .map(|transform| transform.offset())
+            .collect(),
+        alignment_transforms,
+        straighten_angle,
+        memory_fallback,
+        frame_layers,
+        short_reference,
+        alignment_sidecar,
+        sharpen,
+        levels: None,
+        gray_card,
+        radiance,
+        clipping: None,
+    })
+}
+
+fn merged_image(merged: &mut Option<Rgb16Image>) -> Result<&mut Rgb16Image, String> {
+    merged.as_mut().ok_or_else(|| NOT_MERGED.to_string())
+}
+
+// 測り済みの補正（タイムラプスで固定したもの）があればそれを使う
+fn measure_gray_card(
+    request: &MergeRequest,
+    merged: &Rgb16Image,
+) -> Result<Option<GrayCardCorrection>, String> {
+    Ok(match (&request.gray_card_correction, &request.gray_card) {
+        (Some(correction), _) => Some(correction.clone()),
+        (None, Some(settings)) => Some(match &settings.path {
+            Some(path) => {
+                let card = merge::load_rgb16(path)
+                    .map_err(|e| format!("グレーカードの画像を読み込めません: {}", e))?;
+                gray_card::measure(&card, settings)?
+            }
+            None => gray_card::measure(merged, settings)?,
+        }),
+        (None, None) => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{linear_to_srgb, srgb_to_linear, u16_to_unit, unit_to_u16};
+    use crate::filters::SharpenParams;
+    use image::Rgb;
+    use std::path::PathBuf;
+
+    fn request_with(stages: Vec<PipelineStage>) -> MergeRequest {
+        MergeRequest {
+            pipeline: Some(stages),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_pipeline_is_valid() {
+        assert!(resolve(&MergeRequest::default()).is_ok());
+    }
+
+    #[test]
+    fn parses_stage_list_with_parameters() {
+        let stages: Vec<PipelineStage> = serde_json::from_value(serde_json::json!([
+            { "stage": "align", "maxShift": 8 },
+            { "stage": "merge" },
+            { "stage": "tonemap", "exposure": 1.0 },
+            { "stage": "encode" },
+            { "stage": "encode", "sharpen": { "radius": 0.8 } }
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            stages[0],
+            PipelineStage::Align(AlignParams {
+                max_shift: 8,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            stages[2],
+            PipelineStage::Tonemap(TonemapParams {
+                exposure: 1.0,
+                ..Default::default()
+            })
+        );
+        assert_eq!(stages[3], PipelineStage::Encode(EncodeParams::default()));
+        let PipelineStage::Encode(EncodeParams {
+            sharpen: Some(sharpen),
+        }) = &stages[4]
+        else {
+            panic!("encode ステージのパラメータを読めていません");
+        };
+        assert_eq!(sharpen.radius, 0.8);
+        assert!(resolve(&request_with(stages[..4].to_vec())).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_orderings() {
+        let frame_stage_after_merge = vec![
+            PipelineStage::Merge,
+            PipelineStage::Align(AlignParams::default()),
+            PipelineStage::Encode(Default::default()),
+        ];
+        let image_stage_before_merge = vec![
+            PipelineStage::Tonemap(TonemapParams::default()),
+            PipelineStage::Merge,
+            PipelineStage::Encode(Default::default()),
+        ];
+        let encode_not_last = vec![
+            PipelineStage::Merge,
+            PipelineStage::Encode(Default::default()),
+            PipelineStage::Resize(ResizeParams::default()),
+        ];
+        let missing_merge = vec![PipelineStage::Encode(Default::default())];
+        let negative_sharpen = vec![
+            PipelineStage::Merge,
+            PipelineStage::Encode(EncodeParams {
+                sharpen: Some(SharpenParams {
+                    amount: -1.0,
+                    ..Default::default()
+                }),
+            }),
+        ];
+        let duplicated = vec![
+            PipelineStage::Merge,
+            PipelineStage::Denoise(DenoiseParams::default()),
+            PipelineStage::Denoise(DenoiseParams::default()),
+            PipelineStage::Encode(Default::default()),
+        ];
+
+        for stages in [
+            frame_stage_after_merge,
+            image_stage_before_merge,
+            encode_not_last,
+            missing_merge,
+            negative_sharpen,
+            duplicated,
+        ] {
+            assert!(resolve(&request_with(stages)).is_err());
+        }
+    }
+
+    #[test]
+    fn allows_multiple_external_stages() {
+        let external = PipelineStage::External(ExternalParams {
+            command: "/opt/denoiser".to_string(),
+            ..Default::default()
+        });
+        let mut request = request_with(vec![
+            PipelineStage::Merge,
+            external.clone(),
+            external,
+            PipelineStage::Encode(Default::default()),
+        ]);
+        request.allowed_plugins = vec![PathBuf::from("/opt/denoiser")];
+
+        assert!(resolve(&request).is_ok());
+    }
+
+    // フロントエンドから渡したパイプラインでも、allowedPlugins にない実行ファイルは動かさない
+    #[test]
+    fn rejects_unregistered_external_commands() {
+        let request = request_with(vec![
+            PipelineStage::Merge,
+            PipelineStage::External(ExternalParams {
+                command: "sh".to_string(),
+                args: vec!["-c".to_string(), "touch {output}".to_string()],
+                ..Default::default()
+            }),
+            PipelineStage::Encode(Default::default()),
+        ]);
+
+        assert!(resolve(&request).is_err());
+    }
+
+    #[test]
+    fn gray_card_is_applied_before_tonemapping() {
+        let bright = unit_to_u16(linear_to_srgb(0.8));
+        let frames = vec![Rgb16Image::from_pixel(8, 8, Rgb([bright; 3])); 2];
+        let mut request = request_with(vec![
+            PipelineStage::Merge,
+            PipelineStage::Tonemap(TonemapParams::default()),
+            PipelineStage::Encode(Default::default()),
+        ]);
+        request.gray_card_correction = Some(GrayCardCorrection {
+            measured: [0.09, 0.18, 0.18],
+            gains: [2.0, 1.0, 1.0],
+            exposure_ev: 0.0,
+        });
+        let linear = |image: &Rgb16Image| {
+            image
+                .get_pixel(4, 4)
+                .0
+                .map(|v| srgb_to_linear(u16_to_unit(v)))
+        };
+
+        let merged = run(&frames, &request).unwrap();
+
+        assert_eq!(merged.gray_card, request.gray_card_correction);
+        // 倍率をかけて 1 を超えた赤も圧縮されるだけでクリップせず、色比が残る
+        let [red, green, _] = linear(&merged.image);
+        assert!(red < 1.0);
+        assert!((red / green - 2.0).abs() < 0.01, "{} {}", red, green);
+
+        // tonemap ステージがなければ合成の直後にかける
+        request.pipeline = Some(vec![
+            PipelineStage::Merge,
+            PipelineStage::Encode(Default::default()),
+        ]);
+        let [red, green, _] = linear(&run(&frames, &request).unwrap().image);
+        assert_eq!(red, 1.0);
+        assert!((green - 0.8).abs() < 0.001);
+    }
+
+    #[test]
+    fn geometry_options_require_geometry_stage() {
+        let mut request = request_with(vec![
+            PipelineStage::Merge,
+            PipelineStage::Encode(Default::default()),
+        ]);
+        request.auto_straighten = true;
+
+        assert!(resolve(&request).is_err());
+    }
+}
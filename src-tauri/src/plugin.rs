@@ -0,0 +1,256 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::merge::{self, Rgb16Image};
+use crate::progress::{ProgressReporter, CANCELLED};
+
+const INPUT_PLACEHOLDER: &str = "{input}";
+const OUTPUT_PLACEHOLDER: &str = "{output}";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static INVOCATION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// 外部の実行ファイルに中間画像（16bit PNG）を渡し、書き出された画像で置き換える。
+// args 中の {input} / {output} はジョブの作業フォルダ内のパスに置換される。
+// command は設定の allowedPlugins に登録した実行ファイルのフルパスに限る
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExternalParams {
+    pub command: String,
+    pub args: Vec<String>,
+    pub timeout_secs: u64,
+}
+
+impl Default for ExternalParams {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            args: vec![
+                INPUT_PLACEHOLDER.to_string(),
+                OUTPUT_PLACEHOLDER.to_string(),
+            ],
+            timeout_secs: 300,
+        }
+    }
+}
+
+pub fn validate(params: &ExternalParams, allowed: &[PathBuf]) -> Result<(), String> {
+    if params.command.trim().is_empty() {
+        return Err("external ステージの command が指定されていません".to_string());
+    }
+    // WebView から任意のコマンドを実行させないよう、ネイティブのダイアログで登録した実行ファイルだけを許す
+    if !allowed
+        .iter()
+        .any(|path| path.as_path() == Path::new(&params.command))
+    {
+        return Err(format!(
+            "外部コマンド {} は実行を許可されていません（plugin_allow_add で登録してください）",
+            params.command
+        ));
+    }
+    if !params
+        .args
+        .iter()
+        .any(|arg| arg.contains(OUTPUT_PLACEHOLDER))
+    {
+        return Err("external ステージの args に {output} が含まれていません".to_string());
+    }
+    if !(1..=3600).contains(&params.timeout_secs) {
+        return Err("external ステージの timeoutSecs が不正です".to_string());
+    }
+    Ok(())
+}
+
+// 中間画像はジョブの作業フォルダの中に置く。失敗したときは標準エラーの記録を調査用に残す
+pub fn run_external(
+    image: &Rgb16Image,
+    params: &ExternalParams,
+    job_workdir: &Path,
+    progress: &ProgressReporter,
+) -> Result<Rgb16Image, String> {
+    let workdir = create_workdir(job_workdir)?;
+    let result = invoke(image, params, &workdir, progress);
+    if result.is_ok() {
+        let _ = fs::remove_dir_all(&workdir);
+    }
+    result
+}
+
+fn create_workdir(job_workdir: &Path) -> Result<PathBuf, String> {
+    let id = INVOCATION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let dir = job_workdir.join(format!("plugin_{}", id));
+    fs::create_dir_all(&dir).map_err(|e| format!("作業フォルダを作成できません: {}", e))?;
+    Ok(dir)
+}
+
+fn invoke(
+    image: &Rgb16Image,
+    params: &ExternalParams,
+    workdir: &Path,
+    progress: &ProgressReporter,
+) -> Result<Rgb16Image, String> {
+    let input_path = workdir.join("input.png");
+    let output_path = workdir.join("output.png");
+    let stderr_path = workdir.join("stderr.log");
+    merge::save_png(image, &input_path)?;
+
+    let input = input_path.to_string_lossy();
+    let output = output_path.to_string_lossy();
+    let args: Vec<String> = params
+        .args
+        .iter()
+        .map(|arg| {
+            arg.replace(INPUT_PLACEHOLDER, &input)
+                .replace(OUTPUT_PLACEHOLDER, &output)
+        })
+        .collect();
+
+    let stderr = File::create(&stderr_path).map_err(|e| e.to_string())?;
+    let mut child = Command::new(&params.command)
+        .args(&args)
+        .current_dir(workdir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn()
+        .map_err(|e| format!("外部コマンド {} を起動できません: {}", params.command, e))?;
+
+    let deadline = Instant::now() + Duration::from_secs(params.timeout_secs);
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        // merge_cancel や終了時の中止を timeoutSecs まで待たせない
+        if progress.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(CANCELLED.to_string());
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "外部コマンド {} が {} 秒以内に終了しませんでした",
+                params.command, params.timeout_secs
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    if !status.success() {
+        let message = fs::read_to_string(&stderr_path).unwrap_or_default();
+        return Err(format!(
+            "外部コマンド {} が失敗しました（{}）: {}",
+            params.command,
+            status,
+            message.trim()
+        ));
+    }
+    if !output_path.exists() {
+        return Err(format!(
+            "外部コマンド {} が出力画像を書き出しませんでした",
+            params.command
+        ));
+    }
+
+    Ok(merge::load_rgb16(&output)?)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn gradient() -> Rgb16Image {
+        Rgb16Image::from_fn(16, 8, |x, y| Rgb([x as u16 * 4000, y as u16 * 8000, 1234]))
+    }
+
+    #[test]
+    fn passes_image_through_external_command() {
+        let workdir = tempfile::tempdir().unwrap();
+        let params = ExternalParams {
+            command: "cp".to_string(),
+            ..Default::default()
+        };
+
+        let output = run_external(
+            &gradient(),
+            &params,
+            workdir.path(),
+            &ProgressReporter::default(),
+        )
+        .unwrap();
+
+        assert_eq!(output, gradient());
+        // 中間画像は作業フォルダの中に置き、成功したら消す
+        assert_eq!(fs::read_dir(workdir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn reports_failing_command() {
+        let workdir = tempfile::tempdir().unwrap();
+        let params = ExternalParams {
+            command: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "echo broken >&2; exit 3".to_string(),
+                OUTPUT_PLACEHOLDER.to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let error = run_external(
+            &gradient(),
+            &params,
+            workdir.path(),
+            &ProgressReporter::default(),
+        )
+        .unwrap_err();
+
+        assert!(error.contains("broken"));
+    }
+
+    #[test]
+    fn kills_command_when_cancelled() {
+        let workdir = tempfile::tempdir().unwrap();
+        let params = ExternalParams {
+            command: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "sleep 30".to_string(),
+                OUTPUT_PLACEHOLDER.to_string(),
+            ],
+            ..Default::default()
+        };
+        let progress = ProgressReporter::default();
+        let canceller = progress.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+
+        let error = run_external(&gradient(), &params, workdir.path(), &progress).unwrap_err();
+
+        assert_eq!(error, CANCELLED);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn only_allows_registered_executables() {
+        let params = ExternalParams {
+            command: "/usr/bin/denoiser".to_string(),
+            ..Default::default()
+        };
+
+        assert!(validate(&params, &[]).is_err());
+        assert!(validate(&params, &[PathBuf::from("/usr/bin/other")]).is_err());
+        assert!(validate(&params, &[PathBuf::from("/usr/bin/denoiser")]).is_ok());
+    }
+}
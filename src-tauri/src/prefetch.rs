@@ -0,0 +1,247 @@
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::merge::{self, Rgb16Image};
+use crate::paths;
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PrefetchSettings {
+    // メモリを節約したい環境では無効にする
+    pub enabled: bool,
+    // 先読みしたデコード済み画像の合計の上限
+    pub max_mb: u64,
+}
+
+impl Default for PrefetchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_mb: 2048,
+        }
+    }
+}
+
+impl PrefetchSettings {
+    pub fn max_bytes(&self) -> u64 {
+        self.max_mb * MB
+    }
+}
+
+// 次に合成するジョブの入力を、実行中の合成と並行してデコードしておく。
+// 先読みは直近の1ジョブ分（と、これから取り出す合成の分）だけ保持し、合成で取り出した画像は手放す
+#[derive(Default)]
+pub struct Prefetcher {
+    state: Mutex<State>,
+}
+
+impl fmt::Debug for Prefetcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Prefetcher")
+            .field("used_bytes", &self.used_bytes())
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // 新しい先読み要求が来たら増やし、古い要求のデコードを途中で止める
+    generation: u64,
+    entries: Vec<Prefetched>,
+}
+
+struct Prefetched {
+    path: String,
+    stamp: Stamp,
+    image: Rgb16Image,
+}
+
+type Stamp = (u64, SystemTime);
+
+impl Prefetcher {
+    pub fn prefetch(self: &Arc<Self>, paths: Vec<String>, max_bytes: u64) -> JoinHandle<()> {
+        self.prefetch_after(&[], paths, max_bytes)
+    }
+
+    // これから合成を始めるジョブ（current）の先読みは取り出されるまで残し、その次のジョブの入力を先読みする
+    pub fn prefetch_after(
+        self: &Arc<Self>,
+        current: &[String],
+        paths: Vec<String>,
+        max_bytes: u64,
+    ) -> JoinHandle<()> {
+        let generation = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.generation += 1;
+            state
+                .entries
+                .retain(|entry| paths.contains(&entry.path) || current.contains(&entry.path));
+            state.generation
+        };
+        let prefetcher = self.clone();
+        std::thread::spawn(move || prefetcher.fill(&paths, max_bytes, generation))
+    }
+
+    fn fill(&self, paths: &[String], max_bytes: u64, generation: u64) {
+        for path in paths {
+            // 上限を超える画像は読まずに止め、残りは合成時に通常どおり読み込む
+            let Some(needed) = decoded_bytes(path) else {
+                continue;
+            };
+            {
+                let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+                if state.generation != generation {
+                    return;
+                }
+                if state.entries.iter().any(|entry| &entry.path == path) {
+                    continue;
+                }
+                if state.used_bytes() + needed > max_bytes {
+                    return;
+                }
+            }
+            // 読めない入力は合成時にあらためて読み込み、そこでエラーを報告する
+            let (Some(stamp), Ok(image)) = (stamp(path), merge::load_rgb16(path)) else {
+                continue;
+            };
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.generation != generation {
+                return;
+            }
+            state.entries.push(Prefetched {
+                path: path.clone(),
+                stamp,
+                image,
+            });
+        }
+    }
+
+    // 先読み後にファイルが更新されていれば使わない
+    pub fn take(&self, path: &str) -> Option<Rgb16Image> {
+        let entry = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let index = state.entries.iter().position(|entry| entry.path == path)?;
+            state.entries.swap_remove(index)
+        };
+        (stamp(path) == Some(entry.stamp)).then_some(entry.image)
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.generation += 1;
+        state.entries.clear();
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .used_bytes()
+    }
+}
+
+impl State {
+    fn used_bytes(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| entry.image.as_raw().len() as u64 * 2)
+            .sum()
+    }
+}
+
+// ヘッダーだけ読んで、16bit RGB にデコードしたときの大きさを見積もる
+fn decoded_bytes(path: &str) -> Option<u64> {
+    let os_path = paths::input_file(path).ok()?;
+    let (width, height) = image::image_dimensions(os_path).ok()?;
+    Some(width as u64 * height as u64 * 6)
+}
+
+fn stamp(path: &str) -> Option<Stamp> {
+    let metadata = fs::metadata(paths::input_file(path).ok()?).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    fn write_inputs(dir: &std::path::Path, count: u32) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                let path = dir.join(format!("{}.png", i));
+                let image: Rgb16Image = ImageBuffer::from_pixel(20, 10, Rgb([i as u16 * 1000; 3]));
+                image.save(&path).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prefetched_inputs_are_taken_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_inputs(dir.path(), 2);
+        let prefetcher = Arc::new(Prefetcher::default());
+
+        prefetcher.prefetch(paths.clone(), u64::MAX).join().unwrap();
+
+        assert_eq!(prefetcher.used_bytes(), 2 * 20 * 10 * 6);
+        assert_eq!(
+            prefetcher.take(&paths[1]).unwrap(),
+            merge::load_rgb16(&paths[1]).unwrap()
+        );
+        assert!(prefetcher.take(&paths[1]).is_none());
+        assert_eq!(prefetcher.used_bytes(), 20 * 10 * 6);
+    }
+
+    #[test]
+    fn keeps_the_current_job_while_prefetching_the_next() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_inputs(dir.path(), 3);
+        let prefetcher = Arc::new(Prefetcher::default());
+        prefetcher
+            .prefetch(paths[..1].to_vec(), u64::MAX)
+            .join()
+            .unwrap();
+
+        prefetcher
+            .prefetch_after(&paths[..1], paths[1..].to_vec(), u64::MAX)
+            .join()
+            .unwrap();
+
+        assert_eq!(prefetcher.used_bytes(), 3 * 20 * 10 * 6);
+        assert!(prefetcher.take(&paths[0]).is_some());
+        // current を渡さなければ、次のジョブの分だけを残す
+        prefetcher
+            .prefetch(paths[2..].to_vec(), u64::MAX)
+            .join()
+            .unwrap();
+        assert_eq!(prefetcher.used_bytes(), 20 * 10 * 6);
+        assert!(prefetcher.take(&paths[2]).is_some());
+    }
+
+    #[test]
+    fn stops_at_budget_and_ignores_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = write_inputs(dir.path(), 3);
+        let prefetcher = Arc::new(Prefetcher::default());
+
+        // 2枚分に少し足りない上限では1枚目だけ読む
+        prefetcher
+            .prefetch(paths.clone(), 2 * 20 * 10 * 6 - 1)
+            .join()
+            .unwrap();
+        assert_eq!(prefetcher.used_bytes(), 20 * 10 * 6);
+
+        std::fs::write(&paths[0], b"changed").unwrap();
+        assert!(prefetcher.take(&paths[0]).is_none());
+    }
+}
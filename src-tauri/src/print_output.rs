@@ -0,0 +1,196 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tiff::encoder::{colortype, Rational, TiffEncoder};
+use tiff::tags::ResolutionUnit;
+use ts_rs::TS;
+
+use crate::merge::Rgb16Image;
+
+const DEFAULT_DPI: u32 = 300;
+const MM_PER_INCH: f64 = 25.4;
+
+// 印刷向けの出力設定。dpi と widthMm はどちらか一方だけ指定する（どちらもなければ 300dpi）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PrintSettings {
+    pub dpi: Option<u32>,
+    // 印刷したときの横幅。画素数は変えず、この幅になる解像度を書き込む
+    pub width_mm: Option<f64>,
+    // 指定すると、簡易な CMYK 変換で総インキ量がこの値（%）を超える画素を警告する
+    pub ink_limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct PrintLayout {
+    pub dpi: u32,
+    pub width_mm: f64,
+    pub height_mm: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct InkWarning {
+    pub limit_percent: u32,
+    pub max_percent: f64,
+    // 制限を超えた画素の割合
+    pub over_fraction: f64,
+}
+
+impl PrintSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dpi.is_some() && self.width_mm.is_some() {
+            return Err("dpi と widthMm はどちらか一方だけ指定してください".to_string());
+        }
+        // JPEG の JFIF は解像度を 16bit で持つ
+        if self
+            .dpi
+            .is_some_and(|dpi| !(1..=u16::MAX as u32).contains(&dpi))
+        {
+            return Err(format!("dpi は 1〜{} で指定してください", u16::MAX));
+        }
+        if self
+            .width_mm
+            .is_some_and(|width| !(width.is_finite() && width > 0.0))
+        {
+            return Err("widthMm は 0 より大きい値で指定してください".to_string());
+        }
+        if self
+            .ink_limit
+            .is_some_and(|limit| !(100..=400).contains(&limit))
+        {
+            return Err("inkLimit は 100〜400 で指定してください".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn layout(&self, width: u32, height: u32) -> PrintLayout {
+        let dpi = match (self.dpi, self.width_mm) {
+            (Some(dpi), _) => dpi,
+            (None, Some(width_mm)) => {
+                ((width as f64 * MM_PER_INCH / width_mm).round() as u32).clamp(1, u16::MAX as u32)
+            }
+            (None, None) => DEFAULT_DPI,
+        };
+        let to_mm = |side: u32| side as f64 * MM_PER_INCH / dpi as f64;
+        PrintLayout {
+            dpi,
+            width_mm: to_mm(width),
+            height_mm: to_mm(height),
+        }
+    }
+}
+
+// sRGB の値を下色除去なしで CMYK にしたときの総インキ量（%）。
+// 実際の印刷プロファイルより多めに出るため、深いシャドウの目安として使う
+pub fn ink_coverage(rgb: [u16; 3]) -> f64 {
+    let [c, m, y] = rgb.map(|v| 1.0 - v as f64 / u16::MAX as f64);
+    let k = c.min(m).min(y);
+    (c + m + y + k) * 100.0
+}
+
+pub fn ink_warning(image: &Rgb16Image, limit: u32) -> Option<InkWarning> {
+    let mut over = 0u64;
+    let mut max_percent = 0.0f64;
+    for pixel in image.pixels() {
+        let coverage = ink_coverage(pixel.0);
+        max_percent = max_percent.max(coverage);
+        if coverage > limit as f64 {
+            over += 1;
+        }
+    }
+    (over > 0).then(|| InkWarning {
+        limit_percent: limit,
+        max_percent,
+        over_fraction: over as f64 / (image.width() as u64 * image.height() as u64) as f64,
+    })
+}
+
+// image クレートの TIFF 書き出しは解像度を指定できないため、tiff クレートで直接書く
+pub fn write_tiff(
+    image: &Rgb16Image,
+    path: &Path,
+    eight_bit: bool,
+    dpi: u32,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = TiffEncoder::new(BufWriter::new(file)).map_err(|e| e.to_string())?;
+    let (width, height) = image.dimensions();
+    let resolution = Rational { n: dpi, d: 1 };
+    if eight_bit {
+        let data: Vec<u8> = image.as_raw().iter().map(|v| (v >> 8) as u8).collect();
+        let mut tiff = encoder
+            .new_image::<colortype::RGB8>(width, height)
+            .map_err(|e| e.to_string())?;
+        tiff.resolution(ResolutionUnit::Inch, resolution);
+        tiff.write_data(&data).map_err(|e| e.to_string())
+    } else {
+        let mut tiff = encoder
+            .new_image::<colortype::RGB16>(width, height)
+            .map_err(|e| e.to_string())?;
+        tiff.resolution(ResolutionUnit::Inch, resolution);
+        tiff.write_data(image.as_raw()).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use tiff::decoder::Decoder;
+    use tiff::tags::Tag;
+
+    #[test]
+    fn layout_from_dpi_or_physical_width() {
+        let settings = PrintSettings::default();
+        let layout = settings.layout(3000, 2000);
+        assert_eq!(layout.dpi, 300);
+        assert!((layout.width_mm - 254.0).abs() < 1e-9);
+
+        let layout = PrintSettings {
+            width_mm: Some(127.0),
+            ..Default::default()
+        }
+        .layout(3000, 2000);
+        assert_eq!(layout.dpi, 600);
+        assert!((layout.height_mm - 2000.0 * 25.4 / 600.0).abs() < 1e-9);
+
+        assert!(PrintSettings {
+            dpi: Some(300),
+            width_mm: Some(100.0),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn warns_only_on_heavy_shadows() {
+        let mut image = Rgb16Image::from_pixel(10, 10, Rgb([40000, 30000, 20000]));
+        assert!(ink_warning(&image, 300).is_none());
+
+        image.put_pixel(0, 0, Rgb([0, 0, 0]));
+        let warning = ink_warning(&image, 300).unwrap();
+        assert!((warning.max_percent - 400.0).abs() < 1e-9);
+        assert!((warning.over_fraction - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tiff_records_resolution() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("print.tif");
+        let image =
+            Rgb16Image::from_fn(20, 10, |x, y| Rgb([x as u16 * 3000, y as u16 * 6000, 100]));
+
+        write_tiff(&image, &path, false, 350).unwrap();
+
+        let mut decoder = Decoder::new(File::open(&path).unwrap()).unwrap();
+        let x_resolution = decoder.get_tag_u32_vec(Tag::XResolution).unwrap();
+        assert_eq!(x_resolution, [350, 1]);
+        assert_eq!(decoder.get_tag_u32(Tag::ResolutionUnit).unwrap(), 2);
+        assert_eq!(image::open(&path).unwrap().to_rgb16(), image);
+    }
+}
@@ -0,0 +1,247 @@
+use std::path::Path;
+
+use exr::prelude::read_first_rgba_layer_from_file;
+use image::{ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{luminance, srgb_to_linear, u16_to_unit};
+use crate::decode;
+use crate::formats;
+use crate::paths;
+
+const PROBE_MAX_RADIUS: u32 = 16;
+
+pub type LinearImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeSample {
+    pub x: u32,
+    pub y: u32,
+    pub rgb: [f32; 3],
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeStats {
+    pub mean: [f32; 3],
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub luminance: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceProbe {
+    pub path: String,
+    pub center: [f32; 3],
+    pub stats: ProbeStats,
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeResult {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub center: [f32; 3],
+    pub stats: ProbeStats,
+    pub samples: Vec<ProbeSample>,
+    pub sources: Vec<SourceProbe>,
+}
+
+// EXR はそのままの値、PNG/JPEG は sRGB とみなしてリニア化した値を返す
+pub fn load_linear(path: &str) -> Result<LinearImage, String> {
+    let os_path = paths::input_file(path)?;
+    formats::ensure_decodable(&os_path)?;
+    let is_exr = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+
+    if is_exr {
+        decode::check_exr(&os_path)?;
+        let image = read_first_rgba_layer_from_file(
+            &os_path,
+            |resolution, _| LinearImage::new(resolution.width() as u32, resolution.height() as u32),
+            |pixels: &mut LinearImage, position, (r, g, b, _a): (f32, f32, f32, f32)| {
+                pixels.put_pixel(position.x() as u32, position.y() as u32, Rgb([r, g, b]));
+            },
+        )
+        .map_err(|e| format!("{} を読み込めません: {}", path, e))?;
+        return Ok(image.layer_data.channel_data.pixels);
+    }
+
+    // 合成の入力と同じく、imageLimits を超える画像はデコードしない
+    let image = decode::decode_rgb16(&os_path)?;
+    Ok(LinearImage::from_fn(
+        image.width(),
+        image.height(),
+        |x, y| {
+            Rgb(image
+                .get_pixel(x, y)
+                .0
+                .map(|v| srgb_to_linear(u16_to_unit(v))))
+        },
+    ))
+}
+
+pub fn probe(
+    path: &str,
+    x: u32,
+    y: u32,
+    radius: u32,
+    source_paths: &[String],
+) -> Result<ProbeResult, String> {
+    if radius > PROBE_MAX_RADIUS {
+        return Err(format!("半径は最大{}pxまでです", PROBE_MAX_RADIUS));
+    }
+
+    let image = load_linear(path)?;
+    if x >= image.width() || y >= image.height() {
+        return Err("指定座標が画像の範囲外です".to_string());
+    }
+
+    let samples = collect_samples(&image, x, y, radius);
+    let stats = summarize(&samples);
+    let center = image.get_pixel(x, y).0;
+
+    let mut sources = Vec::new();
+    for source_path in source_paths {
+        let source = load_linear(source_path)?;
+        if source.width() != image.width() || source.height() != image.height() {
+            return Err(format!("画像サイズが一致しません: {}", source_path));
+        }
+        let source_samples = collect_samples(&source, x, y, radius);
+        sources.push(SourceProbe {
+            path: source_path.clone(),
+            center: source.get_pixel(x, y).0,
+            stats: summarize(&source_samples),
+        });
+    }
+
+    Ok(ProbeResult {
+        path: path.to_string(),
+        width: image.width(),
+        height: image.height(),
+        center,
+        stats,
+        samples,
+        sources,
+    })
+}
+
+fn collect_samples(image: &LinearImage, x: u32, y: u32, radius: u32) -> Vec<ProbeSample> {
+    let x0 = x.saturating_sub(radius);
+    let y0 = y.saturating_sub(radius);
+    let x1 = (x + radius).min(image.width() - 1);
+    let y1 = (y + radius).min(image.height() - 1);
+
+    let mut samples = Vec::new();
+    for sy in y0..=y1 {
+        for sx in x0..=x1 {
+            samples.push(ProbeSample {
+                x: sx,
+                y: sy,
+                rgb: image.get_pixel(sx, sy).0,
+            });
+        }
+    }
+    samples
+}
+
+fn summarize(samples: &[ProbeSample]) -> ProbeStats {
+    let mut sum = [0.0f64; 3];
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+
+    for sample in samples {
+        for c in 0..3 {
+            sum[c] += sample.rgb[c] as f64;
+            min[c] = min[c].min(sample.rgb[c]);
+            max[c] = max[c].max(sample.rgb[c]);
+        }
+    }
+
+    let count = samples.len().max(1) as f64;
+    let mean = [
+        (sum[0] / count) as f32,
+        (sum[1] / count) as f32,
+        (sum[2] / count) as f32,
+    ];
+
+    ProbeStats {
+        mean,
+        min,
+        max,
+        luminance: luminance(mean),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::Rgb16Image;
+
+    fn write_png(dir: &Path, name: &str, value: u16) -> String {
+        let path = dir.join(name);
+        Rgb16Image::from_fn(8, 6, |x, y| {
+            if (x, y) == (0, 0) {
+                Rgb([u16::MAX, 0, 0])
+            } else {
+                Rgb([value, value, value])
+            }
+        })
+        .save(&path)
+        .unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn linearizes_srgb_and_clips_the_window_at_the_edges() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged = write_png(dir.path(), "merged.png", 32768);
+        let source = write_png(dir.path(), "source.png", 0);
+
+        let result = probe(&merged, 0, 0, 2, std::slice::from_ref(&source)).unwrap();
+
+        assert_eq!((result.width, result.height), (8, 6));
+        assert_eq!(result.center, [1.0, 0.0, 0.0]);
+        // 左上の角では範囲外を除いた 3x3 の画素だけを使う
+        assert_eq!(result.samples.len(), 9);
+        let gray = srgb_to_linear(u16_to_unit(32768));
+        assert!((gray - 0.2140).abs() < 1e-3, "{}", gray);
+        assert_eq!(result.stats.min, [gray, 0.0, 0.0]);
+        assert_eq!(result.stats.max, [1.0, gray, gray]);
+        assert!((result.stats.mean[1] - gray * 8.0 / 9.0).abs() < 1e-6);
+        assert_eq!(result.sources.len(), 1);
+        assert_eq!(result.sources[0].path, source);
+        assert_eq!(result.sources[0].stats.max, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn reads_exr_values_above_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("radiance.exr");
+        exr::prelude::write_rgb_file(&path, 4, 4, |x, _| (x as f32 * 2.0, 0.5, 0.25)).unwrap();
+
+        let result = probe(&path.to_string_lossy(), 3, 1, 0, &[]).unwrap();
+
+        assert_eq!(result.center, [6.0, 0.5, 0.25]);
+        assert_eq!(result.samples.len(), 1);
+        assert!(result.stats.luminance > 1.0);
+    }
+
+    #[test]
+    fn rejects_out_of_range_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged = write_png(dir.path(), "merged.png", 100);
+        let small = dir.path().join("small.png");
+        Rgb16Image::new(4, 4).save(&small).unwrap();
+
+        assert!(probe(&merged, 8, 0, 1, &[]).is_err());
+        assert!(probe(&merged, 0, 0, PROBE_MAX_RADIUS + 1, &[]).is_err());
+        assert!(probe(&merged, 0, 0, 1, &[small.to_string_lossy().to_string()]).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+use ts_rs::TS;
+
+pub const CANCELLED: &str = "合成がキャンセルされました";
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeProgress {
+    pub job_id: Option<String>,
+    // decode / merge / encodePng / encodeExr
+    pub stage: &'static str,
+    // ステージ内の進み具合（0〜1）
+    pub fraction: f64,
+}
+
+type Emit = dyn Fn(&MergeProgress) + Send + Sync;
+
+// 合成の進捗の通知先と中止要求。MergeRequest に持たせて各処理に渡す
+#[derive(Clone, Default)]
+pub struct ProgressReporter {
+    job_id: Option<String>,
+    emit: Option<Arc<Emit>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("job_id", &self.job_id)
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl ProgressReporter {
+    pub fn new(
+        job_id: Option<String>,
+        emit: impl Fn(&MergeProgress) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            job_id,
+            emit: Some(Arc::new(emit)),
+            cancelled: Arc::default(),
+        }
+    }
+
+    pub fn job_id(&self) -> Option<&str> {
+        self.job_id.as_deref()
+    }
+
+    // 複製した ProgressReporter とも共有され、実行中の処理が次の確認で中止する
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, stage: &'static str, fraction: f64) {
+        if let Some(emit) = &self.emit {
+            emit(&MergeProgress {
+                job_id: self.job_id.clone(),
+                stage,
+                fraction: fraction.clamp(0.0, 1.0),
+            });
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn check_cancelled(&self) -> Result<(), String> {
+        if self.is_cancelled() {
+            Err(CANCELLED.to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::config::Preset;
+use crate::grouping::GroupingRules;
+use crate::history::HistoryStore;
+
+const PROJECT_FILE: &str = "project.json";
+const HISTORY_FILE: &str = "history.json";
+
+// 撮影ごとに監視フォルダ・出力先・プリセット・履歴をまとめる単位
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    pub watch_folder: Option<String>,
+    pub output_dir: Option<String>,
+    #[serde(default)]
+    pub presets: Vec<Preset>,
+    // 未指定なら設定のグループ分け規則を使う
+    #[serde(default)]
+    pub grouping: Option<GroupingRules>,
+    pub created_at: String,
+}
+
+pub struct Workspace {
+    root: PathBuf,
+    active: Mutex<Option<Project>>,
+    history: Mutex<HistoryStore>,
+}
+
+impl Workspace {
+    // プロジェクト未選択時の履歴は root 直下に置く
+    pub fn new(root: PathBuf) -> Self {
+        let history = HistoryStore::load(root.join(HISTORY_FILE));
+        Self {
+            root,
+            active: Mutex::new(None),
+            history: Mutex::new(history),
+        }
+    }
+
+    fn projects_dir(&self) -> PathBuf {
+        self.root.join("projects")
+    }
+
+    fn project_dir(&self, id: &str) -> Result<PathBuf, String> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err("プロジェクト ID が不正です".to_string());
+        }
+        Ok(self.projects_dir().join(id))
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        watch_folder: Option<String>,
+        output_dir: Option<String>,
+    ) -> Result<Project, String> {
+        if name.trim().is_empty() {
+            return Err("プロジェクト名が空です".to_string());
+        }
+        let now = Local::now();
+        let mut id = now.format("%Y%m%d_%H%M%S_%3f").to_string();
+        let mut suffix = 1;
+        while self.project_dir(&id)?.exists() {
+            suffix += 1;
+            id = format!("{}_{}", now.format("%Y%m%d_%H%M%S_%3f"), suffix);
+        }
+
+        let project = Project {
+            id,
+            name: name.trim().to_string(),
+            watch_folder,
+            output_dir,
+            presets: Vec::new(),
+            grouping: None,
+            created_at: now.to_rfc3339(),
+        };
+        save_project(&self.project_dir(&project.id)?, &project)?;
+        Ok(project)
+    }
+
+    pub fn list(&self) -> Result<Vec<Project>, String> {
+        let entries = match fs::read_dir(self.projects_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let mut projects: Vec<Project> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| load_project(&entry.path()).ok())
+            .collect();
+        projects.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(projects)
+    }
+
+    pub fn open(&self, id: &str) -> Result<Project, String> {
+        let dir = self.project_dir(id)?;
+        let project = load_project(&dir)?;
+        self.switch(Some(project.clone()), dir.join(HISTORY_FILE))?;
+        Ok(project)
+    }
+
+    pub fn close(&self) -> Result<(), String> {
+        self.switch(None, self.root.join(HISTORY_FILE))
+    }
+
+    fn switch(&self, project: Option<Project>, history_path: PathBuf) -> Result<(), String> {
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        *history = HistoryStore::load(history_path);
+        *active = project;
+        Ok(())
+    }
+
+    pub fn active(&self) -> Result<Option<Project>, String> {
+        let active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(active.clone())
+    }
+
+    // 開いているプロジェクトを変更して保存する。プロジェクト未選択なら None を返す
+    pub fn update_active<T>(
+        &self,
+        apply: impl FnOnce(&mut Project) -> Result<T, String>,
+    ) -> Result<Option<T>, String> {
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        let project = match active.as_mut() {
+            Some(project) => project,
+            None => return Ok(None),
+        };
+        let mut next = project.clone();
+        let value = apply(&mut next)?;
+        save_project(&self.project_dir(&next.id)?, &next)?;
+        *project = next;
+        Ok(Some(value))
+    }
+
+    pub fn with_history<T>(&self, apply: impl FnOnce(&mut HistoryStore) -> T) -> Result<T, String> {
+        let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(apply(&mut history))
+    }
+}
+
+fn load_project(dir: &Path) -> Result<Project, String> {
+    let text = fs::read_to_string(dir.join(PROJECT_FILE))
+        .map_err(|e| format!("プロジェクトを読み込めません: {}", e))?;
+    serde_json::from_str(&text).map_err(|e| format!("プロジェクトの形式が不正です: {}", e))
+}
+
+fn save_project(dir: &Path, project: &Project) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let text = serde_json::to_string_pretty(project).map_err(|e| e.to_string())?;
+    fs::write(dir.join(PROJECT_FILE), text)
+        .map_err(|e| format!("プロジェクトを保存できません: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{MergeRequest, MergeResult};
+
+    fn result(png: &str) -> MergeResult {
+        MergeResult {
+            output_png_path: png.to_string(),
+            output_exr_path: None,
+            output_layered_exr_path: None,
+            transforms_path: None,
+            output_short_reference_path: None,
+            output_preview_path: None,
+            output_dng_path: None,
+            sent_to: Vec::new(),
+            width: 4,
+            height: 4,
+            merged_at: Local::now().to_rfc3339(),
+            algorithm: "average".to_string(),
+            stages: Vec::new(),
+            alignment_offsets: Vec::new(),
+            alignment_transforms: Vec::new(),
+            straighten_angle: None,
+            skipped_frames: Vec::new(),
+            exposure_order: Vec::new(),
+            excluded_inputs: Vec::new(),
+            clipping: None,
+            duration_ms: 0,
+            memory_fallback: None,
+            deliverables: Vec::new(),
+            frame_quality: Vec::new(),
+            gray_card: None,
+            auto_levels: None,
+            exposure_compensation: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn projects_keep_separate_histories() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = Workspace::new(dir.path().to_path_buf());
+        let wedding = workspace.create("wedding", None, None).unwrap();
+        let request = MergeRequest::default();
+
+        workspace
+            .with_history(|history| history.record(&request, &result("global.png")))
+            .unwrap()
+            .unwrap();
+        workspace.open(&wedding.id).unwrap();
+        workspace
+            .with_history(|history| history.record(&request, &result("wedding.png")))
+            .unwrap()
+            .unwrap();
+
+        let entries = workspace
+            .with_history(|history| history.entries().to_vec())
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].output_png_path, "wedding.png");
+
+        workspace.close().unwrap();
+        let entries = workspace
+            .with_history(|history| history.entries().to_vec())
+            .unwrap();
+        assert_eq!(entries[0].output_png_path, "global.png");
+        assert_eq!(workspace.list().unwrap(), vec![wedding]);
+    }
+
+    #[test]
+    fn rejects_path_like_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let workspace = Workspace::new(dir.path().to_path_buf());
+
+        assert!(workspace.open("../outside").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::color::{srgb_to_linear, u16_to_unit, unit_to_u16};
+use crate::frame_select;
+use crate::merge::Rgb16Image;
+
+// 露出の比を求めるときに、両方のフレームで白飛び・黒つぶれしていないとみなす範囲（sRGB の 0〜1）
+const WELL_EXPOSED: std::ops::RangeInclusive<f32> = 0.05..=0.9;
+const RATIO_SAMPLE_STEP: usize = 4;
+
+// トーンマップ前の線形の放射輝度。最も明るい値が 1 になるよう縮めた 16bit と、
+// 基準フレームの明るさに戻すための EV（DNG の BaselineExposure）
+pub struct Radiance {
+    pub image: Rgb16Image,
+    pub baseline_exposure: f64,
+}
+
+// 位置合わせ後のフレームを線形化し、中央のフレームを基準にした露出の比で割ってから重み付き平均する。
+// 重みは白飛び・黒つぶれに近いほど小さく、どのフレームでも使えない画素は暗い側・明るい側のフレームで埋める。
+// 全画面の f32 バッファを持たないよう、1 周目で最大値だけを求め、2 周目で計算し直して 16bit に詰める
+pub fn merge(frames: &[Rgb16Image]) -> Option<Radiance> {
+    let reference = frames.get(frames.len() / 2)?;
+    let (width, height) = reference.dimensions();
+    let exposures: Vec<f32> = frames
+        .iter()
+        .map(|frame| relative_exposure(frame, reference) as f32)
+        .collect();
+    let fallbacks = (
+        index_of(&exposures, |a, b| a < b),
+        index_of(&exposures, |a, b| a > b),
+    );
+    let pixels = width as usize * height as usize;
+
+    let peak = (0..pixels)
+        .flat_map(|i| pixel_radiance(frames, &exposures, fallbacks, i))
+        .fold(1.0f32, f32::max);
+    let mut raw = Vec::with_capacity(pixels * 3);
+    for i in 0..pixels {
+        let value = pixel_radiance(frames, &exposures, fallbacks, i);
+        raw.extend(value.iter().map(|value| unit_to_u16(value / peak)));
+    }
+    Some(Radiance {
+        image: Rgb16Image::from_raw(width, height, raw)?,
+        baseline_exposure: (peak as f64).log2(),
+    })
+}
+
+// i 番目の画素の線形の放射輝度。fallbacks は（最も暗いフレーム, 最も明るいフレーム）
+fn pixel_radiance(
+    frames: &[Rgb16Image],
+    exposures: &[f32],
+    (darkest, brightest): (usize, usize),
+    i: usize,
+) -> [f32; 3] {
+    let mut sum = [0.0f32; 3];
+    let mut total = 0.0f32;
+    let mut peak = 0.0f32;
+    for (frame, exposure) in frames.iter().zip(exposures) {
+        let pixel = &frame.as_raw()[i * 3..i * 3 + 3];
+        let brightest_channel = pixel.iter().copied().max().unwrap_or(0);
+        peak = peak.max(u16_to_unit(brightest_channel));
+        let weight = weight(u16_to_unit(brightest_channel));
+        for (sum, value) in sum.iter_mut().zip(pixel) {
+            *sum += weight * srgb_to_linear(u16_to_unit(*value)) / exposure;
+        }
+        total += weight;
+    }
+    if total > f32::EPSILON {
+        return sum.map(|sum| sum / total);
+    }
+    let fallback = if peak > 0.5 { darkest } else { brightest };
+    let pixel = &frames[fallback].as_raw()[i * 3..i * 3 + 3];
+    [0, 1, 2].map(|c| srgb_to_linear(u16_to_unit(pixel[c])) / exposures[fallback])
+}
+
+// 白飛び・黒つぶれの両端で 0 になる山形の重み
+fn weight(value: f32) -> f32 {
+    value.min(1.0 - value).max(0.0)
+}
+
+fn index_of(values: &[f32], better: impl Fn(f32, f32) -> bool) -> usize {
+    let mut best = 0;
+    for (i, value) in values.iter().enumerate() {
+        if better(*value, values[best]) {
+            best = i;
+        }
+    }
+    best
+}
+
+// reference に対する frame の露出の比（2 なら 1 段明るい）。
+// 両方で適正に写っている画素の線形の和から求め、そのような画素がなければ平均輝度の EV の差を使う
+fn relative_exposure(frame: &Rgb16Image, reference: &Rgb16Image) -> f64 {
+    let mut frame_sum = 0.0f64;
+    let mut reference_sum = 0.0f64;
+    for (a, b) in frame
+        .as_raw()
+        .chunks_exact(3)
+        .zip(reference.as_raw().chunks_exact(3))
+        .step_by(RATIO_SAMPLE_STEP)
+    {
+        for (a, b) in a.iter().zip(b) {
+            let (a, b) = (u16_to_unit(*a), u16_to_unit(*b));
+            if WELL_EXPOSED.contains(&a) && WELL_EXPOSED.contains(&b) {
+                frame_sum += srgb_to_linear(a) as f64;
+                reference_sum += srgb_to_linear(b) as f64;
+            }
+        }
+    }
+    if frame_sum > 0.0 && reference_sum > 0.0 {
+        frame_sum / reference_sum
+    } else {
+        (frame_select::estimate_ev(frame) - frame_select::estimate_ev(reference)).exp2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::linear_to_srgb;
+    use image::Rgb;
+
+    // 線形の放射輝度 scene を exposure 倍して sRGB で写し、1 を超えた分は白飛びさせる
+    fn shoot(scene: &[f32], exposure: f32) -> Rgb16Image {
+        Rgb16Image::from_fn(scene.len() as u32, 1, |x, _| {
+            Rgb([unit_to_u16(linear_to_srgb(scene[x as usize] * exposure)); 3])
+        })
+    }
+
+    #[test]
+    fn recovers_highlights_beyond_the_reference_frame() {
+        let scene: Vec<f32> = (0..64).map(|i| 0.01 * 1.1f32.powi(i)).collect();
+        let frames = [shoot(&scene, 0.25), shoot(&scene, 1.0), shoot(&scene, 4.0)];
+
+        let radiance = merge(&frames).unwrap();
+
+        // 基準フレームで白飛びする明るさまで、線形のまま残る
+        let peak = scene.iter().copied().fold(0.0, f32::max);
+        assert!(radiance.baseline_exposure > 1.9);
+        assert!((radiance.baseline_exposure - (peak as f64).log2()).abs() < 0.1);
+        let restore = (radiance.baseline_exposure as f32).exp2();
+        for (x, expected) in scene.iter().enumerate() {
+            let value = u16_to_unit(radiance.image.get_pixel(x as u32, 0).0[0]) * restore;
+            assert!(
+                (value - expected).abs() < expected * 0.05 + 0.002,
+                "{} {}",
+                value,
+                expected
+            );
+        }
+        assert!(merge(&[]).is_none());
+    }
+}
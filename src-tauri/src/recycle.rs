@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::output_path;
+use crate::paths;
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteOutcome {
+    pub path: String,
+    pub deleted: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteReport {
+    pub results: Vec<DeleteOutcome>,
+    pub deleted_count: usize,
+    pub failed_count: usize,
+}
+
+// 完全には削除せず、OS のごみ箱（Windows ではリサイクルビン）へ移す
+pub fn delete_to_recycle(paths: &[String], protected: &[PathBuf]) -> DeleteReport {
+    delete_with(paths, protected, |path| {
+        trash::delete(path).map_err(|e| format!("ごみ箱へ移動できません: {}", e))
+    })
+}
+
+// 1件の失敗で止めず、ファイルごとの結果を返す
+fn delete_with(
+    paths: &[String],
+    protected: &[PathBuf],
+    remove: impl Fn(&Path) -> Result<(), String>,
+) -> DeleteReport {
+    let results: Vec<DeleteOutcome> = paths
+        .iter()
+        .map(|path| {
+            let result = paths::input_file(path)
+                .map_err(String::from)
+                .and_then(
+                    |os_path| match output_path::protected_root(&os_path, protected) {
+                        Some(root) => Err(format!(
+                            "監視フォルダ {} 内のファイルは削除できません",
+                            root.to_string_lossy()
+                        )),
+                        None => remove(&os_path),
+                    },
+                );
+            DeleteOutcome {
+                path: path.clone(),
+                deleted: result.is_ok(),
+                error: result.err(),
+            }
+        })
+        .collect();
+    let deleted_count = results.iter().filter(|outcome| outcome.deleted).count();
+    DeleteReport {
+        failed_count: results.len() - deleted_count,
+        deleted_count,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_each_file_and_respects_protected_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let watch = dir.path().join("watch");
+        std::fs::create_dir(&watch).unwrap();
+        let source = dir.path().join("a.png");
+        let watched = watch.join("b.png");
+        std::fs::write(&source, b"").unwrap();
+        std::fs::write(&watched, b"").unwrap();
+        let paths: Vec<String> = [&source, &watched, &dir.path().join("missing.png")]
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+
+        let report = delete_with(&paths, &[watch], |path| {
+            std::fs::remove_file(path).map_err(|e| e.to_string())
+        });
+
+        assert_eq!((report.deleted_count, report.failed_count), (1, 2));
+        assert!(report.results[0].deleted);
+        assert!(!source.exists());
+        assert!(watched.exists());
+        assert!(report.results[2].error.is_some());
+    }
+}
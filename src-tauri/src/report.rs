@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::history::HistoryEntry;
+
+// Excel で開いたときに UTF-8 として読まれるよう CSV の先頭に付ける
+const UTF8_BOM: &str = "\u{FEFF}";
+const CSV_HEADER: &[&str] = &[
+    "id",
+    "mergedAt",
+    "durationMs",
+    "algorithm",
+    "algorithmParams",
+    "stages",
+    "width",
+    "height",
+    "rating",
+    "tags",
+    "inputCount",
+    "inputs",
+    "outputPng",
+    "outputExr",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+pub fn export_history(
+    entries: &[&HistoryEntry],
+    format: ReportFormat,
+    path: &Path,
+) -> Result<usize, String> {
+    let text = match format {
+        ReportFormat::Csv => to_csv(entries)?,
+        ReportFormat::Json => serde_json::to_string_pretty(entries).map_err(|e| e.to_string())?,
+    };
+    fs::write(path, text).map_err(|e| format!("レポートを書き出せません: {}", e))?;
+    Ok(entries.len())
+}
+
+fn to_csv(entries: &[&HistoryEntry]) -> Result<String, String> {
+    let mut lines = vec![CSV_HEADER.join(",")];
+    for entry in entries {
+        let params = serde_json::to_string(&entry.algorithm_params).map_err(|e| e.to_string())?;
+        let fields = [
+            entry.id.to_string(),
+            entry.merged_at.clone(),
+            entry
+                .duration_ms
+                .map(|ms| ms.to_string())
+                .unwrap_or_default(),
+            entry.algorithm.clone(),
+            params,
+            entry.stages.join(" > "),
+            entry.width.to_string(),
+            entry.height.to_string(),
+            entry.rating.to_string(),
+            entry.tags.join(";"),
+            entry.input_paths.len().to_string(),
+            entry.input_paths.join(";"),
+            entry.output_png_path.clone(),
+            entry.output_exr_path.clone().unwrap_or_default(),
+        ];
+        let escaped: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+        lines.push(escaped.join(","));
+    }
+    Ok(format!("{}{}\r\n", UTF8_BOM, lines.join("\r\n")))
+}
+
+// RFC 4180: 区切り・引用符・改行を含む値は引用符で囲み、引用符は二重にする
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> HistoryEntry {
+        let mut algorithm_params = serde_json::Map::new();
+        algorithm_params.insert("contrastWeight".to_string(), serde_json::json!(1.0));
+        HistoryEntry {
+            id: 7,
+            merged_at: "2024-03-01T10:00:00+09:00".to_string(),
+            input_paths: vec!["a.jpg".to_string(), "b,c.jpg".to_string()],
+            output_png_path: "out.png".to_string(),
+            output_exr_path: None,
+            algorithm: "fusion".to_string(),
+            algorithm_params,
+            stages: vec!["merge".to_string(), "encode".to_string()],
+            duration_ms: Some(1234),
+            width: 4,
+            height: 3,
+            rating: 5,
+            tags: vec!["keeper".to_string()],
+            preset: None,
+            stale: false,
+            request: None,
+            output_paths: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn writes_escaped_csv_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.csv");
+        let entry = entry();
+
+        assert_eq!(
+            export_history(&[&entry], ReportFormat::Csv, &path).unwrap(),
+            1
+        );
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.trim_start_matches(UTF8_BOM).lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            "7,2024-03-01T10:00:00+09:00,1234,fusion,\"{\"\"contrastWeight\"\":1.0}\",merge > encode,4,3,5,keeper,2,\"a.jpg;b,c.jpg\",out.png,"
+        );
+    }
+
+    #[test]
+    fn writes_json_array() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let entry = entry();
+
+        export_history(&[&entry], ReportFormat::Json, &path).unwrap();
+
+        let parsed: Vec<HistoryEntry> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(vec![entry]).unwrap()
+        );
+    }
+}
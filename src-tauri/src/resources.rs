@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::disk_cache::CacheUsage;
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUsage {
+    // 取得できない OS では null
+    pub rss_bytes: Option<u64>,
+    pub caches: Vec<CacheUsage>,
+    // 先読み済みで合成を待っている入力のデコード後の大きさ
+    pub prefetch_bytes: u64,
+    // 実行中の合成ジョブの数。0 の通知はすべてのジョブが終わったことを表す
+    pub queue_depth: usize,
+}
+
+// ジョブの実行中だけ使用量を定期的に通知する。同時に複数のジョブがあっても通知は1本にまとめる
+#[derive(Default)]
+pub struct ResourceMonitor {
+    running: AtomicBool,
+}
+
+impl ResourceMonitor {
+    // すでに通知中なら false を返す
+    pub fn try_start(&self) -> bool {
+        !self.running.swap(true, Ordering::SeqCst)
+    }
+
+    // ジョブがなくなるまで interval ごとに emit を呼び、最後に queue_depth 0 で1回呼ぶ
+    pub fn run(
+        &self,
+        interval: Duration,
+        queue_depth: impl Fn() -> usize,
+        mut emit: impl FnMut(usize),
+    ) {
+        loop {
+            let depth = queue_depth();
+            if depth == 0 {
+                self.running.store(false, Ordering::SeqCst);
+                // 止めた直後に始まったジョブの通知を取りこぼさない
+                if queue_depth() == 0 || !self.try_start() {
+                    emit(0);
+                    return;
+                }
+                continue;
+            }
+            emit(depth);
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+pub fn rss_bytes() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        parse_vm_rss(&std::fs::read_to_string("/proc/self/status").ok()?)
+    }
+    #[cfg(windows)]
+    {
+        windows::working_set()
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        None
+    }
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ProcessMemoryCounters {
+        cb: u32,
+        page_fault_count: u32,
+        peak_working_set_size: usize,
+        working_set_size: usize,
+        quota_peak_paged_pool_usage: usize,
+        quota_paged_pool_usage: usize,
+        quota_peak_non_paged_pool_usage: usize,
+        quota_non_paged_pool_usage: usize,
+        pagefile_usage: usize,
+        peak_pagefile_usage: usize,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn K32GetProcessMemoryInfo(
+            process: *mut c_void,
+            counters: *mut ProcessMemoryCounters,
+            size: u32,
+        ) -> i32;
+    }
+
+    pub fn working_set() -> Option<u64> {
+        let size = std::mem::size_of::<ProcessMemoryCounters>() as u32;
+        let mut counters = ProcessMemoryCounters {
+            cb: size,
+            ..Default::default()
+        };
+        // SAFETY: 自プロセスの疑似ハンドルと、大きさを設定した構造体を渡している
+        let ok = unsafe { K32GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, size) };
+        (ok != 0).then_some(counters.working_set_size as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn reports_until_jobs_finish_then_allows_restart() {
+        let monitor = ResourceMonitor::default();
+        let remaining = Cell::new(3usize);
+        let mut emitted = Vec::new();
+
+        assert!(monitor.try_start());
+        assert!(!monitor.try_start());
+        monitor.run(
+            Duration::ZERO,
+            || remaining.get(),
+            |depth| {
+                emitted.push(depth);
+                remaining.set(depth.saturating_sub(1));
+            },
+        );
+
+        assert_eq!(emitted, [3, 2, 1, 0]);
+        assert!(monitor.try_start());
+    }
+
+    #[test]
+    fn parses_resident_size_from_proc_status() {
+        let status = "Name:\thdr\nVmPeak:\t  9000 kB\nVmRSS:\t  1234 kB\n";
+        assert_eq!(parse_vm_rss(status), Some(1234 * 1024));
+        assert_eq!(parse_vm_rss("Name:\thdr\n"), None);
+    }
+}
@@ -0,0 +1,308 @@
+use std::path::PathBuf;
+
+use chrono::Local;
+use image::imageops::{self, FilterType};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::encode;
+use crate::merge::Rgb16Image;
+use crate::output_lock::OutputReservation;
+use crate::output_path::{self, TemplateContext};
+use crate::paths;
+use crate::progress::ProgressReporter;
+use crate::watch_filter::OwnOutputs;
+use crate::workdir;
+
+const DEFAULT_FILE_NAME: &str = "{groupName}";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum SendFormat {
+    #[default]
+    Exr,
+    Png,
+}
+
+impl SendFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            SendFormat::Exr => "exr",
+            SendFormat::Png => "png",
+        }
+    }
+}
+
+// 合成結果を Blender・Unity などのプロジェクトのフォルダへ、そのプロジェクトの命名規則で書き出す送り先
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SendTarget {
+    // プリセットの sendTo と history_send_to で指定する名前
+    pub name: String,
+    // 例: `D:/Game/Assets/HDRIs`
+    pub folder: String,
+    // 拡張子を除いたファイル名。出力先テンプレートと同じ項目（{groupName}・{yyyy} など）が使え、/ でフォルダも作れる
+    pub file_name: String,
+    pub format: SendFormat,
+    // 長辺の上限。超える場合は縮小する
+    pub max_size: Option<u32>,
+    // 幅・高さをそれぞれ以下の2のべき乗に縮小する（Unity のテクスチャ向け）
+    pub power_of_two: bool,
+    // 正距円筒の環境マップとして、高さを幅の半分にそろえる
+    pub latlong: bool,
+    // 同じ名前のファイルがあれば上書きする。false なら _2, _3 … を付ける
+    pub overwrite: bool,
+}
+
+impl Default for SendTarget {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            folder: String::new(),
+            file_name: DEFAULT_FILE_NAME.to_string(),
+            format: SendFormat::default(),
+            max_size: None,
+            power_of_two: false,
+            latlong: false,
+            overwrite: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct SentOutput {
+    pub target: String,
+    pub path: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    // プリセットで自動的に送ったときの失敗。合成結果は残したまま、ここに記録する
+    pub error: Option<String>,
+}
+
+// 送り先のファイル名の展開と、書き込み先の制限に使う値
+pub struct SendContext<'a> {
+    pub group_name: &'a str,
+    pub algorithm: &'a str,
+    pub protected_dirs: &'a [PathBuf],
+    pub write_roots: &'a [PathBuf],
+    pub own_outputs: Option<&'a OwnOutputs>,
+}
+
+// 名前で送り先を選ぶ。見つからない名前があればエラー
+pub fn find_targets(targets: &[SendTarget], names: &[String]) -> Result<Vec<SendTarget>, String> {
+    names
+        .iter()
+        .map(|name| {
+            targets
+                .iter()
+                .find(|target| target.name == *name)
+                .cloned()
+                .ok_or_else(|| format!("送り先が見つかりません: {}", name))
+        })
+        .collect()
+}
+
+pub fn target_size(width: u32, height: u32, target: &SendTarget) -> (u32, u32) {
+    let mut size = (
+        width as f64,
+        if target.latlong {
+            width as f64 / 2.0
+        } else {
+            height as f64
+        },
+    );
+    if let Some(max_size) = target.max_size {
+        let scale = (max_size as f64 / size.0.max(size.1)).min(1.0);
+        size = (size.0 * scale, size.1 * scale);
+    }
+    let (width, height) = (
+        (size.0.round() as u32).max(1),
+        (size.1.round() as u32).max(1),
+    );
+    if !target.power_of_two {
+        return (width, height);
+    }
+    let floor_power = |side: u32| 1 << (31 - side.leading_zeros());
+    let width = floor_power(width);
+    match target.latlong {
+        true => (width, (width / 2).max(1)),
+        false => (width, floor_power(height)),
+    }
+}
+
+pub fn send(
+    image: &Rgb16Image,
+    target: &SendTarget,
+    context: &SendContext,
+) -> Result<SentOutput, String> {
+    if target.folder.trim().is_empty() {
+        return Err(format!(
+            "送り先 {} のフォルダが指定されていません",
+            target.name
+        ));
+    }
+    if target.max_size == Some(0) {
+        return Err("送り先の maxSize は1以上にしてください".to_string());
+    }
+    let file_name = if target.file_name.trim().is_empty() {
+        DEFAULT_FILE_NAME
+    } else {
+        target.file_name.as_str()
+    };
+    let folder = PathBuf::from(&target.folder);
+    let resolved = output_path::resolve_template(
+        &format!("{{outputRoot}}/{}", file_name),
+        &TemplateContext {
+            output_root: &folder,
+            now: Local::now(),
+            group_name: context.group_name,
+            algorithm: context.algorithm,
+        },
+    )?;
+    let dir = resolved
+        .parent()
+        .ok_or("送り先の決定に失敗しました")?
+        .to_path_buf();
+    let stem = resolved
+        .file_name()
+        .ok_or("送り先のファイル名が空です")?
+        .to_string_lossy()
+        .to_string();
+    output_path::ensure_outside_protected(&dir, context.protected_dirs)?;
+    paths::ensure_writable(&dir, context.write_roots)?;
+    std::fs::create_dir_all(paths::extended(&dir))
+        .map_err(|e| format!("送り先のフォルダを作成できません: {}", e))?;
+
+    let extension = target.format.extension();
+    let reservation = OutputReservation::reserve(&dir, &stem, target.overwrite, |name| {
+        vec![format!("{}.{}", name, extension)]
+    })?;
+    let path = dir.join(format!("{}.{}", reservation.name(), extension));
+    let (width, height) = target_size(image.width(), image.height(), target);
+    let resized = (image.dimensions() != (width, height))
+        .then(|| imageops::resize(image, width, height, FilterType::Lanczos3));
+    let output = resized.as_ref().unwrap_or(image);
+
+    if let Some(own_outputs) = context.own_outputs {
+        own_outputs.record(&path);
+    }
+    let progress = ProgressReporter::default();
+    let os_path = paths::extended(&path);
+    let partial = workdir::partial_path(None, &os_path);
+    let written = match target.format {
+        SendFormat::Exr => encode::write_exr(output, &partial, false, &progress),
+        SendFormat::Png => encode::write_png(output, &partial, &progress),
+    };
+    written
+        .and_then(|_| workdir::move_into_place(&partial, &os_path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&partial);
+        })?;
+    Ok(SentOutput {
+        target: target.name.clone(),
+        path: Some(path.to_string_lossy().to_string()),
+        width,
+        height,
+        error: None,
+    })
+}
+
+// プリセットの送り先へ順に送る。失敗した送り先は error に記録して残りを続ける。
+// EXR の送り先には線形のまま、PNG の送り先にはレベル補正後の sdr を送る
+pub fn send_all(
+    image: &Rgb16Image,
+    sdr: &Rgb16Image,
+    targets: &[SendTarget],
+    context: &SendContext,
+) -> Vec<SentOutput> {
+    targets
+        .iter()
+        .map(|target| {
+            let source = match target.format {
+                SendFormat::Exr => image,
+                SendFormat::Png => sdr,
+            };
+            send(source, target, context).unwrap_or_else(|e| SentOutput {
+                target: target.name.clone(),
+                path: None,
+                width: 0,
+                height: 0,
+                error: Some(e),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use std::path::Path;
+
+    fn context<'a>(protected: &'a [PathBuf]) -> SendContext<'a> {
+        SendContext {
+            group_name: "IMG_0001",
+            algorithm: "fusion",
+            protected_dirs: protected,
+            write_roots: &[],
+            own_outputs: None,
+        }
+    }
+
+    #[test]
+    fn sizes_latlong_maps_to_powers_of_two() {
+        let latlong = SendTarget {
+            latlong: true,
+            power_of_two: true,
+            max_size: Some(2048),
+            ..Default::default()
+        };
+        assert_eq!(target_size(6000, 4000, &latlong), (2048, 1024));
+        assert_eq!(target_size(1500, 1000, &latlong), (1024, 512));
+        let plain = SendTarget {
+            max_size: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(target_size(3000, 2000, &plain), (1000, 667));
+        assert_eq!(target_size(640, 426, &SendTarget::default()), (640, 426));
+    }
+
+    #[test]
+    fn writes_with_the_project_naming_scheme() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = SendTarget {
+            name: "unity".to_string(),
+            folder: dir.path().join("Assets").to_string_lossy().to_string(),
+            file_name: "HDRIs/hdri_{groupName}".to_string(),
+            latlong: true,
+            power_of_two: true,
+            ..Default::default()
+        };
+        let image = Rgb16Image::from_pixel(300, 200, Rgb([30000; 3]));
+
+        let sent = send(&image, &target, &context(&[])).unwrap();
+        let again = send(&image, &target, &context(&[])).unwrap();
+
+        let path = sent.path.unwrap();
+        assert!(path.ends_with("hdri_IMG_0001.exr"));
+        assert!(Path::new(&path).parent().unwrap().ends_with("Assets/HDRIs"));
+        assert_eq!((sent.width, sent.height), (256, 128));
+        assert!(again.path.unwrap().ends_with("hdri_IMG_0001_2.exr"));
+        let overwrite = SendTarget {
+            overwrite: true,
+            ..target.clone()
+        };
+        assert_eq!(
+            send(&image, &overwrite, &context(&[]))
+                .unwrap()
+                .path
+                .unwrap(),
+            path
+        );
+
+        let protected = [dir.path().to_path_buf()];
+        let failed = send_all(&image, &image, &[target], &context(&protected));
+        assert!(failed[0].error.is_some() && failed[0].path.is_none());
+    }
+}
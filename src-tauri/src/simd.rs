@@ -0,0 +1,466 @@
+// 画素ごとの内側ループの SIMD 実装。AVX2 が使える CPU では実行時に切り替え、それ以外はスカラー実装を使う。
+// どちらの実装でも結果が一致するよう（和の順序で丸めが変わる luma_sum を除く）に揃えている
+
+const LUMA_WEIGHTS: [f64; 3] = [0.2126, 0.7152, 0.0722];
+
+pub fn avx2_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+// RGB を交互に並べた 16bit 画素列の、0〜1 に正規化した輝度の総和
+pub fn luma_sum(rgb: &[u16]) -> f64 {
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        // SAFETY: AVX2 と FMA が使えることを直前に確認している
+        return unsafe { avx2::luma_sum(rgb) };
+    }
+    luma_sum_scalar(rgb)
+}
+
+fn luma_sum_scalar(rgb: &[u16]) -> f64 {
+    rgb.chunks_exact(3)
+        .map(|pixel| {
+            pixel
+                .iter()
+                .zip(LUMA_WEIGHTS)
+                .map(|(value, weight)| *value as f64 / u16::MAX as f64 * weight)
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+// 同じ長さの 16bit 画素列を要素ごとに平均する（端数は切り捨て）。
+// ベクトル実装は範囲を確かめずに読むため、1 枚以上あり、どれも output より短くないことを先に確かめる
+pub fn average_u16(frames: &[&[u16]], output: &mut [u16]) {
+    assert!(!frames.is_empty(), "平均するフレームがありません");
+    assert!(
+        frames.iter().all(|frame| frame.len() >= output.len()),
+        "フレームの長さが出力より短くなっています"
+    );
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        // SAFETY: AVX2 と FMA が使えることを直前に確認している
+        unsafe { avx2::average_u16(frames, output) };
+        return;
+    }
+    average_u16_scalar(frames, output, 0);
+}
+
+fn average_u16_scalar(frames: &[&[u16]], output: &mut [u16], start: usize) {
+    let count = frames.len() as u32;
+    for (i, value) in output.iter_mut().enumerate().skip(start) {
+        let sum: u32 = frames.iter().map(|frame| frame[i] as u32).sum();
+        *value = (sum / count) as u16;
+    }
+}
+
+// 平面ごとの RGB から輝度の平面を作る
+pub fn luma_planes(r: &[f32], g: &[f32], b: &[f32]) -> Vec<f32> {
+    assert!(
+        g.len() >= r.len() && b.len() >= r.len(),
+        "RGB の平面の長さがそろっていません"
+    );
+    let mut output = vec![0.0; r.len()];
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        // SAFETY: AVX2 と FMA が使えることを直前に確認している
+        unsafe { avx2::luma_planes(r, g, b, &mut output) };
+        return output;
+    }
+    luma_planes_scalar(r, g, b, &mut output, 0);
+    output
+}
+
+fn luma_planes_scalar(r: &[f32], g: &[f32], b: &[f32], output: &mut [f32], start: usize) {
+    for i in start..output.len() {
+        output[i] = 0.2126 * r[i] + 0.7152 * g[i] + 0.0722 * b[i];
+    }
+}
+
+// 露光融合の重みを画素ごとに合計 1 にそろえる。合計が 0 の画素は等分にする
+pub fn normalize_weights(weights: &mut [&mut [f32]]) {
+    assert!(!weights.is_empty(), "正規化する重みがありません");
+    let len = weights[0].len();
+    assert!(
+        weights.iter().all(|weight| weight.len() == len),
+        "重みの平面の長さがそろっていません"
+    );
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        // SAFETY: AVX2 と FMA が使えることを直前に確認している
+        unsafe { avx2::normalize_weights(weights) };
+        return;
+    }
+    normalize_weights_scalar(weights, 0);
+}
+
+fn normalize_weights_scalar(weights: &mut [&mut [f32]], start: usize) {
+    let even = 1.0 / weights.len() as f32;
+    for i in start..weights[0].len() {
+        let total = weights
+            .iter()
+            .fold(0.0f32, |total, weight| total + weight[i]);
+        for weight in weights.iter_mut() {
+            weight[i] = if total > 0.0 { weight[i] / total } else { even };
+        }
+    }
+}
+
+// 線形 RGB の平面に露出の倍率 gain とチャンネルごとの gains を掛け、輝度に拡張 Reinhard を適用して
+// 色比を保ったまま縮める（1 で頭打ち）。輝度が 0 以下の画素は黒にする
+pub fn reinhard_planes(
+    r: &mut [f32],
+    g: &mut [f32],
+    b: &mut [f32],
+    gain: f32,
+    gains: [f32; 3],
+    white_squared: f32,
+) {
+    assert!(
+        g.len() == r.len() && b.len() == r.len(),
+        "RGB の平面の長さがそろっていません"
+    );
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        // SAFETY: AVX2 と FMA が使えることを直前に確認している
+        unsafe { avx2::reinhard_planes([r, g, b], gain, gains, white_squared) };
+        return;
+    }
+    reinhard_planes_scalar([r, g, b], gain, gains, white_squared, 0);
+}
+
+fn reinhard_planes_scalar(
+    [r, g, b]: [&mut [f32]; 3],
+    gain: f32,
+    gains: [f32; 3],
+    white_squared: f32,
+    start: usize,
+) {
+    for i in start..r.len() {
+        let mut linear = [r[i], g[i], b[i]];
+        for (value, channel_gain) in linear.iter_mut().zip(gains) {
+            *value = *value * gain * channel_gain;
+        }
+        let luma = 0.2126 * linear[0] + 0.7152 * linear[1] + 0.0722 * linear[2];
+        let scale = if luma > 0.0 {
+            luma * (1.0 + luma / white_squared) / (1.0 + luma) / luma
+        } else {
+            0.0
+        };
+        r[i] = (linear[0] * scale).min(1.0);
+        g[i] = (linear[1] * scale).min(1.0);
+        b[i] = (linear[2] * scale).min(1.0);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::LUMA_WEIGHTS;
+
+    // 12 要素（4 画素）ずつ、RGB の並びに合わせた重みを掛けて 4 レーンの f64 に積む
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn luma_sum(rgb: &[u16]) -> f64 {
+        let scale = 1.0 / u16::MAX as f64;
+        let [r, g, b] = LUMA_WEIGHTS.map(|weight| weight * scale);
+        let weights = [
+            _mm256_setr_pd(r, g, b, r),
+            _mm256_setr_pd(g, b, r, g),
+            _mm256_setr_pd(b, r, g, b),
+        ];
+        let mut sums = [_mm256_setzero_pd(); 3];
+        let chunks = rgb.chunks_exact(12);
+        let remainder = chunks.remainder();
+        for chunk in chunks {
+            for (part, (sum, weight)) in sums.iter_mut().zip(&weights).enumerate() {
+                let values = _mm_setr_epi32(
+                    chunk[part * 4] as i32,
+                    chunk[part * 4 + 1] as i32,
+                    chunk[part * 4 + 2] as i32,
+                    chunk[part * 4 + 3] as i32,
+                );
+                *sum = _mm256_fmadd_pd(_mm256_cvtepi32_pd(values), *weight, *sum);
+            }
+        }
+        let total = _mm256_add_pd(_mm256_add_pd(sums[0], sums[1]), sums[2]);
+        let mut lanes = [0.0f64; 4];
+        _mm256_storeu_pd(lanes.as_mut_ptr(), total);
+        lanes.iter().sum::<f64>() + super::luma_sum_scalar(remainder)
+    }
+
+    // 8 要素ずつ 32bit に広げて足し、浮動小数点の除算で平均を求める。
+    // 和は 2^24 未満なので f32 で正確に表せ、切り捨て結果はスカラー実装と一致する
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn average_u16(frames: &[&[u16]], output: &mut [u16]) {
+        let count = _mm256_set1_ps(frames.len() as f32);
+        let lanes = output.len() / 8 * 8;
+        for start in (0..lanes).step_by(8) {
+            let mut sum = _mm256_setzero_si256();
+            for frame in frames {
+                let values = _mm_loadu_si128(frame[start..].as_ptr() as *const __m128i);
+                sum = _mm256_add_epi32(sum, _mm256_cvtepu16_epi32(values));
+            }
+            let average = _mm256_cvttps_epi32(_mm256_div_ps(_mm256_cvtepi32_ps(sum), count));
+            let mut values = [0i32; 8];
+            _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, average);
+            for (value, averaged) in output[start..start + 8].iter_mut().zip(values) {
+                *value = averaged as u16;
+            }
+        }
+        super::average_u16_scalar(frames, output, lanes);
+    }
+
+    // FMA を使わず掛け算と足し算を分け、スカラー実装と同じ丸めにする
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn luma_planes(r: &[f32], g: &[f32], b: &[f32], output: &mut [f32]) {
+        let [wr, wg, wb] = [0.2126f32, 0.7152, 0.0722].map(|weight| _mm256_set1_ps(weight));
+        let lanes = output.len() / 8 * 8;
+        for start in (0..lanes).step_by(8) {
+            let red = _mm256_mul_ps(_mm256_loadu_ps(r[start..].as_ptr()), wr);
+            let green = _mm256_mul_ps(_mm256_loadu_ps(g[start..].as_ptr()), wg);
+            let blue = _mm256_mul_ps(_mm256_loadu_ps(b[start..].as_ptr()), wb);
+            let luma = _mm256_add_ps(_mm256_add_ps(red, green), blue);
+            _mm256_storeu_ps(output[start..].as_mut_ptr(), luma);
+        }
+        super::luma_planes_scalar(r, g, b, output, lanes);
+    }
+
+    // 合計はスカラー実装と同じ順に足し、合計が 0 の画素だけ等分の値に差し替える
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn normalize_weights(weights: &mut [&mut [f32]]) {
+        let even = _mm256_set1_ps(1.0 / weights.len() as f32);
+        let zero = _mm256_setzero_ps();
+        let lanes = weights[0].len() / 8 * 8;
+        for start in (0..lanes).step_by(8) {
+            let mut total = zero;
+            for weight in weights.iter() {
+                total = _mm256_add_ps(total, _mm256_loadu_ps(weight[start..].as_ptr()));
+            }
+            let positive = _mm256_cmp_ps(total, zero, _CMP_GT_OQ);
+            for weight in weights.iter_mut() {
+                let divided = _mm256_div_ps(_mm256_loadu_ps(weight[start..].as_ptr()), total);
+                let normalized = _mm256_blendv_ps(even, divided, positive);
+                _mm256_storeu_ps(weight[start..].as_mut_ptr(), normalized);
+            }
+        }
+        super::normalize_weights_scalar(weights, lanes);
+    }
+
+    // スカラー実装と同じ順で掛け算・割り算をし、FMA は使わない
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn reinhard_planes(
+        [r, g, b]: [&mut [f32]; 3],
+        gain: f32,
+        gains: [f32; 3],
+        white_squared: f32,
+    ) {
+        let gain_lanes = _mm256_set1_ps(gain);
+        let gain_planes = gains.map(|channel_gain| _mm256_set1_ps(channel_gain));
+        let [wr, wg, wb] = [0.2126f32, 0.7152, 0.0722].map(|weight| _mm256_set1_ps(weight));
+        let white_lanes = _mm256_set1_ps(white_squared);
+        let one = _mm256_set1_ps(1.0);
+        let zero = _mm256_setzero_ps();
+        let lanes = r.len() / 8 * 8;
+        for start in (0..lanes).step_by(8) {
+            let load = |plane: &[f32], channel_gain: __m256| {
+                _mm256_mul_ps(
+                    _mm256_mul_ps(_mm256_loadu_ps(plane[start..].as_ptr()), gain_lanes),
+                    channel_gain,
+                )
+            };
+            let red = load(r, gain_planes[0]);
+            let green = load(g, gain_planes[1]);
+            let blue = load(b, gain_planes[2]);
+            let luma = _mm256_add_ps(
+                _mm256_add_ps(_mm256_mul_ps(wr, red), _mm256_mul_ps(wg, green)),
+                _mm256_mul_ps(wb, blue),
+            );
+            let curve = _mm256_mul_ps(luma, _mm256_add_ps(one, _mm256_div_ps(luma, white_lanes)));
+            let mapped = _mm256_div_ps(curve, _mm256_add_ps(one, luma));
+            let scale = _mm256_and_ps(
+                _mm256_div_ps(mapped, luma),
+                _mm256_cmp_ps(luma, zero, _CMP_GT_OQ),
+            );
+            for (plane, value) in [&mut *r, &mut *g, &mut *b]
+                .into_iter()
+                .zip([red, green, blue])
+            {
+                let scaled = _mm256_min_ps(_mm256_mul_ps(value, scale), one);
+                _mm256_storeu_ps(plane[start..].as_mut_ptr(), scaled);
+            }
+        }
+        super::reinhard_planes_scalar([r, g, b], gain, gains, white_squared, lanes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(len: usize, seed: u32) -> Vec<u16> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 16) as u16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vector_kernels_match_scalar_results() {
+        // 端数の要素もスカラー側で処理されるよう、レーン幅で割り切れない長さにする
+        let frames: Vec<Vec<u16>> = (0..5).map(|seed| samples(3 * 1001, seed)).collect();
+        let slices: Vec<&[u16]> = frames.iter().map(|frame| frame.as_slice()).collect();
+
+        let mut vector = vec![0u16; 3 * 1001];
+        let mut scalar = vec![0u16; 3 * 1001];
+        average_u16(&slices, &mut vector);
+        average_u16_scalar(&slices, &mut scalar, 0);
+        assert_eq!(vector, scalar);
+
+        let expected = luma_sum_scalar(&frames[0]);
+        assert!((luma_sum(&frames[0]) - expected).abs() < 1e-9 * expected);
+
+        let planes: Vec<Vec<f32>> = frames[..3]
+            .iter()
+            .map(|frame| frame.iter().map(|v| *v as f32 / 65535.0).collect())
+            .collect();
+        let mut expected = vec![0.0; planes[0].len()];
+        luma_planes_scalar(&planes[0], &planes[1], &planes[2], &mut expected, 0);
+        assert_eq!(luma_planes(&planes[0], &planes[1], &planes[2]), expected);
+
+        // 合計が 0 の画素も混ぜる
+        let mut vector = planes.clone();
+        let mut scalar = planes.clone();
+        for plane in vector.iter_mut().chain(scalar.iter_mut()) {
+            plane[5] = 0.0;
+        }
+        normalize_weights(
+            &mut vector
+                .iter_mut()
+                .map(|p| p.as_mut_slice())
+                .collect::<Vec<_>>(),
+        );
+        normalize_weights_scalar(
+            &mut scalar
+                .iter_mut()
+                .map(|p| p.as_mut_slice())
+                .collect::<Vec<_>>(),
+            0,
+        );
+        assert_eq!(vector, scalar);
+        assert_eq!(vector[0][5], 1.0 / 3.0);
+
+        let [mut r, mut g, mut b] = [0, 1, 2].map(|i| planes[i].clone());
+        let [mut sr, mut sg, mut sb] = [0, 1, 2].map(|i| planes[i].clone());
+        r[7] = 0.0;
+        g[7] = 0.0;
+        b[7] = 0.0;
+        sr[7] = 0.0;
+        sg[7] = 0.0;
+        sb[7] = 0.0;
+        reinhard_planes(&mut r, &mut g, &mut b, 2.0, [1.1, 1.0, 0.9], 16.0);
+        reinhard_planes_scalar([&mut sr, &mut sg, &mut sb], 2.0, [1.1, 1.0, 0.9], 16.0, 0);
+        assert_eq!((r, g, b), (sr, sg, sb));
+    }
+
+    // cargo test --release simd_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn simd_benchmark() {
+        use std::time::Instant;
+
+        fn time(label: &str, mut run: impl FnMut()) -> f64 {
+            run();
+            let start = Instant::now();
+            for _ in 0..10 {
+                run();
+            }
+            let elapsed = start.elapsed().as_secs_f64() * 100.0;
+            println!("{:<24} {:>8.2} ms", label, elapsed);
+            elapsed
+        }
+
+        // 24MP 相当の RGB
+        let len = 3 * 6000 * 4000;
+        let frames: Vec<Vec<u16>> = (0..3).map(|seed| samples(len, seed)).collect();
+        let slices: Vec<&[u16]> = frames.iter().map(|frame| frame.as_slice()).collect();
+        let planes: Vec<Vec<f32>> = frames
+            .iter()
+            .map(|frame| {
+                frame[..len / 3]
+                    .iter()
+                    .map(|v| *v as f32 / 65535.0)
+                    .collect()
+            })
+            .collect();
+        println!("AVX2: {}", avx2_available());
+
+        let mut output = vec![0u16; len];
+        time("average_u16 scalar", || {
+            average_u16_scalar(&slices, &mut output, 0)
+        });
+        time("average_u16", || average_u16(&slices, &mut output));
+
+        time("luma_sum scalar", || {
+            std::hint::black_box(luma_sum_scalar(&frames[0]));
+        });
+        time("luma_sum", || {
+            std::hint::black_box(luma_sum(&frames[0]));
+        });
+
+        // ベクトル版と同じく出力の確保も含めて測る
+        time("luma_planes scalar", || {
+            let mut luma = vec![0.0f32; len / 3];
+            luma_planes_scalar(&planes[0], &planes[1], &planes[2], &mut luma, 0);
+            std::hint::black_box(luma);
+        });
+        time("luma_planes", || {
+            std::hint::black_box(luma_planes(&planes[0], &planes[1], &planes[2]));
+        });
+
+        let mut weights = planes.clone();
+        time("normalize_weights scalar", || {
+            normalize_weights_scalar(
+                &mut weights
+                    .iter_mut()
+                    .map(|p| p.as_mut_slice())
+                    .collect::<Vec<_>>(),
+                0,
+            )
+        });
+        time("normalize_weights", || {
+            normalize_weights(
+                &mut weights
+                    .iter_mut()
+                    .map(|p| p.as_mut_slice())
+                    .collect::<Vec<_>>(),
+            )
+        });
+
+        let [mut r, mut g, mut b] = [0, 1, 2].map(|i| planes[i].clone());
+        time("reinhard_planes scalar", || {
+            reinhard_planes_scalar([&mut r, &mut g, &mut b], 1.0, [1.0; 3], 16.0, 0)
+        });
+        time("reinhard_planes", || {
+            reinhard_planes(&mut r, &mut g, &mut b, 1.0, [1.0; 3], 16.0)
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "フレームの長さ")]
+    fn rejects_frames_shorter_than_the_output() {
+        let short = samples(7, 1);
+        let full = samples(16, 2);
+        average_u16(&[&full, &short], &mut [0u16; 16]);
+    }
+}
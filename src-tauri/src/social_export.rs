@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::filters::{self, SharpenParams};
+use crate::merge::Rgb16Image;
+use crate::output_lock::OutputReservation;
+use crate::output_path;
+use crate::paths;
+use crate::watch_filter::OwnOutputs;
+use crate::workdir;
+
+// 縮小で甘くなった輪郭を戻す程度の出力シャープ。空のノイズは強調しない
+const OUTPUT_SHARPEN: SharpenParams = SharpenParams {
+    radius: 0.7,
+    amount: 0.4,
+    threshold: 0.01,
+};
+
+// 各サービスの推奨サイズの目安。超える画像はサービス側で再圧縮されて劣化するため、先に縮小しておく
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum SocialPlatform {
+    // 幅 1080。縦横比 4:5〜1.91:1 を外れる画像は中央で切り抜く
+    Instagram,
+    Facebook,
+    X,
+    Flickr,
+    // ブログなど一般的な Web 掲載
+    Web,
+}
+
+impl SocialPlatform {
+    fn name(self) -> &'static str {
+        match self {
+            SocialPlatform::Instagram => "instagram",
+            SocialPlatform::Facebook => "facebook",
+            SocialPlatform::X => "x",
+            SocialPlatform::Flickr => "flickr",
+            SocialPlatform::Web => "web",
+        }
+    }
+
+    // 収める幅と高さ
+    fn bounds(self) -> (u32, u32) {
+        match self {
+            SocialPlatform::Instagram => (1080, 1350),
+            SocialPlatform::Facebook | SocialPlatform::Flickr => (2048, 2048),
+            SocialPlatform::X => (4096, 4096),
+            SocialPlatform::Web => (1920, 1920),
+        }
+    }
+
+    // 幅 / 高さの範囲
+    fn aspect_range(self) -> Option<(f64, f64)> {
+        match self {
+            SocialPlatform::Instagram => Some((0.8, 1.91)),
+            _ => None,
+        }
+    }
+
+    fn quality(self) -> u8 {
+        match self {
+            SocialPlatform::Web => 85,
+            _ => 90,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct SocialExport {
+    pub platform: SocialPlatform,
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+// 書き出し先の制限。lib.rs が設定から埋める
+pub struct ExportTarget<'a> {
+    pub dir: &'a Path,
+    pub base_name: &'a str,
+    pub protected_dirs: &'a [PathBuf],
+    pub write_roots: &'a [PathBuf],
+    pub own_outputs: Option<&'a OwnOutputs>,
+}
+
+// 切り抜く範囲（x, y, 幅, 高さ）と縮小後の大きさ。縦横比の範囲を外れる分は中央で切り抜く
+pub fn layout(width: u32, height: u32, platform: SocialPlatform) -> ([u32; 4], (u32, u32)) {
+    let mut crop = [0, 0, width, height];
+    if let Some((min, max)) = platform.aspect_range() {
+        let aspect = width as f64 / height as f64;
+        if aspect < min {
+            let cropped = ((width as f64 / min).round() as u32).min(height);
+            crop = [0, (height - cropped) / 2, width, cropped];
+        } else if aspect > max {
+            let cropped = ((height as f64 * max).round() as u32).min(width);
+            crop = [(width - cropped) / 2, 0, cropped, height];
+        }
+    }
+    let (max_width, max_height) = platform.bounds();
+    let scale = (max_width as f64 / crop[2] as f64)
+        .min(max_height as f64 / crop[3] as f64)
+        .min(1.0);
+    let size = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+    (crop, (size(crop[2]), size(crop[3])))
+}
+
+// 枠に収まるよう切り抜いて縮小してからシャープをかける
+pub fn prepare(image: &Rgb16Image, platform: SocialPlatform) -> Rgb16Image {
+    let ([x, y, width, height], size) = layout(image.width(), image.height(), platform);
+    let cropped = (image.dimensions() != (width, height))
+        .then(|| imageops::crop_imm(image, x, y, width, height).to_image());
+    let source = cropped.as_ref().unwrap_or(image);
+    if source.dimensions() == size {
+        return filters::sharpen(source, &OUTPUT_SHARPEN);
+    }
+    let resized = imageops::resize(source, size.0, size.1, FilterType::Lanczos3);
+    filters::sharpen(&resized, &OUTPUT_SHARPEN)
+}
+
+// 合成結果は sRGB なので、そのまま 8bit の JPEG にする。EXIF・ICC などのメタデータは書き込まない
+pub fn export(
+    image: &Rgb16Image,
+    platform: SocialPlatform,
+    target: &ExportTarget,
+) -> Result<SocialExport, String> {
+    output_path::ensure_outside_protected(target.dir, target.protected_dirs)?;
+    paths::ensure_writable(target.dir, target.write_roots)?;
+    let stem = format!("{}_{}", target.base_name, platform.name());
+    let reservation = OutputReservation::reserve(target.dir, &stem, false, |name| {
+        vec![format!("{}.jpg", name)]
+    })?;
+    let path = target.dir.join(format!("{}.jpg", reservation.name()));
+    let prepared = prepare(image, platform);
+    let eight_bit = DynamicImage::ImageRgb16(prepared).into_rgb8();
+
+    if let Some(own_outputs) = target.own_outputs {
+        own_outputs.record(&path);
+    }
+    let os_path = paths::extended(&path);
+    let partial = workdir::partial_path(None, &os_path);
+    File::create(&partial)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+            JpegEncoder::new_with_quality(BufWriter::new(file), platform.quality())
+                .encode_image(&eight_bit)
+                .map_err(|e| e.to_string())
+        })
+        .and_then(|_| workdir::move_into_place(&partial, &os_path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&partial);
+        })?;
+    Ok(SocialExport {
+        platform,
+        path: path.to_string_lossy().to_string(),
+        width: eight_bit.width(),
+        height: eight_bit.height(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn fits_the_platform_size_and_aspect() {
+        assert_eq!(
+            layout(3000, 1000, SocialPlatform::Instagram),
+            ([545, 0, 1910, 1000], (1080, 565))
+        );
+        assert_eq!(
+            layout(1200, 2400, SocialPlatform::Instagram),
+            ([0, 450, 1200, 1500], (1080, 1350))
+        );
+        assert_eq!(layout(3000, 1000, SocialPlatform::Facebook).1, (2048, 683));
+        assert_eq!(layout(800, 600, SocialPlatform::X).1, (800, 600));
+    }
+
+    #[test]
+    fn writes_a_jpeg_without_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = Rgb16Image::from_fn(480, 320, |x, _| Rgb([(x * 100) as u16; 3]));
+        let target = ExportTarget {
+            dir: dir.path(),
+            base_name: "hdr_merge_1",
+            protected_dirs: &[],
+            write_roots: &[],
+            own_outputs: None,
+        };
+
+        let exported = export(&image, SocialPlatform::Web, &target).unwrap();
+
+        assert!(exported.path.ends_with("hdr_merge_1_web.jpg"));
+        assert_eq!((exported.width, exported.height), (480, 320));
+        let bytes = std::fs::read(&exported.path).unwrap();
+        // APP1（EXIF・XMP）と APP2（ICC）のマーカーがない
+        assert!(!bytes
+            .windows(2)
+            .any(|w| w == [0xFF, 0xE1] || w == [0xFF, 0xE2]));
+        assert!(export(&image, SocialPlatform::Web, &target)
+            .unwrap()
+            .path
+            .ends_with("hdr_merge_1_web_2.jpg"));
+    }
+}
@@ -0,0 +1,360 @@
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::{linear_to_srgb, srgb_to_linear, u16_to_unit};
+use crate::merge::Rgb16Image;
+
+type Matrix = [[f32; 3]; 3];
+
+// 各色域のリニア RGB → XYZ（D50、ICC の PCS に合わせて Bradford で順応済み）
+const SRGB_TO_XYZ: Matrix = [
+    [0.436_074_7, 0.385_064_9, 0.143_080_4],
+    [0.222_504_5, 0.716_878_6, 0.060_616_9],
+    [0.013_932_2, 0.097_104_5, 0.714_173_3],
+];
+const DISPLAY_P3_TO_XYZ: Matrix = [
+    [0.515_1, 0.292_0, 0.157_1],
+    [0.241_2, 0.692_2, 0.066_6],
+    [-0.001_1, 0.041_9, 0.784_1],
+];
+const ADOBE_RGB_TO_XYZ: Matrix = [
+    [0.609_755_9, 0.205_240_1, 0.149_224],
+    [0.311_124_2, 0.625_656, 0.063_219_7],
+    [0.019_481_1, 0.060_890_2, 0.744_838_7],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum ProofTarget {
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SoftProofParams {
+    pub target: ProofTarget,
+    // 指定するとこの ICC プロファイル（マトリクス・TRC 形式）を target の代わりに使う
+    pub icc_path: Option<String>,
+    // false なら、表示側がカラーマネジメントせず sRGB の値をそのまま出す場合を再現する
+    pub color_managed: bool,
+    pub max_size: u32,
+}
+
+impl Default for SoftProofParams {
+    fn default() -> Self {
+        Self {
+            target: ProofTarget::Srgb,
+            icc_path: None,
+            color_managed: true,
+            max_size: 2048,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct SoftProofResult {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    // 対象の色域に収まらず切り詰めた画素の割合（color_managed のときのみ）
+    pub out_of_gamut_fraction: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Curve {
+    Srgb,
+    Gamma(f32),
+    Table(Vec<f32>),
+    // ICC parametricCurve の type 4: Y = (aX + b)^g + e (X >= d), Y = cX + f (X < d)
+    Parametric([f32; 7]),
+}
+
+impl Curve {
+    fn to_linear(&self, value: f32) -> f32 {
+        match self {
+            Curve::Srgb => srgb_to_linear(value),
+            Curve::Gamma(gamma) => value.powf(*gamma),
+            Curve::Table(table) => {
+                let position = value.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+                let index = (position as usize).min(table.len() - 2);
+                let t = position - index as f32;
+                table[index] * (1.0 - t) + table[index + 1] * t
+            }
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                if value >= *d {
+                    (a * value + b).max(0.0).powf(*g) + e
+                } else {
+                    c * value + f
+                }
+            }
+        }
+    }
+}
+
+// 表示側の色空間。マトリクス（RGB → XYZ D50）と各チャンネルのトーンカーブで表す
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayProfile {
+    to_xyz: Matrix,
+    curves: [Curve; 3],
+}
+
+impl DisplayProfile {
+    pub fn builtin(target: ProofTarget) -> Self {
+        let (to_xyz, curve) = match target {
+            ProofTarget::Srgb => (SRGB_TO_XYZ, Curve::Srgb),
+            ProofTarget::DisplayP3 => (DISPLAY_P3_TO_XYZ, Curve::Srgb),
+            ProofTarget::AdobeRgb => (ADOBE_RGB_TO_XYZ, Curve::Gamma(563.0 / 256.0)),
+        };
+        Self {
+            to_xyz,
+            curves: [curve.clone(), curve.clone(), curve],
+        }
+    }
+
+    pub fn load_icc(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("{} を読み込めません: {}", path.to_string_lossy(), e))?;
+        parse_icc(&bytes).map_err(|e| format!("{}: {}", path.to_string_lossy(), e))
+    }
+}
+
+fn parse_icc(bytes: &[u8]) -> Result<DisplayProfile, String> {
+    let u32_at = |offset: usize| -> Result<u32, String> {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| "ICC プロファイルが壊れています".to_string())
+    };
+    let fixed_at = |offset: usize| u32_at(offset).map(|v| v as i32 as f32 / 65536.0);
+    if bytes.get(36..40) != Some(b"acsp") {
+        return Err("ICC プロファイルではありません".to_string());
+    }
+    let count = u32_at(128)? as usize;
+    let tag = |signature: &[u8; 4]| -> Result<usize, String> {
+        (0..count)
+            .map(|i| 132 + i * 12)
+            .find(|&entry| bytes.get(entry..entry + 4) == Some(signature))
+            .map(|entry| u32_at(entry + 4).map(|offset| offset as usize))
+            .unwrap_or_else(|| {
+                Err(format!(
+                    "マトリクス・TRC 形式のプロファイルのみ対応しています（{} がありません）",
+                    String::from_utf8_lossy(signature)
+                ))
+            })
+    };
+
+    let mut to_xyz = [[0.0; 3]; 3];
+    for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+        let offset = tag(signature)?;
+        for (row, line) in to_xyz.iter_mut().enumerate() {
+            line[column] = fixed_at(offset + 8 + row * 4)?;
+        }
+    }
+
+    let mut curves = Vec::new();
+    for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+        let offset = tag(signature)?;
+        let curve = match bytes.get(offset..offset + 4) {
+            Some(b"curv") => match u32_at(offset + 8)? as usize {
+                0 => Curve::Gamma(1.0),
+                1 => Curve::Gamma((u32_at(offset + 12)? >> 16) as f32 / 256.0),
+                entries => Curve::Table(
+                    (0..entries)
+                        .map(|i| {
+                            let at = offset + 12 + i * 2;
+                            bytes
+                                .get(at..at + 2)
+                                .map(|b| u16::from_be_bytes([b[0], b[1]]) as f32 / 65535.0)
+                                .ok_or_else(|| "ICC プロファイルが壊れています".to_string())
+                        })
+                        .collect::<Result<_, _>>()?,
+                ),
+            },
+            Some(b"para") => {
+                let function = (u32_at(offset + 8)? >> 16) as usize;
+                let counts = [1, 3, 4, 5, 7];
+                let values: Vec<f32> =
+                    (0..*counts.get(function).ok_or("未対応のトーンカーブです")?)
+                        .map(|i| fixed_at(offset + 12 + i * 4))
+                        .collect::<Result<_, _>>()?;
+                Curve::Parametric(parametric(function, &values))
+            }
+            _ => return Err("未対応のトーンカーブです".to_string()),
+        };
+        curves.push(curve);
+    }
+    let [r, g, b]: [Curve; 3] = curves.try_into().expect("3 channels");
+    Ok(DisplayProfile {
+        to_xyz,
+        curves: [r, g, b],
+    })
+}
+
+// ICC の parametricCurve の各形式を type 4 の係数 [g, a, b, c, d, e, f] にそろえる
+fn parametric(function: usize, v: &[f32]) -> [f32; 7] {
+    match function {
+        0 => [v[0], 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        1 => [v[0], v[1], v[2], 0.0, -v[2] / v[1], 0.0, 0.0],
+        2 => [v[0], v[1], v[2], 0.0, -v[2] / v[1], v[3], v[3]],
+        3 => [v[0], v[1], v[2], v[3], v[4], 0.0, 0.0],
+        _ => [v[0], v[1], v[2], v[3], v[4], v[5], v[6]],
+    }
+}
+
+fn multiply(matrix: &Matrix, v: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn invert(m: &Matrix) -> Matrix {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ]
+    .map(|row| row.map(|value| value / det))
+}
+
+// sRGB の画像が対象の表示でどう見えるかを、sRGB の画面で確認できる画像にする
+pub fn proof(
+    image: &Rgb16Image,
+    profile: &DisplayProfile,
+    color_managed: bool,
+    max_size: u32,
+) -> (RgbImage, f64) {
+    let (width, height) = image.dimensions();
+    let scaled;
+    let source = if width.max(height) > max_size {
+        let scale = max_size as f64 / width.max(height) as f64;
+        let target = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+        scaled = imageops::resize(image, target(width), target(height), FilterType::Triangle);
+        &scaled
+    } else {
+        image
+    };
+
+    let xyz_to_srgb = invert(&SRGB_TO_XYZ);
+    let xyz_to_target = invert(&profile.to_xyz);
+    let mut clipped = 0usize;
+    let output = RgbImage::from_fn(source.width(), source.height(), |x, y| {
+        let encoded = source.get_pixel(x, y).0.map(u16_to_unit);
+        let xyz = if color_managed {
+            // 表示側で対象の色域へ変換され、収まらない色は切り詰められる
+            let linear = encoded.map(srgb_to_linear);
+            let target = multiply(&xyz_to_target, multiply(&SRGB_TO_XYZ, linear));
+            if target.iter().any(|v| !(-1e-3..=1.0 + 1e-3).contains(v)) {
+                clipped += 1;
+            }
+            multiply(&profile.to_xyz, target.map(|v| v.clamp(0.0, 1.0)))
+        } else {
+            // sRGB の値がそのまま対象のトーンカーブ・原色で表示される
+            let mut linear = [0.0; 3];
+            for (channel, value) in linear.iter_mut().enumerate() {
+                *value = profile.curves[channel].to_linear(encoded[channel]);
+            }
+            multiply(&profile.to_xyz, linear)
+        };
+        let srgb = multiply(&xyz_to_srgb, xyz);
+        Rgb(srgb.map(|v| (linear_to_srgb(v.clamp(0.0, 1.0)) * 255.0).round() as u8))
+    });
+    let pixels = (output.width() as usize * output.height() as usize).max(1);
+    (output, clipped as f64 / pixels as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swatches() -> Rgb16Image {
+        let colors = [
+            [65535, 0, 0],
+            [0, 65535, 0],
+            [30000, 30000, 30000],
+            [65535; 3],
+        ];
+        Rgb16Image::from_fn(4, 1, |x, _| Rgb(colors[x as usize]))
+    }
+
+    #[test]
+    fn managed_wide_gamut_matches_source_and_unmanaged_oversaturates() {
+        let source = swatches();
+        let expected = image::DynamicImage::ImageRgb16(source.clone()).to_rgb8();
+        let p3 = DisplayProfile::builtin(ProofTarget::DisplayP3);
+
+        let (managed, clipped) = proof(&source, &p3, true, 2048);
+        for (a, b) in managed.pixels().zip(expected.pixels()) {
+            assert!(
+                a.0.iter().zip(b.0).all(|(a, b)| a.abs_diff(b) <= 2),
+                "{:?} {:?}",
+                a,
+                b
+            );
+        }
+        assert_eq!(clipped, 0.0);
+
+        // 純色は sRGB の範囲を超えて切り詰められ、灰色と白はそのまま
+        let (unmanaged, _) = proof(&source, &p3, false, 2048);
+        assert_eq!(unmanaged.get_pixel(0, 0).0, [255, 0, 0]);
+        assert!(unmanaged
+            .get_pixel(2, 0)
+            .0
+            .iter()
+            .all(|v| v.abs_diff(expected.get_pixel(2, 0)[0]) <= 2));
+        assert!(unmanaged.get_pixel(3, 0).0.iter().all(|v| *v >= 253));
+    }
+
+    #[test]
+    fn parses_matrix_trc_icc_profile() {
+        // ヘッダー 128 バイト + タグ表 + XYZ・curv のタグ
+        let mut bytes = vec![0u8; 128];
+        bytes[36..40].copy_from_slice(b"acsp");
+        let tags: [&[u8; 4]; 6] = [b"rXYZ", b"gXYZ", b"bXYZ", b"rTRC", b"gTRC", b"bTRC"];
+        bytes.extend((tags.len() as u32).to_be_bytes());
+        let data_start = 132 + tags.len() * 12;
+        let mut data = Vec::new();
+        for (i, signature) in tags.iter().enumerate() {
+            bytes.extend(*signature);
+            bytes.extend(((data_start + data.len()) as u32).to_be_bytes());
+            bytes.extend(20u32.to_be_bytes());
+            if i < 3 {
+                data.extend(b"XYZ \0\0\0\0");
+                for row in SRGB_TO_XYZ {
+                    data.extend(((row[i] * 65536.0).round() as i32).to_be_bytes());
+                }
+            } else {
+                data.extend(b"curv\0\0\0\0");
+                data.extend(1u32.to_be_bytes());
+                data.extend(((563u32) << 16).to_be_bytes());
+            }
+        }
+        bytes.extend(data);
+
+        let profile = parse_icc(&bytes).unwrap();
+
+        assert!((profile.to_xyz[1][1] - 0.716_878_6).abs() < 1e-4);
+        assert_eq!(profile.curves[0], Curve::Gamma(563.0 / 256.0));
+        assert!(parse_icc(&bytes[..100]).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+// 失敗理由ごとの件数が増え続けないよう、理由の種類数に上限を設ける
+const MAX_FAILURE_REASONS: usize = 50;
+const OTHER_REASON: &str = "その他";
+
+// 合成の利用状況。端末内にのみ保存し、外部へは送信しない
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UsageStats {
+    pub since: String,
+    pub merges_succeeded: u64,
+    pub merges_failed: u64,
+    pub total_frames: u64,
+    pub total_duration_ms: u64,
+    pub average_duration_ms: f64,
+    pub failure_reasons: BTreeMap<String, u64>,
+}
+
+impl Default for UsageStats {
+    fn default() -> Self {
+        Self {
+            since: Local::now().to_rfc3339(),
+            merges_succeeded: 0,
+            merges_failed: 0,
+            total_frames: 0,
+            total_duration_ms: 0,
+            average_duration_ms: 0.0,
+            failure_reasons: BTreeMap::new(),
+        }
+    }
+}
+
+impl UsageStats {
+    pub fn record(&mut self, frames: usize, duration: Duration, outcome: Result<(), &str>) {
+        let duration_ms = duration.as_millis() as u64;
+        match outcome {
+            Ok(()) => {
+                self.merges_succeeded += 1;
+                self.total_frames += frames as u64;
+                self.total_duration_ms += duration_ms;
+                self.average_duration_ms =
+                    self.total_duration_ms as f64 / self.merges_succeeded as f64;
+            }
+            Err(message) => {
+                self.merges_failed += 1;
+                let reason = failure_reason(message);
+                let key = if self.failure_reasons.contains_key(&reason)
+                    || self.failure_reasons.len() < MAX_FAILURE_REASONS
+                {
+                    reason
+                } else {
+                    OTHER_REASON.to_string()
+                };
+                *self.failure_reasons.entry(key).or_insert(0) += 1;
+            }
+        }
+    }
+}
+
+// パスなどの詳細を除き、": " より前の定型部分を失敗理由として集計する
+fn failure_reason(message: &str) -> String {
+    message
+        .split(": ")
+        .next()
+        .unwrap_or(message)
+        .trim()
+        .to_string()
+}
+
+pub struct StatsStore {
+    path: PathBuf,
+    data: Mutex<UsageStats>,
+}
+
+impl StatsStore {
+    pub fn load(path: PathBuf) -> Self {
+        let data = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            data: Mutex::new(data),
+        }
+    }
+
+    pub fn snapshot(&self) -> Result<UsageStats, String> {
+        let data = self.data.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(data.clone())
+    }
+
+    pub fn record(
+        &self,
+        frames: usize,
+        duration: Duration,
+        outcome: Result<(), &str>,
+    ) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap_or_else(PoisonError::into_inner);
+        data.record(frames, duration, outcome);
+        self.save(&data)
+    }
+
+    pub fn reset(&self) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap_or_else(PoisonError::into_inner);
+        *data = UsageStats::default();
+        self.save(&data)
+    }
+
+    fn save(&self, data: &UsageStats) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let text = serde_json::to_string_pretty(data).map_err(|e| e.to_string())?;
+        fs::write(&self.path, text).map_err(|e| format!("統計を保存できません: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_durations_and_groups_failure_reasons() {
+        let mut stats = UsageStats::default();
+        stats.record(3, Duration::from_millis(1000), Ok(()));
+        stats.record(5, Duration::from_millis(2000), Ok(()));
+        stats.record(
+            2,
+            Duration::from_millis(10),
+            Err("画像サイズが一致しません: a.png"),
+        );
+        stats.record(
+            2,
+            Duration::from_millis(10),
+            Err("画像サイズが一致しません: b.png"),
+        );
+
+        assert_eq!(stats.merges_succeeded, 2);
+        assert_eq!(stats.merges_failed, 2);
+        assert_eq!(stats.total_frames, 8);
+        assert_eq!(stats.average_duration_ms, 1500.0);
+        assert_eq!(stats.failure_reasons["画像サイズが一致しません"], 2);
+    }
+
+    #[test]
+    fn persists_and_resets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        let store = StatsStore::load(path.clone());
+        store.record(3, Duration::from_millis(500), Ok(())).unwrap();
+
+        assert_eq!(
+            StatsStore::load(path.clone())
+                .snapshot()
+                .unwrap()
+                .merges_succeeded,
+            1
+        );
+
+        store.reset().unwrap();
+        assert_eq!(
+            StatsStore::load(path).snapshot().unwrap().merges_succeeded,
+            0
+        );
+    }
+}
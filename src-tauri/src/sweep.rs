@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+use crate::geometry;
+use crate::merge::{self, MergeRequest, Rgb16Image};
+
+const SWEEP_PREVIEW_SIZE: u32 = 1024;
+const SWEEP_MAX_COMBINATIONS: usize = 36;
+const SWEEP_FIXED_FIELDS: [&str; 3] = ["paths", "outputDir", "outputExr"];
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct SweepPreview {
+    pub params: BTreeMap<String, Value>,
+    pub preview_path: String,
+    pub width: u32,
+    pub height: u32,
+    pub straighten_angle: Option<f64>,
+}
+
+// parameter_grid は MergeRequest のフィールド名（camelCase）→ 試す値の一覧。
+// 合成方式のパラメータは "algorithmParams.contrastWeight" のようにドット区切りで指定する
+pub fn run_sweep(
+    request: &MergeRequest,
+    parameter_grid: &BTreeMap<String, Vec<Value>>,
+    output_dir: &Path,
+) -> Result<Vec<SweepPreview>, String> {
+    let base = serde_json::to_value(request).map_err(|e| e.to_string())?;
+    let base_fields = base.as_object().ok_or("合成設定の変換に失敗しました")?;
+
+    for (key, values) in parameter_grid {
+        let field = key.split('.').next().unwrap_or_default();
+        if SWEEP_FIXED_FIELDS.contains(&field) {
+            return Err(format!("{} はパラメータ比較の対象にできません", key));
+        }
+        if !base_fields.contains_key(field) {
+            return Err(format!("不明なパラメータです: {}", key));
+        }
+        if values.is_empty() {
+            return Err(format!("{} の候補値がありません", key));
+        }
+    }
+
+    let combinations = expand_grid(parameter_grid);
+    if combinations.len() > SWEEP_MAX_COMBINATIONS {
+        return Err(format!(
+            "組み合わせ数が多すぎます（最大{}通り）",
+            SWEEP_MAX_COMBINATIONS
+        ));
+    }
+
+    let images = merge::load_inputs(&request.paths)?;
+    std::fs::create_dir_all(output_dir).map_err(|e| e.to_string())?;
+
+    let mut previews = Vec::new();
+    for (index, params) in combinations.into_iter().enumerate() {
+        let mut patched = base.clone();
+        for (key, value) in &params {
+            set_field(&mut patched, key, value.clone())?;
+        }
+        let mut variant: MergeRequest = serde_json::from_value(patched)
+            .map_err(|e| format!("パラメータの組み合わせが不正です: {}", e))?;
+
+        // ROI は元解像度の座標なので縮小前に切り出し、以降はプレビュー解像度で処理する
+        let inputs = images
+            .iter()
+            .map(|image| match &variant.roi {
+                Some(roi) => geometry::crop_roi(image, roi).map(|cropped| downscale(&cropped)),
+                None => Ok(downscale(image)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        variant.roi = None;
+
+        let merged = merge::process(&inputs, &variant)?;
+        let preview = merged.image;
+        let preview_path = output_dir.join(format!("sweep_{:02}.png", index + 1));
+        merge::save_png(&preview, &preview_path)?;
+
+        previews.push(SweepPreview {
+            params,
+            preview_path: preview_path.to_string_lossy().to_string(),
+            width: preview.width(),
+            height: preview.height(),
+            straighten_angle: merged.straighten_angle,
+        });
+    }
+
+    Ok(previews)
+}
+
+fn set_field(target: &mut Value, key: &str, value: Value) -> Result<(), String> {
+    let mut current = target;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        let fields = current
+            .as_object_mut()
+            .ok_or_else(|| format!("{} はオブジェクトではありません", key))?;
+        if parts.peek().is_none() {
+            fields.insert(part.to_string(), value);
+            return Ok(());
+        }
+        current = fields
+            .entry(part.to_string())
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+    Ok(())
+}
+
+fn expand_grid(parameter_grid: &BTreeMap<String, Vec<Value>>) -> Vec<BTreeMap<String, Value>> {
+    let mut combinations = vec![BTreeMap::new()];
+    for (key, values) in parameter_grid {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut next = combination.clone();
+                    next.insert(key.clone(), value.clone());
+                    next
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+fn downscale(image: &Rgb16Image) -> Rgb16Image {
+    let longest = image.width().max(image.height());
+    if longest <= SWEEP_PREVIEW_SIZE {
+        return image.clone();
+    }
+
+    let scale = SWEEP_PREVIEW_SIZE as f64 / longest as f64;
+    let width = ((image.width() as f64 * scale).round() as u32).max(1);
+    let height = ((image.height() as f64 * scale).round() as u32).max(1);
+    image::imageops::resize(image, width, height, FilterType::Triangle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{self, TestBracketOptions};
+    use serde_json::json;
+
+    fn bracket_request(dir: &Path) -> MergeRequest {
+        let options = TestBracketOptions {
+            width: 48,
+            height: 32,
+            ..Default::default()
+        };
+        let bracket = synthetic::generate_bracket(&dir.join("source"), &options).unwrap();
+        MergeRequest {
+            paths: bracket.paths,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn writes_one_preview_per_combination() {
+        let dir = tempfile::tempdir().unwrap();
+        let request = bracket_request(dir.path());
+        let grid = BTreeMap::from([
+            (
+                "algorithm".to_string(),
+                vec![json!("average"), json!("fusion")],
+            ),
+            (
+                "roi".to_string(),
+                vec![
+                    Value::Null,
+                    json!({ "x": 8, "y": 4, "width": 16, "height": 12 }),
+                ],
+            ),
+        ]);
+        let output_dir = dir.path().join("sweep");
+
+        let previews = run_sweep(&request, &grid, &output_dir).unwrap();
+
+        assert_eq!(previews.len(), 4);
+        let combinations: Vec<(&Value, &Value)> = previews
+            .iter()
+            .map(|preview| (&preview.params["algorithm"], &preview.params["roi"]))
+            .collect();
+        assert_eq!(combinations[0], (&json!("average"), &Value::Null));
+        assert_eq!(combinations[3].0, &json!("fusion"));
+        // ROI は元の解像度で切り出してから合成する
+        assert_eq!((previews[0].width, previews[0].height), (48, 32));
+        assert_eq!((previews[1].width, previews[1].height), (16, 12));
+        for (index, preview) in previews.iter().enumerate() {
+            let path = Path::new(&preview.preview_path);
+            assert_eq!(path, output_dir.join(format!("sweep_{:02}.png", index + 1)));
+            assert_eq!(
+                image::image_dimensions(path).unwrap(),
+                (preview.width, preview.height)
+            );
+        }
+        // 合成方式が違えばプレビューも違う
+        assert_ne!(
+            std::fs::read(&previews[0].preview_path).unwrap(),
+            std::fs::read(&previews[2].preview_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn sets_nested_algorithm_params() {
+        let mut target = json!({ "algorithmParams": {} });
+        set_field(&mut target, "algorithmParams.contrastWeight", json!(2.0)).unwrap();
+        set_field(&mut target, "roi.x", json!(3)).unwrap();
+
+        assert_eq!(target["algorithmParams"]["contrastWeight"], json!(2.0));
+        assert_eq!(target["roi"]["x"], json!(3));
+        assert!(set_field(&mut target, "algorithmParams.contrastWeight.x", json!(1)).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_grids_before_merging() {
+        let dir = tempfile::tempdir().unwrap();
+        // 入力がなくても、合成の前に格子の誤りを返す
+        let request = MergeRequest::default();
+        let output_dir = dir.path().join("sweep");
+        let grid = |key: &str, values: Vec<Value>| BTreeMap::from([(key.to_string(), values)]);
+
+        for invalid in [
+            grid("paths", vec![json!([])]),
+            grid("outputExr", vec![json!(true)]),
+            grid("noSuchField", vec![json!(1)]),
+            grid("algorithm", Vec::new()),
+        ] {
+            assert!(run_sweep(&request, &invalid, &output_dir).is_err());
+        }
+        let mut too_many = grid("algorithm", vec![json!("average"); 6]);
+        too_many.insert("deterministic".to_string(), vec![json!(true); 7]);
+        let error = run_sweep(&request, &too_many, &output_dir).unwrap_err();
+        assert!(error.contains("組み合わせ数"), "{}", error);
+        assert!(!output_dir.exists());
+    }
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::color::linear_to_srgb;
+use crate::merge::{self, Rgb16Image};
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TestBracketOptions {
+    pub frames: u32,
+    pub ev_spacing: f64,
+    pub width: u32,
+    pub height: u32,
+    // 標準偏差（0〜1 のスケール）
+    pub noise: f64,
+    // 1フレームあたりの移動物体のずれ（px）
+    pub motion: f64,
+    pub seed: u64,
+}
+
+impl Default for TestBracketOptions {
+    fn default() -> Self {
+        Self {
+            frames: 3,
+            ev_spacing: 2.0,
+            width: 640,
+            height: 426,
+            noise: 0.01,
+            motion: 0.0,
+            seed: 1,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct TestBracket {
+    pub paths: Vec<String>,
+    pub ev_offsets: Vec<f64>,
+}
+
+pub fn generate_bracket(
+    output_dir: &Path,
+    options: &TestBracketOptions,
+) -> Result<TestBracket, String> {
+    if options.frames < 2 || options.frames > 9 {
+        return Err("フレーム数は2〜9枚で指定してください".to_string());
+    }
+    if options.width < 16 || options.height < 16 {
+        return Err("画像サイズが小さすぎます".to_string());
+    }
+
+    std::fs::create_dir_all(output_dir).map_err(|e| e.to_string())?;
+
+    let mut rng = XorShift::new(options.seed);
+    let center = (options.frames - 1) as f64 / 2.0;
+    let mut paths = Vec::new();
+    let mut ev_offsets = Vec::new();
+
+    for frame in 0..options.frames {
+        let ev = (frame as f64 - center) * options.ev_spacing;
+        let shift = frame as f64 * options.motion;
+        let image = render_frame(options, ev, shift, &mut rng);
+
+        let path = output_dir.join(format!("bracket_{:02}_ev{:+.1}.png", frame + 1, ev));
+        merge::save_png(&image, &path)?;
+        paths.push(path.to_string_lossy().to_string());
+        ev_offsets.push(ev);
+    }
+
+    Ok(TestBracket { paths, ev_offsets })
+}
+
+fn render_frame(
+    options: &TestBracketOptions,
+    ev: f64,
+    shift: f64,
+    rng: &mut XorShift,
+) -> Rgb16Image {
+    let exposure = 2f64.powf(ev);
+    let width = options.width as f64;
+    let height = options.height as f64;
+
+    Rgb16Image::from_fn(options.width, options.height, |x, y| {
+        let fx = x as f64 / width;
+        let fy = y as f64 / height;
+        let radiance = scene_radiance(fx, fy, shift / width, height / width);
+
+        let mut rgb = [0u16; 3];
+        for (c, value) in rgb.iter_mut().enumerate() {
+            let tint = [1.0, 0.95, 0.85][c];
+            let linear = (radiance * tint * exposure).clamp(0.0, 1.0);
+            let encoded = linear_to_srgb(linear as f32) as f64 + rng.gaussian() * options.noise;
+            *value = (encoded.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16;
+        }
+        image::Rgb(rgb)
+    })
+}
+
+// 左から右に約 12EV の対数グラデーションに、明るい円と移動する暗い矩形を重ねたシーン
+fn scene_radiance(fx: f64, fy: f64, shift: f64, aspect: f64) -> f64 {
+    let mut radiance = 2f64.powf(fx * 12.0 - 9.0) * (0.6 + 0.4 * fy);
+
+    let dx = fx - 0.75;
+    let dy = (fy - 0.3) * aspect;
+    if dx * dx + dy * dy < 0.01 {
+        radiance = 16.0;
+    }
+
+    let box_x = 0.2 + shift;
+    if (box_x..box_x + 0.15).contains(&fx) && (0.55..0.8).contains(&fy) {
+        radiance *= 0.1;
+    }
+
+    radiance
+}
+
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn gaussian(&mut self) -> f64 {
+        let u1 = self.next_f64().max(f64::MIN_POSITIVE);
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_requested_frames_with_increasing_exposure() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            frames: 3,
+            width: 32,
+            height: 24,
+            noise: 0.0,
+            ..Default::default()
+        };
+
+        let bracket = generate_bracket(dir.path(), &options).unwrap();
+
+        assert_eq!(bracket.ev_offsets, vec![-2.0, 0.0, 2.0]);
+        let means: Vec<f64> = bracket
+            .paths
+            .iter()
+            .map(|path| {
+                let image = merge::load_rgb16(path).unwrap();
+                assert_eq!(image.dimensions(), (32, 24));
+                image.pixels().map(|p| p[1] as f64).sum::<f64>() / (32.0 * 24.0)
+            })
+            .collect();
+        assert!(means[0] < means[1] && means[1] < means[2]);
+    }
+
+    #[test]
+    fn same_seed_produces_identical_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = TestBracketOptions {
+            frames: 2,
+            width: 24,
+            height: 24,
+            seed: 7,
+            ..Default::default()
+        };
+
+        let first = generate_bracket(&dir.path().join("a"), &options).unwrap();
+        let second = generate_bracket(&dir.path().join("b"), &options).unwrap();
+
+        for (a, b) in first.paths.iter().zip(&second.paths) {
+            assert_eq!(std::fs::read(a).unwrap(), std::fs::read(b).unwrap());
+        }
+    }
+}
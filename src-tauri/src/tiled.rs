@@ -0,0 +1,180 @@
+use image::{imageops, GenericImage};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::algorithms::{AlgorithmParams, MergeAlgorithm, TileSupport};
+use crate::merge::Rgb16Image;
+
+const DEFAULT_TILE_SIZE: u32 = 1024;
+const MIN_TILE_SIZE: u32 = 256;
+const MAX_TILE_SIZE: u32 = 4096;
+
+// 原寸の作業領域を確保できず、タイルに分けて合成したことを示す
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryFallback {
+    // 原寸で一度に合成した場合の作業領域の見積もり
+    pub required_bytes: u64,
+    pub budget_bytes: Option<u64>,
+    pub tile_size: u32,
+}
+
+// 作業領域が予算を超えるか確保できない場合は、エラーにせずタイルに分けて合成する
+pub fn merge_frames(
+    algorithm: &dyn MergeAlgorithm,
+    frames: &[Rgb16Image],
+    params: &AlgorithmParams,
+    budget_bytes: Option<u64>,
+) -> Result<(Rgb16Image, Option<MemoryFallback>), String> {
+    let (width, height) = frames[0].dimensions();
+    let per_pixel = algorithm.working_bytes_per_pixel(frames.len());
+    let required_bytes = width as u64 * height as u64 * per_pixel;
+    if fits(required_bytes, budget_bytes) {
+        return Ok((algorithm.merge(frames, params)?, None));
+    }
+
+    let tile_size = tile_size(
+        per_pixel,
+        budget_bytes,
+        algorithm.tile_support(width, height),
+    );
+    let merged = merge_tiled(algorithm, frames, params, tile_size)?;
+    Ok((
+        merged,
+        Some(MemoryFallback {
+            required_bytes,
+            budget_bytes,
+            tile_size,
+        }),
+    ))
+}
+
+fn fits(required_bytes: u64, budget_bytes: Option<u64>) -> bool {
+    if budget_bytes.is_some_and(|budget| required_bytes > budget) {
+        return false;
+    }
+    // 確保できるかを先に試し、試した領域はすぐに手放す
+    usize::try_from(required_bytes)
+        .is_ok_and(|bytes| Vec::<u8>::new().try_reserve_exact(bytes).is_ok())
+}
+
+// 余白を含めたタイル1枚の作業領域が予算に収まる大きさ。余白が大きいと最小の大きさでも予算を超える
+fn tile_size(per_pixel: u64, budget_bytes: Option<u64>, support: TileSupport) -> u32 {
+    let Some(budget) = budget_bytes else {
+        return DEFAULT_TILE_SIZE;
+    };
+    let side = ((budget / per_pixel.max(1)) as f64).sqrt() as u32;
+    side.saturating_sub(2 * support.margin)
+        .clamp(MIN_TILE_SIZE, MAX_TILE_SIZE)
+}
+
+pub fn merge_tiled(
+    algorithm: &dyn MergeAlgorithm,
+    frames: &[Rgb16Image],
+    params: &AlgorithmParams,
+    tile_size: u32,
+) -> Result<Rgb16Image, String> {
+    let (width, height) = frames[0].dimensions();
+    let support = algorithm.tile_support(width, height);
+    // 余白を削った後のタイルの左上も揃うよう、大きさを揃える間隔の倍数にする
+    let alignment = support.alignment.max(1);
+    let tile_size = tile_size.max(1).div_ceil(alignment) * alignment;
+    let align = |value: u32| value / alignment * alignment;
+    let mut merged = Rgb16Image::new(width, height);
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            let tile_width = tile_size.min(width - x);
+            let tile_height = tile_size.min(height - y);
+            let left = align(x.saturating_sub(support.margin));
+            let top = align(y.saturating_sub(support.margin));
+            let right = (x + tile_width + support.margin).min(width);
+            let bottom = (y + tile_height + support.margin).min(height);
+            let crops: Vec<Rgb16Image> = frames
+                .iter()
+                .map(|frame| {
+                    imageops::crop_imm(frame, left, top, right - left, bottom - top).to_image()
+                })
+                .collect();
+
+            let tile = algorithm.merge_tile(&crops, params, (width, height))?;
+            let center = imageops::crop_imm(&tile, x - left, y - top, tile_width, tile_height);
+            merged
+                .copy_from(&*center, x, y)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms;
+    use image::Rgb;
+
+    fn frames() -> Vec<Rgb16Image> {
+        sized_frames(300, 170)
+    }
+
+    fn sized_frames(width: u32, height: u32) -> Vec<Rgb16Image> {
+        (0..3u32)
+            .map(|i| {
+                Rgb16Image::from_fn(width, height, |x, y| {
+                    Rgb([
+                        (x * 200 + i * 5000) as u16,
+                        (y * 300) as u16,
+                        (i * 20000) as u16,
+                    ])
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tiled_average_matches_full_resolution() {
+        let average = algorithms::find("average").unwrap();
+        let params = AlgorithmParams::default();
+        let frames = frames();
+
+        let tiled = merge_tiled(average, &frames, &params, 128).unwrap();
+
+        assert_eq!(tiled, average.merge(&frames, &params).unwrap());
+    }
+
+    #[test]
+    fn tiled_fusion_has_no_seams() {
+        let fusion = algorithms::find("fusion").unwrap();
+        let params = algorithms::resolve_params(fusion, &AlgorithmParams::default()).unwrap();
+        // 5 段のピラミッドで余白は 64 画素。中ほどのタイルは画像の一部だけを切り出す
+        let frames = sized_frames(600, 100);
+        assert_eq!(fusion.tile_support(600, 100).margin, 64);
+
+        let full = fusion.merge(&frames, &params).unwrap();
+        let tiled = merge_tiled(fusion, &frames, &params, 48).unwrap();
+
+        let largest = full
+            .as_raw()
+            .iter()
+            .zip(tiled.as_raw())
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(largest <= 2, "{}", largest);
+    }
+
+    #[test]
+    fn falls_back_to_tiles_over_budget() {
+        let fusion = algorithms::find("fusion").unwrap();
+        let params = algorithms::resolve_params(fusion, &AlgorithmParams::default()).unwrap();
+        let frames = frames();
+
+        let (full, fallback) = merge_frames(fusion, &frames, &params, None).unwrap();
+        assert!(fallback.is_none());
+
+        let (tiled, fallback) = merge_frames(fusion, &frames, &params, Some(1024 * 1024)).unwrap();
+        let fallback = fallback.unwrap();
+        assert_eq!(fallback.required_bytes, 300 * 170 * (3 * 32 + 24));
+        assert_eq!(fallback.tile_size, MIN_TILE_SIZE);
+        assert_eq!(tiled.dimensions(), full.dimensions());
+    }
+}
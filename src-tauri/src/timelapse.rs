@@ -0,0 +1,689 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::deflicker::{self, DeflickerFrame, DeflickerOptions, OutputEncoding};
+use crate::formats;
+use crate::gray_card::GrayCardCorrection;
+use crate::grouping::{self, GroupInput, GroupStatus, GroupingRules};
+use crate::levels::AppliedLevels;
+use crate::merge::{MergeMode, MergeRequest, MergeResult};
+use crate::output_lock::OutputReservation;
+use crate::output_path;
+use crate::paths;
+use crate::watch_filter::OwnOutputs;
+use crate::workdir::write_atomically;
+
+const DEFAULT_OUTPUT_FOLDER: &str = "timelapse";
+const DEFAULT_PREFIX: &str = "frame_";
+const SIDECAR_FILE: &str = "sequence.json";
+const SIDECAR_VERSION: u32 = 1;
+// 設定の出力先テンプレートで日付ごとに分かれないよう、連番はすべて出力先の直下に置く
+const FLAT_TEMPLATE: &str = "{outputRoot}";
+
+// インターバル撮影で繰り返したブラケットのフォルダを、すべて同じ設定で合成して連番で書き出す
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelapseRequest {
+    pub folder: String,
+    // 1ブラケットの分け方。インターバル撮影では framesPerBracket を指定することが多い
+    #[serde(default)]
+    pub grouping: GroupingRules,
+    // 各ブラケットに使う合成設定。paths は無視する（空の配列でよい）
+    pub settings: MergeRequest,
+    // 未指定なら folder の中の timelapse フォルダ
+    pub output_dir: Option<String>,
+    pub prefix: Option<String>,
+    #[serde(default = "default_start_number")]
+    pub start_number: u32,
+    #[serde(default = "default_digits")]
+    pub digits: usize,
+    // 編集ソフトの連番読み込みと同じ書式（例: `frame_%06d.exr`）。拡張子を exr にすると EXR の連番も書き出す。
+    // 指定すると prefix・digits は使わない
+    pub name_pattern: Option<String>,
+    // 指定すると、編集ソフトでクリップとして読み込むためのフレームレートなどを sequence.json に書く
+    pub frame_rate: Option<f64>,
+    // 指定すると、すべて合成したあとに連番の明るさをそろえて書き出し直す
+    pub deflicker: Option<DeflickerOptions>,
+}
+
+fn default_start_number() -> u32 {
+    1
+}
+
+fn default_digits() -> usize {
+    5
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelapseFrame {
+    pub number: u32,
+    pub paths: Vec<String>,
+    pub output_png_path: String,
+    pub output_exr_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedBracket {
+    pub key: String,
+    pub paths: Vec<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelapseResult {
+    pub output_dir: String,
+    pub frames: Vec<TimelapseFrame>,
+    // 枚数の足りないブラケット。連番は詰めて振るため欠番にはならない
+    pub skipped: Vec<SkippedBracket>,
+    // 先頭のフレームで測り、すべてのフレームにかけたグレーカードの補正
+    pub gray_card: Option<GrayCardCorrection>,
+    // 先頭のフレームで求め、すべてのフレームにかけた autoLevels の黒点・白点
+    #[serde(default)]
+    pub auto_levels: Option<AppliedLevels>,
+    // deflicker を指定したときの各フレームの補正
+    #[serde(default)]
+    pub deflicker: Vec<DeflickerFrame>,
+    // frameRate を指定したときの sequence.json
+    #[serde(default)]
+    pub sidecar_path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequenceSidecar {
+    pub version: u32,
+    pub frame_rate: f64,
+    pub start_number: u32,
+    pub frame_count: usize,
+    // 例: frame_%06d.png
+    pub png_pattern: String,
+    pub exr_pattern: Option<String>,
+}
+
+// `%d`・`%06d` の前後で分けた連番の名前（拡張子は含まない）
+#[derive(Debug, Clone, PartialEq)]
+struct SequenceName {
+    before: String,
+    width: usize,
+    after: String,
+}
+
+impl SequenceName {
+    // 戻り値の 2 つめは書式に付いていた拡張子
+    fn parse(pattern: &str) -> Result<(Self, Option<String>), String> {
+        let invalid = || {
+            format!(
+                "連番の書式は frame_%06d.exr のように %d を 1 つ含めてください: {}",
+                pattern
+            )
+        };
+        let (stem, extension) = match pattern.rsplit_once('.') {
+            Some((stem, extension)) if !extension.contains('%') => {
+                (stem, Some(extension.to_lowercase()))
+            }
+            _ => (pattern, None),
+        };
+        if extension
+            .as_deref()
+            .is_some_and(|extension| !matches!(extension, "png" | "exr"))
+        {
+            return Err(format!(
+                "連番の拡張子は png か exr にしてください: {}",
+                pattern
+            ));
+        }
+        let (before, rest) = stem.split_once('%').ok_or_else(invalid)?;
+        let (width, after) = rest.split_once('d').ok_or_else(invalid)?;
+        let width = match width {
+            "" => 0,
+            digits if digits.starts_with('0') => digits.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        if width > 9
+            || after.contains('%')
+            || before.contains(['/', '\\'])
+            || after.contains(['/', '\\'])
+        {
+            return Err(invalid());
+        }
+        Ok((
+            Self {
+                before: before.to_string(),
+                width,
+                after: after.to_string(),
+            },
+            extension,
+        ))
+    }
+
+    fn format(&self, number: u32) -> String {
+        format!(
+            "{}{:0width$}{}",
+            self.before,
+            number,
+            self.after,
+            width = self.width
+        )
+    }
+
+    fn pattern(&self, extension: &str) -> String {
+        let width = match self.width {
+            0 => String::new(),
+            width => format!("0{}", width),
+        };
+        format!("{}%{}d{}.{}", self.before, width, self.after, extension)
+    }
+}
+
+struct PlannedBracket {
+    paths: Vec<String>,
+    merge_mode: MergeMode,
+}
+
+// sequence.json の書き出し先の制限。lib.rs が設定から埋める
+pub struct SidecarTarget<'a> {
+    pub protected_dirs: &'a [PathBuf],
+    pub write_roots: &'a [PathBuf],
+    pub own_outputs: Option<&'a OwnOutputs>,
+}
+
+// ブラケットを 1 つずつ合成要求にして渡し、結果を受け取って連番を進める。
+// 合成はジョブの順番待ちを通すため、呼び出し側（lib.rs）が行う
+pub struct Sequence {
+    settings: MergeRequest,
+    output_dir: PathBuf,
+    name: SequenceName,
+    start_number: u32,
+    frame_rate: Option<f64>,
+    next_number: u32,
+    pending: std::vec::IntoIter<PlannedBracket>,
+    current: Option<Vec<String>>,
+    frames: Vec<TimelapseFrame>,
+    skipped: Vec<SkippedBracket>,
+    gray_card: Option<GrayCardCorrection>,
+    auto_levels: Option<AppliedLevels>,
+    // bracket で exifExposureCompensation を指定したとき、先頭のブラケットで読んだ EXIF の露出（暗い順）
+    exif_reference_evs: Option<Vec<f64>>,
+    // 合成中のブラケットが EXIF の露出の基準になる
+    measuring_exif_reference: bool,
+    deflicker: Option<DeflickerOptions>,
+}
+
+impl Sequence {
+    pub fn plan(request: &TimelapseRequest) -> Result<Self, String> {
+        let settings = &request.settings;
+        // ブラケットごとに使うフレームが変わると、フレームの間で明るさが揺れる
+        if settings.frame_selection.is_some() {
+            return Err("タイムラプスでは frameSelection を指定できません".to_string());
+        }
+        if settings.auto_exclude_bad_frames {
+            return Err("タイムラプスでは autoExcludeBadFrames を指定できません".to_string());
+        }
+        if let Some(options) = &request.deflicker {
+            options.validate()?;
+        }
+        let mut settings = settings.clone();
+        let name = match &request.name_pattern {
+            Some(pattern) => {
+                let (name, extension) = SequenceName::parse(pattern)?;
+                if extension.as_deref() == Some("exr") {
+                    settings.output_exr = true;
+                }
+                name
+            }
+            None if (1..=9).contains(&request.digits) => SequenceName {
+                before: request
+                    .prefix
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_PREFIX.to_string()),
+                width: request.digits,
+                after: String::new(),
+            },
+            None => return Err("連番の桁数は1〜9で指定してください".to_string()),
+        };
+        if request
+            .frame_rate
+            .is_some_and(|rate| !(rate.is_finite() && rate > 0.0))
+        {
+            return Err("frameRate は 0 より大きい値にしてください".to_string());
+        }
+        let folder = Path::new(&request.folder);
+        let inputs: Vec<GroupInput> = formats::list_decodable(folder)?
+            .into_iter()
+            .map(|path| GroupInput {
+                path: path.to_string_lossy().to_string(),
+                detected_at: None,
+            })
+            .collect();
+        if inputs.is_empty() {
+            return Err(format!("画像が見つかりません: {}", request.folder));
+        }
+
+        let mut planned = Vec::new();
+        let mut skipped = Vec::new();
+        for group in grouping::group_images(&inputs, &request.grouping)? {
+            if group.status != GroupStatus::Complete || group.paths.len() < 2 {
+                skipped.push(SkippedBracket {
+                    reason: format!("ブラケットの枚数が足りません（{}枚）", group.paths.len()),
+                    key: group.key,
+                    paths: group.paths,
+                });
+                continue;
+            }
+            planned.push(PlannedBracket {
+                paths: group.paths,
+                merge_mode: group.merge_mode,
+            });
+        }
+        if planned.is_empty() {
+            return Err("合成できるブラケットがありません".to_string());
+        }
+
+        Ok(Self {
+            settings,
+            output_dir: request
+                .output_dir
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| folder.join(DEFAULT_OUTPUT_FOLDER)),
+            name,
+            start_number: request.start_number,
+            frame_rate: request.frame_rate,
+            next_number: request.start_number,
+            pending: planned.into_iter(),
+            current: None,
+            frames: Vec::new(),
+            skipped,
+            gray_card: None,
+            auto_levels: None,
+            exif_reference_evs: None,
+            measuring_exif_reference: false,
+            deflicker: request.deflicker.clone(),
+        })
+    }
+
+    // next_request の次に合成するブラケットの入力。先読みに使う
+    pub fn upcoming_paths(&self) -> Option<Vec<String>> {
+        let bracket = self.pending.as_slice().first()?;
+        Some(bracket.paths.clone())
+    }
+
+    // 次に合成するブラケットの要求。すべて合成したら None
+    pub fn next_request(&mut self) -> Option<MergeRequest> {
+        let bracket = self.pending.next()?;
+        let mut request = MergeRequest {
+            paths: bracket.paths.clone(),
+            merge_mode: bracket.merge_mode,
+            output_dir: Some(self.output_dir.to_string_lossy().to_string()),
+            output_template: Some(FLAT_TEMPLATE.to_string()),
+            output_name: Some(self.name.format(self.next_number)),
+            // 多くのブラケットを裏で続けて合成するため、自動合成と同じく他のアプリの邪魔をしない
+            automatic: true,
+            ..self.settings.clone()
+        };
+        // 白バランスと露出の補正、レベル補正のカーブはフレームごとに測り直すとちらつくため、先頭で測った値を使い続ける。
+        // それ以外の合成・トーンマップの設定は settings（プリセットは先頭で反映済み）のまま変えない
+        if let Some(correction) = &self.gray_card {
+            request.gray_card = None;
+            request.gray_card_correction = Some(correction.clone());
+        }
+        request.levels_correction = self.auto_levels;
+        // bracket の中では露出を変えて撮っているため、ブラケットの間で同じ段のフレームの露出をそろえる
+        self.measuring_exif_reference = false;
+        if request.exif_exposure_compensation && request.merge_mode == MergeMode::Bracket {
+            self.measuring_exif_reference = self.exif_reference_evs.is_none();
+            request.exif_reference_evs = Some(self.exif_reference_evs.clone().unwrap_or_default());
+        }
+        self.current = Some(bracket.paths);
+        Some(request)
+    }
+
+    pub fn record(&mut self, result: &MergeResult) {
+        let paths = self.current.take().unwrap_or_default();
+        if self.gray_card.is_none() {
+            self.gray_card = result.gray_card.clone();
+        }
+        if self.auto_levels.is_none() {
+            self.auto_levels = result.auto_levels;
+        }
+        if self.measuring_exif_reference {
+            let mut evs: Vec<f64> = result
+                .exposure_compensation
+                .iter()
+                .map(|frame| frame.exif_ev)
+                .collect();
+            evs.sort_by(f64::total_cmp);
+            self.exif_reference_evs = Some(evs);
+        }
+        self.frames.push(TimelapseFrame {
+            number: self.next_number,
+            paths,
+            output_png_path: result.output_png_path.clone(),
+            output_exr_path: result.output_exr_path.clone(),
+        });
+        self.next_number += 1;
+    }
+
+    // deflicker を指定していれば、書き出した PNG の明るさをそろえて PNG・EXR を上書きする
+    pub fn finish(self, target: &SidecarTarget) -> Result<TimelapseResult, String> {
+        let deflickered = match &self.deflicker {
+            Some(options) if self.frames.len() >= 2 => {
+                let inputs: Vec<PathBuf> = self
+                    .frames
+                    .iter()
+                    .map(|frame| PathBuf::from(&frame.output_png_path))
+                    .collect();
+                let outputs: Vec<Vec<PathBuf>> = self
+                    .frames
+                    .iter()
+                    .map(|frame| {
+                        std::iter::once(&frame.output_png_path)
+                            .chain(&frame.output_exr_path)
+                            .map(PathBuf::from)
+                            .collect()
+                    })
+                    .collect();
+                deflicker::deflicker_files(
+                    &inputs,
+                    &outputs,
+                    options,
+                    &OutputEncoding::from(&self.settings),
+                )?
+            }
+            _ => Vec::new(),
+        };
+        let sidecar_path = match self.frame_rate {
+            Some(frame_rate) => {
+                let sidecar = SequenceSidecar {
+                    version: SIDECAR_VERSION,
+                    frame_rate,
+                    start_number: self.start_number,
+                    frame_count: self.frames.len(),
+                    png_pattern: self.name.pattern("png"),
+                    exr_pattern: self.settings.output_exr.then(|| self.name.pattern("exr")),
+                };
+                let path = self.output_dir.join(SIDECAR_FILE);
+                write_sidecar(&path, &sidecar, target)?;
+                Some(path.to_string_lossy().to_string())
+            }
+            None => None,
+        };
+        Ok(TimelapseResult {
+            output_dir: self.output_dir.to_string_lossy().to_string(),
+            frames: self.frames,
+            skipped: self.skipped,
+            gray_card: self.gray_card,
+            auto_levels: self.auto_levels,
+            deflicker: deflickered,
+            sidecar_path,
+        })
+    }
+}
+
+// 連番と同じく名前を変えられないため、書き出し中のジョブと重なればエラーにし、既存のものは上書きする
+fn write_sidecar(
+    path: &Path,
+    sidecar: &SequenceSidecar,
+    target: &SidecarTarget,
+) -> Result<(), String> {
+    let dir = path.parent().ok_or("連番の情報の保存先がありません")?;
+    output_path::ensure_outside_protected(dir, target.protected_dirs)?;
+    paths::ensure_writable(dir, target.write_roots)?;
+    let _reservation = OutputReservation::reserve_files(&[path.to_path_buf()])?;
+    let text = serde_json::to_string_pretty(sidecar).map_err(|e| e.to_string())?;
+    if let Some(own_outputs) = target.own_outputs {
+        own_outputs.record(path);
+    }
+    write_atomically(None, path, |partial| {
+        std::fs::write(partial, text).map_err(|e| e.to_string())
+    })
+    .map_err(|e| format!("連番の情報を保存できません: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exif::ExposureCompensation;
+    use crate::gray_card::{CardRegion, GrayCardSettings};
+    use crate::levels::AutoLevels;
+    use crate::merge;
+    use crate::output_color::{self, OutputColorSpace};
+    use crate::synthetic::{self, TestBracketOptions};
+
+    // IMG_0001.png から始まる連番で、3枚ずつのブラケットを brackets 回撮ったフォルダ
+    fn capture_folder(dir: &Path, brackets: usize, extra: usize) -> PathBuf {
+        let options = TestBracketOptions {
+            width: 48,
+            height: 32,
+            ..Default::default()
+        };
+        let bracket = synthetic::generate_bracket(&dir.join("source"), &options).unwrap();
+        let folder = dir.join("capture");
+        std::fs::create_dir_all(&folder).unwrap();
+        let count = brackets * bracket.paths.len() + extra;
+        for number in 0..count {
+            let source = &bracket.paths[number % bracket.paths.len()];
+            let target = folder.join(format!("IMG_{:04}.png", number + 1));
+            std::fs::copy(source, target).unwrap();
+        }
+        folder
+    }
+
+    fn request(folder: &Path) -> TimelapseRequest {
+        TimelapseRequest {
+            folder: folder.to_string_lossy().to_string(),
+            grouping: GroupingRules {
+                frames_per_bracket: Some(3),
+                max_gap_secs: None,
+                ..GroupingRules::default()
+            },
+            settings: MergeRequest::default(),
+            output_dir: None,
+            prefix: None,
+            start_number: 1,
+            digits: 5,
+            name_pattern: None,
+            frame_rate: None,
+            deflicker: None,
+        }
+    }
+
+    const UNRESTRICTED: SidecarTarget<'static> = SidecarTarget {
+        protected_dirs: &[],
+        write_roots: &[],
+        own_outputs: None,
+    };
+
+    fn run(sequence: &mut Sequence) {
+        while let Some(request) = sequence.next_request() {
+            let result = merge::run_merge(&request).unwrap();
+            sequence.record(&result);
+        }
+    }
+
+    #[test]
+    fn numbers_each_bracket_sequentially() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = capture_folder(dir.path(), 3, 1);
+
+        let mut sequence = Sequence::plan(&request(&folder)).unwrap();
+        run(&mut sequence);
+        let result = sequence.finish(&UNRESTRICTED).unwrap();
+
+        let names: Vec<String> = result
+            .frames
+            .iter()
+            .map(|frame| {
+                let path = Path::new(&frame.output_png_path);
+                assert!(path.starts_with(folder.join(DEFAULT_OUTPUT_FOLDER)));
+                path.file_name().unwrap().to_string_lossy().to_string()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            ["frame_00001.png", "frame_00002.png", "frame_00003.png"]
+        );
+        assert!(result.frames[1].paths[0].ends_with("IMG_0004.png"));
+        // 最後の 1 枚だけのブラケットは合成しない
+        assert_eq!(result.skipped.len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_first_gray_card_and_levels_corrections() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = capture_folder(dir.path(), 2, 0);
+        let mut request = request(&folder);
+        request.settings.gray_card = Some(GrayCardSettings {
+            region: CardRegion {
+                x: 0.4,
+                y: 0.4,
+                width: 0.2,
+                height: 0.2,
+            },
+            path: None,
+            target: None,
+        });
+        request.settings.auto_levels = Some(AutoLevels::default());
+
+        let mut sequence = Sequence::plan(&request).unwrap();
+        let first = sequence.next_request().unwrap();
+        assert!(first.gray_card.is_some());
+        assert!(first.levels_correction.is_none());
+        let first_result = merge::run_merge(&first).unwrap();
+        sequence.record(&first_result);
+        let second = sequence.next_request().unwrap();
+
+        assert!(first_result.auto_levels.is_some());
+        assert_eq!(second.levels_correction, first_result.auto_levels);
+
+        assert!(first.automatic && second.automatic);
+        assert!(second.gray_card.is_none());
+        assert_eq!(second.gray_card_correction, sequence.gray_card);
+        assert_eq!(second.output_name.as_deref(), Some("frame_00002"));
+    }
+
+    #[test]
+    fn matches_bracket_exposures_to_the_first_bracket() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = capture_folder(dir.path(), 2, 0);
+        let mut request = request(&folder);
+        request.settings.exif_exposure_compensation = true;
+
+        let mut sequence = Sequence::plan(&request).unwrap();
+        let first = sequence.next_request().unwrap();
+        assert!(first.exif_exposure_compensation);
+        assert_eq!(first.exif_reference_evs, Some(Vec::new()));
+        // テスト用のブラケットには EXIF がないため、補正の記録だけを差し替える
+        let mut result = merge::run_merge(&MergeRequest {
+            exif_exposure_compensation: false,
+            ..first.clone()
+        })
+        .unwrap();
+        result.exposure_compensation = first
+            .paths
+            .iter()
+            .zip([-2.0, -6.0, -4.0])
+            .map(|(path, exif_ev)| ExposureCompensation {
+                path: path.clone(),
+                exif_ev,
+                correction_ev: 0.0,
+            })
+            .collect();
+        sequence.record(&result);
+
+        let second = sequence.next_request().unwrap();
+        assert_eq!(second.exif_reference_evs, Some(vec![-6.0, -4.0, -2.0]));
+    }
+
+    #[test]
+    fn deflickers_the_written_frames_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = capture_folder(dir.path(), 3, 0);
+        let mut request = request(&folder);
+        request.settings.output_exr = true;
+        request.settings.output_color_space = OutputColorSpace::DisplayP3;
+        request.deflicker = Some(DeflickerOptions {
+            window: 3,
+            ..DeflickerOptions::default()
+        });
+
+        let mut sequence = Sequence::plan(&request).unwrap();
+        run(&mut sequence);
+        let result = sequence.finish(&UNRESTRICTED).unwrap();
+
+        assert_eq!(result.deflicker.len(), 3);
+        assert_eq!(result.deflicker[1].output_paths.len(), 2);
+        assert_eq!(result.deflicker[0].path, result.frames[0].output_png_path);
+        // 書き直した PNG も合成時と同じプロファイルを持つ
+        let file = std::fs::File::open(&result.frames[1].output_png_path).unwrap();
+        let reader = png::Decoder::new(file).read_info().unwrap();
+        assert_eq!(
+            reader.info().icc_profile.as_deref(),
+            output_color::icc_profile(OutputColorSpace::DisplayP3).as_deref()
+        );
+    }
+
+    #[test]
+    fn names_frames_with_the_sequence_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = capture_folder(dir.path(), 2, 0);
+        let mut request = request(&folder);
+        request.name_pattern = Some("shot_%06d.exr".to_string());
+        request.frame_rate = Some(24.0);
+        request.start_number = 0;
+
+        let mut sequence = Sequence::plan(&request).unwrap();
+        run(&mut sequence);
+        let own_outputs = OwnOutputs::default();
+        let result = sequence
+            .finish(&SidecarTarget {
+                own_outputs: Some(&own_outputs),
+                ..UNRESTRICTED
+            })
+            .unwrap();
+
+        let exr = result.frames[1].output_exr_path.as_deref().unwrap();
+        assert!(exr.ends_with("shot_000001.exr"), "{}", exr);
+        let sidecar_path = result.sidecar_path.unwrap();
+        // 監視フォルダの中に書き出しても検出し直さない
+        assert!(own_outputs.contains(Path::new(&sidecar_path)));
+        let text = std::fs::read_to_string(&sidecar_path).unwrap();
+        let sidecar: SequenceSidecar = serde_json::from_str(&text).unwrap();
+        assert_eq!(sidecar.frame_count, 2);
+        assert_eq!(sidecar.start_number, 0);
+        assert_eq!(sidecar.png_pattern, "shot_%06d.png");
+        assert_eq!(sidecar.exr_pattern.as_deref(), Some("shot_%06d.exr"));
+    }
+
+    #[test]
+    fn parses_printf_style_patterns() {
+        let (name, extension) = SequenceName::parse("frame_%04d_hdr").unwrap();
+        assert_eq!(name.format(12), "frame_0012_hdr");
+        assert_eq!(extension, None);
+        assert_eq!(SequenceName::parse("%d.png").unwrap().0.format(7), "7");
+        for invalid in [
+            "frame.png",
+            "frame_%6d",
+            "a_%d_%d",
+            "frame_%06d.tif",
+            "../%d",
+        ] {
+            assert!(SequenceName::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn rejects_settings_that_vary_per_bracket() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = capture_folder(dir.path(), 2, 0);
+        let mut request = request(&folder);
+        request.settings.auto_exclude_bad_frames = true;
+
+        assert!(Sequence::plan(&request).is_err());
+    }
+}
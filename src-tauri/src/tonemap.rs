@@ -0,0 +1,150 @@
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::algorithms::{self, Plane};
+use crate::color::{
+    linear_to_srgb, luminance, srgb_to_linear, srgb_to_linear_table, u16_to_unit, unit_to_u16,
+};
+use crate::merge::Rgb16Image;
+use crate::simd;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TonemapParams {
+    // トーンマッピング前に掛ける露出補正（EV）
+    pub exposure: f32,
+    // この輝度（リニア）が白になるよう圧縮する
+    pub white_point: f32,
+    // 局所コントラスト（明るさの中くらいの起伏）を強める量。0 なら全体のトーンカーブだけ、1 で起伏を2倍にする。
+    // ダイナミックレンジの広い場面を圧縮すると眠くなるのを補う
+    pub clarity: f32,
+}
+
+impl Default for TonemapParams {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            white_point: 4.0,
+            clarity: 0.0,
+        }
+    }
+}
+
+// 一番細かい段はノイズが多いため強めず、これより粗い段だけを強める
+const CLARITY_FINEST_LEVEL: usize = 1;
+// ピラミッドの一番粗い段の短辺の目安
+const CLARITY_BASE_SIZE: u32 = 16;
+// 局所コントラストで変える明るさの上限（EV）。強い境界のハローを抑える
+const MAX_CLARITY_EV: f32 = 1.0;
+
+// 輝度に拡張 Reinhard を適用し、色比を保ったまま RGB を縮める
+// gains（グレーカードの補正）は露出補正と同じく圧縮の前に線形 RGB へかけるので、1 を超えてもクリップしない
+pub fn tonemap(image: &Rgb16Image, params: &TonemapParams, gains: [f32; 3]) -> Rgb16Image {
+    let gain = 2f32.powf(params.exposure);
+    let white_squared = params.white_point * params.white_point;
+
+    let to_linear = srgb_to_linear_table();
+    let [mut r, mut g, mut b] = [0, 1, 2].map(|channel| {
+        image
+            .pixels()
+            .map(|pixel| to_linear[pixel.0[channel] as usize])
+            .collect::<Vec<f32>>()
+    });
+    simd::reinhard_planes(&mut r, &mut g, &mut b, gain, gains, white_squared);
+
+    let mut output = image.clone();
+    for (i, pixel) in output.pixels_mut().enumerate() {
+        *pixel = Rgb([r[i], g[i], b[i]].map(|v| unit_to_u16(linear_to_srgb(v))));
+    }
+    if params.clarity > 0.0 {
+        apply_clarity(&mut output, params.clarity);
+    }
+    output
+}
+
+// 対数輝度のラプラシアンピラミッドの中間の段を強めて戻し、輝度の差だけ RGB に掛ける
+fn apply_clarity(image: &mut Rgb16Image, clarity: f32) {
+    let log_luma: Vec<f32> = image
+        .pixels()
+        .map(|pixel| {
+            luminance(pixel.0.map(|v| srgb_to_linear(u16_to_unit(v))))
+                .max(1e-4)
+                .log2()
+        })
+        .collect();
+    let short_side = image.width().min(image.height()) / CLARITY_BASE_SIZE;
+    let levels = (short_side.max(1).ilog2() as usize + 1).min(8);
+    if levels <= CLARITY_FINEST_LEVEL + 1 {
+        return;
+    }
+    let plane = Plane {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        data: log_luma.clone(),
+    };
+    let mut pyramid = algorithms::laplacian_pyramid(&plane, levels);
+    // 最後の段は平均の明るさなので変えない
+    for band in &mut pyramid[CLARITY_FINEST_LEVEL..levels - 1] {
+        for value in &mut band.data {
+            *value *= 1.0 + clarity;
+        }
+    }
+    let boosted = algorithms::collapse(&pyramid);
+
+    for ((pixel, before), after) in image.pixels_mut().zip(&log_luma).zip(&boosted.data) {
+        let gain = 2f32.powf((after - before).clamp(-MAX_CLARITY_EV, MAX_CLARITY_EV));
+        *pixel = Rgb(pixel.0.map(|v| {
+            unit_to_u16(linear_to_srgb(
+                (srgb_to_linear(u16_to_unit(v)) * gain).min(1.0),
+            ))
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 左右で明るさの違う面に、細かい明暗の縞を重ねた画像
+    fn striped_scene() -> Rgb16Image {
+        Rgb16Image::from_fn(128, 128, |x, y| {
+            let base = if x < 64 { 0.05 } else { 0.6 };
+            let stripe = if (y / 8) % 2 == 0 { 1.3 } else { 0.7 };
+            Rgb([unit_to_u16(linear_to_srgb(base * stripe)); 3])
+        })
+    }
+
+    fn contrast(image: &Rgb16Image, x: u32) -> i32 {
+        image.get_pixel(x, 4).0[0] as i32 - image.get_pixel(x, 12).0[0] as i32
+    }
+
+    #[test]
+    fn clarity_boosts_local_contrast() {
+        let scene = striped_scene();
+        let flat = tonemap(&scene, &TonemapParams::default(), [1.0; 3]);
+        let clear = tonemap(
+            &scene,
+            &TonemapParams {
+                clarity: 1.0,
+                ..Default::default()
+            },
+            [1.0; 3],
+        );
+
+        assert!(contrast(&clear, 32) > contrast(&flat, 32));
+        assert!(contrast(&clear, 96) > contrast(&flat, 96));
+        // 0 なら全体のトーンカーブだけ
+        assert_eq!(
+            tonemap(&scene, &TonemapParams::default(), [1.0; 3]),
+            tonemap(
+                &scene,
+                &TonemapParams {
+                    clarity: 0.0,
+                    ..Default::default()
+                },
+                [1.0; 3],
+            )
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::formats;
+
+// 書き出し直後の作成・変更イベントを拾えれば十分なので、一定時間で記録を捨てる
+const OWN_OUTPUT_TTL: Duration = Duration::from_secs(600);
+
+// アプリ自身が書き出したファイル。出力先が監視フォルダ内でも検出し直さないようにする
+#[derive(Debug, Default)]
+pub struct OwnOutputs {
+    written: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+impl OwnOutputs {
+    // 書き込み前に呼ぶ。書き込み中のイベントも除外できるよう、フォルダだけ作成済みであればよい
+    pub fn record(&self, path: &Path) {
+        let mut written = self.written.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        written.retain(|_, at| now.duration_since(*at) < OWN_OUTPUT_TTL);
+        written.insert(normalize(path), now);
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.written
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&normalize(path))
+            .is_some_and(|at| at.elapsed() < OWN_OUTPUT_TTL)
+    }
+}
+
+// 検出の重複判定と書き込み完了の待ち方。RAW は JPEG より書き込みに時間がかかるため拡張子ごとに上書きできる
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WatchTiming {
+    // 同じファイルのイベントをこの時間（ms）は重複として捨てる
+    pub debounce_ms: u64,
+    // 大きさと更新日時がこの時間（ms）変わらなくなるまで検出を待つ。0 なら読めた時点で検出する
+    pub stable_ms: u64,
+    // 拡張子（ドットなし、大文字小文字は区別しない）ごとの上書き
+    pub extensions: BTreeMap<String, ExtensionTiming>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExtensionTiming {
+    pub debounce_ms: Option<u64>,
+    pub stable_ms: Option<u64>,
+}
+
+impl Default for WatchTiming {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 500,
+            stable_ms: 0,
+            extensions: BTreeMap::new(),
+        }
+    }
+}
+
+impl WatchTiming {
+    // ファイルに当てはまる重複判定の時間と安定待ちの時間
+    pub fn for_path(&self, path: &Path) -> (Duration, Duration) {
+        let overrides = formats::extension_of(path).and_then(|extension| {
+            self.extensions
+                .iter()
+                .find(|(key, _)| key.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+                .map(|(_, timing)| timing)
+        });
+        let debounce = overrides
+            .and_then(|timing| timing.debounce_ms)
+            .unwrap_or(self.debounce_ms);
+        let stable = overrides
+            .and_then(|timing| timing.stable_ms)
+            .unwrap_or(self.stable_ms);
+        (
+            Duration::from_millis(debounce),
+            Duration::from_millis(stable),
+        )
+    }
+}
+
+// 設定の watchIgnoreDirs を比較用に正規化する
+pub fn ignore_dirs(dirs: &[String]) -> Vec<PathBuf> {
+    dirs.iter()
+        .filter(|dir| !dir.trim().is_empty())
+        .map(|dir| normalize(Path::new(dir)))
+        .collect()
+}
+
+pub fn is_ignored(path: &Path, ignore_dirs: &[PathBuf]) -> bool {
+    let path = normalize(path);
+    ignore_dirs.iter().any(|dir| path.starts_with(dir))
+}
+
+// ファイル自体がまだ無くても比較できるよう、親フォルダを実パスにしてファイル名を付け直す
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_recorded_outputs_by_real_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("out")).unwrap();
+        let own = OwnOutputs::default();
+
+        own.record(&dir.path().join("out").join("hdr_merge_1.png"));
+        std::fs::write(dir.path().join("out").join("hdr_merge_1.png"), b"").unwrap();
+
+        assert!(own.contains(&dir.path().join("out/./hdr_merge_1.png")));
+        assert!(own.contains(&dir.path().join("out/../out/hdr_merge_1.png")));
+        assert!(!own.contains(&dir.path().join("out").join("IMG_0001.JPG")));
+    }
+
+    #[test]
+    fn ignores_files_below_configured_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("merged/2024")).unwrap();
+        let dirs = ignore_dirs(&[
+            dir.path().join("merged").to_string_lossy().to_string(),
+            " ".to_string(),
+        ]);
+
+        assert_eq!(dirs.len(), 1);
+        assert!(is_ignored(&dir.path().join("merged/2024/a.png"), &dirs));
+        assert!(!is_ignored(&dir.path().join("merged_old/a.png"), &dirs));
+    }
+
+    #[test]
+    fn timing_can_be_overridden_per_extension() {
+        let timing: WatchTiming = serde_json::from_value(serde_json::json!({
+            "stableMs": 200,
+            "extensions": { ".CR2": { "debounceMs": 2000, "stableMs": 1500 }, "nef": { "stableMs": 3000 } }
+        }))
+        .unwrap();
+        let ms = Duration::from_millis;
+
+        assert_eq!(
+            timing.for_path(Path::new("IMG_0001.cr2")),
+            (ms(2000), ms(1500))
+        );
+        assert_eq!(
+            timing.for_path(Path::new("DSC_0001.NEF")),
+            (ms(500), ms(3000))
+        );
+        assert_eq!(
+            timing.for_path(Path::new("IMG_0001.jpg")),
+            (ms(500), ms(200))
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use chrono::Local;
+
+use crate::merge::PARTIAL_SUFFIX;
+use crate::paths;
+
+// アプリのキャッシュフォルダ内に作るジョブごとの作業フォルダの置き場所
+pub const WORKDIR_CACHE: &str = "jobs";
+const FAILURE_FILE: &str = "failure.txt";
+// 調査用に残す失敗した作業フォルダの数と期間
+const MAX_KEPT_FAILURES: usize = 10;
+const MAX_FAILURE_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+static NEXT_WORKDIR: AtomicU64 = AtomicU64::new(0);
+
+// 合成の途中のファイル（書き込み途中の出力など）を置くフォルダ。出力フォルダには完成したファイルだけを移す
+#[derive(Debug)]
+pub struct JobWorkdir {
+    path: PathBuf,
+}
+
+impl JobWorkdir {
+    pub fn create(cache_dir: &Path, job_id: Option<&str>) -> Result<Self, String> {
+        let label: String = job_id
+            .unwrap_or("job")
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let name = format!(
+            "{}_{}_{}",
+            Local::now().format("%Y%m%d_%H%M%S"),
+            NEXT_WORKDIR.fetch_add(1, Ordering::Relaxed) + 1,
+            label
+        );
+        let path = cache_dir.join(WORKDIR_CACHE).join(name);
+        fs::create_dir_all(paths::extended(&path))
+            .map_err(|e| format!("作業フォルダを作成できません: {}", e))?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // 成功・中止なら消す。失敗したときは原因を書き残し、調査できるようにフォルダを残してその場所を返す
+    pub fn finish(self, failure: Option<&str>) -> Option<PathBuf> {
+        match failure {
+            None => {
+                let _ = fs::remove_dir_all(paths::extended(&self.path));
+                None
+            }
+            Some(message) => {
+                let text = format!("time: {}\nerror: {}\n", Local::now().to_rfc3339(), message);
+                let _ = fs::write(self.path.join(FAILURE_FILE), text);
+                Some(self.path)
+            }
+        }
+    }
+}
+
+// 起動時に、失敗して残した作業フォルダ（異常終了で残ったものを含む）を片付ける。
+// 新しい順に MAX_KEPT_FAILURES 件まで、MAX_FAILURE_AGE より新しいものだけを残し、消した数を返す
+pub fn prune(cache_dir: &Path, now: SystemTime) -> usize {
+    let Ok(entries) = fs::read_dir(paths::extended(&cache_dir.join(WORKDIR_CACHE))) else {
+        return 0;
+    };
+    let mut dirs: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| {
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, entry.path())
+        })
+        .collect();
+    dirs.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    let mut removed = 0;
+    for (index, (modified, path)) in dirs.into_iter().enumerate() {
+        let expired = now
+            .duration_since(modified)
+            .is_ok_and(|age| age > MAX_FAILURE_AGE);
+        if (index >= MAX_KEPT_FAILURES || expired) && fs::remove_dir_all(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+// 書き込み途中のファイルの置き場所。作業フォルダがなければ出力の隣に置く
+pub fn partial_path(workdir: Option<&Path>, target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(PARTIAL_SUFFIX);
+    match workdir {
+        Some(dir) => paths::extended(dir).join(name),
+        None => target.with_file_name(name),
+    }
+}
+
+// 作業フォルダが別のドライブにあると名前の変更では移せないため、出力の隣に複製してから置き換える
+pub fn move_into_place(partial: &Path, target: &Path) -> Result<(), String> {
+    if fs::rename(partial, target).is_ok() {
+        return Ok(());
+    }
+    let beside = partial_path(None, target);
+    let result = fs::copy(partial, &beside)
+        .map_err(|e| e.to_string())
+        .and_then(|_| fs::rename(&beside, target).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = fs::remove_file(&beside);
+    }
+    let _ = fs::remove_file(partial);
+    result
+}
+
+// 書き込み途中で終了しても出力名の壊れたファイルが残らないよう、一時ファイルから名前を変更する
+pub fn write_atomically(
+    workdir: Option<&Path>,
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let path = paths::extended(path);
+    let partial_path = partial_path(workdir, &path);
+
+    let result = write(&partial_path).and_then(|_| move_into_place(&partial_path, &path));
+    // 作業フォルダの中のものは失敗の調査用に残す
+    if result.is_err() && workdir.is_none() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_on_success_and_keeps_failures() {
+        let cache = tempfile::tempdir().unwrap();
+
+        let succeeded = JobWorkdir::create(cache.path(), Some("job/1")).unwrap();
+        let path = succeeded.path().to_path_buf();
+        assert!(path.starts_with(cache.path().join(WORKDIR_CACHE)));
+        assert!(path.to_string_lossy().ends_with("_job_1"));
+        fs::write(path.join("hdr.png.partial"), b"123").unwrap();
+        assert_eq!(succeeded.finish(None), None);
+        assert!(!path.exists());
+
+        let failed = JobWorkdir::create(cache.path(), None).unwrap();
+        fs::write(failed.path().join("hdr.png.partial"), b"123").unwrap();
+        let kept = failed.finish(Some("EXR を書き出せません")).unwrap();
+        assert!(kept.join("hdr.png.partial").exists());
+        let failure = fs::read_to_string(kept.join(FAILURE_FILE)).unwrap();
+        assert!(failure.contains("error: EXR を書き出せません"));
+    }
+
+    #[test]
+    fn prunes_old_and_excess_failures() {
+        let cache = tempfile::tempdir().unwrap();
+        let kept: Vec<PathBuf> = (0..MAX_KEPT_FAILURES + 2)
+            .map(|_| {
+                let workdir = JobWorkdir::create(cache.path(), None).unwrap();
+                workdir.finish(Some("失敗")).unwrap()
+            })
+            .collect();
+        let now = SystemTime::now();
+
+        // 新しさの差がないため、どれを消すかではなく件数だけを確かめる
+        assert_eq!(prune(cache.path(), now), 2);
+        let remaining = || {
+            fs::read_dir(cache.path().join(WORKDIR_CACHE))
+                .unwrap()
+                .count()
+        };
+        assert_eq!(remaining(), MAX_KEPT_FAILURES);
+        assert_eq!(prune(cache.path(), now), 0);
+        assert_eq!(
+            prune(cache.path(), now + MAX_FAILURE_AGE * 2),
+            MAX_KEPT_FAILURES
+        );
+        assert_eq!(remaining(), 0);
+        assert!(kept.iter().all(|path| !path.exists()));
+        assert_eq!(prune(&cache.path().join("missing"), now), 0);
+    }
+
+    #[test]
+    fn moves_partial_files_out_of_the_workdir() {
+        let cache = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let workdir = JobWorkdir::create(cache.path(), None).unwrap();
+        let target = output.path().join("hdr.png");
+
+        let partial = partial_path(Some(workdir.path()), &target);
+        assert!(partial.starts_with(paths::extended(workdir.path())));
+        fs::write(&partial, b"png").unwrap();
+        move_into_place(&partial, &target).unwrap();
+
+        assert_eq!(fs::read(&target).unwrap(), b"png");
+        assert!(!partial.exists());
+        assert_eq!(fs::read_dir(output.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+// 合成を走らせるスレッドの OS 上の優先度。同じ PC で録画やゲームをしていても処理落ちさせないよう下げられる
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum WorkerPriority {
+    #[default]
+    Normal,
+    // Windows はバックグラウンドモード（CPU に加えてディスク I/O・メモリの優先度も下がる）、
+    // Linux は nice 10、macOS はスレッドのバックグラウンド指定
+    Background,
+}
+
+// 下げた優先度は元に戻せない OS があるため、Background では使い捨てのスレッドで実行して終わるまで待つ。
+// 優先度を下げられなくても合成は続ける
+pub fn run<T: Send>(priority: WorkerPriority, job: impl FnOnce() -> T + Send) -> T {
+    if priority == WorkerPriority::Normal {
+        return job();
+    }
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("merge-worker".to_string())
+            .spawn_scoped(scope, || {
+                let _ = lower_current_thread();
+                job()
+            })
+            .expect("合成用のスレッドを作成できません")
+            .join()
+            .unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+    })
+}
+
+#[cfg(windows)]
+fn lower_current_thread() -> bool {
+    use std::ffi::c_void;
+
+    const THREAD_MODE_BACKGROUND_BEGIN: i32 = 0x0001_0000;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadPriority(thread: *mut c_void, priority: i32) -> i32;
+    }
+
+    // SAFETY: 自スレッドの疑似ハンドルを渡している
+    unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) != 0 }
+}
+
+// Linux の nice はスレッドごとに効き、PRIO_PROCESS・0 は呼び出したスレッドだけを指す
+#[cfg(target_os = "linux")]
+fn lower_current_thread() -> bool {
+    extern "C" {
+        fn setpriority(which: i32, who: u32, priority: i32) -> i32;
+    }
+
+    // SAFETY: 引数は値渡しのみ
+    unsafe { setpriority(0, 0, 10) == 0 }
+}
+
+#[cfg(target_os = "macos")]
+fn lower_current_thread() -> bool {
+    const PRIO_DARWIN_THREAD: i32 = 3;
+    const PRIO_DARWIN_BG: i32 = 0x1000;
+
+    extern "C" {
+        fn setpriority(which: i32, who: u32, priority: i32) -> i32;
+    }
+
+    // SAFETY: 引数は値渡しのみ
+    unsafe { setpriority(PRIO_DARWIN_THREAD, 0, PRIO_DARWIN_BG) == 0 }
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+fn lower_current_thread() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    fn nice() -> i32 {
+        extern "C" {
+            fn getpriority(which: i32, who: u32) -> i32;
+        }
+        // SAFETY: 引数は値渡しのみ
+        unsafe { getpriority(0, 0) }
+    }
+
+    #[test]
+    fn runs_the_job_on_a_lowered_thread_only() {
+        assert_eq!(run(WorkerPriority::Normal, || 1 + 1), 2);
+        let caller = std::thread::current().id();
+        let (value, worker) = run(WorkerPriority::Background, || {
+            (3, std::thread::current().id())
+        });
+        assert_eq!(value, 3);
+        assert_ne!(worker, caller);
+
+        #[cfg(target_os = "linux")]
+        {
+            let before = nice();
+            let lowered = run(WorkerPriority::Background, nice);
+            assert!(lowered > before || before >= 10);
+            assert_eq!(nice(), before);
+        }
+    }
+}
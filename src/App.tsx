@@ -2,6 +2,7 @@ import { useEffect, useMemo, useState } from "react";
 import { convertFileSrc, invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { open } from "@tauri-apps/plugin-dialog";
+import type { EventPayloads, ImageStat, MergeResult } from "./types/api";
 
 const GROUP_WINDOW_MS = 2 * 60 * 1000;
 const MAX_GROUP_IMAGES = 5;
@@ -17,19 +18,6 @@ type ImageGroup = {
   images: DetectedImage[];
 };
 
-type ImageStat = {
-  path: string;
-  averageLuma: number;
-};
-
-type MergeResult = {
-  outputPngPath: string;
-  outputExrPath?: string | null;
-  width: number;
-  height: number;
-  mergedAt: string;
-};
-
 export default function App() {
   const [watchFolder, setWatchFolder] = useState("");
   const [isWatching, setIsWatching] = useState(false);
@@ -70,7 +58,7 @@ export default function App() {
   }, []);
 
   useEffect(() => {
-    const unlistenPromise = listen<string>("hdr://file-detected", (event) => {
+    const unlistenPromise = listen<EventPayloads["hdr://file-detected"]>("hdr://file-detected", (event) => {
       const path = event.payload;
       const detectedAt = Date.now();
 
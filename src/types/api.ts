@@ -0,0 +1,377 @@
+// src-tauri/src/api_schema.rs から生成したファイルです。直接編集せず、
+// VHDR_UPDATE_API_TYPES=1 cargo test api_schema で書き出し直してください
+
+export type AlgorithmInfo = { name: string, label: string, description: string, parameters: Array<ParameterSchema>, };
+
+export type AlignModel = "translation" | "similarity";
+
+export type AlignParams = { maxShift: number, model: AlignModel, maxRotation: number, maxScale: number, alignPrecision: AlignPrecision, };
+
+export type AlignPrecision = "pixel" | "subpixel";
+
+export type AlignTransform = { dx: number, dy: number, rotation: number, scale: number, };
+
+export type AnalysisBatch<T> = { jobId: string, stats: Array<T>, errors: Array<AnalysisError>, processed: number, total: number, done: boolean, cancelled: boolean, };
+
+export type AnalysisError = { path: string, message: string, };
+
+export type ApiNegotiation = { apiVersion: number, latestVersion: number, minVersion: number, features: Array<string>, capabilities: Capabilities, };
+
+export type AppliedLevels = { blackPercentile: number, whitePercentile: number, blackPoint: number, whitePoint: number, };
+
+export type AutoLevels = { blackPercentile: number, whitePercentile: number, };
+
+export type BatchItem = { paths: Array<string>, outputPngPath: string | null, outputExrPath: string | null, width: number | null, height: number | null, algorithm: string | null, durationMs: number | null, error: string | null, };
+
+export type BatchRequest = { requests: Array<MergeRequest>, reportDir: string | null, };
+
+export type BatchResult = { summaryPath: string, contactSheetPath: string | null, contactSheetError: string | null, summary: BatchSummary, };
+
+export type BatchSummary = { version: number, startedAt: string, finishedAt: string, succeeded: number, failed: number, cancelled: boolean, items: Array<BatchItem>, };
+
+export type BracketGroup = { key: string, id: string, paths: Array<string>, firstDetectedAt: number, lastDetectedAt: number, status: GroupStatus, expectedFrames: number | null, timeoutAction: IncompletePolicy | null, mergeMode: MergeMode, };
+
+export type BracketRecommendation = { frames: number, evSpacing: number, centerEv: number, sceneRangeEv: number, clippedHighlights: number, clippedShadows: number, exceedsMaxFrames: boolean, };
+
+export type CacheLimits = { thumbnailMaxMb: number, analysisMaxMb: number, };
+
+export type CacheUsage = { name: string, entries: number, bytes: number, maxBytes: number, };
+
+export type Capabilities = { version: string, inputFormats: Array<string>, watchOnlyFormats: Array<string>, inspectFormats: Array<string>, outputFormats: Array<string>, algorithms: Array<AlgorithmInfo>, pipelineStages: Array<string>, outputTemplatePlaceholders: Array<string>, gpuAvailable: boolean, maxMergeFrames: number, maxSelectionCandidates: number, maxDecodeBytes: number | null, imageLimits: ImageLimits, cpuThreads: number, };
+
+export type CardRegion = { x: number, y: number, width: number, height: number, };
+
+export type CleanupReport = { removedFiles: number, bytesReclaimed: number, errors: Array<string>, };
+
+export type ClipboardCopy = { sourcePath: string, width: number, height: number, };
+
+export type ClippingSummary = { path: string, unrecoverableFraction: number, recoveredFraction: number, };
+
+export type ColorOptions = { grayCard: GrayCardSettings | null, autoLevels: AutoLevels | null, };
+
+export type CompareResult = { ssim: number, psnr: number | null, heatmapPath: string, width: number, height: number, };
+
+export type CompareTiles = { level: number, tileSize: number, width: number, height: number, tiles: Array<TilePair>, };
+
+export type DashboardSummary = { watching: boolean, folder: string | null, pendingFiles: number, queueDepth: number, lastResultPath: string | null, errorCount: number, idle: boolean, };
+
+export type DefaultOutputDir = { "kind": "inputFolder" } | { "kind": "subfolder", name: string | null, } | { "kind": "mirroredTree", root: string, sourceRoot: string | null, };
+
+export type DeflickerFrame = { path: string, outputPaths: Array<string>, measuredEv: number, correctionEv: number, };
+
+export type DeflickerOptions = { window: number, strength: number, outputDir: string | null, };
+
+export type DeflickerResult = { outputDir: string, frames: Array<DeflickerFrame>, };
+
+export type DeghostParams = { threshold: number, };
+
+export type DeleteOutcome = { path: string, deleted: boolean, error: string | null, };
+
+export type DeleteReport = { results: Array<DeleteOutcome>, deletedCount: number, failedCount: number, };
+
+export type Deliverable = { format: DeliverableFormat, suffix: string, maxSize: number | null, bitDepth: number | null, quality: number | null, print: PrintSettings | null, };
+
+export type DeliverableFormat = "png" | "jpeg" | "tiff" | "exr";
+
+export type DeliverableOutput = { suffix: string, format: DeliverableFormat, path: string, width: number, height: number, print: PrintLayout | null, inkWarning: InkWarning | null, };
+
+export type DenoiseParams = { strength: number, };
+
+export type Detection = { path: string, detectedAt: string, eventKind: DetectionKind, outcome: DetectionOutcome, };
+
+export type DetectionKind = "create" | "modify";
+
+export type DetectionOutcome = "detected" | "debounced" | "unsupportedExtension" | "ownOutput" | "ignoredDir" | "incomplete";
+
+export type DropSuggestion = { "kind": "folder", path: string, imageCount: number, } | { "kind": "merge" } & LaunchRequest | { "kind": "preview", path: string, } | { "kind": "unsupported", reason: string, };
+
+export type EncodeParams = { sharpen: SharpenParams | null, };
+
+export type ExposureCompensation = { path: string, exifEv: number, correctionEv: number, };
+
+export type ExrColorSpace = "linearRec709" | "rec2020" | "acesCg";
+
+export type ExtensionTiming = { debounceMs: number | null, stableMs: number | null, };
+
+export type ExternalParams = { command: string, args: Array<string>, timeoutSecs: number, };
+
+export type FalseColorBand = { label: string, color: [number, number, number], fraction: number, };
+
+export type FalseColorMode = "ire" | "stops" | "zebra";
+
+export type FalseColorResult = { path: string, mode: FalseColorMode, width: number, height: number, bands: Array<FalseColorBand>, };
+
+export type FixedSettings = { outputName: string | null, exifReferenceEvs: Array<number> | null, grayCardCorrection: GrayCardCorrection | null, levelsCorrection: AppliedLevels | null, presetApplied: boolean, sendTargets: Array<SendTarget>, };
+
+export type FrameExposure = { path: string, ev: number, };
+
+export type FrameOptions = { selection: FrameSelection | null, allowPartial: boolean, autoExcludeBadFrames: boolean, exifExposureCompensation: boolean, };
+
+export type FrameQuality = { scores: Array<FrameScore>, recommendedExclusions: Array<string>, };
+
+export type FrameScore = { path: string, ev: number, sharpness: number, inconsistency: number, excludeReason: string | null, };
+
+export type FrameSelection = { maxFrames: number, minEvStep: number, };
+
+export type GeometryOptions = { roi: Roi | null, perspective: PerspectiveCorners | null, autoStraighten: boolean, transformsPath: string | null, };
+
+export type GrayCardCorrection = { measured: [number, number, number], gains: [number, number, number], exposureEv: number, };
+
+export type GrayCardSettings = { region: CardRegion, path: string | null, target: number | null, };
+
+export type GroupInput = { path: string, detectedAt: number | null, };
+
+export type GroupStatus = "complete" | "waiting" | "timedOut";
+
+export type GroupingRules = { maxGapSecs: number | null, pattern: string | null, framesPerBracket: number | null, maxImages: number, expectedFrames: number | null, incompleteTimeoutSecs: number | null, incompletePolicy: IncompletePolicy, framesPerExposure: number | null, };
+
+export type HdrPreviewParams = { maxSize: number, sdrWhiteNits: number, peakNits: number, };
+
+export type HistoryEntry = { id: number, mergedAt: string, inputPaths: Array<string>, outputPngPath: string, outputExrPath: string | null, algorithm: string, algorithmParams: { [key in string]?: JsonValue }, stages: Array<string>, durationMs: number | null, width: number, height: number, rating: number, tags: Array<string>, preset: string | null, stale: boolean, request: MergeRequest | null, outputPaths: Array<string>, };
+
+export type HistoryFilter = { minRating: number | null, tags: Array<string>, query: string | null, from: string | null, to: string | null, };
+
+export type HistoryPage = { entries: Array<HistoryEntry>, total: number, offset: number, };
+
+export type IdleSettings = { enabled: boolean, afterMinutes: number, };
+
+export type ImageLimits = { maxWidth: number, maxHeight: number, maxPixels: number, };
+
+export type ImageStat = { path: string, averageLuma: number, };
+
+export type ImportSummary = { presetsAdded: number, presetsUpdated: number, };
+
+export type IncompletePolicy = "mergePartial" | "discard" | "hold";
+
+export type InkWarning = { limitPercent: number, maxPercent: number, overFraction: number, };
+
+export type InputCheck = { path: string, } & InputStatus;
+
+export type InputStatus = { "status": "ok" } | { "status": "decodeError", message: string, } | { "status": "tooLarge", width: number, height: number, maxWidth: number, maxHeight: number, maxPixels: number, } | { "status": "sizeMismatch", width: number, height: number, expectedWidth: number, expectedHeight: number, } | { "status": "duplicate", of: string, };
+
+export type JobFailure = { jobId: string | null, kind: "merge" | "analysis", message: string, reportPath: string | null, failedAt: string, };
+
+export type JsonValue = number | string | boolean | Array<JsonValue> | { [key in string]?: JsonValue } | null;
+
+export type LaunchRejected = { paths: Array<string>, reason: string, };
+
+export type LaunchRequest = { paths: Array<string>, frameSelection: FrameSelection | null, };
+
+export type MemoryFallback = { requiredBytes: number, budgetBytes: number | null, tileSize: number, };
+
+export type MergeBacklog = { running: number, pending: number, };
+
+export type MergeMode = "bracket" | "noiseStack" | "hybrid" | "longExposure";
+
+export type MergeProgress = { jobId: string | null, stage: string, fraction: number, };
+
+export type MergeQuality = "fast" | "balanced" | "best";
+
+export type MergeRequest = { paths: Array<string>, outputDir: string | null, outputTemplate: string | null, groupName: string | null, outputExr: boolean, outputLayeredExr: boolean, exrPreview: boolean, outputDng: boolean, exrColorSpace: ExrColorSpace | null, outputColorSpace: OutputColorSpace, saveTransforms: boolean, transformsPath: string | null, deliverables: Array<Deliverable>, algorithm: string | null, algorithmParams: { [key in string]?: JsonValue }, pipeline: Array<PipelineStage> | null, roi: Roi | null, perspective: PerspectiveCorners | null, autoStraighten: boolean, deterministic: boolean, frameSelection: FrameSelection | null, allowPartial: boolean, autoExcludeBadFrames: boolean, clippingMap: boolean, outputShortReference: boolean, quality: MergeQuality, mergeMode: MergeMode, exifExposureCompensation: boolean, grayCard: GrayCardSettings | null, autoLevels: AutoLevels | null, preset: string | null, jobId: string | null, automatic: boolean, memoryBudgetMb: number | null, };
+
+export type MergeRequestV2 = { apiVersion: number, paths: Array<string>, jobId: string | null, preset: string | null, automatic: boolean, output: OutputOptions, processing: ProcessingOptions, geometry: GeometryOptions, frames: FrameOptions, color: ColorOptions, };
+
+export type MergeResult = { outputPngPath: string, outputExrPath: string | null, outputLayeredExrPath: string | null, transformsPath: string | null, outputShortReferencePath: string | null, sentTo: Array<SentOutput>, outputPreviewPath: string | null, outputDngPath: string | null, width: number, height: number, mergedAt: string, algorithm: string, stages: Array<string>, alignmentOffsets: Array<[number, number]>, alignmentTransforms: Array<AlignTransform>, straightenAngle: number | null, skippedFrames: Array<SkippedFrame>, exposureOrder: Array<FrameExposure>, excludedInputs: Array<InputCheck>, clipping: ClippingSummary | null, durationMs: number, memoryFallback: MemoryFallback | null, deliverables: Array<DeliverableOutput>, frameQuality: Array<FrameScore>, grayCard: GrayCardCorrection | null, autoLevels: AppliedLevels | null, exposureCompensation: Array<ExposureCompensation>, };
+
+export type NormalizedPoint = { x: number, y: number, };
+
+export type OutputColorSpace = "srgb" | "displayP3" | "rec2020";
+
+export type OutputOptions = { dir: string | null, template: string | null, groupName: string | null, exr: boolean, layeredExr: boolean, exrPreview: boolean, dng: boolean, exrColorSpace: ExrColorSpace | null, colorSpace: OutputColorSpace, saveTransforms: boolean, clippingMap: boolean, shortReference: boolean, deliverables: Array<Deliverable>, };
+
+export type ParameterKind = { "type": "number", min: number, max: number, step: number, } | { "type": "boolean" };
+
+export type ParameterSchema = { name: string, label: string, default: JsonValue, } & ParameterKind;
+
+export type PendingGroup = { id: number, paths: Array<string>, createdAt: string, updatedAt: string, mergeMode: MergeMode | null, };
+
+export type PendingJob = { request: MergeRequest, fixed: FixedSettings, interruptedAt: string, };
+
+export type PerspectiveCorners = { topLeft: NormalizedPoint, topRight: NormalizedPoint, bottomRight: NormalizedPoint, bottomLeft: NormalizedPoint, };
+
+export type PipelineStage = { "stage": "align" } & AlignParams | { "stage": "deghost" } & DeghostParams | { "stage": "merge" } | { "stage": "geometry" } | { "stage": "denoise" } & DenoiseParams | { "stage": "tonemap" } & TonemapParams | { "stage": "resize" } & ResizeParams | { "stage": "external" } & ExternalParams | { "stage": "encode" } & EncodeParams;
+
+export type PrefetchSettings = { enabled: boolean, maxMb: number, };
+
+export type Preset = { name: string, description: string | null, algorithm: string | null, algorithmParams: { [key in string]?: JsonValue }, pipeline: Array<PipelineStage> | null, outputExr: boolean, deterministic: boolean, sendTo: Array<string>, };
+
+export type PrintLayout = { dpi: number, widthMm: number, heightMm: number, };
+
+export type PrintSettings = { dpi: number | null, widthMm: number | null, inkLimit: number | null, };
+
+export type ProbeResult = { path: string, width: number, height: number, center: [number, number, number], stats: ProbeStats, samples: Array<ProbeSample>, sources: Array<SourceProbe>, };
+
+export type ProbeSample = { x: number, y: number, rgb: [number, number, number], };
+
+export type ProbeStats = { mean: [number, number, number], min: [number, number, number], max: [number, number, number], luminance: number, };
+
+export type ProcessingOptions = { mode: MergeMode, algorithm: string | null, algorithmParams: { [key in string]?: JsonValue }, pipeline: Array<PipelineStage> | null, quality: MergeQuality, deterministic: boolean, memoryBudgetMb: number | null, };
+
+export type Project = { id: string, name: string, watchFolder: string | null, outputDir: string | null, presets: Array<Preset>, grouping: GroupingRules | null, createdAt: string, };
+
+export type ProofTarget = "srgb" | "displayP3" | "adobeRgb";
+
+export type ReportFormat = "csv" | "json";
+
+export type ReprocessFailure = { id: number, message: string, };
+
+export type ResizeParams = { maxSize: number, };
+
+export type ResourceUsage = { rssBytes: number | null, caches: Array<CacheUsage>, prefetchBytes: number, queueDepth: number, };
+
+export type Roi = { x: number, y: number, width: number, height: number, };
+
+export type SecondInstance = { args: Array<string>, cwd: string, };
+
+export type SendFormat = "exr" | "png";
+
+export type SendTarget = { name: string, folder: string, fileName: string, format: SendFormat, maxSize: number | null, powerOfTwo: boolean, latlong: boolean, overwrite: boolean, };
+
+export type SentOutput = { target: string, path: string | null, width: number, height: number, error: string | null, };
+
+export type Settings = { watchFolder: string | null, watchExtensions: Array<string>, watchIgnoreDirs: Array<string>, watchTiming: WatchTiming, grouping: GroupingRules, outputDir: string | null, defaultOutputDir: DefaultOutputDir, outputTemplate: string | null, outputExr: boolean, protectWatchFolder: boolean, allowedWriteRoots: Array<string>, allowedPlugins: Array<string>, defaultPreset: string | null, activeProject: string | null, recentFolders: Array<string>, recentOutputs: Array<string>, cacheLimits: CacheLimits, prefetch: PrefetchSettings, memoryBudgetMb: number | null, reprocessOnPresetChange: boolean, maxConcurrentJobs: number | null, workerPriority: WorkerPriority, pauseWhileRunning: Array<string>, idle: IdleSettings, imageLimits: ImageLimits, sendTargets: Array<SendTarget>, };
+
+export type SharpenParams = { radius: number, amount: number, threshold: number, };
+
+export type SkippedBracket = { key: string, paths: Array<string>, reason: string, };
+
+export type SkippedFrame = { path: string, ev: number, reason: string, };
+
+export type SocialExport = { platform: SocialPlatform, path: string, width: number, height: number, };
+
+export type SocialPlatform = "instagram" | "facebook" | "x" | "flickr" | "web";
+
+export type SoftProofParams = { target: ProofTarget, iccPath: string | null, colorManaged: boolean, maxSize: number, };
+
+export type SoftProofResult = { path: string, width: number, height: number, outOfGamutFraction: number, };
+
+export type SourceProbe = { path: string, center: [number, number, number], stats: ProbeStats, };
+
+export type StaleReprocess = { updated: Array<HistoryEntry>, failed: Array<ReprocessFailure>, };
+
+export type SweepPreview = { params: { [key in string]?: JsonValue }, previewPath: string, width: number, height: number, straightenAngle: number | null, };
+
+export type TestBracket = { paths: Array<string>, evOffsets: Array<number>, };
+
+export type TestBracketOptions = { frames: number, evSpacing: number, width: number, height: number, noise: number, motion: number, seed: number, };
+
+export type TilePair = { column: number, row: number, x: number, y: number, width: number, height: number, sourcePath: string, mergedPath: string, };
+
+export type TileSpec = { level: number, tileSize: number, region: Roi | null, };
+
+export type TimelapseFrame = { number: number, paths: Array<string>, outputPngPath: string, outputExrPath: string | null, };
+
+export type TimelapseRequest = { folder: string, grouping: GroupingRules, settings: MergeRequest, outputDir: string | null, prefix: string | null, startNumber: number, digits: number, namePattern: string | null, frameRate: number | null, deflicker: DeflickerOptions | null, };
+
+export type TimelapseResult = { outputDir: string, frames: Array<TimelapseFrame>, skipped: Array<SkippedBracket>, grayCard: GrayCardCorrection | null, autoLevels: AppliedLevels | null, deflicker: Array<DeflickerFrame>, sidecarPath: string | null, };
+
+export type TonemapParams = { exposure: number, whitePoint: number, clarity: number, };
+
+export type UsageStats = { since: string, mergesSucceeded: number, mergesFailed: number, totalFrames: number, totalDurationMs: number, averageDurationMs: number, failureReasons: { [key in string]?: number }, };
+
+export type WatchFolderStats = { watchFolder: string | null, pendingFiles: number, pendingGroups: number, watchFolderBytes: number, outputFolder: string | null, outputFolderBytes: number, lastDetectedAt: string | null, };
+
+export type WatchTiming = { debounceMs: number, stableMs: number, extensions: { [key in string]?: ExtensionTiming }, };
+
+export type WorkerPriority = "normal" | "background";
+
+export interface EventPayloads {
+  "hdr://file-detected": string;
+  "hdr://open-files": LaunchRequest;
+  "hdr://launch-rejected": LaunchRejected;
+  "hdr://second-instance": SecondInstance;
+  "hdr://shutdown-waiting": number;
+  "hdr://bracket-timeout": BracketGroup;
+  "hdr://merge-progress": MergeProgress;
+  "hdr://merge-backlog": MergeBacklog;
+  "hdr://analysis-progress": AnalysisBatch<ImageStat>;
+  "hdr://job-failed": JobFailure;
+  "hdr://resource-usage": ResourceUsage;
+  "hdr://idle-changed": boolean;
+  "hdr://dashboard": DashboardSummary;
+  "hdr://auto-pause": Array<string>;
+}
+
+export interface Commands {
+  watcher_set_folder: { args: { folder: string }; result: null };
+  watcher_start: { args: {}; result: null };
+  watcher_stop: { args: {}; result: null };
+  watcher_is_running: { args: {}; result: boolean };
+  watcher_is_idle: { args: {}; result: boolean };
+  watcher_inject_event: { args: { path: string; kind: DetectionKind }; result: DetectionOutcome };
+  watch_folder_stats: { args: {}; result: WatchFolderStats };
+  detection_log_entries: { args: { limit?: number | null }; result: Array<Detection> };
+  detection_log_export: { args: { path: string }; result: number };
+  analyze_images: { args: { paths: Array<string> }; result: Array<ImageStat> };
+  analyze_images_stream: { args: { paths: Array<string> }; result: string };
+  analyze_images_cancel: { args: { jobId: string }; result: boolean };
+  recommend_bracket: { args: { path: string }; result: BracketRecommendation };
+  get_thumbnail: { args: { path: string; maxSize?: number | null }; result: string };
+  copy_result_to_clipboard: { args: { id?: number | null; path?: string | null }; result: ClipboardCopy };
+  get_hdr_preview: { args: { path: string; params?: HdrPreviewParams | null }; result: string };
+  get_soft_proof: { args: { path: string; params?: SoftProofParams | null }; result: SoftProofResult };
+  cache_stats: { args: {}; result: Array<CacheUsage> };
+  validate_merge_inputs: { args: { paths: Array<string> }; result: Array<InputCheck> };
+  suggest_frame_exclusions: { args: { paths: Array<string> }; result: FrameQuality };
+  group_images: { args: { inputs: Array<GroupInput>; rules?: GroupingRules | null }; result: Array<BracketGroup> };
+  settings_get: { args: {}; result: Settings };
+  settings_set: { args: { settings: Settings }; result: null };
+  write_root_add: { args: {}; result: Array<string> };
+  write_root_remove: { args: { path: string }; result: Array<string> };
+  plugin_allow_add: { args: {}; result: Array<string> };
+  plugin_allow_remove: { args: { path: string }; result: Array<string> };
+  presets_list: { args: {}; result: Array<Preset> };
+  preset_save: { args: { preset: Preset }; result: null };
+  preset_delete: { args: { name: string }; result: null };
+  project_create: { args: { name: string; watchFolder?: string | null; outputDir?: string | null }; result: Project };
+  project_list: { args: {}; result: Array<Project> };
+  project_open: { args: { id: string }; result: Project };
+  project_close: { args: {}; result: null };
+  project_current: { args: {}; result: Project | null };
+  history_list: { args: { filter?: HistoryFilter | null }; result: Array<HistoryEntry> };
+  history_search: { args: { query?: string | null; from?: string | null; to?: string | null; tags?: Array<string> | null; offset?: number | null; limit?: number | null }; result: HistoryPage };
+  history_export: { args: { format: ReportFormat; path: string; filter?: HistoryFilter | null }; result: number };
+  history_set_rating: { args: { id: number; stars: number }; result: HistoryEntry };
+  history_set_tags: { args: { id: number; tags: Array<string> }; result: HistoryEntry };
+  history_send_to: { args: { id: number; target: string }; result: SentOutput };
+  export_social: { args: { historyId: number; platform: SocialPlatform; outputDir?: string | null }; result: SocialExport };
+  config_export: { args: { path: string }; result: null };
+  config_import: { args: { path: string }; result: ImportSummary };
+  list_merge_algorithms: { args: {}; result: Array<AlgorithmInfo> };
+  api_negotiate: { args: { clientVersion: number }; result: ApiNegotiation };
+  get_capabilities: { args: {}; result: Capabilities };
+  merge_hdr: { args: { request: MergeRequest }; result: MergeResult };
+  merge_hdr_v2: { args: { request: MergeRequestV2 }; result: MergeResult };
+  group_create: { args: { paths: Array<string>; mergeMode?: MergeMode | null }; result: PendingGroup };
+  group_add_file: { args: { groupId: number; path: string }; result: PendingGroup };
+  group_remove_file: { args: { groupId: number; path: string }; result: PendingGroup };
+  group_list: { args: {}; result: Array<PendingGroup> };
+  group_delete: { args: { groupId: number }; result: PendingGroup };
+  group_merge: { args: { groupId: number; preset?: string | null; automatic?: boolean | null }; result: MergeResult };
+  merge_backlog: { args: {}; result: MergeBacklog };
+  merge_cancel: { args: { jobId: string }; result: boolean };
+  merge_prefetch: { args: { paths: Array<string> }; result: boolean };
+  history_reprocess_stale: { args: { presetName: string }; result: StaleReprocess };
+  recent_folders_list: { args: {}; result: Array<string> };
+  recent_outputs_list: { args: {}; result: Array<string> };
+  take_launch_request: { args: {}; result: LaunchRequest | null };
+  handle_dropped_paths: { args: { paths: Array<string> }; result: DropSuggestion };
+  delete_to_recycle: { args: { paths: Array<string> }; result: DeleteReport };
+  maintenance_cleanup: { args: {}; result: CleanupReport };
+  jobs_pending: { args: {}; result: Array<PendingJob> };
+  jobs_resume: { args: { index: number }; result: MergeResult };
+  jobs_clear_pending: { args: {}; result: null };
+  stats_get: { args: {}; result: UsageStats };
+  stats_reset: { args: {}; result: null };
+  merge_sweep: { args: { request: MergeRequest; parameterGrid: { [key in string]?: Array<JsonValue> } }; result: Array<SweepPreview> };
+  merge_timelapse: { args: { request: TimelapseRequest }; result: TimelapseResult };
+  merge_batch: { args: { request: BatchRequest }; result: BatchResult };
+  deflicker_sequence: { args: { folder: string; options?: DeflickerOptions | null }; result: DeflickerResult };
+  compare_images: { args: { pathA: string; pathB: string }; result: CompareResult };
+  generate_compare_tiles: { args: { sourcePath: string; mergedPath: string; tileSpec?: TileSpec | null }; result: CompareTiles };
+  generate_false_color: { args: { path: string; mode: FalseColorMode }; result: FalseColorResult };
+  probe_pixels: { args: { path: string; x: number; y: number; radius: number; sourcePaths?: Array<string> | null }; result: ProbeResult };
+  generate_test_bracket: { args: { outputDir: string; options?: TestBracketOptions | null }; result: TestBracket };
+  auto_pause_status: { args: {}; result: Array<string> };
+  dashboard_summary: { args: {}; result: DashboardSummary };
+}